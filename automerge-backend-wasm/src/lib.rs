@@ -223,8 +223,45 @@ pub fn get_changes_added(input: Object, input2: Object) -> Result<JsValue, JsVal
 }
 
 #[wasm_bindgen(js_name = getMissingDeps)]
-pub fn get_missing_deps(input: Object) -> Result<JsValue, JsValue> {
-    get_input(input, |state| rust_to_js(state.0.get_missing_deps(&[])))
+pub fn get_missing_deps(input: Object, heads: JsValue) -> Result<JsValue, JsValue> {
+    let heads: Vec<ChangeHash> = if heads.is_undefined() {
+        Vec::new()
+    } else {
+        js_to_rust(&heads)?
+    };
+    get_input(input, |state| rust_to_js(state.0.get_missing_deps(&heads)))
+}
+
+#[wasm_bindgen(js_name = getChangeByHash)]
+pub fn get_change_by_hash(input: Object, hash: JsValue) -> Result<JsValue, JsValue> {
+    let hash: ChangeHash = js_to_rust(&hash)?;
+    get_input(input, |state| match state.0.get_change_by_hash(&hash) {
+        Some(change) => {
+            let mut change = change.clone();
+            change.compress();
+            Ok(serde_wasm_bindgen::to_value(&BinaryChange(
+                change.raw_bytes().to_vec(),
+            ))?)
+        }
+        None => Ok(JsValue::null()),
+    })
+}
+
+#[wasm_bindgen(js_name = encodeChange)]
+pub fn encode_change(change: JsValue) -> Result<JsValue, JsValue> {
+    let change: amp::Change = js_to_rust(&change)?;
+    let mut change = Change::from(change);
+    change.compress();
+    Ok(serde_wasm_bindgen::to_value(&BinaryChange(
+        change.raw_bytes().to_vec(),
+    ))?)
+}
+
+#[wasm_bindgen(js_name = decodeChange)]
+pub fn decode_change(change: JsValue) -> Result<JsValue, JsValue> {
+    let change: BinaryChange = serde_wasm_bindgen::from_value(change)?;
+    let change = Change::from_bytes(change.0).map_err(to_js_err)?;
+    rust_to_js(change.decode())
 }
 
 fn import_changes(changes: &Array) -> Result<Vec<Change>, AutomergeError> {
@@ -23,6 +23,8 @@ pub struct RawSyncMessage {
     pub need: Vec<ChangeHash>,
     pub have: Vec<RawSyncHave>,
     pub changes: Vec<BinaryChange>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ephemeral: Option<serde_bytes::ByteBuf>,
 }
 
 impl TryFrom<SyncMessage> for RawSyncMessage {
@@ -44,6 +46,7 @@ impl TryFrom<SyncMessage> for RawSyncMessage {
             need: value.need,
             have,
             changes,
+            ephemeral: value.ephemeral.map(serde_bytes::ByteBuf::from),
         })
     }
 }
@@ -62,12 +65,9 @@ impl TryFrom<RawSyncMessage> for SyncMessage {
             .into_iter()
             .map(|b| Change::from_bytes(b.0))
             .collect::<Result<_, _>>()?;
-        Ok(Self {
-            heads: value.heads,
-            need: value.need,
-            have,
-            changes,
-        })
+        let mut message = SyncMessage::new(value.heads, value.need, have, changes);
+        message.ephemeral = value.ephemeral.map(serde_bytes::ByteBuf::into_vec);
+        Ok(message)
     }
 }
 
@@ -49,4 +49,34 @@ describe('Automerge.Backend', () => {
       const doc2 = Backend.applyLocalChange(doc1, change)
     })
   })
+
+  describe('change encoding', () => {
+    const change = {
+      actor: '55f250d0f76b4e15923600f98ebed8d7',
+      seq: 1,
+      startOp: 1,
+      deps: [],
+      time: 0,
+      message: 'set a bird',
+      ops: [{action: 'set', obj: '_root', key: 'bird', insert: false, pred: [], value: 'magpie'}],
+      extra_bytes: []
+    }
+
+    it('should round trip a change through encodeChange and decodeChange', () => {
+      const decoded = Backend.decodeChange(Backend.encodeChange(change))
+      assert.strictEqual(decoded.actor, change.actor)
+      assert.strictEqual(decoded.message, change.message)
+      assert.deepStrictEqual(decoded.ops, change.ops)
+      assert.strictEqual(typeof decoded.hash, 'string')
+    })
+
+    it('should look up applied changes by hash', () => {
+      const binary = Backend.encodeChange(change)
+      const hash = Backend.decodeChange(binary).hash
+      const [doc] = Backend.applyChanges(Backend.init(), [binary])
+      assert.deepStrictEqual(Backend.getChangeByHash(doc, hash), binary)
+      assert.strictEqual(Backend.getChangeByHash(doc, '00'.repeat(32)), null)
+      assert.deepStrictEqual(Backend.getMissingDeps(doc, [hash]), [])
+    })
+  })
 })
@@ -1,4 +1,4 @@
-use std::cmp::Ordering;
+use std::{cmp::Ordering, collections::HashMap, convert::TryFrom};
 
 use automerge_protocol as amp;
 use smol_str::SmolStr;
@@ -8,8 +8,13 @@ use crate::{
     internal::{ActorId, ElementId, InternalOp, Key, ObjectId, OpId},
 };
 
+/// The actors of a document, each of which is given a small index the first time it is seen so
+/// that internal op IDs don't carry whole actor IDs around.
 #[derive(PartialEq, Debug, Clone, Default)]
-pub(crate) struct ActorMap(Vec<amp::ActorId>);
+pub(crate) struct ActorMap {
+    actors: Vec<amp::ActorId>,
+    indices: HashMap<amp::ActorId, ActorId>,
+}
 
 impl ActorMap {
     pub fn import_key(&mut self, key: &amp::Key) -> Key {
@@ -20,12 +25,13 @@ impl ActorMap {
     }
 
     pub fn import_actor(&mut self, actor: &amp::ActorId) -> ActorId {
-        if let Some(idx) = self.0.iter().position(|a| a == actor) {
-            ActorId(idx)
-        } else {
-            self.0.push(actor.clone());
-            ActorId(self.0.len() - 1)
+        if let Some(idx) = self.indices.get(actor) {
+            return *idx;
         }
+        let idx = ActorId(u32::try_from(self.actors.len()).expect("more than u32::MAX actors"));
+        self.actors.push(actor.clone());
+        self.indices.insert(actor.clone(), idx);
+        idx
     }
 
     pub fn import_opid(&mut self, opid: &amp::OpId) -> OpId {
@@ -57,8 +63,19 @@ impl ActorMap {
         }
     }
 
+    /// Like `import_obj` but without interning actors we have not seen before.
+    pub fn lookup_obj(&self, obj: &amp::ObjectId) -> Option<ObjectId> {
+        match obj {
+            amp::ObjectId::Root => Some(ObjectId::Root),
+            amp::ObjectId::Id(amp::OpId(counter, actor)) => {
+                let idx = self.indices.get(actor)?;
+                Some(ObjectId::Id(OpId(*counter, *idx)))
+            }
+        }
+    }
+
     pub fn export_actor(&self, actor: ActorId) -> amp::ActorId {
-        self.0[actor.0].clone()
+        self.actors[actor.0 as usize].clone()
     }
 
     pub fn export_opid(&self, opid: &OpId) -> amp::OpId {
@@ -74,16 +91,12 @@ impl ActorMap {
 
     #[allow(dead_code)]
     pub fn index_of(&mut self, actor: &amp::ActorId) -> usize {
-        if let Some(index) = self.0.iter().position(|a| a == actor) {
-            return index;
-        }
-        self.0.push(actor.clone());
-        self.0.len() - 1
+        self.import_actor(actor).0 as usize
     }
 
     #[allow(dead_code)]
     pub fn actor_for(&self, index: usize) -> Option<&amp::ActorId> {
-        self.0.get(index)
+        self.actors.get(index)
     }
 
     pub fn cmp(&self, eid1: &ElementId, eid2: &ElementId) -> Ordering {
@@ -119,8 +132,8 @@ impl ActorMap {
 
     fn cmp_opid(&self, op1: &OpId, op2: &OpId) -> Ordering {
         if op1.0 == op2.0 {
-            let actor1 = &self.0[(op1.1).0];
-            let actor2 = &self.0[(op2.1).0];
+            let actor1 = &self.actors[(op1.1).0 as usize];
+            let actor2 = &self.actors[(op2.1).0 as usize];
             actor1.cmp(actor2)
             //op1.1.cmp(&op2.1)
         } else {
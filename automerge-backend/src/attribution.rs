@@ -0,0 +1,78 @@
+use automerge_protocol as amp;
+
+use crate::{error::AutomergeError, Backend, Change};
+
+/// Who introduced one element of a list or text object, see [`Backend::attribute`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attribution {
+    /// The element, i.e. the ID of the op which inserted it
+    pub element: amp::ElementId,
+    pub actor: amp::ActorId,
+    /// The change which inserted the element
+    pub change: amp::ChangeHash,
+    /// The timestamp of `change`
+    pub time: i64,
+    /// When this backend received `change`, see [`Backend::received_at`]
+    pub received_at: Option<i64>,
+    /// Whether the element was inserted after `heads_before`, i.e. by a change which is not one
+    /// of `heads_before` or their dependencies
+    pub added: bool,
+}
+
+impl Backend {
+    /// Attribute each element of the sequence `object`, as it was at `heads_after`, to the actor
+    /// and change which inserted it. The elements are returned in order, so the attribution of
+    /// the nth character of a text object is the nth entry. Elements which were inserted since
+    /// `heads_before` are marked as `added`, pass empty `heads_before` to mark everything.
+    ///
+    /// Elements are attributed to their insertion, a later change which overwrote the value of a
+    /// list element is not taken into account.
+    pub fn attribute(
+        &self,
+        object: &amp::ObjectId,
+        heads_before: &[amp::ChangeHash],
+        heads_after: &[amp::ChangeHash],
+    ) -> Result<Vec<Attribution>, AutomergeError> {
+        let before = self.ancestors(heads_before)?;
+        let after = self.at_heads(heads_after)?;
+        let object_id = after
+            .actors
+            .lookup_obj(object)
+            .ok_or(AutomergeError::MissingObjectError)?;
+        let state = after.op_set.get_obj(&object_id)?;
+        if !state.is_seq() {
+            return Err(AutomergeError::NotASequence(object.clone()));
+        }
+
+        let mut attributions = Vec::new();
+        for slot in &state.seq {
+            let element = state.element_of(*slot);
+            if state.conflicts(&element.into()).next().is_none() {
+                continue;
+            }
+            let op_id = after.actors.export_opid(&element);
+            let change = self
+                .change_containing(&op_id)
+                .ok_or_else(|| AutomergeError::InvalidOpId(op_id.to_string()))?;
+            attributions.push(Attribution {
+                element: amp::ElementId::Id(op_id.clone()),
+                actor: op_id.1,
+                change: change.hash,
+                time: change.time,
+                received_at: self.received_at(&change.hash),
+                added: !before.contains(&change.hash),
+            });
+        }
+        Ok(attributions)
+    }
+
+    /// The change which contains the op `op_id`
+    pub(crate) fn change_containing(&self, op_id: &amp::OpId) -> Option<&Change> {
+        let changes = self.states.get(&op_id.1)?;
+        // Changes by one actor are in sequence order, so their start ops are increasing
+        let after = changes.partition_point(|&i| self.history[i].start_op <= op_id.0);
+        changes
+            .get(after.checked_sub(1)?)
+            .map(|&i| &self.history[i])
+    }
+}
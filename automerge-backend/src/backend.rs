@@ -1,32 +1,86 @@
 use core::cmp::max;
 use std::{
-    collections::{HashMap, HashSet, VecDeque},
+    collections::{BTreeSet, HashMap, HashSet, VecDeque},
     fmt::Debug,
+    sync::atomic::{AtomicUsize, Ordering},
 };
 
 use amp::ChangeHash;
 use automerge_protocol as amp;
+use bytes::Bytes;
 
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+use crate::quota::ChangeRates;
 use crate::{
     actor_map::ActorMap,
-    change::encode_document,
+    change::{encode_document, encode_features, load_blocks, load_blocks_reporting, DecodeMode},
+    checkpoint::Checkpoints,
+    encoding::ColumnCompression,
     error::AutomergeError,
     event_handlers::{EventHandlerId, EventHandlers},
+    features::{self, check_features},
+    limits,
+    load_progress::LoadPhase,
     op_handle::OpHandle,
     op_set::OpSet,
-    patches::{generate_from_scratch_diff, IncrementalPatch},
+    patches::{generate_diff_between, generate_from_scratch_diff, IncrementalPatch},
+    quarantine::Quarantine,
+    quota::Quotas,
+    received::ReceivedAt,
+    shared::Shared,
+    timestamps::TimestampPolicy,
+    verification::Unverified,
     Change, EventHandler,
 };
 
 #[derive(Debug, Default, Clone)]
 pub struct Backend {
-    queue: Vec<Change>,
-    op_set: OpSet,
-    states: HashMap<amp::ActorId, Vec<usize>>,
-    actors: ActorMap,
-    history: Vec<Change>,
-    history_index: HashMap<amp::ChangeHash, usize>,
-    event_handlers: EventHandlers,
+    pub(crate) queue: Shared<Vec<Change>>,
+    pub(crate) op_set: Shared<OpSet>,
+    pub(crate) states: Shared<HashMap<amp::ActorId, Vec<usize>>>,
+    pub(crate) actors: Shared<ActorMap>,
+    pub(crate) history: Shared<Vec<Change>>,
+    pub(crate) history_index: Shared<HashMap<amp::ChangeHash, usize>>,
+    pub(crate) event_handlers: EventHandlers,
+    pub(crate) quotas: Quotas,
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    pub(crate) change_rates: Shared<ChangeRates>,
+    pub(crate) timestamp_policy: TimestampPolicy,
+    pub(crate) quarantine: Shared<Quarantine>,
+    /// The features the document requires, see `Backend::required_features`
+    pub(crate) features: BTreeSet<String>,
+    /// The features among `features` which were declared with `Backend::require_feature` or
+    /// loaded from a saved document, rather than worked out from the ops
+    pub(crate) declared_features: BTreeSet<String>,
+    /// Changes whose checksums haven't been checked yet, see `load_unverified`. Clones which
+    /// haven't changed it share the set, so checking a change through one clears it for all.
+    pub(crate) unverified: Shared<Unverified>,
+    pub(crate) checkpoints: Shared<Checkpoints>,
+    pub(crate) received_at: Shared<ReceivedAt>,
+    /// How many of the changes in `history` have been written out by `save` or
+    /// `save_incremental`, or were read by `load`
+    saved: SavedLen,
+}
+
+/// The number of changes which have been saved. An atomic rather than a `Cell` as `save` takes
+/// `&self` and the backend has to be `Sync`.
+#[derive(Debug, Default)]
+struct SavedLen(AtomicUsize);
+
+impl SavedLen {
+    fn get(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn set(&self, len: usize) {
+        self.0.store(len, Ordering::Relaxed);
+    }
+}
+
+impl Clone for SavedLen {
+    fn clone(&self) -> Self {
+        SavedLen(AtomicUsize::new(self.get()))
+    }
 }
 
 impl Backend {
@@ -68,12 +122,15 @@ impl Backend {
     }
 
     pub fn load_changes(&mut self, changes: Vec<Change>) -> Result<(), AutomergeError> {
-        self.apply_without_patch(changes)?;
+        self.apply_without_patch(changes, &mut |_| {})?;
         Ok(())
     }
 
     pub fn apply_changes(&mut self, changes: Vec<Change>) -> Result<amp::Patch, AutomergeError> {
-        self.apply(changes, None)
+        let applied_before = self.history.len();
+        let patch = self.apply(changes, None)?;
+        self.record_received(applied_before);
+        Ok(patch)
     }
 
     pub fn get_heads(&self) -> Vec<amp::ChangeHash> {
@@ -100,11 +157,18 @@ impl Backend {
     ///
     /// Generating the patch can itself be expensive and not always required, for instance when
     /// loading a new backend from bytes.
-    fn apply_without_patch(&mut self, changes: Vec<Change>) -> Result<(), AutomergeError> {
+    ///
+    /// `progress` is called with the number of changes handled after each one.
+    fn apply_without_patch(
+        &mut self,
+        changes: Vec<Change>,
+        progress: &mut dyn FnMut(usize),
+    ) -> Result<(), AutomergeError> {
         let mut patch = IncrementalPatch::new();
 
-        for change in changes {
+        for (index, change) in changes.into_iter().enumerate() {
             self.add_change(change, false, &mut patch)?;
+            progress(index + 1);
         }
 
         Ok(())
@@ -140,7 +204,9 @@ impl Backend {
             }
         }
 
+        self.clamp_timestamp(&mut change);
         let bin_change: Change = change.into();
+        limits::check(&bin_change)?;
         let hash = bin_change.hash;
 
         let patch: amp::Patch = self.apply(vec![bin_change], Some(actor_seq))?;
@@ -193,7 +259,7 @@ impl Backend {
         diffs: &mut IncrementalPatch,
     ) -> Result<(), AutomergeError> {
         if local {
-            self.apply_change(change, diffs)
+            self.apply_change(change, true, diffs)
         } else {
             self.queue.push(change);
             self.apply_queued_ops(diffs)
@@ -202,17 +268,35 @@ impl Backend {
 
     fn apply_queued_ops(&mut self, diffs: &mut IncrementalPatch) -> Result<(), AutomergeError> {
         while let Some(next_change) = self.pop_next_causally_ready_change() {
-            self.apply_change(next_change, diffs)?;
+            self.apply_change(next_change, false, diffs)?;
         }
         Ok(())
     }
 
+    /// Apply a single causally ready change.
+    ///
+    /// Remote changes which exceed the quotas are quarantined rather than failing the whole
+    /// batch, local ones are returned to the caller as an error.
     fn apply_change(
         &mut self,
         change: Change,
+        local: bool,
         diffs: &mut IncrementalPatch,
     ) -> Result<(), AutomergeError> {
         if self.history_index.contains_key(&change.hash) {
+            // a good copy of a change we haven't verified yet proves the copy we have is good too
+            let unverified = self.unverified.get_mut();
+            if unverified.contains(&change.hash) && change.verify_checksum().is_ok() {
+                unverified.remove(&change.hash);
+            }
+            return Ok(());
+        }
+
+        if let Err(reason) = self.check_change(&change) {
+            if local {
+                return Err(reason.into());
+            }
+            self.quarantine(change, reason);
             return Ok(());
         }
 
@@ -231,20 +315,27 @@ impl Backend {
         op_set.update_deps(change);
 
         let ops = OpHandle::extract(change, &mut self.actors);
+        self.features.extend(
+            ops.iter()
+                .filter_map(|op| features::required_by(&op.op.action))
+                .map(String::from),
+        );
 
         op_set.max_op = max(
             op_set.max_op,
             (start_op + (ops.len() as u64)).saturating_sub(1),
         );
 
+        let op_count = ops.len() as u64;
         op_set.apply_ops(ops, diffs, &mut self.actors)?;
 
         self.event_handlers.after_apply_change(change);
+        self.maybe_checkpoint(op_count);
 
         Ok(())
     }
 
-    fn update_history(&mut self, change: Change) -> usize {
+    pub(crate) fn update_history(&mut self, change: Change) -> usize {
         let history_index = self.history.len();
 
         self.states
@@ -280,6 +371,42 @@ impl Backend {
         self.make_patch(diffs, None)
     }
 
+    /// A patch which creates the document as it was when `heads` were the heads, for viewing
+    /// history. Errors with `UnknownChanges` if any of the heads aren't in this backend.
+    pub fn get_patch_at(&self, heads: &[amp::ChangeHash]) -> Result<amp::Patch, AutomergeError> {
+        self.at_heads(heads)?.get_patch()
+    }
+
+    /// An independent backend containing only `heads` and the changes they depend on, e.g. to
+    /// branch a draft off a published version of a document. The fork has the same quotas,
+    /// timestamp policy, checkpoint interval and declared features as this backend but no event
+    /// handlers, and only requires the features of ops it contains. Errors with
+    /// `UnknownChanges` if any of the heads aren't in this backend.
+    pub fn fork_at(&self, heads: &[amp::ChangeHash]) -> Result<Backend, AutomergeError> {
+        let mut fork = self.at_heads(heads)?;
+        fork.quotas.clone_from(&self.quotas);
+        fork.timestamp_policy = self.timestamp_policy;
+        fork.features.extend(self.declared_features.iter().cloned());
+        fork.declared_features.clone_from(&self.declared_features);
+        fork.set_checkpoint_interval(self.checkpoint_interval());
+        Ok(fork)
+    }
+
+    /// The diff which turns the document as it was at `heads_before` into the document as it
+    /// was at `heads_after`, e.g. to show what changed since a user last looked at a document.
+    /// Neither set of heads has to be an ancestor of the other.
+    pub fn diff(
+        &self,
+        heads_before: &[amp::ChangeHash],
+        heads_after: &[amp::ChangeHash],
+    ) -> Result<amp::RootDiff, AutomergeError> {
+        let before = self.at_heads(heads_before)?;
+        let after = self.at_heads(heads_after)?;
+        let before_workshop = before.op_set.patch_workshop(&before.actors);
+        let after_workshop = after.op_set.patch_workshop(&after.actors);
+        Ok(generate_diff_between(&before_workshop, &after_workshop))
+    }
+
     pub fn get_changes_for_actor_id(
         &self,
         actor_id: &amp::ActorId,
@@ -355,17 +482,123 @@ impl Backend {
     }
 
     pub fn save(&self) -> Result<Vec<u8>, AutomergeError> {
+        let document = self.encode()?;
+        self.saved.set(self.history.len());
+        Ok(document)
+    }
+
+    /// Like `save` but compresses the columns of the document with zstd rather than DEFLATE,
+    /// which makes documents with a lot of text considerably smaller. The result requires
+    /// [`crate::ZSTD_COLUMNS_FEATURE`], so it can only be loaded by builds with the `zstd`
+    /// feature.
+    #[cfg(feature = "zstd")]
+    pub fn save_zstd(&self) -> Result<Vec<u8>, AutomergeError> {
+        let document = self.encode_with(ColumnCompression::Zstd)?;
+        self.saved.set(self.history.len());
+        Ok(document)
+    }
+
+    /// Encode the whole document, like `save` but without affecting `save_incremental`
+    pub(crate) fn encode(&self) -> Result<Vec<u8>, AutomergeError> {
+        self.encode_with(ColumnCompression::Deflate)
+    }
+
+    fn encode_with(&self, compression: ColumnCompression) -> Result<Vec<u8>, AutomergeError> {
+        let features = features::file_features(&self.features, compression);
+        if self.history.is_empty() && features.is_empty() {
+            return Ok(Self::empty_document().to_vec());
+        }
+        self.verify_all()?;
         let changes: Vec<amp::Change> = self.history.iter().map(Change::decode).collect();
         //self.history.iter().map(|change| change.decode()).collect();
-        Ok(encode_document(&changes)?)
+        let document = encode_document(&changes, compression)?;
+        if features.is_empty() {
+            Ok(document)
+        } else {
+            let mut bytes = encode_features(&features)?;
+            bytes.extend(document);
+            Ok(bytes)
+        }
+    }
+
+    /// The binary changes which have been added since the last call to `save`, `save_incremental`
+    /// or `load`, concatenated.
+    ///
+    /// Appending the result to the bytes of the last save gives a file which `load` reads as the
+    /// current document, so long-lived documents can be persisted without rewriting them every
+    /// time. Returns an empty vector if nothing has changed.
+    pub fn save_incremental(&mut self) -> Result<Vec<u8>, AutomergeError> {
+        let unsaved = &self.history[self.saved.get()..];
+        for change in unsaved {
+            self.verify_change(&change.hash)?;
+        }
+        let bytes = unsaved
+            .iter()
+            .flat_map(|change| change.raw_bytes().iter().copied())
+            .collect();
+        self.saved.set(self.history.len());
+        Ok(bytes)
+    }
+
+    /// Apply changes stored by `save_incremental` (or a whole saved document) to this backend,
+    /// returning the patch for the frontend. Changes this backend already has are skipped.
+    pub fn load_incremental(&mut self, data: &[u8]) -> Result<amp::Patch, AutomergeError> {
+        let (changes, features) = load_blocks(&Bytes::copy_from_slice(data), DecodeMode::Strict)?;
+        check_features(&features, &[])?;
+        let up_to_date = self.saved.get() == self.history.len();
+        let patch = self.apply(changes, None)?;
+        self.add_loaded_features(features);
+        // the loaded changes are in storage already, unless they were added after changes which
+        // haven't been saved yet, in which case they will be written again (which is harmless)
+        if up_to_date {
+            self.saved.set(self.history.len());
+        }
+        Ok(patch)
+    }
+
+    pub fn load(data: Vec<u8>) -> Result<Self, AutomergeError> {
+        Self::load_with(&data.into(), &[], DecodeMode::Strict)
     }
 
+    /// Like `load` for a buffer which is shared with something else. Changes which were saved
+    /// with `save_incremental` are not copied out of `data` but decoded from it in place, so
+    /// `data` is kept alive for as long as the backend holds any of them.
     // allow this for API reasons
     #[allow(clippy::needless_pass_by_value)]
-    pub fn load(data: Vec<u8>) -> Result<Self, AutomergeError> {
-        let changes = Change::load_document(&data)?;
+    pub fn load_bytes(data: Bytes) -> Result<Self, AutomergeError> {
+        Self::load_with(&data, &[], DecodeMode::Strict)
+    }
+
+    /// Like `load` but also accepts documents requiring any of the `supported` features, on top
+    /// of the ones this library implements itself.
+    pub fn load_with_features(data: &[u8], supported: &[&str]) -> Result<Self, AutomergeError> {
+        Self::load_with(&Bytes::copy_from_slice(data), supported, DecodeMode::Strict)
+    }
+
+    pub(crate) fn load_with(
+        data: &Bytes,
+        supported: &[&str],
+        mode: DecodeMode,
+    ) -> Result<Self, AutomergeError> {
+        Self::load_reporting(data, supported, mode, &mut |_, _, _| {})
+    }
+
+    pub(crate) fn load_reporting(
+        data: &Bytes,
+        supported: &[&str],
+        mode: DecodeMode,
+        progress: &mut dyn FnMut(LoadPhase, usize, usize),
+    ) -> Result<Self, AutomergeError> {
+        let (changes, features) = load_blocks_reporting(data, mode, progress)?;
+        check_features(&features, supported)?;
         let mut backend = Self::new();
-        backend.load_changes(changes)?;
+        let total = changes.len();
+        progress(LoadPhase::Apply, 0, total);
+        backend.apply_without_patch(changes, &mut |done| {
+            progress(LoadPhase::Apply, done, total);
+        })?;
+        backend.add_loaded_features(features);
+        backend.saved.set(backend.history.len());
         Ok(backend)
     }
 
@@ -387,7 +620,7 @@ impl Backend {
 
         let mut missing = missing
             .into_iter()
-            .filter(|hash| !in_queue.contains(hash))
+            .filter(|hash| !in_queue.contains(hash) && !self.is_quarantined(hash))
             .copied()
             .collect::<Vec<_>>();
         missing.sort();
@@ -400,6 +633,32 @@ impl Backend {
             .and_then(|index| self.history.get(*index))
     }
 
+    /// Look up several changes at once, in the order of `hashes`.
+    ///
+    /// If any of the hashes are not known to this backend then the error lists all of them. The
+    /// changes are verified if they haven't been yet, see `verify_change`.
+    pub fn get_changes_by_hashes(
+        &self,
+        hashes: &[amp::ChangeHash],
+    ) -> Result<Vec<&Change>, AutomergeError> {
+        let mut changes = Vec::with_capacity(hashes.len());
+        let mut unknown = Vec::new();
+        for hash in hashes {
+            match self.get_change_by_hash(hash) {
+                Some(change) => {
+                    self.verify_change(hash)?;
+                    changes.push(change);
+                }
+                None => unknown.push(*hash),
+            }
+        }
+        if unknown.is_empty() {
+            Ok(changes)
+        } else {
+            Err(AutomergeError::UnknownChanges(unknown))
+        }
+    }
+
     pub fn get_change_by_hash_mut(&mut self, hash: &amp::ChangeHash) -> Option<&mut Change> {
         self.history_index
             .get(hash)
@@ -498,6 +757,36 @@ impl Backend {
         }
     }
 
+    /// A new backend containing only `heads` and their transitive dependencies, i.e. the
+    /// document as it was when these were the heads.
+    pub(crate) fn at_heads(&self, heads: &[amp::ChangeHash]) -> Result<Self, AutomergeError> {
+        self.replay(&self.ancestors(heads)?)
+    }
+
+    /// The hashes of `heads` and all of their transitive dependencies
+    pub(crate) fn ancestors(
+        &self,
+        heads: &[amp::ChangeHash],
+    ) -> Result<HashSet<amp::ChangeHash>, AutomergeError> {
+        let mut included = HashSet::new();
+        let mut unknown = Vec::new();
+        let mut stack = heads.to_vec();
+        while let Some(hash) = stack.pop() {
+            if !included.insert(hash) {
+                continue;
+            }
+            match self.history_index.get(&hash) {
+                Some(index) => stack.extend(self.history[*index].deps.iter().copied()),
+                None => unknown.push(hash),
+            }
+        }
+        if unknown.is_empty() {
+            Ok(included)
+        } else {
+            Err(AutomergeError::UnknownChanges(unknown))
+        }
+    }
+
     /// Adds the event handler and returns the id of the handler.
     pub fn add_event_handler(&mut self, handler: EventHandler) -> EventHandlerId {
         self.event_handlers.add_handler(handler)
@@ -0,0 +1,165 @@
+use std::{
+    borrow::Cow,
+    collections::HashSet,
+    io::{Read, Write},
+};
+
+use automerge_protocol as amp;
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+
+use crate::{decoding, decoding::Decoder, encoding::Encodable, AutomergeError, Backend, Change};
+
+const BUNDLE_MAGIC: [u8; 4] = *b"AMBN";
+const BUNDLE_VERSION: u8 = 1;
+const HASH_SIZE: usize = 32;
+
+impl Backend {
+    /// Package the changes needed to get from `heads_from` to `heads_to` into a single compressed
+    /// file, for passing documents around where there is no sync connection, e.g. by email or on
+    /// a USB stick. The bundle contains every ancestor of `heads_to` which isn't an ancestor of
+    /// `heads_from`, so an empty `heads_from` bundles the whole history up to `heads_to`.
+    ///
+    /// The bundle also lists the changes it depends on but doesn't contain, so that
+    /// [`Backend::apply_bundle`] can tell the recipient what they are missing rather than
+    /// silently queueing the changes.
+    pub fn export_bundle(
+        &self,
+        heads_from: &[amp::ChangeHash],
+        heads_to: &[amp::ChangeHash],
+    ) -> Result<Vec<u8>, AutomergeError> {
+        let excluded = self.ancestors(heads_from)?;
+        let included = self.ancestors(heads_to)?;
+        let changes: Vec<&Change> = self
+            .history
+            .iter()
+            .filter(|change| included.contains(&change.hash) && !excluded.contains(&change.hash))
+            .collect();
+        let contained: HashSet<_> = changes.iter().map(|change| change.hash).collect();
+        let mut needs: Vec<amp::ChangeHash> = changes
+            .iter()
+            .flat_map(|change| change.deps.iter())
+            .filter(|dep| !contained.contains(dep))
+            .copied()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        needs.sort();
+        let mut heads = heads_to.to_vec();
+        heads.sort();
+
+        let mut bytes = BUNDLE_MAGIC.to_vec();
+        bytes.push(BUNDLE_VERSION);
+        encode_hashes(&mut bytes, &needs)?;
+        encode_hashes(&mut bytes, &heads)?;
+        Ok(deflate_after(bytes, &changes))
+    }
+
+    /// Apply a bundle made by [`Backend::export_bundle`], returning the patch for the frontend.
+    /// Changes this backend already has are skipped.
+    ///
+    /// Fails with [`AutomergeError::UnknownChanges`] listing the changes the bundle depends on
+    /// which this backend doesn't have, in which case nothing is applied.
+    pub fn apply_bundle(&mut self, bundle: &[u8]) -> Result<amp::Patch, AutomergeError> {
+        let bundle = Bundle::decode(bundle)?;
+        let missing: Vec<_> = bundle
+            .needs
+            .into_iter()
+            .filter(|hash| !self.history_index.contains_key(hash))
+            .collect();
+        if !missing.is_empty() {
+            return Err(AutomergeError::UnknownChanges(missing));
+        }
+        let changes = bundle
+            .changes
+            .into_iter()
+            .filter(|change| !self.history_index.contains_key(&change.hash))
+            .collect();
+        self.apply_changes(changes)
+    }
+
+    /// What a bundle contains, without applying it
+    pub fn inspect_bundle(bundle: &[u8]) -> Result<BundleManifest, AutomergeError> {
+        let bundle = Bundle::decode(bundle)?;
+        Ok(BundleManifest {
+            needs: bundle.needs,
+            heads: bundle.heads,
+            changes: bundle.changes.iter().map(|change| change.hash).collect(),
+        })
+    }
+}
+
+/// The contents of a bundle, see [`Backend::inspect_bundle`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BundleManifest {
+    /// The changes the recipient must already have to apply the bundle
+    pub needs: Vec<amp::ChangeHash>,
+    /// The heads of the document the bundle was exported from
+    pub heads: Vec<amp::ChangeHash>,
+    /// The changes in the bundle, in the order they will be applied
+    pub changes: Vec<amp::ChangeHash>,
+}
+
+struct Bundle {
+    needs: Vec<amp::ChangeHash>,
+    heads: Vec<amp::ChangeHash>,
+    changes: Vec<Change>,
+}
+
+impl Bundle {
+    fn decode(bytes: &[u8]) -> Result<Self, AutomergeError> {
+        if bytes.len() < BUNDLE_MAGIC.len() || bytes[..BUNDLE_MAGIC.len()] != BUNDLE_MAGIC {
+            return Err(decoding::Error::WrongMagicBytes.into());
+        }
+        let mut decoder = Decoder::new(Cow::Borrowed(&bytes[BUNDLE_MAGIC.len()..]));
+        let version = decoder.read::<u8>()?;
+        if version != BUNDLE_VERSION {
+            return Err(AutomergeError::UnknownVersion(u64::from(version)));
+        }
+        let needs = decode_hashes(&mut decoder)?;
+        let heads = decode_hashes(&mut decoder)?;
+        let compressed = &bytes[BUNDLE_MAGIC.len() + decoder.offset..];
+        let mut raw = Vec::new();
+        DeflateDecoder::new(compressed)
+            .read_to_end(&mut raw)
+            .map_err(|_| AutomergeError::BadCompressedChunk)?;
+        let changes = if raw.is_empty() {
+            Vec::new()
+        } else {
+            Change::load_document(&raw)?
+        };
+        Ok(Bundle {
+            needs,
+            heads,
+            changes,
+        })
+    }
+}
+
+/// Append the compressed bytes of `changes` to `bytes`
+fn deflate_after(bytes: Vec<u8>, changes: &[&Change]) -> Vec<u8> {
+    // Writing to a `Vec` can't fail
+    let mut encoder = DeflateEncoder::new(bytes, Compression::best());
+    for change in changes {
+        encoder.write_all(change.raw_bytes()).unwrap();
+    }
+    encoder.finish().unwrap()
+}
+
+fn encode_hashes(buf: &mut Vec<u8>, hashes: &[amp::ChangeHash]) -> Result<(), AutomergeError> {
+    hashes.len().encode(buf)?;
+    for hash in hashes {
+        buf.extend_from_slice(&hash.0);
+    }
+    Ok(())
+}
+
+fn decode_hashes(decoder: &mut Decoder) -> Result<Vec<amp::ChangeHash>, decoding::Error> {
+    let len = decoder.read::<usize>()?;
+    let mut hashes = Vec::with_capacity(len.min(1024));
+    for _ in 0..len {
+        let hash = decoder.read_bytes(HASH_SIZE)?;
+        hashes
+            .push(std::convert::TryFrom::try_from(hash).map_err(decoding::Error::BadChangeFormat)?);
+    }
+    Ok(hashes)
+}
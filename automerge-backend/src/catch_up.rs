@@ -0,0 +1,53 @@
+use automerge_protocol as amp;
+
+use crate::{AutomergeError, Backend};
+
+/// What to send a peer so that it catches up with us, see [`Backend::catch_up`].
+///
+/// Either way the receiving backend can apply the bytes with `load_incremental` (or `load`, if
+/// it doesn't have anything yet).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CatchUp {
+    /// The binary changes the peer is missing, concatenated
+    Changes(Vec<u8>),
+    /// The whole document, as returned by `save`
+    Snapshot(Vec<u8>),
+}
+
+impl CatchUp {
+    pub fn bytes(&self) -> &[u8] {
+        match self {
+            CatchUp::Changes(bytes) | CatchUp::Snapshot(bytes) => bytes,
+        }
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        match self {
+            CatchUp::Changes(bytes) | CatchUp::Snapshot(bytes) => bytes,
+        }
+    }
+}
+
+impl Backend {
+    /// Work out what to send a peer which has seen `their_heads` so that it catches up with us.
+    ///
+    /// A document snapshot compresses the history much better than the individual changes, so
+    /// it is sent when the peer has nothing or is missing more than half of the changes. In all
+    /// other cases sending just the missing changes is cheaper.
+    pub fn catch_up(&self, their_heads: &[amp::ChangeHash]) -> Result<CatchUp, AutomergeError> {
+        let missing = self.get_changes(their_heads);
+        if !missing.is_empty() && (their_heads.is_empty() || missing.len() * 2 > self.history.len())
+        {
+            return Ok(CatchUp::Snapshot(self.encode()?));
+        }
+        for change in &missing {
+            self.verify_change(&change.hash)?;
+        }
+        Ok(CatchUp::Changes(
+            missing
+                .iter()
+                .flat_map(|change| change.raw_bytes().iter().copied())
+                .collect(),
+        ))
+    }
+}
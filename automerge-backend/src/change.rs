@@ -2,7 +2,7 @@
 use core::fmt::Debug;
 use std::{
     borrow::Cow,
-    collections::{HashMap, HashSet},
+    collections::{BTreeSet, HashMap, HashSet},
     convert::{TryFrom, TryInto},
     io::{Read, Write},
     ops::Range,
@@ -11,33 +11,38 @@ use std::{
 
 use amp::{OpType, SortedVec};
 use automerge_protocol as amp;
+use bytes::Bytes;
 use flate2::{
     bufread::{DeflateDecoder, DeflateEncoder},
     Compression,
 };
 use itertools::Itertools;
 use nonzero_ext::nonzero;
-use sha2::{Digest, Sha256};
 use tracing::instrument;
 
 use crate::{
     columnar::{
-        ChangeEncoder, ChangeIterator, ColumnEncoder, DepsIterator, DocChange, DocOp, DocOpEncoder,
-        DocOpIterator, OperationIterator, COLUMN_TYPE_DEFLATE,
+        check_change_columns, decompress_column, ChangeEncoder, ChangeIterator, ColumnEncoder,
+        DepsIterator, DocChange, DocOp, DocOpEncoder, DocOpIterator, OperationIterator,
+        COLUMN_TYPE_DEFLATE,
     },
     decoding,
     decoding::{Decodable, InvalidChangeError},
     encoding,
-    encoding::{Encodable, DEFLATE_MIN_SIZE},
+    encoding::{ColumnCompression, Encodable, DEFLATE_MIN_SIZE},
     error::AutomergeError,
     expanded_op::ExpandedOpIterator,
+    features::check_features,
+    hash,
     internal::InternalOpType,
+    load_progress::LoadPhase,
 };
 
 const HASH_BYTES: usize = 32;
 const BLOCK_TYPE_DOC: u8 = 0;
 const BLOCK_TYPE_CHANGE: u8 = 1;
 const BLOCK_TYPE_DEFLATE: u8 = 2;
+const BLOCK_TYPE_FEATURES: u8 = 3;
 const CHUNK_START: usize = 8;
 const HASH_RANGE: Range<usize> = 4..8;
 
@@ -78,7 +83,7 @@ fn encode(change: &amp::Change) -> Change {
 
     bytes.extend(&chunk.bytes);
 
-    let hash_result = Sha256::digest(&bytes[CHUNK_START..bytes.len()]);
+    let hash_result = hash::sha256(&bytes[CHUNK_START..bytes.len()]);
     let hash: amp::ChangeHash = hash_result[..].try_into().unwrap();
 
     bytes.splice(HASH_RANGE, hash_result[0..4].iter().copied());
@@ -90,7 +95,7 @@ fn encode(change: &amp::Change) -> Change {
     // std::assert_eq!(c1, c0);
     // perhaps we should add something like this to the test suite
 
-    let bytes = ChangeBytes::Uncompressed(bytes);
+    let bytes = ChangeBytes::Uncompressed(bytes.into());
 
     Change {
         bytes,
@@ -171,13 +176,14 @@ fn encode_chunk(change: &amp::Change, deps: &[amp::ChangeHash]) -> ChunkIntermed
     }
 }
 
+/// The bytes of a change, which may be a slice of the buffer the change was loaded from
 #[derive(PartialEq, Debug, Clone)]
 enum ChangeBytes {
     Compressed {
-        compressed: Vec<u8>,
-        uncompressed: Vec<u8>,
+        compressed: Bytes,
+        uncompressed: Bytes,
     },
-    Uncompressed(Vec<u8>),
+    Uncompressed(Bytes),
 }
 
 impl ChangeBytes {
@@ -203,7 +209,7 @@ impl ChangeBytes {
                     leb128::write::unsigned(&mut result, deflated_len as u64).unwrap();
                     result.extend(&deflated[..]);
                     *self = ChangeBytes::Compressed {
-                        compressed: result,
+                        compressed: result.into(),
                         uncompressed: std::mem::take(uncompressed),
                     }
                 }
@@ -241,20 +247,37 @@ impl Change {
 
     #[instrument(level = "debug", skip(bytes))]
     pub fn load_document(bytes: &[u8]) -> Result<Vec<Change>, AutomergeError> {
-        load_blocks(bytes)
+        let (changes, features) = load_blocks(&Bytes::copy_from_slice(bytes), DecodeMode::Strict)?;
+        check_features(&features, &[])?;
+        Ok(changes)
     }
 
     pub fn from_bytes(bytes: Vec<u8>) -> Result<Change, decoding::Error> {
+        decode_change(bytes.into())
+    }
+
+    /// Like [`Change::from_bytes`] but without copying: the change keeps a reference to `bytes`
+    /// and decodes its operations from there when they are needed.
+    pub fn from_shared_bytes(bytes: Bytes) -> Result<Change, decoding::Error> {
         decode_change(bytes)
     }
 
+    /// Like [`Change::from_bytes`] but keeps checking the change after finding a problem and
+    /// verifies every operation column, so that the error describes everything that is wrong
+    /// with it (as `DecodingError::Multiple` if there is more than one problem).
+    ///
+    /// This is slower than `from_bytes` and intended for diagnosing corrupt data.
+    pub fn from_bytes_lenient(bytes: Vec<u8>) -> Result<Change, decoding::Error> {
+        decode_change_with(bytes.into(), DecodeMode::Lenient)
+    }
+
     pub fn max_op(&self) -> u64 {
         // TODO - this could be a lot more efficient
         let len = self.iter_ops().count();
         self.start_op + (len as u64) - 1
     }
 
-    fn message(&self) -> Option<String> {
+    pub fn message(&self) -> Option<String> {
         let m = &self.bytes.uncompressed()[self.message.clone()];
         if m.is_empty() {
             None
@@ -280,6 +303,9 @@ impl Change {
                         InternalOpType::Del => OpType::Del(nonzero!(1_u32)),
                         InternalOpType::Inc(i) => OpType::Inc(i),
                         InternalOpType::Set(value) => OpType::Set(value),
+                        InternalOpType::Move(source) => OpType::Move(source),
+                        InternalOpType::MarkBegin(mark) => OpType::MarkBegin(mark),
+                        InternalOpType::MarkEnd(begin) => OpType::MarkEnd(begin),
                     },
                     obj: op.obj.clone().into_owned(),
                     key: op.key.into_owned(),
@@ -306,6 +332,21 @@ impl Change {
     pub fn raw_bytes(&self) -> &[u8] {
         self.bytes.raw()
     }
+
+    /// The uncompressed body of the change, i.e. everything after the chunk header
+    pub(crate) fn body(&self) -> &[u8] {
+        &self.bytes.uncompressed()[self.body_start..]
+    }
+
+    /// Check the hash of this change against the checksum in its header.
+    ///
+    /// Changes are always checked when they are decoded, unless they were loaded with
+    /// `Backend::load_unverified`.
+    pub fn verify_checksum(&self) -> Result<(), decoding::Error> {
+        check_hash(self.bytes.uncompressed())
+            .map(|_| ())
+            .map_err(|(_, e)| e)
+    }
 }
 
 impl TryFrom<&[u8]> for Change {
@@ -369,24 +410,44 @@ pub(crate) struct Document {
     extra_bytes: Range<usize>,
 }
 
+/// How thoroughly to check chunks while decoding them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum DecodeMode {
+    /// Stop at the first problem.
+    Strict,
+    /// Keep going after a problem so that all of them can be reported, see
+    /// [`Change::from_bytes_lenient`].
+    Lenient,
+    /// Skip checksum verification. Change hashes are still calculated as they identify the
+    /// changes, but whole document chunks are not hashed at all. The checksums stored in change
+    /// chunks can be verified later with [`Change::verify_checksum`].
+    Trusted,
+}
+
 fn decode_header(bytes: &[u8]) -> Result<(u8, amp::ChangeHash, Range<usize>), decoding::Error> {
     let (chunktype, body) = decode_header_without_hash(bytes)?;
+    let hash = check_hash(bytes).map_err(|(_, e)| e)?;
+    Ok((chunktype, hash, body))
+}
 
-    let calculated_hash = Sha256::digest(&bytes[PREAMBLE_BYTES..]);
+/// Calculate the hash of a chunk and compare it to the checksum in the header. On a mismatch
+/// the calculated hash is returned alongside the error.
+fn check_hash(bytes: &[u8]) -> Result<amp::ChangeHash, (amp::ChangeHash, decoding::Error)> {
+    let calculated_hash = hash::sha256(&bytes[PREAMBLE_BYTES..]);
+    let hash = amp::ChangeHash(calculated_hash);
 
     let checksum = &bytes[4..8];
     if checksum != &calculated_hash[0..4] {
-        return Err(decoding::Error::InvalidChecksum {
-            found: checksum.try_into().unwrap(),
-            calculated: calculated_hash[0..4].try_into().unwrap(),
-        });
+        return Err((
+            hash,
+            decoding::Error::InvalidChecksum {
+                found: checksum.try_into().unwrap(),
+                calculated: calculated_hash[0..4].try_into().unwrap(),
+            },
+        ));
     }
 
-    let hash = calculated_hash[..]
-        .try_into()
-        .map_err(InvalidChangeError::from)?;
-
-    Ok((chunktype, hash, body))
+    Ok(hash)
 }
 
 fn decode_header_without_hash(bytes: &[u8]) -> Result<(u8, Range<usize>), decoding::Error> {
@@ -492,24 +553,94 @@ fn decode_columns(
     ops
 }
 
-fn decode_block(bytes: &[u8], changes: &mut Vec<Change>) -> Result<(), decoding::Error> {
+/// The byte range of each column, by column ID
+type Columns = HashMap<u32, Range<usize>>;
+
+/// Decompress the compressed columns among `columns`, so that a corrupt column is an
+/// error here rather than a panic once the columns are being read. The decompressed columns are
+/// appended to a copy of `bytes` under their uncompressed column IDs.
+fn inflate_columns(
+    bytes: &[u8],
+    columns: Columns,
+) -> Result<(Cow<'_, [u8]>, Columns), decoding::Error> {
+    if columns.keys().all(|id| id & COLUMN_TYPE_DEFLATE == 0) {
+        return Ok((Cow::Borrowed(bytes), columns));
+    }
+    let mut data = bytes.to_vec();
+    let mut inflated = HashMap::with_capacity(columns.len());
+    for (id, range) in columns {
+        if id & COLUMN_TYPE_DEFLATE == 0 {
+            inflated.insert(id, range);
+            continue;
+        }
+        let compressed = bytes
+            .get(range.clone())
+            .ok_or(decoding::Error::NotEnoughBytes)?;
+        let start = data.len();
+        decompress_column(compressed, &mut data).map_err(|source| {
+            decoding::Error::DecompressColumn {
+                column: id & !COLUMN_TYPE_DEFLATE,
+                offset: range.start,
+                source,
+            }
+        })?;
+        inflated.insert(id & !COLUMN_TYPE_DEFLATE, start..data.len());
+    }
+    Ok((Cow::Owned(data), inflated))
+}
+
+fn decode_block(
+    bytes: Bytes,
+    changes: &mut Vec<Change>,
+    features: &mut BTreeSet<String>,
+    mode: DecodeMode,
+    progress: &mut dyn FnMut(LoadPhase, usize, usize),
+) -> Result<(), decoding::Error> {
     match bytes[PREAMBLE_BYTES] {
         BLOCK_TYPE_DOC => {
-            changes.extend(decode_document(bytes)?);
+            changes.extend(decode_document(&bytes, mode, progress)?);
             Ok(())
         }
         BLOCK_TYPE_CHANGE | BLOCK_TYPE_DEFLATE => {
-            changes.push(decode_change(bytes.to_vec())?);
+            changes.push(decode_change_with(bytes, mode)?);
+            Ok(())
+        }
+        BLOCK_TYPE_FEATURES => {
+            features.extend(decode_features(&bytes)?);
             Ok(())
         }
         found => Err(decoding::Error::WrongType {
-            expected_one_of: vec![BLOCK_TYPE_DOC, BLOCK_TYPE_CHANGE, BLOCK_TYPE_DEFLATE],
+            expected_one_of: vec![
+                BLOCK_TYPE_DOC,
+                BLOCK_TYPE_CHANGE,
+                BLOCK_TYPE_DEFLATE,
+                BLOCK_TYPE_FEATURES,
+            ],
             found,
         }),
     }
 }
 
-fn decode_change(bytes: Vec<u8>) -> Result<Change, decoding::Error> {
+fn decode_change(bytes: Bytes) -> Result<Change, decoding::Error> {
+    decode_change_with(bytes, DecodeMode::Strict)
+}
+
+/// Read one field of a chunk, attaching the field name and offset to any error.
+fn read_field<T>(
+    field: &'static str,
+    cursor: &mut Range<usize>,
+    read: impl FnOnce(&mut Range<usize>) -> Result<T, decoding::Error>,
+) -> Result<T, decoding::Error> {
+    let offset = cursor.start;
+    read(cursor).map_err(|e| e.in_field(field, offset))
+}
+
+/// Decode a change chunk.
+///
+/// In lenient mode a bad checksum does not stop decoding and the operation columns are checked
+/// in full, all of the problems found are then returned together. A field which can't be read
+/// still stops decoding immediately as the position of everything after it is unknown.
+fn decode_change_with(bytes: Bytes, mode: DecodeMode) -> Result<Change, decoding::Error> {
     let (chunktype, body) = decode_header_without_hash(&bytes)?;
     let bytes = if chunktype == BLOCK_TYPE_DEFLATE {
         decompress_chunk(0..PREAMBLE_BYTES, body, bytes)?
@@ -517,7 +648,22 @@ fn decode_change(bytes: Vec<u8>) -> Result<Change, decoding::Error> {
         ChangeBytes::Uncompressed(bytes)
     };
 
-    let (chunktype, hash, body) = decode_header(bytes.uncompressed())?;
+    let mut errors = Vec::new();
+    let (chunktype, hash, body) = match mode {
+        DecodeMode::Strict => decode_header(bytes.uncompressed())?,
+        DecodeMode::Lenient | DecodeMode::Trusted => {
+            let (chunktype, body) = decode_header_without_hash(bytes.uncompressed())?;
+            let hash = match check_hash(bytes.uncompressed()) {
+                Ok(hash) => hash,
+                Err((hash, _)) if mode == DecodeMode::Trusted => hash,
+                Err((hash, e)) => {
+                    errors.push(e);
+                    hash
+                }
+            };
+            (chunktype, hash, body)
+        }
+    };
 
     if chunktype != BLOCK_TYPE_CHANGE {
         return Err(decoding::Error::WrongType {
@@ -528,21 +674,44 @@ fn decode_change(bytes: Vec<u8>) -> Result<Change, decoding::Error> {
 
     let body_start = body.start;
     let mut cursor = body;
-
-    let deps = decode_hashes(bytes.uncompressed(), &mut cursor)?;
-
-    let actor =
-        amp::ActorId::from(&bytes.uncompressed()[slice_bytes(bytes.uncompressed(), &mut cursor)?]);
-    let seq = read_slice(bytes.uncompressed(), &mut cursor)?;
-    let start_op = read_slice(bytes.uncompressed(), &mut cursor)?;
-    let time = read_slice(bytes.uncompressed(), &mut cursor)?;
-    let message = slice_bytes(bytes.uncompressed(), &mut cursor)?;
-
-    let actors = decode_actors(bytes.uncompressed(), &mut cursor, Some(actor))?;
-
-    let ops_info = decode_column_info(bytes.uncompressed(), &mut cursor, false)?;
+    let raw = bytes.uncompressed();
+
+    let fields = (|| {
+        let deps = read_field("deps", &mut cursor, |c| decode_hashes(raw, c))?;
+        let actor = read_field("actor", &mut cursor, |c| {
+            let range = slice_bytes(raw, c)?;
+            raw.get(range)
+                .map(amp::ActorId::from)
+                .ok_or(decoding::Error::NotEnoughBytes)
+        })?;
+        let seq = read_field("seq", &mut cursor, |c| read_slice(raw, c))?;
+        let start_op = read_field("startOp", &mut cursor, |c| read_slice(raw, c))?;
+        let time = read_field("time", &mut cursor, |c| read_slice(raw, c))?;
+        let message = read_field("message", &mut cursor, |c| slice_bytes(raw, c))?;
+        let actors = read_field("actors", &mut cursor, |c| {
+            decode_actors(raw, c, Some(actor))
+        })?;
+        let ops_info = read_field("columns", &mut cursor, |c| {
+            decode_column_info(raw, c, false)
+        })?;
+        Ok((deps, seq, start_op, time, message, actors, ops_info))
+    })();
+    let (deps, seq, start_op, time, message, actors, ops_info) = match fields {
+        Ok(fields) => fields,
+        Err(e) => {
+            errors.push(e);
+            return Err(collect_errors(errors));
+        }
+    };
     let ops = decode_columns(&mut cursor, &ops_info);
 
+    if mode == DecodeMode::Lenient {
+        errors.extend(check_change_columns(raw, &ops));
+    }
+    if !errors.is_empty() {
+        return Err(collect_errors(errors));
+    }
+
     Ok(Change {
         bytes,
         body_start,
@@ -558,10 +727,18 @@ fn decode_change(bytes: Vec<u8>) -> Result<Change, decoding::Error> {
     })
 }
 
+fn collect_errors(mut errors: Vec<decoding::Error>) -> decoding::Error {
+    if errors.len() == 1 {
+        errors.remove(0)
+    } else {
+        decoding::Error::Multiple(errors)
+    }
+}
+
 fn decompress_chunk(
     preamble: Range<usize>,
     body: Range<usize>,
-    compressed: Vec<u8>,
+    compressed: Bytes,
 ) -> Result<ChangeBytes, decoding::Error> {
     let mut decoder = DeflateDecoder::new(&compressed[body]);
     let mut decompressed = Vec::new();
@@ -572,7 +749,7 @@ fn decompress_chunk(
     leb128::write::unsigned::<Vec<u8>>(&mut result, decompressed.len() as u64).unwrap();
     result.extend(decompressed);
     Ok(ChangeBytes::Compressed {
-        uncompressed: result,
+        uncompressed: result.into(),
         compressed,
     })
 }
@@ -703,12 +880,55 @@ fn doc_changes_to_uncompressed_changes<'a>(
     })
 }
 
-fn load_blocks(bytes: &[u8]) -> Result<Vec<Change>, AutomergeError> {
+/// Decode all the chunks in `bytes`, returning the changes along with the features required by
+/// any feature chunks.
+///
+/// Changes stored as change chunks are not copied but refer to `bytes`, so the buffer lives as
+/// long as any of them. Changes in document chunks are rebuilt and own their bytes.
+pub(crate) fn load_blocks(
+    bytes: &Bytes,
+    mode: DecodeMode,
+) -> Result<(Vec<Change>, BTreeSet<String>), AutomergeError> {
+    load_blocks_reporting(bytes, mode, &mut |_, _, _| {})
+}
+
+/// Like `load_blocks`, reporting the bytes decoded so far and the changes of document chunks
+/// hashed so far to `progress`
+pub(crate) fn load_blocks_reporting(
+    bytes: &Bytes,
+    mode: DecodeMode,
+    progress: &mut dyn FnMut(LoadPhase, usize, usize),
+) -> Result<(Vec<Change>, BTreeSet<String>), AutomergeError> {
     let mut changes = Vec::new();
-    for slice in split_blocks(bytes)? {
-        decode_block(slice, &mut changes)?;
+    let mut features = BTreeSet::new();
+    let mut offset = 0;
+    progress(LoadPhase::Decode, 0, bytes.len());
+    for (index, slice) in split_blocks(bytes)?.into_iter().enumerate() {
+        // the columns of the following document may be compressed with zstd, which this build
+        // can't decompress
+        #[cfg(not(feature = "zstd"))]
+        if features.contains(crate::ZSTD_COLUMNS_FEATURE) {
+            return Err(AutomergeError::UnsupportedFeatures(vec![
+                crate::ZSTD_COLUMNS_FEATURE.to_owned(),
+            ]));
+        }
+        let len = slice.len();
+        decode_block(
+            bytes.slice_ref(slice),
+            &mut changes,
+            &mut features,
+            mode,
+            progress,
+        )
+        .map_err(|e| decoding::Error::InChunk {
+            index,
+            offset,
+            source: Box::new(e),
+        })?;
+        offset += len;
+        progress(LoadPhase::Decode, offset, bytes.len());
     }
-    Ok(changes)
+    Ok((changes, features))
 }
 
 fn split_blocks(bytes: &[u8]) -> Result<Vec<&[u8]>, decoding::Error> {
@@ -746,8 +966,18 @@ fn pop_block(bytes: &[u8]) -> Result<Option<Range<usize>>, decoding::Error> {
     Ok(Some(0..end))
 }
 
-fn decode_document(bytes: &[u8]) -> Result<Vec<Change>, decoding::Error> {
-    let (chunktype, _hash, mut cursor) = decode_header(bytes)?;
+fn decode_document(
+    bytes: &[u8],
+    mode: DecodeMode,
+    progress: &mut dyn FnMut(LoadPhase, usize, usize),
+) -> Result<Vec<Change>, decoding::Error> {
+    let (chunktype, mut cursor) = if mode == DecodeMode::Trusted {
+        // the heads check below covers the content of the document anyway
+        decode_header_without_hash(bytes)?
+    } else {
+        let (chunktype, _hash, cursor) = decode_header(bytes)?;
+        (chunktype, cursor)
+    };
 
     // chunktype == 0 is a document, chunktype = 1 is a change
     if chunktype > 0 {
@@ -757,29 +987,41 @@ fn decode_document(bytes: &[u8]) -> Result<Vec<Change>, decoding::Error> {
         });
     }
 
-    let actors = decode_actors(bytes, &mut cursor, None)?;
+    let actors = read_field("actors", &mut cursor, |c| decode_actors(bytes, c, None))?;
 
-    let heads = decode_hashes(bytes, &mut cursor)?;
+    let heads = read_field("heads", &mut cursor, |c| decode_hashes(bytes, c))?;
 
-    let changes_info = decode_column_info(bytes, &mut cursor, true)?;
-    let ops_info = decode_column_info(bytes, &mut cursor, true)?;
+    let changes_info = read_field("changeColumns", &mut cursor, |c| {
+        decode_column_info(bytes, c, true)
+    })?;
+    let ops_info = read_field("opColumns", &mut cursor, |c| {
+        decode_column_info(bytes, c, true)
+    })?;
 
     let changes_data = decode_columns(&mut cursor, &changes_info);
-    let mut doc_changes = ChangeIterator::new(bytes, &changes_data).collect::<Vec<_>>();
-    let doc_changes_deps = DepsIterator::new(bytes, &changes_data);
+    let ops_data = decode_columns(&mut cursor, &ops_info);
+    let (changes_bytes, changes_data) = inflate_columns(bytes, changes_data)?;
+    let (ops_bytes, ops_data) = inflate_columns(bytes, ops_data)?;
+
+    let mut doc_changes = ChangeIterator::new(&changes_bytes, &changes_data).collect::<Vec<_>>();
+    let doc_changes_deps = DepsIterator::new(&changes_bytes, &changes_data);
 
     let doc_changes_len = doc_changes.len();
 
-    let ops_data = decode_columns(&mut cursor, &ops_info);
-    let doc_ops: Vec<_> = DocOpIterator::new(bytes, &actors, &ops_data).collect();
+    let doc_ops: Vec<_> = DocOpIterator::new(&ops_bytes, &actors, &ops_data).collect();
 
     group_doc_change_and_doc_ops(&mut doc_changes, doc_ops, &actors)?;
 
     let uncompressed_changes =
         doc_changes_to_uncompressed_changes(doc_changes.into_iter(), &actors);
 
-    let changes = compress_doc_changes(uncompressed_changes, doc_changes_deps, doc_changes_len)
-        .ok_or(decoding::Error::NoDocChanges)?;
+    let changes = compress_doc_changes(
+        uncompressed_changes,
+        doc_changes_deps,
+        doc_changes_len,
+        progress,
+    )
+    .ok_or(decoding::Error::NoDocChanges)?;
 
     let mut calculated_heads = HashSet::new();
     for change in &changes {
@@ -800,6 +1042,7 @@ fn compress_doc_changes(
     uncompressed_changes: impl Iterator<Item = amp::Change>,
     doc_changes_deps: impl Iterator<Item = Vec<usize>>,
     num_changes: usize,
+    progress: &mut dyn FnMut(LoadPhase, usize, usize),
 ) -> Option<Vec<Change>> {
     let mut changes: Vec<Change> = Vec::with_capacity(num_changes);
 
@@ -809,6 +1052,7 @@ fn compress_doc_changes(
             uncompressed_change.deps.push(changes.get(idx)?.hash);
         }
         changes.push(uncompressed_change.into());
+        progress(LoadPhase::Verify, changes.len(), num_changes);
     }
 
     Some(changes)
@@ -819,6 +1063,9 @@ fn group_doc_ops(changes: &[amp::Change], actors: &[amp::ActorId]) -> Vec<DocOp>
     let mut by_obj_id = HashMap::<amp::ObjectId, HashMap<amp::Key, HashMap<amp::OpId, _>>>::new();
     let mut by_ref = HashMap::<amp::ObjectId, HashMap<amp::Key, Vec<amp::OpId>>>::new();
     let mut is_seq = HashSet::<amp::ObjectId>::new();
+    // the element each move op relocates, ops which refer to a moved element by the ID of the move
+    // are stored alongside the ops for the element itself
+    let mut moved_to = HashMap::<amp::OpId, amp::OpId>::new();
     let mut ops = Vec::new();
 
     for change in changes {
@@ -835,6 +1082,11 @@ fn group_doc_ops(changes: &[amp::Change], actors: &[amp::ActorId]) -> Vec<DocOp>
                 }
             }
 
+            if let InternalOpType::Move(ref source) = op.action {
+                let element = moved_to.get(source).unwrap_or(source).clone();
+                moved_to.insert(opid.clone(), element);
+            }
+
             let key = if op.insert {
                 by_ref
                     .entry(objid.clone().into_owned())
@@ -844,7 +1096,12 @@ fn group_doc_ops(changes: &[amp::Change], actors: &[amp::ActorId]) -> Vec<DocOp>
                     .push(opid.clone());
                 Cow::Owned(opid.clone().into())
             } else {
-                op.key.clone()
+                match op.key.as_ref() {
+                    amp::Key::Seq(amp::ElementId::Id(id)) if moved_to.contains_key(id) => {
+                        Cow::Owned(moved_to[id].clone().into())
+                    }
+                    _ => op.key.clone(),
+                }
             };
 
             by_obj_id
@@ -910,7 +1167,9 @@ fn group_doc_ops(changes: &[amp::Change], actors: &[amp::ActorId]) -> Vec<DocOp>
             if let Some(key_ops) = by_obj_id.get(objid).and_then(|d| d.get(&key)) {
                 for opid in key_ops.keys().sorted() {
                     let op = key_ops.get(opid).unwrap();
-                    if op.action != InternalOpType::Del {
+                    // deletions are implied by the successors of the ops they delete, unless
+                    // they refer to a moved element by the ID of a move
+                    if op.action != InternalOpType::Del || op.key != key {
                         ops.push(op.clone());
                     }
                 }
@@ -934,7 +1193,10 @@ fn get_heads(changes: &[amp::Change]) -> HashSet<amp::ChangeHash> {
 }
 
 #[instrument(level = "debug", skip(changes))]
-pub(crate) fn encode_document(changes: &[amp::Change]) -> Result<Vec<u8>, encoding::Error> {
+pub(crate) fn encode_document(
+    changes: &[amp::Change],
+    compression: ColumnCompression,
+) -> Result<Vec<u8>, encoding::Error> {
     let mut bytes: Vec<u8> = Vec::new();
 
     let heads = get_heads(changes);
@@ -949,11 +1211,11 @@ pub(crate) fn encode_document(changes: &[amp::Change]) -> Result<Vec<u8>, encodi
         .cloned()
         .collect();
 
-    let (change_bytes, change_info) = ChangeEncoder::encode_changes(changes, &actors);
+    let (change_bytes, change_info) = ChangeEncoder::encode_changes(changes, &actors, compression);
 
     let doc_ops = group_doc_ops(changes, &actors);
 
-    let (ops_bytes, ops_info) = DocOpEncoder::encode_doc_ops(doc_ops, &mut actors);
+    let (ops_bytes, ops_info) = DocOpEncoder::encode_doc_ops(doc_ops, &mut actors, compression);
 
     bytes.extend(&MAGIC_BYTES);
     bytes.extend(vec![0, 0, 0, 0]); // we dont know the hash yet so fill in a fake
@@ -982,13 +1244,51 @@ pub(crate) fn encode_document(changes: &[amp::Change]) -> Result<Vec<u8>, encodi
 
     bytes.extend(&chunk);
 
-    let hash_result = Sha256::digest(&bytes[CHUNK_START..bytes.len()]);
+    let hash_result = hash::sha256(&bytes[CHUNK_START..bytes.len()]);
+
+    bytes.splice(HASH_RANGE, hash_result[0..4].iter().copied());
+
+    Ok(bytes)
+}
+
+/// Encode a chunk listing the features a document requires of the implementation loading it.
+///
+/// Implementations which predate feature chunks reject the whole document as they don't know
+/// the chunk type, which is what we want: they can't be relied on to merge it correctly either.
+pub(crate) fn encode_features(features: &BTreeSet<String>) -> Result<Vec<u8>, encoding::Error> {
+    let mut bytes: Vec<u8> = Vec::new();
+    bytes.extend(&MAGIC_BYTES);
+    bytes.extend(vec![0, 0, 0, 0]); // we dont know the hash yet so fill in a fake
+    bytes.push(BLOCK_TYPE_FEATURES);
+
+    let mut chunk = Vec::new();
+    features.len().encode(&mut chunk)?;
+    for feature in features {
+        feature.encode(&mut chunk)?;
+    }
+
+    leb128::write::unsigned(&mut bytes, chunk.len() as u64).unwrap();
+    bytes.extend(&chunk);
 
+    let hash_result = hash::sha256(&bytes[CHUNK_START..bytes.len()]);
     bytes.splice(HASH_RANGE, hash_result[0..4].iter().copied());
 
     Ok(bytes)
 }
 
+fn decode_features(bytes: &[u8]) -> Result<Vec<String>, decoding::Error> {
+    let (_, _hash, mut cursor) = decode_header(bytes)?;
+    read_field("features", &mut cursor, |cursor| {
+        let num_features: usize = read_slice(bytes, cursor)?;
+        // every feature takes at least a byte, so don't trust the count beyond that
+        let mut features = Vec::with_capacity(num_features.min(bytes.len()));
+        for _ in 0..num_features {
+            features.push(read_slice(bytes, cursor)?);
+        }
+        Ok(features)
+    })
+}
+
 pub(crate) const MAGIC_BYTES: [u8; 4] = [0x85, 0x6f, 0x4a, 0x83];
 pub(crate) const PREAMBLE_BYTES: usize = 8;
 pub(crate) const HEADER_BYTES: usize = PREAMBLE_BYTES + 1;
@@ -1336,13 +1636,13 @@ mod tests {
             deps: Vec::new(),
             extra_bytes: Vec::new(),
         };
-        let mut doc = encode_document(&[change]).unwrap();
+        let mut doc = encode_document(&[change], ColumnCompression::Deflate).unwrap();
         let hash: [u8; 4] = doc[4..8].try_into().unwrap();
         doc[4] = 0;
         doc[5] = 0;
         doc[6] = 0;
         doc[7] = 1;
-        let decode_result = decode_document(&doc);
+        let decode_result = decode_document(&doc, DecodeMode::Strict, &mut |_, _, _| {});
         if let Err(decoding::Error::InvalidChecksum {
             found: [0, 0, 0, 1],
             calculated,
@@ -1361,4 +1661,35 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_inflate_columns() {
+        let column = "a column of text ".repeat(100).into_bytes();
+        let mut deflated = Vec::new();
+        DeflateEncoder::new(&column[..], Compression::default())
+            .read_to_end(&mut deflated)
+            .unwrap();
+        let mut bytes = vec![1, 2, 3];
+        bytes.extend(&deflated);
+        let mut columns = HashMap::new();
+        columns.insert(0x15, 0..3);
+        columns.insert(0x25 | COLUMN_TYPE_DEFLATE, 3..bytes.len());
+
+        let (inflated, columns) = inflate_columns(&bytes, columns).unwrap();
+        assert_eq!(&inflated[columns[&0x15].clone()], &[1, 2, 3]);
+        assert_eq!(&inflated[columns[&0x25].clone()], &column[..]);
+        assert!(!columns.contains_key(&(0x25 | COLUMN_TYPE_DEFLATE)));
+
+        // 0xff starts a deflate block of the reserved type
+        let mut columns = HashMap::new();
+        columns.insert(0x25 | COLUMN_TYPE_DEFLATE, 0..4);
+        assert!(matches!(
+            inflate_columns(&[0xff; 4], columns),
+            Err(decoding::Error::DecompressColumn {
+                column: 0x25,
+                offset: 0,
+                ..
+            })
+        ));
+    }
 }
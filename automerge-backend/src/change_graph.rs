@@ -0,0 +1,97 @@
+use std::{collections::HashMap, fmt::Write};
+
+use automerge_protocol as amp;
+
+use crate::{Backend, Change};
+
+/// Fill colours for the changes of each actor, reused if there are more actors than colours
+const PALETTE: [&str; 8] = [
+    "#a6cee3", "#b2df8a", "#fb9a99", "#fdbf6f", "#cab2d6", "#ffff99", "#1f78b4", "#33a02c",
+];
+
+/// The output format of [`Backend::export_change_graph`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphFormat {
+    /// Graphviz, render with e.g. `dot -Tsvg`
+    Dot,
+    /// A Mermaid flowchart, which GitHub and many wikis render inline
+    Mermaid,
+}
+
+impl Backend {
+    /// The change graph of this document, for debugging: one node per change labelled with a
+    /// prefix of its hash, its actor and seq, and its message, with an edge from each dependency
+    /// to the change which depends on it. Changes by the same actor share a colour and the
+    /// heads have a thicker border.
+    ///
+    /// Comparing the graphs of two peers is a quick way to see where they have diverged.
+    pub fn export_change_graph(&self, format: GraphFormat) -> String {
+        let heads = self.get_heads();
+        let mut colours: HashMap<&amp::ActorId, &str> = HashMap::new();
+        let mut out = String::new();
+        match format {
+            GraphFormat::Dot => out
+                .push_str("digraph changes {\n  rankdir=LR;\n  node [shape=box, style=filled];\n"),
+            GraphFormat::Mermaid => out.push_str("flowchart LR\n"),
+        }
+        for change in &self.history {
+            let next_colour = PALETTE[colours.len() % PALETTE.len()];
+            let colour = *colours.entry(change.actor_id()).or_insert(next_colour);
+            let label = label(change);
+            let id = node_id(&change.hash);
+            let is_head = heads.contains(&change.hash);
+            // writing to a string can't fail
+            let _ = match format {
+                GraphFormat::Dot => writeln!(
+                    out,
+                    "  {} [label=\"{}\", fillcolor=\"{}\"{}];",
+                    id,
+                    label
+                        .replace('\\', "\\\\")
+                        .replace('"', "\\\"")
+                        .replace('\n', "\\n"),
+                    colour,
+                    if is_head { ", penwidth=3" } else { "" }
+                ),
+                GraphFormat::Mermaid => writeln!(
+                    out,
+                    "  {}[\"{}\"]\n  style {} fill:{}{}",
+                    id,
+                    label.replace('"', "#quot;").replace('\n', "<br/>"),
+                    id,
+                    colour,
+                    if is_head { ",stroke-width:3px" } else { "" }
+                ),
+            };
+            for dep in &change.deps {
+                let _ = match format {
+                    GraphFormat::Dot => writeln!(out, "  {} -> {};", node_id(dep), id),
+                    GraphFormat::Mermaid => writeln!(out, "  {} --> {}", node_id(dep), id),
+                };
+            }
+        }
+        if format == GraphFormat::Dot {
+            out.push_str("}\n");
+        }
+        out
+    }
+}
+
+fn node_id(hash: &amp::ChangeHash) -> String {
+    format!("c{}", hex::encode(&hash.0[..6]))
+}
+
+fn label(change: &Change) -> String {
+    let actor = change.actor_id().to_hex_string();
+    let mut label = format!(
+        "{}\n{}:{}",
+        &hex::encode(change.hash.0)[..8],
+        &actor[..actor.len().min(8)],
+        change.seq
+    );
+    if let Some(message) = change.message() {
+        label.push('\n');
+        label.push_str(&message);
+    }
+    label
+}
@@ -0,0 +1,151 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use automerge_protocol as amp;
+
+use crate::{error::AutomergeError, Backend, Change};
+
+/// Somewhere to keep changes, addressed by their hash.
+///
+/// As a change is identified by its contents a store can be shared by any number of documents.
+/// Documents forked from a common ancestor then only store the history they share once, and
+/// each document is fully described by its heads, see [`Backend::save_to_store`] and
+/// [`Backend::load_from_store`].
+pub trait ChangeStore {
+    /// The encoded change with `hash`, if the store has it.
+    fn get(&self, hash: &amp::ChangeHash) -> io::Result<Option<Vec<u8>>>;
+
+    /// Store an encoded change. Storing a change which is already present does nothing.
+    fn put(&mut self, hash: amp::ChangeHash, bytes: &[u8]) -> io::Result<()>;
+
+    fn contains(&self, hash: &amp::ChangeHash) -> io::Result<bool> {
+        Ok(self.get(hash)?.is_some())
+    }
+}
+
+/// A [`ChangeStore`] which keeps changes in memory.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryChangeStore(HashMap<amp::ChangeHash, Vec<u8>>);
+
+impl MemoryChangeStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of changes in the store.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl ChangeStore for MemoryChangeStore {
+    fn get(&self, hash: &amp::ChangeHash) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.0.get(hash).cloned())
+    }
+
+    fn put(&mut self, hash: amp::ChangeHash, bytes: &[u8]) -> io::Result<()> {
+        self.0.entry(hash).or_insert_with(|| bytes.to_vec());
+        Ok(())
+    }
+
+    fn contains(&self, hash: &amp::ChangeHash) -> io::Result<bool> {
+        Ok(self.0.contains_key(hash))
+    }
+}
+
+/// A [`ChangeStore`] which keeps each change in its own file in a directory, named after the
+/// hex encoded hash of the change.
+#[derive(Debug, Clone)]
+pub struct DirChangeStore {
+    dir: PathBuf,
+}
+
+impl DirChangeStore {
+    /// Use `dir` as a change store, creating it if it doesn't exist.
+    pub fn open<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        fs::create_dir_all(dir.as_ref())?;
+        Ok(Self {
+            dir: dir.as_ref().to_path_buf(),
+        })
+    }
+
+    fn path(&self, hash: &amp::ChangeHash) -> PathBuf {
+        self.dir.join(hex::encode(hash.0))
+    }
+}
+
+impl ChangeStore for DirChangeStore {
+    fn get(&self, hash: &amp::ChangeHash) -> io::Result<Option<Vec<u8>>> {
+        match fs::read(self.path(hash)) {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn put(&mut self, hash: amp::ChangeHash, bytes: &[u8]) -> io::Result<()> {
+        let path = self.path(&hash);
+        if path.exists() {
+            return Ok(());
+        }
+        // write to a temporary file first so a crash never leaves a truncated change behind
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, bytes)?;
+        fs::rename(tmp, path)
+    }
+
+    fn contains(&self, hash: &amp::ChangeHash) -> io::Result<bool> {
+        Ok(self.path(hash).exists())
+    }
+}
+
+impl Backend {
+    /// Write every change in the history to `store`, skipping the ones it already has, and
+    /// return the heads which identify this document in the store.
+    pub fn save_to_store<S: ChangeStore>(
+        &self,
+        store: &mut S,
+    ) -> Result<Vec<amp::ChangeHash>, AutomergeError> {
+        for change in &self.history {
+            if !store.contains(&change.hash)? {
+                store.put(change.hash, change.raw_bytes())?;
+            }
+        }
+        Ok(self.get_heads())
+    }
+
+    /// Load the document with `heads` from `store`, i.e. the heads and all of their transitive
+    /// dependencies.
+    ///
+    /// Fails with `AutomergeError::UnknownChanges` if the store is missing any of the changes.
+    pub fn load_from_store<S: ChangeStore>(
+        store: &S,
+        heads: &[amp::ChangeHash],
+    ) -> Result<Self, AutomergeError> {
+        let mut stack = heads.to_vec();
+        let mut seen = HashSet::new();
+        let mut changes = Vec::new();
+        while let Some(hash) = stack.pop() {
+            if !seen.insert(hash) {
+                continue;
+            }
+            let bytes = store
+                .get(&hash)?
+                .ok_or_else(|| AutomergeError::UnknownChanges(vec![hash]))?;
+            let change = Change::from_bytes(bytes)?;
+            stack.extend(change.deps.iter().copied());
+            changes.push(change);
+        }
+        // dependencies were found after their dependents, the queue sorts the order out
+        let mut backend = Self::new();
+        backend.load_changes(changes)?;
+        Ok(backend)
+    }
+}
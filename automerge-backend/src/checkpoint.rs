@@ -0,0 +1,118 @@
+use std::{collections::HashSet, sync::Arc};
+
+use automerge_protocol as amp;
+
+use crate::{
+    actor_map::ActorMap, error::AutomergeError, op_set::OpSet, shared::Shared, Backend, Change,
+};
+
+/// Copies of the op set taken every so many ops, so that reading the document at some earlier
+/// heads only has to replay the changes since the nearest checkpoint rather than the whole
+/// history.
+///
+/// Checkpoints are off by default as each one holds a full copy of the op set, see
+/// [`Backend::set_checkpoint_interval`].
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Checkpoints {
+    interval: Option<u64>,
+    ops_since_last: u64,
+    // shared between clones of a backend as they're never modified
+    taken: Vec<Arc<Checkpoint>>,
+}
+
+#[derive(Debug)]
+struct Checkpoint {
+    /// The state after applying `history[..history_len]`
+    history_len: usize,
+    op_set: Shared<OpSet>,
+    actors: Shared<ActorMap>,
+}
+
+impl Backend {
+    /// Take a checkpoint of the document every `interval` ops, or stop taking them if `interval`
+    /// is `None`. This speeds up reading old versions of large documents, e.g. with
+    /// [`Backend::get_patch_at`] or [`Backend::value_at`], at the cost of keeping a copy of the
+    /// document in memory for every checkpoint. Turning checkpoints off drops the ones already
+    /// taken.
+    pub fn set_checkpoint_interval(&mut self, interval: Option<u64>) {
+        let interval = interval.filter(|&i| i > 0);
+        if interval.is_none() {
+            self.checkpoints.taken.clear();
+        }
+        self.checkpoints.interval = interval;
+    }
+
+    pub fn checkpoint_interval(&self) -> Option<u64> {
+        self.checkpoints.interval
+    }
+
+    /// How many checkpoints have been taken
+    pub fn checkpoint_count(&self) -> usize {
+        self.checkpoints.taken.len()
+    }
+
+    /// Called after each change is applied with the number of ops it contained
+    pub(crate) fn maybe_checkpoint(&mut self, ops: u64) {
+        if let Some(interval) = self.checkpoints.interval {
+            self.checkpoints.ops_since_last += ops;
+            if self.checkpoints.ops_since_last >= interval {
+                self.checkpoints.ops_since_last = 0;
+                self.checkpoints.taken.push(Arc::new(Checkpoint {
+                    history_len: self.history.len(),
+                    op_set: self.op_set.clone(),
+                    actors: self.actors.clone(),
+                }));
+            }
+        }
+    }
+
+    /// A new backend containing the changes in `included`, starting from the latest checkpoint
+    /// whose changes are all included. `included` must be closed under dependencies.
+    pub(crate) fn replay(
+        &self,
+        included: &HashSet<amp::ChangeHash>,
+    ) -> Result<Self, AutomergeError> {
+        // The history is in causal order so every prefix of it is a valid state, the checkpoint
+        // can be used as long as it doesn't contain anything past the first excluded change
+        let first_excluded = self
+            .history
+            .iter()
+            .position(|change| !included.contains(&change.hash))
+            .unwrap_or(self.history.len());
+        let checkpoint = self
+            .checkpoints
+            .taken
+            .iter()
+            .rev()
+            .find(|checkpoint| checkpoint.history_len <= first_excluded);
+
+        let mut backend = Self::new();
+        let start = match checkpoint {
+            Some(checkpoint) => {
+                backend.op_set = checkpoint.op_set.clone();
+                backend.actors = checkpoint.actors.clone();
+                for change in &self.history[..checkpoint.history_len] {
+                    backend.update_history(change.clone());
+                }
+                checkpoint.history_len
+            }
+            None => 0,
+        };
+        let changes: Vec<Change> = self.history[start..]
+            .iter()
+            .filter(|change| included.contains(&change.hash))
+            .cloned()
+            .collect();
+        backend.load_changes(changes)?;
+        // The new backend's history starts with the same changes, so the checkpoints of that
+        // prefix are valid for it too
+        backend.checkpoints.taken = self
+            .checkpoints
+            .taken
+            .iter()
+            .filter(|checkpoint| checkpoint.history_len <= first_excluded)
+            .cloned()
+            .collect();
+        Ok(backend)
+    }
+}
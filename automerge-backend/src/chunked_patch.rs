@@ -0,0 +1,406 @@
+use std::{collections::BTreeMap, convert::TryFrom};
+
+use automerge_protocol as amp;
+use smol_str::SmolStr;
+
+use crate::{AutomergeError, Backend, Change};
+
+type Props = BTreeMap<SmolStr, BTreeMap<amp::OpId, amp::Diff>>;
+
+impl Backend {
+    /// Apply `changes` like [`Backend::apply_changes`], but deliver the patch as a sequence of
+    /// patches which each describe at most roughly `max_edits` edits, so that a frontend can
+    /// render between them rather than stalling on one enormous patch.
+    ///
+    /// The patches have to be applied in order. Each of them leaves the frontend in a
+    /// consistent state, but only the last one leaves it in the state the changes describe. An
+    /// edit is an inserted, updated or removed list element or character, or a map property
+    /// which was set or deleted. Small objects are kept in a single patch, larger ones are
+    /// spread across several.
+    pub fn apply_changes_chunked(
+        &mut self,
+        changes: Vec<Change>,
+        max_edits: usize,
+    ) -> Result<Vec<amp::Patch>, AutomergeError> {
+        let patch = self.apply_changes(changes)?;
+        Ok(split_patch(patch, max_edits))
+    }
+}
+
+/// Split `patch` into patches of at most `max_edits` edits each, as far as possible: a run of
+/// list updates to one index has to stay together, because the frontend takes the updates in a
+/// patch to be every value the index has.
+///
+/// Every chunk carries the clock, deps and max op of the whole patch, only the last carries the
+/// actor and sequence number, so that a frontend doesn't treat its local change as
+/// acknowledged until all of it has arrived.
+fn split_patch(mut patch: amp::Patch, max_edits: usize) -> Vec<amp::Patch> {
+    let props = std::mem::take(&mut patch.diffs.props);
+    let chunks = split_props(props, max_edits.max(1));
+    let last = chunks.len() - 1;
+    chunks
+        .into_iter()
+        .enumerate()
+        .map(|(i, props)| {
+            let mut chunk = amp::Patch {
+                diffs: amp::RootDiff { props },
+                ..patch.clone()
+            };
+            if i != last {
+                chunk.actor = None;
+                chunk.seq = None;
+            }
+            chunk
+        })
+        .collect()
+}
+
+fn cost(diff: &amp::Diff) -> usize {
+    match diff {
+        amp::Diff::Map(amp::MapDiff { props, .. })
+        | amp::Diff::Table(amp::TableDiff { props, .. }) => props.values().map(values_cost).sum(),
+        amp::Diff::List(amp::ListDiff { edits, .. })
+        | amp::Diff::Text(amp::TextDiff { edits, .. }) => edits.iter().map(edit_cost).sum(),
+        amp::Diff::Value(_) | amp::Diff::Cursor(_) => 0,
+    }
+}
+
+/// The cost of the values of one map key. A key with no values has been deleted, which is
+/// still an edit.
+fn values_cost(values: &BTreeMap<amp::OpId, amp::Diff>) -> usize {
+    values
+        .values()
+        .map(|diff| 1 + cost(diff))
+        .sum::<usize>()
+        .max(1)
+}
+
+fn edit_cost(edit: &amp::DiffEdit) -> usize {
+    match edit {
+        amp::DiffEdit::SingleElementInsert { value, .. } | amp::DiffEdit::Update { value, .. } => {
+            1 + cost(value)
+        }
+        amp::DiffEdit::MultiElementInsert(insert) => insert.values.len(),
+        amp::DiffEdit::TextInsert(insert) => insert.characters().map_or(1, |c| c.len()),
+        amp::DiffEdit::Remove { .. } | amp::DiffEdit::Marks(_) => 1,
+    }
+}
+
+/// `diff` with its edits or properties removed, which leaves the object it describes as it is
+/// (or creates it empty, if it is new)
+fn skeleton(diff: &amp::Diff) -> amp::Diff {
+    match diff {
+        amp::Diff::Map(amp::MapDiff { object_id, .. }) => amp::Diff::Map(amp::MapDiff {
+            object_id: object_id.clone(),
+            props: Props::new(),
+        }),
+        amp::Diff::Table(amp::TableDiff { object_id, .. }) => amp::Diff::Table(amp::TableDiff {
+            object_id: object_id.clone(),
+            props: Props::new(),
+        }),
+        amp::Diff::List(amp::ListDiff { object_id, .. }) => amp::Diff::List(amp::ListDiff {
+            object_id: object_id.clone(),
+            edits: Vec::new(),
+        }),
+        amp::Diff::Text(amp::TextDiff { object_id, .. }) => amp::Diff::Text(amp::TextDiff {
+            object_id: object_id.clone(),
+            edits: Vec::new(),
+        }),
+        amp::Diff::Value(_) | amp::Diff::Cursor(_) => diff.clone(),
+    }
+}
+
+/// Split a diff into diffs of the same object which applied in order have the same effect
+fn split(diff: amp::Diff, max_edits: usize) -> Vec<amp::Diff> {
+    if cost(&diff) <= max_edits {
+        return vec![diff];
+    }
+    match diff {
+        amp::Diff::Map(amp::MapDiff { object_id, props }) => split_props(props, max_edits)
+            .into_iter()
+            .map(|props| {
+                amp::Diff::Map(amp::MapDiff {
+                    object_id: object_id.clone(),
+                    props,
+                })
+            })
+            .collect(),
+        amp::Diff::Table(amp::TableDiff { object_id, props }) => split_props(props, max_edits)
+            .into_iter()
+            .map(|props| {
+                amp::Diff::Table(amp::TableDiff {
+                    object_id: object_id.clone(),
+                    props,
+                })
+            })
+            .collect(),
+        amp::Diff::List(amp::ListDiff { object_id, edits }) => split_edits(edits, max_edits)
+            .into_iter()
+            .map(|edits| {
+                amp::Diff::List(amp::ListDiff {
+                    object_id: object_id.clone(),
+                    edits,
+                })
+            })
+            .collect(),
+        amp::Diff::Text(amp::TextDiff { object_id, edits }) => split_edits(edits, max_edits)
+            .into_iter()
+            .map(|edits| {
+                amp::Diff::Text(amp::TextDiff {
+                    object_id: object_id.clone(),
+                    edits,
+                })
+            })
+            .collect(),
+        diff @ (amp::Diff::Value(_) | amp::Diff::Cursor(_)) => vec![diff],
+    }
+}
+
+/// Split every value of a map key (or list index) in turn. Each piece lists every value, as the
+/// frontend drops any value which isn't listed, with the values which aren't being split
+/// reduced to their skeletons.
+fn split_values(
+    values: &BTreeMap<amp::OpId, amp::Diff>,
+    max_edits: usize,
+) -> Vec<BTreeMap<amp::OpId, amp::Diff>> {
+    let mut pieces = Vec::new();
+    for (opid, diff) in values {
+        // Primitive values are already in every piece
+        if cost(diff) == 0 {
+            continue;
+        }
+        for piece in split(diff.clone(), max_edits) {
+            let mut piece_values: BTreeMap<_, _> = values
+                .iter()
+                .map(|(other, diff)| (other.clone(), skeleton(diff)))
+                .collect();
+            piece_values.insert(opid.clone(), piece);
+            pieces.push(piece_values);
+        }
+    }
+    if pieces.is_empty() {
+        pieces.push(values.clone());
+    }
+    pieces
+}
+
+fn split_props(props: Props, max_edits: usize) -> Vec<Props> {
+    let mut chunks = Vec::new();
+    let mut current = Props::new();
+    let mut current_cost = 0;
+    for (key, values) in props {
+        let key_cost = values_cost(&values);
+        if current_cost + key_cost <= max_edits {
+            current.insert(key, values);
+            current_cost += key_cost;
+            continue;
+        }
+        if !current.is_empty() {
+            chunks.push(std::mem::take(&mut current));
+        }
+        if key_cost <= max_edits {
+            current.insert(key, values);
+            current_cost = key_cost;
+        } else {
+            let mut pieces = split_values(&values, max_edits);
+            let last = pieces.pop();
+            chunks.extend(pieces.into_iter().map(|piece| {
+                let mut props = Props::new();
+                props.insert(key.clone(), piece);
+                props
+            }));
+            current_cost = last.as_ref().map_or(0, values_cost);
+            if let Some(last) = last {
+                current.insert(key, last);
+            }
+        }
+    }
+    if !current.is_empty() || chunks.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+/// Group the edits which have to stay in the same patch: consecutive updates of one index
+fn group_edits(edits: Vec<amp::DiffEdit>) -> Vec<Vec<amp::DiffEdit>> {
+    let mut groups: Vec<Vec<amp::DiffEdit>> = Vec::new();
+    for edit in edits {
+        if let (
+            amp::DiffEdit::Update { index, .. },
+            Some(Some(amp::DiffEdit::Update {
+                index: group_index, ..
+            })),
+        ) = (&edit, groups.last().map(|group| group.last()))
+        {
+            if index == group_index {
+                groups.last_mut().unwrap().push(edit);
+                continue;
+            }
+        }
+        groups.push(vec![edit]);
+    }
+    groups
+}
+
+/// Split a group of edits which costs more than `max_edits` on its own
+fn split_group(mut group: Vec<amp::DiffEdit>, max_edits: usize) -> Vec<Vec<amp::DiffEdit>> {
+    if group.len() > 1 {
+        // Conflicting updates of one index
+        let index = match &group[0] {
+            amp::DiffEdit::Update { index, .. } => *index,
+            _ => unreachable!(),
+        };
+        let values = group
+            .into_iter()
+            .filter_map(|edit| match edit {
+                amp::DiffEdit::Update { op_id, value, .. } => Some((op_id, value)),
+                _ => None,
+            })
+            .collect();
+        return split_values(&values, max_edits)
+            .into_iter()
+            .map(|values| {
+                values
+                    .into_iter()
+                    .map(|(op_id, value)| amp::DiffEdit::Update {
+                        index,
+                        op_id,
+                        value,
+                    })
+                    .collect()
+            })
+            .collect();
+    }
+    match group.pop() {
+        Some(amp::DiffEdit::SingleElementInsert {
+            index,
+            elem_id,
+            op_id,
+            value,
+        }) => {
+            // The element is inserted with the first piece of its value and the rest arrive as
+            // updates
+            let mut pieces = split(value, max_edits).into_iter();
+            let first = pieces
+                .next()
+                .map(|value| amp::DiffEdit::SingleElementInsert {
+                    index,
+                    elem_id,
+                    op_id: op_id.clone(),
+                    value,
+                });
+            first
+                .into_iter()
+                .chain(pieces.map(|value| amp::DiffEdit::Update {
+                    index,
+                    op_id: op_id.clone(),
+                    value,
+                }))
+                .map(|edit| vec![edit])
+                .collect()
+        }
+        Some(amp::DiffEdit::MultiElementInsert(insert)) => split_run(
+            insert.index,
+            &insert.elem_id,
+            &insert.values.iter().cloned().collect::<Vec<_>>(),
+            max_edits,
+            |index, elem_id, values: Vec<amp::ScalarValue>| {
+                amp::ScalarValues::try_from(values).ok().map(|values| {
+                    amp::DiffEdit::MultiElementInsert(amp::MultiElementInsert {
+                        index,
+                        elem_id,
+                        values,
+                    })
+                })
+            },
+        )
+        .unwrap_or_else(|| vec![vec![amp::DiffEdit::MultiElementInsert(insert)]]),
+        Some(amp::DiffEdit::TextInsert(insert)) => {
+            let characters: Option<Vec<String>> = insert
+                .characters()
+                .map(|c| c.into_iter().map(str::to_string).collect());
+            characters
+                .and_then(|characters| {
+                    split_run(
+                        insert.index,
+                        &insert.elem_id,
+                        &characters,
+                        max_edits,
+                        |index, elem_id, characters: Vec<String>| {
+                            let mut run = amp::TextInsert {
+                                index,
+                                elem_id,
+                                text: characters.concat(),
+                                lengths: characters
+                                    .iter()
+                                    .map(|c| c.chars().count() as u32)
+                                    .collect(),
+                            };
+                            if run.lengths.iter().all(|l| *l == 1) {
+                                run.lengths.clear();
+                            }
+                            Some(amp::DiffEdit::TextInsert(run))
+                        },
+                    )
+                })
+                .unwrap_or_else(|| vec![vec![amp::DiffEdit::TextInsert(insert)]])
+        }
+        Some(edit) => vec![vec![edit]],
+        None => Vec::new(),
+    }
+}
+
+/// Split a run of inserted values into runs of `max_edits`, giving each the element ID which
+/// its first value had in the whole run
+fn split_run<T, F>(
+    index: u64,
+    elem_id: &amp::ElementId,
+    values: &[T],
+    max_edits: usize,
+    make_edit: F,
+) -> Option<Vec<Vec<amp::DiffEdit>>>
+where
+    T: Clone,
+    F: Fn(u64, amp::ElementId, Vec<T>) -> Option<amp::DiffEdit>,
+{
+    values
+        .chunks(max_edits)
+        .enumerate()
+        .map(|(i, run)| {
+            let offset = (i * max_edits) as u64;
+            let elem_id = elem_id.increment_by(offset)?;
+            make_edit(index + offset, elem_id, run.to_vec()).map(|edit| vec![edit])
+        })
+        .collect()
+}
+
+fn split_edits(edits: Vec<amp::DiffEdit>, max_edits: usize) -> Vec<Vec<amp::DiffEdit>> {
+    let mut chunks = Vec::new();
+    let mut current = Vec::new();
+    let mut current_cost = 0;
+    for group in group_edits(edits) {
+        let group_cost: usize = group.iter().map(edit_cost).sum();
+        if current_cost + group_cost <= max_edits {
+            current.extend(group);
+            current_cost += group_cost;
+            continue;
+        }
+        if !current.is_empty() {
+            chunks.push(std::mem::take(&mut current));
+        }
+        if group_cost <= max_edits {
+            current = group;
+            current_cost = group_cost;
+        } else {
+            let mut pieces = split_group(group, max_edits);
+            let last = pieces.pop().unwrap_or_default();
+            chunks.extend(pieces);
+            current_cost = last.iter().map(edit_cost).sum();
+            current = last;
+        }
+    }
+    if !current.is_empty() || chunks.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
@@ -0,0 +1,160 @@
+use std::convert::TryFrom;
+
+use automerge_protocol as amp;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{decoding, encoding, BloomFilter, Change, SyncHave, SyncMessage};
+
+/// Turns patches into bytes for sending to a frontend and back again.
+///
+/// [`JsonCodec`] is the built-in implementation, deployments which want protobuf, CBOR or
+/// anything else implement this themselves.
+pub trait PatchCodec {
+    type Error;
+
+    fn encode_patch(&self, patch: &amp::Patch) -> Result<Vec<u8>, Self::Error>;
+    fn decode_patch(&self, bytes: &[u8]) -> Result<amp::Patch, Self::Error>;
+}
+
+/// Turns changes and sync messages into bytes for sending between peers and back again.
+///
+/// [`BinaryCodec`] uses the compressed columnar format that `Backend::save` and
+/// `SyncMessage::encode` produce, [`JsonCodec`] the JSON representation of `amp::Change`.
+pub trait ChangeCodec {
+    type Error;
+
+    fn encode_change(&self, change: &Change) -> Result<Vec<u8>, Self::Error>;
+    fn decode_change(&self, bytes: &[u8]) -> Result<Change, Self::Error>;
+    fn encode_sync_message(&self, message: SyncMessage) -> Result<Vec<u8>, Self::Error>;
+    fn decode_sync_message(&self, bytes: &[u8]) -> Result<SyncMessage, Self::Error>;
+}
+
+/// The errors produced by the built-in codecs
+#[derive(Error, Debug)]
+pub enum CodecError {
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Encoding(#[from] encoding::Error),
+    #[error(transparent)]
+    Decoding(#[from] decoding::Error),
+    #[error("Invalid bloom filter: {0}")]
+    InvalidHex(#[from] hex::FromHexError),
+}
+
+/// Encodes changes and sync messages in the binary format used everywhere else in this crate.
+/// There is no binary format for patches, so this only implements [`ChangeCodec`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BinaryCodec;
+
+impl ChangeCodec for BinaryCodec {
+    type Error = CodecError;
+
+    fn encode_change(&self, change: &Change) -> Result<Vec<u8>, Self::Error> {
+        Ok(change.raw_bytes().to_vec())
+    }
+
+    fn decode_change(&self, bytes: &[u8]) -> Result<Change, Self::Error> {
+        Ok(Change::from_bytes(bytes.to_vec())?)
+    }
+
+    fn encode_sync_message(&self, message: SyncMessage) -> Result<Vec<u8>, Self::Error> {
+        Ok(message.encode()?)
+    }
+
+    fn decode_sync_message(&self, bytes: &[u8]) -> Result<SyncMessage, Self::Error> {
+        Ok(SyncMessage::decode(bytes)?)
+    }
+}
+
+/// Encodes patches and changes as the JSON the JavaScript implementation uses. Sync messages
+/// are a JSON object with the changes in the same form and the bloom filters hex encoded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct JsonCodec;
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonSyncMessage {
+    heads: Vec<amp::ChangeHash>,
+    need: Vec<amp::ChangeHash>,
+    have: Vec<JsonSyncHave>,
+    changes: Vec<amp::Change>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ephemeral: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonSyncHave {
+    last_sync: Vec<amp::ChangeHash>,
+    bloom: String,
+}
+
+impl PatchCodec for JsonCodec {
+    type Error = CodecError;
+
+    fn encode_patch(&self, patch: &amp::Patch) -> Result<Vec<u8>, Self::Error> {
+        Ok(serde_json::to_vec(patch)?)
+    }
+
+    fn decode_patch(&self, bytes: &[u8]) -> Result<amp::Patch, Self::Error> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+}
+
+impl ChangeCodec for JsonCodec {
+    type Error = CodecError;
+
+    fn encode_change(&self, change: &Change) -> Result<Vec<u8>, Self::Error> {
+        Ok(serde_json::to_vec(&change.decode())?)
+    }
+
+    fn decode_change(&self, bytes: &[u8]) -> Result<Change, Self::Error> {
+        let change: amp::Change = serde_json::from_slice(bytes)?;
+        Ok(Change::from(change))
+    }
+
+    fn encode_sync_message(&self, message: SyncMessage) -> Result<Vec<u8>, Self::Error> {
+        let have = message
+            .have
+            .into_iter()
+            .map(|have| {
+                Ok(JsonSyncHave {
+                    last_sync: have.last_sync,
+                    bloom: hex::encode(have.bloom.into_bytes()?),
+                })
+            })
+            .collect::<Result<_, CodecError>>()?;
+        let message = JsonSyncMessage {
+            heads: message.heads,
+            need: message.need,
+            have,
+            changes: message.changes.iter().map(Change::decode).collect(),
+            ephemeral: message.ephemeral.map(hex::encode),
+        };
+        Ok(serde_json::to_vec(&message)?)
+    }
+
+    fn decode_sync_message(&self, bytes: &[u8]) -> Result<SyncMessage, Self::Error> {
+        let message: JsonSyncMessage = serde_json::from_slice(bytes)?;
+        let have = message
+            .have
+            .into_iter()
+            .map(|have| {
+                let bloom = hex::decode(have.bloom)?;
+                Ok(SyncHave {
+                    last_sync: have.last_sync,
+                    bloom: BloomFilter::try_from(bloom.as_slice())?,
+                })
+            })
+            .collect::<Result<_, CodecError>>()?;
+        Ok(SyncMessage {
+            heads: message.heads,
+            need: message.need,
+            have,
+            changes: message.changes.into_iter().map(Change::from).collect(),
+            ephemeral: message.ephemeral.map(hex::decode).transpose()?,
+        })
+    }
+}
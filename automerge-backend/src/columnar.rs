@@ -16,8 +16,9 @@ use smol_str::SmolStr;
 use tracing::instrument;
 
 use crate::{
+    decoding,
     decoding::{BooleanDecoder, Decodable, Decoder, DeltaDecoder, RleDecoder},
-    encoding::{BooleanEncoder, ColData, DeltaEncoder, Encodable, RleEncoder},
+    encoding::{BooleanEncoder, ColData, ColumnCompression, DeltaEncoder, Encodable, RleEncoder},
     expanded_op::ExpandedOp,
     internal::InternalOpType,
 };
@@ -83,6 +84,7 @@ pub struct OperationIterator<'a> {
     pub(crate) insert: BooleanDecoder<'a>,
     pub(crate) value: ValueIterator<'a>,
     pub(crate) pred: PredIterator<'a>,
+    pub(crate) mark_name: RleDecoder<'a, SmolStr>,
 }
 
 impl<'a> OperationIterator<'a> {
@@ -118,6 +120,7 @@ impl<'a> OperationIterator<'a> {
             },
             insert: col_iter(bytes, ops, COL_INSERT),
             action: col_iter(bytes, ops, COL_ACTION),
+            mark_name: col_iter(bytes, ops, COL_MARK_NAME),
         }
     }
 }
@@ -132,6 +135,7 @@ impl<'a> Iterator for OperationIterator<'a> {
         let key = self.keys.next()?;
         let pred = self.pred.next()?;
         let value = self.value.next()?;
+        let mark_name = self.mark_name.next()?;
         let action = match action {
             Action::Set => InternalOpType::Set(value),
             Action::MakeList => InternalOpType::Make(amp::ObjType::List),
@@ -140,6 +144,18 @@ impl<'a> Iterator for OperationIterator<'a> {
             Action::MakeTable => InternalOpType::Make(amp::ObjType::Table),
             Action::Del => InternalOpType::Del,
             Action::Inc => InternalOpType::Inc(value.to_i64()?),
+            Action::Move => match value {
+                amp::ScalarValue::Cursor(source) => InternalOpType::Move(source),
+                _ => return None,
+            },
+            Action::MarkBegin => InternalOpType::MarkBegin(amp::MarkData {
+                name: mark_name?,
+                value,
+            }),
+            Action::MarkEnd => match value {
+                amp::ScalarValue::Cursor(begin) => InternalOpType::MarkEnd(begin),
+                _ => return None,
+            },
         };
         Some(ExpandedOp {
             action,
@@ -160,6 +176,7 @@ pub(crate) struct DocOpIterator<'a> {
     pub(crate) insert: BooleanDecoder<'a>,
     pub(crate) value: ValueIterator<'a>,
     pub(crate) succ: SuccIterator<'a>,
+    pub(crate) mark_name: RleDecoder<'a, SmolStr>,
 }
 
 impl<'a> Iterator for DocOpIterator<'a> {
@@ -173,6 +190,7 @@ impl<'a> Iterator for DocOpIterator<'a> {
         let key = self.keys.next()?;
         let succ = self.succ.next()?;
         let value = self.value.next()?;
+        let mark_name = self.mark_name.next()?;
         let action = match action {
             Action::Set => InternalOpType::Set(value),
             Action::MakeList => InternalOpType::Make(amp::ObjType::List),
@@ -181,6 +199,18 @@ impl<'a> Iterator for DocOpIterator<'a> {
             Action::MakeTable => InternalOpType::Make(amp::ObjType::Table),
             Action::Del => InternalOpType::Del,
             Action::Inc => InternalOpType::Inc(value.to_i64()?),
+            Action::Move => match value {
+                amp::ScalarValue::Cursor(source) => InternalOpType::Move(source),
+                _ => return None,
+            },
+            Action::MarkBegin => InternalOpType::MarkBegin(amp::MarkData {
+                name: mark_name?,
+                value,
+            }),
+            Action::MarkEnd => match value {
+                amp::ScalarValue::Cursor(begin) => InternalOpType::MarkEnd(begin),
+                _ => return None,
+            },
         };
         Some(DocOp {
             actor,
@@ -229,6 +259,7 @@ impl<'a> DocOpIterator<'a> {
             },
             insert: col_iter(bytes, ops, COL_INSERT),
             action: col_iter(bytes, ops, COL_ACTION),
+            mark_name: col_iter(bytes, ops, COL_MARK_NAME),
         }
     }
 }
@@ -793,13 +824,17 @@ pub(crate) struct ChangeEncoder {
 
 impl ChangeEncoder {
     #[instrument(level = "debug", skip(changes, actors))]
-    pub fn encode_changes<'a, 'b, I>(changes: I, actors: &'a [amp::ActorId]) -> (Vec<u8>, Vec<u8>)
+    pub fn encode_changes<'a, 'b, I>(
+        changes: I,
+        actors: &'a [amp::ActorId],
+        compression: ColumnCompression,
+    ) -> (Vec<u8>, Vec<u8>)
     where
         I: IntoIterator<Item = &'b amp::Change>,
     {
         let mut e = Self::new();
         e.encode(changes, actors);
-        e.finish()
+        e.finish(compression)
     }
 
     fn new() -> ChangeEncoder {
@@ -850,7 +885,7 @@ impl ChangeEncoder {
         }
     }
 
-    fn finish(self) -> (Vec<u8>, Vec<u8>) {
+    fn finish(self, compression: ColumnCompression) -> (Vec<u8>, Vec<u8>) {
         let mut coldata = vec![
             self.actor.finish(DOC_ACTOR),
             self.seq.finish(DOC_SEQ),
@@ -873,7 +908,7 @@ impl ChangeEncoder {
             .encode(&mut info)
             .ok();
         for d in &mut coldata {
-            d.deflate();
+            d.compress(compression);
             d.encode_col_len(&mut info).ok();
         }
         for d in &coldata {
@@ -892,6 +927,7 @@ pub(crate) struct DocOpEncoder {
     action: RleEncoder<Action>,
     val: ValEncoder,
     succ: SuccEncoder,
+    mark_name: RleEncoder<SmolStr>,
 }
 
 // FIXME - actors should not be mut here
@@ -901,13 +937,14 @@ impl DocOpEncoder {
     pub(crate) fn encode_doc_ops<'a, I>(
         ops: I,
         actors: &'a mut Vec<amp::ActorId>,
+        compression: ColumnCompression,
     ) -> (Vec<u8>, Vec<u8>)
     where
         I: IntoIterator<Item = DocOp>,
     {
         let mut e = Self::new();
         e.encode(ops, actors);
-        e.finish()
+        e.finish(compression)
     }
 
     fn new() -> DocOpEncoder {
@@ -920,6 +957,7 @@ impl DocOpEncoder {
             action: RleEncoder::new(),
             val: ValEncoder::new(),
             succ: SuccEncoder::new(),
+            mark_name: RleEncoder::new(),
         }
     }
 
@@ -943,6 +981,20 @@ impl DocOpEncoder {
                     self.val.append_value(&amp::ScalarValue::Int(*val), actors);
                     Action::Inc
                 }
+                InternalOpType::Move(source) => {
+                    self.val
+                        .append_value(&amp::ScalarValue::Cursor(source.clone()), actors);
+                    Action::Move
+                }
+                InternalOpType::MarkBegin(mark) => {
+                    self.val.append_value(&mark.value, actors);
+                    Action::MarkBegin
+                }
+                InternalOpType::MarkEnd(begin) => {
+                    self.val
+                        .append_value(&amp::ScalarValue::Cursor(begin.clone()), actors);
+                    Action::MarkEnd
+                }
                 InternalOpType::Del => {
                     // FIXME throw error
                     self.val.append_null();
@@ -959,10 +1011,14 @@ impl DocOpEncoder {
                 }
             };
             self.action.append_value(action);
+            match &op.action {
+                InternalOpType::MarkBegin(mark) => self.mark_name.append_value(mark.name.clone()),
+                _ => self.mark_name.append_null(),
+            }
         }
     }
 
-    fn finish(self) -> (Vec<u8>, Vec<u8>) {
+    fn finish(self, compression: ColumnCompression) -> (Vec<u8>, Vec<u8>) {
         let mut coldata = vec![
             self.actor.finish(COL_ID_ACTOR),
             self.ctr.finish(COL_ID_CTR),
@@ -973,6 +1029,7 @@ impl DocOpEncoder {
         coldata.extend(self.key.finish());
         coldata.extend(self.val.finish());
         coldata.extend(self.succ.finish());
+        coldata.push(self.mark_name.finish(COL_MARK_NAME));
         coldata.sort_unstable_by(|a, b| a.col.cmp(&b.col));
 
         let mut info = Vec::new();
@@ -984,7 +1041,7 @@ impl DocOpEncoder {
             .encode(&mut info)
             .ok();
         for d in &mut coldata {
-            d.deflate();
+            d.compress(compression);
             d.encode_col_len(&mut info).ok();
         }
         for d in &coldata {
@@ -1003,6 +1060,7 @@ pub(crate) struct ColumnEncoder {
     action: RleEncoder<Action>,
     val: ValEncoder,
     pred: PredEncoder,
+    mark_name: RleEncoder<SmolStr>,
 }
 
 impl ColumnEncoder {
@@ -1026,6 +1084,7 @@ impl ColumnEncoder {
             action: RleEncoder::new(),
             val: ValEncoder::new(),
             pred: PredEncoder::new(),
+            mark_name: RleEncoder::new(),
         }
     }
 
@@ -1057,6 +1116,20 @@ impl ColumnEncoder {
                 self.val.append_null();
                 Action::Del
             }
+            InternalOpType::Move(source) => {
+                self.val
+                    .append_value(&amp::ScalarValue::Cursor(source.clone()), actors);
+                Action::Move
+            }
+            InternalOpType::MarkBegin(mark) => {
+                self.val.append_value(&mark.value, actors);
+                Action::MarkBegin
+            }
+            InternalOpType::MarkEnd(begin) => {
+                self.val
+                    .append_value(&amp::ScalarValue::Cursor(begin.clone()), actors);
+                Action::MarkEnd
+            }
             InternalOpType::Make(kind) => {
                 self.val.append_null();
                 match kind {
@@ -1068,12 +1141,16 @@ impl ColumnEncoder {
             }
         };
         self.action.append_value(action);
+        match &op.action {
+            InternalOpType::MarkBegin(mark) => self.mark_name.append_value(mark.name.clone()),
+            _ => self.mark_name.append_null(),
+        }
     }
 
     fn finish(self) -> (Vec<u8>, HashMap<u32, Range<usize>>) {
         // allocate for the exact number of columns
         let mut coldata = Vec::with_capacity(
-            2 + ObjEncoder::COLUMNS
+            3 + ObjEncoder::COLUMNS
                 + KeyEncoder::COLUMNS
                 + ValEncoder::COLUMNS
                 + PredEncoder::COLUMNS,
@@ -1084,6 +1161,7 @@ impl ColumnEncoder {
         coldata.extend(self.key.finish());
         coldata.extend(self.val.finish());
         coldata.extend(self.pred.finish());
+        coldata.push(self.mark_name.finish(COL_MARK_NAME));
         coldata.sort_unstable_by(|a, b| a.col.cmp(&b.col));
 
         let non_empty_column_count = coldata.iter().filter(|&d| !d.data.is_empty()).count();
@@ -1116,11 +1194,10 @@ where
     let bytes = if let Some(r) = ops.get(&col_id) {
         Cow::Borrowed(&bytes[r.clone()])
     } else if let Some(r) = ops.get(&(col_id | COLUMN_TYPE_DEFLATE)) {
-        let mut decoder = DeflateDecoder::new(&bytes[r.clone()]);
         let mut inflated = Vec::new();
-        //TODO this could throw if the compression is corrupt, we should propagate the error rather
-        //than unwrapping
-        decoder.read_to_end(&mut inflated).unwrap();
+        // Documents are inflated by `change::inflate_columns`, which reports corrupt columns, and
+        // changes can't contain compressed columns, so this shouldn't be reachable
+        decompress_column(&bytes[r.clone()], &mut inflated).unwrap();
         Cow::Owned(inflated)
     } else {
         Cow::from(&[] as &[u8])
@@ -1128,6 +1205,128 @@ where
     T::from(bytes)
 }
 
+/// A human readable name for an operation column, for error messages.
+pub(crate) fn column_name(col: u32) -> &'static str {
+    match col & !COLUMN_TYPE_DEFLATE {
+        COL_OBJ_ACTOR => "objActor",
+        COL_OBJ_CTR => "objCtr",
+        COL_KEY_ACTOR => "keyActor",
+        COL_KEY_CTR => "keyCtr",
+        COL_KEY_STR => "keyStr",
+        COL_ID_ACTOR => "idActor",
+        COL_ID_CTR => "idCtr",
+        COL_INSERT => "insert",
+        COL_ACTION => "action",
+        COL_VAL_LEN => "valLen",
+        COL_VAL_RAW => "valRaw",
+        COL_REF_ACTOR => "refActor",
+        COL_REF_CTR => "refCtr",
+        COL_PRED_NUM => "predNum",
+        COL_PRED_ACTOR => "predActor",
+        COL_PRED_CTR => "predCtr",
+        COL_SUCC_NUM => "succNum",
+        COL_SUCC_ACTOR => "succActor",
+        COL_SUCC_CTR => "succCtr",
+        _ => "unknown",
+    }
+}
+
+fn count_column_rows(
+    bytes: &[u8],
+    ops: &HashMap<u32, Range<usize>>,
+    col: u32,
+) -> Result<usize, decoding::Error> {
+    let rows = match col & 7 {
+        COLUMN_TYPE_INT_DELTA => col_iter::<DeltaDecoder>(bytes, ops, col).count_rows(),
+        COLUMN_TYPE_BOOLEAN => col_iter::<BooleanDecoder>(bytes, ops, col).count_rows(),
+        COLUMN_TYPE_STRING_RLE => col_iter::<RleDecoder<SmolStr>>(bytes, ops, col).count_rows(),
+        COLUMN_TYPE_INT_RLE => col_iter::<RleDecoder<u64>>(bytes, ops, col).count_rows(),
+        _ => col_iter::<RleDecoder<usize>>(bytes, ops, col).count_rows(),
+    };
+    rows.map_err(|offset| decoding::Error::CorruptColumn {
+        column: column_name(col),
+        offset: ops.get(&col).map_or(0, |r| r.start) + offset,
+    })
+}
+
+/// Check that every operation column of a change decodes cleanly and that the columns agree on
+/// how many operations there are, returning every problem found.
+pub(crate) fn check_change_columns(
+    bytes: &[u8],
+    ops: &HashMap<u32, Range<usize>>,
+) -> Vec<decoding::Error> {
+    let mut errors = Vec::new();
+    let expected = match count_column_rows(bytes, ops, COL_ACTION) {
+        Ok(rows) => rows,
+        Err(e) => {
+            errors.push(e);
+            return errors;
+        }
+    };
+
+    let per_op = [
+        COL_OBJ_ACTOR,
+        COL_OBJ_CTR,
+        COL_KEY_ACTOR,
+        COL_KEY_CTR,
+        COL_KEY_STR,
+        COL_INSERT,
+        COL_VAL_LEN,
+        COL_REF_ACTOR,
+        COL_REF_CTR,
+        COL_PRED_NUM,
+    ];
+    for col in per_op {
+        if !ops.contains_key(&col) {
+            continue;
+        }
+        match count_column_rows(bytes, ops, col) {
+            Ok(found) if found != expected => errors.push(decoding::Error::ColumnLength {
+                column: column_name(col),
+                expected,
+                found,
+            }),
+            Ok(_) => {}
+            Err(e) => errors.push(e),
+        }
+    }
+    if !errors.is_empty() {
+        // the grouped columns can't be checked if the columns they depend on are broken
+        return errors;
+    }
+
+    let raw_len: usize = col_iter::<RleDecoder<usize>>(bytes, ops, COL_VAL_LEN)
+        .take(expected)
+        .map(|len| len.unwrap_or(0) >> 4)
+        .sum();
+    let found = ops.get(&COL_VAL_RAW).map_or(0, Range::len);
+    if raw_len != found {
+        errors.push(decoding::Error::ValueColumnLength {
+            column: column_name(COL_VAL_RAW),
+            expected: raw_len,
+            found,
+        });
+    }
+
+    let preds: usize = col_iter::<RleDecoder<usize>>(bytes, ops, COL_PRED_NUM)
+        .take(expected)
+        .map(|num| num.unwrap_or(0))
+        .sum();
+    for col in [COL_PRED_ACTOR, COL_PRED_CTR] {
+        match count_column_rows(bytes, ops, col) {
+            Ok(found) if found != preds => errors.push(decoding::Error::ColumnLength {
+                column: column_name(col),
+                expected: preds,
+                found,
+            }),
+            Ok(_) => {}
+            Err(e) => errors.push(e),
+        }
+    }
+
+    errors
+}
+
 const VALUE_TYPE_NULL: usize = 0;
 const VALUE_TYPE_FALSE: usize = 1;
 const VALUE_TYPE_TRUE: usize = 2;
@@ -1152,34 +1351,53 @@ pub(crate) const COLUMN_TYPE_VALUE_LEN: u32 = 6;
 pub(crate) const COLUMN_TYPE_VALUE_RAW: u32 = 7;
 pub(crate) const COLUMN_TYPE_DEFLATE: u32 = 8;
 
+/// Decompress a column flagged with [`COLUMN_TYPE_DEFLATE`] onto the end of `out`.
+///
+/// Documents requiring [`crate::ZSTD_COLUMNS_FEATURE`] compress their columns with zstd instead,
+/// which is recognised by the zstd magic number. A DEFLATE stream never starts with it: the
+/// first byte would begin a stored block, whose length isn't followed by its complement.
+pub(crate) fn decompress_column(compressed: &[u8], out: &mut Vec<u8>) -> io::Result<usize> {
+    #[cfg(feature = "zstd")]
+    if compressed.starts_with(&zstd::zstd_safe::MAGICNUMBER.to_le_bytes()) {
+        return zstd::stream::read::Decoder::new(compressed)?.read_to_end(out);
+    }
+    DeflateDecoder::new(compressed).read_to_end(out)
+}
+
 #[derive(PartialEq, Debug, Clone, Copy)]
 #[repr(u32)]
 pub(crate) enum Action {
-    MakeMap,
-    Set,
-    MakeList,
-    Del,
-    MakeText,
-    Inc,
-    MakeTable,
+    MakeMap = 0,
+    Set = 1,
+    MakeList = 2,
+    Del = 3,
+    MakeText = 4,
+    Inc = 5,
+    MakeTable = 6,
+    // 7 is `link` in the JS implementation, which isn't supported here
+    MarkBegin = 8,
+    MarkEnd = 9,
+    Move = 10,
 }
-const ACTIONS: [Action; 7] = [
-    Action::MakeMap,
-    Action::Set,
-    Action::MakeList,
-    Action::Del,
-    Action::MakeText,
-    Action::Inc,
-    Action::MakeTable,
-];
 
 impl Decodable for Action {
     fn decode<R>(bytes: &mut R) -> Option<Self>
     where
         R: Read,
     {
-        let num = usize::decode::<R>(bytes)?;
-        ACTIONS.get(num).copied()
+        match usize::decode::<R>(bytes)? {
+            0 => Some(Action::MakeMap),
+            1 => Some(Action::Set),
+            2 => Some(Action::MakeList),
+            3 => Some(Action::Del),
+            4 => Some(Action::MakeText),
+            5 => Some(Action::Inc),
+            6 => Some(Action::MakeTable),
+            8 => Some(Action::MarkBegin),
+            9 => Some(Action::MarkEnd),
+            10 => Some(Action::Move),
+            _ => None,
+        }
     }
 }
 
@@ -1202,6 +1420,7 @@ const COL_SUCC_ACTOR: u32 = 8 << 4 | COLUMN_TYPE_ACTOR_ID;
 const COL_SUCC_CTR: u32 = 8 << 4 | COLUMN_TYPE_INT_DELTA;
 const COL_REF_CTR: u32 = 6 << 4 | COLUMN_TYPE_INT_RLE;
 const COL_REF_ACTOR: u32 = 6 << 4 | COLUMN_TYPE_ACTOR_ID;
+const COL_MARK_NAME: u32 = 9 << 4 | COLUMN_TYPE_STRING_RLE;
 
 const DOC_ACTOR: u32 = /* 0 << 4 */ COLUMN_TYPE_ACTOR_ID;
 const DOC_SEQ: u32 = /* 0 << 4 */ COLUMN_TYPE_INT_DELTA;
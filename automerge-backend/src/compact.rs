@@ -0,0 +1,388 @@
+use std::collections::{HashMap, HashSet};
+
+use automerge_protocol as amp;
+use thiserror::Error;
+
+use crate::{
+    error::AutomergeError,
+    internal::{Key, ObjectId},
+    object_store::ObjState,
+    op_handle::OpHandle,
+    Backend, Change,
+};
+
+/// Options for [`Backend::compact`]
+#[derive(Debug, Clone, Default)]
+pub struct CompactOptions {
+    /// The heads to compact up to. Everything these heads depend on is replaced by a single
+    /// snapshot change. Defaults to the current heads.
+    pub baseline: Option<Vec<amp::ChangeHash>>,
+    /// The actor which authors the snapshot change, a random actor is used if this is `None`.
+    /// This must not be an actor with changes after the baseline.
+    pub actor: Option<amp::ActorId>,
+    /// Also save the compacted document, see [`Compacted::saved`]
+    pub save: bool,
+}
+
+/// The result of [`Backend::compact`]
+#[derive(Debug)]
+pub struct Compacted {
+    /// A backend holding the snapshot change followed by every change made since the baseline
+    pub backend: Backend,
+    /// The heads of the history which was discarded. A peer which sends changes depending on
+    /// any of these has not seen the compacted document yet, and can't be merged with it.
+    pub discarded_heads: Vec<amp::ChangeHash>,
+    /// The hash of the snapshot change which replaces the discarded history
+    pub snapshot: amp::ChangeHash,
+    /// The new hash of each change made since the baseline. These changes get new hashes
+    /// because their dependencies and op IDs change.
+    pub rewritten: HashMap<amp::ChangeHash, amp::ChangeHash>,
+    /// The output of [`Backend::save`] for the compacted backend, if [`CompactOptions::save`]
+    /// was set
+    pub saved: Option<Vec<u8>>,
+}
+
+#[derive(Error, Debug)]
+pub enum CompactionError {
+    #[error("Change {0:?} is concurrent with the baseline")]
+    ConcurrentWithBaseline(amp::ChangeHash),
+    #[error("Change {change:?} refers to {opid}, which does not survive compaction")]
+    DanglingReference {
+        change: amp::ChangeHash,
+        opid: amp::OpId,
+    },
+    #[error("The snapshot actor {0} has changes after the baseline")]
+    ActorInUse(amp::ActorId),
+}
+
+impl Backend {
+    /// Discard the history up to a baseline set of heads, see [`CompactOptions`].
+    ///
+    /// The document as it was at the baseline is written out as a single snapshot change, which
+    /// keeps only the winning value of each key or element, so conflicts and deleted elements
+    /// are gone afterwards. Every change made since the baseline is then rewritten on top of
+    /// the snapshot. That requires each of those changes to depend on all of the baseline heads,
+    /// otherwise this fails with `CompactionError::ConcurrentWithBaseline`.
+    ///
+    /// The compacted document has a different history, so peers can't sync it with the
+    /// original. They should compare their heads with [`Compacted::discarded_heads`] to spot
+    /// that.
+    pub fn compact(&self, options: CompactOptions) -> Result<Compacted, AutomergeError> {
+        let baseline = options.baseline.unwrap_or_else(|| self.get_heads());
+        let discarded = self.ancestors(&baseline)?;
+        let later: Vec<&Change> = self
+            .history
+            .iter()
+            .filter(|change| !discarded.contains(&change.hash))
+            .collect();
+        check_covers_baseline(&baseline, &later)?;
+
+        let actor = options.actor.unwrap_or_else(amp::ActorId::random);
+        if let Some(change) = later.iter().find(|change| change.actor_id() == &actor) {
+            return Err(CompactionError::ActorInUse(change.actor_id().clone()).into());
+        }
+
+        let at_baseline = self.at_heads(&baseline)?;
+        let mut writer = SnapshotWriter {
+            backend: &at_baseline,
+            actor: actor.clone(),
+            ops: Vec::new(),
+            cursors: Vec::new(),
+            ids: HashMap::new(),
+        };
+        writer.write()?;
+        let snapshot: Change = amp::Change {
+            operations: writer.ops,
+            actor_id: actor,
+            hash: None,
+            seq: 1,
+            start_op: 1,
+            time: self
+                .history
+                .iter()
+                .filter(|change| discarded.contains(&change.hash))
+                .map(|change| change.time)
+                .max()
+                .unwrap_or(0),
+            message: None,
+            deps: Vec::new(),
+            extra_bytes: Vec::new(),
+        }
+        .into();
+
+        let mut rewriter = Rewriter {
+            ids: writer.ids,
+            shift: snapshot.max_op(),
+            later: HashMap::new(),
+        };
+        for change in &later {
+            rewriter
+                .later
+                .entry(change.actor_id().clone())
+                .or_default()
+                .push(change.start_op..=change.max_op());
+        }
+        let mut rewritten = HashMap::new();
+        let mut seqs: HashMap<amp::ActorId, u64> = HashMap::new();
+        let mut changes = vec![snapshot.clone()];
+        for change in later {
+            let seq = seqs.entry(change.actor_id().clone()).or_insert(0);
+            *seq += 1;
+            let mut deps: Vec<amp::ChangeHash> = change
+                .deps
+                .iter()
+                .map(|dep| rewritten.get(dep).copied().unwrap_or(snapshot.hash))
+                .collect();
+            deps.sort_unstable();
+            deps.dedup();
+            let new = Change::from(rewriter.rewrite(change, *seq, deps)?);
+            rewritten.insert(change.hash, new.hash);
+            changes.push(new);
+        }
+
+        let mut backend = Self::new();
+        backend.load_changes(changes)?;
+        let saved = if options.save {
+            Some(backend.save()?)
+        } else {
+            None
+        };
+        Ok(Compacted {
+            backend,
+            discarded_heads: at_baseline.get_heads(),
+            snapshot: snapshot.hash,
+            rewritten,
+            saved,
+        })
+    }
+}
+
+/// Check that every change in `later` depends on all of `baseline`
+fn check_covers_baseline(
+    baseline: &[amp::ChangeHash],
+    later: &[&Change],
+) -> Result<(), CompactionError> {
+    let heads: HashSet<&amp::ChangeHash> = baseline.iter().collect();
+    let mut covered: HashMap<amp::ChangeHash, HashSet<&amp::ChangeHash>> = HashMap::new();
+    for change in later {
+        let mut reached = HashSet::new();
+        for dep in &change.deps {
+            if let Some(deps_reached) = covered.get(dep) {
+                reached.extend(deps_reached.iter().copied());
+            } else if let Some(head) = heads.get(dep) {
+                reached.insert(*head);
+            }
+        }
+        if reached.len() < heads.len() {
+            return Err(CompactionError::ConcurrentWithBaseline(change.hash));
+        }
+        covered.insert(change.hash, reached);
+    }
+    Ok(())
+}
+
+/// Writes out the ops which recreate a document from scratch
+struct SnapshotWriter<'a> {
+    backend: &'a Backend,
+    actor: amp::ActorId,
+    ops: Vec<amp::Op>,
+    /// Cursors are set once everything else exists, as they may point at any element
+    cursors: Vec<(amp::ObjectId, amp::Key, amp::OpId, amp::OpId)>,
+    /// The op in the snapshot which replaces each object, element, mark and winning value
+    ids: HashMap<amp::OpId, amp::OpId>,
+}
+
+impl SnapshotWriter<'_> {
+    fn write(&mut self) -> Result<(), AutomergeError> {
+        self.write_object(&ObjectId::Root, &amp::ObjectId::Root)?;
+        for (obj, key, value_op, target) in std::mem::take(&mut self.cursors) {
+            let target = self.map_id(&target);
+            self.push(amp::Op {
+                action: amp::OpType::Set(amp::ScalarValue::Cursor(target)),
+                obj,
+                key,
+                pred: vec![value_op].into(),
+                insert: false,
+            });
+        }
+        Ok(())
+    }
+
+    fn write_object(
+        &mut self,
+        object_id: &ObjectId,
+        new_id: &amp::ObjectId,
+    ) -> Result<(), AutomergeError> {
+        let backend = self.backend;
+        let object = backend.op_set.get_obj(object_id)?;
+        if !object.is_seq() {
+            let mut keys: Vec<&Key> = object.props.keys().collect();
+            keys.sort_by_key(|key| backend.actors.key_to_string(key));
+            for key in keys {
+                if let Some(op) = backend.winner(object, key) {
+                    let key = amp::Key::Map(backend.actors.key_to_string(key));
+                    self.write_value(object, op, new_id, key, false)?;
+                }
+            }
+            return Ok(());
+        }
+
+        let mut prev = amp::ElementId::Head;
+        for slot in object.insertions_in_order() {
+            let old_slot = backend.actors.export_opid(&slot);
+            let key = amp::Key::Seq(prev.clone());
+            let action = if let Some(mark) = object.marks.get(&slot) {
+                amp::OpType::MarkBegin(mark.clone())
+            } else if let Some(begin) = object.mark_ends.get(&slot) {
+                amp::OpType::MarkEnd(self.map_id(&backend.actors.export_opid(begin)))
+            } else if object.is_visible(slot) {
+                let element = object.element_of(slot);
+                // Safety: a visible element has at least one value
+                let op = backend.winner(object, &element.into()).unwrap();
+                let id = self.write_value(object, op, new_id, key, true)?;
+                self.ids.insert(old_slot, id.clone());
+                self.ids
+                    .insert(backend.actors.export_opid(&element), id.clone());
+                prev = amp::ElementId::Id(id);
+                continue;
+            } else {
+                continue;
+            };
+            let id = self.push(amp::Op {
+                action,
+                obj: new_id.clone(),
+                key,
+                pred: Vec::new().into(),
+                insert: true,
+            });
+            self.ids.insert(old_slot, id.clone());
+            prev = amp::ElementId::Id(id);
+        }
+        Ok(())
+    }
+
+    /// Write the value of `op`, returning the ID of the op which sets it
+    fn write_value(
+        &mut self,
+        object: &ObjState,
+        op: &OpHandle,
+        obj: &amp::ObjectId,
+        key: amp::Key,
+        insert: bool,
+    ) -> Result<amp::OpId, AutomergeError> {
+        let value = op.adjusted_value();
+        let action = match op.child() {
+            Some(child) => amp::OpType::Make(self.backend.op_set.get_obj(&child)?.obj_type),
+            // The cursor itself is set later, this reserves the key or element
+            None if matches!(value, amp::ScalarValue::Cursor(_)) => {
+                amp::OpType::Set(amp::ScalarValue::Null)
+            }
+            None => amp::OpType::Set(value.clone()),
+        };
+        let id = self.push(amp::Op {
+            action,
+            obj: obj.clone(),
+            key: key.clone(),
+            pred: Vec::new().into(),
+            insert,
+        });
+        self.ids
+            .insert(self.backend.actors.export_opid(&op.id), id.clone());
+        if let amp::ScalarValue::Cursor(target) = value {
+            let key = if object.is_seq() {
+                amp::Key::Seq(amp::ElementId::Id(id.clone()))
+            } else {
+                key
+            };
+            self.cursors.push((obj.clone(), key, id.clone(), target));
+        } else if let Some(child) = op.child() {
+            self.write_object(&child, &amp::ObjectId::Id(id.clone()))?;
+        }
+        Ok(id)
+    }
+
+    fn push(&mut self, op: amp::Op) -> amp::OpId {
+        self.ops.push(op);
+        self.actor.op_id_at(self.ops.len() as u64)
+    }
+
+    fn map_id(&self, id: &amp::OpId) -> amp::OpId {
+        self.ids.get(id).cloned().unwrap_or_else(|| id.clone())
+    }
+}
+
+/// Moves the changes made since the baseline on top of the snapshot
+struct Rewriter {
+    /// The op in the snapshot which replaces each discarded op
+    ids: HashMap<amp::OpId, amp::OpId>,
+    /// The number of ops in the snapshot, which is added to the counter of every later op
+    shift: u64,
+    /// The op counters used by each actor after the baseline
+    later: HashMap<amp::ActorId, Vec<std::ops::RangeInclusive<u64>>>,
+}
+
+impl Rewriter {
+    fn rewrite(
+        &self,
+        change: &Change,
+        seq: u64,
+        deps: Vec<amp::ChangeHash>,
+    ) -> Result<amp::Change, CompactionError> {
+        let mut decoded = change.decode();
+        let opid = |id: &amp::OpId| {
+            self.map(id)
+                .ok_or_else(|| CompactionError::DanglingReference {
+                    change: change.hash,
+                    opid: id.clone(),
+                })
+        };
+        let mut operations = Vec::with_capacity(decoded.operations.len());
+        for op in decoded.operations {
+            let obj = match op.obj {
+                amp::ObjectId::Id(id) => amp::ObjectId::Id(opid(&id)?),
+                amp::ObjectId::Root => amp::ObjectId::Root,
+            };
+            let key = match op.key {
+                amp::Key::Seq(amp::ElementId::Id(id)) => amp::Key::Seq(opid(&id)?.into()),
+                key => key,
+            };
+            let action = match op.action {
+                amp::OpType::Move(source) => amp::OpType::Move(opid(&source)?),
+                amp::OpType::MarkEnd(begin) => amp::OpType::MarkEnd(opid(&begin)?),
+                amp::OpType::Set(amp::ScalarValue::Cursor(target)) => {
+                    amp::OpType::Set(amp::ScalarValue::Cursor(opid(&target)?))
+                }
+                action => action,
+            };
+            // Conflicting values and deleted elements are not in the snapshot, so there's
+            // nothing left to overwrite
+            let pred = op.pred.into_iter().filter_map(|id| self.map(&id)).collect();
+            operations.push(amp::Op {
+                action,
+                obj,
+                key,
+                pred,
+                insert: op.insert,
+            });
+        }
+        decoded.operations = operations;
+        decoded.hash = None;
+        decoded.seq = seq;
+        decoded.start_op += self.shift;
+        decoded.deps = deps;
+        Ok(decoded)
+    }
+
+    /// The new ID of the op `id`, or `None` if it was discarded
+    fn map(&self, id: &amp::OpId) -> Option<amp::OpId> {
+        let is_later = self
+            .later
+            .get(&id.1)
+            .is_some_and(|ranges| ranges.iter().any(|range| range.contains(&id.0)));
+        if is_later {
+            Some(id.increment_by(self.shift))
+        } else {
+            self.ids.get(id).cloned()
+        }
+    }
+}
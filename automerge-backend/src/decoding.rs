@@ -47,6 +47,74 @@ pub enum Error {
     Leb128(#[from] leb128::read::Error),
     #[error(transparent)]
     Io(#[from] io::Error),
+    #[error("{source} (while reading {field} at byte {offset})")]
+    InField {
+        field: &'static str,
+        offset: usize,
+        #[source]
+        source: Box<Error>,
+    },
+    #[error("{source} (in chunk {index} at byte {offset})")]
+    InChunk {
+        index: usize,
+        offset: usize,
+        #[source]
+        source: Box<Error>,
+    },
+    #[error("Column {column} at byte {offset} could not be decompressed: {source}")]
+    DecompressColumn {
+        column: u32,
+        offset: usize,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Column {column} could not be decoded past byte {offset}")]
+    CorruptColumn { column: &'static str, offset: usize },
+    #[error("Column {column} has {found} rows but {expected} were expected")]
+    ColumnLength {
+        column: &'static str,
+        expected: usize,
+        found: usize,
+    },
+    #[error("Column {column} should contain {expected} bytes of values but has {found}")]
+    ValueColumnLength {
+        column: &'static str,
+        expected: usize,
+        found: usize,
+    },
+    #[error("{} errors: {}", .0.len(), display_all(.0))]
+    Multiple(Vec<Error>),
+    #[error(
+        "Data was compressed with dictionary {found:02x?} but dictionary {expected:02x?} was used"
+    )]
+    WrongDictionary { expected: [u8; 4], found: [u8; 4] },
+}
+
+fn display_all(errors: &[Error]) -> String {
+    errors
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+impl Error {
+    /// Wrap this error with the name of the field being read and the byte it started at.
+    pub(crate) fn in_field(self, field: &'static str, offset: usize) -> Self {
+        Error::InField {
+            field,
+            offset,
+            source: Box::new(self),
+        }
+    }
+
+    /// The errors contained in this one, a single error for anything other than `Multiple`.
+    pub fn errors(&self) -> Vec<&Error> {
+        match self {
+            Error::Multiple(errors) => errors.iter().collect(),
+            other => vec![other],
+        }
+    }
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -136,6 +204,20 @@ impl<'a> From<Cow<'a, [u8]>> for BooleanDecoder<'a> {
     }
 }
 
+impl BooleanDecoder<'_> {
+    /// Count the values in the column without materialising them, returning the byte offset at
+    /// which decoding failed if the data is corrupt.
+    pub(crate) fn count_rows(mut self) -> Result<usize, usize> {
+        let mut rows = 0_usize;
+        while !self.decoder.done() {
+            let offset = self.decoder.offset;
+            let run: usize = self.decoder.read().map_err(|_| offset)?;
+            rows = rows.checked_add(run).ok_or(offset)?;
+        }
+        Ok(rows)
+    }
+}
+
 // this is an endless iterator that returns false after input is exhausted
 impl<'a> Iterator for BooleanDecoder<'a> {
     type Item = bool;
@@ -173,6 +255,36 @@ impl<'a, T> From<Cow<'a, [u8]>> for RleDecoder<'a, T> {
     }
 }
 
+impl<T> RleDecoder<'_, T>
+where
+    T: Debug + Decodable,
+{
+    /// Count the values (including nulls) in the column, returning the byte offset at which
+    /// decoding failed if the data is corrupt.
+    pub(crate) fn count_rows(mut self) -> Result<usize, usize> {
+        let mut rows = 0_usize;
+        while !self.decoder.done() {
+            let offset = self.decoder.offset;
+            let count: i64 = self.decoder.read().map_err(|_| offset)?;
+            let run = match count {
+                count if count > 0 => {
+                    self.decoder.read::<T>().map_err(|_| offset)?;
+                    count as usize
+                }
+                count if count < 0 => {
+                    for _ in 0..count.unsigned_abs() {
+                        self.decoder.read::<T>().map_err(|_| offset)?;
+                    }
+                    count.unsigned_abs() as usize
+                }
+                _ => self.decoder.read::<usize>().map_err(|_| offset)?,
+            };
+            rows = rows.checked_add(run).ok_or(offset)?;
+        }
+        Ok(rows)
+    }
+}
+
 // this decoder needs to be able to send type T or 'null'
 // it is an endless iterator that will return all 'null's
 // once input is exhausted
@@ -241,16 +353,18 @@ impl<'a> From<Cow<'a, [u8]>> for DeltaDecoder<'a> {
     }
 }
 
+impl DeltaDecoder<'_> {
+    pub(crate) fn count_rows(self) -> Result<usize, usize> {
+        self.rle.count_rows()
+    }
+}
+
 impl<'a> Iterator for DeltaDecoder<'a> {
     type Item = Option<u64>;
 
     fn next(&mut self) -> Option<Option<u64>> {
         if let Some(delta) = self.rle.next()? {
-            if delta < 0 {
-                self.absolute_val -= delta.abs() as u64;
-            } else {
-                self.absolute_val += delta as u64;
-            }
+            self.absolute_val = self.absolute_val.wrapping_add(delta as u64);
             Some(Some(self.absolute_val))
         } else {
             Some(None)
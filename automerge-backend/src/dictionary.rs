@@ -0,0 +1,232 @@
+use std::io::{Read, Write};
+
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+use sha2::{Digest, Sha256};
+
+use crate::{decoding, Backend, Change};
+
+/// DEFLATE can only refer back this far, so there is no point in a bigger dictionary
+const MAX_DICTIONARY_SIZE: usize = 32 * 1024;
+const ID_BYTES: usize = 4;
+/// zstd dictionaries are trained on at most this many bytes of the most recent changes
+#[cfg(feature = "zstd")]
+const MAX_ZSTD_SAMPLES_SIZE: usize = 1024 * 1024;
+#[cfg(feature = "zstd")]
+const MAX_ZSTD_DICTIONARY_SIZE: usize = 32 * 1024;
+#[cfg(feature = "zstd")]
+const ZSTD_LEVEL: i32 = 19;
+/// Hashed in front of the bytes of a zstd dictionary, so it never has the same ID as a DEFLATE
+/// dictionary with the same bytes
+#[cfg(feature = "zstd")]
+const ZSTD_ID_SALT: &[u8] = b"zstd";
+
+/// A dictionary of byte sequences which are common in the changes of a document.
+///
+/// Single changes and sync messages are usually too small to compress well on their own, but they
+/// look a lot like the changes which came before them. Compressing them with a dictionary taken
+/// from those changes (see [`Backend::train_compression_dictionary`]) makes them much smaller.
+/// Both sides need the same dictionary, so it has to be exchanged once with [`Self::as_bytes`] and
+/// [`Self::from_bytes`].
+///
+/// The compressed data is prefixed with the ID of the dictionary so that using the wrong one is
+/// detected. With [`Self::train`] the rest is a DEFLATE stream which continues on from the
+/// dictionary. With the `zstd` feature [`Self::train_zstd`] trains a zstd dictionary instead,
+/// which compresses better but isn't available everywhere, e.g. not in the wasm build.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompressionDictionary {
+    bytes: Vec<u8>,
+    id: [u8; ID_BYTES],
+    method: Method,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Method {
+    /// The dictionary itself, deflated up to a sync flush
+    Deflate { prefix: Vec<u8> },
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+impl CompressionDictionary {
+    /// Build a dictionary from the bodies of `changes`, preferring the later ones.
+    pub fn train<'a, I>(changes: I) -> Self
+    where
+        I: IntoIterator<Item = &'a Change>,
+        I::IntoIter: DoubleEndedIterator,
+    {
+        let mut bodies = Vec::new();
+        let mut size = 0;
+        for change in changes.into_iter().rev() {
+            if size >= MAX_DICTIONARY_SIZE {
+                break;
+            }
+            let body = change.body();
+            let body = &body[body.len().saturating_sub(MAX_DICTIONARY_SIZE - size)..];
+            size += body.len();
+            bodies.push(body);
+        }
+        // the most recent changes go last, where matches are cheapest
+        Self::from_bytes(bodies.into_iter().rev().flatten().copied().collect())
+    }
+
+    /// Build a zstd dictionary from the bodies of `changes`, preferring the later ones.
+    ///
+    /// zstd can't train on only a few changes, in which case the most recent changes are used as
+    /// they are.
+    #[cfg(feature = "zstd")]
+    pub fn train_zstd<'a, I>(changes: I) -> Self
+    where
+        I: IntoIterator<Item = &'a Change>,
+        I::IntoIter: DoubleEndedIterator,
+    {
+        let mut samples = Vec::new();
+        let mut size = 0;
+        for change in changes.into_iter().rev() {
+            if size >= MAX_ZSTD_SAMPLES_SIZE {
+                break;
+            }
+            size += change.body().len();
+            samples.push(change.body());
+        }
+        let bytes =
+            zstd::dict::from_samples(&samples, MAX_ZSTD_DICTIONARY_SIZE).unwrap_or_else(|_| {
+                let mut raw: Vec<u8> = samples.into_iter().rev().flatten().copied().collect();
+                raw.drain(..raw.len().saturating_sub(MAX_ZSTD_DICTIONARY_SIZE));
+                raw
+            });
+        CompressionDictionary {
+            id: dictionary_id(&[ZSTD_ID_SALT, &bytes]),
+            bytes,
+            method: Method::Zstd,
+        }
+    }
+
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        let (mut prefix, prefix_len) = deflate_after(&bytes, &[]);
+        prefix.truncate(prefix_len);
+        CompressionDictionary {
+            id: dictionary_id(&[&bytes]),
+            bytes,
+            method: Method::Deflate { prefix },
+        }
+    }
+
+    /// Load a dictionary built by [`Self::train_zstd`]
+    #[cfg(feature = "zstd")]
+    pub fn from_zstd_bytes(bytes: Vec<u8>) -> Result<Self, decoding::Error> {
+        zstd::bulk::Compressor::with_dictionary(ZSTD_LEVEL, &bytes)?;
+        Ok(CompressionDictionary {
+            id: dictionary_id(&[ZSTD_ID_SALT, &bytes]),
+            bytes,
+            method: Method::Zstd,
+        })
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// The first bytes of the SHA-256 hash of the dictionary
+    pub fn id(&self) -> [u8; ID_BYTES] {
+        self.id
+    }
+
+    pub fn compress(&self, data: &[u8]) -> Vec<u8> {
+        let mut result = self.id.to_vec();
+        match &self.method {
+            Method::Deflate { .. } => {
+                let (deflated, prefix_len) = deflate_after(&self.bytes, data);
+                result.extend(&deflated[prefix_len..]);
+            }
+            #[cfg(feature = "zstd")]
+            Method::Zstd => result.extend(zstd_compress(&self.bytes, data)),
+        }
+        result
+    }
+
+    pub fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, decoding::Error> {
+        if data.len() < ID_BYTES {
+            return Err(decoding::Error::NotEnoughBytes);
+        }
+        let mut found = [0; ID_BYTES];
+        found.copy_from_slice(&data[..ID_BYTES]);
+        if found != self.id {
+            return Err(decoding::Error::WrongDictionary {
+                expected: self.id,
+                found,
+            });
+        }
+        match &self.method {
+            Method::Deflate { prefix } => {
+                let mut decoder = DeflateDecoder::new(prefix.as_slice().chain(&data[ID_BYTES..]));
+                let mut result = Vec::new();
+                decoder.read_to_end(&mut result)?;
+                if result.len() < self.bytes.len() {
+                    return Err(decoding::Error::NotEnoughBytes);
+                }
+                Ok(result.split_off(self.bytes.len()))
+            }
+            #[cfg(feature = "zstd")]
+            Method::Zstd => {
+                let mut decoder =
+                    zstd::stream::read::Decoder::with_dictionary(&data[ID_BYTES..], &self.bytes)?;
+                let mut result = Vec::new();
+                decoder.read_to_end(&mut result)?;
+                Ok(result)
+            }
+        }
+    }
+}
+
+/// The first bytes of the SHA-256 hash of `parts`
+fn dictionary_id(parts: &[&[u8]]) -> [u8; ID_BYTES] {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    let mut id = [0; ID_BYTES];
+    id.copy_from_slice(&hasher.finalize()[..ID_BYTES]);
+    id
+}
+
+#[cfg(feature = "zstd")]
+fn zstd_compress(dictionary: &[u8], data: &[u8]) -> Vec<u8> {
+    use zstd::stream::raw::CParameter;
+
+    // The dictionary was checked when it was built, and compressing into a `Vec` can't fail
+    let mut compressor = zstd::bulk::Compressor::with_dictionary(ZSTD_LEVEL, dictionary).unwrap();
+    // the ID in front of the data already says which dictionary it needs
+    compressor
+        .set_parameter(CParameter::DictIdFlag(false))
+        .unwrap();
+    compressor.compress(data).unwrap()
+}
+
+/// Deflate `dictionary` followed by `data`, returning the result and where the part for `data`
+/// starts
+fn deflate_after(dictionary: &[u8], data: &[u8]) -> (Vec<u8>, usize) {
+    // Writing to a `Vec` can't fail
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(dictionary).unwrap();
+    // a sync flush ends the block on a byte boundary, so the rest can follow on from any stream
+    // which produces the dictionary
+    encoder.flush().unwrap();
+    let prefix_len = encoder.get_ref().len();
+    encoder.write_all(data).unwrap();
+    (encoder.finish().unwrap(), prefix_len)
+}
+
+impl Backend {
+    /// A dictionary for compressing future changes of this document, see
+    /// [`CompressionDictionary`]
+    pub fn train_compression_dictionary(&self) -> CompressionDictionary {
+        CompressionDictionary::train(&self.history)
+    }
+
+    /// A zstd dictionary for compressing future changes of this document, see
+    /// [`CompressionDictionary::train_zstd`]
+    #[cfg(feature = "zstd")]
+    pub fn train_zstd_compression_dictionary(&self) -> CompressionDictionary {
+        CompressionDictionary::train_zstd(&self.history)
+    }
+}
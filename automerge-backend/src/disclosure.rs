@@ -0,0 +1,35 @@
+use std::collections::HashSet;
+
+use automerge_protocol as amp;
+
+use crate::{error::AutomergeError, Backend, Change};
+
+impl Backend {
+    /// All changes made by any of `actors`, in the order they were applied to this backend.
+    pub fn get_changes_by_actors(&self, actors: &[amp::ActorId]) -> Vec<&Change> {
+        self.history
+            .iter()
+            .filter(|change| actors.contains(change.actor_id()))
+            .collect()
+    }
+
+    /// Build a new document out of the changes made by `actors`, e.g. to share only your own
+    /// edits with a reviewer.
+    ///
+    /// Only changes which are causally self-contained are included: a change which depends on a
+    /// change by some other actor is left out, and so is everything that depends on it in turn.
+    pub fn restrict_to_actors(&self, actors: &[amp::ActorId]) -> Result<Self, AutomergeError> {
+        let mut included = HashSet::new();
+        let mut changes = Vec::new();
+        // the history is in causal order, so every dependency is visited before its dependents
+        for change in self.get_changes_by_actors(actors) {
+            if change.deps.iter().all(|dep| included.contains(dep)) {
+                included.insert(change.hash);
+                changes.push(change.clone());
+            }
+        }
+        let mut backend = Self::new();
+        backend.load_changes(changes)?;
+        Ok(backend)
+    }
+}
@@ -0,0 +1,86 @@
+use automerge_protocol as amp;
+use nonzero_ext::nonzero;
+
+use crate::{error::AutomergeError, internal::InternalOpType, Backend};
+
+/// Everything that has happened to a single element of a list or text object.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ElementHistory {
+    /// The operation which inserted the element.
+    pub insert: ElementOp,
+    /// Operations which overwrote or incremented the value of the element, in the order they
+    /// were applied.
+    pub updates: Vec<ElementOp>,
+    /// Operations which deleted the element. There can be more than one if several actors
+    /// deleted it concurrently.
+    pub deletions: Vec<ElementOp>,
+}
+
+/// An operation on a sequence element along with the change it came from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ElementOp {
+    pub op_id: amp::OpId,
+    pub action: amp::OpType,
+    pub change: amp::ChangeHash,
+    pub time: i64,
+    /// When this backend received `change`, see [`Backend::received_at`]
+    pub received_at: Option<i64>,
+}
+
+impl Backend {
+    /// Returns the insertion, updates and deletions of `element` in the sequence `object`.
+    pub fn element_history(
+        &self,
+        object: &amp::ObjectId,
+        element: &amp::ElementId,
+    ) -> Result<ElementHistory, AutomergeError> {
+        let missing = || AutomergeError::MissingElement(object.clone(), element.clone());
+        let element_opid = element.as_opid().ok_or_else(missing)?;
+        let element_key = amp::Key::Seq(element.clone());
+
+        let mut insert = None;
+        let mut updates = Vec::new();
+        let mut deletions = Vec::new();
+        for change in &self.history {
+            for (i, op) in change.iter_ops().enumerate() {
+                if op.obj.as_ref() != object {
+                    continue;
+                }
+                let op_id = amp::OpId(change.start_op + i as u64, change.actor_id().clone());
+                let is_insert = op.insert && &op_id == element_opid;
+                if !is_insert && (op.insert || op.key.as_ref() != &element_key) {
+                    continue;
+                }
+                let action = match op.action {
+                    InternalOpType::Make(obj_type) => amp::OpType::Make(obj_type),
+                    InternalOpType::Del => amp::OpType::Del(nonzero!(1_u32)),
+                    InternalOpType::Inc(by) => amp::OpType::Inc(by),
+                    InternalOpType::Set(value) => amp::OpType::Set(value),
+                    InternalOpType::Move(source) => amp::OpType::Move(source),
+                    InternalOpType::MarkBegin(mark) => amp::OpType::MarkBegin(mark),
+                    InternalOpType::MarkEnd(begin) => amp::OpType::MarkEnd(begin),
+                };
+                let element_op = ElementOp {
+                    op_id,
+                    action,
+                    change: change.hash,
+                    time: change.time,
+                    received_at: self.received_at(&change.hash),
+                };
+                if is_insert {
+                    insert = Some(element_op);
+                } else if let amp::OpType::Del(_) = element_op.action {
+                    deletions.push(element_op);
+                } else {
+                    updates.push(element_op);
+                }
+            }
+        }
+
+        Ok(ElementHistory {
+            insert: insert.ok_or_else(missing)?,
+            updates,
+            deletions,
+        })
+    }
+}
@@ -0,0 +1,33 @@
+use std::sync::OnceLock;
+
+use crate::{change::encode_document, encoding::ColumnCompression, Backend};
+
+static EMPTY_DOCUMENT: OnceLock<Vec<u8>> = OnceLock::new();
+
+impl Backend {
+    /// Whether any change has been applied to or queued in this document. An empty document has
+    /// no heads and saves to exactly [`Backend::empty_document`].
+    pub fn is_empty(&self) -> bool {
+        self.history.is_empty() && self.queue.is_empty()
+    }
+
+    /// The bytes [`Backend::save`] produces for a document which has never been changed. They
+    /// are the same for every empty document and every version of this library, so storage
+    /// can recognise a never-edited document without loading it.
+    ///
+    /// # Panics
+    ///
+    /// Never, encoding a document with no changes can't fail.
+    pub fn empty_document() -> &'static [u8] {
+        EMPTY_DOCUMENT.get_or_init(|| {
+            encode_document(&[], ColumnCompression::Deflate)
+                .expect("encoding a document with no changes can't fail")
+        })
+    }
+
+    /// Whether `bytes` is a saved document with no changes in it. No bytes at all also count,
+    /// as `load` reads them as an empty document.
+    pub fn is_empty_document(bytes: &[u8]) -> bool {
+        bytes.is_empty() || bytes == Self::empty_document()
+    }
+}
@@ -12,6 +12,17 @@ use smol_str::SmolStr;
 use crate::columnar::COLUMN_TYPE_DEFLATE;
 
 pub(crate) const DEFLATE_MIN_SIZE: usize = 256;
+#[cfg(feature = "zstd")]
+const ZSTD_COLUMN_LEVEL: i32 = 19;
+
+/// How the columns of a saved document are compressed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ColumnCompression {
+    Deflate,
+    /// Only readers supporting [`crate::ZSTD_COLUMNS_FEATURE`] can load the document
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
 
 /// The error type for encoding operations.
 #[derive(Debug, thiserror::Error)]
@@ -78,8 +89,9 @@ impl DeltaEncoder {
     }
 
     pub fn append_value(&mut self, value: u64) {
+        // the deltas of signed columns like the change time wrap around
         self.rle
-            .append_value(value as i64 - self.absolute_value as i64);
+            .append_value((value as i64).wrapping_sub(self.absolute_value as i64));
         self.absolute_value = value;
     }
 
@@ -336,7 +348,7 @@ pub(crate) struct ColData {
     pub col: u32,
     pub data: Vec<u8>,
     #[cfg(debug_assertions)]
-    has_been_deflated: bool,
+    has_been_compressed: bool,
 }
 
 impl ColData {
@@ -345,7 +357,7 @@ impl ColData {
             col: col_id,
             data,
             #[cfg(debug_assertions)]
-            has_been_deflated: false,
+            has_been_compressed: false,
         }
     }
 
@@ -358,19 +370,30 @@ impl ColData {
         Ok(len)
     }
 
-    pub fn deflate(&mut self) {
+    /// Compress the column if it is big enough to be worth it. Columns compressed with zstd are
+    /// flagged with `COLUMN_TYPE_DEFLATE` as well, see `columnar::decompress_column`.
+    pub fn compress(&mut self, compression: ColumnCompression) {
         #[cfg(debug_assertions)]
         {
-            debug_assert!(!self.has_been_deflated);
-            self.has_been_deflated = true;
+            debug_assert!(!self.has_been_compressed);
+            self.has_been_compressed = true;
         }
         if self.data.len() > DEFLATE_MIN_SIZE {
-            let mut deflated = Vec::new();
-            let mut deflater = DeflateEncoder::new(&self.data[..], Compression::default());
-            //This unwrap should be okay as we're reading and writing to in memory buffers
-            deflater.read_to_end(&mut deflated).unwrap();
+            //These unwraps should be okay as we're reading and writing to in memory buffers
+            let compressed = match compression {
+                ColumnCompression::Deflate => {
+                    let mut deflated = Vec::new();
+                    let mut deflater = DeflateEncoder::new(&self.data[..], Compression::default());
+                    deflater.read_to_end(&mut deflated).unwrap();
+                    deflated
+                }
+                #[cfg(feature = "zstd")]
+                ColumnCompression::Zstd => {
+                    zstd::bulk::compress(&self.data, ZSTD_COLUMN_LEVEL).unwrap()
+                }
+            };
             self.col |= COLUMN_TYPE_DEFLATE;
-            self.data = deflated;
+            self.data = compressed;
         }
     }
 }
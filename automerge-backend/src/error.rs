@@ -4,7 +4,10 @@ use std::fmt::Debug;
 use automerge_protocol as amp;
 use thiserror::Error;
 
-use crate::{decoding, encoding};
+use crate::{
+    compact::CompactionError, decoding, encoding, limits::LimitExceeded, quota::QuotaExceeded,
+    timestamps::TimeWentBackwards,
+};
 
 #[derive(Error, Debug)]
 pub enum AutomergeError {
@@ -58,8 +61,33 @@ pub enum AutomergeError {
     DecodingError(#[from] decoding::Error),
     #[error("Attempted to create a cursor for opid {opid} which was not an element in a sequence")]
     InvalidCursor { opid: amp::OpId },
+    #[error("Attempted to move {opid} which was not an element in the sequence being modified")]
+    InvalidMove { opid: amp::OpId },
+    #[error("Mark op {opid} was not an insertion into a text object, or ended a mark which does not exist")]
+    InvalidMark { opid: amp::OpId },
     #[error("A compressed chunk could not be decompressed")]
     BadCompressedChunk,
+    #[error("Object {0} is not a list or text object")]
+    NotASequence(amp::ObjectId),
+    #[error("Unknown changes: {0:?}")]
+    UnknownChanges(Vec<amp::ChangeHash>),
+    #[error("Document requires features which are not supported: {0:?}")]
+    UnsupportedFeatures(Vec<String>),
+    #[error("Change store error: {0}")]
+    ChangeStore(#[from] std::io::Error),
+    #[error("Change {hash:?} is corrupt: {source}")]
+    CorruptChange {
+        hash: amp::ChangeHash,
+        source: decoding::Error,
+    },
+    #[error(transparent)]
+    QuotaExceeded(#[from] QuotaExceeded),
+    #[error(transparent)]
+    TimeWentBackwards(#[from] TimeWentBackwards),
+    #[error(transparent)]
+    LimitExceeded(#[from] LimitExceeded),
+    #[error(transparent)]
+    Compaction(#[from] CompactionError),
 }
 
 #[derive(Error, Debug)]
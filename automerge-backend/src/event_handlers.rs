@@ -1,4 +1,7 @@
-use std::fmt::Debug;
+use std::{
+    fmt::Debug,
+    sync::{Mutex, PoisonError},
+};
 
 use crate::Change;
 
@@ -8,18 +11,25 @@ pub struct EventHandlerId(usize);
 /// A sequence of event handlers.
 ///
 /// This maintains the order of insertion so handlers will be called in a consistent order.
+///
+/// Handlers are only `Send`, they are behind a mutex so that a backend can still be shared
+/// between threads. It is only ever accessed through `&mut self`, so it is never locked.
 #[derive(Debug, Default)]
-pub struct EventHandlers(Vec<EventHandler>);
+pub struct EventHandlers(Mutex<Vec<EventHandler>>);
 
 impl Clone for EventHandlers {
     fn clone(&self) -> Self {
-        EventHandlers(Vec::new())
+        EventHandlers::default()
     }
 }
 
 impl EventHandlers {
+    fn handlers(&mut self) -> &mut Vec<EventHandler> {
+        self.0.get_mut().unwrap_or_else(PoisonError::into_inner)
+    }
+
     pub(crate) fn before_apply_change(&mut self, change: &Change) {
-        for handler in &mut self.0 {
+        for handler in self.handlers() {
             if let EventHandler::BeforeApplyChange(f) = handler {
                 f.0(change);
             }
@@ -27,7 +37,7 @@ impl EventHandlers {
     }
 
     pub(crate) fn after_apply_change(&mut self, change: &Change) {
-        for handler in &mut self.0 {
+        for handler in self.handlers() {
             if let EventHandler::AfterApplyChange(f) = handler {
                 f.0(change);
             }
@@ -36,14 +46,16 @@ impl EventHandlers {
 
     /// Adds the event handler and returns the id of the handler.
     pub fn add_handler(&mut self, handler: EventHandler) -> EventHandlerId {
-        self.0.push(handler);
-        EventHandlerId(self.0.len() - 1)
+        let handlers = self.handlers();
+        handlers.push(handler);
+        EventHandlerId(handlers.len() - 1)
     }
 
     /// Remove the handler with the given id, returning whether it removed a handler or not.
     pub fn remove_handler(&mut self, id: EventHandlerId) -> bool {
-        if id.0 < self.0.len() {
-            self.0.remove(id.0);
+        let handlers = self.handlers();
+        if id.0 < handlers.len() {
+            handlers.remove(id.0);
             true
         } else {
             false
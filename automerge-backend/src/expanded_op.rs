@@ -40,6 +40,9 @@ impl<'a> Iterator for ExpandedOpIterator<'a> {
                 amp::OpType::Set(v) => InternalOpType::Set(v.clone()),
                 amp::OpType::Make(ot) => InternalOpType::Make(*ot),
                 amp::OpType::Inc(i) => InternalOpType::Inc(*i),
+                amp::OpType::Move(source) => InternalOpType::Move(source.clone()),
+                amp::OpType::MarkBegin(mark) => InternalOpType::MarkBegin(mark.clone()),
+                amp::OpType::MarkEnd(begin) => InternalOpType::MarkEnd(begin.clone()),
                 amp::OpType::Del(count) => {
                     if count.get() == 1 {
                         InternalOpType::Del
@@ -0,0 +1,253 @@
+use std::fmt;
+
+use automerge_protocol as amp;
+
+use crate::{
+    error::AutomergeError,
+    internal::{ElementId, InternalOpType, Key, ObjectId},
+    Backend, Change, PathElement,
+};
+
+/// How the value at a path came to be, see [`Backend::explain`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Explanation {
+    /// The path which was explained, relative to the root
+    pub path: Vec<PathElement>,
+    /// The ops which made the objects along the path, outermost first
+    pub containers: Vec<ExplainedOp>,
+    /// Every op on the value itself, in the order they were applied: for a map key the sets and
+    /// deletes of that key, for a sequence element its insertion, moves, updates and deletes.
+    pub ops: Vec<ExplainedOp>,
+}
+
+/// One op in an [`Explanation`], with the change it came from
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExplainedOp {
+    pub op_id: amp::OpId,
+    pub action: amp::OpType,
+    /// The ops this one overwrote. More than one means it resolved a conflict.
+    pub pred: Vec<amp::OpId>,
+    pub change: amp::ChangeHash,
+    pub seq: u64,
+    pub time: i64,
+    pub message: Option<String>,
+    /// The dependencies of `change`. More than one means the change was made on top of
+    /// concurrent changes which had been merged.
+    pub deps: Vec<amp::ChangeHash>,
+    /// Whether the value this op set is part of the current value, either as the winner or as
+    /// a conflict
+    pub current: bool,
+}
+
+impl ExplainedOp {
+    /// Whether this op's change was made after merging concurrent changes
+    pub fn is_merge(&self) -> bool {
+        self.deps.len() > 1
+    }
+
+    fn new(change: &Change, op_id: amp::OpId, action: amp::OpType, pred: Vec<amp::OpId>) -> Self {
+        ExplainedOp {
+            op_id,
+            action,
+            pred,
+            change: change.hash,
+            seq: change.seq,
+            time: change.time,
+            message: change.message(),
+            deps: change.deps.clone(),
+            current: false,
+        }
+    }
+}
+
+impl Explanation {
+    /// The ops whose values make up the current value, i.e. the winner and any conflicts
+    pub fn current(&self) -> impl Iterator<Item = &ExplainedOp> {
+        self.ops.iter().filter(|op| op.current)
+    }
+}
+
+impl Backend {
+    /// Explain how the value at `path` (from the root of the document) came to be: the changes
+    /// which made the objects containing it and every change which touched the value itself,
+    /// with the ops each one overwrote and whether it was made after a merge. This is meant for
+    /// working out why a document contains something unexpected; the [`fmt::Display`]
+    /// implementation of the result is a readable report.
+    ///
+    /// Returns `None` if an object along the path or the element at a list index doesn't exist.
+    /// A map key which has been deleted can still be explained.
+    pub fn explain(&self, path: &[PathElement]) -> Result<Option<Explanation>, AutomergeError> {
+        let mut containers = Vec::new();
+        let mut object_id = ObjectId::Root;
+        let last = if let Some((last, parents)) = path.split_last() {
+            for element in parents {
+                let op = self.visible_op(&object_id, element);
+                if let Some((op, child)) = op.and_then(|op| op.child().map(|child| (op, child))) {
+                    object_id = child;
+                    containers.push(self.explained_op(&self.actors.export_opid(&op.id))?);
+                } else {
+                    return Ok(None);
+                }
+            }
+            last
+        } else {
+            return Ok(None);
+        };
+
+        Ok(self.explain_value(path, last, &object_id, containers))
+    }
+
+    fn explain_value(
+        &self,
+        path: &[PathElement],
+        last: &PathElement,
+        object_id: &ObjectId,
+        containers: Vec<ExplainedOp>,
+    ) -> Option<Explanation> {
+        let object = self.op_set.objs.get(object_id)?;
+        let key = match (last, object.is_seq()) {
+            (PathElement::Key(key), false) => Key::Map(key.clone()),
+            (PathElement::Index(index), true) => {
+                let element = object
+                    .seq
+                    .into_iter()
+                    .map(|slot| object.element_of(*slot))
+                    .filter(|element| object.conflicts(&(*element).into()).next().is_some())
+                    .nth(*index)?;
+                element.into()
+            }
+            _ => return None,
+        };
+        let current: Vec<amp::OpId> = object
+            .conflicts(&key)
+            .map(|op| self.actors.export_opid(&op.id))
+            .collect();
+
+        let object = self.actors.export_obj(object_id);
+        let (amp_key, element) = match &key {
+            Key::Map(name) => (amp::Key::Map(name.clone()), None),
+            Key::Seq(element) => {
+                let element = match element {
+                    ElementId::Id(id) => self.actors.export_opid(id),
+                    ElementId::Head => return None,
+                };
+                (
+                    amp::Key::Seq(amp::ElementId::Id(element.clone())),
+                    Some(element),
+                )
+            }
+        };
+        let mut ops = Vec::new();
+        for change in &self.history {
+            for (i, op) in change.iter_ops().enumerate() {
+                if op.obj.as_ref() != &object {
+                    continue;
+                }
+                let op_id = amp::OpId(change.start_op + i as u64, change.actor_id().clone());
+                let relevant = match &element {
+                    None => op.key.as_ref() == &amp_key,
+                    Some(element) => {
+                        (op.insert && &op_id == element)
+                            || (!op.insert && op.key.as_ref() == &amp_key)
+                            || matches!(&op.action, InternalOpType::Move(source) if source == element)
+                    }
+                };
+                if relevant {
+                    let mut explained = ExplainedOp::new(
+                        change,
+                        op_id,
+                        amp::OpType::from(&op.action),
+                        op.pred.iter().cloned().collect(),
+                    );
+                    explained.current = current.contains(&explained.op_id);
+                    ops.push(explained);
+                }
+            }
+        }
+
+        Some(Explanation {
+            path: path.to_vec(),
+            containers,
+            ops,
+        })
+    }
+
+    fn explained_op(&self, op_id: &amp::OpId) -> Result<ExplainedOp, AutomergeError> {
+        let change = self
+            .change_containing(op_id)
+            .ok_or_else(|| AutomergeError::InvalidOpId(op_id.to_string()))?;
+        let index = (op_id.0 - change.start_op) as usize;
+        let op = change
+            .iter_ops()
+            .nth(index)
+            .ok_or_else(|| AutomergeError::InvalidOpId(op_id.to_string()))?;
+        let mut explained = ExplainedOp::new(
+            change,
+            op_id.clone(),
+            amp::OpType::from(&op.action),
+            op.pred.iter().cloned().collect(),
+        );
+        explained.current = true;
+        Ok(explained)
+    }
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "$")?;
+        for element in &self.path {
+            match element {
+                PathElement::Key(key) => write!(f, "[{:?}]", key.as_str())?,
+                PathElement::Index(index) => write!(f, "[{index}]")?,
+            }
+        }
+        writeln!(f)?;
+        for (depth, container) in self.containers.iter().enumerate() {
+            write!(f, "  in object made by ")?;
+            write_op(f, container)?;
+            writeln!(f, " (depth {})", depth + 1)?;
+        }
+        if self.ops.is_empty() {
+            writeln!(f, "  never set")?;
+        }
+        for op in &self.ops {
+            write!(f, "  {} ", if op.current { "*" } else { " " })?;
+            write_op(f, op)?;
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_op(f: &mut fmt::Formatter<'_>, op: &ExplainedOp) -> fmt::Result {
+    let action = match &op.action {
+        amp::OpType::Make(obj_type) => format!("make {obj_type:?}").to_lowercase(),
+        amp::OpType::Del(_) => "delete".to_string(),
+        amp::OpType::Inc(by) => format!("increment by {by}"),
+        amp::OpType::Set(value) => format!("set {value:?}"),
+        amp::OpType::MultiSet(values) => format!("set {values:?}"),
+        amp::OpType::Move(source) => format!("move {source}"),
+        amp::OpType::MarkBegin(mark) => format!("mark {}", mark.name),
+        amp::OpType::MarkEnd(begin) => format!("end mark {begin}"),
+    };
+    write!(
+        f,
+        "{} {} in change {} (seq {}, time {})",
+        op.op_id,
+        action,
+        &op.change.to_string()[..8],
+        op.seq,
+        op.time
+    )?;
+    if !op.pred.is_empty() {
+        let pred: Vec<String> = op.pred.iter().map(ToString::to_string).collect();
+        write!(f, ", overwrote {}", pred.join(", "))?;
+    }
+    if op.is_merge() {
+        write!(f, ", after merging {} heads", op.deps.len())?;
+    }
+    if let Some(message) = &op.message {
+        write!(f, ": {message}")?;
+    }
+    Ok(())
+}
@@ -0,0 +1,229 @@
+use std::{borrow::Cow, collections::BTreeSet};
+
+use crate::{
+    encoding::ColumnCompression, error::AutomergeError, internal::InternalOpType, Backend,
+};
+
+/// Required by documents containing move ops, which implementations without them would read as
+/// something else
+pub const MOVE_FEATURE: &str = "move";
+
+/// Required by documents containing marks, which implementations without them would read as
+/// something else
+pub const MARKS_FEATURE: &str = "marks";
+
+/// Required by documents saved with `Backend::save_zstd`, whose columns are compressed with
+/// zstd. It describes the saved file rather than the document, so it isn't kept once the
+/// document has been loaded.
+pub const ZSTD_COLUMNS_FEATURE: &str = "zstd-columns";
+
+/// The document features this implementation knows how to merge correctly.
+#[cfg(not(feature = "zstd"))]
+pub const SUPPORTED_FEATURES: &[&str] = &[MOVE_FEATURE, MARKS_FEATURE];
+/// The document features this implementation knows how to merge correctly.
+#[cfg(feature = "zstd")]
+pub const SUPPORTED_FEATURES: &[&str] = &[MOVE_FEATURE, MARKS_FEATURE, ZSTD_COLUMNS_FEATURE];
+
+/// Fail if `required` contains anything that is neither built in nor in `supported`.
+pub(crate) fn check_features(
+    required: &BTreeSet<String>,
+    supported: &[&str],
+) -> Result<(), AutomergeError> {
+    check_implemented(required, SUPPORTED_FEATURES, supported)
+}
+
+/// Like `check_features` for an implementation supporting only `implemented`, so that older
+/// readers can be tested
+fn check_implemented(
+    required: &BTreeSet<String>,
+    implemented: &[&str],
+    supported: &[&str],
+) -> Result<(), AutomergeError> {
+    let unsupported: Vec<_> = required
+        .iter()
+        .filter(|feature| {
+            !implemented.contains(&feature.as_str()) && !supported.contains(&feature.as_str())
+        })
+        .cloned()
+        .collect();
+    if unsupported.is_empty() {
+        Ok(())
+    } else {
+        Err(AutomergeError::UnsupportedFeatures(unsupported))
+    }
+}
+
+/// The features in `loaded` which belong to the document rather than to the file it was loaded
+/// from
+pub(crate) fn document_features(loaded: BTreeSet<String>) -> impl Iterator<Item = String> {
+    loaded
+        .into_iter()
+        .filter(|feature| feature != ZSTD_COLUMNS_FEATURE)
+}
+
+/// The features required to load a file which stores a document requiring `document` and whose
+/// columns are compressed with `compression`
+pub(crate) fn file_features(
+    document: &BTreeSet<String>,
+    compression: ColumnCompression,
+) -> Cow<'_, BTreeSet<String>> {
+    match compression {
+        ColumnCompression::Deflate => Cow::Borrowed(document),
+        #[cfg(feature = "zstd")]
+        ColumnCompression::Zstd => {
+            let mut features = document.clone();
+            features.insert(ZSTD_COLUMNS_FEATURE.to_owned());
+            Cow::Owned(features)
+        }
+    }
+}
+
+/// The features which are required because of the ops a document contains
+const OP_FEATURES: &[&str] = &[MOVE_FEATURE, MARKS_FEATURE];
+
+/// The feature a document containing an op with `action` requires, if any
+pub(crate) fn required_by(action: &InternalOpType) -> Option<&'static str> {
+    match action {
+        InternalOpType::Move(_) => Some(MOVE_FEATURE),
+        InternalOpType::MarkBegin(_) | InternalOpType::MarkEnd(_) => Some(MARKS_FEATURE),
+        _ => None,
+    }
+}
+
+impl Backend {
+    /// The features an implementation must support to load this document.
+    pub fn required_features(&self) -> &BTreeSet<String> {
+        &self.features
+    }
+
+    /// Declare that this document relies on `feature`, e.g. because it contains operations older
+    /// implementations would merge incorrectly.
+    ///
+    /// Required features are written to the start of the saved document, and loading it with an
+    /// implementation which doesn't support them fails with
+    /// [`AutomergeError::UnsupportedFeatures`] rather than producing a subtly wrong document.
+    /// Features which aren't in [`SUPPORTED_FEATURES`] can still be declared; loading the
+    /// document then requires [`Backend::load_with_features`]. The features of the operations
+    /// this library implements, like [`MOVE_FEATURE`], are required as soon as the document
+    /// contains such an operation.
+    pub fn require_feature<S: Into<String>>(&mut self, feature: S) {
+        let feature = feature.into();
+        self.declared_features.insert(feature.clone());
+        self.features.insert(feature);
+    }
+
+    /// Add the features `loaded` from the header of a saved document. The features of ops are
+    /// worked out from the ops themselves, so they aren't counted as declared.
+    pub(crate) fn add_loaded_features(&mut self, loaded: BTreeSet<String>) {
+        for feature in document_features(loaded) {
+            if !OP_FEATURES.contains(&feature.as_str()) {
+                self.declared_features.insert(feature.clone());
+            }
+            self.features.insert(feature);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+
+    use amp::SortedVec;
+    use automerge_protocol as amp;
+
+    use super::*;
+    use crate::Change;
+
+    fn change(operations: Vec<amp::Op>) -> Change {
+        amp::Change {
+            actor_id: "7b7723afd9e6480397a4d467b7693156".try_into().unwrap(),
+            seq: 1,
+            start_op: 1,
+            time: 0,
+            message: None,
+            hash: None,
+            deps: Vec::new(),
+            operations,
+            extra_bytes: Vec::new(),
+        }
+        .into()
+    }
+
+    /// Applies `op` after making a sequence of `obj_type` with one element, at op 1 and 2
+    fn backend_with(obj_type: amp::ObjType, op: amp::Op) -> Backend {
+        let actor: amp::ActorId = "7b7723afd9e6480397a4d467b7693156".try_into().unwrap();
+        let list = amp::ObjectId::from(actor.op_id_at(1));
+        let mut backend = Backend::new();
+        backend
+            .apply_changes(vec![change(vec![
+                amp::Op {
+                    action: amp::OpType::Make(obj_type),
+                    obj: amp::ObjectId::Root,
+                    key: "list".into(),
+                    insert: false,
+                    pred: SortedVec::new(),
+                },
+                amp::Op {
+                    action: amp::OpType::Set("a".into()),
+                    obj: list.clone(),
+                    key: amp::ElementId::Head.into(),
+                    insert: true,
+                    pred: SortedVec::new(),
+                },
+                amp::Op { obj: list, ..op },
+            ])])
+            .unwrap();
+        backend
+    }
+
+    #[test]
+    fn test_move_ops_require_move() {
+        let actor: amp::ActorId = "7b7723afd9e6480397a4d467b7693156".try_into().unwrap();
+        let backend = backend_with(
+            amp::ObjType::List,
+            amp::Op {
+                action: amp::OpType::Move(actor.op_id_at(2)),
+                obj: amp::ObjectId::Root,
+                key: amp::ElementId::Head.into(),
+                insert: true,
+                pred: SortedVec::new(),
+            },
+        );
+        assert!(backend.required_features().contains(MOVE_FEATURE));
+
+        let loaded = Backend::load(backend.save().unwrap()).unwrap();
+        assert!(loaded.required_features().contains(MOVE_FEATURE));
+        assert!(matches!(
+            check_implemented(loaded.required_features(), &[], &[]),
+            Err(AutomergeError::UnsupportedFeatures(features))
+                if features == vec![MOVE_FEATURE.to_string()]
+        ));
+    }
+
+    #[test]
+    fn test_marks_require_marks() {
+        let backend = backend_with(
+            amp::ObjType::Text,
+            amp::Op {
+                action: amp::OpType::MarkBegin(amp::MarkData {
+                    name: "bold".into(),
+                    value: true.into(),
+                }),
+                obj: amp::ObjectId::Root,
+                key: amp::ElementId::Head.into(),
+                insert: true,
+                pred: SortedVec::new(),
+            },
+        );
+        assert!(backend.required_features().contains(MARKS_FEATURE));
+        let saved = backend.save().unwrap();
+
+        // a reader which knows about moves but not marks refuses the document
+        let loaded = Backend::load(saved).unwrap();
+        assert!(matches!(
+            check_implemented(loaded.required_features(), &[MOVE_FEATURE], &[]),
+            Err(AutomergeError::UnsupportedFeatures(features))
+                if features == vec![MARKS_FEATURE.to_string()]
+        ));
+    }
+}
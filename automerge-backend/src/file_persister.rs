@@ -0,0 +1,301 @@
+use std::{
+    convert::TryInto,
+    fs::{self, File, OpenOptions},
+    io::{self, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::persister::Persister;
+
+const LOG_FILE: &str = "changes.log";
+const SNAPSHOT_FILE: &str = "snapshot";
+const SYNC_DIR: &str = "sync";
+/// Each record is the length of the change, its CRC-32 and the change
+const RECORD_HEADER_BYTES: usize = 8;
+
+/// A [`Persister`] which keeps a document in a directory: a snapshot file, a log which changes
+/// are appended to, and a file for the sync state of each peer.
+///
+/// Every record in the log carries a checksum, so a record which was only partly written when
+/// the process crashed is detected when the log is next opened. The log is truncated at the
+/// first bad record, losing only changes which were never flushed and so never acknowledged.
+///
+/// Once the log is longer than `max_log_bytes` the persister asks for a snapshot, which
+/// [`crate::PersistentBackend`] writes to a new snapshot file before emptying the log.
+#[derive(Debug)]
+pub struct FilePersister {
+    dir: PathBuf,
+    log: File,
+    log_bytes: u64,
+    max_log_bytes: u64,
+    /// Set when a failed append couldn't be undone, so the next one removes the torn record
+    torn: bool,
+}
+
+impl FilePersister {
+    /// Open the document in `dir`, creating the directory if it doesn't exist, and truncate its
+    /// log at the first record which is incomplete or corrupt.
+    pub fn open<P: AsRef<Path>>(dir: P, max_log_bytes: u64) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(dir.join(SYNC_DIR))?;
+        let log_path = dir.join(LOG_FILE);
+        let valid_bytes = match fs::read(&log_path) {
+            Ok(bytes) => read_records(&bytes).1 as u64,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e),
+        };
+        let log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&log_path)?;
+        if log.metadata()?.len() > valid_bytes {
+            tracing::warn!(
+                valid_bytes,
+                "truncating change log after an incomplete or corrupt record"
+            );
+            log.set_len(valid_bytes)?;
+            log.sync_all()?;
+        }
+        Ok(Self {
+            dir,
+            log,
+            log_bytes: valid_bytes,
+            max_log_bytes,
+            torn: false,
+        })
+    }
+
+    /// The size of the log, in bytes
+    pub fn log_bytes(&self) -> u64 {
+        self.log_bytes
+    }
+
+    fn sync_state_path(&self, peer: &[u8]) -> PathBuf {
+        self.dir.join(SYNC_DIR).join(hex::encode(peer))
+    }
+}
+
+/// What appending to the log needs from a file, so that failed writes can be tested
+trait LogFile: Write + Seek {
+    fn set_len(&self, len: u64) -> io::Result<()>;
+}
+
+impl LogFile for File {
+    fn set_len(&self, len: u64) -> io::Result<()> {
+        File::set_len(self, len)
+    }
+}
+
+/// Append `record` to a log holding `valid_bytes` of complete records. If the write fails the
+/// log is cut back to `valid_bytes`, as a torn record would hide every record appended after
+/// it when the log is read. Returns whether the log may still hold a torn record.
+fn append_record<L: LogFile>(
+    log: &mut L,
+    valid_bytes: u64,
+    record: &[u8],
+) -> (bool, io::Result<()>) {
+    match log.write_all(record) {
+        Ok(()) => (false, Ok(())),
+        Err(e) => {
+            let torn = truncate(log, valid_bytes).is_err();
+            (torn, Err(e))
+        }
+    }
+}
+
+fn truncate<L: LogFile>(log: &mut L, len: u64) -> io::Result<()> {
+    log.set_len(len)?;
+    log.seek(SeekFrom::Start(len))?;
+    Ok(())
+}
+
+fn checksum(bytes: &[u8]) -> [u8; 4] {
+    crc32fast::hash(bytes).to_le_bytes()
+}
+
+/// The changes in the log and the number of bytes they take up, which is less than the length
+/// of the log if a record is incomplete or corrupt
+fn read_records(mut log: &[u8]) -> (Vec<Vec<u8>>, usize) {
+    let mut records = Vec::new();
+    let mut valid_bytes = 0;
+    while log.len() >= RECORD_HEADER_BYTES {
+        let len = u32::from_le_bytes(log[..4].try_into().unwrap()) as usize;
+        let record_end = match RECORD_HEADER_BYTES.checked_add(len) {
+            Some(end) if end <= log.len() => end,
+            _ => break,
+        };
+        let bytes = &log[RECORD_HEADER_BYTES..record_end];
+        if log[4..RECORD_HEADER_BYTES] != checksum(bytes) {
+            break;
+        }
+        records.push(bytes.to_vec());
+        valid_bytes += record_end;
+        log = &log[record_end..];
+    }
+    (records, valid_bytes)
+}
+
+/// Replace the contents of `path` so that a crash leaves either the old or the new contents.
+/// Once this returns the new contents will survive a crash.
+fn write_atomically(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    fs::rename(tmp, path)?;
+    sync_parent_dir(path)
+}
+
+/// Make a rename into the directory containing `path` durable, until then a crash can undo it
+#[cfg(unix)]
+fn sync_parent_dir(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(dir) => File::open(dir)?.sync_all(),
+        None => Ok(()),
+    }
+}
+
+/// Directories can't be opened as files on other platforms, where renames are assumed durable
+#[cfg(not(unix))]
+fn sync_parent_dir(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+fn read_if_exists(path: &Path) -> io::Result<Option<Vec<u8>>> {
+    match fs::read(path) {
+        Ok(bytes) => Ok(Some(bytes)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+impl Persister for FilePersister {
+    fn append_change(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let len: u32 = bytes
+            .len()
+            .try_into()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "change is too large"))?;
+        let mut record = Vec::with_capacity(RECORD_HEADER_BYTES + bytes.len());
+        record.extend_from_slice(&len.to_le_bytes());
+        record.extend_from_slice(&checksum(bytes));
+        record.extend_from_slice(bytes);
+        if self.torn {
+            truncate(&mut self.log, self.log_bytes)?;
+            self.torn = false;
+        }
+        let (torn, result) = append_record(&mut self.log, self.log_bytes, &record);
+        self.torn = torn;
+        result?;
+        self.log_bytes += record.len() as u64;
+        Ok(())
+    }
+
+    fn load_changes(&self) -> io::Result<Vec<Vec<u8>>> {
+        let log = fs::read(self.dir.join(LOG_FILE))?;
+        Ok(read_records(&log).0)
+    }
+
+    fn store_snapshot(&mut self, bytes: &[u8]) -> io::Result<()> {
+        // The rename of the snapshot has to be durable before the log is emptied, otherwise a
+        // crash could keep the empty log and lose the snapshot
+        write_atomically(&self.dir.join(SNAPSHOT_FILE), bytes)?;
+        // A crash before the log is emptied leaves changes in it which are also in the
+        // snapshot, loading them again does no harm
+        truncate(&mut self.log, 0)?;
+        self.log.sync_all()?;
+        self.log_bytes = 0;
+        self.torn = false;
+        Ok(())
+    }
+
+    fn load_snapshot(&self) -> io::Result<Option<Vec<u8>>> {
+        read_if_exists(&self.dir.join(SNAPSHOT_FILE))
+    }
+
+    fn store_sync_state(&mut self, peer: &[u8], bytes: &[u8]) -> io::Result<()> {
+        write_atomically(&self.sync_state_path(peer), bytes)
+    }
+
+    fn load_sync_state(&self, peer: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        read_if_exists(&self.sync_state_path(peer))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.log.sync_data()
+    }
+
+    fn wants_snapshot(&self) -> bool {
+        self.log_bytes > self.max_log_bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+
+    /// A log in memory which fails writes once it holds `capacity` bytes
+    struct ShortLog {
+        bytes: RefCell<Vec<u8>>,
+        capacity: usize,
+    }
+
+    impl Write for ShortLog {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let mut bytes = self.bytes.borrow_mut();
+            let n = buf.len().min(self.capacity.saturating_sub(bytes.len()));
+            if n == 0 {
+                return Err(io::Error::new(io::ErrorKind::WriteZero, "disk full"));
+            }
+            bytes.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Seek for ShortLog {
+        fn seek(&mut self, _: SeekFrom) -> io::Result<u64> {
+            Ok(self.bytes.borrow().len() as u64)
+        }
+    }
+
+    impl LogFile for ShortLog {
+        fn set_len(&self, len: u64) -> io::Result<()> {
+            self.bytes.borrow_mut().truncate(len as usize);
+            Ok(())
+        }
+    }
+
+    fn record(bytes: &[u8]) -> Vec<u8> {
+        let mut record = (bytes.len() as u32).to_le_bytes().to_vec();
+        record.extend_from_slice(&checksum(bytes));
+        record.extend_from_slice(bytes);
+        record
+    }
+
+    #[test]
+    fn test_short_write_is_rolled_back() {
+        let first = record(b"first");
+        let mut log = ShortLog {
+            bytes: RefCell::new(Vec::new()),
+            capacity: first.len() + 10,
+        };
+        assert!(append_record(&mut log, 0, &first).1.is_ok());
+
+        let valid = first.len() as u64;
+        let (torn, result) = append_record(&mut log, valid, &record(b"too long to fit"));
+        assert!(result.is_err());
+        assert!(!torn);
+        assert_eq!(log.bytes.borrow().len(), first.len());
+
+        // once there is room again the next record follows the first, not the torn one
+        log.capacity = usize::MAX;
+        assert!(append_record(&mut log, valid, &record(b"third")).1.is_ok());
+        let (records, _) = read_records(&log.bytes.borrow());
+        assert_eq!(records, vec![b"first".to_vec(), b"third".to_vec()]);
+    }
+}
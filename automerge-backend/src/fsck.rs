@@ -0,0 +1,223 @@
+use std::collections::{HashMap, HashSet};
+
+use automerge_protocol as amp;
+
+use crate::{error::AutomergeError, Backend};
+
+/// A way in which the indices of a backend disagree with its changes, see [`Backend::fsck`].
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum Inconsistency {
+    #[error("change {hash:?} is listed at {found:?} in the history index but is at {expected}")]
+    HistoryIndex {
+        hash: amp::ChangeHash,
+        expected: usize,
+        found: Option<usize>,
+    },
+    #[error("the history index has {found} entries for {expected} changes")]
+    HistoryIndexSize { expected: usize, found: usize },
+    #[error("the history index of actor {actor} has the wrong change for seq {seq}")]
+    ActorSeq { actor: amp::ActorId, seq: u64 },
+    #[error("the clock has {found} changes for actor {actor} but the history has {expected}")]
+    Clock {
+        actor: amp::ActorId,
+        expected: u64,
+        found: u64,
+    },
+    #[error("change {hash:?} depends on {dep:?} which is not before it in the history")]
+    MissingDependency {
+        hash: amp::ChangeHash,
+        dep: amp::ChangeHash,
+    },
+    #[error("the max op is {found} but the changes go up to {expected}")]
+    MaxOp { expected: u64, found: u64 },
+    #[error("the heads are {found:?} but the history has heads {expected:?}")]
+    Heads {
+        expected: Vec<amp::ChangeHash>,
+        found: Vec<amp::ChangeHash>,
+    },
+}
+
+impl Backend {
+    /// Check the indices this backend keeps (the history index, the clock, the heads and the max
+    /// op) against the changes it holds.
+    ///
+    /// An empty result means everything is consistent. Anything else points to a bug, or to a
+    /// partial write in the storage the document was loaded from; see [`Backend::repair`].
+    pub fn fsck(&self) -> Vec<Inconsistency> {
+        let mut problems = Vec::new();
+
+        if self.history_index.len() != self.history.len() {
+            problems.push(Inconsistency::HistoryIndexSize {
+                expected: self.history.len(),
+                found: self.history_index.len(),
+            });
+        }
+        let mut counts: HashMap<&amp::ActorId, u64> = HashMap::new();
+        let mut seen = HashSet::new();
+        let mut depended_on = HashSet::new();
+        let mut max_op = 0;
+        for (index, change) in self.history.iter().enumerate() {
+            let found = self.history_index.get(&change.hash).copied();
+            if found != Some(index) {
+                problems.push(Inconsistency::HistoryIndex {
+                    hash: change.hash,
+                    expected: index,
+                    found,
+                });
+            }
+            for dep in &change.deps {
+                if !seen.contains(dep) {
+                    problems.push(Inconsistency::MissingDependency {
+                        hash: change.hash,
+                        dep: *dep,
+                    });
+                }
+                depended_on.insert(*dep);
+            }
+            seen.insert(change.hash);
+            *counts.entry(change.actor_id()).or_default() += 1;
+            max_op = max_op.max(change.max_op());
+        }
+
+        let mut actors: HashSet<&amp::ActorId> = counts.keys().copied().collect();
+        actors.extend(self.states.keys());
+        for actor in actors {
+            let indices = self.states.get(actor).map_or(&[][..], Vec::as_slice);
+            let expected = counts.get(actor).copied().unwrap_or(0);
+            if indices.len() as u64 != expected {
+                problems.push(Inconsistency::Clock {
+                    actor: actor.clone(),
+                    expected,
+                    found: indices.len() as u64,
+                });
+            }
+            for (i, index) in indices.iter().enumerate() {
+                let seq = i as u64 + 1;
+                let matches = matches!(
+                    self.history.get(*index),
+                    Some(change) if change.actor_id() == actor && change.seq == seq
+                );
+                if !matches {
+                    problems.push(Inconsistency::ActorSeq {
+                        actor: actor.clone(),
+                        seq,
+                    });
+                }
+            }
+        }
+
+        if self.op_set.max_op != max_op {
+            problems.push(Inconsistency::MaxOp {
+                expected: max_op,
+                found: self.op_set.max_op,
+            });
+        }
+
+        let mut heads: Vec<_> = seen.difference(&depended_on).copied().collect();
+        heads.sort_unstable();
+        let found = self.get_heads();
+        if heads != found {
+            problems.push(Inconsistency::Heads {
+                expected: heads,
+                found,
+            });
+        }
+
+        problems
+    }
+
+    /// Rebuild all the indices and the document state from the changes, to recover from the
+    /// problems reported by [`Backend::fsck`].
+    ///
+    /// Changes whose dependencies are missing end up waiting in the queue, as if they had just
+    /// been received. Features, quotas, event handlers and quarantined changes are kept.
+    pub fn repair(&mut self) -> Result<(), AutomergeError> {
+        let mut rebuilt = Backend::new();
+        rebuilt.load_changes(self.history.to_vec())?;
+        self.op_set = rebuilt.op_set;
+        self.actors = rebuilt.actors;
+        self.states = rebuilt.states;
+        self.history = rebuilt.history;
+        self.history_index = rebuilt.history_index;
+        self.queue.extend(rebuilt.queue.iter().cloned());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+
+    use amp::SortedVec;
+    use automerge_protocol::{ActorId, ObjectId, Op, OpType};
+
+    use super::*;
+    use crate::Change;
+
+    fn backend() -> Backend {
+        let actor: ActorId = "7b7723afd9e6480397a4d467b7693156".try_into().unwrap();
+        let change: Change = amp::Change {
+            actor_id: actor,
+            seq: 1,
+            start_op: 1,
+            time: 0,
+            message: None,
+            hash: None,
+            deps: Vec::new(),
+            operations: vec![Op {
+                obj: ObjectId::Root,
+                action: OpType::Set("magpie".into()),
+                key: "bird".into(),
+                insert: false,
+                pred: SortedVec::new(),
+            }],
+            extra_bytes: Vec::new(),
+        }
+        .try_into()
+        .unwrap();
+        let mut backend = Backend::new();
+        backend.apply_changes(vec![change]).unwrap();
+        backend
+    }
+
+    #[test]
+    fn test_consistent_backend() {
+        assert_eq!(backend().fsck(), Vec::new());
+        assert_eq!(Backend::new().fsck(), Vec::new());
+    }
+
+    #[test]
+    fn test_repair_rebuilds_indices() {
+        let mut backend = backend();
+        let hash = backend.history[0].hash;
+        backend.history_index.clear();
+        backend.op_set.max_op = 7;
+        backend.op_set.deps.clear();
+        assert_eq!(
+            backend.fsck(),
+            vec![
+                Inconsistency::HistoryIndexSize {
+                    expected: 1,
+                    found: 0
+                },
+                Inconsistency::HistoryIndex {
+                    hash,
+                    expected: 0,
+                    found: None
+                },
+                Inconsistency::MaxOp {
+                    expected: 1,
+                    found: 7
+                },
+                Inconsistency::Heads {
+                    expected: vec![hash],
+                    found: Vec::new()
+                },
+            ]
+        );
+
+        backend.repair().unwrap();
+        assert_eq!(backend.fsck(), Vec::new());
+        assert_eq!(backend.get_patch().unwrap().max_op, 1);
+    }
+}
@@ -0,0 +1,50 @@
+use std::{fmt, sync::OnceLock};
+
+use sha2::{Digest, Sha256};
+
+/// An implementation of SHA-256, which computes change hashes and the checksums of chunks.
+///
+/// The default is [`Sha2`], an implementation in Rust which uses the SHA extensions of the CPU
+/// when it has them. Another implementation, e.g. one backed by a platform crypto library, can
+/// be installed for the whole process with [`set_change_hasher`]. Every implementation must
+/// compute exactly SHA-256, anything else gives changes hashes which no other peer agrees with.
+pub trait ChangeHasher: Send + Sync {
+    fn sha256(&self, bytes: &[u8]) -> [u8; 32];
+}
+
+/// The SHA-256 of the `sha2` crate
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Sha2;
+
+impl ChangeHasher for Sha2 {
+    fn sha256(&self, bytes: &[u8]) -> [u8; 32] {
+        Sha256::digest(bytes).into()
+    }
+}
+
+static HASHER: OnceLock<&'static dyn ChangeHasher> = OnceLock::new();
+
+/// Returned by [`set_change_hasher`] if a hasher has already been chosen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HasherAlreadySet;
+
+impl fmt::Display for HasherAlreadySet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a change hasher has already been set")
+    }
+}
+
+impl std::error::Error for HasherAlreadySet {}
+
+/// Use `hasher` for all hashing from now on.
+///
+/// This has to happen before anything is hashed, i.e. before the first change is created or
+/// decoded, as from then on the default is in use. It fails if a hasher has already been chosen,
+/// so libraries should leave this to the application.
+pub fn set_change_hasher(hasher: &'static dyn ChangeHasher) -> Result<(), HasherAlreadySet> {
+    HASHER.set(hasher).map_err(|_| HasherAlreadySet)
+}
+
+pub(crate) fn sha256(bytes: &[u8]) -> [u8; 32] {
+    HASHER.get_or_init(|| &Sha2).sha256(bytes)
+}
@@ -0,0 +1,97 @@
+use std::{ops::Range, slice};
+
+use automerge_protocol as amp;
+
+use crate::{received::ReceivedAt, Backend, Change};
+
+/// The changes in a backend in the order they were applied, as returned by [`Backend::history`].
+///
+/// Filters can be combined, a change is only returned if it matches all of them.
+pub struct History<'a> {
+    changes: slice::Iter<'a, Change>,
+    received_at: &'a ReceivedAt,
+    actor: Option<amp::ActorId>,
+    time: Option<Range<i64>>,
+    received: Option<Range<i64>>,
+    message: Option<String>,
+}
+
+impl History<'_> {
+    /// Only return changes made by `actor`
+    #[must_use]
+    pub fn by_actor(mut self, actor: amp::ActorId) -> Self {
+        self.actor = Some(actor);
+        self
+    }
+
+    /// Only return changes whose timestamp, in milliseconds since the epoch, is in `time`.
+    /// Timestamps come from the clock of the actor which made the change, so they may not be in
+    /// order.
+    #[must_use]
+    pub fn between(mut self, time: Range<i64>) -> Self {
+        self.time = Some(time);
+        self
+    }
+
+    /// Only return changes which this backend received in `time`, by its own clock, see
+    /// [`Backend::received_at`]. Changes without a receive time are left out.
+    #[must_use]
+    pub fn received_between(mut self, time: Range<i64>) -> Self {
+        self.received = Some(time);
+        self
+    }
+
+    /// Only return changes whose message contains `needle`
+    #[must_use]
+    pub fn message_contains(mut self, needle: &str) -> Self {
+        self.message = Some(needle.to_string());
+        self
+    }
+
+    fn matches(&self, change: &Change) -> bool {
+        self.actor.as_ref().is_none_or(|a| change.actor_id() == a)
+            && self.time.as_ref().is_none_or(|t| t.contains(&change.time))
+            && self.received.as_ref().is_none_or(|t| {
+                self.received_at
+                    .get(&change.hash)
+                    .is_some_and(|r| t.contains(&r))
+            })
+            && self.message.as_ref().is_none_or(|needle| {
+                change
+                    .message()
+                    .is_some_and(|m| m.contains(needle.as_str()))
+            })
+    }
+}
+
+impl<'a> Iterator for History<'a> {
+    type Item = (amp::ChangeHash, &'a Change);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let change = self.changes.next()?;
+            if self.matches(change) {
+                return Some((change.hash, change));
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.changes.size_hint().1)
+    }
+}
+
+impl Backend {
+    /// Iterate over the changes in this backend in the order they were applied, which is a
+    /// topological order: every change comes after its dependencies.
+    pub fn history(&self) -> History<'_> {
+        History {
+            changes: self.history.iter(),
+            received_at: &self.received_at,
+            actor: None,
+            time: None,
+            received: None,
+            message: None,
+        }
+    }
+}
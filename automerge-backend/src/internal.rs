@@ -3,7 +3,7 @@ use nonzero_ext::nonzero;
 use smol_str::SmolStr;
 
 #[derive(Eq, PartialEq, Hash, Debug, Clone, Copy)]
-pub(crate) struct ActorId(pub usize);
+pub(crate) struct ActorId(pub u32);
 
 #[derive(Eq, PartialEq, Debug, Hash, Clone, Copy)]
 pub(crate) struct OpId(pub u64, pub ActorId);
@@ -54,6 +54,9 @@ pub(crate) enum InternalOpType {
     Del,
     Inc(i64),
     Set(amp::ScalarValue),
+    Move(amp::OpId),
+    MarkBegin(amp::MarkData),
+    MarkEnd(amp::OpId),
 }
 
 impl Key {
@@ -97,6 +100,9 @@ impl From<&InternalOpType> for amp::OpType {
             InternalOpType::Make(ot) => amp::OpType::Make(*ot),
             InternalOpType::Set(v) => amp::OpType::Set(v.clone()),
             InternalOpType::Inc(i) => amp::OpType::Inc(*i),
+            InternalOpType::Move(source) => amp::OpType::Move(source.clone()),
+            InternalOpType::MarkBegin(mark) => amp::OpType::MarkBegin(mark.clone()),
+            InternalOpType::MarkEnd(begin) => amp::OpType::MarkEnd(begin.clone()),
         }
     }
 }
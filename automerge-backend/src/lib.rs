@@ -31,30 +31,104 @@ macro_rules! log {
 }
 
 mod actor_map;
+mod attribution;
 mod backend;
+mod bundle;
+mod catch_up;
 mod change;
+mod change_graph;
+mod change_store;
+mod checkpoint;
+mod chunked_patch;
+mod codec;
 mod columnar;
+mod compact;
 mod concurrent_operations;
 mod decoding;
+mod dictionary;
+mod disclosure;
+mod element_history;
+mod empty;
 mod encoding;
 mod error;
 mod event_handlers;
 mod expanded_op;
+mod explain;
+mod features;
+mod file_persister;
+mod fsck;
+mod hash;
+mod history;
 mod internal;
+pub mod limits;
+mod load_progress;
 mod object_store;
 mod op_handle;
+mod op_ids;
 mod op_set;
 mod ordered_set;
+mod orphaned_cursors;
+mod patch_size;
 mod patches;
+mod persister;
+mod playback;
+mod quarantine;
+mod quota;
+mod received;
+mod shared;
+#[cfg(feature = "sled")]
+mod sled_persister;
+mod snapshot;
+#[cfg(feature = "sqlite")]
+mod sqlite_persister;
 mod sync;
+mod timestamps;
+mod value;
+mod verification;
 
+pub use attribution::Attribution;
 pub use backend::Backend;
+pub use bundle::BundleManifest;
+pub use bytes::Bytes;
+pub use catch_up::CatchUp;
 pub use change::Change;
+pub use change_graph::GraphFormat;
+pub use change_store::{ChangeStore, DirChangeStore, MemoryChangeStore};
+pub use codec::{BinaryCodec, ChangeCodec, CodecError, JsonCodec, PatchCodec};
+pub use compact::{CompactOptions, Compacted, CompactionError};
 pub use decoding::Error as DecodingError;
+pub use dictionary::CompressionDictionary;
+pub use element_history::{ElementHistory, ElementOp};
 pub use encoding::Error as EncodingError;
 pub use error::AutomergeError;
 pub use event_handlers::{ChangeEventHandler, EventHandler, EventHandlerId};
-pub use sync::{BloomFilter, SyncHave, SyncMessage, SyncState};
+pub use explain::{ExplainedOp, Explanation};
+pub use features::{MARKS_FEATURE, MOVE_FEATURE, SUPPORTED_FEATURES, ZSTD_COLUMNS_FEATURE};
+pub use file_persister::FilePersister;
+pub use fsck::Inconsistency;
+pub use hash::{set_change_hasher, ChangeHasher, HasherAlreadySet, Sha2};
+pub use history::History;
+pub use load_progress::LoadPhase;
+pub use op_ids::OpIdAllocator;
+pub use orphaned_cursors::OrphanedCursor;
+pub use patch_size::PatchSizeEstimate;
+pub use persister::{MemoryPersister, PersistentBackend, Persister};
+pub use playback::{Playback, PlaybackEvent};
+pub use quarantine::{QuarantinedChange, Rejection, DEFAULT_QUARANTINE_CAPACITY};
+pub use quota::{QuotaExceeded, Quotas};
+pub use received::Clock;
+#[cfg(feature = "sled")]
+pub use sled_persister::SledPersister;
+pub use snapshot::OwnedSnapshot;
+#[cfg(feature = "sqlite")]
+pub use sqlite_persister::SqlitePersister;
+pub use sync::{
+    BloomFilter, SyncHave, SyncManager, SyncMessage, SyncState, MAX_RECEIVED_EPHEMERAL,
+};
+#[cfg(feature = "async")]
+pub use sync::{FramedTransport, SyncDriver, SyncDriverError, SyncTransport, TokioTransport};
+pub use timestamps::{TimeWentBackwards, TimestampPolicy};
+pub use value::{PathElement, Value};
 
 #[cfg(test)]
 mod tests {
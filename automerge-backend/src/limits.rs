@@ -0,0 +1,81 @@
+//! Limits on the changes this implementation will create.
+//!
+//! Changes are checked against these limits in [`crate::Backend::apply_local_change`], so a
+//! change which breaks one is never encoded into the document. Bindings can call [`check`] to
+//! validate a change up front and report a precise error rather than a failure from deep inside
+//! the backend, or another implementation failing to load the document later.
+//!
+//! These are limits of the format or of the implementations which read it. To restrict what a
+//! particular application accepts further, see [`crate::Quotas`].
+use std::convert::TryFrom;
+
+use thiserror::Error;
+
+use crate::Change;
+
+/// The longest actor ID in bytes. The format itself has no limit, but an actor ID is repeated in
+/// the string form of every op ID it makes, so there has to be one somewhere. Random actor IDs
+/// are 16 bytes.
+pub fn max_actor_id_bytes() -> usize {
+    128
+}
+
+/// The largest sequence number or op counter. The JavaScript implementation stores these as
+/// numbers, which are only exact up to 2^53 - 1.
+pub fn max_safe_integer() -> u64 {
+    (1 << 53) - 1
+}
+
+/// The largest encoded change in bytes. Changes are loaded whole, and the wasm bindings can't
+/// address more than 4GiB.
+pub fn max_change_bytes() -> usize {
+    usize::try_from(u32::MAX).unwrap_or(usize::MAX)
+}
+
+/// The most operations a change may contain, there is no limit in the format. Use
+/// [`crate::Quotas::max_ops_per_change`] to impose one.
+pub fn max_ops_per_change() -> Option<usize> {
+    None
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum LimitExceeded {
+    #[error("Actor ID is {len} bytes long, the maximum is {max}")]
+    ActorIdTooLong { len: usize, max: usize },
+    #[error("{field} is {value}, the maximum is {max}")]
+    IntegerTooLarge {
+        field: &'static str,
+        value: u64,
+        max: u64,
+    },
+    #[error("Change is {size} bytes long, the maximum is {max}")]
+    ChangeTooLarge { size: usize, max: usize },
+}
+
+/// Check that `change` is within all of the limits in this module
+pub fn check(change: &Change) -> Result<(), LimitExceeded> {
+    let actor_len = change.actor_id().to_bytes().len();
+    if actor_len > max_actor_id_bytes() {
+        return Err(LimitExceeded::ActorIdTooLong {
+            len: actor_len,
+            max: max_actor_id_bytes(),
+        });
+    }
+    for (field, value) in [("seq", change.seq), ("max op", change.max_op())] {
+        if value > max_safe_integer() {
+            return Err(LimitExceeded::IntegerTooLarge {
+                field,
+                value,
+                max: max_safe_integer(),
+            });
+        }
+    }
+    let size = change.raw_bytes().len();
+    if size > max_change_bytes() {
+        return Err(LimitExceeded::ChangeTooLarge {
+            size,
+            max: max_change_bytes(),
+        });
+    }
+    Ok(())
+}
@@ -0,0 +1,36 @@
+use bytes::Bytes;
+
+use crate::{change::DecodeMode, error::AutomergeError, Backend};
+
+/// The stages of loading a document, as reported by [`Backend::load_with_progress`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LoadPhase {
+    /// Reading the chunks of the file and checking their checksums. Progress is in bytes.
+    Decode,
+    /// Rebuilding the changes of a whole document chunk and computing their hashes, which are
+    /// checked against the heads the document recorded. Progress is in changes of the chunk
+    /// being decoded, so this phase happens within `Decode`, once per document chunk.
+    Verify,
+    /// Applying the changes to the document. Progress is in changes.
+    Apply,
+}
+
+impl Backend {
+    /// Like [`Backend::load`], calling `progress(phase, done, total)` as loading goes along, so
+    /// that an application can show how far it has got.
+    ///
+    /// `progress` is called for every chunk decoded and every change hashed or applied, which
+    /// can be millions of times for a large document, so it should be cheap: update a counter
+    /// and leave redrawing to something else.
+    pub fn load_with_progress<F>(data: &[u8], mut progress: F) -> Result<Self, AutomergeError>
+    where
+        F: FnMut(LoadPhase, usize, usize),
+    {
+        Self::load_reporting(
+            &Bytes::copy_from_slice(data),
+            &[],
+            DecodeMode::Strict,
+            &mut progress,
+        )
+    }
+}
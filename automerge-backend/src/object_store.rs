@@ -25,6 +25,16 @@ pub(crate) struct ObjState {
     pub following: HashMap<ElementId, Vec<ElementId>, FxBuildHasher>,
     pub insertions: HashMap<ElementId, OpHandle, FxBuildHasher>,
     pub seq: SkipList<OpId>,
+    /// The winning move op for each element which has been moved. The ID of the move op is the
+    /// slot the element currently occupies in `seq`.
+    pub moves: HashMap<OpId, OpId, FxBuildHasher>,
+    /// The element each move op relocates
+    pub moved_to: HashMap<OpId, OpId, FxBuildHasher>,
+    /// The `MarkBegin` ops in a text object. Like move ops these are insertions, so they have a
+    /// place in `following` and `insertions`, but they never appear in `seq`.
+    pub marks: HashMap<OpId, amp::MarkData, FxBuildHasher>,
+    /// The `MarkBegin` op each `MarkEnd` op closes
+    pub mark_ends: HashMap<OpId, OpId, FxBuildHasher>,
 }
 
 impl ObjState {
@@ -38,6 +48,10 @@ impl ObjState {
             obj_type,
             inbound: None,
             seq: SkipList::new(),
+            moves: HashMap::default(),
+            moved_to: HashMap::default(),
+            marks: HashMap::default(),
+            mark_ends: HashMap::default(),
         }
     }
 
@@ -57,8 +71,47 @@ impl ObjState {
         self.props.get(key).into_iter().flat_map(|i| i.iter())
     }
 
+    /// The element which occupies `slot`. Elements which have never been moved occupy the slot
+    /// created by their own insertion.
+    pub fn element_of(&self, slot: OpId) -> OpId {
+        self.moved_to.get(&slot).copied().unwrap_or(slot)
+    }
+
+    /// The slot `element` currently occupies
+    pub fn slot_of(&self, element: OpId) -> OpId {
+        self.moves.get(&element).copied().unwrap_or(element)
+    }
+
+    /// Whether `slot` holds an element which has not been deleted
+    pub fn is_visible(&self, slot: OpId) -> bool {
+        let element = self.element_of(slot);
+        self.slot_of(element) == slot && self.conflicts(&element.into()).next().is_some()
+    }
+
+    /// Every insertion into the sequence in order. This includes deleted elements, slots which
+    /// moved elements have left and the boundaries of marks.
+    pub fn insertions_in_order(&self) -> Vec<OpId> {
+        let mut result = Vec::with_capacity(self.insertions.len());
+        let mut stack: Vec<&ElementId> = self
+            .following
+            .get(&ElementId::Head)
+            .into_iter()
+            .flat_map(|children| children.iter().rev())
+            .collect();
+        while let Some(element) = stack.pop() {
+            if let ElementId::Id(id) = element {
+                result.push(*id);
+            }
+            if let Some(children) = self.following.get(element) {
+                stack.extend(children.iter().rev());
+            }
+        }
+        result
+    }
+
     #[tracing::instrument(level = "debug", skip(self))]
     pub fn index_of(&self, id: OpId) -> Option<usize> {
+        let id = self.slot_of(self.element_of(id));
         let mut prev_id = id.into();
         let mut index = None;
         // reverse walk through the following/insertions and looking for something that not deleted
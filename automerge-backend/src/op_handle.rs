@@ -1,5 +1,6 @@
 use std::{
     borrow::Cow,
+    convert::TryFrom,
     fmt,
     hash::{Hash, Hasher},
     ops::Deref,
@@ -17,7 +18,10 @@ use crate::{
 pub(crate) struct OpHandle {
     pub id: OpId,
     pub op: InternalOp,
-    pub delta: i64,
+    /// The sum of the increments applied to a counter. This is kept exactly, rather than
+    /// wrapping or saturating as each increment is applied, so that the result doesn't depend on
+    /// the order increments arrive in.
+    pub delta: i128,
 }
 
 impl OpHandle {
@@ -41,14 +45,22 @@ impl OpHandle {
 
     pub fn adjusted_value(&self) -> amp::ScalarValue {
         match &self.action {
-            InternalOpType::Set(amp::ScalarValue::Counter(a)) => {
-                amp::ScalarValue::Counter(a + self.delta)
+            InternalOpType::Set(amp::ScalarValue::Counter(_)) => {
+                amp::ScalarValue::Counter(saturate(self.exact_counter().unwrap()))
             }
             InternalOpType::Set(val) => val.clone(),
             _ => amp::ScalarValue::Null,
         }
     }
 
+    /// The value of a counter before it is clamped to fit in an `i64`
+    pub fn exact_counter(&self) -> Option<i128> {
+        match &self.action {
+            InternalOpType::Set(amp::ScalarValue::Counter(a)) => Some(i128::from(*a) + self.delta),
+            _ => None,
+        }
+    }
+
     pub fn child(&self) -> Option<ObjectId> {
         match &self.action {
             InternalOpType::Make(_) => Some(self.id.into()),
@@ -68,7 +80,7 @@ impl OpHandle {
         if let InternalOpType::Inc(amount) = inc.action {
             if inc.pred.contains(&self.id) {
                 if let InternalOpType::Set(amp::ScalarValue::Counter(_)) = self.action {
-                    self.delta += amount;
+                    self.delta += i128::from(amount);
                     return true;
                 }
             }
@@ -77,6 +89,12 @@ impl OpHandle {
     }
 }
 
+/// Counters which overflow an `i64` stick at `i64::MAX` or `i64::MIN` until enough increments in
+/// the other direction bring them back into range
+fn saturate(value: i128) -> i64 {
+    i64::try_from(value).unwrap_or(if value > 0 { i64::MAX } else { i64::MIN })
+}
+
 impl fmt::Debug for OpHandle {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("OpHandle")
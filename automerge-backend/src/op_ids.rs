@@ -0,0 +1,127 @@
+use automerge_protocol as amp;
+
+use crate::Backend;
+
+/// Hands out the sequence number and op IDs for a new change, for building changes without an
+/// automerge frontend (e.g. in bindings to other languages).
+///
+/// Every op in a change gets the next op counter, starting at one more than the highest counter
+/// in the document. Ops which stand for several ops, `MultiSet` and `Del` with a count greater
+/// than one, take one counter per value or deleted element.
+///
+/// ```
+/// # use automerge_backend::Backend;
+/// # use automerge_protocol as amp;
+/// # use std::convert::TryInto;
+/// let mut backend = Backend::new();
+/// let actor = amp::ActorId::random();
+/// let mut ids = backend.op_id_allocator(&actor);
+/// let list = ids.next_op_id();
+/// let ops = vec![amp::Op {
+///     action: amp::OpType::Make(amp::ObjType::List),
+///     obj: amp::ObjectId::Root,
+///     key: "birds".into(),
+///     insert: false,
+///     pred: amp::SortedVec::new(),
+/// }];
+/// let change = ids.finish(ops, 0, None);
+/// backend.apply_changes(vec![change.try_into().unwrap()]).unwrap();
+/// assert_eq!(backend.max_op(), list.0);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpIdAllocator {
+    actor: amp::ActorId,
+    seq: u64,
+    start_op: u64,
+    next_op: u64,
+    deps: Vec<amp::ChangeHash>,
+}
+
+impl OpIdAllocator {
+    pub fn actor(&self) -> &amp::ActorId {
+        &self.actor
+    }
+
+    /// The sequence number of the change
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// The counter of the first op in the change
+    pub fn start_op(&self) -> u64 {
+        self.start_op
+    }
+
+    /// The current heads of the document, which the change depends on
+    pub fn deps(&self) -> &[amp::ChangeHash] {
+        &self.deps
+    }
+
+    /// The ID of the next op
+    pub fn next_op_id(&mut self) -> amp::OpId {
+        self.allocate(1)
+    }
+
+    /// Reserve `count` op IDs in one go, e.g. for a `MultiSet` with `count` values. Returns the
+    /// first one, the others follow on from it.
+    pub fn allocate(&mut self, count: u64) -> amp::OpId {
+        let op_id = self.actor.op_id_at(self.next_op);
+        self.next_op += count;
+        op_id
+    }
+
+    /// The change containing `operations`, which should be the ops the IDs were allocated for.
+    pub fn finish(
+        self,
+        operations: Vec<amp::Op>,
+        time: i64,
+        message: Option<String>,
+    ) -> amp::Change {
+        debug_assert_eq!(
+            operations.iter().map(op_count).sum::<u64>(),
+            self.next_op - self.start_op,
+            "the number of ops doesn't match the number of op IDs allocated"
+        );
+        amp::Change {
+            actor_id: self.actor,
+            seq: self.seq,
+            start_op: self.start_op,
+            time,
+            message,
+            hash: None,
+            deps: self.deps,
+            operations,
+            extra_bytes: Vec::new(),
+        }
+    }
+}
+
+/// How many op IDs `op` takes up
+fn op_count(op: &amp::Op) -> u64 {
+    match &op.action {
+        amp::OpType::MultiSet(values) => values.len() as u64,
+        amp::OpType::Del(count) => u64::from(count.get()),
+        _ => 1,
+    }
+}
+
+impl Backend {
+    /// The highest op counter in the document, a new change starts at the one after it.
+    pub fn max_op(&self) -> u64 {
+        self.op_set.max_op
+    }
+
+    /// Start a new change by `actor` on top of the current state of the document, see
+    /// [`OpIdAllocator`].
+    pub fn op_id_allocator(&self, actor: &amp::ActorId) -> OpIdAllocator {
+        let seq = self.states.get(actor).map_or(0, Vec::len) as u64 + 1;
+        let start_op = self.op_set.max_op + 1;
+        OpIdAllocator {
+            actor: actor.clone(),
+            seq,
+            start_op,
+            next_op: start_op,
+            deps: self.get_heads(),
+        }
+    }
+}
@@ -6,7 +6,10 @@
 //! document::state) the implementation fetches the root object ID's history
 //! and then recursively walks through the tree of histories constructing the
 //! state. Obviously this is not very efficient.
-use std::collections::{HashMap, HashSet};
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+};
 
 use automerge_protocol as amp;
 use fxhash::FxBuildHasher;
@@ -88,10 +91,29 @@ impl OpSet {
     #[instrument(level = "debug", skip(self))]
     fn apply_op(
         &mut self,
-        op: OpHandle,
+        mut op: OpHandle,
         actors: &mut ActorMap,
         patch: &mut IncrementalPatch,
     ) -> Result<(), AutomergeError> {
+        if let InternalOpType::Move(ref source) = op.op.action {
+            let source = source.clone();
+            return self.apply_move(op, &source, actors, patch);
+        }
+        if matches!(
+            op.op.action,
+            InternalOpType::MarkBegin(_) | InternalOpType::MarkEnd(_)
+        ) {
+            return self.apply_mark(op, actors, patch);
+        }
+
+        // Ops which modify an element refer to it by whichever slot the frontend saw it in, the
+        // element's ops are all stored under the ID of its original insertion
+        if !op.insert {
+            if let (Some(obj), Some(slot)) = (self.objs.get(&op.obj), op.key.to_opid()) {
+                op.op.key = obj.element_of(slot).into();
+            }
+        }
+
         if let (Some(child), Some(obj_type)) = (op.child(), op.obj_type()) {
             //let child = actors.import_obj(child);
             self.objs.insert(child, ObjState::new(obj_type));
@@ -158,7 +180,7 @@ impl OpSet {
                         .operation_key()
                         .to_opid()
                         .ok_or(AutomergeError::HeadToOpId)?;
-                    let index = object.seq.remove_key(&opid).unwrap();
+                    let index = object.seq.remove_key(&object.slot_of(opid)).unwrap();
                     tracing::debug!(opid=?opid, index=%index, "deleting element");
                     patch.record_seq_remove(&object_id, op.clone(), index);
                 }
@@ -167,10 +189,11 @@ impl OpSet {
                         .operation_key()
                         .to_opid()
                         .ok_or(AutomergeError::HeadToOpId)?;
-                    let index = object.index_of(id).unwrap_or(0);
+                    let slot = object.slot_of(id);
+                    let index = object.index_of(slot).unwrap_or(0);
                     tracing::debug!(new_id=?id, index=%index, after=?op.operation_key(), "inserting new element");
-                    object.seq.insert_index(index, id);
-                    patch.record_seq_insert(&object_id, op.clone(), index, op.id);
+                    object.seq.insert_index(index, slot);
+                    patch.record_seq_insert(&object_id, op.clone(), index, slot);
                 }
                 (false, false) => {}
             };
@@ -201,6 +224,100 @@ impl OpSet {
         Ok(())
     }
 
+    /// Apply a move op. The op inserts a new slot into the sequence which the moved element
+    /// occupies if the op beats every other move of that element.
+    fn apply_move(
+        &mut self,
+        op: OpHandle,
+        source: &amp::OpId,
+        actors: &mut ActorMap,
+        patch: &mut IncrementalPatch,
+    ) -> Result<(), AutomergeError> {
+        let internal_source = actors.import_opid(source);
+        let object_id = op.obj;
+        let object = self.get_obj_mut(&object_id)?;
+        if !object.is_seq() || !object.insertions.contains_key(&internal_source.into()) {
+            return Err(AutomergeError::InvalidMove {
+                opid: source.clone(),
+            });
+        }
+
+        let element = object.element_of(internal_source);
+        let current = object.slot_of(element);
+        let slot = op.id;
+        object.insert_after(
+            op.key.as_element_id().ok_or(AutomergeError::MapKeyInSeq)?,
+            op.clone(),
+            actors,
+        );
+        object.moved_to.insert(slot, element);
+
+        if actors.cmp(&slot.into(), &current.into()) != Ordering::Greater {
+            tracing::debug!(element=?element, slot=?slot, "move lost to a concurrent move");
+            return Ok(());
+        }
+        object.moves.insert(element, slot);
+
+        let ops = object
+            .conflicts(&element.into())
+            .cloned()
+            .collect::<Vec<_>>();
+        if ops.is_empty() {
+            // the element has been deleted, if it comes back it will be in the new slot
+            return Ok(());
+        }
+        if let Some(index) = object.seq.remove_key(&current) {
+            patch.record_seq_remove(&object_id, op, index);
+        }
+        let index = object.index_of(slot).unwrap_or(0);
+        tracing::debug!(element=?element, slot=?slot, index=%index, "moving element");
+        object.seq.insert_index(index, slot);
+        patch.record_seq_move(&object_id, ops, index, slot);
+        Ok(())
+    }
+
+    /// Apply a `MarkBegin` or `MarkEnd` op. These only record a boundary in the sequence, the
+    /// spans they make up are worked out when the patch is generated.
+    fn apply_mark(
+        &mut self,
+        op: OpHandle,
+        actors: &mut ActorMap,
+        patch: &mut IncrementalPatch,
+    ) -> Result<(), AutomergeError> {
+        let object_id = op.obj;
+        let begin = match &op.op.action {
+            InternalOpType::MarkEnd(begin) => Some(actors.import_opid(begin)),
+            _ => None,
+        };
+        let object = self.get_obj_mut(&object_id)?;
+        let valid = match begin {
+            Some(begin) => object.marks.contains_key(&begin),
+            None => true,
+        };
+        if object.obj_type != amp::ObjType::Text || !op.insert || !valid {
+            return Err(AutomergeError::InvalidMark {
+                opid: actors.export_opid(&op.id),
+            });
+        }
+
+        match (&op.op.action, begin) {
+            (_, Some(begin)) => {
+                object.mark_ends.insert(op.id, begin);
+            }
+            (InternalOpType::MarkBegin(mark), None) => {
+                object.marks.insert(op.id, mark.clone());
+            }
+            _ => {}
+        }
+        object.insert_after(
+            op.key.as_element_id().ok_or(AutomergeError::MapKeyInSeq)?,
+            op,
+            actors,
+        );
+        patch.record_marks_change(&object_id);
+        Ok(())
+    }
+
     fn unlink(&mut self, op: &OpHandle, overwritten: &[OpHandle]) -> Result<(), AutomergeError> {
         if let Some(child) = op.child() {
             self.get_obj_mut(&child)?.inbound = Some(op.clone());
@@ -350,4 +467,8 @@ impl<'a> PatchWorkshop for PatchWorkshopImpl<'a> {
     fn make_external_objid(&self, object_id: &ObjectId) -> amp::ObjectId {
         self.actors.export_obj(object_id)
     }
+
+    fn cmp_opid(&self, op1: &crate::internal::OpId, op2: &crate::internal::OpId) -> Ordering {
+        self.actors.cmp(&(*op1).into(), &(*op2).into())
+    }
 }
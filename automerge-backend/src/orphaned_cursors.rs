@@ -0,0 +1,146 @@
+use automerge_protocol as amp;
+
+use crate::{
+    actor_map::ActorMap,
+    internal::{ElementId, InternalOpType, Key, ObjectId, OpId},
+    object_store::ObjState,
+    Backend,
+};
+
+/// A cursor which points at an element that has since been deleted, see
+/// [`Backend::orphaned_cursors`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrphanedCursor {
+    /// The object the cursor is stored in
+    pub object: amp::ObjectId,
+    /// Where in `object` the cursor is stored
+    pub key: amp::Key,
+    /// The op which set the cursor
+    pub op_id: amp::OpId,
+    /// The list or text object the cursor points into
+    pub sequence: amp::ObjectId,
+    /// The deleted element the cursor points at
+    pub element: amp::OpId,
+    /// The closest element to `element` which hasn't been deleted, along with its index. This is
+    /// the next surviving element after it, or the last one in the sequence if there is nothing
+    /// after it, and `None` if the sequence is empty.
+    pub nearest: Option<(amp::OpId, usize)>,
+}
+
+impl Backend {
+    /// Find every cursor in the document which points at a deleted element.
+    ///
+    /// Such a cursor still resolves to the index the element would have had, but it no longer
+    /// moves with the text around it, so applications which keep cursors in long lived documents
+    /// should rewrite them every so often, see [`Backend::repair_cursors`]. Cursors held in
+    /// conflicting values are reported separately.
+    pub fn orphaned_cursors(&self) -> Vec<OrphanedCursor> {
+        let mut orphans = Vec::new();
+        for (obj_id, obj) in &self.op_set.objs {
+            for (key, ops) in &obj.props {
+                for op in ops.iter() {
+                    if let InternalOpType::Set(amp::ScalarValue::Cursor(target)) = &op.op.action {
+                        if let Some((seq_id, seq, element)) = self.sequence_containing(target) {
+                            if seq.conflicts(&element.into()).next().is_some() {
+                                continue;
+                            }
+                            orphans.push(OrphanedCursor {
+                                object: self.actors.export_obj(obj_id),
+                                key: export_key(key, &self.actors),
+                                op_id: self.actors.export_opid(&op.id),
+                                sequence: self.actors.export_obj(seq_id),
+                                element: target.clone(),
+                                nearest: nearest_visible(seq, element)
+                                    .map(|(id, index)| (self.actors.export_opid(&id), index)),
+                            });
+                        }
+                    }
+                }
+            }
+        }
+        orphans.sort_by(|a, b| a.op_id.cmp(&b.op_id));
+        orphans
+    }
+
+    /// A change by `actor` which points each of `orphans` at its nearest surviving element, or
+    /// `None` if there is nothing to rewrite. Cursors into sequences which are now empty are left
+    /// alone.
+    ///
+    /// The change is made on top of the current state of the document, so it should be applied
+    /// before anything else. It is meant to be applied with `apply_changes` so that the frontend
+    /// receives it like any other remote change, `actor` should not be one a frontend is using.
+    pub fn repair_cursors(
+        &self,
+        actor: &amp::ActorId,
+        orphans: &[OrphanedCursor],
+        time: i64,
+    ) -> Option<amp::Change> {
+        let mut ids = self.op_id_allocator(actor);
+        let operations: Vec<amp::Op> = orphans
+            .iter()
+            .filter_map(|orphan| {
+                let (nearest, _) = orphan.nearest.as_ref()?;
+                ids.next_op_id();
+                Some(amp::Op {
+                    action: amp::OpType::Set(amp::ScalarValue::Cursor(nearest.clone())),
+                    obj: orphan.object.clone(),
+                    key: orphan.key.clone(),
+                    insert: false,
+                    pred: vec![orphan.op_id.clone()].into(),
+                })
+            })
+            .collect();
+        if operations.is_empty() {
+            None
+        } else {
+            Some(ids.finish(
+                operations,
+                time,
+                Some("Repair orphaned cursors".to_string()),
+            ))
+        }
+    }
+
+    /// The sequence `element` was inserted into, and the element's internal ID
+    fn sequence_containing(&self, element: &amp::OpId) -> Option<(&ObjectId, &ObjState, OpId)> {
+        let element = match self
+            .actors
+            .lookup_obj(&amp::ObjectId::Id(element.clone()))?
+        {
+            ObjectId::Id(id) => id,
+            ObjectId::Root => return None,
+        };
+        self.op_set
+            .objs
+            .iter()
+            .find(|(_, obj)| obj.insertions.contains_key(&element.into()))
+            .map(|(obj_id, obj)| (obj_id, obj, element))
+    }
+}
+
+fn export_key(key: &Key, actors: &ActorMap) -> amp::Key {
+    match key {
+        Key::Map(name) => amp::Key::Map(name.clone()),
+        Key::Seq(ElementId::Head) => amp::Key::head(),
+        Key::Seq(ElementId::Id(id)) => amp::Key::Seq(amp::ElementId::Id(actors.export_opid(id))),
+    }
+}
+
+/// The first visible element after the deleted `element`, or the last one before it
+fn nearest_visible(seq: &ObjState, element: OpId) -> Option<(OpId, usize)> {
+    let order = seq.insertions_in_order();
+    let slot = seq.slot_of(element);
+    let position = order.iter().position(|s| *s == slot)?;
+    let visible_before = order[..position]
+        .iter()
+        .filter(|s| seq.is_visible(**s))
+        .count();
+    if let Some(after) = order[position..].iter().find(|s| seq.is_visible(**s)) {
+        return Some((seq.element_of(*after), visible_before));
+    }
+    let before = order[..position]
+        .iter()
+        .rev()
+        .find(|s| seq.is_visible(**s))?;
+    Some((seq.element_of(*before), visible_before - 1))
+}
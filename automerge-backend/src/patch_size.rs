@@ -0,0 +1,80 @@
+use automerge_protocol as amp;
+
+use crate::{internal::InternalOpType, Backend};
+
+/// Roughly what a single edit or property costs in a JSON patch before its value is taken into
+/// account: the op ID, the element ID, the key and the surrounding structure.
+const EDIT_OVERHEAD: usize = 96;
+
+/// How large a patch is going to be, as estimated by [`Backend::estimate_patch_size`].
+///
+/// This is meant for deciding how to bring a peer up to date, e.g. whether to send a patch, the
+/// whole document, or to page through the changes, so the numbers are approximate.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PatchSizeEstimate {
+    /// The number of changes the peer is missing
+    pub changes: usize,
+    /// The number of ops the patch has to describe
+    pub ops: usize,
+    /// The approximate size of the patch when serialized as JSON
+    pub patch_bytes: usize,
+    /// The size of the missing changes in the binary format
+    pub change_bytes: usize,
+}
+
+impl Backend {
+    /// Estimate the size of the patch which brings a frontend that has seen `heads` up to date,
+    /// without generating it.
+    ///
+    /// If `heads` is empty this is the size of `get_patch`, which only depends on the current
+    /// state of the document. Otherwise every op of the missing changes is counted, whether it is
+    /// still visible or not.
+    pub fn estimate_patch_size(&self, heads: &[amp::ChangeHash]) -> PatchSizeEstimate {
+        let changes = self.get_changes(heads);
+        let mut estimate = PatchSizeEstimate {
+            changes: changes.len(),
+            change_bytes: changes.iter().map(|c| c.raw_bytes().len()).sum(),
+            ..PatchSizeEstimate::default()
+        };
+        if heads.is_empty() {
+            for op in self
+                .op_set
+                .objs
+                .values()
+                .flat_map(|obj| obj.props.values())
+                .flat_map(|ops| ops.iter())
+            {
+                estimate.add_op(&op.action);
+            }
+        } else {
+            for change in changes {
+                for op in change.iter_ops() {
+                    estimate.add_op(&op.action);
+                }
+            }
+        }
+        estimate
+    }
+}
+
+impl PatchSizeEstimate {
+    fn add_op(&mut self, action: &InternalOpType) {
+        self.ops += 1;
+        self.patch_bytes += EDIT_OVERHEAD
+            + match action {
+                InternalOpType::Set(value) => value_size(value),
+                InternalOpType::MarkBegin(mark) => mark.name.len() + value_size(&mark.value),
+                _ => 0,
+            };
+    }
+}
+
+fn value_size(value: &amp::ScalarValue) -> usize {
+    match value {
+        amp::ScalarValue::Str(s) => s.len() + 2,
+        // base64 encoded
+        amp::ScalarValue::Bytes(b) => b.len() * 4 / 3 + 4,
+        amp::ScalarValue::Null => 4,
+        _ => 8,
+    }
+}
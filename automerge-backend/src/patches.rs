@@ -1,9 +1,12 @@
+mod diff_between;
 mod edits;
 mod from_scratch_diff;
+mod gen_mark_diff;
 mod gen_value_diff;
 mod incremental_diff;
 mod patch_workshop;
 
+pub(crate) use diff_between::generate_diff_between;
 pub(crate) use edits::Edits;
 pub(crate) use from_scratch_diff::generate_from_scratch_diff;
 pub(crate) use incremental_diff::IncrementalPatch;
@@ -0,0 +1,231 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use automerge_protocol as amp;
+use smol_str::SmolStr;
+
+use super::{
+    from_scratch_diff::construct_object, gen_mark_diff::gen_mark_diff,
+    gen_value_diff::gen_value_diff, Edits, PatchWorkshop,
+};
+use crate::{
+    internal::{ObjectId, OpId},
+    object_store::ObjState,
+    op_handle::OpHandle,
+};
+
+/// Generate a diff which turns the document in `before` into the document in `after`.
+///
+/// The two workshops are usually for different op sets, so internal IDs can't be compared
+/// between them, everything is matched up by external op ID instead. Objects which exist in both
+/// are diffed recursively, anything else is constructed from scratch.
+pub(crate) fn generate_diff_between(
+    before: &dyn PatchWorkshop,
+    after: &dyn PatchWorkshop,
+) -> amp::RootDiff {
+    let mut differ = Differ { before, after };
+    // Safety: every op set has a root object
+    let props = differ.props(
+        before.get_obj(&ObjectId::Root).unwrap(),
+        after.get_obj(&ObjectId::Root).unwrap(),
+    );
+    amp::RootDiff { props }
+}
+
+struct Differ<'a> {
+    before: &'a dyn PatchWorkshop,
+    after: &'a dyn PatchWorkshop,
+}
+
+impl Differ<'_> {
+    /// The diff of the object with internal ID `before_id` in `before` and `after_id` in
+    /// `after`, along with whether anything in it changed
+    fn object(&mut self, before_id: &ObjectId, after_id: &ObjectId) -> (amp::Diff, bool) {
+        // Safety: the objects are the children of ops in their op sets
+        let old = self.before.get_obj(before_id).expect("missing object");
+        let new = self.after.get_obj(after_id).expect("missing object");
+        let object_id = self.after.make_external_objid(after_id);
+        match new.obj_type {
+            amp::ObjType::Map => {
+                let props = self.props(old, new);
+                let changed = !props.is_empty();
+                (amp::Diff::Map(amp::MapDiff { object_id, props }), changed)
+            }
+            amp::ObjType::Table => {
+                let props = self.props(old, new);
+                let changed = !props.is_empty();
+                (
+                    amp::Diff::Table(amp::TableDiff { object_id, props }),
+                    changed,
+                )
+            }
+            amp::ObjType::List => {
+                let edits = self.edits(old, new);
+                let changed = !edits.is_empty();
+                (amp::Diff::List(amp::ListDiff { object_id, edits }), changed)
+            }
+            amp::ObjType::Text => {
+                let mut edits = self.edits(old, new);
+                let marks = gen_mark_diff(new, self.after);
+                if marks != gen_mark_diff(old, self.before) {
+                    edits.push(amp::DiffEdit::Marks(marks));
+                }
+                let changed = !edits.is_empty();
+                (amp::Diff::Text(amp::TextDiff { object_id, edits }), changed)
+            }
+        }
+    }
+
+    fn props(
+        &mut self,
+        old: &ObjState,
+        new: &ObjState,
+    ) -> BTreeMap<SmolStr, BTreeMap<amp::OpId, amp::Diff>> {
+        let mut keys: BTreeMap<SmolStr, (Vec<&OpHandle>, Vec<&OpHandle>)> = BTreeMap::new();
+        for (key, ops) in &old.props {
+            let entry = keys.entry(self.before.key_to_string(key)).or_default();
+            entry.0.extend(ops.iter());
+        }
+        for (key, ops) in &new.props {
+            let entry = keys.entry(self.after.key_to_string(key)).or_default();
+            entry.1.extend(ops.iter());
+        }
+        keys.into_iter()
+            .filter_map(|(key, (old_ops, new_ops))| {
+                self.values(&old_ops, &new_ops).map(|values| (key, values))
+            })
+            .collect()
+    }
+
+    /// The values of a key or element afterwards, or `None` if they're the same as before
+    fn values(
+        &mut self,
+        old_ops: &[&OpHandle],
+        new_ops: &[&OpHandle],
+    ) -> Option<BTreeMap<amp::OpId, amp::Diff>> {
+        let mut changed = old_ops.len() != new_ops.len();
+        let mut values = BTreeMap::new();
+        for op in new_ops {
+            let op_id = self.after.make_external_opid(&op.id);
+            let old = old_ops
+                .iter()
+                .find(|old| self.before.make_external_opid(&old.id) == op_id);
+            let value = match (op.child(), old.and_then(|old| old.child())) {
+                (Some(child), Some(old_child)) => {
+                    let (diff, child_changed) = self.object(&old_child, &child);
+                    changed |= child_changed;
+                    diff
+                }
+                (Some(child), None) => {
+                    changed = true;
+                    construct_object(&child, self.after)
+                }
+                (None, _) => {
+                    let value = gen_value_diff(op, &op.adjusted_value(), self.after);
+                    changed |= old.is_none_or(|old| {
+                        old.child().is_some()
+                            || gen_value_diff(old, &old.adjusted_value(), self.before) != value
+                    });
+                    value
+                }
+            };
+            values.insert(op_id, value);
+        }
+        if changed {
+            Some(values)
+        } else {
+            None
+        }
+    }
+
+    /// Edits which turn the visible elements of `old` into those of `new`. Elements which were
+    /// deleted are removed first, then the elements of `new` are walked in order, inserting any
+    /// which are missing. An element which moved is removed from its old position and inserted
+    /// again.
+    fn edits(&mut self, old: &ObjState, new: &ObjState) -> Vec<amp::DiffEdit> {
+        let old_elements = visible_elements(old, self.before);
+        let new_elements = visible_elements(new, self.after);
+        let old_ops: HashMap<&amp::OpId, &[&OpHandle]> = old_elements
+            .iter()
+            .map(|(id, ops)| (id, ops.as_slice()))
+            .collect();
+        let in_new: HashSet<&amp::OpId> = new_elements.iter().map(|(id, _)| id).collect();
+
+        let mut edits = Edits::new();
+        let mut current: Vec<&amp::OpId> = Vec::with_capacity(old_elements.len());
+        for (id, _) in &old_elements {
+            if in_new.contains(id) {
+                current.push(id);
+            } else {
+                edits.append_edit(amp::DiffEdit::Remove {
+                    index: current.len() as u64,
+                    count: 1,
+                });
+            }
+        }
+
+        for (index, (id, ops)) in new_elements.iter().enumerate() {
+            if current.get(index) == Some(&id) {
+                if let Some(values) = self.values(old_ops[id], ops) {
+                    for (op_id, value) in values {
+                        edits.append_edit(amp::DiffEdit::Update {
+                            index: index as u64,
+                            op_id,
+                            value,
+                        });
+                    }
+                }
+                continue;
+            }
+            if let Some(moved_from) = current.iter().skip(index).position(|c| *c == id) {
+                let moved_from = index + moved_from;
+                current.remove(moved_from);
+                edits.append_edit(amp::DiffEdit::Remove {
+                    index: moved_from as u64,
+                    count: 1,
+                });
+            }
+            current.insert(index, id);
+            for (i, op) in ops.iter().enumerate() {
+                let value = match op.child() {
+                    Some(child) => construct_object(&child, self.after),
+                    None => gen_value_diff(op, &op.adjusted_value(), self.after),
+                };
+                let op_id = self.after.make_external_opid(&op.id);
+                edits.append_edit(if i == 0 {
+                    amp::DiffEdit::SingleElementInsert {
+                        index: index as u64,
+                        elem_id: id.clone().into(),
+                        op_id,
+                        value,
+                    }
+                } else {
+                    amp::DiffEdit::Update {
+                        index: index as u64,
+                        op_id,
+                        value,
+                    }
+                });
+            }
+        }
+        edits.into_vec()
+    }
+}
+
+/// The external ID and values of each visible element of a sequence, in order
+fn visible_elements<'a>(
+    object: &'a ObjState,
+    workshop: &dyn PatchWorkshop,
+) -> Vec<(amp::OpId, Vec<&'a OpHandle>)> {
+    object
+        .seq
+        .into_iter()
+        .filter_map(|slot: &OpId| {
+            let ops: Vec<&OpHandle> = object.conflicts(&object.element_of(*slot).into()).collect();
+            if ops.is_empty() {
+                None
+            } else {
+                Some((workshop.make_external_opid(slot), ops))
+            }
+        })
+        .collect()
+}
@@ -1,20 +1,20 @@
 use core::cmp::max;
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
 use automerge_protocol as amp;
 
-use super::{gen_value_diff::gen_value_diff, Edits, PatchWorkshop};
+use super::{gen_mark_diff::gen_mark_diff, gen_value_diff::gen_value_diff, Edits, PatchWorkshop};
 use crate::{internal::ObjectId, object_store::ObjState};
 
 /// Used to generate a diff when there is no previous state to diff against.
 /// This works by starting at the root object and then recursively constructing
 /// all the objects contained in it.
 pub(crate) fn generate_from_scratch_diff(workshop: &dyn PatchWorkshop) -> amp::RootDiff {
-    let mut props = HashMap::new();
+    let mut props = BTreeMap::new();
 
     for (key, ops) in &workshop.get_obj(&ObjectId::Root).unwrap().props {
         if !ops.is_empty() {
-            let mut opid_to_value = HashMap::new();
+            let mut opid_to_value = BTreeMap::new();
             for op in ops.iter() {
                 let amp_opid = workshop.make_external_opid(&op.id);
                 if let Some(child_id) = op.child() {
@@ -35,11 +35,11 @@ fn construct_map(
     object: &ObjState,
     workshop: &dyn PatchWorkshop,
 ) -> amp::MapDiff {
-    let mut props = HashMap::new();
+    let mut props = BTreeMap::new();
 
     for (key, ops) in &object.props {
         if !ops.is_empty() {
-            let mut opid_to_value = HashMap::new();
+            let mut opid_to_value = BTreeMap::new();
             for op in ops.iter() {
                 let amp_opid = workshop.make_external_opid(&op.id);
                 if let Some(child_id) = op.child() {
@@ -63,11 +63,11 @@ fn construct_table(
     object: &ObjState,
     workshop: &dyn PatchWorkshop,
 ) -> amp::TableDiff {
-    let mut props = HashMap::new();
+    let mut props = BTreeMap::new();
 
     for (key, ops) in &object.props {
         if !ops.is_empty() {
-            let mut opid_to_value = HashMap::new();
+            let mut opid_to_value = BTreeMap::new();
             for op in ops.iter() {
                 let amp_opid = workshop.make_external_opid(&op.id);
                 if let Some(child_id) = op.child() {
@@ -98,7 +98,7 @@ fn construct_list(
 
     for opid in &object.seq {
         max_counter = max(max_counter, opid.0);
-        let key = (*opid).into(); // FIXME - something is wrong here
+        let key = object.element_of(*opid).into();
         if let Some(ops) = object.props.get(&key) {
             if !ops.is_empty() {
                 for op in ops.iter() {
@@ -115,12 +115,9 @@ fn construct_list(
                             value,
                         });
                     } else {
-                        let key = workshop
-                            .make_external_opid(&key.to_opid().unwrap_or(op.id))
-                            .into();
                         edits.append_edit(amp::DiffEdit::SingleElementInsert {
                             index,
-                            elem_id: key,
+                            elem_id: workshop.make_external_opid(opid).into(),
                             op_id: amp_opid,
                             value,
                         });
@@ -149,7 +146,7 @@ fn construct_text(
 
     for opid in &object.seq {
         max_counter = max(max_counter, opid.0);
-        let key = (*opid).into(); // FIXME - something is wrong here
+        let key = object.element_of(*opid).into();
         if let Some(ops) = object.props.get(&key) {
             if !ops.is_empty() {
                 for op in ops.iter() {
@@ -166,12 +163,9 @@ fn construct_text(
                             value,
                         });
                     } else {
-                        let key = workshop
-                            .make_external_opid(&key.to_opid().unwrap_or(op.id))
-                            .into();
                         edits.append_edit(amp::DiffEdit::SingleElementInsert {
                             index,
-                            elem_id: key,
+                            elem_id: workshop.make_external_opid(opid).into(),
                             op_id: amp_opid,
                             value,
                         });
@@ -182,13 +176,16 @@ fn construct_text(
             }
         }
     }
+    if !object.marks.is_empty() {
+        edits.append_edit(amp::DiffEdit::Marks(gen_mark_diff(object, workshop)));
+    }
     amp::TextDiff {
         object_id: workshop.make_external_objid(object_id),
         edits: edits.into_vec(),
     }
 }
 
-fn construct_object(object_id: &ObjectId, workshop: &dyn PatchWorkshop) -> amp::Diff {
+pub(super) fn construct_object(object_id: &ObjectId, workshop: &dyn PatchWorkshop) -> amp::Diff {
     // Safety: if the object is missing when we're generating a diff from
     // scratch then the document is corrupt
     let object = workshop.get_obj(object_id).expect("missing object");
@@ -0,0 +1,92 @@
+use std::{cmp::Ordering, collections::BTreeMap};
+
+use automerge_protocol as amp;
+use smol_str::SmolStr;
+
+use super::PatchWorkshop;
+use crate::{internal::OpId, object_store::ObjState};
+
+/// Work out which characters of a text object each of its marks applies to.
+///
+/// We walk every insertion into the object in order, keeping track of which spans are open. Where
+/// several open spans have the same name the one with the highest op ID decides the value of the
+/// mark. This is linear in the length of the text, which is fine as long as we only do it for
+/// text objects which have marks.
+pub(super) fn gen_mark_diff(object: &ObjState, workshop: &dyn PatchWorkshop) -> amp::MarkDiff {
+    let mut marks = Vec::new();
+    // the `MarkBegin` ops of the spans which contain the current position
+    let mut open: Vec<OpId> = Vec::new();
+    // the value of each mark at the current position, and where its run of that value started
+    let mut current: BTreeMap<SmolStr, (amp::ScalarValue, u64)> = BTreeMap::new();
+    let mut open_changed = false;
+    let mut index = 0;
+
+    for slot in object.insertions_in_order() {
+        if object.marks.contains_key(&slot) {
+            open.push(slot);
+            open_changed = true;
+        } else if let Some(begin) = object.mark_ends.get(&slot) {
+            open.retain(|o| o != begin);
+            open_changed = true;
+        } else if object.is_visible(slot) {
+            if open_changed {
+                let values = winning_values(object, &open, workshop);
+                let ended: Vec<SmolStr> = current
+                    .iter()
+                    .filter(|(name, (value, _))| values.get(*name) != Some(value))
+                    .map(|(name, _)| name.clone())
+                    .collect();
+                for name in ended {
+                    let (value, start) = current.remove(&name).unwrap();
+                    marks.push(amp::MarkSpan {
+                        start,
+                        end: index,
+                        name,
+                        value,
+                    });
+                }
+                for (name, value) in values {
+                    current.entry(name).or_insert((value, index));
+                }
+                open_changed = false;
+            }
+            index += 1;
+        }
+    }
+    for (name, (value, start)) in current {
+        marks.push(amp::MarkSpan {
+            start,
+            end: index,
+            name,
+            value,
+        });
+    }
+
+    marks.sort_by(|a, b| (a.start, &a.name).cmp(&(b.start, &b.name)));
+    amp::MarkDiff { marks }
+}
+
+/// The value of each mark in the spans `open`, leaving out marks which have been set to null
+fn winning_values(
+    object: &ObjState,
+    open: &[OpId],
+    workshop: &dyn PatchWorkshop,
+) -> BTreeMap<SmolStr, amp::ScalarValue> {
+    let mut winners: BTreeMap<&SmolStr, OpId> = BTreeMap::new();
+    for begin in open {
+        let name = &object.marks[begin].name;
+        match winners.get(name) {
+            Some(winner) if workshop.cmp_opid(winner, begin) == Ordering::Greater => {}
+            _ => {
+                winners.insert(name, *begin);
+            }
+        }
+    }
+    winners
+        .into_iter()
+        .filter_map(|(name, begin)| match &object.marks[&begin].value {
+            amp::ScalarValue::Null => None,
+            value => Some((name.clone(), value.clone())),
+        })
+        .collect()
+}
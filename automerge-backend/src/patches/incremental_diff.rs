@@ -1,11 +1,14 @@
 use std::{
     borrow::Cow,
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
 };
 
 use automerge_protocol as amp;
 
-use super::{gen_value_diff::gen_value_diff, Edits, PatchWorkshop};
+use super::{
+    from_scratch_diff::construct_object, gen_mark_diff::gen_mark_diff,
+    gen_value_diff::gen_value_diff, Edits, PatchWorkshop,
+};
 use crate::{
     actor_map::ActorMap,
     internal::{InternalOpType, Key, ObjectId, OpId},
@@ -21,6 +24,9 @@ enum PendingDiff {
     // contains the op handle, the index to insert after and the new element's id
     SeqUpdate(OpHandle, usize, OpId),
     SeqRemove(OpHandle, usize),
+    // contains the conflicting ops for an element which was moved, the index it was moved to and
+    // the slot it now occupies
+    SeqMove(Vec<OpHandle>, usize, OpId),
     Set(OpHandle),
     CursorChange(Key),
 }
@@ -32,6 +38,7 @@ impl PendingDiff {
             | Self::SeqUpdate(op, ..)
             | Self::SeqRemove(op, ..)
             | Self::Set(op) => op.operation_key(),
+            Self::SeqMove(ops, ..) => ops[0].operation_key(),
             Self::CursorChange(k) => Cow::Borrowed(k),
         }
     }
@@ -123,6 +130,27 @@ impl IncrementalPatch {
         self.append_diffs(oid, new_diffs);
     }
 
+    /// Record that an element was moved to `index`, where it now occupies `slot`. `ops` are the
+    /// operations which make up the element's value, the frontend receives these in full as the
+    /// element is removed from its old position.
+    pub(crate) fn record_seq_move(
+        &mut self,
+        oid: &ObjectId,
+        ops: Vec<OpHandle>,
+        index: usize,
+        slot: OpId,
+    ) {
+        if !ops.is_empty() {
+            self.append_diff(oid, PendingDiff::SeqMove(ops, index, slot));
+        }
+    }
+
+    /// Record that the formatting of a text object may have changed without any of its
+    /// characters changing
+    pub(crate) fn record_marks_change(&mut self, oid: &ObjectId) {
+        self.0.entry(*oid).or_default();
+    }
+
     pub(crate) fn record_seq_remove(&mut self, oid: &ObjectId, op: OpHandle, index: usize) {
         self.append_diff(oid, PendingDiff::SeqRemove(op, index));
     }
@@ -161,11 +189,11 @@ impl IncrementalPatch {
         if let Some(root) = self.0.remove(&ObjectId::Root) {
             // I may have duplicate keys - I do this to make sure I visit each one only once
             let keys: HashSet<_> = root.iter().map(PendingDiff::operation_key).collect();
-            let mut props = HashMap::with_capacity(keys.len());
+            let mut props = BTreeMap::new();
             let obj = workshop.get_obj(&ObjectId::Root).expect("no root found");
             for key in &keys {
                 let key_string = workshop.key_to_string(key);
-                let mut opid_to_value = HashMap::new();
+                let mut opid_to_value = BTreeMap::new();
                 for op in obj.conflicts(key) {
                     let link = match op.action {
                         InternalOpType::Set(ref value) => gen_value_diff(op, value, workshop),
@@ -179,7 +207,7 @@ impl IncrementalPatch {
             amp::RootDiff { props }
         } else {
             amp::RootDiff {
-                props: HashMap::new(),
+                props: BTreeMap::new(),
             }
         }
     }
@@ -211,11 +239,11 @@ impl IncrementalPatch {
             match obj.obj_type {
                 amp::ObjType::Map => amp::Diff::Map(amp::MapDiff {
                     object_id: workshop.make_external_objid(obj_id),
-                    props: HashMap::new(),
+                    props: BTreeMap::new(),
                 }),
                 amp::ObjType::Table => amp::Diff::Table(amp::TableDiff {
                     object_id: workshop.make_external_objid(obj_id),
-                    props: HashMap::new(),
+                    props: BTreeMap::new(),
                 }),
                 amp::ObjType::List => amp::Diff::List(amp::ListDiff {
                     object_id: workshop.make_external_objid(obj_id),
@@ -262,7 +290,11 @@ impl IncrementalPatch {
                     let value = match op.action {
                         InternalOpType::Set(ref value) => gen_value_diff(op, value, workshop),
                         InternalOpType::Make(_) => self.gen_obj_diff(&op.id.into(), workshop),
-                        InternalOpType::Del | InternalOpType::Inc(..) => {
+                        InternalOpType::Del
+                        | InternalOpType::Inc(..)
+                        | InternalOpType::Move(_)
+                        | InternalOpType::MarkBegin(_)
+                        | InternalOpType::MarkEnd(_) => {
                             // do nothing
                             continue;
                         }
@@ -273,6 +305,9 @@ impl IncrementalPatch {
                         value,
                     });
                 }
+                PendingDiff::SeqMove(ops, index, slot) => {
+                    append_move_edits(&mut edits, &mut seen_op_ids, ops, *index, slot, workshop);
+                }
                 PendingDiff::SeqRemove(op, index) => {
                     seen_op_ids.insert(op.id);
 
@@ -346,7 +381,11 @@ impl IncrementalPatch {
                     let value = match op.action {
                         InternalOpType::Set(ref value) => gen_value_diff(op, value, workshop),
                         InternalOpType::Make(_) => self.gen_obj_diff(&op.id.into(), workshop),
-                        InternalOpType::Del | InternalOpType::Inc(..) => {
+                        InternalOpType::Del
+                        | InternalOpType::Inc(..)
+                        | InternalOpType::Move(_)
+                        | InternalOpType::MarkBegin(_)
+                        | InternalOpType::MarkEnd(_) => {
                             // do nothing
                             continue;
                         }
@@ -357,6 +396,9 @@ impl IncrementalPatch {
                         value,
                     });
                 }
+                PendingDiff::SeqMove(ops, index, slot) => {
+                    append_move_edits(&mut edits, &mut seen_op_ids, ops, *index, slot, workshop);
+                }
                 PendingDiff::SeqRemove(op, index) => {
                     seen_op_ids.insert(op.id);
 
@@ -391,6 +433,9 @@ impl IncrementalPatch {
                 }
             }
         }
+        if !obj.marks.is_empty() {
+            edits.append_edit(amp::DiffEdit::Marks(gen_mark_diff(obj, workshop)));
+        }
         amp::TextDiff {
             object_id: workshop.make_external_objid(obj_id),
             edits: edits.into_vec(),
@@ -406,10 +451,10 @@ impl IncrementalPatch {
     ) -> amp::MapDiff {
         // I may have duplicate keys - I do this to make sure I visit each one only once
         let keys: HashSet<_> = pending.iter().map(PendingDiff::operation_key).collect();
-        let mut props = HashMap::with_capacity(keys.len());
+        let mut props = BTreeMap::new();
         for key in &keys {
             let key_string = workshop.key_to_string(key);
-            let mut opid_to_value = HashMap::new();
+            let mut opid_to_value = BTreeMap::new();
             for op in obj.conflicts(key) {
                 let value = match op.action {
                     InternalOpType::Set(ref value) => gen_value_diff(op, value, workshop),
@@ -433,12 +478,12 @@ impl IncrementalPatch {
         pending: &[PendingDiff],
         workshop: &dyn PatchWorkshop,
     ) -> amp::TableDiff {
-        let mut props = HashMap::new();
+        let mut props = BTreeMap::new();
         // I may have duplicate keys - I do this to make sure I visit each one only once
         let keys: HashSet<_> = pending.iter().map(PendingDiff::operation_key).collect();
         for key in &keys {
             let key_string = workshop.key_to_string(key);
-            let mut opid_to_value = HashMap::new();
+            let mut opid_to_value = BTreeMap::new();
             for op in obj.conflicts(key) {
                 let link = match op.action {
                     InternalOpType::Set(ref value) => gen_value_diff(op, value, workshop),
@@ -455,3 +500,38 @@ impl IncrementalPatch {
         }
     }
 }
+
+/// Generate the edits which insert a moved element at its new position. The element's value is
+/// constructed from scratch because the frontend discards the old copy when it is removed.
+fn append_move_edits(
+    edits: &mut Edits,
+    seen_op_ids: &mut HashSet<OpId>,
+    ops: &[OpHandle],
+    index: usize,
+    slot: &OpId,
+    workshop: &dyn PatchWorkshop,
+) {
+    let elem_id: amp::ElementId = workshop.make_external_opid(slot).into();
+    for (i, op) in ops.iter().enumerate() {
+        seen_op_ids.insert(op.id);
+        let value = match op.child() {
+            Some(child_id) => construct_object(&child_id, workshop),
+            None => gen_value_diff(op, &op.adjusted_value(), workshop),
+        };
+        let op_id = workshop.make_external_opid(&op.id);
+        if i == 0 {
+            edits.append_edit(amp::DiffEdit::SingleElementInsert {
+                index: index as u64,
+                elem_id: elem_id.clone(),
+                op_id,
+                value,
+            });
+        } else {
+            edits.append_edit(amp::DiffEdit::Update {
+                index: index as u64,
+                op_id,
+                value,
+            });
+        }
+    }
+}
@@ -1,3 +1,5 @@
+use std::cmp::Ordering;
+
 use automerge_protocol as amp;
 use smol_str::SmolStr;
 
@@ -20,4 +22,5 @@ pub(crate) trait PatchWorkshop {
     fn get_obj(&self, object_id: &ObjectId) -> Option<&ObjState>;
     fn make_external_objid(&self, object_id: &ObjectId) -> amp::ObjectId;
     fn make_external_opid(&self, opid: &OpId) -> amp::OpId;
+    fn cmp_opid(&self, op1: &OpId, op2: &OpId) -> Ordering;
 }
@@ -0,0 +1,310 @@
+use std::{collections::HashMap, io};
+
+use automerge_protocol as amp;
+
+use crate::{AutomergeError, Backend, Change, QuarantinedChange, Quotas, SyncMessage, SyncState};
+
+/// Somewhere to keep one document durably: a snapshot of the document made with
+/// [`Backend::save`], the changes applied since the snapshot, and the [`SyncState`] of every
+/// peer the document syncs with.
+///
+/// [`PersistentBackend`] drives a persister, so implementations only have to store bytes.
+pub trait Persister {
+    /// Append an encoded change to the changes since the snapshot. If this fails none of the
+    /// change may be kept, as it is appended again when the write is retried.
+    fn append_change(&mut self, bytes: &[u8]) -> io::Result<()>;
+
+    /// Every change appended since the last snapshot, in the order they were appended
+    fn load_changes(&self) -> io::Result<Vec<Vec<u8>>>;
+
+    /// Replace the snapshot with `bytes`, which includes every change appended so far, so they
+    /// can be dropped
+    fn store_snapshot(&mut self, bytes: &[u8]) -> io::Result<()>;
+
+    fn load_snapshot(&self) -> io::Result<Option<Vec<u8>>>;
+
+    /// Store the encoded sync state for `peer`, replacing any previous state
+    fn store_sync_state(&mut self, peer: &[u8], bytes: &[u8]) -> io::Result<()>;
+
+    fn load_sync_state(&self, peer: &[u8]) -> io::Result<Option<Vec<u8>>>;
+
+    /// Make sure everything stored so far will survive a crash. Called before a change is
+    /// acknowledged.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Whether the changes have built up enough that they should be replaced with a snapshot,
+    /// which [`PersistentBackend`] checks after storing changes
+    fn wants_snapshot(&self) -> bool {
+        false
+    }
+}
+
+impl<P: Persister + ?Sized> Persister for Box<P> {
+    fn append_change(&mut self, bytes: &[u8]) -> io::Result<()> {
+        (**self).append_change(bytes)
+    }
+
+    fn load_changes(&self) -> io::Result<Vec<Vec<u8>>> {
+        (**self).load_changes()
+    }
+
+    fn store_snapshot(&mut self, bytes: &[u8]) -> io::Result<()> {
+        (**self).store_snapshot(bytes)
+    }
+
+    fn load_snapshot(&self) -> io::Result<Option<Vec<u8>>> {
+        (**self).load_snapshot()
+    }
+
+    fn store_sync_state(&mut self, peer: &[u8], bytes: &[u8]) -> io::Result<()> {
+        (**self).store_sync_state(peer, bytes)
+    }
+
+    fn load_sync_state(&self, peer: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        (**self).load_sync_state(peer)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (**self).flush()
+    }
+
+    fn wants_snapshot(&self) -> bool {
+        (**self).wants_snapshot()
+    }
+}
+
+/// A [`Persister`] which keeps everything in memory, for tests and for documents which don't
+/// need to outlive the process.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryPersister {
+    snapshot: Option<Vec<u8>>,
+    changes: Vec<Vec<u8>>,
+    sync_states: HashMap<Vec<u8>, Vec<u8>>,
+}
+
+impl MemoryPersister {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Persister for MemoryPersister {
+    fn append_change(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.changes.push(bytes.to_vec());
+        Ok(())
+    }
+
+    fn load_changes(&self) -> io::Result<Vec<Vec<u8>>> {
+        Ok(self.changes.clone())
+    }
+
+    fn store_snapshot(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.snapshot = Some(bytes.to_vec());
+        self.changes.clear();
+        Ok(())
+    }
+
+    fn load_snapshot(&self) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.snapshot.clone())
+    }
+
+    fn store_sync_state(&mut self, peer: &[u8], bytes: &[u8]) -> io::Result<()> {
+        self.sync_states.insert(peer.to_vec(), bytes.to_vec());
+        Ok(())
+    }
+
+    fn load_sync_state(&self, peer: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.sync_states.get(peer).cloned())
+    }
+}
+
+/// A [`Backend`] which writes every change it applies to a [`Persister`] before returning the
+/// patch, so a change which has been acknowledged is never lost.
+///
+/// Changes which can't be applied yet because their dependencies are missing are only written
+/// once they are applied; until then a crash loses them, and sync will fetch them again.
+///
+/// If the persister fails the change has still been applied to the backend, but isn't
+/// acknowledged: the error is returned instead of the patch. Every later call which changes the
+/// document writes whatever hasn't been written yet before anything else, as does
+/// [`Self::persist`], so nothing is skipped. Once that succeeds a frontend can catch up with
+/// [`Backend::get_patch`].
+///
+/// To keep the stored changes from growing without bound, [`Self::set_compact_after`] makes the
+/// backend replace them with a snapshot every so often. It also does so whenever the persister
+/// [wants a snapshot](Persister::wants_snapshot).
+#[derive(Debug)]
+pub struct PersistentBackend<P> {
+    backend: Backend,
+    persister: P,
+    /// The number of changes stored since the snapshot
+    stored_changes: usize,
+    /// How much of the backend's history has been written to the persister and flushed
+    persisted: usize,
+    compact_after: Option<usize>,
+}
+
+impl<P: Persister> PersistentBackend<P> {
+    /// Load the document kept in `persister`, which is empty if nothing has been stored yet
+    pub fn load(persister: P) -> Result<Self, AutomergeError> {
+        let mut backend = match persister.load_snapshot()? {
+            Some(snapshot) => Backend::load(snapshot)?,
+            None => Backend::new(),
+        };
+        let changes = persister
+            .load_changes()?
+            .into_iter()
+            .map(Change::from_bytes)
+            .collect::<Result<Vec<_>, _>>()?;
+        let stored_changes = changes.len();
+        backend.load_changes(changes)?;
+        Ok(Self {
+            persisted: backend.history.len(),
+            backend,
+            persister,
+            stored_changes,
+            compact_after: None,
+        })
+    }
+
+    pub fn compact_after(&self) -> Option<usize> {
+        self.compact_after
+    }
+
+    /// Compact the document whenever more than `changes` changes have been stored since the last
+    /// snapshot, or never if `None` (the default)
+    pub fn set_compact_after(&mut self, changes: Option<usize>) {
+        self.compact_after = changes;
+    }
+
+    pub fn backend(&self) -> &Backend {
+        &self.backend
+    }
+
+    /// Enforce `quotas` on every change applied from now on, see [`Backend::set_quotas`]
+    pub fn set_quotas(&mut self, quotas: Quotas) {
+        self.backend.set_quotas(quotas);
+    }
+
+    /// See [`Backend::take_quarantined`]. Quarantined changes aren't persisted.
+    pub fn take_quarantined(&mut self) -> Vec<QuarantinedChange> {
+        self.backend.take_quarantined()
+    }
+
+    pub fn persister(&self) -> &P {
+        &self.persister
+    }
+
+    pub fn persister_mut(&mut self) -> &mut P {
+        &mut self.persister
+    }
+
+    pub fn into_inner(self) -> (Backend, P) {
+        (self.backend, self.persister)
+    }
+
+    pub fn apply_changes(&mut self, changes: Vec<Change>) -> Result<amp::Patch, AutomergeError> {
+        self.persist()?;
+        let patch = self.backend.apply_changes(changes)?;
+        self.persist()?;
+        Ok(patch)
+    }
+
+    pub fn apply_local_change(
+        &mut self,
+        change: amp::Change,
+    ) -> Result<amp::Patch, AutomergeError> {
+        self.persist()?;
+        let (patch, _) = self.backend.apply_local_change(change)?;
+        self.persist()?;
+        Ok(patch)
+    }
+
+    /// The sync state which was stored for `peer`, or a fresh one
+    pub fn sync_state(&self, peer: &[u8]) -> Result<SyncState, AutomergeError> {
+        match self.persister.load_sync_state(peer)? {
+            Some(bytes) => Ok(SyncState::decode(&bytes)?),
+            None => Ok(SyncState::default()),
+        }
+    }
+
+    /// Receive a sync message from `peer`, storing any changes it contained and then the updated
+    /// sync state
+    pub fn receive_sync_message(
+        &mut self,
+        peer: &[u8],
+        sync_state: &mut SyncState,
+        message: SyncMessage,
+    ) -> Result<Option<amp::Patch>, AutomergeError> {
+        let patch = self.apply_sync_message(sync_state, message)?;
+        self.store_sync_state(peer, sync_state)?;
+        Ok(patch)
+    }
+
+    /// Like [`Self::receive_sync_message`] for a peer whose sync state isn't stored, e.g.
+    /// because it only lasts as long as the connection to the peer
+    pub fn apply_sync_message(
+        &mut self,
+        sync_state: &mut SyncState,
+        message: SyncMessage,
+    ) -> Result<Option<amp::Patch>, AutomergeError> {
+        self.persist()?;
+        let patch = self.backend.receive_sync_message(sync_state, message)?;
+        self.persist()?;
+        Ok(patch)
+    }
+
+    pub fn generate_sync_message(&self, sync_state: &mut SyncState) -> Option<SyncMessage> {
+        self.backend.generate_sync_message(sync_state)
+    }
+
+    pub fn store_sync_state(
+        &mut self,
+        peer: &[u8],
+        sync_state: &SyncState,
+    ) -> Result<(), AutomergeError> {
+        self.persister
+            .store_sync_state(peer, &sync_state.encode()?)?;
+        Ok(())
+    }
+
+    /// Replace the stored changes with a snapshot of the whole document, which is smaller and
+    /// quicker to load
+    pub fn compact(&mut self) -> Result<(), AutomergeError> {
+        let snapshot = self.backend.save()?;
+        self.persister.store_snapshot(&snapshot)?;
+        self.persister.flush()?;
+        self.stored_changes = 0;
+        self.persisted = self.backend.history.len();
+        Ok(())
+    }
+
+    /// Whether every change in the backend has been written to the persister
+    pub fn is_persisted(&self) -> bool {
+        self.persisted == self.backend.history.len()
+    }
+
+    /// Write every change which hasn't been written yet, which is only needed after the
+    /// persister has failed
+    pub fn persist(&mut self) -> Result<(), AutomergeError> {
+        if self.is_persisted() {
+            return Ok(());
+        }
+        // If this fails part way through the next call appends the changes which were written
+        // again, which is harmless as loading skips duplicates
+        for change in &self.backend.history[self.persisted..] {
+            self.persister.append_change(change.raw_bytes())?;
+            self.stored_changes += 1;
+        }
+        let too_many = matches!(self.compact_after, Some(limit) if self.stored_changes > limit);
+        if too_many || self.persister.wants_snapshot() {
+            self.compact()
+        } else {
+            self.persister.flush()?;
+            self.persisted = self.backend.history.len();
+            Ok(())
+        }
+    }
+}
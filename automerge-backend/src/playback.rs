@@ -0,0 +1,99 @@
+use std::{slice, time::Duration};
+
+use automerge_protocol as amp;
+
+use crate::{error::AutomergeError, Backend, Change};
+
+/// One step of a [`Playback`]: the patch produced by applying a single change from the history.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlaybackEvent {
+    /// The timestamp recorded in the change, in milliseconds since the epoch.
+    pub time: i64,
+    /// How long to wait after the previous event before showing this one.
+    pub delay: Duration,
+    pub hash: amp::ChangeHash,
+    pub patch: amp::Patch,
+}
+
+/// Replays the history of a backend change by change, as returned by [`Backend::playback`].
+///
+/// Each event carries the delay since the previous one, derived from the change timestamps, so
+/// the consumer decides how to wait (sleeping a thread, a timer in an event loop, or not at all
+/// when generating benchmark workloads). Changes whose timestamps run backwards, e.g. because of
+/// clock skew between actors, have a delay of zero.
+pub struct Playback<'a> {
+    changes: slice::Iter<'a, Change>,
+    backend: Backend,
+    last_time: Option<i64>,
+    speed: f64,
+    max_delay: Option<Duration>,
+}
+
+impl Playback<'_> {
+    /// Scale the delays between events, `2.0` plays back twice as fast as the original edits.
+    ///
+    /// # Panics
+    ///
+    /// Iterating panics if `speed` is not a positive number.
+    #[must_use]
+    pub fn speed(mut self, speed: f64) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    /// Cap the delay between events, so long pauses in the original editing session are
+    /// skipped over.
+    #[must_use]
+    pub fn max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = Some(max_delay);
+        self
+    }
+
+    fn delay(&self, time: i64) -> Duration {
+        let elapsed = self
+            .last_time
+            .map_or(0, |last| time.saturating_sub(last).max(0));
+        let delay = Duration::from_millis(elapsed as u64).div_f64(self.speed);
+        match self.max_delay {
+            Some(max) => delay.min(max),
+            None => delay,
+        }
+    }
+}
+
+impl Iterator for Playback<'_> {
+    type Item = Result<PlaybackEvent, AutomergeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let change = self.changes.next()?;
+        let delay = self.delay(change.time);
+        self.last_time = Some(change.time);
+        Some(
+            self.backend
+                .apply_changes(vec![change.clone()])
+                .map(|patch| PlaybackEvent {
+                    time: change.time,
+                    delay,
+                    hash: change.hash,
+                    patch,
+                }),
+        )
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.changes.size_hint()
+    }
+}
+
+impl Backend {
+    /// Replay the history of this backend from an empty document.
+    pub fn playback(&self) -> Playback<'_> {
+        Playback {
+            changes: self.history.iter(),
+            backend: Backend::new(),
+            last_time: None,
+            speed: 1.0,
+            max_delay: None,
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use std::collections::HashSet;
+
+use automerge_protocol as amp;
+use thiserror::Error;
+
+use crate::{
+    error::AutomergeError, quota::QuotaExceeded, timestamps::TimeWentBackwards, Backend, Change,
+};
+
+/// The number of changes [`Backend::quarantine_capacity`] is by default
+pub const DEFAULT_QUARANTINE_CAPACITY: usize = 1000;
+
+/// A remote change which the backend refused to apply, along with why.
+///
+/// Quarantined changes are kept out of the history (and so out of `save` and `get_changes`) but
+/// are not dropped, they can be inspected with [`Backend::quarantined`], taken out of the backend
+/// with [`Backend::take_quarantined`] or applied again with [`Backend::retry_quarantined`] once
+/// the quotas have been relaxed.
+///
+/// So that a misbehaving peer can't fill up memory with rejected changes, only the most recent
+/// [`Backend::quarantine_capacity`] are kept.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuarantinedChange {
+    pub change: Change,
+    pub reason: Rejection,
+}
+
+/// Why a change was quarantined
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum Rejection {
+    #[error(transparent)]
+    Quota(#[from] QuotaExceeded),
+    #[error(transparent)]
+    Timestamp(#[from] TimeWentBackwards),
+}
+
+impl From<Rejection> for AutomergeError {
+    fn from(rejection: Rejection) -> Self {
+        match rejection {
+            Rejection::Quota(e) => e.into(),
+            Rejection::Timestamp(e) => e.into(),
+        }
+    }
+}
+
+/// The rejected changes of a backend, oldest first, indexed by hash
+#[derive(Debug, Clone)]
+pub(crate) struct Quarantine {
+    changes: Vec<QuarantinedChange>,
+    hashes: HashSet<amp::ChangeHash>,
+    capacity: usize,
+}
+
+impl Default for Quarantine {
+    fn default() -> Self {
+        Quarantine {
+            changes: Vec::new(),
+            hashes: HashSet::new(),
+            capacity: DEFAULT_QUARANTINE_CAPACITY,
+        }
+    }
+}
+
+impl Quarantine {
+    fn insert(&mut self, quarantined: QuarantinedChange) {
+        if self.capacity == 0 || !self.hashes.insert(quarantined.change.hash) {
+            return;
+        }
+        self.changes.push(quarantined);
+        self.evict();
+    }
+
+    /// Drop the oldest changes until there are no more than `capacity`
+    fn evict(&mut self) {
+        if self.changes.len() > self.capacity {
+            let excess = self.changes.len() - self.capacity;
+            for evicted in self.changes.drain(..excess) {
+                tracing::warn!(hash = ?evicted.change.hash, "dropping quarantined change");
+                self.hashes.remove(&evicted.change.hash);
+            }
+        }
+    }
+}
+
+impl Backend {
+    /// Check a change against the timestamp policy and the quotas
+    pub(crate) fn check_change(&mut self, change: &Change) -> Result<(), Rejection> {
+        self.check_timestamp(change)?;
+        self.check_quotas(change)?;
+        Ok(())
+    }
+
+    pub(crate) fn quarantine(&mut self, change: Change, reason: Rejection) {
+        self.quarantine.insert(QuarantinedChange { change, reason });
+    }
+
+    pub(crate) fn is_quarantined(&self, hash: &amp::ChangeHash) -> bool {
+        self.quarantine.hashes.contains(hash)
+    }
+
+    /// The changes which have been rejected so far, in the order they were rejected.
+    pub fn quarantined(&self) -> &[QuarantinedChange] {
+        &self.quarantine.changes
+    }
+
+    /// Remove all of the quarantined changes from the backend and return them.
+    pub fn take_quarantined(&mut self) -> Vec<QuarantinedChange> {
+        self.quarantine.hashes.clear();
+        std::mem::take(&mut self.quarantine.changes)
+    }
+
+    /// The most changes the backend keeps in quarantine
+    pub fn quarantine_capacity(&self) -> usize {
+        self.quarantine.capacity
+    }
+
+    /// Keep at most `capacity` rejected changes, dropping the oldest ones when more are
+    /// rejected. A capacity of 0 drops rejected changes straight away.
+    pub fn set_quarantine_capacity(&mut self, capacity: usize) {
+        self.quarantine.capacity = capacity;
+        self.quarantine.evict();
+    }
+
+    /// Try to apply all of the quarantined changes again, under the current quotas.
+    ///
+    /// Any which are still rejected end up back in quarantine with an updated reason.
+    pub fn retry_quarantined(&mut self) -> Result<amp::Patch, AutomergeError> {
+        let changes = self
+            .take_quarantined()
+            .into_iter()
+            .map(|q| q.change)
+            .collect();
+        self.apply_changes(changes)
+    }
+}
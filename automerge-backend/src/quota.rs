@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+use std::time::{Duration, Instant};
+
+use automerge_protocol as amp;
+use thiserror::Error;
+
+use crate::{
+    internal::{InternalOpType, ObjectId},
+    Backend, Change,
+};
+
+/// Hard limits on the changes a [`Backend`] will accept.
+///
+/// Every limit is optional and unset by default. The limits are checked for every change the
+/// backend applies, whether it came from `apply_local_change` or from a remote peer, and a change
+/// which breaks any of them is rejected as a whole before any of its operations are applied.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Quotas {
+    /// The deepest an object may be nested below the root. Objects directly in the root are at
+    /// depth 1.
+    pub max_depth: Option<usize>,
+    /// The maximum length in bytes of a single string or bytes value.
+    pub max_value_size: Option<usize>,
+    /// The maximum number of operations in a single change, counting each element of a
+    /// multi-insert or multi-delete separately.
+    pub max_ops_per_change: Option<usize>,
+    /// The maximum number of changes a single actor may have applied in any one second window.
+    ///
+    /// This is measured against the wall clock when the backend applies the change, not the
+    /// timestamp in the change, so a peer which catches up on a long backlog in one go can hit
+    /// it. There is no clock to measure it with on wasm32-unknown-unknown, so it doesn't exist
+    /// there.
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    pub max_changes_per_second: Option<usize>,
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum QuotaExceeded {
+    #[error("Change {hash:?} nests an object at depth {depth}, the maximum is {max}")]
+    Depth {
+        hash: amp::ChangeHash,
+        depth: usize,
+        max: usize,
+    },
+    #[error("Change {hash:?} contains a value of {size} bytes, the maximum is {max}")]
+    ValueSize {
+        hash: amp::ChangeHash,
+        size: usize,
+        max: usize,
+    },
+    #[error("Change {hash:?} contains {ops} operations, the maximum is {max}")]
+    OpsPerChange {
+        hash: amp::ChangeHash,
+        ops: usize,
+        max: usize,
+    },
+    #[error("Actor {actor} applied more than {max} changes in one second")]
+    Rate { actor: amp::ActorId, max: usize },
+}
+
+/// Times at which recent changes from each actor were applied, for `max_changes_per_second`.
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ChangeRates(HashMap<amp::ActorId, Vec<Instant>>);
+
+impl Backend {
+    /// Create a backend which enforces `quotas` on every change applied to it.
+    pub fn with_quotas(quotas: Quotas) -> Self {
+        let mut backend = Self::new();
+        backend.quotas = quotas;
+        backend
+    }
+
+    pub fn quotas(&self) -> &Quotas {
+        &self.quotas
+    }
+
+    pub fn set_quotas(&mut self, quotas: Quotas) {
+        self.quotas = quotas;
+    }
+
+    pub(crate) fn check_quotas(&mut self, change: &Change) -> Result<(), QuotaExceeded> {
+        if self.quotas == Quotas::default() {
+            return Ok(());
+        }
+
+        let mut ops = 0;
+        let mut created: HashMap<amp::ObjectId, usize> = HashMap::new();
+        for op in change.iter_ops() {
+            let op_id = amp::OpId(change.start_op + ops as u64, change.actor_id().clone());
+            ops += 1;
+            match &op.action {
+                InternalOpType::Set(value) => {
+                    let size = match value {
+                        amp::ScalarValue::Str(s) => s.len(),
+                        amp::ScalarValue::Bytes(b) => b.len(),
+                        _ => continue,
+                    };
+                    if let Some(max) = self.quotas.max_value_size {
+                        if size > max {
+                            return Err(QuotaExceeded::ValueSize {
+                                hash: change.hash,
+                                size,
+                                max,
+                            });
+                        }
+                    }
+                }
+                InternalOpType::Make(_) => {
+                    if let Some(max) = self.quotas.max_depth {
+                        let parent_depth = match created.get(op.obj.as_ref()) {
+                            Some(depth) => *depth,
+                            None => self.depth_of(&op.obj),
+                        };
+                        let depth = parent_depth + 1;
+                        if depth > max {
+                            return Err(QuotaExceeded::Depth {
+                                hash: change.hash,
+                                depth,
+                                max,
+                            });
+                        }
+                        created.insert(amp::ObjectId::Id(op_id), depth);
+                    }
+                }
+                InternalOpType::Del
+                | InternalOpType::Inc(_)
+                | InternalOpType::Move(_)
+                | InternalOpType::MarkBegin(_)
+                | InternalOpType::MarkEnd(_) => {}
+            }
+        }
+
+        if let Some(max) = self.quotas.max_ops_per_change {
+            if ops > max {
+                return Err(QuotaExceeded::OpsPerChange {
+                    hash: change.hash,
+                    ops,
+                    max,
+                });
+            }
+        }
+
+        #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+        if let Some(max) = self.quotas.max_changes_per_second {
+            let now = Instant::now();
+            let times = self
+                .change_rates
+                .0
+                .entry(change.actor_id().clone())
+                .or_default();
+            times.retain(|t| now.duration_since(*t) < Duration::from_secs(1));
+            if times.len() >= max {
+                return Err(QuotaExceeded::Rate {
+                    actor: change.actor_id().clone(),
+                    max,
+                });
+            }
+            times.push(now);
+        }
+
+        Ok(())
+    }
+
+    /// How far below the root `obj` currently sits. An object which has been detached from its
+    /// parent is treated as if it were in the root.
+    fn depth_of(&self, obj: &amp::ObjectId) -> usize {
+        let mut depth = 0;
+        let mut current = self.actors.lookup_obj(obj);
+        while let Some(ObjectId::Id(id)) = current {
+            depth += 1;
+            current = self
+                .op_set
+                .objs
+                .get(&ObjectId::Id(id))
+                .and_then(|state| state.inbound.as_ref())
+                .map(|inbound| inbound.obj);
+        }
+        depth
+    }
+}
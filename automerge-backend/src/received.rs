@@ -0,0 +1,123 @@
+use std::{borrow::Cow, collections::HashMap, convert::TryFrom};
+
+use automerge_protocol as amp;
+
+use crate::{decoding, decoding::Decoder, encoding, encoding::Encodable, Backend};
+
+const RECEIVED_AT_TYPE: u8 = 0x52; // first byte of encoded receive times, for identification
+const HASH_SIZE: usize = 32;
+
+/// Milliseconds since the epoch, for timestamping received changes
+pub type Clock = fn() -> i64;
+
+/// When this backend first applied each remote change, by its local clock.
+///
+/// This is local bookkeeping rather than part of the document: it isn't written by `save` and
+/// isn't sent to other peers, see [`Backend::encode_received_at`] for persisting it.
+#[derive(Debug, Clone)]
+pub(crate) struct ReceivedAt {
+    clock: Option<Clock>,
+    times: HashMap<amp::ChangeHash, i64>,
+}
+
+impl Default for ReceivedAt {
+    fn default() -> Self {
+        ReceivedAt {
+            clock: default_clock(),
+            times: HashMap::new(),
+        }
+    }
+}
+
+fn default_clock() -> Option<Clock> {
+    // There's no system clock on wasm32-unknown-unknown, the host has to provide one
+    if cfg!(all(target_arch = "wasm32", target_os = "unknown")) {
+        None
+    } else {
+        Some(system_clock)
+    }
+}
+
+fn system_clock() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as i64)
+}
+
+impl ReceivedAt {
+    pub(crate) fn get(&self, hash: &amp::ChangeHash) -> Option<i64> {
+        self.times.get(hash).copied()
+    }
+}
+
+impl Backend {
+    /// Use `clock` to timestamp remote changes as they are applied, or stop timestamping them if
+    /// it is `None`. By default the system clock is used, except on wasm32-unknown-unknown where
+    /// there isn't one.
+    pub fn set_received_clock(&mut self, clock: Option<Clock>) {
+        self.received_at.clock = clock;
+    }
+
+    /// When the change `hash` was first applied to this backend by `apply_changes` (or a sync
+    /// message), in milliseconds since the epoch. This is `None` for local changes, changes read
+    /// by `load` or `load_incremental`, and changes applied while there was no clock.
+    ///
+    /// Unlike the change's own `time`, which is set by whoever made it, this comes from the local
+    /// clock, so `now - received_at` is how long ago this peer learnt of the change.
+    pub fn received_at(&self, hash: &amp::ChangeHash) -> Option<i64> {
+        self.received_at.get(hash)
+    }
+
+    /// Timestamp the changes in `history` from `from` onwards as just received
+    pub(crate) fn record_received(&mut self, from: usize) {
+        if let Some(clock) = self.received_at.clock {
+            let now = clock();
+            for change in &self.history[from..] {
+                self.received_at.times.entry(change.hash).or_insert(now);
+            }
+        }
+    }
+
+    /// Encode the receive times so they can be stored alongside the document, and given to
+    /// [`Backend::restore_received_at`] after it is loaded again.
+    pub fn encode_received_at(&self) -> Result<Vec<u8>, encoding::Error> {
+        // in history order, so the encoding of a backend is deterministic
+        let times: Vec<_> = self
+            .history
+            .iter()
+            .filter_map(|change| Some((change.hash, self.received_at(&change.hash)?)))
+            .collect();
+        let mut buf = vec![RECEIVED_AT_TYPE];
+        times.len().encode(&mut buf)?;
+        for (hash, time) in times {
+            buf.extend_from_slice(&hash.0);
+            time.encode(&mut buf)?;
+        }
+        Ok(buf)
+    }
+
+    /// Restore receive times written by [`Backend::encode_received_at`]. Times for changes this
+    /// backend doesn't have are kept in case the changes arrive later, and a time it already has
+    /// for a change is left alone.
+    pub fn restore_received_at(&mut self, bytes: &[u8]) -> Result<(), decoding::Error> {
+        let mut decoder = Decoder::new(Cow::Borrowed(bytes));
+        let record_type = decoder.read::<u8>()?;
+        if record_type != RECEIVED_AT_TYPE {
+            return Err(decoding::Error::WrongType {
+                expected_one_of: vec![RECEIVED_AT_TYPE],
+                found: record_type,
+            });
+        }
+        let count = decoder.read::<usize>()?;
+        let mut times = Vec::with_capacity(count.min(bytes.len() / HASH_SIZE));
+        for _ in 0..count {
+            let hash = amp::ChangeHash::try_from(decoder.read_bytes(HASH_SIZE)?)
+                .map_err(decoding::Error::BadChangeFormat)?;
+            times.push((hash, decoder.read::<i64>()?));
+        }
+        for (hash, time) in times {
+            self.received_at.times.entry(hash).or_insert(time);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,128 @@
+use std::{convert::TryInto, io};
+
+use crate::persister::Persister;
+
+const SNAPSHOT_KEY: &[u8] = b"snapshot";
+/// Changes are keyed by this prefix and their position in the log as a big endian `u64`, so they
+/// iterate in the order they were appended
+const CHANGE_PREFIX: &[u8] = b"change/";
+/// Sync states are keyed by this prefix and the peer
+const SYNC_PREFIX: &[u8] = b"sync/";
+
+/// A [`Persister`] which keeps a document in a [`sled::Tree`], so many documents can share one
+/// database by giving each its own tree.
+///
+/// Replacing the changes with a snapshot is a single atomic batch, so a crash never leaves the
+/// snapshot without the changes or the other way round. Changes are only durable once
+/// [`Persister::flush`] has flushed the tree, which [`crate::PersistentBackend`] does before
+/// acknowledging them.
+///
+/// Once more than `max_changes` changes have been appended since the last snapshot the
+/// persister asks for a snapshot.
+#[derive(Debug, Clone)]
+pub struct SledPersister {
+    tree: sled::Tree,
+    /// The position of the next change in the log
+    next_change: u64,
+    /// The number of changes since the last snapshot
+    changes: u64,
+    max_changes: u64,
+}
+
+impl SledPersister {
+    /// Use the document in `tree`, which is empty if nothing has been stored in it yet
+    pub fn open(tree: sled::Tree, max_changes: u64) -> io::Result<Self> {
+        let mut changes = 0;
+        let mut next_change = 0;
+        for entry in tree.scan_prefix(CHANGE_PREFIX).keys() {
+            next_change = change_position(&entry?)? + 1;
+            changes += 1;
+        }
+        Ok(Self {
+            tree,
+            next_change,
+            changes,
+            max_changes,
+        })
+    }
+
+    pub fn tree(&self) -> &sled::Tree {
+        &self.tree
+    }
+}
+
+fn change_key(position: u64) -> Vec<u8> {
+    let mut key = CHANGE_PREFIX.to_vec();
+    key.extend_from_slice(&position.to_be_bytes());
+    key
+}
+
+fn change_position(key: &[u8]) -> io::Result<u64> {
+    key[CHANGE_PREFIX.len()..]
+        .try_into()
+        .map(u64::from_be_bytes)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "malformed change key"))
+}
+
+fn sync_state_key(peer: &[u8]) -> Vec<u8> {
+    let mut key = SYNC_PREFIX.to_vec();
+    key.extend_from_slice(peer);
+    key
+}
+
+impl Persister for SledPersister {
+    fn append_change(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.tree.insert(change_key(self.next_change), bytes)?;
+        self.next_change += 1;
+        self.changes += 1;
+        Ok(())
+    }
+
+    fn load_changes(&self) -> io::Result<Vec<Vec<u8>>> {
+        self.tree
+            .scan_prefix(CHANGE_PREFIX)
+            .values()
+            .map(|change| Ok(change?.to_vec()))
+            .collect()
+    }
+
+    fn store_snapshot(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let mut batch = sled::Batch::default();
+        batch.insert(SNAPSHOT_KEY, bytes);
+        for key in self.tree.scan_prefix(CHANGE_PREFIX).keys() {
+            batch.remove(key?);
+        }
+        self.tree.apply_batch(batch)?;
+        self.tree.flush()?;
+        self.changes = 0;
+        Ok(())
+    }
+
+    fn load_snapshot(&self) -> io::Result<Option<Vec<u8>>> {
+        Ok(self
+            .tree
+            .get(SNAPSHOT_KEY)?
+            .map(|snapshot| snapshot.to_vec()))
+    }
+
+    fn store_sync_state(&mut self, peer: &[u8], bytes: &[u8]) -> io::Result<()> {
+        self.tree.insert(sync_state_key(peer), bytes)?;
+        Ok(())
+    }
+
+    fn load_sync_state(&self, peer: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        Ok(self
+            .tree
+            .get(sync_state_key(peer))?
+            .map(|state| state.to_vec()))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.tree.flush()?;
+        Ok(())
+    }
+
+    fn wants_snapshot(&self) -> bool {
+        self.changes > self.max_changes
+    }
+}
@@ -0,0 +1,92 @@
+use std::{ops::Deref, sync::Arc};
+
+use crate::Backend;
+
+/// An immutable capture of the in-memory state of a [`Backend`].
+///
+/// Taking a snapshot doesn't copy the document: the op set, clocks, history, queued and
+/// quarantined changes and checkpoints are shared with the backend until the backend next changes
+/// them, and only then copied. Snapshots can be
+/// cloned freely and sent to other threads. The snapshot derefs to a [`Backend`] so all of the
+/// read-only methods (`get_heads`, `get_patch`, `get_changes` and so on) are available on it
+/// directly, which makes it suitable for handing out short-lived read replicas of a document
+/// without re-decoding it from bytes.
+///
+/// Event handlers are not part of a snapshot.
+#[derive(Debug, Clone)]
+pub struct OwnedSnapshot(Arc<Backend>);
+
+impl Deref for OwnedSnapshot {
+    type Target = Backend;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl From<OwnedSnapshot> for Backend {
+    /// Turn the snapshot back into a writable backend.
+    ///
+    /// The state is still shared with other handles to the snapshot until either changes it.
+    fn from(snapshot: OwnedSnapshot) -> Self {
+        Arc::try_unwrap(snapshot.0).unwrap_or_else(|shared| (*shared).clone())
+    }
+}
+
+impl Backend {
+    /// Capture the current state of this backend. This takes the same time however large the
+    /// document is: only the settings (quotas, timestamp policy and features) are copied.
+    pub fn snapshot(&self) -> OwnedSnapshot {
+        OwnedSnapshot(Arc::new(self.clone()))
+    }
+
+    /// Reset this backend to the state captured in `snapshot`.
+    ///
+    /// Any event handlers registered on this backend are kept.
+    pub fn restore(&mut self, snapshot: OwnedSnapshot) {
+        let event_handlers = std::mem::take(&mut self.event_handlers);
+        *self = Backend::from(snapshot);
+        self.event_handlers = event_handlers;
+    }
+
+    /// Run `f` against a throwaway copy of this backend and return whatever it produces.
+    ///
+    /// This is useful for previewing the effect of applying some changes (e.g. a merge or a
+    /// migration) without committing to them: `f` can apply changes and return the resulting
+    /// patch, and the copy is dropped afterwards. Event handlers registered on this backend are
+    /// not fired.
+    ///
+    /// The copy shares this backend's state like a [snapshot](Backend::snapshot), so `f` can
+    /// read it for free. The first change `f` applies copies the op set, history and indices
+    /// though, which costs as much as cloning the backend.
+    pub fn speculate<F, T>(&self, f: F) -> T
+    where
+        F: FnOnce(&mut Backend) -> T,
+    {
+        let mut scratch = self.clone();
+        f(&mut scratch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::Shared;
+
+    #[test]
+    fn test_snapshot_shares_state() {
+        let mut backend = Backend::new();
+        let snapshot = backend.snapshot();
+        assert!(Shared::ptr_eq(&snapshot.op_set, &backend.op_set));
+        assert!(Shared::ptr_eq(&snapshot.history, &backend.history));
+        assert!(Shared::ptr_eq(&snapshot.queue, &backend.queue));
+        assert!(Shared::ptr_eq(&snapshot.quarantine, &backend.quarantine));
+        assert!(Shared::ptr_eq(&snapshot.unverified, &backend.unverified));
+        assert!(Shared::ptr_eq(&snapshot.checkpoints, &backend.checkpoints));
+
+        // changing the backend leaves the snapshot alone
+        backend.op_set.max_op = 7;
+        assert!(!Shared::ptr_eq(&snapshot.op_set, &backend.op_set));
+        assert_eq!(snapshot.op_set.max_op, 0);
+    }
+}
@@ -0,0 +1,166 @@
+use std::io;
+
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::persister::Persister;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS automerge_changes (
+        doc TEXT NOT NULL,
+        position INTEGER NOT NULL,
+        bytes BLOB NOT NULL,
+        PRIMARY KEY (doc, position)
+    );
+    CREATE TABLE IF NOT EXISTS automerge_snapshots (
+        doc TEXT PRIMARY KEY,
+        bytes BLOB NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS automerge_sync_states (
+        doc TEXT NOT NULL,
+        peer BLOB NOT NULL,
+        bytes BLOB NOT NULL,
+        PRIMARY KEY (doc, peer)
+    );
+";
+
+/// A [`Persister`] which keeps documents in an SQLite database, with every row keyed by the ID
+/// of its document so one database can hold many documents.
+///
+/// The tables are created if they don't exist. Each statement is its own transaction, and
+/// replacing the changes with a snapshot is a single transaction, so nothing is acknowledged
+/// before it is durable.
+///
+/// Once more than `max_changes` changes have been appended since the last snapshot the
+/// persister asks for a snapshot.
+#[derive(Debug)]
+pub struct SqlitePersister {
+    connection: Connection,
+    doc: String,
+    /// The number of changes since the last snapshot
+    changes: u64,
+    max_changes: u64,
+}
+
+fn to_io(error: rusqlite::Error) -> io::Error {
+    io::Error::other(error)
+}
+
+impl SqlitePersister {
+    /// Use the document with ID `doc` in the database behind `connection`, which is empty if
+    /// nothing has been stored for it yet
+    pub fn open<D: Into<String>>(
+        connection: Connection,
+        doc: D,
+        max_changes: u64,
+    ) -> io::Result<Self> {
+        connection.execute_batch(SCHEMA).map_err(to_io)?;
+        let doc = doc.into();
+        let changes: i64 = connection
+            .query_row(
+                "SELECT COUNT(*) FROM automerge_changes WHERE doc = ?1",
+                params![doc],
+                |row| row.get(0),
+            )
+            .map_err(to_io)?;
+        Ok(Self {
+            connection,
+            doc,
+            changes: changes as u64,
+            max_changes,
+        })
+    }
+
+    pub fn doc(&self) -> &str {
+        &self.doc
+    }
+
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+
+    pub fn into_connection(self) -> Connection {
+        self.connection
+    }
+}
+
+impl Persister for SqlitePersister {
+    fn append_change(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.connection
+            .execute(
+                "INSERT INTO automerge_changes (doc, position, bytes)
+                 SELECT ?1, IFNULL(MAX(position), 0) + 1, ?2
+                 FROM automerge_changes WHERE doc = ?1",
+                params![self.doc, bytes],
+            )
+            .map_err(to_io)?;
+        self.changes += 1;
+        Ok(())
+    }
+
+    fn load_changes(&self) -> io::Result<Vec<Vec<u8>>> {
+        let mut statement = self
+            .connection
+            .prepare_cached("SELECT bytes FROM automerge_changes WHERE doc = ?1 ORDER BY position")
+            .map_err(to_io)?;
+        let changes = statement
+            .query_map(params![self.doc], |row| row.get(0))
+            .map_err(to_io)?;
+        changes.collect::<Result<_, _>>().map_err(to_io)
+    }
+
+    fn store_snapshot(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let transaction = self.connection.transaction().map_err(to_io)?;
+        transaction
+            .execute(
+                "INSERT OR REPLACE INTO automerge_snapshots (doc, bytes) VALUES (?1, ?2)",
+                params![self.doc, bytes],
+            )
+            .map_err(to_io)?;
+        transaction
+            .execute(
+                "DELETE FROM automerge_changes WHERE doc = ?1",
+                params![self.doc],
+            )
+            .map_err(to_io)?;
+        transaction.commit().map_err(to_io)?;
+        self.changes = 0;
+        Ok(())
+    }
+
+    fn load_snapshot(&self) -> io::Result<Option<Vec<u8>>> {
+        self.connection
+            .query_row(
+                "SELECT bytes FROM automerge_snapshots WHERE doc = ?1",
+                params![self.doc],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(to_io)
+    }
+
+    fn store_sync_state(&mut self, peer: &[u8], bytes: &[u8]) -> io::Result<()> {
+        self.connection
+            .execute(
+                "INSERT OR REPLACE INTO automerge_sync_states (doc, peer, bytes)
+                 VALUES (?1, ?2, ?3)",
+                params![self.doc, peer, bytes],
+            )
+            .map_err(to_io)?;
+        Ok(())
+    }
+
+    fn load_sync_state(&self, peer: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        self.connection
+            .query_row(
+                "SELECT bytes FROM automerge_sync_states WHERE doc = ?1 AND peer = ?2",
+                params![self.doc, peer],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(to_io)
+    }
+
+    fn wants_snapshot(&self) -> bool {
+        self.changes > self.max_changes
+    }
+}
@@ -10,16 +10,24 @@ use automerge_protocol::{ChangeHash, Patch};
 
 use crate::{
     decoding, decoding::Decoder, encoding, encoding::Encodable, AutomergeError, Backend, Change,
+    CompressionDictionary,
 };
 
 mod bloom;
+mod manager;
 mod state;
+#[cfg(feature = "async")]
+mod transport;
 
 pub use bloom::BloomFilter;
-pub use state::{SyncHave, SyncState};
+pub use manager::SyncManager;
+pub use state::{SyncHave, SyncState, MAX_RECEIVED_EPHEMERAL};
+#[cfg(feature = "async")]
+pub use transport::{FramedTransport, SyncDriver, SyncDriverError, SyncTransport, TokioTransport};
 
 const HASH_SIZE: usize = 32; // 256 bits = 32 bytes
 const MESSAGE_TYPE_SYNC: u8 = 0x42; // first byte of a sync message, for identification
+const MESSAGE_TYPE_EPHEMERAL: u8 = 0x45; // first byte of an ephemeral message
 
 impl Backend {
     pub fn generate_sync_message(&self, sync_state: &mut SyncState) -> Option<SyncMessage> {
@@ -50,6 +58,7 @@ impl Backend {
                         need: Vec::new(),
                         have: vec![SyncHave::default()],
                         changes: Vec::new(),
+                        ephemeral: None,
                     };
                     return Some(reset_msg);
                 }
@@ -83,6 +92,14 @@ impl Backend {
 
         // deduplicate the changes to send with those we have already sent
         changes_to_send.retain(|change| !sync_state.sent_hashes.contains(&change.hash));
+        // never pass on changes which turn out to be corrupt
+        changes_to_send.retain(|change| match self.verify_change(&change.hash) {
+            Ok(()) => true,
+            Err(e) => {
+                tracing::warn!(error = %e, "not sending corrupt change");
+                false
+            }
+        });
 
         sync_state.last_sent_heads = Some(our_heads.clone());
         sync_state
@@ -94,16 +111,24 @@ impl Backend {
             have: our_have,
             need: our_need,
             changes: changes_to_send.into_iter().cloned().collect(),
+            ephemeral: None,
         };
 
         Some(sync_message)
     }
 
+    /// Apply a message from the other peer. If it is an ephemeral message its payload is added
+    /// to [`SyncState::take_ephemeral`] and nothing else changes.
     pub fn receive_sync_message(
         &mut self,
         sync_state: &mut SyncState,
         message: SyncMessage,
     ) -> Result<Option<Patch>, AutomergeError> {
+        if let Some(payload) = message.ephemeral {
+            sync_state.receive_ephemeral(payload);
+            return Ok(None);
+        }
+
         let mut patch = None;
 
         let before_heads = self.get_heads();
@@ -113,6 +138,7 @@ impl Backend {
             changes: message_changes,
             need: message_need,
             have: message_have,
+            ephemeral: _,
         } = message;
 
         let changes_is_empty = message_changes.is_empty();
@@ -240,16 +266,54 @@ impl Backend {
     }
 }
 
+/// A message exchanged with a peer while syncing. Build one with [`SyncMessage::new`] or
+/// [`SyncMessage::ephemeral`].
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub struct SyncMessage {
     pub heads: Vec<ChangeHash>,
     pub need: Vec<ChangeHash>,
     pub have: Vec<SyncHave>,
     pub changes: Vec<Change>,
+    /// The payload of an ephemeral message, see [`SyncState::encode_ephemeral`]. An ephemeral
+    /// message has no heads, needs, haves or changes.
+    pub ephemeral: Option<Vec<u8>>,
 }
 
 impl SyncMessage {
+    pub fn new(
+        heads: Vec<ChangeHash>,
+        need: Vec<ChangeHash>,
+        have: Vec<SyncHave>,
+        changes: Vec<Change>,
+    ) -> SyncMessage {
+        SyncMessage {
+            heads,
+            need,
+            have,
+            changes,
+            ephemeral: None,
+        }
+    }
+
+    /// An ephemeral message carrying `payload`
+    pub fn ephemeral(payload: Vec<u8>) -> SyncMessage {
+        SyncMessage {
+            heads: Vec::new(),
+            need: Vec::new(),
+            have: Vec::new(),
+            changes: Vec::new(),
+            ephemeral: Some(payload),
+        }
+    }
+
     pub fn encode(self) -> Result<Vec<u8>, encoding::Error> {
+        if let Some(payload) = self.ephemeral {
+            let mut buf = vec![MESSAGE_TYPE_EPHEMERAL];
+            payload.as_slice().encode(&mut buf)?;
+            return Ok(buf);
+        }
+
         let mut buf = vec![MESSAGE_TYPE_SYNC];
 
         encode_hashes(&mut buf, &self.heads)?;
@@ -273,9 +337,12 @@ impl SyncMessage {
         let mut decoder = Decoder::new(Cow::Borrowed(bytes));
 
         let message_type = decoder.read::<u8>()?;
+        if message_type == MESSAGE_TYPE_EPHEMERAL {
+            return Ok(SyncMessage::ephemeral(decoder.read()?));
+        }
         if message_type != MESSAGE_TYPE_SYNC {
             return Err(decoding::Error::WrongType {
-                expected_one_of: vec![MESSAGE_TYPE_SYNC],
+                expected_one_of: vec![MESSAGE_TYPE_SYNC, MESSAGE_TYPE_EPHEMERAL],
                 found: message_type,
             });
         }
@@ -303,8 +370,24 @@ impl SyncMessage {
             need,
             have,
             changes,
+            ephemeral: None,
         })
     }
+
+    /// Like `encode` but compressed with a dictionary shared with the other peer
+    pub fn encode_with_dictionary(
+        self,
+        dictionary: &CompressionDictionary,
+    ) -> Result<Vec<u8>, encoding::Error> {
+        Ok(dictionary.compress(&self.encode()?))
+    }
+
+    pub fn decode_with_dictionary(
+        bytes: &[u8],
+        dictionary: &CompressionDictionary,
+    ) -> Result<SyncMessage, decoding::Error> {
+        Self::decode(&dictionary.decompress(bytes)?)
+    }
 }
 
 fn encode_hashes(buf: &mut Vec<u8>, hashes: &[ChangeHash]) -> Result<(), encoding::Error> {
@@ -0,0 +1,122 @@
+use std::{collections::HashMap, hash::Hash};
+
+use automerge_protocol::{ChangeHash, Patch};
+
+use super::{SyncMessage, SyncState};
+use crate::{AutomergeError, Backend};
+
+/// The sync states for every peer one backend is syncing with.
+///
+/// Messages from peers go through [`SyncManager::receive`], which applies them with the right
+/// peer's state. Messages to peers are only generated by [`SyncManager::flush`], and only for
+/// peers which might have something to hear: a peer which sent a message since the last flush,
+/// or every peer if the backend's heads have changed (whether from a peer or from a local
+/// change). Calling `flush` once per tick of the application's event loop rather than after
+/// every change means a burst of changes costs one round of messages.
+///
+/// Like [`Backend`] itself, the manager doesn't do any IO, `P` is whatever the application uses
+/// to tell its connections apart.
+#[derive(Debug, Clone)]
+pub struct SyncManager<P> {
+    peers: HashMap<P, Peer>,
+    /// The heads at the last flush, to tell whether every peer needs a message
+    flushed_heads: Vec<ChangeHash>,
+    /// The heads at the last call to `heads_changed`
+    notified_heads: Vec<ChangeHash>,
+}
+
+#[derive(Debug, Clone)]
+struct Peer {
+    state: SyncState,
+    /// Whether a message should be generated for this peer at the next flush
+    dirty: bool,
+}
+
+impl<P> Default for SyncManager<P> {
+    fn default() -> Self {
+        Self {
+            peers: HashMap::new(),
+            flushed_heads: Vec::new(),
+            notified_heads: Vec::new(),
+        }
+    }
+}
+
+impl<P: Eq + Hash + Clone> SyncManager<P> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start syncing with `peer`, from a state saved with [`SyncState::encode`] or from
+    /// `SyncState::default()`. The peer gets a message at the next flush. If the peer was
+    /// already known its state is replaced.
+    pub fn add_peer(&mut self, peer: P, state: SyncState) {
+        self.peers.insert(peer, Peer { state, dirty: true });
+    }
+
+    /// Stop syncing with `peer`, returning its state so that it can be saved for next time
+    pub fn remove_peer(&mut self, peer: &P) -> Option<SyncState> {
+        self.peers.remove(peer).map(|peer| peer.state)
+    }
+
+    pub fn peers(&self) -> impl Iterator<Item = &P> {
+        self.peers.keys()
+    }
+
+    pub fn state(&self, peer: &P) -> Option<&SyncState> {
+        self.peers.get(peer).map(|peer| &peer.state)
+    }
+
+    /// The state for `peer`, e.g. to [`SyncState::take_ephemeral`] messages
+    pub fn state_mut(&mut self, peer: &P) -> Option<&mut SyncState> {
+        self.peers.get_mut(peer).map(|peer| &mut peer.state)
+    }
+
+    /// Apply a message from `peer`, returning the patch if it had new changes. A message from a
+    /// peer which hasn't been added yet adds it with a fresh state.
+    pub fn receive(
+        &mut self,
+        backend: &mut Backend,
+        peer: &P,
+        message: SyncMessage,
+    ) -> Result<Option<Patch>, AutomergeError> {
+        let sender = self.peers.entry(peer.clone()).or_insert_with(|| Peer {
+            state: SyncState::default(),
+            dirty: true,
+        });
+        let patch = backend.receive_sync_message(&mut sender.state, message)?;
+        sender.dirty = true;
+        Ok(patch)
+    }
+
+    /// Generate the messages which need sending, as `(peer, message)` pairs
+    pub fn flush(&mut self, backend: &Backend) -> Vec<(P, SyncMessage)> {
+        let heads = backend.get_heads();
+        let heads_moved = heads != self.flushed_heads;
+        self.flushed_heads = heads;
+
+        let mut messages = Vec::new();
+        for (id, peer) in &mut self.peers {
+            if peer.dirty || heads_moved {
+                peer.dirty = false;
+                if let Some(message) = backend.generate_sync_message(&mut peer.state) {
+                    messages.push((id.clone(), message));
+                }
+            }
+        }
+        messages
+    }
+
+    /// The backend's heads if they have changed since the last call, for applications which
+    /// need to react to new changes from any source (e.g. to save the document) rather than to
+    /// each patch
+    pub fn heads_changed(&mut self, backend: &Backend) -> Option<Vec<ChangeHash>> {
+        let heads = backend.get_heads();
+        if heads == self.notified_heads {
+            None
+        } else {
+            self.notified_heads.clone_from(&heads);
+            Some(heads)
+        }
+    }
+}
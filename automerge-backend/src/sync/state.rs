@@ -1,11 +1,17 @@
-use std::{borrow::Cow, collections::HashSet};
+use std::{
+    borrow::Cow,
+    collections::{HashSet, VecDeque},
+};
 
 use automerge_protocol::ChangeHash;
 
 use super::{decode_hashes, encode_hashes};
-use crate::{decoding, decoding::Decoder, encoding, BloomFilter};
+use crate::{decoding, decoding::Decoder, encoding, BloomFilter, SyncMessage};
 
 const SYNC_STATE_TYPE: u8 = 0x43; // first byte of an encoded sync state, for identification
+/// The most ephemeral payloads kept for [`SyncState::take_ephemeral`], the oldest are dropped
+/// beyond this
+pub const MAX_RECEIVED_EPHEMERAL: usize = 64;
 
 #[derive(Debug, Clone)]
 pub struct SyncState {
@@ -15,6 +21,8 @@ pub struct SyncState {
     pub their_need: Option<Vec<ChangeHash>>,
     pub their_have: Option<Vec<SyncHave>>,
     pub sent_hashes: HashSet<ChangeHash>,
+    /// Payloads of ephemeral messages received since the last [`SyncState::take_ephemeral`]
+    pub(crate) received_ephemeral: VecDeque<Vec<u8>>,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -24,12 +32,45 @@ pub struct SyncHave {
 }
 
 impl SyncState {
+    /// Encode `payload` as an ephemeral message, e.g. the position of a user's cursor or whether
+    /// they are online.
+    ///
+    /// Ephemeral messages go over the same channel as sync messages and are decoded by
+    /// [`SyncMessage::decode`] in the same way, but they aren't part of the document: the
+    /// receiving backend doesn't store them and the payload is only available from the
+    /// receiver's sync state, see [`SyncState::take_ephemeral`]. They aren't retried either,
+    /// so a peer which is offline when one is sent never sees it, and a receiver which doesn't
+    /// take them keeps only the latest [`MAX_RECEIVED_EPHEMERAL`].
+    pub fn encode_ephemeral(payload: &[u8]) -> Result<Vec<u8>, encoding::Error> {
+        SyncMessage::ephemeral(payload.to_vec()).encode()
+    }
+
+    /// The payloads of the ephemeral messages received with this state since this was last
+    /// called, oldest first
+    pub fn take_ephemeral(&mut self) -> Vec<Vec<u8>> {
+        std::mem::take(&mut self.received_ephemeral).into()
+    }
+
+    pub(crate) fn receive_ephemeral(&mut self, payload: Vec<u8>) {
+        if self.received_ephemeral.len() == MAX_RECEIVED_EPHEMERAL {
+            self.received_ephemeral.pop_front();
+        }
+        self.received_ephemeral.push_back(payload);
+    }
+
+    /// Encode the state for storing alongside the document, so that the next sync with this
+    /// peer can pick up where this one left off rather than starting from an empty history.
+    ///
+    /// Only the heads both sides are known to share are kept. Everything else describes a
+    /// conversation in progress (what was last sent, what the peer says it has and needs) and
+    /// would be wrong on a new connection, so a decoded state starts that conversation again.
     pub fn encode(&self) -> Result<Vec<u8>, encoding::Error> {
         let mut buf = vec![SYNC_STATE_TYPE];
         encode_hashes(&mut buf, &self.shared_heads)?;
         Ok(buf)
     }
 
+    /// Decode a state stored with [`SyncState::encode`]
     pub fn decode(bytes: &[u8]) -> Result<Self, decoding::Error> {
         let mut decoder = Decoder::new(Cow::Borrowed(bytes));
 
@@ -49,6 +90,7 @@ impl SyncState {
             their_need: None,
             their_have: Some(Vec::new()),
             sent_hashes: HashSet::new(),
+            received_ephemeral: VecDeque::new(),
         })
     }
 }
@@ -62,6 +104,7 @@ impl Default for SyncState {
             their_need: None,
             their_have: None,
             sent_hashes: HashSet::new(),
+            received_ephemeral: VecDeque::new(),
         }
     }
 }
@@ -0,0 +1,293 @@
+use std::{convert::TryFrom, io};
+
+use async_trait::async_trait;
+use automerge_protocol::Patch;
+use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use thiserror::Error;
+
+use super::{SyncMessage, SyncState};
+use crate::{AutomergeError, Backend};
+
+/// Frames larger than this are rejected by [`FramedTransport`] rather than allocated
+const MAX_FRAME_LEN: u32 = 256 * 1024 * 1024;
+
+/// A connection to one peer which sync messages are sent over, see [`SyncDriver`]
+#[async_trait]
+pub trait SyncTransport {
+    type Error: std::error::Error + Send + Sync + 'static;
+
+    /// Send one encoded message to the peer
+    async fn send(&mut self, message: Vec<u8>) -> Result<(), Self::Error>;
+
+    /// Wait for the next message from the peer, or `None` if the connection has closed
+    async fn recv(&mut self) -> Result<Option<Vec<u8>>, Self::Error>;
+}
+
+#[derive(Error, Debug)]
+pub enum SyncDriverError<E> {
+    #[error("transport error: {0}")]
+    Transport(#[source] E),
+    #[error(transparent)]
+    Automerge(#[from] AutomergeError),
+}
+
+/// Runs the sync protocol with one peer over a [`SyncTransport`].
+///
+/// The driver owns the transport and the [`SyncState`] for the peer but not the backend, which is
+/// passed to each call so the application can keep using it (and run drivers for other peers)
+/// in between.
+///
+/// ```ignore
+/// let mut driver = SyncDriver::new(FramedTransport::new(stream), SyncState::default());
+/// driver.run(&mut backend, |patch| frontend.apply_patch(patch).unwrap()).await?;
+/// ```
+#[derive(Debug)]
+pub struct SyncDriver<T> {
+    transport: T,
+    state: SyncState,
+}
+
+impl<T: SyncTransport + Send> SyncDriver<T> {
+    pub fn new(transport: T, state: SyncState) -> Self {
+        Self { transport, state }
+    }
+
+    /// The sync state for the peer, e.g. to persist with [`SyncState::encode`] or to
+    /// [`SyncState::take_ephemeral`] messages
+    pub fn state_mut(&mut self) -> &mut SyncState {
+        &mut self.state
+    }
+
+    pub fn into_parts(self) -> (T, SyncState) {
+        (self.transport, self.state)
+    }
+
+    /// Send the peer whatever it is missing, if anything. Returns whether a message was sent.
+    ///
+    /// Call this after making local changes so that the peer hears about them. This takes the
+    /// backend mutably only so that the returned future is `Send`, `Backend` isn't `Sync`.
+    pub async fn send(&mut self, backend: &mut Backend) -> Result<bool, SyncDriverError<T::Error>> {
+        if let Some(message) = backend.generate_sync_message(&mut self.state) {
+            self.transport
+                .send(message.encode().map_err(AutomergeError::from)?)
+                .await
+                .map_err(SyncDriverError::Transport)?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Send an ephemeral message to the peer, see [`SyncState::encode_ephemeral`]
+    pub async fn send_ephemeral(
+        &mut self,
+        payload: &[u8],
+    ) -> Result<(), SyncDriverError<T::Error>> {
+        let message = SyncState::encode_ephemeral(payload).map_err(AutomergeError::from)?;
+        self.transport
+            .send(message)
+            .await
+            .map_err(SyncDriverError::Transport)
+    }
+
+    /// Wait for a message from the peer, apply it to `backend` and send the reply if there is
+    /// one.
+    ///
+    /// Returns `None` if the connection has closed, otherwise the patch from applying the
+    /// message (which is `None` if the message had no new changes).
+    pub async fn recv(
+        &mut self,
+        backend: &mut Backend,
+    ) -> Result<Option<Option<Patch>>, SyncDriverError<T::Error>> {
+        let received = self
+            .transport
+            .recv()
+            .await
+            .map_err(SyncDriverError::Transport)?;
+        if let Some(bytes) = received {
+            let message = SyncMessage::decode(&bytes).map_err(AutomergeError::from)?;
+            let patch = backend.receive_sync_message(&mut self.state, message)?;
+            self.send(backend).await?;
+            Ok(Some(patch))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Sync with the peer until the connection closes, passing every patch to `on_patch`
+    pub async fn run<F>(
+        &mut self,
+        backend: &mut Backend,
+        mut on_patch: F,
+    ) -> Result<(), SyncDriverError<T::Error>>
+    where
+        F: FnMut(Patch) + Send,
+    {
+        self.send(backend).await?;
+        while let Some(patch) = self.recv(backend).await? {
+            if let Some(patch) = patch {
+                on_patch(patch);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The length prefix of a frame holding `message`
+fn frame_len(message: &[u8]) -> io::Result<[u8; 4]> {
+    u32::try_from(message.len())
+        .ok()
+        .filter(|len| *len <= MAX_FRAME_LEN)
+        .map(u32::to_be_bytes)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "sync message too large"))
+}
+
+/// Check the length prefix of a frame which is being received
+fn check_frame_len(len: [u8; 4]) -> io::Result<u64> {
+    let len = u32::from_be_bytes(len);
+    if len > MAX_FRAME_LEN {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "sync message too large",
+        ))
+    } else {
+        Ok(u64::from(len))
+    }
+}
+
+/// Check that the stream didn't end part way through a frame of `len` bytes
+fn check_frame(message: Vec<u8>, len: u64) -> io::Result<Vec<u8>> {
+    if message.len() as u64 == len {
+        Ok(message)
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "connection closed part way through a sync message",
+        ))
+    }
+}
+
+/// A [`SyncTransport`] over a byte stream which implements the `futures` IO traits.
+///
+/// Each message is prefixed with its length as a big endian `u32`. See [`TokioTransport`] for
+/// streams which implement the tokio IO traits instead, like a tokio `TcpStream`.
+///
+/// The buffer for a message grows as the message arrives, so a peer can't make us allocate a
+/// large buffer just by sending a large length.
+#[derive(Debug)]
+pub struct FramedTransport<S> {
+    stream: S,
+}
+
+impl<S> FramedTransport<S> {
+    pub fn new(stream: S) -> Self {
+        Self { stream }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+#[async_trait]
+impl<S> SyncTransport for FramedTransport<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    type Error = io::Error;
+
+    async fn send(&mut self, message: Vec<u8>) -> Result<(), Self::Error> {
+        self.stream.write_all(&frame_len(&message)?).await?;
+        self.stream.write_all(&message).await?;
+        self.stream.flush().await
+    }
+
+    async fn recv(&mut self) -> Result<Option<Vec<u8>>, Self::Error> {
+        let mut len = [0; 4];
+        match self.stream.read_exact(&mut len).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let len = check_frame_len(len)?;
+        let mut message = Vec::new();
+        (&mut self.stream)
+            .take(len)
+            .read_to_end(&mut message)
+            .await?;
+        check_frame(message, len).map(Some)
+    }
+}
+
+/// A [`SyncTransport`] over a byte stream which implements the tokio IO traits.
+///
+/// Messages are framed in the same way as [`FramedTransport`], so the two can talk to each other.
+///
+/// Syncing with a peer over TCP:
+///
+/// ```ignore
+/// use automerge_backend::{SyncDriver, SyncState, TokioTransport};
+///
+/// // one side listens
+/// let listener = tokio::net::TcpListener::bind("127.0.0.1:9000").await?;
+/// let (stream, _) = listener.accept().await?;
+/// let mut driver = SyncDriver::new(TokioTransport::new(stream), SyncState::default());
+/// driver.run(&mut backend, |patch| frontend.apply_patch(patch).unwrap()).await?;
+///
+/// // and the other connects
+/// let stream = tokio::net::TcpStream::connect("127.0.0.1:9000").await?;
+/// let mut driver = SyncDriver::new(TokioTransport::new(stream), SyncState::default());
+/// driver.run(&mut backend, |patch| frontend.apply_patch(patch).unwrap()).await?;
+/// ```
+///
+/// To make local changes while the connection is open, call [`SyncDriver::send`] after each
+/// change and [`SyncDriver::recv`] in a loop rather than [`SyncDriver::run`], e.g. in the arms
+/// of a `tokio::select!`.
+#[derive(Debug)]
+pub struct TokioTransport<S> {
+    stream: S,
+}
+
+impl<S> TokioTransport<S> {
+    pub fn new(stream: S) -> Self {
+        Self { stream }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+#[async_trait]
+impl<S> SyncTransport for TokioTransport<S>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send,
+{
+    type Error = io::Error;
+
+    async fn send(&mut self, message: Vec<u8>) -> Result<(), Self::Error> {
+        use tokio::io::AsyncWriteExt;
+
+        self.stream.write_all(&frame_len(&message)?).await?;
+        self.stream.write_all(&message).await?;
+        self.stream.flush().await
+    }
+
+    async fn recv(&mut self) -> Result<Option<Vec<u8>>, Self::Error> {
+        use tokio::io::AsyncReadExt;
+
+        let mut len = [0; 4];
+        match self.stream.read_exact(&mut len).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let len = check_frame_len(len)?;
+        let mut message = Vec::new();
+        (&mut self.stream)
+            .take(len)
+            .read_to_end(&mut message)
+            .await?;
+        check_frame(message, len).map(Some)
+    }
+}
@@ -0,0 +1,74 @@
+use automerge_protocol as amp;
+use thiserror::Error;
+
+use crate::{Backend, Change};
+
+/// What a [`Backend`] does with a change whose timestamp is earlier than that of the previous
+/// change from the same actor, i.e. one made on a machine whose clock went backwards.
+///
+/// `tolerance` is in milliseconds, like the timestamps the frontend puts in changes. A change
+/// which is behind by no more than the tolerance is accepted as it is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TimestampPolicy {
+    /// Accept every change as it is. This is the default.
+    #[default]
+    Unchecked,
+    /// Give local changes the timestamp of the actor's previous change. Remote changes are
+    /// accepted as they are, changing their timestamp would change their hash.
+    Clamp { tolerance: i64 },
+    /// Reject the change with [`TimeWentBackwards`]. Local changes fail and remote ones are
+    /// quarantined, like changes which exceed the [`Quotas`](crate::Quotas).
+    Reject { tolerance: i64 },
+}
+
+/// A change rejected by [`TimestampPolicy::Reject`]
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error(
+    "Change {hash:?} has timestamp {time}, before the previous change from its actor at {previous}"
+)]
+pub struct TimeWentBackwards {
+    pub hash: amp::ChangeHash,
+    pub time: i64,
+    pub previous: i64,
+}
+
+impl Backend {
+    pub fn timestamp_policy(&self) -> TimestampPolicy {
+        self.timestamp_policy
+    }
+
+    pub fn set_timestamp_policy(&mut self, policy: TimestampPolicy) {
+        self.timestamp_policy = policy;
+    }
+
+    /// The timestamp of the last change applied from `actor`
+    fn last_time(&self, actor: &amp::ActorId) -> Option<i64> {
+        let index = self.states.get(actor)?.last()?;
+        Some(self.history[*index].time)
+    }
+
+    pub(crate) fn clamp_timestamp(&self, change: &mut amp::Change) {
+        if let TimestampPolicy::Clamp { tolerance } = self.timestamp_policy {
+            if let Some(previous) = self.last_time(&change.actor_id) {
+                if previous.saturating_sub(change.time) > tolerance {
+                    change.time = previous;
+                }
+            }
+        }
+    }
+
+    pub(crate) fn check_timestamp(&self, change: &Change) -> Result<(), TimeWentBackwards> {
+        if let TimestampPolicy::Reject { tolerance } = self.timestamp_policy {
+            if let Some(previous) = self.last_time(change.actor_id()) {
+                if previous.saturating_sub(change.time) > tolerance {
+                    return Err(TimeWentBackwards {
+                        hash: change.hash,
+                        time: change.time,
+                        previous,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,160 @@
+use std::{collections::BTreeMap, convert::TryFrom};
+
+use automerge_protocol as amp;
+use smol_str::SmolStr;
+
+use crate::{
+    error::AutomergeError,
+    internal::{Key, ObjectId},
+    object_store::ObjState,
+    op_handle::OpHandle,
+    Backend,
+};
+
+/// A value read directly out of a backend by [`Backend::value_at`].
+///
+/// Where there are conflicting values the one a frontend would show is used, i.e. the value set
+/// by the op with the highest ID.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Map(BTreeMap<SmolStr, Value>),
+    Table(BTreeMap<SmolStr, Value>),
+    List(Vec<Value>),
+    Text(String),
+    Primitive(amp::ScalarValue),
+}
+
+/// One step of the path passed to [`Backend::value_at`]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum PathElement {
+    /// A key in a map or table
+    Key(SmolStr),
+    /// An index into a list or text object
+    Index(usize),
+}
+
+impl From<&str> for PathElement {
+    fn from(key: &str) -> Self {
+        PathElement::Key(key.into())
+    }
+}
+
+impl From<usize> for PathElement {
+    fn from(index: usize) -> Self {
+        PathElement::Index(index)
+    }
+}
+
+impl Backend {
+    /// Read the value at `path` below `object`, without having to keep a frontend in sync with
+    /// this backend. Returns `None` if the object or path does not exist.
+    ///
+    /// If `heads` is given the value is read as of those heads instead of the current state,
+    /// which fails with `AutomergeError::UnknownChanges` if any of them are not in the history.
+    pub fn value_at(
+        &self,
+        object: &amp::ObjectId,
+        path: &[PathElement],
+        heads: Option<&[amp::ChangeHash]>,
+    ) -> Result<Option<Value>, AutomergeError> {
+        if let Some(heads) = heads {
+            return self.at_heads(heads)?.value_at(object, path, None);
+        }
+        Ok(self.read(object, path))
+    }
+
+    /// Whether the counter at `path` below `object` has overflowed. Increments are summed
+    /// exactly, so all replicas agree on the value of a counter whatever order they see the
+    /// increments in, but the value is clamped to `i64::MAX` or `i64::MIN` when it is read.
+    /// Returns `None` if there is no counter at `path`.
+    pub fn counter_saturated(&self, object: &amp::ObjectId, path: &[PathElement]) -> Option<bool> {
+        let (last, parents) = path.split_last()?;
+        let mut object_id = self.actors.lookup_obj(object)?;
+        for element in parents {
+            object_id = self.visible_op(&object_id, element)?.child()?;
+        }
+        let exact = self.visible_op(&object_id, last)?.exact_counter()?;
+        Some(i64::try_from(exact).is_err())
+    }
+
+    fn read(&self, object: &amp::ObjectId, path: &[PathElement]) -> Option<Value> {
+        let mut object_id = self.actors.lookup_obj(object)?;
+        for (i, element) in path.iter().enumerate() {
+            let op = self.visible_op(&object_id, element)?;
+            match op.child() {
+                Some(child) => object_id = child,
+                // a primitive can only be the last step of the path
+                None if i == path.len() - 1 => return Some(Value::Primitive(op.adjusted_value())),
+                None => return None,
+            }
+        }
+        self.materialize(&object_id)
+    }
+
+    /// The winning op of `element` in `object_id`
+    pub(crate) fn visible_op(
+        &self,
+        object_id: &ObjectId,
+        element: &PathElement,
+    ) -> Option<&OpHandle> {
+        let object = self.op_set.objs.get(object_id)?;
+        match (element, object.is_seq()) {
+            (PathElement::Key(key), false) => self.winner(object, &Key::Map(key.clone())),
+            (PathElement::Index(index), true) => object
+                .seq
+                .into_iter()
+                .filter_map(|slot| self.winner(object, &object.element_of(*slot).into()))
+                .nth(*index),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn winner<'a>(&self, object: &'a ObjState, key: &Key) -> Option<&'a OpHandle> {
+        object
+            .conflicts(key)
+            .max_by(|a, b| self.actors.cmp(&a.id.into(), &b.id.into()))
+    }
+
+    fn materialize(&self, object_id: &ObjectId) -> Option<Value> {
+        let object = self.op_set.objs.get(object_id)?;
+        let value_of = |op: &OpHandle| match op.child() {
+            Some(child) => self.materialize(&child),
+            None => Some(Value::Primitive(op.adjusted_value())),
+        };
+        let props = || {
+            object
+                .props
+                .keys()
+                .filter_map(|key| match key {
+                    Key::Map(name) => {
+                        let value = value_of(self.winner(object, key)?)?;
+                        Some((name.clone(), value))
+                    }
+                    Key::Seq(_) => None,
+                })
+                .collect()
+        };
+        let elements = || {
+            object
+                .seq
+                .into_iter()
+                .filter_map(|slot| self.winner(object, &object.element_of(*slot).into()))
+        };
+        Some(match object.obj_type {
+            amp::ObjType::Map => Value::Map(props()),
+            amp::ObjType::Table => Value::Table(props()),
+            amp::ObjType::List => Value::List(elements().filter_map(value_of).collect()),
+            amp::ObjType::Text => Value::Text(
+                elements()
+                    .filter_map(|op| match op.adjusted_value() {
+                        amp::ScalarValue::Str(s) => Some(s),
+                        _ => None,
+                    })
+                    .fold(String::new(), |mut text, s| {
+                        text.push_str(&s);
+                        text
+                    }),
+            ),
+        })
+    }
+}
@@ -0,0 +1,86 @@
+use std::{
+    collections::HashSet,
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+
+use automerge_protocol as amp;
+#[cfg(feature = "unverified-load")]
+use bytes::Bytes;
+
+#[cfg(feature = "unverified-load")]
+use crate::change::DecodeMode;
+use crate::{error::AutomergeError, Backend};
+
+/// The changes whose checksums haven't been checked yet. They are checked through `&Backend`,
+/// so the set is behind a lock rather than a `RefCell` to keep the backend `Sync`.
+#[derive(Debug, Default)]
+pub(crate) struct Unverified(Mutex<HashSet<amp::ChangeHash>>);
+
+impl Unverified {
+    /// The set can't be left half updated, so it is still good after a panic
+    pub fn lock(&self) -> MutexGuard<'_, HashSet<amp::ChangeHash>> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn get_mut(&mut self) -> &mut HashSet<amp::ChangeHash> {
+        self.0.get_mut().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Clone for Unverified {
+    fn clone(&self) -> Self {
+        Unverified(Mutex::new(self.lock().clone()))
+    }
+}
+
+impl Backend {
+    /// Load a document without checking the checksums of its chunks, for documents read back from
+    /// storage which is already trusted, such as a server's own disk.
+    ///
+    /// Whole document chunks are not hashed at all, which roughly halves the hashing work done
+    /// while loading a large document. The contents are still checked against the heads recorded
+    /// in the document, which are hashes of the changes themselves.
+    ///
+    /// The checksums of individual changes are checked lazily: the first time a change is handed
+    /// out by hash (`get_changes_by_hashes`) or sent to a peer, and for every change when the
+    /// document is saved or [`Backend::verify_all`] is called.
+    #[cfg(feature = "unverified-load")]
+    pub fn load_unverified(data: &[u8]) -> Result<Self, AutomergeError> {
+        let backend = Self::load_with(&Bytes::copy_from_slice(data), &[], DecodeMode::Trusted)?;
+        *backend.unverified.lock() = backend.history.iter().map(|change| change.hash).collect();
+        Ok(backend)
+    }
+
+    /// Whether the history of this backend has been fully checked.
+    pub fn is_verified(&self) -> bool {
+        self.unverified.lock().is_empty()
+    }
+
+    /// Check the checksum of the change with `hash`, if that hasn't been done yet.
+    ///
+    /// Unknown hashes are ignored.
+    pub fn verify_change(&self, hash: &amp::ChangeHash) -> Result<(), AutomergeError> {
+        if !self.unverified.lock().contains(hash) {
+            return Ok(());
+        }
+        if let Some(change) = self.get_change_by_hash(hash) {
+            change
+                .verify_checksum()
+                .map_err(|source| AutomergeError::CorruptChange {
+                    hash: *hash,
+                    source,
+                })?;
+        }
+        self.unverified.lock().remove(hash);
+        Ok(())
+    }
+
+    /// Check the checksum of every change which hasn't been checked yet.
+    pub fn verify_all(&self) -> Result<(), AutomergeError> {
+        let unverified: Vec<_> = self.unverified.lock().iter().copied().collect();
+        for hash in unverified {
+            self.verify_change(&hash)?;
+        }
+        Ok(())
+    }
+}
@@ -1,15 +1,19 @@
 extern crate automerge_backend;
 use std::{convert::TryInto, num::NonZeroU32, str::FromStr};
 
-use amp::{RootDiff, SortedVec};
-use automerge_backend::{AutomergeError, Backend, Change};
+use amp::{testing, RootDiff, SortedVec};
+use automerge_backend::{AutomergeError, Backend, Change, PathElement, Value};
 use automerge_protocol as amp;
 use automerge_protocol::{
-    ActorId, CursorDiff, Diff, DiffEdit, ElementId, ListDiff, MapDiff, ObjectId, Op, Patch,
-    ScalarValue,
+    ActorId, CursorDiff, Diff, DiffEdit, ElementId, ListDiff, MapDiff, MarkData, MarkDiff,
+    MarkSpan, ObjectId, Op, OpType, Patch, ScalarValue, TextDiff,
 };
-use maplit::hashmap;
+use maplit::{btreemap, hashmap};
 use pretty_assertions::assert_eq;
+use proptest::prelude::*;
+
+mod common;
+use common::{change, op, set};
 
 #[test]
 fn test_incremental_diffs_in_a_map() {
@@ -44,7 +48,7 @@ fn test_incremental_diffs_in_a_map() {
         max_op: 1,
         pending_changes: 0,
         diffs: RootDiff {
-            props: hashmap!( "bird".into() => hashmap!( actor.op_id_at(1) => "magpie".into() )),
+            props: btreemap!( "bird".into() => btreemap!( actor.op_id_at(1) => "magpie".into() )),
         },
     };
     assert_eq!(patch, expected_patch)
@@ -83,8 +87,8 @@ fn test_bytes() {
         max_op: 1,
         pending_changes: 0,
         diffs: RootDiff {
-            props: hashmap! {
-                "bird".into() => hashmap!{
+            props: btreemap! {
+                "bird".into() => btreemap!{
                     actor.op_id_at(1) => amp::Diff::Value(amp::ScalarValue::Bytes("AQID".into())),
                 }
             },
@@ -144,8 +148,8 @@ fn test_increment_key_in_map() {
         pending_changes: 0,
         deps: vec![change2.hash],
         diffs: RootDiff {
-            props: hashmap!(
-            "counter".into() => hashmap!{
+            props: btreemap!(
+            "counter".into() => btreemap!{
                 actor.op_id_at(1) =>  ScalarValue::Counter(3).into(),
             }),
         },
@@ -211,8 +215,8 @@ fn test_conflict_on_assignment_to_same_map_key() {
         max_op: 2,
         pending_changes: 0,
         diffs: RootDiff {
-            props: hashmap! {
-                "bird".into() => hashmap!{
+            props: btreemap! {
+                "bird".into() => btreemap!{
                     actor_1.op_id_at(1) => "magpie".into(),
                     actor_2.op_id_at(2) => "blackbird".into(),
                 }
@@ -277,8 +281,8 @@ fn delete_key_from_map() {
         max_op: 2,
         pending_changes: 0,
         diffs: RootDiff {
-            props: hashmap! {
-                "bird".into() => hashmap!{}
+            props: btreemap! {
+                "bird".into() => btreemap!{}
             },
         },
     };
@@ -329,12 +333,12 @@ fn create_nested_maps() {
         seq: None,
         clock: hashmap! {actor.clone() => 1},
         diffs: RootDiff {
-            props: hashmap! {
-                "birds".into() => hashmap!{
+            props: btreemap! {
+                "birds".into() => btreemap!{
                     actor.op_id_at(1) => Diff::Map(MapDiff{
                         object_id: actor.op_id_at(1).into(),
-                        props: hashmap!{
-                            "wrens".into() => hashmap!{
+                        props: btreemap!{
+                            "wrens".into() => btreemap!{
                                 actor.op_id_at(2) => Diff::Value(ScalarValue::F64(3.0))
                             }
                         }
@@ -411,12 +415,12 @@ fn test_assign_to_nested_keys_in_map() {
         pending_changes: 0,
         deps: vec![change2.hash],
         diffs: RootDiff {
-            props: hashmap! {
-                "birds".into() => hashmap!{
+            props: btreemap! {
+                "birds".into() => btreemap!{
                     actor.op_id_at(1) => Diff::Map(MapDiff{
                         object_id: actor.op_id_at(1).into(),
-                        props: hashmap!{
-                            "sparrows".into() => hashmap!{
+                        props: btreemap!{
+                            "sparrows".into() => btreemap!{
                                 actor.op_id_at(3) => Diff::Value(ScalarValue::F64(15.0))
                             }
                         }
@@ -474,8 +478,8 @@ fn test_create_lists() {
         seq: None,
         deps: vec![change.hash],
         diffs: RootDiff {
-            props: hashmap! {
-                "birds".into() => hashmap!{
+            props: btreemap! {
+                "birds".into() => btreemap!{
                     actor.op_id_at(1) => Diff::List(ListDiff{
                         object_id: actor.op_id_at(1).into(),
                         edits: vec![DiffEdit::SingleElementInsert{
@@ -558,8 +562,8 @@ fn test_apply_updates_inside_lists() {
         pending_changes: 0,
         seq: None,
         diffs: RootDiff {
-            props: hashmap! {
-                "birds".into() => hashmap!{
+            props: btreemap! {
+                "birds".into() => btreemap!{
                     actor.op_id_at(1) => Diff::List(ListDiff{
                         object_id: actor.op_id_at(1).into(),
                         edits: vec![DiffEdit::Update{
@@ -641,8 +645,8 @@ fn test_delete_list_elements() {
         },
         deps: vec![change2.hash],
         diffs: RootDiff {
-            props: hashmap! {
-                "birds".into() => hashmap!{
+            props: btreemap! {
+                "birds".into() => btreemap!{
                     actor.op_id_at(1) => Diff::List(ListDiff{
                         object_id:  actor.op_id_at(1).into(),
                         edits: vec![DiffEdit::Remove{index: 0, count: 1}]
@@ -720,8 +724,8 @@ fn test_handle_list_element_insertion_and_deletion_in_same_change() {
         pending_changes: 0,
         deps: vec![change2.hash],
         diffs: RootDiff {
-            props: hashmap! {
-                "birds".into() => hashmap!{
+            props: btreemap! {
+                "birds".into() => btreemap!{
                     actor.op_id_at(1) => Diff::List(ListDiff{
                         object_id: actor.op_id_at(1).into(),
                         edits: vec![
@@ -820,16 +824,16 @@ fn test_handle_changes_within_conflicted_objects() {
         pending_changes: 0,
         deps: vec![change1.hash, change3.hash],
         diffs: RootDiff {
-            props: hashmap! {
-                "conflict".into() => hashmap!{
+            props: btreemap! {
+                "conflict".into() => btreemap!{
                     actor1.op_id_at(1) => Diff::List(ListDiff{
                         object_id: actor1.op_id_at(1).into(),
                         edits: Vec::new(),
                     }),
                     actor2.op_id_at(1) => Diff::Map(MapDiff{
                         object_id: actor2.op_id_at(1).into(),
-                        props: hashmap!{
-                            "sparrow".into() => hashmap!{
+                        props: btreemap!{
+                            "sparrow".into() => btreemap!{
                                 actor2.op_id_at(2) => Diff::Value(ScalarValue::F64(12.0))
                             }
                         }
@@ -985,8 +989,8 @@ fn test_handle_changes_within_conflicted_lists() {
         pending_changes: 0,
         deps: vec![change4.hash],
         diffs: RootDiff {
-            props: hashmap! {
-                "todos".into() => hashmap!{
+            props: btreemap! {
+                "todos".into() => btreemap!{
                     actor1.op_id_at(1) => Diff::List(ListDiff{
                         object_id: actor1.op_id_at(1).into(),
                         edits: vec![
@@ -995,8 +999,8 @@ fn test_handle_changes_within_conflicted_lists() {
                                 op_id: actor1.op_id_at(3),
                                 value: Diff::Map(MapDiff{
                                     object_id: actor1.op_id_at(3).into(),
-                                    props: hashmap!{
-                                        "done".into() => hashmap!{
+                                    props: btreemap!{
+                                        "done".into() => btreemap!{
                                             actor1.op_id_at(6) => Diff::Value(true.into())
                                         }
                                     }
@@ -1007,7 +1011,7 @@ fn test_handle_changes_within_conflicted_lists() {
                                 op_id: actor2.op_id_at(3),
                                 value: Diff::Map(MapDiff{
                                     object_id: actor2.op_id_at(3).into(),
-                                    props: hashmap!{},
+                                    props: btreemap!{},
                                 })
                             }
                         ]
@@ -1059,8 +1063,8 @@ fn test_support_date_objects_at_root() {
         actor: None,
         deps: vec![change.hash],
         diffs: RootDiff {
-            props: hashmap! {
-                "now".into() => hashmap!{
+            props: btreemap! {
+                "now".into() => btreemap!{
                     actor.op_id_at(1) => Diff::Value(ScalarValue::Timestamp(1_586_528_122_277))
                 }
             },
@@ -1114,8 +1118,8 @@ fn test_support_date_objects_in_a_list() {
         actor: None,
         seq: None,
         diffs: RootDiff {
-            props: hashmap! {
-                "list".into() => hashmap!{
+            props: btreemap! {
+                "list".into() => btreemap!{
                     actor.op_id_at(1) => Diff::List(ListDiff{
                         object_id: actor.op_id_at(1).into(),
                         edits: vec![DiffEdit::SingleElementInsert{
@@ -1184,8 +1188,8 @@ fn test_cursor_objects() {
         actor: None,
         seq: None,
         diffs: RootDiff {
-            props: hashmap! {
-                "list".into() => hashmap!{
+            props: btreemap! {
+                "list".into() => btreemap!{
                     actor.op_id_at(1) => Diff::List(ListDiff{
                         object_id: actor.op_id_at(1).into(),
                         edits: vec![DiffEdit::SingleElementInsert{
@@ -1196,7 +1200,7 @@ fn test_cursor_objects() {
                         }],
                     })
                 },
-                "cursor".into() => hashmap!{
+                "cursor".into() => btreemap!{
                     actor.op_id_at(3) => Diff::Cursor(CursorDiff{
                         object_id: actor.op_id_at(1).into(),
                         elem_id: actor.op_id_at(2),
@@ -1314,8 +1318,8 @@ fn test_updating_sequences_updates_referring_cursors() {
         actor: None,
         seq: None,
         diffs: RootDiff {
-            props: hashmap! {
-                "list".into() => hashmap!{
+            props: btreemap! {
+                "list".into() => btreemap!{
                     actor.op_id_at(1) => Diff::List(ListDiff{
                         object_id: actor.op_id_at(1).into(),
                         edits: vec![DiffEdit::SingleElementInsert{
@@ -1326,7 +1330,7 @@ fn test_updating_sequences_updates_referring_cursors() {
                         }],
                     })
                 },
-                "cursor".into() => hashmap!{
+                "cursor".into() => btreemap!{
                     actor.op_id_at(3) => Diff::Cursor(CursorDiff{
                         object_id: actor.op_id_at(1).into(),
                         elem_id: actor.op_id_at(2),
@@ -1414,14 +1418,14 @@ fn test_updating_sequences_updates_referring_cursors_with_deleted_items() {
         actor: None,
         seq: None,
         diffs: RootDiff {
-            props: hashmap! {
-                "list".into() => hashmap!{
+            props: btreemap! {
+                "list".into() => btreemap!{
                     actor.op_id_at(1) => Diff::List(ListDiff{
                         object_id: actor.op_id_at(1).into(),
                         edits: vec![DiffEdit::Remove{index: 0, count: 1}],
                     })
                 },
-                "cursor".into() => hashmap!{
+                "cursor".into() => btreemap!{
                     actor.op_id_at(4) => Diff::Cursor(CursorDiff{
                         object_id: actor.op_id_at(1).into(),
                         elem_id: actor.op_id_at(3),
@@ -1433,3 +1437,498 @@ fn test_updating_sequences_updates_referring_cursors_with_deleted_items() {
     };
     assert_eq!(patch, expected_patch);
 }
+
+#[test]
+fn test_counter_overflow_saturates_independent_of_order() {
+    let alice: ActorId = "7b7723afd9e6480397a4d467b7693156".try_into().unwrap();
+    let bob: ActorId = "9f17f3a4c2e54bd1a0a54c37e0f0c1d2".try_into().unwrap();
+    let make = change(&alice)
+        .op(set(
+            &ObjectId::Root,
+            "views",
+            ScalarValue::Counter(i64::MAX - 1),
+            Vec::new(),
+        ))
+        .encode();
+    let inc = |actor: &ActorId, seq: u64, by: i64| {
+        change(actor)
+            .seq(seq)
+            .start_op(2)
+            .deps(vec![make.hash])
+            .op(op(
+                OpType::Inc(by),
+                &ObjectId::Root,
+                "views".into(),
+                false,
+                vec![alice.op_id_at(1)],
+            ))
+            .encode()
+    };
+    let up = inc(&alice, 2, 5);
+    let down = inc(&bob, 1, -5);
+    let path = [PathElement::from("views")];
+    let counter = |backend: &Backend| {
+        backend
+            .value_at(&ObjectId::Root, &path, None)
+            .unwrap()
+            .unwrap()
+    };
+
+    let mut first = Backend::new();
+    first.apply_changes(vec![make.clone(), up.clone()]).unwrap();
+    assert_eq!(
+        counter(&first),
+        Value::Primitive(ScalarValue::Counter(i64::MAX))
+    );
+    assert_eq!(first.counter_saturated(&ObjectId::Root, &path), Some(true));
+    first.apply_changes(vec![down.clone()]).unwrap();
+
+    let mut second = Backend::new();
+    second.apply_changes(vec![make, down.clone()]).unwrap();
+    assert_eq!(
+        second.counter_saturated(&ObjectId::Root, &path),
+        Some(false)
+    );
+    second.apply_changes(vec![up]).unwrap();
+
+    assert_eq!(counter(&first), counter(&second));
+    assert_eq!(
+        counter(&first),
+        Value::Primitive(ScalarValue::Counter(i64::MAX - 1))
+    );
+    assert_eq!(first.counter_saturated(&ObjectId::Root, &path), Some(false));
+    assert_eq!(
+        first.counter_saturated(&ObjectId::Root, &[PathElement::from("missing")]),
+        None
+    );
+}
+
+fn insert(obj: &ObjectId, after: ElementId, action: OpType) -> Op {
+    op(action, obj, after.into(), true, Vec::new())
+}
+
+fn move_op(list: &ObjectId, element: amp::OpId, after: ElementId) -> Op {
+    insert(list, after, OpType::Move(element))
+}
+
+/// Creates the list ["a", "b", "c"] under the "list" key
+fn initial_list(actor: &ActorId) -> Change {
+    let list = ObjectId::from(actor.op_id_at(1));
+    change(actor)
+        .op(op(
+            OpType::Make(amp::ObjType::List),
+            &ObjectId::Root,
+            "list".into(),
+            false,
+            Vec::new(),
+        ))
+        .op(insert(&list, ElementId::Head, OpType::Set("a".into())))
+        .op(insert(
+            &list,
+            actor.op_id_at(2).into(),
+            OpType::Set("b".into()),
+        ))
+        .op(insert(
+            &list,
+            actor.op_id_at(3).into(),
+            OpType::Set("c".into()),
+        ))
+        .encode()
+}
+
+fn list_edits(patch: &amp::Patch, list: &amp::OpId) -> Vec<DiffEdit> {
+    match &patch.diffs.props["list"][list] {
+        Diff::List(ListDiff { edits, .. }) => edits.clone(),
+        other => panic!("expected a list diff, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_concurrent_moves_converge() {
+    let actor1: ActorId = "02ef21f3c9eb4087880ebedd7c4bbe43".try_into().unwrap();
+    let actor2: ActorId = "2a1d376b24f744008d4af58252d644dd".try_into().unwrap();
+    let list_id = actor1.op_id_at(1);
+    let list = ObjectId::from(list_id.clone());
+
+    let initial = initial_list(&actor1);
+    // actor1 moves "c" to the front
+    let to_front = change(&actor1)
+        .seq(2)
+        .start_op(5)
+        .deps(vec![initial.hash])
+        .op(move_op(&list, actor1.op_id_at(4), ElementId::Head))
+        .encode();
+    // actor2 concurrently updates "b" and moves "c" after "a"
+    let after_a = change(&actor2)
+        .start_op(5)
+        .deps(vec![initial.hash])
+        .op(op(
+            OpType::Set("B".into()),
+            &list,
+            actor1.op_id_at(3).into(),
+            false,
+            vec![actor1.op_id_at(3)],
+        ))
+        .op(move_op(
+            &list,
+            actor1.op_id_at(4),
+            actor1.op_id_at(2).into(),
+        ))
+        .encode();
+
+    let mut backend1 = Backend::new();
+    backend1
+        .apply_changes(vec![initial.clone(), to_front.clone()])
+        .unwrap();
+    let patch = backend1.apply_changes(vec![after_a.clone()]).unwrap();
+    assert_eq!(
+        list_edits(&patch, &list_id),
+        vec![
+            DiffEdit::Update {
+                index: 2,
+                op_id: actor2.op_id_at(5),
+                value: Diff::Value("B".into()),
+            },
+            DiffEdit::Remove { index: 0, count: 1 },
+            DiffEdit::SingleElementInsert {
+                index: 1,
+                elem_id: actor2.op_id_at(6).into(),
+                op_id: actor1.op_id_at(4),
+                value: Diff::Value("c".into()),
+            },
+        ]
+    );
+
+    let mut backend2 = Backend::new();
+    backend2
+        .apply_changes(vec![initial, after_a, to_front])
+        .unwrap();
+
+    // the move with the highest op ID wins, so "c" ends up after "a" for both backends
+    let expected = vec![
+        DiffEdit::SingleElementInsert {
+            index: 0,
+            elem_id: actor1.op_id_at(2).into(),
+            op_id: actor1.op_id_at(2),
+            value: Diff::Value("a".into()),
+        },
+        DiffEdit::SingleElementInsert {
+            index: 1,
+            elem_id: actor2.op_id_at(6).into(),
+            op_id: actor1.op_id_at(4),
+            value: Diff::Value("c".into()),
+        },
+        DiffEdit::SingleElementInsert {
+            index: 2,
+            elem_id: actor1.op_id_at(3).into(),
+            op_id: actor2.op_id_at(5),
+            value: Diff::Value("B".into()),
+        },
+    ];
+    assert_eq!(
+        list_edits(&backend1.get_patch().unwrap(), &list_id),
+        expected
+    );
+    assert_eq!(
+        list_edits(&backend2.get_patch().unwrap(), &list_id),
+        expected
+    );
+
+    let loaded = Backend::load(backend1.save().unwrap()).unwrap();
+    assert_eq!(list_edits(&loaded.get_patch().unwrap(), &list_id), expected);
+}
+
+#[test]
+fn test_edits_to_moved_element_use_its_new_id() {
+    let actor: ActorId = "7b7723afd9e6480397a4d467b7693156".try_into().unwrap();
+    let list_id = actor.op_id_at(1);
+    let list = ObjectId::from(list_id.clone());
+
+    let initial = initial_list(&actor);
+    let moved = change(&actor)
+        .seq(2)
+        .start_op(5)
+        .deps(vec![initial.hash])
+        .op(move_op(&list, actor.op_id_at(2), actor.op_id_at(4).into()))
+        .encode();
+    // ["b", "c", "a"], "a" is now known by the ID of the move
+    let edited = change(&actor)
+        .seq(3)
+        .start_op(6)
+        .deps(vec![moved.hash])
+        .op(op(
+            OpType::Del(NonZeroU32::new(1).unwrap()),
+            &list,
+            actor.op_id_at(5).into(),
+            false,
+            vec![actor.op_id_at(2)],
+        ))
+        .op(insert(
+            &list,
+            actor.op_id_at(5).into(),
+            OpType::Set("d".into()),
+        ))
+        .encode();
+
+    let mut backend = Backend::new();
+    backend.apply_changes(vec![initial, moved, edited]).unwrap();
+    assert_eq!(
+        list_edits(&backend.get_patch().unwrap(), &list_id),
+        vec![
+            DiffEdit::MultiElementInsert(amp::MultiElementInsert {
+                index: 0,
+                elem_id: actor.op_id_at(3).into(),
+                values: vec![ScalarValue::from("b"), ScalarValue::from("c")]
+                    .try_into()
+                    .unwrap(),
+            }),
+            DiffEdit::SingleElementInsert {
+                index: 2,
+                elem_id: actor.op_id_at(7).into(),
+                op_id: actor.op_id_at(7),
+                value: Diff::Value("d".into()),
+            },
+        ]
+    );
+
+    let loaded = Backend::load(backend.save().unwrap()).unwrap();
+    assert_eq!(loaded.get_patch().unwrap(), backend.get_patch().unwrap());
+}
+
+#[test]
+fn test_move_of_element_in_another_object_is_an_error() {
+    let actor: ActorId = "7b7723afd9e6480397a4d467b7693156".try_into().unwrap();
+    let initial = initial_list(&actor);
+    let bad_move = change(&actor)
+        .seq(2)
+        .start_op(5)
+        .deps(vec![initial.hash])
+        .op(move_op(&ObjectId::Root, actor.op_id_at(2), ElementId::Head))
+        .encode();
+
+    let mut backend = Backend::new();
+    backend.apply_changes(vec![initial]).unwrap();
+    let result = backend.apply_changes(vec![bad_move]);
+    assert!(matches!(result, Err(AutomergeError::InvalidMove { .. })));
+}
+
+/// Marks the characters after `after` up to and including `last`, the `MarkBegin` op will have
+/// the ID `begin`
+fn mark(
+    text: &ObjectId,
+    after: ElementId,
+    last: amp::OpId,
+    begin: amp::OpId,
+    name: &str,
+    value: ScalarValue,
+) -> Vec<Op> {
+    vec![
+        insert(
+            text,
+            after,
+            OpType::MarkBegin(MarkData {
+                name: name.into(),
+                value,
+            }),
+        ),
+        insert(text, last.into(), OpType::MarkEnd(begin)),
+    ]
+}
+
+/// Creates the text "abcd" under the "text" key
+fn initial_text(actor: &ActorId) -> Change {
+    let text = ObjectId::from(actor.op_id_at(1));
+    let mut initial = change(actor).op(op(
+        OpType::Make(amp::ObjType::Text),
+        &ObjectId::Root,
+        "text".into(),
+        false,
+        Vec::new(),
+    ));
+    let mut after = ElementId::Head;
+    for (i, c) in ["a", "b", "c", "d"].iter().enumerate() {
+        initial = initial.op(insert(&text, after, OpType::Set((*c).into())));
+        after = actor.op_id_at(i as u64 + 2).into();
+    }
+    initial.encode()
+}
+
+fn marks(patch: &amp::Patch, text: &amp::OpId) -> Vec<MarkSpan> {
+    match &patch.diffs.props["text"][text] {
+        Diff::Text(TextDiff { edits, .. }) => match edits.last() {
+            Some(DiffEdit::Marks(MarkDiff { marks })) => marks.clone(),
+            other => panic!("expected a marks edit, got {:?}", other),
+        },
+        other => panic!("expected a text diff, got {:?}", other),
+    }
+}
+
+fn span(start: u64, end: u64, name: &str, value: ScalarValue) -> MarkSpan {
+    MarkSpan {
+        start,
+        end,
+        name: name.into(),
+        value,
+    }
+}
+
+#[test]
+fn test_mark_covers_characters_inserted_inside_it() {
+    let actor: ActorId = "7b7723afd9e6480397a4d467b7693156".try_into().unwrap();
+    let text_id = actor.op_id_at(1);
+    let text = ObjectId::from(text_id.clone());
+
+    let initial = initial_text(&actor);
+    // bold "bc"
+    let bold = change(&actor)
+        .seq(2)
+        .start_op(6)
+        .deps(vec![initial.hash])
+        .ops(mark(
+            &text,
+            actor.op_id_at(2).into(),
+            actor.op_id_at(4),
+            actor.op_id_at(6),
+            "bold",
+            ScalarValue::Boolean(true),
+        ))
+        .encode();
+    // insert "x" after "c", which is inside the span
+    let typed = change(&actor)
+        .seq(3)
+        .start_op(8)
+        .deps(vec![bold.hash])
+        .op(insert(
+            &text,
+            actor.op_id_at(4).into(),
+            OpType::Set("x".into()),
+        ))
+        .encode();
+
+    let mut backend = Backend::new();
+    backend.apply_changes(vec![initial]).unwrap();
+    let patch = backend.apply_changes(vec![bold]).unwrap();
+    assert_eq!(
+        patch.diffs.props["text"][&text_id],
+        Diff::Text(TextDiff {
+            object_id: text.clone(),
+            edits: vec![DiffEdit::Marks(MarkDiff {
+                marks: vec![span(1, 3, "bold", ScalarValue::Boolean(true))]
+            })],
+        })
+    );
+
+    let patch = backend.apply_changes(vec![typed]).unwrap();
+    let expected = vec![span(1, 4, "bold", ScalarValue::Boolean(true))];
+    assert_eq!(marks(&patch, &text_id), expected);
+    assert_eq!(marks(&backend.get_patch().unwrap(), &text_id), expected);
+
+    let loaded = Backend::load(backend.save().unwrap()).unwrap();
+    assert_eq!(loaded.get_patch().unwrap(), backend.get_patch().unwrap());
+}
+
+#[test]
+fn test_concurrent_overlapping_marks_converge() {
+    let actor1: ActorId = "02ef21f3c9eb4087880ebedd7c4bbe43".try_into().unwrap();
+    let actor2: ActorId = "2a1d376b24f744008d4af58252d644dd".try_into().unwrap();
+    let text_id = actor1.op_id_at(1);
+    let text = ObjectId::from(text_id.clone());
+
+    let initial = initial_text(&actor1);
+    // actor1 links the whole text
+    let link = change(&actor1)
+        .seq(2)
+        .start_op(6)
+        .deps(vec![initial.hash])
+        .ops(mark(
+            &text,
+            ElementId::Head,
+            actor1.op_id_at(5),
+            actor1.op_id_at(6),
+            "link",
+            "https://example.com".into(),
+        ))
+        .encode();
+    // actor2 concurrently removes the link from "bc" and makes "c" bold
+    let unlink = change(&actor2)
+        .start_op(6)
+        .deps(vec![initial.hash])
+        .ops(mark(
+            &text,
+            actor1.op_id_at(2).into(),
+            actor1.op_id_at(4),
+            actor2.op_id_at(6),
+            "link",
+            ScalarValue::Null,
+        ))
+        .ops(mark(
+            &text,
+            actor1.op_id_at(3).into(),
+            actor1.op_id_at(4),
+            actor2.op_id_at(8),
+            "bold",
+            ScalarValue::Boolean(true),
+        ))
+        .encode();
+
+    let mut backend1 = Backend::new();
+    backend1
+        .apply_changes(vec![initial.clone(), link.clone()])
+        .unwrap();
+    backend1.apply_changes(vec![unlink.clone()]).unwrap();
+    let mut backend2 = Backend::new();
+    backend2.apply_changes(vec![initial, unlink, link]).unwrap();
+
+    // actor2's ops have the same counters as actor1's but a greater actor ID, so the unlink wins
+    let expected = vec![
+        span(0, 1, "link", "https://example.com".into()),
+        span(2, 3, "bold", ScalarValue::Boolean(true)),
+        span(3, 4, "link", "https://example.com".into()),
+    ];
+    assert_eq!(marks(&backend1.get_patch().unwrap(), &text_id), expected);
+    assert_eq!(marks(&backend2.get_patch().unwrap(), &text_id), expected);
+}
+
+#[test]
+fn test_mark_outside_text_is_an_error() {
+    let actor: ActorId = "7b7723afd9e6480397a4d467b7693156".try_into().unwrap();
+    let initial = initial_text(&actor);
+    let bad_mark = change(&actor)
+        .seq(2)
+        .start_op(6)
+        .deps(vec![initial.hash])
+        .op(op(
+            OpType::MarkBegin(MarkData {
+                name: "bold".into(),
+                value: ScalarValue::Boolean(true),
+            }),
+            &ObjectId::Root,
+            "text".into(),
+            false,
+            Vec::new(),
+        ))
+        .encode();
+
+    let mut backend = Backend::new();
+    backend.apply_changes(vec![initial]).unwrap();
+    let result = backend.apply_changes(vec![bad_mark]);
+    assert!(matches!(result, Err(AutomergeError::InvalidMark { .. })));
+}
+
+proptest! {
+    #[test]
+    fn test_valid_changes_are_accepted(change in testing::valid_change(50)) {
+        let mut backend = Backend::new();
+        backend.apply_changes(vec![Change::from(change.clone())]).unwrap();
+        backend.get_patch().unwrap();
+
+        // compared by their debug output as a NaN isn't equal to itself
+        let decoded = backend.get_changes(&[])[0].decode();
+        prop_assert_eq!(format!("{:?}", decoded.operations), format!("{:?}", change.operations));
+
+        let saved = backend.save().unwrap();
+        let loaded = Backend::load(saved.clone()).unwrap();
+        prop_assert_eq!(loaded.get_heads(), backend.get_heads());
+        prop_assert_eq!(loaded.save().unwrap(), saved);
+    }
+}
@@ -2,12 +2,18 @@ extern crate automerge_backend;
 use std::{collections::HashSet, convert::TryInto, num::NonZeroU32};
 
 use amp::{RootDiff, SortedVec};
-use automerge_backend::{Backend, Change};
+use automerge_backend::{
+    limits::{self, LimitExceeded},
+    AutomergeError, Backend, Change,
+};
 use automerge_protocol as amp;
 use automerge_protocol::{
     ActorId, ChangeHash, Diff, DiffEdit, ElementId, ListDiff, ObjType, ObjectId, Op, OpType, Patch,
 };
-use maplit::hashmap;
+use maplit::{btreemap, hashmap};
+
+mod common;
+use common::{change, set};
 
 #[test]
 fn test_apply_local_change() {
@@ -65,8 +71,8 @@ fn test_apply_local_change() {
         },
         deps: Vec::new(),
         diffs: RootDiff {
-            props: hashmap! {
-                "bird".into() => hashmap!{
+            props: btreemap! {
+                "bird".into() => btreemap!{
                     "1@eb738e04ef8848ce8b77309b6c7f7e39".try_into().unwrap() => Diff::Value("magpie".into())
                 }
             },
@@ -512,8 +518,8 @@ fn test_handle_list_insertion_and_deletion_in_same_change() {
         },
         deps: Vec::new(),
         diffs: RootDiff {
-            props: hashmap! {
-                "birds".into() => hashmap!{
+            props: btreemap! {
+                "birds".into() => btreemap!{
                     actor.op_id_at(1) => Diff::List(ListDiff{
                         object_id: ObjectId::from(actor.op_id_at(1)),
                         edits: vec![
@@ -609,3 +615,46 @@ fn assert_changes_equal(mut change1: amp::Change, change2: amp::Change) {
     change1.deps = change2.deps;
     assert_eq!(change1, change2_clone)
 }
+
+#[test]
+fn test_limits_are_enforced_for_local_changes() {
+    let wren = |actor: &ActorId, start_op: u64| {
+        change(actor)
+            .start_op(start_op)
+            .op(set(&ObjectId::Root, "bird", "wren", Vec::new()))
+            .build()
+    };
+    let long_actor = ActorId::from(vec![1; limits::max_actor_id_bytes() + 1]);
+    assert_eq!(
+        limits::check(&Change::from(wren(&long_actor, 1))),
+        Err(LimitExceeded::ActorIdTooLong {
+            len: limits::max_actor_id_bytes() + 1,
+            max: limits::max_actor_id_bytes(),
+        })
+    );
+    let mut backend = Backend::new();
+    assert!(matches!(
+        backend.apply_local_change(wren(&long_actor, 1)),
+        Err(AutomergeError::LimitExceeded(
+            LimitExceeded::ActorIdTooLong { .. }
+        ))
+    ));
+
+    let max = limits::max_safe_integer();
+    assert!(limits::check(&Change::from(wren(&ActorId::random(), max))).is_ok());
+    assert!(matches!(
+        backend.apply_local_change(wren(&ActorId::random(), max + 1)),
+        Err(AutomergeError::LimitExceeded(
+            LimitExceeded::IntegerTooLarge {
+                field: "max op",
+                ..
+            }
+        ))
+    ));
+
+    assert!(backend.get_changes(&[]).is_empty());
+    backend
+        .apply_local_change(wren(&ActorId::random(), 1))
+        .unwrap();
+    assert_eq!(limits::max_ops_per_change(), None);
+}
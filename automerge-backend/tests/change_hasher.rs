@@ -0,0 +1,38 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use automerge_backend::{set_change_hasher, Backend, Change, ChangeHasher, Sha2};
+use automerge_protocol::ActorId;
+
+mod common;
+use common::seq_change;
+
+/// Counts the bytes hashed, leaving the hashing to the default
+struct CountingHasher(AtomicUsize);
+
+impl ChangeHasher for CountingHasher {
+    fn sha256(&self, bytes: &[u8]) -> [u8; 32] {
+        self.0.fetch_add(bytes.len(), Ordering::SeqCst);
+        Sha2.sha256(bytes)
+    }
+}
+
+static HASHER: CountingHasher = CountingHasher(AtomicUsize::new(0));
+
+// The hasher is chosen once per process, so everything happens in one test
+#[test]
+fn test_custom_hasher_is_used() {
+    set_change_hasher(&HASHER).unwrap();
+    assert!(set_change_hasher(&Sha2).is_err());
+
+    let mut backend = Backend::new();
+    let change = seq_change(&ActorId::random(), 1, "key", 1).build();
+    backend.apply_local_change(change).unwrap();
+    let hashed = HASHER.0.load(Ordering::SeqCst);
+    assert!(hashed > 0);
+
+    let bytes = backend.get_changes(&[])[0].raw_bytes().to_vec();
+    let decoded = Change::from_bytes(bytes.clone()).unwrap();
+    assert_eq!(HASHER.0.load(Ordering::SeqCst), hashed + bytes.len() - 8);
+    // the hash is SHA-256 of everything after the magic bytes and checksum
+    assert_eq!(decoded.hash.0, Sha2.sha256(&bytes[8..]));
+}
@@ -0,0 +1,136 @@
+//! Helpers for building changes, shared by the integration tests with `mod common;`
+
+// each test binary only uses some of these
+#![allow(dead_code)]
+
+use automerge_backend::Change;
+use automerge_protocol as amp;
+use automerge_protocol::{ActorId, ElementId, Key, ObjType, ObjectId, Op, OpType, ScalarValue};
+
+/// Builds an [`amp::Change`], starting from the first change of an actor with no dependencies,
+/// no message, a time of zero and no ops
+pub struct ChangeBuilder(amp::Change);
+
+pub fn change(actor: &ActorId) -> ChangeBuilder {
+    ChangeBuilder(amp::Change {
+        actor_id: actor.clone(),
+        seq: 1,
+        start_op: 1,
+        time: 0,
+        message: None,
+        hash: None,
+        deps: Vec::new(),
+        operations: Vec::new(),
+        extra_bytes: Vec::new(),
+    })
+}
+
+impl ChangeBuilder {
+    pub fn seq(mut self, seq: u64) -> Self {
+        self.0.seq = seq;
+        self
+    }
+
+    pub fn start_op(mut self, start_op: u64) -> Self {
+        self.0.start_op = start_op;
+        self
+    }
+
+    pub fn time(mut self, time: i64) -> Self {
+        self.0.time = time;
+        self
+    }
+
+    pub fn message(mut self, message: &str) -> Self {
+        self.0.message = Some(message.to_string());
+        self
+    }
+
+    pub fn deps(mut self, deps: Vec<amp::ChangeHash>) -> Self {
+        self.0.deps = deps;
+        self
+    }
+
+    pub fn op(mut self, op: Op) -> Self {
+        self.0.operations.push(op);
+        self
+    }
+
+    pub fn ops(mut self, ops: Vec<Op>) -> Self {
+        self.0.operations.extend(ops);
+        self
+    }
+
+    pub fn build(self) -> amp::Change {
+        self.0
+    }
+
+    /// Build the change and encode it, for `Backend::apply_changes`
+    pub fn encode(self) -> Change {
+        self.0.into()
+    }
+}
+
+pub fn op(action: OpType, obj: &ObjectId, key: Key, insert: bool, pred: Vec<amp::OpId>) -> Op {
+    Op {
+        action,
+        obj: obj.clone(),
+        key,
+        insert,
+        pred: pred.into(),
+    }
+}
+
+/// Set `key` of the map `obj` to `value`, overwriting the ops in `pred`
+pub fn set<K, V>(obj: &ObjectId, key: K, value: V, pred: Vec<amp::OpId>) -> Op
+where
+    K: Into<Key>,
+    V: Into<ScalarValue>,
+{
+    op(OpType::Set(value.into()), obj, key.into(), false, pred)
+}
+
+/// Make an object of type `obj_type` at `key` of the map `obj`
+pub fn make(obj: &ObjectId, key: &str, obj_type: ObjType) -> Op {
+    op(OpType::Make(obj_type), obj, key.into(), false, Vec::new())
+}
+
+/// Insert `value` into the sequence `obj` after the element `after`
+pub fn insert<V: Into<ScalarValue>>(obj: &ObjectId, after: Key, value: V) -> Op {
+    op(OpType::Set(value.into()), obj, after, true, Vec::new())
+}
+
+/// The key of the list element after `id`
+pub fn after(id: &amp::OpId) -> Key {
+    Key::Seq(ElementId::Id(id.clone()))
+}
+
+/// The `seq`th change of `actor` when each of its changes is one op setting `key` in the root,
+/// overwriting the previous one
+pub fn seq_change<V: Into<ScalarValue>>(
+    actor: &ActorId,
+    seq: u64,
+    key: &str,
+    value: V,
+) -> ChangeBuilder {
+    let pred = if seq == 1 {
+        Vec::new()
+    } else {
+        vec![actor.op_id_at(seq - 1)]
+    };
+    change(actor)
+        .seq(seq)
+        .start_op(seq)
+        .op(set(&ObjectId::Root, key, value, pred))
+}
+
+/// The `seq`th change of `actor`, with one op setting `key` in the root to `seq` without
+/// overwriting anything
+pub fn set_key(actor: &ActorId, seq: u64, deps: Vec<amp::ChangeHash>, key: &str) -> Change {
+    change(actor)
+        .seq(seq)
+        .start_op(seq)
+        .deps(deps)
+        .op(set(&ObjectId::Root, key, seq as i64, Vec::new()))
+        .encode()
+}
@@ -1,4 +1,16 @@
-use automerge_backend::{Backend, Change};
+use std::{
+    convert::TryInto,
+    sync::atomic::{AtomicI64, Ordering},
+    time::Duration,
+};
+
+use amp::SortedVec;
+use automerge_backend::{AutomergeError, Backend, CatchUp, Change, GraphFormat};
+use automerge_protocol as amp;
+use automerge_protocol::ActorId;
+
+mod common;
+use common::{seq_change, set_key};
 
 // This test reproduces issue 95 (https://github.com/automerge/automerge-rs/issues/95)
 // where compressed changes were losing their header during decompression such
@@ -31,3 +43,423 @@ fn test_deflate_correctly() {
     let change_back = backend.get_changes(&[]);
     assert_eq!(change_back[0].raw_bytes().to_vec(), init_change);
 }
+
+#[test]
+fn test_get_changes_by_hashes() {
+    let actor: amp::ActorId = "7b7723afd9e6480397a4d467b7693156".try_into().unwrap();
+    let change: Change = amp::Change {
+        actor_id: actor,
+        seq: 1,
+        start_op: 1,
+        time: 0,
+        message: None,
+        hash: None,
+        deps: Vec::new(),
+        operations: vec![amp::Op {
+            action: amp::OpType::Set("magpie".into()),
+            obj: amp::ObjectId::Root,
+            key: "bird".into(),
+            insert: false,
+            pred: SortedVec::new(),
+        }],
+        extra_bytes: Vec::new(),
+    }
+    .try_into()
+    .unwrap();
+    let mut backend = Backend::new();
+    backend.apply_changes(vec![change.clone()]).unwrap();
+
+    let found = backend.get_changes_by_hashes(&[change.hash]).unwrap();
+    assert_eq!(found, vec![&change]);
+
+    let unknown = amp::ChangeHash([1; 32]);
+    match backend.get_changes_by_hashes(&[change.hash, unknown]) {
+        Err(AutomergeError::UnknownChanges(hashes)) => assert_eq!(hashes, vec![unknown]),
+        other => panic!("expected unknown changes error, got {:?}", other),
+    }
+}
+
+/// A chain of `n` changes each setting the "counter" key
+fn changes(actor: &ActorId, n: u64) -> Vec<Change> {
+    let mut deps = Vec::new();
+    (1..=n)
+        .map(|seq| {
+            let change = seq_change(actor, seq, "counter", seq as i64)
+                .deps(deps.clone())
+                .encode();
+            deps = vec![change.hash];
+            change
+        })
+        .collect()
+}
+
+#[test]
+fn test_peer_without_anything_gets_snapshot() {
+    let actor: ActorId = "7b7723afd9e6480397a4d467b7693156".try_into().unwrap();
+    let mut backend = Backend::new();
+    backend.apply_changes(changes(&actor, 10)).unwrap();
+
+    let catch_up = backend.catch_up(&[]).unwrap();
+    assert!(matches!(catch_up, CatchUp::Snapshot(_)));
+    let peer = Backend::load(catch_up.into_bytes()).unwrap();
+    assert_eq!(peer.get_heads(), backend.get_heads());
+    // sending a snapshot to a peer doesn't count as saving
+    assert!(!backend.save_incremental().unwrap().is_empty());
+}
+
+#[test]
+fn test_peer_missing_a_few_changes_gets_changes() {
+    let actor: ActorId = "7b7723afd9e6480397a4d467b7693156".try_into().unwrap();
+    let all = changes(&actor, 10);
+    let mut backend = Backend::new();
+    backend.apply_changes(all.clone()).unwrap();
+    let mut peer = Backend::new();
+    peer.apply_changes(all[..8].to_vec()).unwrap();
+
+    let catch_up = backend.catch_up(&peer.get_heads()).unwrap();
+    assert_eq!(
+        catch_up,
+        CatchUp::Changes([all[8].raw_bytes(), all[9].raw_bytes()].concat())
+    );
+    peer.load_incremental(catch_up.bytes()).unwrap();
+    assert_eq!(peer.get_heads(), backend.get_heads());
+
+    // once caught up there is nothing to send
+    assert_eq!(
+        backend.catch_up(&peer.get_heads()).unwrap(),
+        CatchUp::Changes(Vec::new())
+    );
+}
+
+#[test]
+fn test_peer_missing_most_changes_gets_snapshot() {
+    let actor: ActorId = "7b7723afd9e6480397a4d467b7693156".try_into().unwrap();
+    let all = changes(&actor, 10);
+    let mut backend = Backend::new();
+    backend.apply_changes(all.clone()).unwrap();
+    let mut peer = Backend::new();
+    peer.apply_changes(all[..2].to_vec()).unwrap();
+
+    let catch_up = backend.catch_up(&peer.get_heads()).unwrap();
+    assert!(matches!(catch_up, CatchUp::Snapshot(_)));
+    peer.load_incremental(catch_up.bytes()).unwrap();
+    assert_eq!(peer.get_heads(), backend.get_heads());
+}
+
+#[test]
+fn test_history_filters() {
+    let alice: ActorId = "7b7723afd9e6480397a4d467b7693156".try_into().unwrap();
+    let bob: ActorId = "9f17f3a4c2e54bd1a0a54c37e0f0c1d2".try_into().unwrap();
+    let change = |actor: &ActorId, seq: u64, time: i64, message: &str| {
+        seq_change(actor, seq, &actor.to_hex_string(), seq as i64)
+            .time(time)
+            .message(message)
+            .build()
+    };
+    let mut backend = Backend::new();
+    backend
+        .apply_local_change(change(&alice, 1, 1000, "add title"))
+        .unwrap();
+    backend
+        .apply_local_change(change(&alice, 2, 2000, "fix typo in title"))
+        .unwrap();
+    let mut other = Backend::new();
+    let (_, from_bob) = other
+        .apply_local_change(change(&bob, 1, 3000, "add author"))
+        .unwrap();
+    let from_bob = from_bob.clone();
+    backend.apply_changes(vec![from_bob]).unwrap();
+
+    let messages = |h: automerge_backend::History| -> Vec<String> {
+        h.map(|(hash, c)| {
+            assert_eq!(hash, c.hash);
+            c.message().unwrap()
+        })
+        .collect()
+    };
+    assert_eq!(
+        messages(backend.history()),
+        vec!["add title", "fix typo in title", "add author"]
+    );
+    assert_eq!(
+        messages(backend.history().by_actor(bob.clone())),
+        vec!["add author"]
+    );
+    assert_eq!(
+        messages(backend.history().between(1500..3500)),
+        vec!["fix typo in title", "add author"]
+    );
+    assert_eq!(
+        messages(backend.history().message_contains("title").between(0..1500)),
+        vec!["add title"]
+    );
+    assert!(messages(backend.history().by_actor(bob).message_contains("title")).is_empty());
+}
+
+static NOW: AtomicI64 = AtomicI64::new(0);
+
+fn now() -> i64 {
+    NOW.load(Ordering::SeqCst)
+}
+
+#[test]
+fn test_received_at() {
+    let alice: ActorId = "7b7723afd9e6480397a4d467b7693156".try_into().unwrap();
+    let bob: ActorId = "9f17f3a4c2e54bd1a0a54c37e0f0c1d2".try_into().unwrap();
+    let change = |actor: &ActorId, seq: u64| {
+        seq_change(actor, seq, &actor.to_hex_string(), seq as i64)
+            .time(1)
+            .build()
+    };
+    let mut remote = Backend::new();
+    let b1 = remote
+        .apply_local_change(change(&bob, 1))
+        .unwrap()
+        .1
+        .clone();
+    let b2 = remote
+        .apply_local_change(change(&bob, 2))
+        .unwrap()
+        .1
+        .clone();
+
+    let mut backend = Backend::new();
+    backend.set_received_clock(Some(now));
+    NOW.store(1000, Ordering::SeqCst);
+    let a1 = backend
+        .apply_local_change(change(&alice, 1))
+        .unwrap()
+        .1
+        .hash;
+    // b2 waits in the queue for b1, it's received when it is applied
+    backend.apply_changes(vec![b2.clone()]).unwrap();
+    assert_eq!(backend.received_at(&b2.hash), None);
+    NOW.store(2000, Ordering::SeqCst);
+    backend.apply_changes(vec![b1.clone()]).unwrap();
+    NOW.store(3000, Ordering::SeqCst);
+    backend.apply_changes(vec![b1.clone()]).unwrap();
+
+    assert_eq!(backend.received_at(&a1), None);
+    assert_eq!(backend.received_at(&b1.hash), Some(2000));
+    assert_eq!(backend.received_at(&b2.hash), Some(2000));
+    let received: Vec<_> = backend
+        .history()
+        .received_between(1500..2500)
+        .map(|(hash, _)| hash)
+        .collect();
+    assert_eq!(received, vec![b1.hash, b2.hash]);
+
+    // Receive times aren't part of the document, they're persisted separately
+    let encoded = backend.encode_received_at().unwrap();
+    let mut loaded = Backend::load(backend.save().unwrap()).unwrap();
+    assert_eq!(loaded.received_at(&b1.hash), None);
+    loaded.restore_received_at(&encoded).unwrap();
+    assert_eq!(loaded.received_at(&b1.hash), Some(2000));
+    assert_eq!(loaded.received_at(&b2.hash), Some(2000));
+    assert_eq!(loaded.received_at(&a1), None);
+    assert!(loaded.restore_received_at(&[0x43]).is_err());
+}
+
+#[test]
+fn test_export_change_graph() {
+    let alice: ActorId = "7b7723afd9e6480397a4d467b7693156".try_into().unwrap();
+    let bob: ActorId = "9f17f3a4c2e54bd1a0a54c37e0f0c1d2".try_into().unwrap();
+    let mut backend = Backend::new();
+    backend
+        .apply_local_change(
+            seq_change(&alice, 1, "title", "birds")
+                .message("add \"title\"")
+                .build(),
+        )
+        .unwrap();
+    let base = backend.get_heads();
+    let mut other = Backend::new();
+    let (_, from_bob) = other
+        .apply_local_change(
+            seq_change(&bob, 1, "author", "bob")
+                .message("add author")
+                .deps(base.clone())
+                .build(),
+        )
+        .unwrap();
+    let from_bob = from_bob.clone();
+    backend.apply_changes(vec![from_bob]).unwrap();
+
+    let node = |hash: &amp::ChangeHash| format!("c{}", &hash.to_string()[..12]);
+    let first = node(&base[0]);
+    let second = node(&backend.get_heads()[0]);
+
+    let dot = backend.export_change_graph(GraphFormat::Dot);
+    assert!(dot.starts_with("digraph changes {"));
+    assert!(dot.contains(&format!("{} -> {};", first, second)));
+    assert!(dot.contains(&format!(
+        "{} [label=\"{}\\n7b7723af:1\\nadd \\\"title\\\"\"",
+        first,
+        &base[0].to_string()[..8]
+    )));
+    assert_eq!(dot.matches("penwidth=3").count(), 1);
+    assert!(dot.trim_end().ends_with('}'));
+
+    let mermaid = backend.export_change_graph(GraphFormat::Mermaid);
+    assert!(mermaid.starts_with("flowchart LR\n"));
+    assert!(mermaid.contains(&format!("{} --> {}", first, second)));
+    assert!(mermaid.contains("9f17f3a4:1<br/>add author\"]"));
+    assert!(mermaid.contains("add #quot;title#quot;"));
+}
+
+#[test]
+fn test_restrict_to_actors() {
+    let mine: ActorId = "7b7723afd9e6480397a4d467b7693156".try_into().unwrap();
+    let theirs: ActorId = "37704788917a499cb0206fa8519ac4d9".try_into().unwrap();
+
+    let first = set_key(&mine, 1, Vec::new(), "a");
+    let other = set_key(&theirs, 1, Vec::new(), "b");
+    let second = set_key(&mine, 2, vec![first.hash], "c");
+    // builds on the other actor's edit, so it can't be shared on its own
+    let merged = set_key(&mine, 3, vec![other.hash, second.hash], "d");
+    let after_merge = set_key(&mine, 4, vec![merged.hash], "e");
+
+    let mut backend = Backend::new();
+    backend
+        .apply_changes(vec![
+            first.clone(),
+            other.clone(),
+            second.clone(),
+            merged.clone(),
+            after_merge.clone(),
+        ])
+        .unwrap();
+
+    let by_actor = backend.get_changes_by_actors(std::slice::from_ref(&mine));
+    assert_eq!(by_actor, vec![&first, &second, &merged, &after_merge]);
+    assert_eq!(
+        backend
+            .get_changes_by_actors(&[theirs.clone(), mine.clone()])
+            .len(),
+        5
+    );
+
+    let restricted = backend.restrict_to_actors(&[mine]).unwrap();
+    assert_eq!(restricted.get_heads(), vec![second.hash]);
+    assert!(restricted.get_change_by_hash(&other.hash).is_none());
+    assert!(restricted.get_change_by_hash(&merged.hash).is_none());
+}
+
+#[test]
+fn test_bundles_carry_changes_between_heads() {
+    let actor: ActorId = "7b7723afd9e6480397a4d467b7693156".try_into().unwrap();
+    let first = set_key(&actor, 1, Vec::new(), "a");
+    let second = set_key(&actor, 2, vec![first.hash], "b");
+    let third = set_key(&actor, 3, vec![second.hash], "c");
+    let mut backend = Backend::new();
+    backend
+        .apply_changes(vec![first.clone(), second.clone(), third.clone()])
+        .unwrap();
+
+    // the whole history
+    let everything = backend.export_bundle(&[], &backend.get_heads()).unwrap();
+    let manifest = Backend::inspect_bundle(&everything).unwrap();
+    assert!(manifest.needs.is_empty());
+    assert_eq!(manifest.heads, vec![third.hash]);
+    assert_eq!(manifest.changes, vec![first.hash, second.hash, third.hash]);
+    let mut copy = Backend::new();
+    copy.apply_bundle(&everything).unwrap();
+    assert_eq!(copy.get_heads(), backend.get_heads());
+    assert_eq!(copy.get_patch().unwrap(), backend.get_patch().unwrap());
+
+    // just the last two changes, which need the first
+    let recent = backend
+        .export_bundle(&[first.hash], &backend.get_heads())
+        .unwrap();
+    assert_eq!(
+        Backend::inspect_bundle(&recent).unwrap().changes,
+        vec![second.hash, third.hash]
+    );
+    let mut behind = Backend::new();
+    match behind.apply_bundle(&recent) {
+        Err(AutomergeError::UnknownChanges(missing)) => assert_eq!(missing, vec![first.hash]),
+        other => panic!("expected missing dependencies, got {:?}", other),
+    }
+    assert!(behind.get_heads().is_empty());
+    behind.apply_changes(vec![first.clone()]).unwrap();
+    behind.apply_bundle(&recent).unwrap();
+    assert_eq!(behind.get_heads(), vec![third.hash]);
+
+    // applying a bundle twice is harmless, as is one ending before our heads
+    behind.apply_bundle(&recent).unwrap();
+    let old = backend.export_bundle(&[], &[second.hash]).unwrap();
+    behind.apply_bundle(&old).unwrap();
+    assert_eq!(behind.get_heads(), vec![third.hash]);
+}
+
+#[test]
+fn test_bundle_errors() {
+    let actor: ActorId = "7b7723afd9e6480397a4d467b7693156".try_into().unwrap();
+    let first = set_key(&actor, 1, Vec::new(), "a");
+    let mut backend = Backend::new();
+    backend.apply_changes(vec![first.clone()]).unwrap();
+
+    let unknown = set_key(&actor, 2, vec![first.hash], "b").hash;
+    assert!(matches!(
+        backend.export_bundle(&[], &[unknown]),
+        Err(AutomergeError::UnknownChanges(_))
+    ));
+
+    let empty = backend.export_bundle(&[first.hash], &[first.hash]).unwrap();
+    assert!(Backend::inspect_bundle(&empty).unwrap().changes.is_empty());
+    assert!(backend.apply_bundle(&empty).is_ok());
+
+    assert!(backend.apply_bundle(b"not a bundle").is_err());
+    let bundle = backend.export_bundle(&[], &[first.hash]).unwrap();
+    assert!(backend.apply_bundle(&bundle[..bundle.len() - 4]).is_err());
+}
+
+fn backend_with_times(times: &[i64]) -> Backend {
+    let actor: ActorId = "7b7723afd9e6480397a4d467b7693156".try_into().unwrap();
+    let mut backend = Backend::new();
+    for (i, time) in times.iter().enumerate() {
+        let change = seq_change(&actor, i as u64 + 1, "keystrokes", i as i64 + 1).time(*time);
+        backend.apply_local_change(change.build()).unwrap();
+    }
+    backend
+}
+
+#[test]
+fn test_playback_delays() {
+    let backend = backend_with_times(&[1000, 2000, 2500, 2400]);
+    let events = backend.playback().collect::<Result<Vec<_>, _>>().unwrap();
+
+    let delays: Vec<_> = events.iter().map(|e| e.delay).collect();
+    assert_eq!(
+        delays,
+        vec![
+            Duration::from_millis(0),
+            Duration::from_millis(1000),
+            Duration::from_millis(500),
+            Duration::from_millis(0),
+        ]
+    );
+    let hashes: Vec<_> = events.iter().map(|e| e.hash).collect();
+    let expected: Vec<_> = backend.get_changes(&[]).iter().map(|c| c.hash).collect();
+    assert_eq!(hashes, expected);
+    assert_eq!(events.last().unwrap().patch.max_op, 4);
+}
+
+#[test]
+fn test_playback_scaled() {
+    let backend = backend_with_times(&[0, 1000, 60_000]);
+    let delays: Vec<_> = backend
+        .playback()
+        .speed(2.0)
+        .max_delay(Duration::from_secs(5))
+        .map(|e| e.unwrap().delay)
+        .collect();
+    assert_eq!(
+        delays,
+        vec![
+            Duration::from_millis(0),
+            Duration::from_millis(500),
+            Duration::from_secs(5),
+        ]
+    );
+}
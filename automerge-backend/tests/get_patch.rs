@@ -2,14 +2,18 @@ extern crate automerge_backend;
 use std::{convert::TryInto, num::NonZeroU32};
 
 use amp::{RootDiff, SortedVec};
-use automerge_backend::{Backend, Change};
+use automerge_backend::{AutomergeError, Backend, Change, PathElement, Value};
 use automerge_protocol as amp;
 use automerge_protocol::{
-    ActorId, Diff, DiffEdit, ElementId, ListDiff, MapDiff, ObjectId, Op, Patch, ScalarValue,
+    ActorId, CursorDiff, Diff, DiffEdit, ElementId, Key, ListDiff, MapDiff, ObjType, ObjectId, Op,
+    OpType, Patch, ScalarValue,
 };
-use maplit::hashmap;
+use maplit::{btreemap, hashmap};
 use pretty_assertions::assert_eq;
 
+mod common;
+use common::{after, change, insert, make, op, seq_change, set};
+
 #[test]
 fn test_include_most_recent_value_for_key() {
     let actor: ActorId = "ec28cfbcdb9e4f32ad24b3c776e651b0".try_into().unwrap();
@@ -63,8 +67,8 @@ fn test_include_most_recent_value_for_key() {
         },
         deps: vec![change2.hash],
         diffs: RootDiff {
-            props: hashmap! {
-                "bird".into() => hashmap!{
+            props: btreemap! {
+                "bird".into() => btreemap!{
                     actor.op_id_at(2) => Diff::Value("blackbird".into()),
                 }
             },
@@ -132,8 +136,8 @@ fn test_includes_conflicting_values_for_key() {
         actor: None,
         deps: vec![change1.hash, change2.hash],
         diffs: RootDiff {
-            props: hashmap! {
-                "bird".into() => hashmap!{
+            props: btreemap! {
+                "bird".into() => btreemap!{
                     actor1.op_id_at(1) => Diff::Value("magpie".into()),
                     actor2.op_id_at(1) => Diff::Value("blackbird".into()),
                 },
@@ -200,8 +204,8 @@ fn test_handles_counter_increment_at_keys_in_a_map() {
         pending_changes: 0,
         deps: vec![change2.hash],
         diffs: RootDiff {
-            props: hashmap! {
-                "counter".into() => hashmap!{
+            props: btreemap! {
+                "counter".into() => btreemap!{
                     actor.op_id_at(1) => Diff::Value(ScalarValue::Counter(3))
                 }
             },
@@ -285,12 +289,12 @@ fn test_creates_nested_maps() {
         pending_changes: 0,
         deps: vec![change2.hash],
         diffs: RootDiff {
-            props: hashmap! {
-                "birds".into() => hashmap!{
+            props: btreemap! {
+                "birds".into() => btreemap!{
                     actor.op_id_at(1) => Diff::Map(MapDiff{
                         object_id: ObjectId::from(actor.op_id_at(1)),
-                        props: hashmap!{
-                            "sparrows".into() => hashmap!{
+                        props: btreemap!{
+                            "sparrows".into() => btreemap!{
                                 actor.op_id_at(4) => Diff::Value(ScalarValue::F64(15.0))
                             }
                         }
@@ -348,8 +352,8 @@ fn test_create_lists() {
         seq: None,
         deps: vec![change1.hash],
         diffs: RootDiff {
-            props: hashmap! {
-                "birds".into() => hashmap!{
+            props: btreemap! {
+                "birds".into() => btreemap!{
                     actor.op_id_at(1) => Diff::List(ListDiff{
                         object_id: ObjectId::from(actor.op_id_at(1)),
                         edits: vec![DiffEdit::SingleElementInsert {
@@ -426,8 +430,8 @@ fn test_includes_latests_state_of_list() {
         seq: None,
         deps: vec![change1.hash],
         diffs: RootDiff {
-            props: hashmap! {
-                "todos".into() => hashmap!{
+            props: btreemap! {
+                "todos".into() => btreemap!{
                     actor.op_id_at(1) => Diff::List(ListDiff{
                         object_id: ObjectId::from(actor.op_id_at(1)),
                         edits: vec![DiffEdit::SingleElementInsert{
@@ -436,11 +440,11 @@ fn test_includes_latests_state_of_list() {
                             op_id: actor.op_id_at(2),
                             value: Diff::Map(MapDiff{
                                 object_id: actor.op_id_at(2).into(),
-                                props: hashmap!{
-                                    "title".into() => hashmap!{
+                                props: btreemap!{
+                                    "title".into() => btreemap!{
                                         actor.op_id_at(3) => Diff::Value("water plants".into()),
                                     },
-                                    "done".into() => hashmap!{
+                                    "done".into() => btreemap!{
                                         actor.op_id_at(4) => Diff::Value(false.into())
                                     }
                                 }
@@ -491,8 +495,8 @@ fn test_includes_date_objects_at_root() {
         seq: None,
         deps: vec![change1.hash],
         diffs: RootDiff {
-            props: hashmap! {
-                "now".into() => hashmap!{
+            props: btreemap! {
+                "now".into() => btreemap!{
                     actor.op_id_at(1) => Diff::Value(ScalarValue::Timestamp(1_586_541_033_457))
                 }
             },
@@ -547,8 +551,8 @@ fn test_includes_date_objects_in_a_list() {
         seq: None,
         deps: vec![change1.hash],
         diffs: RootDiff {
-            props: hashmap! {
-                "list".into() => hashmap!{
+            props: btreemap! {
+                "list".into() => btreemap!{
                     actor.op_id_at(1) => Diff::List(ListDiff{
                         object_id: ObjectId::from(actor.op_id_at(1)),
                         edits: vec![DiffEdit::SingleElementInsert {
@@ -656,8 +660,8 @@ fn test_includes_updates_for_conflicting_list_elements() {
         seq: None,
         deps,
         diffs: RootDiff {
-            props: hashmap! {
-                "list".into() => hashmap!{
+            props: btreemap! {
+                "list".into() => btreemap!{
                     local_actor.op_id_at(1) => Diff::List(ListDiff{
                         object_id: ObjectId::from(local_actor.op_id_at(1)),
                         edits: vec![
@@ -688,3 +692,690 @@ fn test_includes_updates_for_conflicting_list_elements() {
     let patch = backend.get_patch().unwrap();
     assert_eq!(patch, expected_patch)
 }
+
+#[test]
+fn test_patch_props_are_ordered() {
+    let actor: ActorId = "7b7723afd9e6480397a4d467b7693156".try_into().unwrap();
+    let birds = ["wren", "magpie", "jay", "chaffinch", "starling", "rook"];
+    let change: Change = amp::Change {
+        actor_id: actor,
+        seq: 1,
+        start_op: 1,
+        time: 0,
+        message: None,
+        hash: None,
+        deps: Vec::new(),
+        operations: birds
+            .iter()
+            .map(|bird| Op {
+                action: amp::OpType::Set(ScalarValue::Boolean(true)),
+                obj: ObjectId::Root,
+                key: (*bird).into(),
+                insert: false,
+                pred: SortedVec::new(),
+            })
+            .collect(),
+        extra_bytes: Vec::new(),
+    }
+    .try_into()
+    .unwrap();
+
+    let mut backend = Backend::new();
+    backend.load_changes(vec![change]).unwrap();
+    let patch = backend.get_patch().unwrap();
+
+    let keys: Vec<_> = patch.diffs.props.keys().map(|k| k.as_str()).collect();
+    assert_eq!(
+        keys,
+        vec!["chaffinch", "jay", "magpie", "rook", "starling", "wren"]
+    );
+    // serialising the same patch twice gives the same bytes
+    assert_eq!(
+        serde_json::to_string(&patch).unwrap(),
+        serde_json::to_string(&backend.get_patch().unwrap()).unwrap()
+    );
+}
+
+#[test]
+fn test_get_patch_at_heads() {
+    let actor: ActorId = "ec28cfbcdb9e4f32ad24b3c776e651b0".try_into().unwrap();
+    let set_bird =
+        |seq: u64, deps: Vec<amp::ChangeHash>, bird: &str, pred: SortedVec<amp::OpId>| {
+            let change: Change = amp::Change {
+                actor_id: actor.clone(),
+                seq,
+                start_op: seq,
+                time: 0,
+                deps,
+                message: None,
+                hash: None,
+                operations: vec![Op {
+                    action: amp::OpType::Set(bird.into()),
+                    key: "bird".into(),
+                    obj: ObjectId::Root,
+                    pred,
+                    insert: false,
+                }],
+                extra_bytes: Vec::new(),
+            }
+            .try_into()
+            .unwrap();
+            change
+        };
+    let change1 = set_bird(1, Vec::new(), "magpie", SortedVec::new());
+    let change2 = set_bird(
+        2,
+        vec![change1.hash],
+        "wren",
+        vec![actor.op_id_at(1)].into(),
+    );
+
+    let mut backend = Backend::new();
+    backend
+        .apply_changes(vec![change1.clone(), change2.clone()])
+        .unwrap();
+
+    let mut expected = Backend::new();
+    expected.apply_changes(vec![change1.clone()]).unwrap();
+    assert_eq!(
+        backend.get_patch_at(&[change1.hash]).unwrap(),
+        expected.get_patch().unwrap()
+    );
+    assert_eq!(
+        backend.get_patch_at(&[change2.hash]).unwrap(),
+        backend.get_patch().unwrap()
+    );
+    assert!(backend.get_patch_at(&[]).unwrap().diffs.props.is_empty());
+
+    let unknown = amp::ChangeHash([7; 32]);
+    assert!(backend.get_patch_at(&[unknown]).is_err());
+}
+
+#[test]
+fn test_empty_document_bytes_are_stable() {
+    let backend = Backend::new();
+    assert!(backend.is_empty());
+    assert!(backend.get_heads().is_empty());
+    assert_eq!(backend.save().unwrap(), Backend::empty_document());
+    // changing these bytes breaks storage which recognises empty documents by them
+    assert_eq!(
+        hex::encode(Backend::empty_document()),
+        "856f4a83b81a9544000400000000"
+    );
+
+    let loaded = Backend::load(Backend::empty_document().to_vec()).unwrap();
+    assert!(loaded.is_empty());
+    assert!(Backend::is_empty_document(Backend::empty_document()));
+    assert!(Backend::is_empty_document(&[]));
+}
+
+#[test]
+fn test_edited_document_is_not_empty() {
+    let mut backend = Backend::new();
+    backend
+        .apply_local_change(seq_change(&ActorId::random(), 1, "key", 1).build())
+        .unwrap();
+    assert!(!backend.is_empty());
+    let saved = backend.save().unwrap();
+    assert!(!Backend::is_empty_document(&saved));
+}
+
+#[test]
+fn test_reading_values_at_paths() {
+    let actor: ActorId = "02ef21f3c9eb4087880ebedd7c4bbe43".try_into().unwrap();
+    let mut backend = Backend::new();
+
+    let mut ids = backend.op_id_allocator(&actor);
+    let birds = ObjectId::from(ids.allocate(3));
+    let text = ObjectId::from(ids.allocate(4));
+    let ops = vec![
+        make(&ObjectId::Root, "birds", ObjType::List),
+        insert(&birds, Key::head(), "wren"),
+        insert(&birds, after(&actor.op_id_at(2)), "robin"),
+        make(&ObjectId::Root, "title", ObjType::Text),
+        insert(&text, Key::head(), "h"),
+        insert(&text, after(&actor.op_id_at(5)), "i"),
+        set(&ObjectId::Root, "count", 2, Vec::new()),
+    ];
+    let first = ids.finish(ops, 0, None);
+    backend.apply_changes(vec![first.into()]).unwrap();
+    let first_heads = backend.get_heads();
+
+    let mut ids = backend.op_id_allocator(&actor);
+    ids.allocate(1);
+    let second = ids.finish(
+        vec![set(&ObjectId::Root, "count", 3, vec![actor.op_id_at(7)])],
+        0,
+        None,
+    );
+    backend.apply_changes(vec![second.into()]).unwrap();
+
+    assert_eq!(
+        backend.value_at(&ObjectId::Root, &[], None).unwrap(),
+        Some(Value::Map(btreemap! {
+            "birds".into() => Value::List(vec![
+                Value::Primitive("wren".into()),
+                Value::Primitive("robin".into()),
+            ]),
+            "title".into() => Value::Text("hi".into()),
+            "count".into() => Value::Primitive(ScalarValue::Int(3)),
+        }))
+    );
+    assert_eq!(
+        backend
+            .value_at(&ObjectId::Root, &["birds".into(), 1.into()], None)
+            .unwrap(),
+        Some(Value::Primitive("robin".into()))
+    );
+    assert_eq!(
+        backend.value_at(&text, &[], None).unwrap(),
+        Some(Value::Text("hi".into()))
+    );
+    assert_eq!(
+        backend
+            .value_at(&ObjectId::Root, &["count".into()], Some(&first_heads))
+            .unwrap(),
+        Some(Value::Primitive(ScalarValue::Int(2)))
+    );
+
+    // paths which don't exist
+    for path in [
+        vec![PathElement::from("fish")],
+        vec!["birds".into(), 2.into()],
+        vec!["count".into(), "value".into()],
+        vec![0.into()],
+    ]
+    .iter()
+    {
+        assert_eq!(backend.value_at(&ObjectId::Root, path, None).unwrap(), None);
+    }
+    let missing = ObjectId::from(actor.op_id_at(100));
+    assert_eq!(backend.value_at(&missing, &[], None).unwrap(), None);
+
+    let unknown_heads = [amp::ChangeHash([0; 32])];
+    assert!(matches!(
+        backend.value_at(&ObjectId::Root, &[], Some(&unknown_heads)),
+        Err(AutomergeError::UnknownChanges(_))
+    ));
+}
+
+#[test]
+fn test_conflicts_resolve_to_highest_op_id() {
+    let actor1: ActorId = "02ef21f3c9eb4087880ebedd7c4bbe43".try_into().unwrap();
+    let actor2: ActorId = "2a1d376b24f744008d4af58252d644dd".try_into().unwrap();
+    let mut backend = Backend::new();
+
+    let changes: Vec<_> = [(&actor1, "magpie"), (&actor2, "jay")]
+        .iter()
+        .map(|(actor, bird)| {
+            let mut ids = backend.op_id_allocator(actor);
+            ids.allocate(1);
+            ids.finish(
+                vec![set(&ObjectId::Root, "bird", *bird, Vec::new())],
+                0,
+                None,
+            )
+            .into()
+        })
+        .collect();
+    backend.apply_changes(changes).unwrap();
+
+    // both ops have counter 1 so the actor ID breaks the tie
+    assert_eq!(
+        backend
+            .value_at(&ObjectId::Root, &["bird".into()], None)
+            .unwrap(),
+        Some(Value::Primitive("jay".into()))
+    );
+}
+
+#[test]
+fn test_allocating_op_ids_for_several_changes() {
+    let actor1: ActorId = "02ef21f3c9eb4087880ebedd7c4bbe43".try_into().unwrap();
+    let actor2: ActorId = "2a1d376b24f744008d4af58252d644dd".try_into().unwrap();
+    let mut backend = Backend::new();
+    assert_eq!(backend.max_op(), 0);
+
+    let mut ids = backend.op_id_allocator(&actor1);
+    assert_eq!((ids.seq(), ids.start_op()), (1, 1));
+    let list = ids.next_op_id();
+    let first = ids.allocate(3);
+    let birds = vec![
+        ScalarValue::from("wren"),
+        ScalarValue::from("robin"),
+        ScalarValue::from("sparrow"),
+    ];
+    let ops = vec![
+        make(&ObjectId::Root, "birds", ObjType::List),
+        op(
+            OpType::MultiSet(birds.try_into().unwrap()),
+            &list.clone().into(),
+            Key::head(),
+            true,
+            Vec::new(),
+        ),
+    ];
+    let change = ids.finish(ops, 0, None);
+    backend.apply_changes(vec![change.into()]).unwrap();
+    assert_eq!(backend.max_op(), 4);
+
+    // a second change by someone else deletes the last two birds
+    let mut ids = backend.op_id_allocator(&actor2);
+    assert_eq!((ids.seq(), ids.start_op()), (1, 5));
+    assert_eq!(ids.deps(), backend.get_heads().as_slice());
+    ids.allocate(2);
+    let ops = vec![op(
+        OpType::Del(NonZeroU32::new(2).unwrap()),
+        &list.into(),
+        after(&actor1.op_id_at(first.0 + 1)),
+        false,
+        vec![actor1.op_id_at(first.0 + 1)],
+    )];
+    let change = ids.finish(ops, 0, None);
+    backend.apply_changes(vec![change.into()]).unwrap();
+    assert_eq!(backend.max_op(), 6);
+
+    let ids = backend.op_id_allocator(&actor1);
+    assert_eq!((ids.seq(), ids.start_op()), (2, 7));
+}
+
+#[test]
+fn test_explain_a_conflict_and_its_resolution() {
+    let alice: ActorId = "02ef21f3c9eb4087880ebedd7c4bbe43".try_into().unwrap();
+    let bob: ActorId = "2a1d376b24f744008d4af58252d644dd".try_into().unwrap();
+    let birds: ObjectId = alice.op_id_at(1).into();
+    let make = change(&alice)
+        .time(1)
+        .message("make birds")
+        .op(make(&ObjectId::Root, "birds", ObjType::Map))
+        .encode();
+    let by_alice = change(&alice)
+        .seq(2)
+        .start_op(2)
+        .time(2)
+        .message("alice's favourite")
+        .deps(vec![make.hash])
+        .op(set(&birds, "favourite", "robin", Vec::new()))
+        .encode();
+    let by_bob = change(&bob)
+        .start_op(2)
+        .time(1)
+        .message("bob's favourite")
+        .deps(vec![make.hash])
+        .op(set(&birds, "favourite", "wren", Vec::new()))
+        .encode();
+    let mut backend = Backend::new();
+    backend
+        .apply_changes(vec![make.clone(), by_alice.clone(), by_bob.clone()])
+        .unwrap();
+
+    let path: Vec<PathElement> = vec!["birds".into(), "favourite".into()];
+    let explanation = backend.explain(&path).unwrap().unwrap();
+    assert_eq!(explanation.containers.len(), 1);
+    assert_eq!(explanation.containers[0].op_id, alice.op_id_at(1));
+    assert_eq!(explanation.containers[0].change, make.hash);
+    assert_eq!(explanation.ops.len(), 2);
+    assert_eq!(explanation.current().count(), 2);
+    assert!(explanation.ops.iter().all(|op| !op.is_merge()));
+
+    let resolve = change(&alice)
+        .seq(3)
+        .start_op(3)
+        .time(3)
+        .message("settle it")
+        .deps(vec![by_alice.hash, by_bob.hash])
+        .op(set(
+            &birds,
+            "favourite",
+            "magpie",
+            vec![alice.op_id_at(2), bob.op_id_at(2)],
+        ))
+        .encode();
+    backend.apply_changes(vec![resolve.clone()]).unwrap();
+    let explanation = backend.explain(&path).unwrap().unwrap();
+    assert_eq!(explanation.ops.len(), 3);
+    let current: Vec<_> = explanation.current().collect();
+    assert_eq!(current.len(), 1);
+    assert_eq!(current[0].change, resolve.hash);
+    assert!(current[0].is_merge());
+    assert_eq!(current[0].pred, vec![alice.op_id_at(2), bob.op_id_at(2)]);
+    assert_eq!(current[0].message.as_deref(), Some("settle it"));
+
+    let report = explanation.to_string();
+    assert!(report.starts_with("$[\"birds\"][\"favourite\"]\n"));
+    assert!(report.contains("after merging 2 heads: settle it"));
+
+    assert_eq!(backend.explain(&["fish".into(), "x".into()]).unwrap(), None);
+}
+
+#[test]
+fn test_explain_a_list_element() {
+    let actor: ActorId = "7b7723afd9e6480397a4d467b7693156".try_into().unwrap();
+    let list: ObjectId = actor.op_id_at(1).into();
+    let first = change(&actor)
+        .time(1)
+        .message("make list")
+        .op(make(&ObjectId::Root, "birds", ObjType::List))
+        .op(insert(&list, Key::head(), "chaffinch"))
+        .op(insert(&list, after(&actor.op_id_at(2)), "greenfinch"))
+        .encode();
+    let second = change(&actor)
+        .seq(2)
+        .start_op(4)
+        .time(2)
+        .message("update")
+        .deps(vec![first.hash])
+        .op(op(
+            OpType::Set("goldfinch".into()),
+            &list,
+            after(&actor.op_id_at(3)),
+            false,
+            vec![actor.op_id_at(3)],
+        ))
+        .encode();
+    let mut backend = Backend::new();
+    backend.apply_changes(vec![first, second]).unwrap();
+
+    let explanation = backend
+        .explain(&["birds".into(), 1.into()])
+        .unwrap()
+        .unwrap();
+    let ops: Vec<_> = explanation.ops.iter().map(|op| op.op_id.clone()).collect();
+    assert_eq!(ops, vec![actor.op_id_at(3), actor.op_id_at(4)]);
+    assert!(!explanation.ops[0].current);
+    assert!(explanation.ops[1].current);
+    assert_eq!(backend.explain(&["birds".into(), 2.into()]).unwrap(), None);
+}
+
+#[test]
+fn test_element_history() {
+    let actor: ActorId = "7b7723afd9e6480397a4d467b7693156".try_into().unwrap();
+    let list: ObjectId = actor.op_id_at(1).into();
+    let elem: amp::ElementId = actor.op_id_at(2).into();
+
+    let change1 = change(&actor)
+        .time(10)
+        .op(make(&ObjectId::Root, "birds", ObjType::List))
+        .op(insert(&list, Key::head(), "chaffinch"))
+        .op(insert(&list, elem.clone().into(), "greenfinch"))
+        .build();
+    let change2 = change(&actor)
+        .seq(2)
+        .start_op(4)
+        .time(20)
+        .op(op(
+            OpType::Set("goldfinch".into()),
+            &list,
+            elem.clone().into(),
+            false,
+            vec![actor.op_id_at(2)],
+        ))
+        .build();
+    let change3 = change(&actor)
+        .seq(3)
+        .start_op(5)
+        .time(30)
+        .op(op(
+            OpType::Del(NonZeroU32::new(1).unwrap()),
+            &list,
+            elem.clone().into(),
+            false,
+            vec![actor.op_id_at(4)],
+        ))
+        .build();
+
+    let mut backend = Backend::new();
+    backend.apply_local_change(change1).unwrap();
+    backend.apply_local_change(change2).unwrap();
+    let (_, last) = backend.apply_local_change(change3).unwrap();
+    let last_hash = last.hash;
+
+    let history = backend.element_history(&list, &elem).unwrap();
+    assert_eq!(history.insert.op_id, actor.op_id_at(2));
+    assert_eq!(history.insert.action, OpType::Set("chaffinch".into()));
+    assert_eq!(history.insert.time, 10);
+    assert_eq!(history.updates.len(), 1);
+    assert_eq!(history.updates[0].op_id, actor.op_id_at(4));
+    assert_eq!(history.updates[0].time, 20);
+    assert_eq!(history.deletions.len(), 1);
+    assert_eq!(history.deletions[0].op_id, actor.op_id_at(5));
+    assert_eq!(history.deletions[0].change, last_hash);
+
+    // the element inserted after ours is not mixed in
+    let other = backend
+        .element_history(&list, &actor.op_id_at(3).into())
+        .unwrap();
+    assert!(other.updates.is_empty());
+    assert!(other.deletions.is_empty());
+
+    assert!(matches!(
+        backend.element_history(&list, &actor.op_id_at(9).into()),
+        Err(AutomergeError::MissingElement(..))
+    ));
+}
+
+#[test]
+fn test_attribute_text() {
+    let alice: ActorId = "7b7723afd9e6480397a4d467b7693156".try_into().unwrap();
+    let bob: ActorId = "9f17f3a4c2e54bd1a0a54c37e0f0c1d2".try_into().unwrap();
+    let text: ObjectId = alice.op_id_at(1).into();
+
+    let mut backend = Backend::new();
+    let (_, first) = backend
+        .apply_local_change(
+            change(&alice)
+                .time(10)
+                .op(make(&ObjectId::Root, "text", ObjType::Text))
+                .op(insert(&text, Key::head(), "a"))
+                .op(insert(&text, after(&alice.op_id_at(2)), "b"))
+                .build(),
+        )
+        .unwrap();
+    let first = first.hash;
+
+    // bob deletes "a" and appends "c"
+    let mut other = Backend::new();
+    other
+        .apply_changes(vec![backend.get_change_by_hash(&first).unwrap().clone()])
+        .unwrap();
+    let (_, second) = other
+        .apply_local_change(
+            change(&bob)
+                .start_op(4)
+                .time(20)
+                .deps(vec![first])
+                .op(insert(&text, after(&alice.op_id_at(3)), "c"))
+                .op(op(
+                    OpType::Del(NonZeroU32::new(1).unwrap()),
+                    &text,
+                    after(&alice.op_id_at(2)),
+                    false,
+                    vec![alice.op_id_at(2)],
+                ))
+                .build(),
+        )
+        .unwrap();
+    let second = second.clone();
+    backend.apply_changes(vec![second.clone()]).unwrap();
+    let second = second.hash;
+
+    let summary = |heads_before: &[amp::ChangeHash], heads_after: &[amp::ChangeHash]| {
+        backend
+            .attribute(&text, heads_before, heads_after)
+            .unwrap()
+            .into_iter()
+            .map(|a| (a.element, a.actor, a.change, a.added))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        summary(&[first], &[second]),
+        vec![
+            (alice.op_id_at(3).into(), alice.clone(), first, false),
+            (bob.op_id_at(4).into(), bob.clone(), second, true),
+        ]
+    );
+    assert_eq!(
+        summary(&[], &[first]),
+        vec![
+            (alice.op_id_at(2).into(), alice.clone(), first, true),
+            (alice.op_id_at(3).into(), alice, first, true),
+        ]
+    );
+
+    assert!(matches!(
+        backend.attribute(&ObjectId::Root, &[], &[second]),
+        Err(AutomergeError::NotASequence(ObjectId::Root))
+    ));
+}
+
+#[test]
+fn test_orphaned_cursors() {
+    let actor: ActorId = "7b7723afd9e6480397a4d467b7693156".try_into().unwrap();
+    let maintenance: ActorId = "9f17f3a4c2e54bd1a0a54c37e0f0c1d2".try_into().unwrap();
+    let root = ObjectId::Root;
+    let text = ObjectId::Id(actor.op_id_at(1));
+    let delete = |elem: amp::OpId| {
+        op(
+            OpType::Del(NonZeroU32::new(1).unwrap()),
+            &text,
+            after(&elem),
+            false,
+            vec![elem],
+        )
+    };
+    let mut backend = Backend::new();
+    let c1 = change(&actor)
+        .op(make(&root, "text", ObjType::Text))
+        .op(insert(&text, Key::head(), "a"))
+        .op(insert(&text, after(&actor.op_id_at(2)), "b"))
+        .op(insert(&text, after(&actor.op_id_at(3)), "c"))
+        .op(set(
+            &root,
+            "cursor",
+            ScalarValue::Cursor(actor.op_id_at(3)),
+            Vec::new(),
+        ))
+        .encode();
+    backend.apply_changes(vec![c1]).unwrap();
+    assert!(backend.orphaned_cursors().is_empty());
+
+    let c2 = change(&actor)
+        .seq(2)
+        .start_op(6)
+        .deps(backend.get_heads())
+        .op(delete(actor.op_id_at(3)))
+        .encode();
+    backend.apply_changes(vec![c2]).unwrap();
+    let orphans = backend.orphaned_cursors();
+    assert_eq!(orphans.len(), 1);
+    assert_eq!(orphans[0].object, root);
+    assert_eq!(orphans[0].key, "cursor".into());
+    assert_eq!(orphans[0].op_id, actor.op_id_at(5));
+    assert_eq!(orphans[0].sequence, text);
+    assert_eq!(orphans[0].element, actor.op_id_at(3));
+    assert_eq!(orphans[0].nearest, Some((actor.op_id_at(4), 1)));
+
+    let repair = backend.repair_cursors(&maintenance, &orphans, 0).unwrap();
+    backend.apply_changes(vec![repair.into()]).unwrap();
+    assert!(backend.orphaned_cursors().is_empty());
+    let patch = backend.get_patch().unwrap();
+    assert_eq!(
+        patch.diffs.props["cursor"].values().collect::<Vec<_>>(),
+        vec![&Diff::Cursor(CursorDiff {
+            object_id: text.clone(),
+            elem_id: actor.op_id_at(4),
+            index: 1,
+        })]
+    );
+
+    // With nothing left to point at, the cursor is reported but can't be repaired
+    let c3 = change(&actor)
+        .seq(3)
+        .start_op(8)
+        .deps(backend.get_heads())
+        .op(delete(actor.op_id_at(2)))
+        .op(delete(actor.op_id_at(4)))
+        .encode();
+    backend.apply_changes(vec![c3]).unwrap();
+    let orphans = backend.orphaned_cursors();
+    assert_eq!(orphans.len(), 1);
+    assert_eq!(orphans[0].element, actor.op_id_at(4));
+    assert_eq!(orphans[0].nearest, None);
+    assert!(backend.repair_cursors(&maintenance, &orphans, 0).is_none());
+}
+
+/// Set `n` keys, overwriting the keys set by the previous change if there is one
+fn set_keys(
+    actor: &ActorId,
+    seq: u64,
+    start_op: u64,
+    deps: Vec<amp::ChangeHash>,
+    n: u64,
+) -> Change {
+    let ops = (0..n).map(|i| {
+        let pred = if seq == 1 {
+            Vec::new()
+        } else {
+            vec![actor.op_id_at(start_op - n + i)]
+        };
+        set(
+            &ObjectId::Root,
+            format!("key{}", i),
+            format!("value {}", i).as_str(),
+            pred,
+        )
+    });
+    change(actor)
+        .seq(seq)
+        .start_op(start_op)
+        .deps(deps)
+        .ops(ops.collect())
+        .encode()
+}
+
+fn patch_len(patch: &amp::Patch) -> usize {
+    serde_json::to_vec(patch).unwrap().len()
+}
+
+#[test]
+fn test_estimate_is_in_the_right_ballpark() {
+    let actor: ActorId = "7b7723afd9e6480397a4d467b7693156".try_into().unwrap();
+    let first = set_keys(&actor, 1, 1, Vec::new(), 50);
+    let mut backend = Backend::new();
+    let patch = backend.apply_changes(vec![first.clone()]).unwrap();
+
+    let estimate = backend.estimate_patch_size(&[]);
+    assert_eq!(estimate.changes, 1);
+    assert_eq!(estimate.ops, 50);
+    assert_eq!(estimate.change_bytes, first.raw_bytes().len());
+    let actual = patch_len(&patch);
+    assert!(
+        estimate.patch_bytes > actual / 2 && estimate.patch_bytes < actual * 2,
+        "estimated {} bytes but the patch is {} bytes",
+        estimate.patch_bytes,
+        actual
+    );
+}
+
+#[test]
+fn test_estimate_since_heads_counts_missing_changes() {
+    let actor: ActorId = "7b7723afd9e6480397a4d467b7693156".try_into().unwrap();
+    let first = set_keys(&actor, 1, 1, Vec::new(), 50);
+    let second = set_keys(&actor, 2, 51, vec![first.hash], 50);
+    let mut backend = Backend::new();
+    backend.apply_changes(vec![first.clone(), second]).unwrap();
+
+    // a peer which has nothing needs the current state, which has 50 keys
+    assert_eq!(backend.estimate_patch_size(&[]).ops, 50);
+    assert_eq!(backend.estimate_patch_size(&[]).changes, 2);
+
+    // a peer which has the first change needs the 50 ops of the second
+    let since_first = backend.estimate_patch_size(&[first.hash]);
+    assert_eq!(since_first.changes, 1);
+    assert_eq!(since_first.ops, 50);
+
+    let up_to_date = backend.estimate_patch_size(&backend.get_heads());
+    assert_eq!(up_to_date.changes, 0);
+    assert_eq!(up_to_date.ops, 0);
+    assert_eq!(up_to_date.patch_bytes, 0);
+}
@@ -1,4 +1,17 @@
-use automerge_backend::Backend;
+use std::{convert::TryInto, num::NonZeroU32};
+
+use automerge_backend::{
+    AutomergeError, Backend, Bytes, Change, ChangeHasher, ChangeStore, CompactOptions,
+    CompactionError, DecodingError, DirChangeStore, LoadPhase, MemoryChangeStore, Sha2,
+};
+use automerge_protocol::{
+    ActorId, Diff, DiffEdit, Key, MarkData, MarkDiff, MarkSpan, ObjType, ObjectId, OpType,
+    ScalarValue, TextDiff,
+};
+use pretty_assertions::assert_eq;
+
+mod common;
+use common::{after, change, make, op, seq_change, set, set_key};
 
 #[test]
 fn test_load_index_out_of_bounds() {
@@ -50,3 +63,758 @@ fn test_load_overflowing_sub() {
     ];
     let _ = Backend::load(bytes);
 }
+
+fn example_change() -> Change {
+    let actor: ActorId = "7b7723afd9e6480397a4d467b7693156".try_into().unwrap();
+    let list: ObjectId = actor.op_id_at(1).into();
+    let birds = vec![
+        ScalarValue::Str("chaffinch".into()),
+        ScalarValue::Str("goldfinch".into()),
+    ];
+    change(&actor)
+        .message("birds")
+        .op(make(&ObjectId::Root, "birds", ObjType::List))
+        .op(op(
+            OpType::MultiSet(birds.try_into().unwrap()),
+            &list,
+            Key::head(),
+            true,
+            Vec::new(),
+        ))
+        .op(op(
+            OpType::Del(NonZeroU32::new(1).unwrap()),
+            &list,
+            after(&actor.op_id_at(2)),
+            false,
+            vec![actor.op_id_at(2)],
+        ))
+        .op(set(
+            &ObjectId::Root,
+            "sightings",
+            ScalarValue::Counter(3),
+            Vec::new(),
+        ))
+        .op(op(
+            OpType::Inc(2),
+            &ObjectId::Root,
+            "sightings".into(),
+            false,
+            vec![actor.op_id_at(5)],
+        ))
+        .encode()
+}
+
+#[test]
+fn test_lenient_decode_of_valid_change() {
+    let change = example_change();
+    let decoded = Change::from_bytes_lenient(change.raw_bytes().to_vec()).unwrap();
+    assert_eq!(decoded, change);
+}
+
+#[test]
+fn test_lenient_decode_collects_errors() {
+    let change = example_change();
+    let mut bytes = change.raw_bytes().to_vec();
+    // the operation columns are at the end of the chunk, mangle the last of them
+    let last = bytes.len() - 1;
+    bytes[last] = 0x80;
+
+    match Change::from_bytes_lenient(bytes.clone()) {
+        Err(DecodingError::Multiple(errors)) => {
+            assert!(matches!(errors[0], DecodingError::InvalidChecksum { .. }));
+            assert!(errors[1..].iter().any(|e| matches!(
+                e,
+                DecodingError::CorruptColumn { .. } | DecodingError::ColumnLength { .. }
+            )));
+        }
+        other => panic!("expected multiple errors, got {:?}", other),
+    }
+
+    // the strict decoder stops at the first problem
+    assert!(matches!(
+        Change::from_bytes(bytes),
+        Err(DecodingError::InvalidChecksum { .. })
+    ));
+}
+
+#[test]
+fn test_load_error_reports_chunk() {
+    let change = example_change();
+    let mut bytes = change.raw_bytes().to_vec();
+    let first_len = bytes.len();
+    let mut corrupt = change.raw_bytes().to_vec();
+    corrupt[4] ^= 0xff;
+    bytes.extend(corrupt);
+
+    match Backend::load(bytes) {
+        Err(AutomergeError::DecodingError(DecodingError::InChunk { index, offset, .. })) => {
+            assert_eq!(index, 1);
+            assert_eq!(offset, first_len);
+        }
+        other => panic!("expected an error in the second chunk, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_lenient_decode_of_run_of_i64_min() {
+    let change = example_change();
+    let mut bytes = change.raw_bytes().to_vec();
+    // the key column ends with the run of the two "sightings" keys, replace its length with
+    // i64::MIN as a signed LEB128
+    let key = b"\x09sightings";
+    let run = bytes
+        .windows(key.len())
+        .position(|window| window == key)
+        .unwrap()
+        - 1;
+    assert_eq!(bytes[run], 2);
+    bytes[run..run + 10]
+        .copy_from_slice(&[0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x7f]);
+
+    match Change::from_bytes_lenient(bytes) {
+        Err(DecodingError::Multiple(errors)) => assert!(errors[1..].iter().any(|e| matches!(
+            e,
+            DecodingError::CorruptColumn { .. } | DecodingError::ColumnLength { .. }
+        ))),
+        other => panic!("expected multiple errors, got {:?}", other),
+    }
+}
+
+/// Make the `seq`th change of `actor` to `backend`, setting "key" to `seq`
+fn set_local_key(backend: &mut Backend, actor: &ActorId, seq: u64) {
+    let change = seq_change(actor, seq, "key", seq as i64)
+        .deps(backend.get_heads())
+        .build();
+    backend.apply_local_change(change).unwrap();
+}
+
+fn load_with_progress(data: &[u8]) -> (Backend, Vec<(LoadPhase, usize, usize)>) {
+    let mut events = Vec::new();
+    let backend = Backend::load_with_progress(data, |phase, done, total| {
+        events.push((phase, done, total));
+    })
+    .unwrap();
+    (backend, events)
+}
+
+fn last(events: &[(LoadPhase, usize, usize)], phase: LoadPhase) -> Option<(usize, usize)> {
+    events
+        .iter()
+        .rev()
+        .find(|(p, _, _)| *p == phase)
+        .map(|(_, done, total)| (*done, *total))
+}
+
+#[test]
+fn test_reports_every_phase() {
+    let mut backend = Backend::new();
+    let actor = ActorId::random();
+    for seq in 1..=5 {
+        set_local_key(&mut backend, &actor, seq);
+    }
+    let data = backend.save().unwrap();
+    let (loaded, events) = load_with_progress(&data);
+    assert_eq!(loaded.get_heads(), backend.get_heads());
+
+    assert_eq!(events.first(), Some(&(LoadPhase::Decode, 0, data.len())));
+    assert_eq!(
+        last(&events, LoadPhase::Decode),
+        Some((data.len(), data.len()))
+    );
+    assert_eq!(last(&events, LoadPhase::Verify), Some((5, 5)));
+    assert_eq!(events.last(), Some(&(LoadPhase::Apply, 5, 5)));
+
+    // applying comes after decoding, and each phase only moves forwards
+    let first_apply = events
+        .iter()
+        .position(|(phase, _, _)| *phase == LoadPhase::Apply)
+        .unwrap();
+    assert!(events[first_apply..]
+        .iter()
+        .all(|(phase, _, _)| *phase == LoadPhase::Apply));
+    for phase in [LoadPhase::Decode, LoadPhase::Verify, LoadPhase::Apply] {
+        let done: Vec<_> = events
+            .iter()
+            .filter(|(p, _, _)| *p == phase)
+            .map(|(_, done, _)| *done)
+            .collect();
+        assert!(done.windows(2).all(|w| w[0] <= w[1]), "{:?}", phase);
+    }
+}
+
+#[test]
+fn test_change_chunks_are_decoded_one_by_one() {
+    let mut backend = Backend::new();
+    let actor = ActorId::random();
+    let mut data = Vec::new();
+    for seq in 1..=3 {
+        set_local_key(&mut backend, &actor, seq);
+        data.extend(backend.save_incremental().unwrap());
+    }
+    let (loaded, events) = load_with_progress(&data);
+    assert_eq!(loaded.get_heads(), backend.get_heads());
+
+    let decoded: Vec<_> = events
+        .iter()
+        .filter(|(phase, _, _)| *phase == LoadPhase::Decode)
+        .collect();
+    // the start and one event per change chunk
+    assert_eq!(decoded.len(), 4);
+    // only document chunks have a verify phase
+    assert_eq!(last(&events, LoadPhase::Verify), None);
+    assert_eq!(last(&events, LoadPhase::Apply), Some((3, 3)));
+}
+
+#[test]
+fn test_incremental_changes_are_not_copied() {
+    let actor = ActorId::random();
+    let mut backend = Backend::new();
+    set_local_key(&mut backend, &actor, 1);
+    let mut saved = backend.save().unwrap();
+    set_local_key(&mut backend, &actor, 2);
+    set_local_key(&mut backend, &actor, 3);
+    saved.extend(backend.save_incremental().unwrap());
+
+    let buffer = Bytes::from(saved.clone());
+    let loaded = Backend::load_bytes(buffer.clone()).unwrap();
+    assert_eq!(loaded.get_heads(), backend.get_heads());
+    assert_eq!(
+        loaded.get_patch().unwrap(),
+        Backend::load(saved).unwrap().get_patch().unwrap()
+    );
+
+    let within_buffer = |change: &Change| {
+        let start = change.raw_bytes().as_ptr() as usize;
+        let buffer_start = buffer.as_ptr() as usize;
+        start >= buffer_start && start < buffer_start + buffer.len()
+    };
+    let changes = loaded.get_changes(&[]);
+    // the first change was in the document chunk, so it was rebuilt
+    assert!(!within_buffer(changes[0]));
+    assert!(within_buffer(changes[1]));
+    assert!(within_buffer(changes[2]));
+}
+
+#[test]
+fn test_change_from_shared_bytes() {
+    let actor = ActorId::random();
+    let mut backend = Backend::new();
+    set_local_key(&mut backend, &actor, 1);
+    let change = backend.get_changes(&[])[0];
+    let bytes = Bytes::copy_from_slice(change.raw_bytes());
+
+    let decoded = Change::from_shared_bytes(bytes.clone()).unwrap();
+    assert_eq!(decoded.hash, change.hash);
+    assert_eq!(decoded.decode(), change.decode());
+    assert_eq!(decoded.raw_bytes().as_ptr(), bytes.as_ptr());
+}
+
+#[test]
+fn test_appending_incremental_saves() {
+    let actor: ActorId = "7b7723afd9e6480397a4d467b7693156".try_into().unwrap();
+    let first = set_key(&actor, 1, Vec::new(), "a");
+    let second = set_key(&actor, 2, vec![first.hash], "b");
+    let third = set_key(&actor, 3, vec![second.hash], "c");
+
+    let mut backend = Backend::new();
+    backend.apply_changes(vec![first]).unwrap();
+    let mut file = backend.save().unwrap();
+    assert!(backend.save_incremental().unwrap().is_empty());
+
+    backend.apply_changes(vec![second.clone()]).unwrap();
+    let delta = backend.save_incremental().unwrap();
+    assert_eq!(delta, second.raw_bytes());
+    file.extend(delta);
+    backend.apply_changes(vec![third]).unwrap();
+    file.extend(backend.save_incremental().unwrap());
+    assert!(backend.save_incremental().unwrap().is_empty());
+
+    let loaded = Backend::load(file).unwrap();
+    assert_eq!(loaded.get_heads(), backend.get_heads());
+    assert_eq!(loaded.get_patch().unwrap(), backend.get_patch().unwrap());
+}
+
+#[test]
+fn test_load_incremental() {
+    let actor: ActorId = "7b7723afd9e6480397a4d467b7693156".try_into().unwrap();
+    let first = set_key(&actor, 1, Vec::new(), "a");
+    let second = set_key(&actor, 2, vec![first.hash], "b");
+
+    let mut backend = Backend::new();
+    backend.apply_changes(vec![first]).unwrap();
+    let saved = backend.save().unwrap();
+    backend.apply_changes(vec![second]).unwrap();
+    let delta = backend.save_incremental().unwrap();
+
+    let mut other = Backend::load(saved.clone()).unwrap();
+    let patch = other.load_incremental(&delta).unwrap();
+    assert_eq!(patch.clock[&actor], 2);
+    assert_eq!(other.get_patch().unwrap(), backend.get_patch().unwrap());
+    // nothing new to save, the changes came from storage
+    assert!(other.save_incremental().unwrap().is_empty());
+
+    // loading changes we already have is a no-op
+    other.load_incremental(&saved).unwrap();
+    assert_eq!(other.get_heads(), backend.get_heads());
+}
+
+fn forks() -> (Backend, Backend) {
+    let actor: ActorId = "7b7723afd9e6480397a4d467b7693156".try_into().unwrap();
+    let other: ActorId = "37704788917a499cb0206fa8519ac4d9".try_into().unwrap();
+    let first = set_key(&actor, 1, Vec::new(), "a");
+    let second = set_key(&actor, 2, vec![first.hash], "b");
+
+    let mut left = Backend::new();
+    left.apply_changes(vec![first.clone(), second.clone()])
+        .unwrap();
+    let mut right = left.clone();
+    left.apply_changes(vec![set_key(&actor, 3, vec![second.hash], "c")])
+        .unwrap();
+    right
+        .apply_changes(vec![set_key(&other, 1, vec![second.hash], "d")])
+        .unwrap();
+    (left, right)
+}
+
+#[test]
+fn test_forks_share_history_in_store() {
+    let (left, right) = forks();
+    let mut store = MemoryChangeStore::new();
+    let left_heads = left.save_to_store(&mut store).unwrap();
+    let right_heads = right.save_to_store(&mut store).unwrap();
+    // two shared changes plus one of each fork's own
+    assert_eq!(store.len(), 4);
+
+    let loaded = Backend::load_from_store(&store, &left_heads).unwrap();
+    assert_eq!(loaded.get_heads(), left.get_heads());
+    assert_eq!(loaded.get_patch().unwrap(), left.get_patch().unwrap());
+    let loaded = Backend::load_from_store(&store, &right_heads).unwrap();
+    assert_eq!(loaded.get_heads(), right.get_heads());
+
+    assert!(matches!(
+        Backend::load_from_store(&MemoryChangeStore::new(), &left_heads),
+        Err(AutomergeError::UnknownChanges(_))
+    ));
+}
+
+#[test]
+fn test_dir_change_store() {
+    let dir = std::env::temp_dir().join(format!("automerge-store-{}", std::process::id()));
+    let (left, right) = forks();
+    let mut store = DirChangeStore::open(&dir).unwrap();
+    let left_heads = left.save_to_store(&mut store).unwrap();
+    right.save_to_store(&mut store).unwrap();
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 4);
+    assert!(store.contains(&left_heads[0]).unwrap());
+
+    let loaded = Backend::load_from_store(&store, &left_heads).unwrap();
+    assert_eq!(loaded.get_heads(), left.get_heads());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "unverified-load")]
+fn nuthatch(actor: &str) -> Change {
+    let actor: ActorId = actor.try_into().unwrap();
+    change(&actor)
+        .op(set(&ObjectId::Root, "bird", "nuthatch", Vec::new()))
+        .encode()
+}
+
+#[cfg(feature = "unverified-load")]
+#[test]
+fn test_load_unverified_document() {
+    let mut backend = Backend::new();
+    backend
+        .apply_changes(vec![nuthatch("7b7723afd9e6480397a4d467b7693156")])
+        .unwrap();
+    let mut saved = backend.save().unwrap();
+    // break the checksum of the document chunk, the heads still match the content
+    saved[4] = saved[4].wrapping_add(1);
+    assert!(Backend::load(saved.clone()).is_err());
+
+    let loaded = Backend::load_unverified(&saved).unwrap();
+    assert_eq!(loaded.get_heads(), backend.get_heads());
+    assert!(!loaded.is_verified());
+    loaded.verify_all().unwrap();
+    assert!(loaded.is_verified());
+}
+
+#[cfg(feature = "unverified-load")]
+#[test]
+fn test_corrupt_changes_are_found_on_save() {
+    let change = nuthatch("7b7723afd9e6480397a4d467b7693156");
+    let mut bytes = change.raw_bytes().to_vec();
+    bytes[4] = bytes[4].wrapping_add(1);
+    assert!(Backend::load(bytes.clone()).is_err());
+
+    let loaded = Backend::load_unverified(&bytes).unwrap();
+    assert_eq!(loaded.get_heads(), vec![change.hash]);
+    match loaded.save() {
+        Err(AutomergeError::CorruptChange { hash, .. }) => assert_eq!(hash, change.hash),
+        other => panic!("expected a corrupt change, got {:?}", other),
+    }
+    assert!(loaded.verify_all().is_err());
+    assert!(!loaded.is_verified());
+}
+
+#[cfg(feature = "unverified-load")]
+#[test]
+fn test_changes_are_verified_lazily() {
+    let good = nuthatch("37704788917a499cb0206fa8519ac4d9");
+    let bad = nuthatch("7b7723afd9e6480397a4d467b7693156");
+    let mut bytes = good.raw_bytes().to_vec();
+    bytes.extend(bad.raw_bytes());
+    let bad_start = good.raw_bytes().len();
+    bytes[bad_start + 4] = bytes[bad_start + 4].wrapping_add(1);
+
+    let mut loaded = Backend::load_unverified(&bytes).unwrap();
+    assert!(loaded.get_changes_by_hashes(&[good.hash]).is_ok());
+    assert!(!loaded.is_verified());
+    assert!(matches!(
+        loaded.get_changes_by_hashes(&[bad.hash]),
+        Err(AutomergeError::CorruptChange { .. })
+    ));
+
+    // receiving an intact copy of the change vouches for the one we have
+    loaded.apply_changes(vec![bad]).unwrap();
+    assert!(loaded.is_verified());
+    loaded.verify_all().unwrap();
+}
+
+fn backend_with_change() -> Backend {
+    let actor: ActorId = "7b7723afd9e6480397a4d467b7693156".try_into().unwrap();
+    let robin = change(&actor)
+        .op(set(&ObjectId::Root, "bird", "robin", Vec::new()))
+        .encode();
+    let mut backend = Backend::new();
+    backend.apply_changes(vec![robin]).unwrap();
+    backend
+}
+
+#[test]
+fn test_documents_without_features_are_unchanged() {
+    let backend = backend_with_change();
+    let saved = backend.save().unwrap();
+    let loaded = Backend::load(saved.clone()).unwrap();
+    assert!(loaded.required_features().is_empty());
+    assert_eq!(loaded.save().unwrap(), saved);
+}
+
+#[test]
+fn test_unsupported_features_fail_to_load() {
+    let mut backend = backend_with_change();
+    backend.require_feature("app:comments");
+    let saved = backend.save().unwrap();
+
+    match Backend::load(saved.clone()) {
+        Err(AutomergeError::UnsupportedFeatures(features)) => {
+            assert_eq!(features, vec!["app:comments".to_string()])
+        }
+        other => panic!("expected unsupported features, got {:?}", other),
+    }
+    assert!(matches!(
+        Change::load_document(&saved),
+        Err(AutomergeError::UnsupportedFeatures(_))
+    ));
+
+    let loaded = Backend::load_with_features(&saved, &["app:comments"]).unwrap();
+    assert_eq!(loaded.get_heads(), backend.get_heads());
+    assert!(loaded.required_features().contains("app:comments"));
+    // the requirement survives another save
+    assert_eq!(loaded.save().unwrap(), saved);
+}
+
+#[test]
+fn test_huge_feature_count_is_an_error() {
+    // a features chunk claiming 2^60 features and containing none of them
+    let mut body = Vec::new();
+    leb128::write::unsigned(&mut body, 1 << 60).unwrap();
+    let mut chunk = vec![0x85, 0x6f, 0x4a, 0x83, 0, 0, 0, 0, 3];
+    leb128::write::unsigned(&mut chunk, body.len() as u64).unwrap();
+    chunk.extend(&body);
+    let hash = Sha2.sha256(&chunk[8..]);
+    chunk[4..8].copy_from_slice(&hash[..4]);
+
+    assert!(Backend::load(chunk).is_err());
+}
+
+/// A backend with a text object holding a few pages of text, typed in one change
+#[cfg(feature = "zstd")]
+fn backend_with_text() -> Backend {
+    use common::insert;
+
+    let actor = ActorId::random();
+    let text = ObjectId::from(actor.op_id_at(1));
+    // words picked by a linear congruential generator, so the text doesn't just repeat
+    let words = [
+        "magpie", "wren", "over", "the", "hedge", "and", "a", "robin", "sings",
+    ];
+    let mut state = 17_u64;
+    let typed: String = (0..2000)
+        .map(|_| {
+            state = state
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1);
+            words[(state >> 33) as usize % words.len()]
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+    let mut typing = change(&actor).op(make(&ObjectId::Root, "text", ObjType::Text));
+    let mut key = Key::head();
+    for (i, c) in typed.chars().enumerate() {
+        typing = typing.op(insert(&text, key, c.to_string().as_str()));
+        key = after(&actor.op_id_at(i as u64 + 2));
+    }
+    let mut backend = Backend::new();
+    backend.apply_changes(vec![typing.encode()]).unwrap();
+    backend
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn test_zstd_columns_round_trip() {
+    let backend = backend_with_text();
+    let deflated = backend.save().unwrap();
+    let zstd = backend.save_zstd().unwrap();
+    assert!(
+        zstd.len() < deflated.len(),
+        "zstd: {} bytes, DEFLATE: {} bytes",
+        zstd.len(),
+        deflated.len()
+    );
+
+    let loaded = Backend::load(zstd).unwrap();
+    assert_eq!(loaded.get_heads(), backend.get_heads());
+    assert_eq!(loaded.get_patch().unwrap(), backend.get_patch().unwrap());
+    // the feature belongs to the file, so saving normally doesn't require zstd
+    assert!(loaded.required_features().is_empty());
+    assert_eq!(loaded.save().unwrap(), deflated);
+}
+
+#[cfg(not(feature = "zstd"))]
+#[test]
+fn test_zstd_columns_are_unsupported_without_zstd() {
+    use automerge_backend::ZSTD_COLUMNS_FEATURE;
+
+    let mut backend = backend_with_change();
+    backend.require_feature(ZSTD_COLUMNS_FEATURE);
+    let saved = backend.save().unwrap();
+    match Backend::load_with_features(&saved, &[ZSTD_COLUMNS_FEATURE]) {
+        Err(AutomergeError::UnsupportedFeatures(features)) => {
+            assert_eq!(features, vec![ZSTD_COLUMNS_FEATURE.to_string()])
+        }
+        other => panic!("expected unsupported features, got {:?}", other),
+    }
+}
+
+fn marks(backend: &Backend) -> Vec<MarkSpan> {
+    let patch = backend.get_patch().unwrap();
+    match patch.diffs.props["text"].values().next() {
+        Some(Diff::Text(TextDiff { edits, .. })) => match edits.last() {
+            Some(DiffEdit::Marks(MarkDiff { marks })) => marks.clone(),
+            other => panic!("expected a marks edit, got {:?}", other),
+        },
+        other => panic!("expected a text diff, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_compact() {
+    let a: ActorId = "7b7723afd9e6480397a4d467b7693156".try_into().unwrap();
+    let b: ActorId = "9f17f3a4c2e54bd1a0a54c37e0f0c1d2".try_into().unwrap();
+    let list = ObjectId::Id(a.op_id_at(1));
+    let text = ObjectId::Id(a.op_id_at(5));
+    let root = ObjectId::Root;
+
+    let c1 = change(&a)
+        .ops(vec![
+            op(
+                OpType::Make(ObjType::List),
+                &root,
+                "items".into(),
+                false,
+                vec![],
+            ),
+            op(OpType::Set("x".into()), &list, Key::head(), true, vec![]),
+            op(
+                OpType::Set("y".into()),
+                &list,
+                after(&a.op_id_at(2)),
+                true,
+                vec![],
+            ),
+            op(
+                OpType::Set(ScalarValue::Counter(5)),
+                &root,
+                "count".into(),
+                false,
+                vec![],
+            ),
+            op(
+                OpType::Make(ObjType::Text),
+                &root,
+                "text".into(),
+                false,
+                vec![],
+            ),
+            op(OpType::Set("h".into()), &text, Key::head(), true, vec![]),
+            op(
+                OpType::Set("i".into()),
+                &text,
+                after(&a.op_id_at(6)),
+                true,
+                vec![],
+            ),
+        ])
+        .encode();
+    let c2 = change(&b)
+        .start_op(8)
+        .deps(vec![c1.hash])
+        .ops(vec![
+            op(
+                OpType::Set("b".into()),
+                &root,
+                "title".into(),
+                false,
+                vec![],
+            ),
+            op(
+                OpType::Del(1.try_into().unwrap()),
+                &list,
+                after(&a.op_id_at(2)),
+                false,
+                vec![a.op_id_at(2)],
+            ),
+        ])
+        .encode();
+    let c3 = change(&a)
+        .seq(2)
+        .start_op(8)
+        .deps(vec![c1.hash])
+        .ops(vec![
+            op(
+                OpType::Set("a".into()),
+                &root,
+                "title".into(),
+                false,
+                vec![],
+            ),
+            op(
+                OpType::MarkBegin(MarkData {
+                    name: "bold".into(),
+                    value: true.into(),
+                }),
+                &text,
+                Key::head(),
+                true,
+                vec![],
+            ),
+            op(
+                OpType::MarkEnd(a.op_id_at(9)),
+                &text,
+                after(&a.op_id_at(6)),
+                true,
+                vec![],
+            ),
+        ])
+        .encode();
+    let c4 = change(&a)
+        .seq(3)
+        .start_op(11)
+        .deps(vec![c2.hash, c3.hash])
+        .ops(vec![
+            op(
+                OpType::Set("merged".into()),
+                &root,
+                "title".into(),
+                false,
+                vec![a.op_id_at(8), b.op_id_at(8)],
+            ),
+            op(
+                OpType::Inc(2),
+                &root,
+                "count".into(),
+                false,
+                vec![a.op_id_at(4)],
+            ),
+            op(
+                OpType::Set("!".into()),
+                &text,
+                after(&a.op_id_at(7)),
+                true,
+                vec![],
+            ),
+            op(
+                OpType::Set("z".into()),
+                &list,
+                after(&a.op_id_at(3)),
+                false,
+                vec![a.op_id_at(3)],
+            ),
+        ])
+        .encode();
+    let c5 = change(&b)
+        .seq(2)
+        .start_op(15)
+        .deps(vec![c4.hash])
+        .ops(vec![op(
+            OpType::Set("w".into()),
+            &list,
+            after(&a.op_id_at(3)),
+            true,
+            vec![],
+        )])
+        .encode();
+
+    let mut backend = Backend::new();
+    backend
+        .apply_changes(vec![c1, c2.clone(), c3.clone()])
+        .unwrap();
+    let baseline = backend.get_heads();
+    backend.apply_changes(vec![c4.clone(), c5.clone()]).unwrap();
+    let document = backend.value_at(&root, &[], None).unwrap();
+    let bold = marks(&backend);
+    assert_eq!(bold.len(), 1);
+
+    let compacted = backend
+        .compact(CompactOptions {
+            baseline: Some(baseline.clone()),
+            save: true,
+            ..CompactOptions::default()
+        })
+        .unwrap();
+    assert_eq!(compacted.discarded_heads, baseline);
+    assert_eq!(compacted.rewritten.len(), 2);
+    assert_eq!(
+        compacted.backend.get_heads(),
+        vec![compacted.rewritten[&c5.hash]]
+    );
+    assert_eq!(compacted.backend.get_changes(&[]).len(), 3);
+    assert_eq!(
+        compacted.backend.value_at(&root, &[], None).unwrap(),
+        document
+    );
+    assert_eq!(marks(&compacted.backend), bold);
+
+    let loaded = Backend::load(compacted.saved.unwrap()).unwrap();
+    assert_eq!(loaded.value_at(&root, &[], None).unwrap(), document);
+
+    // Compacting everything leaves just the snapshot
+    let everything = backend.compact(CompactOptions::default()).unwrap();
+    assert_eq!(everything.backend.get_heads(), vec![everything.snapshot]);
+    assert!(everything.rewritten.is_empty());
+    assert_eq!(
+        everything.backend.value_at(&root, &[], None).unwrap(),
+        document
+    );
+
+    // c3 doesn't depend on c2, so it can't be rewritten on top of a snapshot of c2
+    let result = backend.compact(CompactOptions {
+        baseline: Some(vec![c2.hash]),
+        ..CompactOptions::default()
+    });
+    assert!(matches!(
+        result,
+        Err(AutomergeError::Compaction(CompactionError::ConcurrentWithBaseline(hash))) if hash == c3.hash
+    ));
+}
@@ -0,0 +1,557 @@
+#[cfg(feature = "sled")]
+use automerge_backend::SledPersister;
+#[cfg(feature = "sqlite")]
+use automerge_backend::SqlitePersister;
+use automerge_backend::{
+    Backend, FilePersister, MemoryPersister, PersistentBackend, Persister, SyncState,
+};
+use automerge_protocol as amp;
+use automerge_protocol::{ActorId, ObjectId};
+
+mod common;
+use common::{change, set};
+
+fn local_change(backend: &Backend, actor: &ActorId, key: &str, value: i64) -> amp::Change {
+    let seq = backend
+        .get_changes_for_actor_id(actor)
+        .map_or(0, |changes| changes.len()) as u64
+        + 1;
+    change(actor)
+        .seq(seq)
+        .start_op(backend.get_changes(&[]).len() as u64 + 1)
+        .op(set(&ObjectId::Root, key, value, Vec::new()))
+        .build()
+}
+
+/// Apply a local change setting "key" to `value` on top of the current heads of `doc`
+fn set_value<P: Persister>(doc: &mut PersistentBackend<P>, actor: &ActorId, value: i64) {
+    let mut change = local_change(doc.backend(), actor, "key", value);
+    change.deps = doc.backend().get_heads();
+    doc.apply_local_change(change).unwrap();
+}
+
+/// Tests which every persister should pass, `$new` makes an empty persister
+macro_rules! persister_tests {
+    ($name:ident, $new:expr) => {
+        mod $name {
+            use super::*;
+
+            #[test]
+            fn test_applied_changes_survive_reloading() {
+                let actor = ActorId::random();
+                let mut doc = PersistentBackend::load($new).unwrap();
+                for i in 0..3 {
+                    let change = local_change(doc.backend(), &actor, &format!("key{}", i), i);
+                    doc.apply_local_change(change).unwrap();
+                }
+                let heads = doc.backend().get_heads();
+                assert_eq!(doc.persister().load_changes().unwrap().len(), 3);
+
+                let (_, persister) = doc.into_inner();
+                let reloaded = PersistentBackend::load(persister).unwrap();
+                assert_eq!(reloaded.backend().get_heads(), heads);
+            }
+
+            #[test]
+            fn test_compact_replaces_changes_with_a_snapshot() {
+                let actor = ActorId::random();
+                let mut doc = PersistentBackend::load($new).unwrap();
+                let change = local_change(doc.backend(), &actor, "a", 1);
+                doc.apply_local_change(change).unwrap();
+                doc.compact().unwrap();
+                assert!(doc.persister().load_changes().unwrap().is_empty());
+                assert!(doc.persister().load_snapshot().unwrap().is_some());
+
+                let change = local_change(doc.backend(), &actor, "b", 2);
+                doc.apply_local_change(change).unwrap();
+                assert_eq!(doc.persister().load_changes().unwrap().len(), 1);
+
+                let heads = doc.backend().get_heads();
+                let (_, persister) = doc.into_inner();
+                let reloaded = PersistentBackend::load(persister).unwrap();
+                assert_eq!(reloaded.backend().get_heads(), heads);
+                assert_eq!(reloaded.backend().get_changes(&[]).len(), 2);
+            }
+
+            #[test]
+            fn test_duplicate_changes_are_stored_once() {
+                let mut source = Backend::new();
+                let actor = ActorId::random();
+                let change = local_change(&source, &actor, "a", 1);
+                source.apply_local_change(change).unwrap();
+                let changes: Vec<_> = source.get_changes(&[]).into_iter().cloned().collect();
+
+                let mut doc = PersistentBackend::load($new).unwrap();
+                doc.apply_changes(changes.clone()).unwrap();
+                doc.apply_changes(changes).unwrap();
+                assert_eq!(doc.persister().load_changes().unwrap().len(), 1);
+            }
+
+            #[test]
+            fn test_sync_changes_and_state_are_stored() {
+                let mut source = Backend::new();
+                let actor = ActorId::random();
+                let change = local_change(&source, &actor, "a", 1);
+                source.apply_local_change(change).unwrap();
+
+                let peer = b"source";
+                let mut doc = PersistentBackend::load($new).unwrap();
+                let mut doc_state = doc.sync_state(peer).unwrap();
+                let mut source_state = SyncState::default();
+                loop {
+                    let to_doc = source.generate_sync_message(&mut source_state);
+                    let to_source = doc.generate_sync_message(&mut doc_state);
+                    if to_doc.is_none() && to_source.is_none() {
+                        break;
+                    }
+                    if let Some(message) = to_doc {
+                        doc.receive_sync_message(peer, &mut doc_state, message)
+                            .unwrap();
+                    }
+                    if let Some(message) = to_source {
+                        source
+                            .receive_sync_message(&mut source_state, message)
+                            .unwrap();
+                    }
+                }
+                assert_eq!(doc.backend().get_heads(), source.get_heads());
+                assert_eq!(doc.persister().load_changes().unwrap().len(), 1);
+
+                let stored = doc.sync_state(peer).unwrap();
+                assert_eq!(stored.shared_heads, source.get_heads());
+                assert!(doc
+                    .sync_state(b"someone else")
+                    .unwrap()
+                    .shared_heads
+                    .is_empty());
+            }
+
+            #[test]
+            fn test_compacts_after_too_many_changes() {
+                let actor = ActorId::random();
+                let mut doc = PersistentBackend::load($new).unwrap();
+                doc.set_compact_after(Some(2));
+                for i in 0..2 {
+                    let change = local_change(doc.backend(), &actor, "a", i);
+                    doc.apply_local_change(change).unwrap();
+                }
+                assert_eq!(doc.persister().load_changes().unwrap().len(), 2);
+                assert!(doc.persister().load_snapshot().unwrap().is_none());
+
+                let change = local_change(doc.backend(), &actor, "a", 2);
+                doc.apply_local_change(change).unwrap();
+                assert!(doc.persister().load_changes().unwrap().is_empty());
+                assert!(doc.persister().load_snapshot().unwrap().is_some());
+
+                let change = local_change(doc.backend(), &actor, "a", 3);
+                doc.apply_local_change(change).unwrap();
+                let heads = doc.backend().get_heads();
+                let (_, persister) = doc.into_inner();
+                let reloaded = PersistentBackend::load(persister).unwrap();
+                assert_eq!(reloaded.backend().get_heads(), heads);
+            }
+        }
+    };
+}
+
+persister_tests!(memory, MemoryPersister::new());
+#[cfg(feature = "sled")]
+persister_tests!(
+    sled_tree,
+    SledPersister::open(
+        sled::Config::new()
+            .temporary(true)
+            .open()
+            .unwrap()
+            .open_tree("doc")
+            .unwrap(),
+        u64::MAX,
+    )
+    .unwrap()
+);
+
+#[cfg(feature = "sqlite")]
+persister_tests!(
+    sqlite,
+    SqlitePersister::open(
+        rusqlite::Connection::open_in_memory().unwrap(),
+        "doc",
+        u64::MAX
+    )
+    .unwrap()
+);
+
+/// A [`MemoryPersister`] which fails to append changes while `fail` is set
+#[derive(Default)]
+struct FlakyPersister {
+    inner: MemoryPersister,
+    fail: bool,
+}
+
+impl Persister for FlakyPersister {
+    fn append_change(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        if self.fail {
+            return Err(std::io::Error::new(std::io::ErrorKind::Other, "disk full"));
+        }
+        self.inner.append_change(bytes)
+    }
+
+    fn load_changes(&self) -> std::io::Result<Vec<Vec<u8>>> {
+        self.inner.load_changes()
+    }
+
+    fn store_snapshot(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        self.inner.store_snapshot(bytes)
+    }
+
+    fn load_snapshot(&self) -> std::io::Result<Option<Vec<u8>>> {
+        self.inner.load_snapshot()
+    }
+
+    fn store_sync_state(&mut self, peer: &[u8], bytes: &[u8]) -> std::io::Result<()> {
+        self.inner.store_sync_state(peer, bytes)
+    }
+
+    fn load_sync_state(&self, peer: &[u8]) -> std::io::Result<Option<Vec<u8>>> {
+        self.inner.load_sync_state(peer)
+    }
+}
+
+#[test]
+fn test_a_change_which_failed_to_persist_is_persisted_by_the_next_call() {
+    let actor = ActorId::random();
+    let mut doc = PersistentBackend::load(FlakyPersister::default()).unwrap();
+    doc.persister_mut().fail = true;
+    let change = local_change(doc.backend(), &actor, "a", 1);
+    assert!(doc.apply_local_change(change).is_err());
+    assert!(!doc.is_persisted());
+
+    doc.persister_mut().fail = false;
+    let change = local_change(doc.backend(), &actor, "b", 2);
+    doc.apply_local_change(change).unwrap();
+    assert!(doc.is_persisted());
+    assert_eq!(doc.persister().load_changes().unwrap().len(), 2);
+
+    let heads = doc.backend().get_heads();
+    let (_, persister) = doc.into_inner();
+    let reloaded = PersistentBackend::load(persister).unwrap();
+    assert_eq!(reloaded.backend().get_heads(), heads);
+}
+
+#[test]
+fn test_persist_retries_after_a_failure() {
+    let actor = ActorId::random();
+    let mut doc = PersistentBackend::load(FlakyPersister::default()).unwrap();
+    doc.persister_mut().fail = true;
+    let change = local_change(doc.backend(), &actor, "a", 1);
+    assert!(doc.apply_local_change(change).is_err());
+    assert!(doc.persist().is_err());
+
+    doc.persister_mut().fail = false;
+    doc.persist().unwrap();
+    assert!(doc.is_persisted());
+    assert_eq!(doc.persister().load_changes().unwrap().len(), 1);
+}
+
+mod file_persister {
+    use std::{
+        fs::OpenOptions,
+        io::Write,
+        path::{Path, PathBuf},
+    };
+
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "automerge-file-persister-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn open(dir: &Path, max_log_bytes: u64) -> PersistentBackend<FilePersister> {
+        PersistentBackend::load(FilePersister::open(dir, max_log_bytes).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_changes_and_sync_states_survive_reopening() {
+        let dir = temp_dir("reopen");
+        let actor = ActorId::random();
+        let mut doc = open(&dir, u64::MAX);
+        for i in 0..3 {
+            set_value(&mut doc, &actor, i);
+        }
+        let mut state = SyncState::default();
+        doc.generate_sync_message(&mut state);
+        doc.store_sync_state(b"peer", &state).unwrap();
+        let heads = doc.backend().get_heads();
+        drop(doc);
+
+        let doc = open(&dir, u64::MAX);
+        assert_eq!(doc.backend().get_heads(), heads);
+        assert_eq!(doc.persister().load_changes().unwrap().len(), 3);
+        assert_eq!(
+            doc.sync_state(b"peer").unwrap().shared_heads,
+            state.shared_heads
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_log_is_rotated_into_a_snapshot() {
+        let dir = temp_dir("rotate");
+        let actor = ActorId::random();
+        let mut doc = open(&dir, 200);
+        let mut rotated = false;
+        for i in 0..10 {
+            set_value(&mut doc, &actor, i);
+            assert!(doc.persister().log_bytes() <= 300);
+            rotated |= doc.persister().log_bytes() == 0;
+        }
+        assert!(rotated);
+        assert!(dir.join("snapshot").exists());
+        let heads = doc.backend().get_heads();
+        drop(doc);
+
+        let doc = open(&dir, 200);
+        assert_eq!(doc.backend().get_heads(), heads);
+        assert_eq!(doc.backend().get_changes(&[]).len(), 10);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_torn_write_is_truncated() {
+        let dir = temp_dir("torn");
+        let actor = ActorId::random();
+        let mut doc = open(&dir, u64::MAX);
+        set_value(&mut doc, &actor, 1);
+        set_value(&mut doc, &actor, 2);
+        let heads = doc.backend().get_heads();
+        let good_bytes = doc.persister().log_bytes();
+        drop(doc);
+
+        // half of a record, as if the process died while appending it
+        let mut log = OpenOptions::new()
+            .append(true)
+            .open(dir.join("changes.log"))
+            .unwrap();
+        log.write_all(&[200, 0, 0, 0, 1, 2, 3, 4, 5, 6]).unwrap();
+        drop(log);
+
+        let mut doc = open(&dir, u64::MAX);
+        assert_eq!(doc.backend().get_heads(), heads);
+        assert_eq!(doc.persister().log_bytes(), good_bytes);
+        // appending after the truncation still gives a log which loads
+        set_value(&mut doc, &actor, 3);
+        let heads = doc.backend().get_heads();
+        drop(doc);
+        assert_eq!(open(&dir, u64::MAX).backend().get_heads(), heads);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_corrupt_record_is_truncated() {
+        let dir = temp_dir("corrupt");
+        let actor = ActorId::random();
+        let mut doc = open(&dir, u64::MAX);
+        set_value(&mut doc, &actor, 1);
+        let first_record = doc.persister().log_bytes();
+        set_value(&mut doc, &actor, 2);
+        let log_bytes = doc.persister().log_bytes();
+        drop(doc);
+
+        // flip a byte in the second change
+        let path = dir.join("changes.log");
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[log_bytes as usize - 1] ^= 0xff;
+        std::fs::write(&path, bytes).unwrap();
+
+        let doc = open(&dir, u64::MAX);
+        assert_eq!(doc.persister().log_bytes(), first_record);
+        assert_eq!(doc.backend().get_changes(&[]).len(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
+
+#[cfg(feature = "sled")]
+mod sled_persister {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "automerge-sled-persister-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn open(db: &sled::Db, name: &str, max_changes: u64) -> PersistentBackend<SledPersister> {
+        let tree = db.open_tree(name).unwrap();
+        PersistentBackend::load(SledPersister::open(tree, max_changes).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_documents_survive_reopening_the_database() {
+        let dir = temp_dir("reopen");
+        let actor = ActorId::random();
+        let db = sled::open(&dir).unwrap();
+        let mut doc = open(&db, "birds", u64::MAX);
+        let mut other = open(&db, "trees", u64::MAX);
+        for i in 0..3 {
+            set_value(&mut doc, &actor, i);
+        }
+        set_value(&mut other, &actor, 10);
+        let mut state = SyncState::default();
+        doc.generate_sync_message(&mut state);
+        doc.store_sync_state(b"peer", &state).unwrap();
+        let heads = doc.backend().get_heads();
+        drop((doc, other, db));
+
+        let db = sled::open(&dir).unwrap();
+        let mut doc = open(&db, "birds", u64::MAX);
+        assert_eq!(doc.backend().get_heads(), heads);
+        assert_eq!(doc.persister().load_changes().unwrap().len(), 3);
+        assert_eq!(
+            doc.sync_state(b"peer").unwrap().shared_heads,
+            state.shared_heads
+        );
+        assert_eq!(
+            open(&db, "trees", u64::MAX)
+                .backend()
+                .get_changes(&[])
+                .len(),
+            1
+        );
+
+        // changes appended after reopening go after the ones which were there
+        set_value(&mut doc, &actor, 3);
+        let heads = doc.backend().get_heads();
+        drop(doc);
+        assert_eq!(open(&db, "birds", u64::MAX).backend().get_heads(), heads);
+        drop(db);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_asks_for_a_snapshot_after_max_changes() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let actor = ActorId::random();
+        let mut doc = open(&db, "doc", 2);
+        for i in 0..2 {
+            set_value(&mut doc, &actor, i);
+        }
+        assert_eq!(doc.persister().load_changes().unwrap().len(), 2);
+        assert!(doc.persister().load_snapshot().unwrap().is_none());
+
+        set_value(&mut doc, &actor, 2);
+        assert!(doc.persister().load_changes().unwrap().is_empty());
+        assert!(doc.persister().load_snapshot().unwrap().is_some());
+
+        set_value(&mut doc, &actor, 3);
+        let heads = doc.backend().get_heads();
+        drop(doc);
+        let doc = open(&db, "doc", 2);
+        assert_eq!(doc.backend().get_heads(), heads);
+        assert_eq!(doc.persister().load_changes().unwrap().len(), 1);
+    }
+}
+
+#[cfg(feature = "sqlite")]
+mod sqlite_persister {
+    use std::path::{Path, PathBuf};
+
+    use rusqlite::Connection;
+
+    use super::*;
+
+    fn temp_file(name: &str) -> PathBuf {
+        let file = std::env::temp_dir().join(format!(
+            "automerge-sqlite-persister-{}-{}.db",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&file);
+        file
+    }
+
+    fn open(file: &Path, doc: &str, max_changes: u64) -> PersistentBackend<SqlitePersister> {
+        let connection = Connection::open(file).unwrap();
+        PersistentBackend::load(SqlitePersister::open(connection, doc, max_changes).unwrap())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_documents_survive_reopening_the_database() {
+        let file = temp_file("reopen");
+        let actor = ActorId::random();
+        let mut doc = open(&file, "birds", u64::MAX);
+        let mut other = open(&file, "trees", u64::MAX);
+        for i in 0..3 {
+            set_value(&mut doc, &actor, i);
+        }
+        set_value(&mut other, &actor, 10);
+        let mut state = SyncState::default();
+        doc.generate_sync_message(&mut state);
+        doc.store_sync_state(b"peer", &state).unwrap();
+        let heads = doc.backend().get_heads();
+        drop((doc, other));
+
+        let mut doc = open(&file, "birds", u64::MAX);
+        assert_eq!(doc.backend().get_heads(), heads);
+        assert_eq!(doc.persister().load_changes().unwrap().len(), 3);
+        assert_eq!(
+            doc.sync_state(b"peer").unwrap().shared_heads,
+            state.shared_heads
+        );
+        assert_eq!(
+            open(&file, "trees", u64::MAX)
+                .backend()
+                .get_changes(&[])
+                .len(),
+            1
+        );
+
+        // changes appended after reopening go after the ones which were there
+        set_value(&mut doc, &actor, 3);
+        let heads = doc.backend().get_heads();
+        drop(doc);
+        assert_eq!(open(&file, "birds", u64::MAX).backend().get_heads(), heads);
+        std::fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn test_asks_for_a_snapshot_after_max_changes() {
+        let file = temp_file("snapshot");
+        let actor = ActorId::random();
+        let mut doc = open(&file, "doc", 2);
+        let mut other = open(&file, "other", 2);
+        set_value(&mut other, &actor, 10);
+        for i in 0..2 {
+            set_value(&mut doc, &actor, i);
+        }
+        assert_eq!(doc.persister().load_changes().unwrap().len(), 2);
+        assert!(doc.persister().load_snapshot().unwrap().is_none());
+
+        set_value(&mut doc, &actor, 2);
+        assert!(doc.persister().load_changes().unwrap().is_empty());
+        assert!(doc.persister().load_snapshot().unwrap().is_some());
+        // compacting one document leaves the others alone
+        assert_eq!(other.persister().load_changes().unwrap().len(), 1);
+
+        set_value(&mut doc, &actor, 3);
+        let heads = doc.backend().get_heads();
+        drop(doc);
+        let doc = open(&file, "doc", 2);
+        assert_eq!(doc.backend().get_heads(), heads);
+        assert_eq!(doc.persister().load_changes().unwrap().len(), 1);
+        drop((doc, other));
+        std::fs::remove_file(&file).unwrap();
+    }
+}
@@ -0,0 +1,354 @@
+use std::convert::TryInto;
+
+use automerge_backend::{
+    AutomergeError, Backend, Change, QuotaExceeded, Quotas, Rejection, TimeWentBackwards,
+    TimestampPolicy,
+};
+use automerge_protocol as amp;
+use automerge_protocol::{ActorId, Key, ObjType, ObjectId, OpType};
+
+mod common;
+use common::{change, make, op, seq_change, set, ChangeBuilder};
+
+fn set_change(actor: &ActorId, seq: u64, deps: Vec<amp::ChangeHash>, value: &str) -> Change {
+    seq_change(actor, seq, "bird", value).deps(deps).encode()
+}
+
+#[test]
+fn test_rejected_remote_changes_are_quarantined() {
+    let actor: ActorId = "7b7723afd9e6480397a4d467b7693156".try_into().unwrap();
+    let first = set_change(&actor, 1, Vec::new(), "greater spotted woodpecker");
+    let second = set_change(&actor, 2, vec![first.hash], "jay");
+
+    let mut backend = Backend::with_quotas(Quotas {
+        max_value_size: Some(10),
+        ..Quotas::default()
+    });
+    backend
+        .apply_changes(vec![first.clone(), second.clone()])
+        .unwrap();
+
+    assert!(backend.get_heads().is_empty());
+    let quarantined = backend.quarantined();
+    assert_eq!(quarantined.len(), 1);
+    assert_eq!(quarantined[0].change, first);
+    assert!(matches!(
+        quarantined[0].reason,
+        Rejection::Quota(QuotaExceeded::ValueSize { size: 26, .. })
+    ));
+    // the second change is waiting on the first, but the first should not be requested again
+    assert!(backend.get_missing_deps(&[]).is_empty());
+
+    // still rejected under the same quotas
+    backend.retry_quarantined().unwrap();
+    assert_eq!(backend.quarantined().len(), 1);
+
+    backend.set_quotas(Quotas::default());
+    backend.retry_quarantined().unwrap();
+    assert!(backend.quarantined().is_empty());
+    assert_eq!(backend.get_heads(), vec![second.hash]);
+}
+
+#[test]
+fn test_take_quarantined() {
+    let actor: ActorId = "37704788917a499cb0206fa8519ac4d9".try_into().unwrap();
+    let change = set_change(&actor, 1, Vec::new(), "magpie");
+
+    let mut backend = Backend::with_quotas(Quotas {
+        max_value_size: Some(1),
+        ..Quotas::default()
+    });
+    backend.apply_changes(vec![change.clone()]).unwrap();
+    backend.apply_changes(vec![change.clone()]).unwrap();
+
+    let taken = backend.take_quarantined();
+    assert_eq!(taken.len(), 1);
+    assert_eq!(taken[0].change.raw_bytes(), change.raw_bytes());
+    assert!(backend.quarantined().is_empty());
+}
+
+#[test]
+fn test_quarantine_capacity() {
+    let actor: ActorId = "37704788917a499cb0206fa8519ac4d9".try_into().unwrap();
+    let changes: Vec<_> = ["magpie", "jackdaw", "rook"]
+        .iter()
+        .map(|bird| set_change(&actor, 1, Vec::new(), bird))
+        .collect();
+
+    let mut backend = Backend::with_quotas(Quotas {
+        max_value_size: Some(1),
+        ..Quotas::default()
+    });
+    backend.set_quarantine_capacity(2);
+    for change in &changes {
+        backend.apply_changes(vec![change.clone()]).unwrap();
+    }
+
+    // the oldest change made way for the newer ones
+    let hashes: Vec<_> = backend
+        .quarantined()
+        .iter()
+        .map(|q| q.change.hash)
+        .collect();
+    assert_eq!(hashes, vec![changes[1].hash, changes[2].hash]);
+
+    backend.set_quarantine_capacity(1);
+    assert_eq!(backend.quarantined().len(), 1);
+    assert_eq!(backend.quarantined()[0].change.hash, changes[2].hash);
+
+    // an evicted change is quarantined again if it is sent again
+    backend.apply_changes(vec![changes[0].clone()]).unwrap();
+    assert_eq!(backend.quarantined()[0].change.hash, changes[0].hash);
+}
+
+#[test]
+fn test_max_value_size() {
+    let actor: ActorId = "7b7723afd9e6480397a4d467b7693156".try_into().unwrap();
+    let mut backend = Backend::with_quotas(Quotas {
+        max_value_size: Some(4),
+        ..Quotas::default()
+    });
+    let result = backend.apply_local_change(
+        change(&actor)
+            .op(set(&ObjectId::Root, "bird", "magpie", Vec::new()))
+            .build(),
+    );
+    match result {
+        Err(AutomergeError::QuotaExceeded(QuotaExceeded::ValueSize { size, max, .. })) => {
+            assert_eq!((size, max), (6, 4));
+        }
+        other => panic!("unexpected result {:?}", other),
+    }
+    assert!(backend.get_heads().is_empty());
+}
+
+#[test]
+fn test_max_depth() {
+    let actor: ActorId = "37704788917a499cb0206fa8519ac4d9".try_into().unwrap();
+    let nested = |depth: u64| {
+        let ops = (1..=depth).map(|i| {
+            let parent = if i == 1 {
+                ObjectId::Root
+            } else {
+                actor.op_id_at(i - 1).into()
+            };
+            make(&parent, "child", ObjType::Map)
+        });
+        change(&actor).ops(ops.collect()).build()
+    };
+    let quotas = Quotas {
+        max_depth: Some(2),
+        ..Quotas::default()
+    };
+
+    let mut backend = Backend::with_quotas(quotas.clone());
+    backend.apply_local_change(nested(2)).unwrap();
+
+    let mut backend = Backend::with_quotas(quotas);
+    let result = backend.apply_local_change(nested(3));
+    assert!(matches!(
+        result,
+        Err(AutomergeError::QuotaExceeded(QuotaExceeded::Depth {
+            depth: 3,
+            max: 2,
+            ..
+        }))
+    ));
+}
+
+#[test]
+fn test_max_depth_across_changes() {
+    let actor: ActorId = "d5ffb2d5d3b24fda8e3c0c3ff6d0b2f5".try_into().unwrap();
+    let mut backend = Backend::with_quotas(Quotas {
+        max_depth: Some(1),
+        ..Quotas::default()
+    });
+    backend
+        .apply_local_change(
+            change(&actor)
+                .op(make(&ObjectId::Root, "birds", ObjType::List))
+                .build(),
+        )
+        .unwrap();
+
+    let second = change(&actor)
+        .seq(2)
+        .start_op(2)
+        .op(op(
+            OpType::Make(ObjType::Map),
+            &actor.op_id_at(1).into(),
+            Key::head(),
+            true,
+            Vec::new(),
+        ))
+        .build();
+    let result = backend.apply_local_change(second);
+    assert!(matches!(
+        result,
+        Err(AutomergeError::QuotaExceeded(QuotaExceeded::Depth {
+            depth: 2,
+            ..
+        }))
+    ));
+}
+
+#[test]
+fn test_max_ops_per_change() {
+    let actor: ActorId = "7b7723afd9e6480397a4d467b7693156".try_into().unwrap();
+    let mut backend = Backend::with_quotas(Quotas {
+        max_ops_per_change: Some(2),
+        ..Quotas::default()
+    });
+    let ops = ["magpie", "jay", "rook"]
+        .iter()
+        .map(|bird| set(&ObjectId::Root, *bird, *bird, Vec::new()))
+        .collect();
+    let result = backend.apply_local_change(change(&actor).ops(ops).build());
+    assert!(matches!(
+        result,
+        Err(AutomergeError::QuotaExceeded(QuotaExceeded::OpsPerChange {
+            ops: 3,
+            max: 2,
+            ..
+        }))
+    ));
+}
+
+#[test]
+fn test_max_changes_per_second() {
+    let actor: ActorId = "37704788917a499cb0206fa8519ac4d9".try_into().unwrap();
+    let mut backend = Backend::with_quotas(Quotas {
+        max_changes_per_second: Some(1),
+        ..Quotas::default()
+    });
+    let count = |seq: u64| seq_change(&actor, seq, "count", seq as i64);
+    let (_, first) = backend.apply_local_change(count(1).build()).unwrap();
+    let first_hash = first.hash;
+    let result = backend.apply_local_change(count(2).deps(vec![first_hash]).build());
+    assert!(matches!(
+        result,
+        Err(AutomergeError::QuotaExceeded(QuotaExceeded::Rate {
+            max: 1,
+            ..
+        }))
+    ));
+}
+
+/// The `seq`th change of `actor`, made at `time`
+fn timed(actor: &ActorId, seq: u64, time: i64) -> ChangeBuilder {
+    seq_change(actor, seq, "count", seq as i64).time(time)
+}
+
+#[test]
+fn test_unchecked_by_default() {
+    let actor: ActorId = "7b7723afd9e6480397a4d467b7693156".try_into().unwrap();
+    let mut backend = Backend::new();
+    assert_eq!(backend.timestamp_policy(), TimestampPolicy::Unchecked);
+    backend
+        .apply_local_change(timed(&actor, 1, 5_000).build())
+        .unwrap();
+    let (_, applied) = backend
+        .apply_local_change(timed(&actor, 2, 1_000).build())
+        .unwrap();
+    assert_eq!(applied.time, 1_000);
+}
+
+#[test]
+fn test_clamp_local_changes() {
+    let actor: ActorId = "7b7723afd9e6480397a4d467b7693156".try_into().unwrap();
+    let mut backend = Backend::new();
+    backend.set_timestamp_policy(TimestampPolicy::Clamp { tolerance: 100 });
+    backend
+        .apply_local_change(timed(&actor, 1, 5_000).build())
+        .unwrap();
+
+    let (_, applied) = backend
+        .apply_local_change(timed(&actor, 2, 4_950).build())
+        .unwrap();
+    assert_eq!(applied.time, 4_950);
+
+    let (_, applied) = backend
+        .apply_local_change(timed(&actor, 3, 1_000).build())
+        .unwrap();
+    assert_eq!(applied.time, 4_950);
+}
+
+#[test]
+fn test_clamp_leaves_remote_changes_alone() {
+    let actor: ActorId = "37704788917a499cb0206fa8519ac4d9".try_into().unwrap();
+    let first = timed(&actor, 1, 5_000).encode();
+    let second = timed(&actor, 2, 1_000).deps(vec![first.hash]).encode();
+    let mut backend = Backend::new();
+    backend.set_timestamp_policy(TimestampPolicy::Clamp { tolerance: 0 });
+    backend.apply_changes(vec![first, second.clone()]).unwrap();
+    assert_eq!(backend.get_heads(), vec![second.hash]);
+}
+
+#[test]
+fn test_reject_local_changes() {
+    let actor: ActorId = "7b7723afd9e6480397a4d467b7693156".try_into().unwrap();
+    let mut backend = Backend::new();
+    backend.set_timestamp_policy(TimestampPolicy::Reject { tolerance: 100 });
+    backend
+        .apply_local_change(timed(&actor, 1, 5_000).build())
+        .unwrap();
+    backend
+        .apply_local_change(timed(&actor, 2, 4_900).build())
+        .unwrap();
+    let heads = backend.get_heads();
+
+    let result = backend.apply_local_change(timed(&actor, 3, 4_000).build());
+    match result {
+        Err(AutomergeError::TimeWentBackwards(TimeWentBackwards { time, previous, .. })) => {
+            assert_eq!((time, previous), (4_000, 4_900))
+        }
+        other => panic!("unexpected result {:?}", other),
+    }
+    assert_eq!(backend.get_heads(), heads);
+}
+
+#[test]
+fn test_reject_quarantines_remote_changes() {
+    let alice: ActorId = "37704788917a499cb0206fa8519ac4d9".try_into().unwrap();
+    let bob: ActorId = "2a1d376b24f744008d4af58252d644dd".try_into().unwrap();
+    let first = timed(&alice, 1, 5_000).encode();
+    let second = timed(&alice, 2, 1_000).deps(vec![first.hash]).encode();
+    // Other actors' clocks are not compared
+    let other = timed(&bob, 1, 1_000).encode();
+
+    let mut backend = Backend::new();
+    backend.set_timestamp_policy(TimestampPolicy::Reject { tolerance: 0 });
+    backend
+        .apply_changes(vec![first.clone(), second.clone(), other.clone()])
+        .unwrap();
+    let mut heads = vec![first.hash, other.hash];
+    heads.sort();
+    assert_eq!(backend.get_heads(), heads);
+    assert_eq!(backend.quarantined().len(), 1);
+    assert_eq!(backend.quarantined()[0].change.hash, second.hash);
+    assert!(matches!(
+        backend.quarantined()[0].reason,
+        Rejection::Timestamp(_)
+    ));
+
+    backend.set_timestamp_policy(TimestampPolicy::Unchecked);
+    backend.retry_quarantined().unwrap();
+    assert!(backend.quarantined().is_empty());
+}
+
+#[test]
+fn test_negative_times_survive_save_and_load() {
+    let actor: ActorId = "7b7723afd9e6480397a4d467b7693156".try_into().unwrap();
+    let mut backend = Backend::new();
+    let (_, first) = backend
+        .apply_local_change(timed(&actor, 1, -1_000).build())
+        .unwrap();
+    let hash = first.hash;
+    backend
+        .apply_local_change(timed(&actor, 2, i64::MAX).deps(vec![hash]).build())
+        .unwrap();
+
+    let loaded = Backend::load(backend.save().unwrap()).unwrap();
+    let times: Vec<i64> = loaded.get_changes(&[]).iter().map(|c| c.time).collect();
+    assert_eq!(times, vec![-1_000, i64::MAX]);
+}
@@ -0,0 +1,273 @@
+use std::convert::TryInto;
+
+use automerge_backend::{AutomergeError, Backend, Change, PathElement, Value, MOVE_FEATURE};
+use automerge_protocol as amp;
+use automerge_protocol::{ActorId, ElementId, ObjType, ObjectId, OpType};
+
+mod common;
+use common::{change, insert, make, op, seq_change};
+
+fn set_change(actor: &ActorId, seq: u64, deps: Vec<amp::ChangeHash>, value: &str) -> amp::Change {
+    seq_change(actor, seq, "bird", value).deps(deps).build()
+}
+
+fn set_title(actor: &ActorId, seq: u64, title: &str, deps: Vec<amp::ChangeHash>) -> amp::Change {
+    seq_change(actor, seq, "title", title).deps(deps).build()
+}
+
+fn set(actor: &ActorId, seq: u64, deps: Vec<amp::ChangeHash>) -> Change {
+    seq_change(actor, seq, &actor.to_hex_string(), seq)
+        .deps(deps)
+        .encode()
+}
+
+#[test]
+fn test_restore_snapshot() {
+    let actor: ActorId = "7b7723afd9e6480397a4d467b7693156".try_into().unwrap();
+    let mut backend = Backend::new();
+    backend
+        .apply_local_change(set_change(&actor, 1, Vec::new(), "magpie"))
+        .unwrap();
+
+    let snapshot = backend.snapshot();
+    let heads = snapshot.get_heads();
+    let patch = snapshot.get_patch().unwrap();
+
+    backend
+        .apply_local_change(set_change(&actor, 2, Vec::new(), "jay"))
+        .unwrap();
+    assert_ne!(backend.get_heads(), heads);
+    // the snapshot is unaffected by changes to the backend it was taken from
+    assert_eq!(snapshot.get_heads(), heads);
+
+    backend.restore(snapshot.clone());
+    assert_eq!(backend.get_heads(), heads);
+    assert_eq!(backend.get_patch().unwrap(), patch);
+    assert_eq!(backend.get_changes(&[]).len(), 1);
+}
+
+#[test]
+fn test_fork_from_snapshot() {
+    let actor: ActorId = "37704788917a499cb0206fa8519ac4d9".try_into().unwrap();
+    let mut backend = Backend::new();
+    backend
+        .apply_local_change(set_change(&actor, 1, Vec::new(), "magpie"))
+        .unwrap();
+    let snapshot = backend.snapshot();
+
+    let mut replica = Backend::from(snapshot.clone());
+    replica
+        .apply_local_change(set_change(&actor, 2, Vec::new(), "jay"))
+        .unwrap();
+
+    assert_eq!(replica.get_changes(&[]).len(), 2);
+    assert_eq!(snapshot.get_changes(&[]).len(), 1);
+    assert_eq!(backend.get_heads(), snapshot.get_heads());
+}
+
+#[test]
+fn test_speculate_leaves_backend_untouched() {
+    let actor: ActorId = "d5ffb2d5d3b24fda8e3c0c3ff6d0b2f5".try_into().unwrap();
+    let mut backend = Backend::new();
+    backend
+        .apply_local_change(set_change(&actor, 1, Vec::new(), "magpie"))
+        .unwrap();
+    let heads = backend.get_heads();
+
+    let patch = backend.speculate(|b| {
+        b.apply_local_change(set_change(&actor, 2, Vec::new(), "jay"))
+            .unwrap()
+            .0
+    });
+
+    assert_eq!(patch.max_op, 2);
+    assert_eq!(backend.get_heads(), heads);
+    assert_eq!(backend.get_changes(&[]).len(), 1);
+}
+
+#[test]
+fn test_snapshots_can_be_shared_between_threads() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<automerge_backend::OwnedSnapshot>();
+
+    let actor: ActorId = "7b7723afd9e6480397a4d467b7693156".try_into().unwrap();
+    let mut backend = Backend::new();
+    backend
+        .apply_local_change(set_change(&actor, 1, Vec::new(), "magpie"))
+        .unwrap();
+    let snapshot = backend.snapshot();
+    let heads = std::thread::spawn(move || snapshot.get_heads())
+        .join()
+        .unwrap();
+    assert_eq!(heads, backend.get_heads());
+}
+
+#[test]
+fn test_fork_at() {
+    let published: ActorId = "7b7723afd9e6480397a4d467b7693156".try_into().unwrap();
+    let drafter: ActorId = "9f17f3a4c2e54bd1a0a54c37e0f0c1d2".try_into().unwrap();
+    let title = |backend: &Backend| {
+        backend
+            .value_at(&ObjectId::Root, &[PathElement::from("title")], None)
+            .unwrap()
+    };
+
+    let mut backend = Backend::new();
+    backend.set_checkpoint_interval(Some(1));
+    backend
+        .apply_local_change(set_title(&published, 1, "v1", Vec::new()))
+        .unwrap();
+    let v1 = backend.get_heads();
+    backend
+        .apply_local_change(set_title(&published, 2, "v2", Vec::new()))
+        .unwrap();
+
+    let mut draft = backend.fork_at(&v1).unwrap();
+    assert_eq!(draft.get_heads(), v1);
+    assert_eq!(draft.get_changes(&[]).len(), 1);
+    assert_eq!(draft.checkpoint_interval(), Some(1));
+    assert_eq!(draft.checkpoint_count(), 1);
+    assert_eq!(title(&draft), Some(Value::Primitive("v1".into())));
+
+    // Changes to the fork don't affect the original
+    draft
+        .apply_local_change(set_title(&drafter, 1, "draft", v1.clone()))
+        .unwrap();
+    assert_eq!(title(&draft), Some(Value::Primitive("draft".into())));
+    assert_eq!(title(&backend), Some(Value::Primitive("v2".into())));
+    assert_eq!(backend.get_changes(&[]).len(), 2);
+
+    // but can be merged back in
+    backend
+        .apply_changes(draft.get_changes(&v1).into_iter().cloned().collect())
+        .unwrap();
+    assert_eq!(backend.get_heads().len(), 2);
+
+    let unknown = amp::ChangeHash([7; 32]);
+    assert!(matches!(
+        backend.fork_at(&[unknown]),
+        Err(AutomergeError::UnknownChanges(hashes)) if hashes == vec![unknown]
+    ));
+}
+
+#[test]
+fn test_fork_only_requires_the_features_of_its_ops() {
+    let actor: ActorId = "7b7723afd9e6480397a4d467b7693156".try_into().unwrap();
+    let list = ObjectId::from(actor.op_id_at(1));
+    let mut backend = Backend::new();
+    backend
+        .apply_local_change(
+            change(&actor)
+                .op(make(&ObjectId::Root, "list", ObjType::List))
+                .op(insert(&list, ElementId::Head.into(), "a"))
+                .op(insert(&list, actor.op_id_at(2).into(), "b"))
+                .build(),
+        )
+        .unwrap();
+    let before_move = backend.get_heads();
+    backend
+        .apply_local_change(
+            change(&actor)
+                .seq(2)
+                .start_op(4)
+                .op(op(
+                    OpType::Move(actor.op_id_at(3)),
+                    &list,
+                    ElementId::Head.into(),
+                    true,
+                    Vec::new(),
+                ))
+                .build(),
+        )
+        .unwrap();
+    assert!(backend.required_features().contains(MOVE_FEATURE));
+
+    assert!(backend
+        .fork_at(&before_move)
+        .unwrap()
+        .required_features()
+        .is_empty());
+    // the feature in the header of a saved document comes from the move op too
+    let loaded = Backend::load(backend.save().unwrap()).unwrap();
+    assert!(loaded.required_features().contains(MOVE_FEATURE));
+    assert!(loaded
+        .fork_at(&before_move)
+        .unwrap()
+        .required_features()
+        .is_empty());
+
+    // declared features are kept, whether they were declared here or loaded
+    backend.require_feature("comments");
+    let loaded = Backend::load_with_features(&backend.save().unwrap(), &["comments"]).unwrap();
+    for backend in [&backend, &loaded] {
+        let fork = backend.fork_at(&before_move).unwrap();
+        assert_eq!(
+            fork.required_features().iter().collect::<Vec<_>>(),
+            vec!["comments"]
+        );
+    }
+}
+
+#[test]
+fn test_reads_at_heads_match_with_checkpoints() {
+    let alice: ActorId = "7b7723afd9e6480397a4d467b7693156".try_into().unwrap();
+    let bob: ActorId = "9f17f3a4c2e54bd1a0a54c37e0f0c1d2".try_into().unwrap();
+
+    let mut changes = vec![set(&alice, 1, Vec::new())];
+    // bob's change is concurrent with all of alice's changes but is applied early on
+    changes.push(set(&bob, 1, Vec::new()));
+    for seq in 2..=6 {
+        let dep = changes
+            .iter()
+            .rev()
+            .find(|c| c.actor_id() == &alice)
+            .unwrap()
+            .hash;
+        changes.push(set(&alice, seq, vec![dep]));
+    }
+
+    let mut plain = Backend::new();
+    plain.load_changes(changes.clone()).unwrap();
+    let mut checkpointed = Backend::new();
+    checkpointed.set_checkpoint_interval(Some(2));
+    checkpointed.load_changes(changes.clone()).unwrap();
+    assert_eq!(checkpointed.checkpoint_count(), 3);
+
+    let read = |backend: &Backend, heads: &[amp::ChangeHash]| {
+        backend
+            .value_at(&ObjectId::Root, &[], Some(heads))
+            .unwrap()
+            .unwrap()
+    };
+    for change in &changes {
+        assert_eq!(
+            read(&checkpointed, &[change.hash]),
+            read(&plain, &[change.hash])
+        );
+        assert_eq!(
+            checkpointed.get_patch_at(&[change.hash]).unwrap(),
+            plain.get_patch_at(&[change.hash]).unwrap()
+        );
+    }
+    let alice_only = read(&checkpointed, &[changes[6].hash]);
+    match alice_only {
+        Value::Map(map) => {
+            assert_eq!(map.len(), 1);
+            assert_eq!(
+                map.get(alice.to_hex_string().as_str()),
+                Some(&Value::Primitive(amp::ScalarValue::Uint(6)))
+            );
+        }
+        other => panic!("expected a map, got {:?}", other),
+    }
+    assert_eq!(
+        read(&checkpointed, &checkpointed.get_heads()),
+        checkpointed
+            .value_at(&ObjectId::Root, &[], None)
+            .unwrap()
+            .unwrap()
+    );
+
+    checkpointed.set_checkpoint_interval(None);
+    assert_eq!(checkpointed.checkpoint_count(), 0);
+}
@@ -0,0 +1,696 @@
+use std::{convert::TryInto, fmt::Debug};
+
+use automerge_backend::{
+    Backend, BinaryCodec, Change, ChangeCodec, CompressionDictionary, DecodingError, JsonCodec,
+    PatchCodec, SyncManager, SyncMessage, SyncState, MAX_RECEIVED_EPHEMERAL,
+};
+use automerge_protocol as amp;
+use automerge_protocol::{ActorId, ElementId, ObjType, ObjectId, ScalarValue};
+
+mod common;
+use common::{change, insert, make};
+
+/// Append a change setting `key` in the root map to `value` on top of the current heads, and
+/// return it
+fn set<V: Into<ScalarValue>>(
+    backend: &mut Backend,
+    actor: &ActorId,
+    key: &str,
+    value: V,
+) -> Change {
+    let seq = backend
+        .get_changes_for_actor_id(actor)
+        .map_or(0, |changes| changes.len()) as u64
+        + 1;
+    let applied = change(actor)
+        .seq(seq)
+        .start_op(backend.max_op() + 1)
+        .message(&format!("set {}", key))
+        .deps(backend.get_heads())
+        .op(common::set(&ObjectId::Root, key, value, Vec::new()))
+        .encode();
+    backend.apply_changes(vec![applied.clone()]).unwrap();
+    applied
+}
+
+/// A change typing `text` into the text object `text_id`, one character after another
+fn typing(
+    actor: &ActorId,
+    seq: u64,
+    start_op: u64,
+    deps: Vec<amp::ChangeHash>,
+    text_id: &amp::OpId,
+    after: ElementId,
+    text: &str,
+) -> Change {
+    let text_id = ObjectId::from(text_id.clone());
+    let mut after = after;
+    let ops = text
+        .chars()
+        .enumerate()
+        .map(|(i, c)| {
+            let op = insert(&text_id, after.clone().into(), c.to_string().as_str());
+            after = actor.op_id_at(start_op + i as u64).into();
+            op
+        })
+        .collect();
+    change(actor)
+        .seq(seq)
+        .start_op(start_op)
+        .time(1_600_000_000_000 + seq as i64)
+        .message("typing")
+        .deps(deps)
+        .ops(ops)
+        .encode()
+}
+
+/// A backend with a text object and a few hundred keystroke changes
+fn keystrokes(actor: &ActorId) -> (Backend, amp::OpId) {
+    let text_id = actor.op_id_at(1);
+    let mut backend = Backend::new();
+    backend
+        .apply_changes(vec![change(actor)
+            .op(make(&ObjectId::Root, "text", ObjType::Text))
+            .encode()])
+        .unwrap();
+    let mut after = ElementId::Head;
+    for (i, c) in "the quick brown fox jumps over the lazy dog "
+        .repeat(10)
+        .chars()
+        .enumerate()
+    {
+        let seq = i as u64 + 2;
+        let change = typing(
+            actor,
+            seq,
+            seq,
+            backend.get_heads(),
+            &text_id,
+            after,
+            &c.to_string(),
+        );
+        after = actor.op_id_at(seq).into();
+        backend.apply_changes(vec![change]).unwrap();
+    }
+    (backend, text_id)
+}
+
+/// Pass encoded messages back and forth until neither side has anything left to say, returning
+/// the number of messages sent
+fn sync(
+    a: &mut Backend,
+    a_state: &mut SyncState,
+    b: &mut Backend,
+    b_state: &mut SyncState,
+) -> usize {
+    let mut sent = 0;
+    for _ in 0..10 {
+        let a_to_b = a.generate_sync_message(a_state);
+        if let Some(msg) = &a_to_b {
+            let msg = SyncMessage::decode(&msg.clone().encode().unwrap()).unwrap();
+            b.receive_sync_message(b_state, msg).unwrap();
+            sent += 1;
+        }
+        let b_to_a = b.generate_sync_message(b_state);
+        if let Some(msg) = &b_to_a {
+            let msg = SyncMessage::decode(&msg.clone().encode().unwrap()).unwrap();
+            a.receive_sync_message(a_state, msg).unwrap();
+            sent += 1;
+        }
+        if a_to_b.is_none() && b_to_a.is_none() {
+            return sent;
+        }
+    }
+    panic!("peers did not converge");
+}
+
+#[test]
+fn test_divergent_peers_converge() {
+    let actor1: ActorId = "02ef21f3c9eb4087880ebedd7c4bbe43".try_into().unwrap();
+    let actor2: ActorId = "2a1d376b24f744008d4af58252d644dd".try_into().unwrap();
+    let mut backend1 = Backend::new();
+    let mut backend2 = Backend::new();
+    for seq in 1..=5 {
+        set(&mut backend1, &actor1, &format!("x{}", seq), seq as i64);
+    }
+    for seq in 1..=3 {
+        set(&mut backend2, &actor2, &format!("y{}", seq), seq as i64);
+    }
+
+    let mut state1 = SyncState::default();
+    let mut state2 = SyncState::default();
+    sync(&mut backend1, &mut state1, &mut backend2, &mut state2);
+    assert_eq!(backend1.get_heads(), backend2.get_heads());
+    assert_eq!(backend1.get_changes(&[]).len(), 8);
+    assert_eq!(backend2.get_changes(&[]).len(), 8);
+    assert_eq!(backend1.get_patch().unwrap(), backend2.get_patch().unwrap());
+
+    // once in sync, there is nothing more to send
+    assert!(backend1.generate_sync_message(&mut state1).is_none());
+    assert!(backend2.generate_sync_message(&mut state2).is_none());
+}
+
+#[test]
+fn test_resume_sync_from_saved_state() {
+    let actor1: ActorId = "02ef21f3c9eb4087880ebedd7c4bbe43".try_into().unwrap();
+    let actor2: ActorId = "2a1d376b24f744008d4af58252d644dd".try_into().unwrap();
+    let mut backend1 = Backend::new();
+    let mut backend2 = Backend::new();
+    set(&mut backend1, &actor1, "x1", 1);
+    let mut state1 = SyncState::default();
+    let mut state2 = SyncState::default();
+    sync(&mut backend1, &mut state1, &mut backend2, &mut state2);
+
+    // reconnect with persisted sync state after both sides made more changes
+    set(&mut backend1, &actor1, "x2", 2);
+    set(&mut backend2, &actor2, "y1", 1);
+    let mut state1 = SyncState::decode(&state1.encode().unwrap()).unwrap();
+    let mut state2 = SyncState::decode(&state2.encode().unwrap()).unwrap();
+    sync(&mut backend1, &mut state1, &mut backend2, &mut state2);
+    assert_eq!(backend1.get_heads(), backend2.get_heads());
+    assert_eq!(backend1.get_changes(&[]).len(), 3);
+}
+
+#[test]
+fn test_saved_state_only_summarises_new_changes() {
+    let actor1: ActorId = "02ef21f3c9eb4087880ebedd7c4bbe43".try_into().unwrap();
+    let actor2: ActorId = "2a1d376b24f744008d4af58252d644dd".try_into().unwrap();
+    let mut backend1 = Backend::new();
+    let mut backend2 = Backend::new();
+    for seq in 1..=100 {
+        set(&mut backend1, &actor1, &format!("x{}", seq), seq as i64);
+    }
+    let mut state1 = SyncState::default();
+    let mut state2 = SyncState::default();
+    sync(&mut backend1, &mut state1, &mut backend2, &mut state2);
+    let shared = backend1.get_heads();
+    assert_eq!(state1.shared_heads, shared);
+
+    set(&mut backend2, &actor2, "y1", 1);
+    let mut resumed = SyncState::decode(&state2.encode().unwrap()).unwrap();
+    assert_eq!(resumed.shared_heads, shared);
+    assert!(resumed.sent_hashes.is_empty());
+    let message = backend2.generate_sync_message(&mut resumed).unwrap();
+    assert_eq!(message.have.len(), 1);
+    assert_eq!(message.have[0].last_sync, shared);
+    let resumed_len = message.encode().unwrap().len();
+
+    // starting again from nothing means summarising the whole history
+    let message = backend2
+        .generate_sync_message(&mut SyncState::default())
+        .unwrap();
+    assert!(message.have[0].last_sync.is_empty());
+    assert!(message.encode().unwrap().len() > resumed_len);
+}
+
+#[test]
+fn test_ephemeral_messages_bypass_the_document() {
+    let actor1: ActorId = "02ef21f3c9eb4087880ebedd7c4bbe43".try_into().unwrap();
+    let actor2: ActorId = "2a1d376b24f744008d4af58252d644dd".try_into().unwrap();
+    let mut backend1 = Backend::new();
+    let mut backend2 = Backend::new();
+    set(&mut backend1, &actor1, "x1", 1);
+    set(&mut backend2, &actor2, "y1", 1);
+    let mut state1 = SyncState::default();
+    let mut state2 = SyncState::default();
+
+    // an ephemeral message sent before the peers have synced doesn't disturb the sync
+    let heads_before = backend2.get_heads();
+    for payload in [&b"cursor 3"[..], &b""[..]] {
+        let encoded = SyncState::encode_ephemeral(payload).unwrap();
+        let message = SyncMessage::decode(&encoded).unwrap();
+        assert_eq!(message.ephemeral.as_deref(), Some(payload));
+        assert!(backend2
+            .receive_sync_message(&mut state2, message)
+            .unwrap()
+            .is_none());
+    }
+    assert_eq!(backend2.get_heads(), heads_before);
+    assert_eq!(
+        state2.take_ephemeral(),
+        vec![b"cursor 3".to_vec(), Vec::new()]
+    );
+    assert!(state2.take_ephemeral().is_empty());
+
+    sync(&mut backend1, &mut state1, &mut backend2, &mut state2);
+    assert_eq!(backend1.get_heads(), backend2.get_heads());
+    assert!(state1.take_ephemeral().is_empty());
+}
+
+#[test]
+fn test_sync_messages_have_no_ephemeral_payload() {
+    let actor: ActorId = "02ef21f3c9eb4087880ebedd7c4bbe43".try_into().unwrap();
+    let mut backend = Backend::new();
+    set(&mut backend, &actor, "x1", 1);
+    let message = backend
+        .generate_sync_message(&mut SyncState::default())
+        .unwrap();
+    let decoded = SyncMessage::decode(&message.encode().unwrap()).unwrap();
+    assert!(decoded.ephemeral.is_none());
+    assert_eq!(decoded.heads, backend.get_heads());
+}
+
+#[test]
+fn test_only_the_latest_ephemeral_messages_are_kept() {
+    let mut backend = Backend::new();
+    let mut state = SyncState::default();
+    for i in 0..MAX_RECEIVED_EPHEMERAL + 2 {
+        let encoded = SyncState::encode_ephemeral(&i.to_be_bytes()).unwrap();
+        backend
+            .receive_sync_message(&mut state, SyncMessage::decode(&encoded).unwrap())
+            .unwrap();
+    }
+    let received = state.take_ephemeral();
+    assert_eq!(received.len(), MAX_RECEIVED_EPHEMERAL);
+    assert_eq!(received[0], 2usize.to_be_bytes().to_vec());
+    assert_eq!(
+        received.last(),
+        Some(&(MAX_RECEIVED_EPHEMERAL + 1).to_be_bytes().to_vec())
+    );
+}
+
+/// Sync two backends passing every message through `codec`
+fn sync_with<C>(codec: &C, a: &mut Backend, b: &mut Backend)
+where
+    C: ChangeCodec,
+    C::Error: Debug,
+{
+    let (mut a_state, mut b_state) = (SyncState::default(), SyncState::default());
+    loop {
+        let a_to_b = a.generate_sync_message(&mut a_state);
+        let b_to_a = b.generate_sync_message(&mut b_state);
+        if a_to_b.is_none() && b_to_a.is_none() {
+            break;
+        }
+        if let Some(message) = a_to_b {
+            let bytes = codec.encode_sync_message(message).unwrap();
+            let message = codec.decode_sync_message(&bytes).unwrap();
+            b.receive_sync_message(&mut b_state, message).unwrap();
+        }
+        if let Some(message) = b_to_a {
+            let bytes = codec.encode_sync_message(message).unwrap();
+            let message = codec.decode_sync_message(&bytes).unwrap();
+            a.receive_sync_message(&mut a_state, message).unwrap();
+        }
+    }
+}
+
+fn round_trip_changes<C>(codec: &C)
+where
+    C: ChangeCodec,
+    C::Error: Debug,
+{
+    let actor1: ActorId = "02ef21f3c9eb4087880ebedd7c4bbe43".try_into().unwrap();
+    let actor2: ActorId = "2a1d376b24f744008d4af58252d644dd".try_into().unwrap();
+    let mut a = Backend::new();
+    let mut b = Backend::new();
+    for key in ["wren", "robin", "jay"] {
+        let change = set(&mut a, &actor1, key, key);
+        let decoded = codec
+            .decode_change(&codec.encode_change(&change).unwrap())
+            .unwrap();
+        assert_eq!(decoded.hash, change.hash);
+        assert_eq!(decoded.decode(), change.decode());
+    }
+    set(&mut b, &actor2, "magpie", "magpie");
+
+    sync_with(codec, &mut a, &mut b);
+    assert_eq!(a.get_heads(), b.get_heads());
+    assert_eq!(a.get_changes(&[]).len(), 4);
+}
+
+#[test]
+fn test_binary_codec() {
+    round_trip_changes(&BinaryCodec);
+}
+
+#[test]
+fn test_json_codec() {
+    round_trip_changes(&JsonCodec);
+
+    let actor: ActorId = "02ef21f3c9eb4087880ebedd7c4bbe43".try_into().unwrap();
+    let mut backend = Backend::new();
+    set(&mut backend, &actor, "wren", "wren");
+    let patch = backend.get_patch().unwrap();
+    let bytes = JsonCodec.encode_patch(&patch).unwrap();
+    assert_eq!(bytes, serde_json::to_vec(&patch).unwrap());
+    assert_eq!(JsonCodec.decode_patch(&bytes).unwrap(), patch);
+
+    assert!(JsonCodec.decode_change(b"not json").is_err());
+}
+
+/// A hub syncing with peers which each talk only to the hub
+struct Network {
+    hub: Backend,
+    manager: SyncManager<usize>,
+    peers: Vec<(Backend, SyncState)>,
+}
+
+impl Network {
+    fn new(peers: usize) -> Self {
+        let mut manager = SyncManager::new();
+        for peer in 0..peers {
+            manager.add_peer(peer, SyncState::default());
+        }
+        Network {
+            hub: Backend::new(),
+            manager,
+            peers: (0..peers)
+                .map(|_| (Backend::new(), SyncState::default()))
+                .collect(),
+        }
+    }
+
+    /// Deliver messages until everyone is quiet, returning how many the hub sent
+    fn settle(&mut self) -> usize {
+        let mut sent = 0;
+        for _ in 0..20 {
+            let mut quiet = true;
+            for (peer, message) in self.manager.flush(&self.hub) {
+                sent += 1;
+                quiet = false;
+                let (backend, state) = &mut self.peers[peer];
+                backend
+                    .receive_sync_message(state, roundtrip(message))
+                    .unwrap();
+            }
+            for (peer, (backend, state)) in self.peers.iter_mut().enumerate() {
+                if let Some(message) = backend.generate_sync_message(state) {
+                    quiet = false;
+                    self.manager
+                        .receive(&mut self.hub, &peer, roundtrip(message))
+                        .unwrap();
+                }
+            }
+            if quiet {
+                return sent;
+            }
+        }
+        panic!("network did not settle");
+    }
+}
+
+fn roundtrip(message: SyncMessage) -> SyncMessage {
+    SyncMessage::decode(&message.encode().unwrap()).unwrap()
+}
+
+#[test]
+fn test_changes_reach_every_peer_through_the_hub() {
+    let alice: ActorId = "02ef21f3c9eb4087880ebedd7c4bbe43".try_into().unwrap();
+    let bob: ActorId = "2a1d376b24f744008d4af58252d644dd".try_into().unwrap();
+    let mut network = Network::new(3);
+    network.settle();
+
+    set(&mut network.peers[0].0, &alice, "x", 1);
+    set(&mut network.peers[1].0, &bob, "y", 1);
+    network.settle();
+    let heads = network.hub.get_heads();
+    assert_eq!(heads.len(), 2);
+    for (backend, _) in &network.peers {
+        assert_eq!(backend.get_heads(), heads);
+    }
+}
+
+#[test]
+fn test_flush_only_generates_messages_when_needed() {
+    let actor: ActorId = "7b7723afd9e6480397a4d467b7693156".try_into().unwrap();
+    let mut network = Network::new(2);
+    network.settle();
+    assert!(network.manager.flush(&network.hub).is_empty());
+
+    // several local changes between flushes cost one message per peer
+    for key in ["a", "b", "c"] {
+        set(&mut network.hub, &actor, key, 1);
+    }
+    let messages = network.manager.flush(&network.hub);
+    let mut recipients: Vec<usize> = messages.iter().map(|(peer, _)| *peer).collect();
+    recipients.sort_unstable();
+    assert_eq!(recipients, vec![0, 1]);
+    assert!(network.manager.flush(&network.hub).is_empty());
+}
+
+#[test]
+fn test_heads_changed() {
+    let actor: ActorId = "7b7723afd9e6480397a4d467b7693156".try_into().unwrap();
+    let mut network = Network::new(1);
+    assert_eq!(network.manager.heads_changed(&network.hub), None);
+
+    set(&mut network.peers[0].0, &actor, "x", 1);
+    network.settle();
+    assert_eq!(
+        network.manager.heads_changed(&network.hub),
+        Some(network.hub.get_heads())
+    );
+    assert_eq!(network.manager.heads_changed(&network.hub), None);
+}
+
+#[test]
+fn test_peers_come_and_go() {
+    let mut manager: SyncManager<&str> = SyncManager::new();
+    let mut hub = Backend::new();
+    let other = Backend::new();
+    let message = other
+        .generate_sync_message(&mut SyncState::default())
+        .unwrap();
+    manager.receive(&mut hub, &"stranger", message).unwrap();
+    assert_eq!(manager.peers().collect::<Vec<_>>(), vec![&"stranger"]);
+    assert!(manager.state(&"stranger").is_some());
+
+    let saved = manager.remove_peer(&"stranger").unwrap().encode().unwrap();
+    assert!(manager.state(&"stranger").is_none());
+    manager.add_peer("stranger", SyncState::decode(&saved).unwrap());
+    assert_eq!(manager.flush(&hub).len(), 1);
+    assert!(manager.remove_peer(&"nobody").is_none());
+}
+
+#[test]
+fn test_dictionary_compresses_small_changes() {
+    let actor: ActorId = "7b7723afd9e6480397a4d467b7693156".try_into().unwrap();
+    let (backend, text_id) = keystrokes(&actor);
+    let dictionary = backend.train_compression_dictionary();
+    assert!(!dictionary.as_bytes().is_empty());
+
+    let seq = backend.get_changes(&[]).len() as u64 + 1;
+    let after = actor.op_id_at(seq - 1).into();
+    let next = typing(&actor, seq, seq, backend.get_heads(), &text_id, after, "x");
+    let compressed = dictionary.compress(next.raw_bytes());
+    // on its own a change this small doesn't compress at all
+    let without_dictionary =
+        CompressionDictionary::from_bytes(Vec::new()).compress(next.raw_bytes());
+    assert!(without_dictionary.len() > next.raw_bytes().len());
+    assert!(
+        compressed.len() < next.raw_bytes().len(),
+        "{} bytes compressed to {}",
+        next.raw_bytes().len(),
+        compressed.len()
+    );
+
+    // the other side only needs the bytes of the dictionary
+    let theirs = CompressionDictionary::from_bytes(dictionary.as_bytes().to_vec());
+    assert_eq!(theirs.id(), dictionary.id());
+    let decompressed = theirs.decompress(&compressed).unwrap();
+    assert_eq!(Change::from_bytes(decompressed).unwrap(), next);
+
+    let other = CompressionDictionary::from_bytes(b"something else".to_vec());
+    assert!(matches!(
+        other.decompress(&compressed),
+        Err(DecodingError::WrongDictionary { .. })
+    ));
+}
+
+#[test]
+fn test_sync_messages_with_dictionary() {
+    let actor: ActorId = "7b7723afd9e6480397a4d467b7693156".try_into().unwrap();
+    let (backend, _) = keystrokes(&actor);
+    let dictionary = backend.train_compression_dictionary();
+
+    let message = backend
+        .generate_sync_message(&mut SyncState::default())
+        .unwrap();
+    let plain = message.clone().encode().unwrap();
+    let compressed = message.encode_with_dictionary(&dictionary).unwrap();
+    let decoded = SyncMessage::decode_with_dictionary(&compressed, &dictionary).unwrap();
+    assert_eq!(decoded.encode().unwrap(), plain);
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn test_zstd_dictionary_compresses_small_changes() {
+    let actor: ActorId = "7b7723afd9e6480397a4d467b7693156".try_into().unwrap();
+    let (backend, text_id) = keystrokes(&actor);
+    let dictionary = backend.train_zstd_compression_dictionary();
+    assert!(!dictionary.as_bytes().is_empty());
+
+    let seq = backend.get_changes(&[]).len() as u64 + 1;
+    let after = actor.op_id_at(seq - 1).into();
+    let next = typing(&actor, seq, seq, backend.get_heads(), &text_id, after, "x");
+    let compressed = dictionary.compress(next.raw_bytes());
+    assert!(
+        compressed.len() < next.raw_bytes().len(),
+        "{} bytes compressed to {}",
+        next.raw_bytes().len(),
+        compressed.len()
+    );
+
+    let theirs = CompressionDictionary::from_zstd_bytes(dictionary.as_bytes().to_vec()).unwrap();
+    assert_eq!(theirs.id(), dictionary.id());
+    let decompressed = theirs.decompress(&compressed).unwrap();
+    assert_eq!(Change::from_bytes(decompressed).unwrap(), next);
+
+    // the same bytes used as a DEFLATE dictionary are a different dictionary
+    let deflate = CompressionDictionary::from_bytes(dictionary.as_bytes().to_vec());
+    assert_ne!(deflate.id(), dictionary.id());
+    assert!(matches!(
+        deflate.decompress(&compressed),
+        Err(DecodingError::WrongDictionary { .. })
+    ));
+
+    let message = backend
+        .generate_sync_message(&mut SyncState::default())
+        .unwrap();
+    let plain = message.clone().encode().unwrap();
+    let compressed = message.encode_with_dictionary(&dictionary).unwrap();
+    let decoded = SyncMessage::decode_with_dictionary(&compressed, &theirs).unwrap();
+    assert_eq!(decoded.encode().unwrap(), plain);
+}
+
+#[cfg(feature = "async")]
+mod transport {
+    use async_trait::async_trait;
+    use automerge_backend::{FramedTransport, SyncDriver, SyncTransport, TokioTransport};
+    use futures::{
+        channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender},
+        executor::block_on,
+        io::Cursor,
+    };
+
+    use super::*;
+
+    /// One end of an in-memory connection whose `recv` reports the connection closed when nothing
+    /// is waiting, so a driver's `run` returns as soon as it has handled everything sent to it
+    struct Channel {
+        tx: UnboundedSender<Vec<u8>>,
+        rx: UnboundedReceiver<Vec<u8>>,
+    }
+
+    fn channel_pair() -> (Channel, Channel) {
+        let (a_tx, b_rx) = unbounded();
+        let (b_tx, a_rx) = unbounded();
+        (
+            Channel { tx: a_tx, rx: a_rx },
+            Channel { tx: b_tx, rx: b_rx },
+        )
+    }
+
+    #[async_trait]
+    impl SyncTransport for Channel {
+        type Error = futures::channel::mpsc::SendError;
+
+        async fn send(&mut self, message: Vec<u8>) -> Result<(), Self::Error> {
+            self.tx
+                .unbounded_send(message)
+                .map_err(|e| e.into_send_error())
+        }
+
+        async fn recv(&mut self) -> Result<Option<Vec<u8>>, Self::Error> {
+            Ok(self.rx.try_recv().ok())
+        }
+    }
+
+    #[test]
+    fn test_drivers_sync_two_backends() {
+        let actor1: ActorId = "02ef21f3c9eb4087880ebedd7c4bbe43".try_into().unwrap();
+        let actor2: ActorId = "2a1d376b24f744008d4af58252d644dd".try_into().unwrap();
+        let mut backend1 = Backend::new();
+        let mut backend2 = Backend::new();
+        for seq in 1..=3 {
+            set(&mut backend1, &actor1, &format!("x{}", seq), seq as i64);
+        }
+        set(&mut backend2, &actor2, "y", 1);
+
+        let (end1, end2) = channel_pair();
+        let mut driver1 = SyncDriver::new(end1, SyncState::default());
+        let mut driver2 = SyncDriver::new(end2, SyncState::default());
+        let mut patches = 0;
+        block_on(async {
+            for _ in 0..10 {
+                driver1.run(&mut backend1, |_| patches += 1).await.unwrap();
+                driver2.run(&mut backend2, |_| patches += 1).await.unwrap();
+            }
+        });
+        assert_eq!(backend1.get_heads(), backend2.get_heads());
+        assert_eq!(backend2.get_changes(&[]).len(), 4);
+        assert!(patches >= 2);
+
+        // ephemeral messages go through the driver without touching either document
+        block_on(async {
+            driver1.send_ephemeral(b"hello").await.unwrap();
+            driver2
+                .run(&mut backend2, |_| panic!("unexpected patch"))
+                .await
+                .unwrap();
+        });
+        assert_eq!(
+            driver2.state_mut().take_ephemeral(),
+            vec![b"hello".to_vec()]
+        );
+    }
+
+    #[test]
+    fn test_framed_transport_round_trips_messages() {
+        let mut transport = FramedTransport::new(Cursor::new(Vec::new()));
+        block_on(async {
+            transport.send(b"first".to_vec()).await.unwrap();
+            transport.send(Vec::new()).await.unwrap();
+        });
+        let mut stream = transport.into_inner();
+        assert_eq!(&stream.get_ref()[..4], &[0, 0, 0, 5]);
+        stream.set_position(0);
+        let mut transport = FramedTransport::new(stream);
+        block_on(async {
+            assert_eq!(transport.recv().await.unwrap(), Some(b"first".to_vec()));
+            assert_eq!(transport.recv().await.unwrap(), Some(Vec::new()));
+            assert_eq!(transport.recv().await.unwrap(), None);
+        });
+    }
+
+    #[test]
+    fn test_framed_transport_rejects_bad_frames() {
+        // the stream ends part way through the message
+        let mut transport = FramedTransport::new(Cursor::new(vec![0, 0, 0, 10, 1, 2, 3]));
+        let error = block_on(transport.recv()).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+
+        let mut transport = FramedTransport::new(Cursor::new(vec![0xff; 8]));
+        let error = block_on(transport.recv()).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_tokio_transport_round_trips_messages() {
+        let (a, b) = tokio::io::duplex(1024);
+        let mut a = TokioTransport::new(a);
+        let mut b = TokioTransport::new(b);
+        block_on(async {
+            a.send(b"first".to_vec()).await.unwrap();
+            a.send(Vec::new()).await.unwrap();
+            b.send(b"reply".to_vec()).await.unwrap();
+            assert_eq!(b.recv().await.unwrap(), Some(b"first".to_vec()));
+            assert_eq!(b.recv().await.unwrap(), Some(Vec::new()));
+            assert_eq!(a.recv().await.unwrap(), Some(b"reply".to_vec()));
+            drop(a);
+            assert_eq!(b.recv().await.unwrap(), None);
+        });
+
+        let (mut a, b) = tokio::io::duplex(1024);
+        let mut b = TokioTransport::new(b);
+        block_on(async {
+            tokio::io::AsyncWriteExt::write_all(&mut a, &[0, 0, 0, 10, 1, 2, 3])
+                .await
+                .unwrap();
+            drop(a);
+            let error = b.recv().await.unwrap_err();
+            assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+        });
+    }
+}
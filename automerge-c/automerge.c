@@ -94,6 +94,8 @@ int main() {
   char buff2[BUFSIZE];
   char buff3[BUFSIZE];
 
+  assert(automerge_abi_version() == AUTOMERGE_ABI_VERSION);
+
   printf("begin\n");
 
   Backend * dbA = automerge_init();
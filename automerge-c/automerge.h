@@ -6,10 +6,22 @@
 #include <stdint.h>
 #include <stdbool.h>
 
+/**
+ * Bumped whenever a function in this header changes signature or behaviour in a way which
+ * breaks existing callers. Functions are only ever added within one version.
+ */
+#define AUTOMERGE_ABI_VERSION 1
+
 typedef struct Backend Backend;
 
 typedef struct SyncState SyncState;
 
+/**
+ * The `AUTOMERGE_ABI_VERSION` of the library which is linked in, compare it with the one in the
+ * header to make sure they match
+ */
+uint32_t automerge_abi_version(void);
+
 /**
  * # Safety
  * This must me called with a valid backend pointer
@@ -66,7 +78,7 @@ const char *automerge_error(Backend *backend);
 
 /**
  * # Safety
- * This must me called with a valid backend pointer
+ * This must me called with a valid backend pointer, or null in which case it does nothing
  */
 void automerge_free(Backend *backend);
 
@@ -183,15 +183,29 @@ impl From<Vec<ChangeHash>> for BinaryResults {
   getMissingDeps => automerge_get_missing_deps
 */
 
+/// Bumped whenever a function in this header changes signature or behaviour in a way which
+/// breaks existing callers. Functions are only ever added within one version.
+pub const AUTOMERGE_ABI_VERSION: u32 = 1;
+
+/// The `AUTOMERGE_ABI_VERSION` of the library which is linked in, compare it with the one in the
+/// header to make sure they match
+#[no_mangle]
+pub extern "C" fn automerge_abi_version() -> u32 {
+    AUTOMERGE_ABI_VERSION
+}
+
 #[no_mangle]
 pub extern "C" fn automerge_init() -> *mut Backend {
     Backend::init(automerge_backend::Backend::new()).into()
 }
 
 /// # Safety
-/// This must me called with a valid backend pointer
+/// This must me called with a valid backend pointer, or null in which case it does nothing
 #[no_mangle]
 pub unsafe extern "C" fn automerge_free(backend: *mut Backend) {
+    if backend.is_null() {
+        return;
+    }
     let backend: Backend = *Box::from_raw(backend);
     drop(backend)
 }
@@ -0,0 +1,264 @@
+use std::collections::{BTreeMap, HashSet};
+
+use anyhow::Result;
+use automerge_backend::Backend;
+use automerge_protocol as amp;
+
+type Props<K> = BTreeMap<K, BTreeMap<amp::OpId, amp::Diff>>;
+
+/// Describe how the document in `input_data` changed between the heads `from` and `to`, one
+/// line per edit. Paths use the same syntax as change scripts. `from` defaults to the empty
+/// document and `to` to the current heads.
+fn diff_lines(
+    input_data: &[u8],
+    from: Option<amp::ChangeHash>,
+    to: Option<amp::ChangeHash>,
+) -> Result<Vec<String>> {
+    let backend = Backend::load(input_data.to_vec())?;
+    let from: Vec<_> = from.into_iter().collect();
+    let to = match to {
+        Some(to) => vec![to],
+        None => backend.get_heads(),
+    };
+    let diff = backend.diff(&from, &to)?;
+
+    // The diff of an object looks the same whether it has just been created or only edited
+    let mut existing = HashSet::new();
+    for value in backend.get_patch_at(&from)?.diffs.props.values() {
+        for diff in value.values() {
+            objects_in(diff, &mut existing);
+        }
+    }
+
+    let mut describer = Describer {
+        existing: &existing,
+        lines: Vec::new(),
+    };
+    describer.props("$", &diff.props);
+    Ok(describer.lines)
+}
+
+/// Add the IDs of the objects in `diff` to `objects`
+fn objects_in(diff: &amp::Diff, objects: &mut HashSet<amp::ObjectId>) {
+    match diff {
+        amp::Diff::Map(amp::MapDiff { object_id, props })
+        | amp::Diff::Table(amp::TableDiff { object_id, props }) => {
+            objects.insert(object_id.clone());
+            for diff in props.values().flat_map(BTreeMap::values) {
+                objects_in(diff, objects);
+            }
+        }
+        amp::Diff::List(amp::ListDiff { object_id, edits })
+        | amp::Diff::Text(amp::TextDiff { object_id, edits }) => {
+            objects.insert(object_id.clone());
+            for edit in edits {
+                if let amp::DiffEdit::SingleElementInsert { value, .. }
+                | amp::DiffEdit::Update { value, .. } = edit
+                {
+                    objects_in(value, objects);
+                }
+            }
+        }
+        amp::Diff::Value(_) | amp::Diff::Cursor(_) => {}
+    }
+}
+
+struct Describer<'a> {
+    /// The objects which were in the document at `from`
+    existing: &'a HashSet<amp::ObjectId>,
+    lines: Vec<String>,
+}
+
+impl Describer<'_> {
+    fn props<K: std::ops::Deref<Target = str>>(&mut self, path: &str, props: &Props<K>) {
+        for (key, values) in props {
+            let path = format!("{}[{:?}]", path, &**key);
+            if values.is_empty() {
+                self.lines.push(format!("- delete {}", path));
+            }
+            let conflict = if values.len() > 1 { " (conflict)" } else { "" };
+            for (op_id, diff) in values {
+                let action = format!("set {}", path);
+                self.value(&path, &action, op_id, diff, conflict);
+            }
+        }
+    }
+
+    /// `action` is how the value at `path` was changed, e.g. `set $["birds"]`
+    fn value(
+        &mut self,
+        path: &str,
+        action: &str,
+        op_id: &amp::OpId,
+        diff: &amp::Diff,
+        conflict: &str,
+    ) {
+        let object_id = match diff {
+            amp::Diff::Value(value) => {
+                self.lines.push(format!(
+                    "+ {} = {} by {}{}",
+                    action,
+                    scalar(value),
+                    op_id,
+                    conflict
+                ));
+                return;
+            }
+            amp::Diff::Cursor(cursor) => {
+                self.lines.push(format!(
+                    "+ {} = cursor to {} by {}{}",
+                    action, cursor.elem_id, op_id, conflict
+                ));
+                return;
+            }
+            amp::Diff::Map(amp::MapDiff { object_id, .. })
+            | amp::Diff::Table(amp::TableDiff { object_id, .. })
+            | amp::Diff::List(amp::ListDiff { object_id, .. })
+            | amp::Diff::Text(amp::TextDiff { object_id, .. }) => object_id,
+        };
+        if let Some(obj_type) = diff.object_type() {
+            if !self.existing.contains(object_id) {
+                self.lines.push(format!(
+                    "+ {} = new {} by {}{}",
+                    action, obj_type, op_id, conflict
+                ));
+            }
+        }
+        match diff {
+            amp::Diff::Map(amp::MapDiff { props, .. })
+            | amp::Diff::Table(amp::TableDiff { props, .. }) => self.props(path, props),
+            amp::Diff::List(amp::ListDiff { edits, .. })
+            | amp::Diff::Text(amp::TextDiff { edits, .. }) => self.edits(path, edits),
+            amp::Diff::Value(_) | amp::Diff::Cursor(_) => {}
+        }
+    }
+
+    fn edits(&mut self, path: &str, edits: &[amp::DiffEdit]) {
+        for edit in edits {
+            match edit {
+                amp::DiffEdit::SingleElementInsert {
+                    index,
+                    op_id,
+                    value,
+                    ..
+                } => {
+                    let element = format!("{}[{}]", path, index);
+                    let action = format!("insert {}", element);
+                    self.value(&element, &action, op_id, value, "");
+                }
+                amp::DiffEdit::MultiElementInsert(insert) => {
+                    let values: Vec<_> = insert.values.iter().map(scalar).collect();
+                    self.lines.push(format!(
+                        "+ insert {}[{}] = {}",
+                        path,
+                        insert.index,
+                        values.join(", ")
+                    ));
+                }
+                amp::DiffEdit::TextInsert(insert) => self.lines.push(format!(
+                    "+ insert {}[{}] = {:?}",
+                    path, insert.index, insert.text
+                )),
+                amp::DiffEdit::Update {
+                    index,
+                    op_id,
+                    value,
+                } => {
+                    let element = format!("{}[{}]", path, index);
+                    let action = format!("set {}", element);
+                    self.value(&element, &action, op_id, value, "");
+                }
+                amp::DiffEdit::Remove { index, count } => self
+                    .lines
+                    .push(format!("- delete {}[{}] ({} elements)", path, index, count)),
+                amp::DiffEdit::Marks(marks) => {
+                    for mark in &marks.marks {
+                        self.lines.push(format!(
+                            "~ mark {}[{}..{}] {} = {}",
+                            path,
+                            mark.start,
+                            mark.end,
+                            mark.name,
+                            scalar(&mark.value)
+                        ))
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn scalar(value: &amp::ScalarValue) -> String {
+    match value {
+        amp::ScalarValue::Counter(c) => format!("counter {}", c),
+        amp::ScalarValue::Timestamp(t) => format!("timestamp {}", t),
+        other => serde_json::to_string(other).unwrap_or_else(|_| other.to_string()),
+    }
+}
+
+/// Print the edits which turn the document at `from` into the document at `to`
+pub fn diff(
+    mut input: impl std::io::Read,
+    mut output: impl std::io::Write,
+    from: Option<amp::ChangeHash>,
+    to: Option<amp::ChangeHash>,
+) -> Result<()> {
+    let mut input_data = vec![];
+    input.read_to_end(&mut input_data)?;
+
+    for line in diff_lines(&input_data, from, to)? {
+        writeln!(output, "{}", line)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use automerge_frontend::{Frontend, InvalidChangeRequest, LocalChange, Path, Value};
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn cli_diff_between_changes() {
+        let value = Value::from_json(&json!({"birds": {"wrens": 3.0, "magpies": 1.0}}));
+        let (mut frontend, initial_change) = Frontend::new_with_initial_state(value).unwrap();
+        let mut backend = Backend::new();
+        let patch = backend.apply_local_change(initial_change).unwrap().0;
+        frontend.apply_patch(patch).unwrap();
+        let first = backend.get_heads()[0];
+        let (_, change) = frontend
+            .change::<_, _, InvalidChangeRequest>(None, |d| {
+                d.add_change(LocalChange::set(
+                    Path::root().key("birds").key("wrens"),
+                    Value::from_json(&json!(4.0)),
+                ))?;
+                d.add_change(LocalChange::delete(
+                    Path::root().key("birds").key("magpies"),
+                ))?;
+                d.add_change(LocalChange::set(
+                    Path::root().key("seen"),
+                    Value::from_json(&json!(["wren"])),
+                ))
+            })
+            .unwrap();
+        backend.apply_local_change(change.unwrap()).unwrap();
+        let data = backend.save().unwrap();
+        let actor = frontend.actor_id.to_string();
+
+        assert_eq!(
+            diff_lines(&data, Some(first), None).unwrap(),
+            vec![
+                r#"- delete $["birds"]["magpies"]"#.to_string(),
+                format!(r#"+ set $["birds"]["wrens"] = 4.0 by 4@{}"#, actor),
+                format!(r#"+ set $["seen"] = new list by 6@{}"#, actor),
+                format!(r#"+ insert $["seen"][0] = "wren" by 7@{}"#, actor),
+            ]
+        );
+        assert_eq!(
+            diff_lines(&data, Some(first), Some(first)).unwrap().len(),
+            0
+        );
+        assert_eq!(diff_lines(&data, None, Some(first)).unwrap().len(), 3);
+    }
+}
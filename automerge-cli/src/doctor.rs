@@ -0,0 +1,149 @@
+use std::{fs::File, io::Read, path::PathBuf};
+
+use anyhow::Result;
+use automerge_backend as amb;
+use automerge_frontend::Frontend;
+use clap::Clap;
+use serde_json::{json, Value};
+
+/// Check that a saved Automerge document survives a round trip through the backend and
+/// frontend, and print a JSON report. Exits with a non-zero status if any check fails.
+#[derive(Debug, Clap)]
+#[clap(name = "automerge-doctor")]
+struct Opts {
+    /// The saved document, if omitted the document is read from stdin
+    #[clap(parse(from_os_str))]
+    input_file: Option<PathBuf>,
+}
+
+struct Check {
+    name: &'static str,
+    problems: Vec<String>,
+}
+
+impl Check {
+    fn new(name: &'static str) -> Check {
+        Check {
+            name,
+            problems: Vec::new(),
+        }
+    }
+
+    fn expect_eq<T: PartialEq + std::fmt::Debug>(&mut self, what: &str, expected: T, found: T) {
+        if expected != found {
+            self.problems.push(format!(
+                "{} differ: expected {:?}, found {:?}",
+                what, expected, found
+            ));
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "name": self.name,
+            "ok": self.problems.is_empty(),
+            "problems": self.problems,
+        })
+    }
+}
+
+fn materialize(backend: &amb::Backend) -> Result<Value> {
+    let mut frontend = Frontend::new();
+    frontend.apply_patch(backend.get_patch()?)?;
+    Ok(frontend.state().to_json())
+}
+
+fn sorted_hashes(backend: &amb::Backend) -> Vec<automerge_protocol::ChangeHash> {
+    let mut hashes: Vec<_> = backend.get_changes(&[]).iter().map(|c| c.hash).collect();
+    hashes.sort_unstable();
+    hashes
+}
+
+/// Run every check on `data`, the report is `Err` if the document can't be loaded at all
+fn diagnose(data: &[u8]) -> Result<(Value, bool)> {
+    // `load` skips anything after the last chunk it can read, so bytes which don't start with a
+    // chunk at all load as an empty document
+    let mut decoded = 0;
+    let backend = amb::Backend::load_with_progress(data, |phase, done, _| {
+        if phase == amb::LoadPhase::Decode {
+            decoded = done;
+        }
+    })?;
+    if decoded == 0 && !data.is_empty() {
+        anyhow::bail!("no Automerge chunks found in {} bytes", data.len());
+    }
+    let heads = backend.get_heads();
+    let hashes = sorted_hashes(&backend);
+    let mut checks = Vec::new();
+
+    let mut consistency = Check::new("consistency");
+    consistency
+        .problems
+        .extend(backend.fsck().iter().map(ToString::to_string));
+    checks.push(consistency);
+
+    let mut resave = Check::new("resave");
+    match backend.save().and_then(amb::Backend::load) {
+        Ok(reloaded) => {
+            resave.expect_eq("heads", &heads, &reloaded.get_heads());
+            resave.expect_eq("change hashes", &hashes, &sorted_hashes(&reloaded));
+        }
+        Err(e) => resave.problems.push(e.to_string()),
+    }
+    checks.push(resave);
+
+    // Replaying the changes one at a time exercises the incremental patches, which should end up
+    // in the same state as the patch a frontend would be given on load
+    let mut replay = Check::new("replay");
+    let result = (|| -> Result<()> {
+        let expected = materialize(&backend)?;
+        let mut replayed = amb::Backend::new();
+        let mut frontend = Frontend::new();
+        for change in amb::Change::load_document(data)? {
+            let recomputed = amb::Change::from(change.decode()).hash;
+            replay.expect_eq("change hash", change.hash, recomputed);
+            frontend.apply_patch(replayed.apply_changes(vec![change])?)?;
+        }
+        replay.expect_eq("heads", &heads, &replayed.get_heads());
+        replay.expect_eq("state", &expected, &frontend.state().to_json());
+        Ok(())
+    })();
+    if let Err(e) = result {
+        replay.problems.push(e.to_string());
+    }
+    checks.push(replay);
+
+    let ok = checks.iter().all(|check| check.problems.is_empty());
+    let report = json!({
+        "ok": ok,
+        "changes": hashes.len(),
+        "heads": heads,
+        "checks": checks.iter().map(Check::to_json).collect::<Vec<_>>(),
+    });
+    Ok((report, ok))
+}
+
+fn main() -> Result<()> {
+    let opts = Opts::parse();
+    let mut data = Vec::new();
+    match opts.input_file {
+        Some(path) => File::open(path)?.read_to_end(&mut data)?,
+        None => std::io::stdin().read_to_end(&mut data)?,
+    };
+
+    let (report, ok) = match diagnose(&data) {
+        Ok(result) => result,
+        Err(e) => (
+            json!({
+                "ok": false,
+                "checks": [{"name": "load", "ok": false, "problems": [e.to_string()]}],
+            }),
+            false,
+        ),
+    };
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    if !ok {
+        std::process::exit(1);
+    }
+    Ok(())
+}
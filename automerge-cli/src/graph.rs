@@ -0,0 +1,28 @@
+use anyhow::Result;
+use automerge_backend::{Backend, GraphFormat};
+
+/// Print the change graph of a document as Graphviz DOT or a Mermaid flowchart
+pub fn graph(
+    mut input: impl std::io::Read,
+    mut output: impl std::io::Write,
+    format: GraphFormat,
+) -> Result<()> {
+    let mut input_data = vec![];
+    input.read_to_end(&mut input_data)?;
+
+    let backend = Backend::load(input_data)?;
+    write!(output, "{}", backend.export_change_graph(format))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cli_graph_with_empty_input() {
+        let mut output = Vec::new();
+        graph(&[][..], &mut output, GraphFormat::Mermaid).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "flowchart LR\n");
+    }
+}
@@ -0,0 +1,76 @@
+use anyhow::Result;
+use automerge_backend::Backend;
+
+/// One line per change in `input_data`, in the order they were applied: hash, actor, seq,
+/// time and message
+fn history_lines(input_data: &[u8]) -> Result<Vec<String>> {
+    let backend = Backend::load(input_data.to_vec())?;
+    Ok(backend
+        .history()
+        .map(|(hash, change)| {
+            let mut line = format!(
+                "{} {} {} {}",
+                hash,
+                change.actor_id(),
+                change.seq,
+                change.time
+            );
+            if let Some(message) = change.message() {
+                line.push(' ');
+                line.push_str(&message);
+            }
+            line
+        })
+        .collect())
+}
+
+/// Print the changes in a document, oldest first
+pub fn history(mut input: impl std::io::Read, mut output: impl std::io::Write) -> Result<()> {
+    let mut input_data = vec![];
+    input.read_to_end(&mut input_data)?;
+
+    for line in history_lines(&input_data)? {
+        writeln!(output, "{}", line)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use automerge_frontend::{Frontend, InvalidChangeRequest, LocalChange, Path, Value};
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn cli_history_with_empty_input() {
+        assert!(history_lines(&[]).unwrap().is_empty())
+    }
+
+    #[test]
+    fn cli_history_lists_changes() {
+        let value = Value::from_json(&json!({"birds": 1.0}));
+        let (mut frontend, initial_change) = Frontend::new_with_initial_state(value).unwrap();
+        let mut backend = Backend::new();
+        let patch = backend.apply_local_change(initial_change).unwrap().0;
+        frontend.apply_patch(patch).unwrap();
+        let (_, change) = frontend
+            .change::<_, _, InvalidChangeRequest>(Some("more birds".into()), |d| {
+                d.add_change(LocalChange::set(
+                    Path::root().key("birds"),
+                    Value::from_json(&json!(2.0)),
+                ))
+            })
+            .unwrap();
+        backend.apply_local_change(change.unwrap()).unwrap();
+
+        let lines = history_lines(&backend.save().unwrap()).unwrap();
+        let actor = frontend.actor_id.to_string();
+        assert_eq!(lines.len(), 2);
+        let second: Vec<_> = lines[1].splitn(5, ' ').collect();
+        assert_eq!(second[0], backend.get_heads()[0].to_string());
+        assert_eq!(second[1], actor);
+        assert_eq!(second[2], "2");
+        assert_eq!(second[4], "more birds");
+    }
+}
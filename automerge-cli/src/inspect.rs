@@ -0,0 +1,61 @@
+use anyhow::Result;
+use serde_json::json;
+
+fn inspect_json(input_data: &[u8]) -> Result<serde_json::Value> {
+    let backend = automerge_backend::Backend::load(input_data.to_vec())?;
+    let mut frontend = automerge_frontend::Frontend::new();
+    frontend.apply_patch(backend.get_patch()?)?;
+
+    Ok(json!({
+        "heads": backend.get_heads(),
+        "changes": backend.get_changes(&[]).len(),
+        "maxOp": backend.max_op(),
+        "value": frontend.state().to_json(),
+    }))
+}
+
+/// Print the value of a document along with its heads
+pub fn inspect(
+    mut input: impl std::io::Read,
+    mut output: impl std::io::Write,
+    is_tty: bool,
+) -> Result<()> {
+    let mut input_data = vec![];
+    input.read_to_end(&mut input_data)?;
+
+    let summary = inspect_json(&input_data)?;
+    if is_tty {
+        colored_json::write_colored_json(&summary, &mut output)?;
+        writeln!(output)?;
+    } else {
+        writeln!(output, "{}", serde_json::to_string_pretty(&summary)?)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cli_inspect_with_empty_input() {
+        assert_eq!(
+            inspect_json(&[]).unwrap(),
+            json!({"heads": [], "changes": 0, "maxOp": 0, "value": {}})
+        )
+    }
+
+    #[test]
+    fn cli_inspect_shows_heads() {
+        let value = automerge_frontend::Value::from_json(&json!({"sparrows": 15.0}));
+        let (_, initial_change) =
+            automerge_frontend::Frontend::new_with_initial_state(value).unwrap();
+        let mut backend = automerge_backend::Backend::new();
+        backend.apply_local_change(initial_change).unwrap();
+
+        let summary = inspect_json(&backend.save().unwrap()).unwrap();
+        assert_eq!(summary["heads"], json!(backend.get_heads()));
+        assert_eq!(summary["changes"], 1);
+        assert_eq!(summary["value"], json!({"sparrows": 15.0}));
+    }
+}
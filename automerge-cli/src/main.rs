@@ -4,9 +4,14 @@ use anyhow::{anyhow, Result};
 use clap::Clap;
 
 mod change;
+mod diff;
 mod examine;
 mod export;
+mod graph;
+mod history;
 mod import;
+mod inspect;
+mod merge;
 
 #[derive(Debug, Clap)]
 #[clap(about = "Automerge CLI")]
@@ -33,6 +38,24 @@ impl FromStr for ExportFormat {
     }
 }
 
+#[derive(Debug)]
+enum GraphFormat {
+    Dot,
+    Mermaid,
+}
+
+impl FromStr for GraphFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(input: &str) -> Result<GraphFormat> {
+        match input {
+            "dot" => Ok(GraphFormat::Dot),
+            "mermaid" => Ok(GraphFormat::Mermaid),
+            _ => Err(anyhow!("Invalid graph format: {}", input)),
+        }
+    }
+}
+
 #[derive(Debug, Clap)]
 enum Command {
     /// Output current state of an Automerge document in a specified format
@@ -41,6 +64,10 @@ enum Command {
         #[clap(long, short, default_value = "json")]
         format: ExportFormat,
 
+        /// Shorthand for `--format json`
+        #[clap(long, conflicts_with = "format")]
+        json: bool,
+
         /// Path that contains Automerge changes
         #[clap(parse(from_os_str))]
         changes_file: Option<PathBuf>,
@@ -99,6 +126,62 @@ enum Command {
 
     /// Read an automerge document and print a JSON representation of the changes in it to stdout
     Examine { input_file: Option<PathBuf> },
+
+    /// Merge two automerge documents, writing the result to stdout or the specified output file
+    /// and a JSON report of the changes merged and any conflicting values to stderr
+    Merge {
+        #[clap(parse(from_os_str))]
+        first_file: PathBuf,
+
+        #[clap(parse(from_os_str))]
+        second_file: PathBuf,
+
+        /// Path to write the merged document to, if omitted will write to stdout
+        #[clap(parse(from_os_str), long("out"), short('o'))]
+        output_file: Option<PathBuf>,
+    },
+
+    /// Read an automerge document and print its current value and heads to stdout
+    Inspect {
+        /// The document to inspect, if omitted will assume stdin
+        #[clap(parse(from_os_str))]
+        input_file: Option<PathBuf>,
+    },
+
+    /// List the changes in an automerge document, oldest first, one per line: hash, actor, seq,
+    /// time and message
+    History {
+        /// The document to list the changes of, if omitted will assume stdin
+        #[clap(parse(from_os_str))]
+        input_file: Option<PathBuf>,
+    },
+
+    /// Print the edits which turn one version of an automerge document into another
+    Diff {
+        /// The document to compare versions of, if omitted will assume stdin
+        #[clap(parse(from_os_str))]
+        input_file: Option<PathBuf>,
+
+        /// The hash of the change to compare from, if omitted compares from the empty document
+        #[clap(long)]
+        from: Option<automerge_protocol::ChangeHash>,
+
+        /// The hash of the change to compare to, if omitted compares to the latest version
+        #[clap(long)]
+        to: Option<automerge_protocol::ChangeHash>,
+    },
+
+    /// Print the graph of changes in an automerge document and their dependencies, labelled
+    /// with each change's actor, seq and message
+    Graph {
+        /// Format for output: dot, mermaid
+        #[clap(long, short, default_value = "dot")]
+        format: GraphFormat,
+
+        /// The document to draw the changes of, if omitted will assume stdin
+        #[clap(parse(from_os_str))]
+        input_file: Option<PathBuf>,
+    },
 }
 
 fn open_file_or_stdin(maybe_path: Option<PathBuf>) -> Result<Box<dyn std::io::Read>> {
@@ -133,7 +216,8 @@ fn main() -> Result<()> {
         Command::Export {
             changes_file,
             format,
-        } => match format {
+            json,
+        } => match if json { ExportFormat::Json } else { format } {
             ExportFormat::Json => {
                 let mut in_buffer = open_file_or_stdin(changes_file)?;
                 export::export_json(
@@ -178,5 +262,41 @@ fn main() -> Result<()> {
             }
             Ok(())
         }
+        Command::Merge {
+            first_file,
+            second_file,
+            output_file,
+        } => {
+            let first = File::open(&first_file)?;
+            let second = File::open(&second_file)?;
+            let mut out_buffer = create_file_or_stdout(output_file)?;
+            let report = merge::merge(first, second, &mut out_buffer)?;
+            eprintln!("{}", serde_json::to_string_pretty(&report.to_json())?);
+            Ok(())
+        }
+        Command::Inspect { input_file } => {
+            let in_buffer = open_file_or_stdin(input_file)?;
+            inspect::inspect(in_buffer, std::io::stdout(), atty::is(atty::Stream::Stdout))
+        }
+        Command::History { input_file } => {
+            let in_buffer = open_file_or_stdin(input_file)?;
+            history::history(in_buffer, std::io::stdout())
+        }
+        Command::Diff {
+            input_file,
+            from,
+            to,
+        } => {
+            let in_buffer = open_file_or_stdin(input_file)?;
+            diff::diff(in_buffer, std::io::stdout(), from, to)
+        }
+        Command::Graph { format, input_file } => {
+            let in_buffer = open_file_or_stdin(input_file)?;
+            let format = match format {
+                GraphFormat::Dot => automerge_backend::GraphFormat::Dot,
+                GraphFormat::Mermaid => automerge_backend::GraphFormat::Mermaid,
+            };
+            graph::graph(in_buffer, std::io::stdout(), format)
+        }
     }
 }
@@ -0,0 +1,151 @@
+use anyhow::Result;
+use automerge_backend::Backend;
+use automerge_frontend::{Frontend, Path, Value};
+use serde_json::json;
+
+/// The outcome of merging two documents
+#[derive(Debug)]
+pub struct MergeReport {
+    /// Changes from the second document which the first was missing
+    pub merged_into_first: usize,
+    /// Changes from the first document which the second was missing
+    pub merged_into_second: usize,
+    /// Paths in the merged document which have conflicting values, in the same syntax as change
+    /// scripts
+    pub conflicts: Vec<String>,
+}
+
+impl MergeReport {
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "mergedIntoFirst": self.merged_into_first,
+            "mergedIntoSecond": self.merged_into_second,
+            "conflicts": self.conflicts,
+        })
+    }
+}
+
+/// Merge the saved documents `first` and `second`, returning the saved result
+pub fn merge_documents(first: &[u8], second: &[u8]) -> Result<(Vec<u8>, MergeReport)> {
+    let mut merged = Backend::load(first.to_vec())?;
+    let other = Backend::load(second.to_vec())?;
+    let missing: Vec<_> = merged
+        .get_changes_added(&other)
+        .into_iter()
+        .cloned()
+        .collect();
+    let merged_into_first = missing.len();
+    let merged_into_second = other.get_changes_added(&merged).len();
+    merged.apply_changes(missing)?;
+
+    let mut frontend = Frontend::new();
+    frontend.apply_patch(merged.get_patch()?)?;
+    let mut conflicts = Vec::new();
+    let root = frontend.state().clone();
+    find_conflicts(
+        &frontend,
+        &root,
+        Path::root(),
+        "$".to_string(),
+        &mut conflicts,
+    );
+    conflicts.sort();
+
+    let report = MergeReport {
+        merged_into_first,
+        merged_into_second,
+        conflicts,
+    };
+    Ok((merged.save()?, report))
+}
+
+fn find_conflicts(
+    frontend: &Frontend,
+    value: &Value,
+    path: Path,
+    display: String,
+    conflicts: &mut Vec<String>,
+) {
+    let mut visit = |child: &Value, child_path: Path, child_display: String| {
+        if frontend
+            .get_conflicts(&child_path)
+            .map_or(false, |c| c.len() > 1)
+        {
+            conflicts.push(child_display.clone());
+        }
+        find_conflicts(frontend, child, child_path, child_display, conflicts);
+    };
+    match value {
+        Value::Map(props) | Value::Table(props) => {
+            for (key, child) in props {
+                visit(
+                    child,
+                    path.clone().key(key.clone()),
+                    format!("{}[{:?}]", display, key.as_str()),
+                );
+            }
+        }
+        Value::List(elems) => {
+            for (index, child) in elems.iter().enumerate() {
+                visit(
+                    child,
+                    path.clone().index(index as u32),
+                    format!("{}[{}]", display, index),
+                );
+            }
+        }
+        Value::Text(_) | Value::Primitive(_) => {}
+    }
+}
+
+pub fn merge(
+    mut first: impl std::io::Read,
+    mut second: impl std::io::Read,
+    mut output: impl std::io::Write,
+) -> Result<MergeReport> {
+    let mut first_data = Vec::new();
+    first.read_to_end(&mut first_data)?;
+    let mut second_data = Vec::new();
+    second.read_to_end(&mut second_data)?;
+
+    let (merged, report) = merge_documents(&first_data, &second_data)?;
+    output.write_all(&merged)?;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use automerge_frontend::{InvalidChangeRequest, LocalChange};
+
+    use super::*;
+
+    fn fork_and_set(base: &[u8], actor: &[u8], key: &str, value: &str) -> Vec<u8> {
+        let mut backend = Backend::load(base.to_vec()).unwrap();
+        let mut frontend = Frontend::new_with_actor_id(actor);
+        frontend.apply_patch(backend.get_patch().unwrap()).unwrap();
+        let (_, change) = frontend
+            .change::<_, _, InvalidChangeRequest>(None, |d| {
+                d.add_change(LocalChange::set(Path::root().key("birds").key(key), value))
+            })
+            .unwrap();
+        backend.apply_local_change(change.unwrap()).unwrap();
+        backend.save().unwrap()
+    }
+
+    #[test]
+    fn cli_merge_reports_changes_and_conflicts() {
+        let value = Value::from_json(&json!({"birds": {"wrens": 3.0}}));
+        let (_, initial_change) = Frontend::new_with_initial_state(value).unwrap();
+        let mut backend = Backend::new();
+        backend.apply_local_change(initial_change).unwrap();
+        let base = backend.save().unwrap();
+
+        let first = fork_and_set(&base, &[1], "wrens", "many");
+        let second = fork_and_set(&base, &[2], "wrens", "few");
+        let (merged, report) = merge_documents(&first, &second).unwrap();
+        assert_eq!(report.merged_into_first, 1);
+        assert_eq!(report.merged_into_second, 1);
+        assert_eq!(report.conflicts, vec![r#"$["birds"]["wrens"]"#.to_string()]);
+        assert_eq!(Backend::load(merged).unwrap().get_changes(&[]).len(), 3);
+    }
+}
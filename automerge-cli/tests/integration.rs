@@ -89,3 +89,55 @@ fn import_change_export() {
     });
     assert_eq!(result, expected);
 }
+
+#[test]
+fn doctor_reports_on_imported_document() {
+    let bin = env!("CARGO_BIN_EXE_automerge");
+    let doctor = env!("CARGO_BIN_EXE_automerge-doctor");
+    let json_bytes = serde_json::to_string(&serde_json::json!({"birds": ["wren"]})).unwrap();
+
+    let stdout = cmd!(bin, "import")
+        .stdin_bytes(json_bytes)
+        .pipe(cmd!(doctor))
+        .read()
+        .unwrap();
+    let report: serde_json::Value = serde_json::from_str(stdout.as_str()).unwrap();
+    assert_eq!(report["ok"], true);
+    assert_eq!(report["changes"], 1);
+    assert_eq!(report["checks"].as_array().unwrap().len(), 3);
+
+    let garbage = cmd!(doctor)
+        .stdin_bytes(b"not a document".to_vec())
+        .stdout_capture()
+        .unchecked()
+        .run()
+        .unwrap();
+    assert!(!garbage.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&garbage.stdout).unwrap();
+    assert_eq!(report["checks"][0]["name"], "load");
+}
+
+#[test]
+fn inspect_imported_document() {
+    let bin = env!("CARGO_BIN_EXE_automerge");
+    let initial_state_json = serde_json::json!({"birds": {"wrens": 3.0}});
+    let json_bytes = serde_json::to_string_pretty(&initial_state_json).unwrap();
+
+    let stdout = cmd!(bin, "import")
+        .stdin_bytes(json_bytes.clone())
+        .pipe(cmd!(bin, "inspect"))
+        .read()
+        .unwrap();
+    let summary: serde_json::Value = serde_json::from_str(stdout.as_str()).unwrap();
+    assert_eq!(summary["value"], initial_state_json);
+    assert_eq!(summary["changes"], 1);
+    assert_eq!(summary["heads"].as_array().unwrap().len(), 1);
+
+    let stdout = cmd!(bin, "import")
+        .stdin_bytes(json_bytes)
+        .pipe(cmd!(bin, "export", "--json"))
+        .read()
+        .unwrap();
+    let result: serde_json::Value = serde_json::from_str(stdout.as_str()).unwrap();
+    assert_eq!(result, initial_state_json);
+}
@@ -0,0 +1,242 @@
+//! `#[derive(Automerge)]`, which implements `automerge_frontend::Reconcile` and
+//! `automerge_frontend::Hydrate` so a Rust type can be written into and read out of a document.
+//!
+//! Structs with named fields become maps with a key per field and enums whose variants are all
+//! unit variants become strings. Fields take two attributes:
+//!
+//! - `#[automerge(rename = "key")]` stores the field under `key` rather than its name
+//! - `#[automerge(default)]` hydrates the field with `Default::default()` if it is missing
+//!
+//! ```
+//! use automerge_derive::Automerge;
+//! use automerge_frontend::{Frontend, InvalidChangeRequest, Path, Reconcile};
+//!
+//! #[derive(Automerge, Debug, PartialEq)]
+//! struct Bird {
+//!     name: String,
+//!     #[automerge(rename = "seenAt")]
+//!     seen_at: Vec<u32>,
+//! }
+//!
+//! let bird = Bird { name: "wren".into(), seen_at: vec![3, 5] };
+//! let mut doc = Frontend::new();
+//! doc.change::<_, _, InvalidChangeRequest>(None, |d| {
+//!     bird.reconcile(d, &Path::root().key("bird"))
+//! })
+//! .unwrap();
+//! assert_eq!(doc.hydrate::<Bird>(&Path::root().key("bird")).unwrap(), bird);
+//! ```
+extern crate proc_macro;
+
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{
+    parse_macro_input, spanned::Spanned, Data, DeriveInput, Fields, GenericParam, Generics, Lit,
+    Meta, NestedMeta,
+};
+
+#[proc_macro_derive(Automerge, attributes(automerge))]
+pub fn derive_automerge(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream> {
+    match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => {
+                let fields = fields
+                    .named
+                    .iter()
+                    .map(Field::parse)
+                    .collect::<syn::Result<Vec<_>>>()?;
+                Ok(expand_struct(input, &fields))
+            }
+            _ => Err(syn::Error::new(
+                input.ident.span(),
+                "Automerge can only be derived for structs with named fields",
+            )),
+        },
+        Data::Enum(data) => {
+            let variants = data
+                .variants
+                .iter()
+                .map(|variant| match variant.fields {
+                    Fields::Unit => Ok(&variant.ident),
+                    _ => Err(syn::Error::new(
+                        variant.span(),
+                        "Automerge can only be derived for enums without fields",
+                    )),
+                })
+                .collect::<syn::Result<Vec<_>>>()?;
+            Ok(expand_enum(input, &variants))
+        }
+        Data::Union(_) => Err(syn::Error::new(
+            input.ident.span(),
+            "Automerge can't be derived for unions",
+        )),
+    }
+}
+
+struct Field<'a> {
+    ident: &'a syn::Ident,
+    key: String,
+    default: bool,
+}
+
+impl<'a> Field<'a> {
+    fn parse(field: &'a syn::Field) -> syn::Result<Self> {
+        let ident = field.ident.as_ref().expect("named fields have names");
+        let mut parsed = Field {
+            ident,
+            key: ident.to_string(),
+            default: false,
+        };
+        for attr in field.attrs.iter().filter(|a| a.path.is_ident("automerge")) {
+            let list = match attr.parse_meta()? {
+                Meta::List(list) => list,
+                other => return Err(syn::Error::new(other.span(), "expected automerge(...)")),
+            };
+            for nested in list.nested {
+                match nested {
+                    NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("rename") => match nv
+                        .lit
+                    {
+                        Lit::Str(s) => parsed.key = s.value(),
+                        other => return Err(syn::Error::new(other.span(), "expected a string")),
+                    },
+                    NestedMeta::Meta(Meta::Path(p)) if p.is_ident("default") => {
+                        parsed.default = true;
+                    }
+                    other => {
+                        return Err(syn::Error::new(
+                            other.span(),
+                            "unknown automerge attribute, expected `rename` or `default`",
+                        ))
+                    }
+                }
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+/// `generics` with `bound` added to every type parameter
+fn with_bound(generics: &Generics, bound: TokenStream) -> Generics {
+    let mut generics = generics.clone();
+    for param in &mut generics.params {
+        if let GenericParam::Type(param) = param {
+            param.bounds.push(syn::parse2(bound.clone()).unwrap());
+        }
+    }
+    generics
+}
+
+fn expand_struct(input: &DeriveInput, fields: &[Field]) -> TokenStream {
+    let name = &input.ident;
+
+    let reconcile_generics = with_bound(&input.generics, quote!(::automerge_frontend::Reconcile));
+    let (impl_generics, ty_generics, where_clause) = reconcile_generics.split_for_impl();
+    let reconcile_fields = fields.iter().map(|Field { ident, key, .. }| {
+        quote! {
+            ::automerge_frontend::Reconcile::reconcile(&self.#ident, doc, &path.clone().key(#key))?;
+        }
+    });
+    let reconcile = quote! {
+        impl #impl_generics ::automerge_frontend::Reconcile for #name #ty_generics #where_clause {
+            fn reconcile(
+                &self,
+                doc: &mut dyn ::automerge_frontend::MutableDocument,
+                path: &::automerge_frontend::Path,
+            ) -> ::std::result::Result<(), ::automerge_frontend::InvalidChangeRequest> {
+                ::automerge_frontend::reconcile::reconcile_map(doc, path)?;
+                #(#reconcile_fields)*
+                ::std::result::Result::Ok(())
+            }
+        }
+    };
+
+    let hydrate_generics = with_bound(&input.generics, quote!(::automerge_frontend::Hydrate));
+    let (impl_generics, ty_generics, where_clause) = hydrate_generics.split_for_impl();
+    let hydrate_fields = fields.iter().map(|Field { ident, key, default }| {
+        if *default {
+            quote! {
+                #ident: match ::automerge_frontend::reconcile::field(value, #key) {
+                    ::std::result::Result::Ok(v) => ::automerge_frontend::Hydrate::hydrate(v)?,
+                    ::std::result::Result::Err(::automerge_frontend::HydrateError::MissingField { .. }) => {
+                        ::std::default::Default::default()
+                    }
+                    ::std::result::Result::Err(e) => return ::std::result::Result::Err(e),
+                },
+            }
+        } else {
+            quote! {
+                #ident: ::automerge_frontend::Hydrate::hydrate(
+                    ::automerge_frontend::reconcile::field(value, #key)?,
+                )?,
+            }
+        }
+    });
+    let hydrate = quote! {
+        impl #impl_generics ::automerge_frontend::Hydrate for #name #ty_generics #where_clause {
+            fn hydrate(
+                value: &::automerge_frontend::Value,
+            ) -> ::std::result::Result<Self, ::automerge_frontend::HydrateError> {
+                ::std::result::Result::Ok(#name {
+                    #(#hydrate_fields)*
+                })
+            }
+        }
+    };
+
+    quote! {
+        #reconcile
+        #hydrate
+    }
+}
+
+fn expand_enum(input: &DeriveInput, variants: &[&syn::Ident]) -> TokenStream {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let keys: Vec<String> = variants.iter().map(|v| v.to_string()).collect();
+    let expected = format!("one of {}", keys.join(", "));
+
+    quote! {
+        impl #impl_generics ::automerge_frontend::Reconcile for #name #ty_generics #where_clause {
+            fn reconcile(
+                &self,
+                doc: &mut dyn ::automerge_frontend::MutableDocument,
+                path: &::automerge_frontend::Path,
+            ) -> ::std::result::Result<(), ::automerge_frontend::InvalidChangeRequest> {
+                let variant = match self {
+                    #(#name::#variants => #keys,)*
+                };
+                ::automerge_frontend::reconcile::reconcile_value(doc, path, variant.into())
+            }
+        }
+
+        impl #impl_generics ::automerge_frontend::Hydrate for #name #ty_generics #where_clause {
+            fn hydrate(
+                value: &::automerge_frontend::Value,
+            ) -> ::std::result::Result<Self, ::automerge_frontend::HydrateError> {
+                match value {
+                    ::automerge_frontend::Value::Primitive(::automerge_frontend::Primitive::Str(s)) => {
+                        match s.as_str() {
+                            #(#keys => ::std::result::Result::Ok(#name::#variants),)*
+                            _ => ::std::result::Result::Err(::automerge_frontend::HydrateError::Unexpected {
+                                expected: #expected,
+                                found: value.clone(),
+                            }),
+                        }
+                    }
+                    other => ::std::result::Result::Err(::automerge_frontend::HydrateError::Unexpected {
+                        expected: #expected,
+                        found: other.clone(),
+                    }),
+                }
+            }
+        }
+    }
+}
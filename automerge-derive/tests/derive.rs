@@ -0,0 +1,117 @@
+use std::collections::HashMap;
+
+use automerge_derive::Automerge;
+use automerge_frontend::{
+    Frontend, Hydrate, HydrateError, InvalidChangeRequest, LocalChange, Path, Primitive, Reconcile,
+    Value,
+};
+use pretty_assertions::assert_eq;
+
+#[derive(Automerge, Debug, Clone, PartialEq)]
+enum Habitat {
+    Woodland,
+    Wetland,
+}
+
+#[derive(Automerge, Debug, Clone, PartialEq)]
+struct Sighting {
+    count: u32,
+    #[automerge(rename = "seenBy")]
+    seen_by: Option<String>,
+}
+
+#[derive(Automerge, Debug, Clone, PartialEq)]
+struct Bird {
+    name: String,
+    habitat: Habitat,
+    sightings: Vec<Sighting>,
+    #[automerge(default)]
+    notes: HashMap<String, String>,
+}
+
+fn reconcile(doc: &mut Frontend, bird: &Bird) -> Option<automerge_protocol::Change> {
+    doc.change::<_, _, InvalidChangeRequest>(None, |d| bird.reconcile(d, &Path::root().key("bird")))
+        .unwrap()
+        .1
+}
+
+#[test]
+fn reconcile_and_hydrate_round_trip() {
+    let mut bird = Bird {
+        name: "wren".into(),
+        habitat: Habitat::Woodland,
+        sightings: vec![
+            Sighting {
+                count: 2,
+                seen_by: Some("ada".into()),
+            },
+            Sighting {
+                count: 1,
+                seen_by: None,
+            },
+        ],
+        notes: HashMap::new(),
+    };
+    let mut doc = Frontend::new();
+    reconcile(&mut doc, &bird);
+    assert_eq!(
+        doc.hydrate::<Bird>(&Path::root().key("bird")).unwrap(),
+        bird
+    );
+    assert_eq!(
+        doc.get_value(
+            &Path::root()
+                .key("bird")
+                .key("sightings")
+                .index(0)
+                .key("seenBy")
+        ),
+        Some(Value::Primitive(Primitive::Str("ada".into())))
+    );
+
+    // Reconciling the same value again changes nothing
+    assert!(reconcile(&mut doc, &bird).is_none());
+
+    // Only the parts which differ are written
+    bird.habitat = Habitat::Wetland;
+    bird.sightings.pop();
+    let change = reconcile(&mut doc, &bird).unwrap();
+    assert_eq!(change.operations.len(), 2);
+    assert_eq!(
+        doc.hydrate::<Bird>(&Path::root().key("bird")).unwrap(),
+        bird
+    );
+}
+
+#[test]
+fn hydrate_errors() {
+    let mut doc = Frontend::new();
+    doc.change::<_, _, InvalidChangeRequest>(None, |d| {
+        d.add_change(LocalChange::set(
+            Path::root().key("bird"),
+            Value::from_json(&serde_json::json!({
+                "name": "robin",
+                "habitat": "Desert",
+                "sightings": [],
+            })),
+        ))
+    })
+    .unwrap();
+    assert_eq!(
+        doc.hydrate::<Bird>(&Path::root().key("bird")),
+        Err(HydrateError::Unexpected {
+            expected: "one of Woodland, Wetland",
+            found: "Desert".into(),
+        })
+    );
+    assert_eq!(
+        Sighting::hydrate(&Value::Map(HashMap::new())),
+        Err(HydrateError::MissingField { field: "count" })
+    );
+    assert_eq!(
+        doc.hydrate::<Bird>(&Path::root().key("missing")),
+        Err(HydrateError::NoSuchPath {
+            path: Path::root().key("missing")
+        })
+    );
+}
@@ -2,7 +2,7 @@ use amp::RootDiff;
 use automerge_frontend::Frontend;
 use automerge_protocol as amp;
 use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
-use maplit::hashmap;
+use maplit::{btreemap, hashmap};
 
 pub fn sequential_inserts_in_multiple_patches(c: &mut Criterion) {
     let actor_id = amp::ActorId::random();
@@ -15,8 +15,8 @@ pub fn sequential_inserts_in_multiple_patches(c: &mut Criterion) {
         max_op: 1,
         pending_changes: 0,
         diffs: RootDiff {
-            props: hashmap! {
-                "text".into() => hashmap!{
+            props: btreemap! {
+                "text".into() => btreemap!{
                     make_list_opid.clone() => amp::Diff::Text(amp::TextDiff{
                         object_id: make_list_opid.clone().into(),
                         edits: Vec::new(),
@@ -36,8 +36,8 @@ pub fn sequential_inserts_in_multiple_patches(c: &mut Criterion) {
             max_op: op_num as u64,
             pending_changes: 0,
             diffs: RootDiff {
-                props: hashmap! {
-                    "text".into() => hashmap!{
+                props: btreemap! {
+                    "text".into() => btreemap!{
                         make_list_opid.clone() => amp::Diff::Text(amp::TextDiff{
                             object_id: make_list_opid.clone().into(),
                             edits: vec![amp::DiffEdit::SingleElementInsert{
@@ -97,8 +97,8 @@ pub fn sequential_inserts_in_single_patch(c: &mut Criterion) {
         max_op: 1,
         pending_changes: 0,
         diffs: RootDiff {
-            props: hashmap! {
-                "text".into() => hashmap!{
+            props: btreemap! {
+                "text".into() => btreemap!{
                     make_list_opid.clone() => amp::Diff::Text(amp::TextDiff{
                         object_id: make_list_opid.into(),
                         edits,
@@ -0,0 +1,88 @@
+use std::{collections::HashMap, str::FromStr};
+
+use automerge_protocol::ActorId;
+
+use crate::value::{Primitive, Value};
+
+/// Human readable details about an actor.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ActorInfo {
+    pub name: Option<String>,
+    pub color: Option<String>,
+    pub device: Option<String>,
+}
+
+/// Maps actor IDs to display metadata so that APIs which report who did what can show something
+/// nicer than a hex string.
+///
+/// The registry is local to a frontend and is not synced. Applications typically fill it in from
+/// their own user database, or from a map stored in the document itself with
+/// [`ActorRegistry::load_from_value`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ActorRegistry(HashMap<ActorId, ActorInfo>);
+
+impl ActorRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register `info` for `actor`, returning whatever was registered before.
+    pub fn insert(&mut self, actor: ActorId, info: ActorInfo) -> Option<ActorInfo> {
+        self.0.insert(actor, info)
+    }
+
+    pub fn get(&self, actor: &ActorId) -> Option<&ActorInfo> {
+        self.0.get(actor)
+    }
+
+    pub fn remove(&mut self, actor: &ActorId) -> Option<ActorInfo> {
+        self.0.remove(actor)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The registered name of `actor`, or its hex encoded ID if it has no name.
+    pub fn display_name(&self, actor: &ActorId) -> String {
+        self.get(actor)
+            .and_then(|info| info.name.clone())
+            .unwrap_or_else(|| actor.to_hex_string())
+    }
+
+    /// Register actors from a map of hex encoded actor IDs to maps with optional `name`, `color`
+    /// and `device` strings, e.g. `{"7b77..": {"name": "Alice", "device": "laptop"}}`.
+    ///
+    /// Entries which are not in this shape are skipped. Returns the number of actors registered.
+    pub fn load_from_value(&mut self, value: &Value) -> usize {
+        let entries = match value {
+            Value::Map(entries) | Value::Table(entries) => entries,
+            _ => return 0,
+        };
+        let mut loaded = 0;
+        for (key, entry) in entries {
+            let (actor, fields) = match (ActorId::from_str(key), entry) {
+                (Ok(actor), Value::Map(fields)) => (actor, fields),
+                _ => continue,
+            };
+            let field = |name: &str| match fields.get(name) {
+                Some(Value::Primitive(Primitive::Str(s))) => Some(s.to_string()),
+                _ => None,
+            };
+            self.insert(
+                actor,
+                ActorInfo {
+                    name: field("name"),
+                    color: field("color"),
+                    device: field("device"),
+                },
+            );
+            loaded += 1;
+        }
+        loaded
+    }
+}
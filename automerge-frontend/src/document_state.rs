@@ -0,0 +1,57 @@
+use automerge_protocol as amp;
+
+use crate::{error::InvalidPatch, state_tree::StateTree, value_ref::RootRef, Path, Value};
+
+/// The state of a document as the backend has described it, without anything a [`crate::Frontend`]
+/// adds for local changes.
+///
+/// This is for frontends which want to keep their own representation of a document, e.g. one
+/// tied to a GUI framework, but apply patches exactly the way `Frontend` does. Keep a
+/// `DocumentState` alongside, apply each patch to it with [`apply_diff`] or
+/// [`DocumentState::apply_diff`] and read the result back with [`DocumentState::value`] or
+/// [`DocumentState::value_ref`].
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DocumentState(StateTree);
+
+impl DocumentState {
+    /// The empty document
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Apply `diff` in place. The diff is checked before anything is changed, so if it is
+    /// invalid the state is left as it was.
+    pub fn apply_diff(&mut self, diff: amp::RootDiff) -> Result<(), InvalidPatch> {
+        let checked = self.0.check_diff(diff)?;
+        self.0.apply_diff(checked);
+        Ok(())
+    }
+
+    pub fn value(&self) -> Value {
+        self.0.value()
+    }
+
+    /// A view of the document which doesn't copy it, see [`crate::value_ref`]
+    pub fn value_ref(&self) -> RootRef<'_> {
+        self.0.value_ref()
+    }
+
+    pub fn get_value(&self, path: &Path) -> Option<Value> {
+        self.0
+            .resolve_path(path)
+            .map(|resolved| resolved.default_value())
+    }
+}
+
+/// The state `state` is in after applying `diff`, leaving `state` itself alone.
+///
+/// This copies the whole state, so when the old state isn't needed
+/// [`DocumentState::apply_diff`] is cheaper.
+pub fn apply_diff(
+    state: &DocumentState,
+    diff: amp::RootDiff,
+) -> Result<DocumentState, InvalidPatch> {
+    let mut next = state.clone();
+    next.apply_diff(diff)?;
+    Ok(next)
+}
@@ -0,0 +1,51 @@
+use std::collections::{BTreeMap, HashMap};
+
+use automerge_protocol as amp;
+
+/// Who created an element of a list or text object, and when.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ElementInfo {
+    /// The ID of the op which put the element at its current position
+    pub elem_id: amp::OpId,
+    /// The actor which made that op
+    pub actor: amp::ActorId,
+    /// The time of the change containing that op. This is `None` if the frontend has not seen the
+    /// change, see [`crate::Frontend::record_change_times`].
+    pub timestamp: Option<i64>,
+}
+
+/// The timestamps of the changes a frontend knows about, indexed by the range of op IDs each
+/// change covers.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ChangeTimes(HashMap<amp::ActorId, BTreeMap<u64, (u64, i64)>>);
+
+impl ChangeTimes {
+    pub(crate) fn record(&mut self, change: &amp::Change) {
+        let num_ops: u64 = change
+            .operations
+            .iter()
+            .map(|op| match &op.action {
+                amp::OpType::MultiSet(values) => values.len() as u64,
+                amp::OpType::Del(count) => u64::from(count.get()),
+                _ => 1,
+            })
+            .sum();
+        if num_ops == 0 {
+            return;
+        }
+        self.0.entry(change.actor_id.clone()).or_default().insert(
+            change.start_op,
+            (change.start_op + num_ops - 1, change.time),
+        );
+    }
+
+    /// The time of the change which contains `op_id`
+    pub(crate) fn time_of(&self, op_id: &amp::OpId) -> Option<i64> {
+        let (_, (max_op, time)) = self.0.get(&op_id.1)?.range(..=op_id.0).next_back()?;
+        if op_id.0 <= *max_op {
+            Some(*time)
+        } else {
+            None
+        }
+    }
+}
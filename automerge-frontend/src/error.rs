@@ -2,9 +2,10 @@ use std::{error::Error, fmt};
 
 use automerge_protocol as amp;
 use automerge_protocol::ObjectId;
+use smol_str::SmolStr;
 use thiserror::Error;
 
-use crate::{value::Value, Path};
+use crate::{value::Value, Path, Primitive, ValueKind, ViewId};
 
 #[derive(Debug, PartialEq)]
 pub enum AutomergeFrontendError {
@@ -80,6 +81,8 @@ pub enum InvalidPatch {
     DiffEditWithHeadElemId,
     #[error("Value diff containing cursor")]
     ValueDiffContainedCursor,
+    #[error("Received a text insert whose character lengths did not match its text")]
+    MalformedTextInsert,
 }
 
 #[derive(Error, Debug, PartialEq)]
@@ -88,6 +91,8 @@ pub enum InvalidChangeRequest {
     CannotOverwriteCounter { path: Path },
     #[error("attempted an operation on a path that does not exist: {path:?}")]
     NoSuchPathError { path: Path },
+    #[error(transparent)]
+    InvalidPath(#[from] InvalidPath),
     #[error("attempted to set a non map object {value:?} as the root")]
     CannotSetNonMapObjectAsRoot { value: Value },
     #[error("attempted to increment an object which is not a counter at {path:?}")]
@@ -100,6 +105,22 @@ pub enum InvalidChangeRequest {
     InsertPastEndOfSequence { path: Path, sequence_length: u64 },
     #[error("attempted to insert something into a text object which is not a character, object: {object:?}")]
     InsertNonTextInTextObject { path: Path, object: Value },
+    #[error("attempted to filter an object which is not a sequence at {path:?}")]
+    RetainForNonSequenceObject { path: Path },
+    #[error("attempted to move an element of an object which is not a list at {path:?}")]
+    MoveForNonListObject { path: Path },
+    #[error("attempted to mark an object which is not text at {path:?}")]
+    MarkForNonTextObject { path: Path },
+    #[error("attempted to splice text into an object which is not text at {path:?}")]
+    SpliceForNonTextObject { path: Path },
+    #[error("attempted to mark the empty or reversed range {start}..{end} at {path:?}")]
+    InvalidMarkRange { path: Path, start: u32, end: u32 },
+    #[error(
+        "attempted to store {value:?}, which is not a number, in a numeric register at {path:?}"
+    )]
+    NotANumber { path: Path, value: Primitive },
+    #[error("there is no entry {id} in the trash")]
+    NoSuchTrashEntry { id: SmolStr },
     #[error("attmpted to delete root object")]
     CannotDeleteRootObject,
     #[error("Attempted to access a missing index")]
@@ -109,9 +130,115 @@ pub enum InvalidChangeRequest {
     },
 }
 
+/// A change was made at a path which doesn't exist, see [`InvalidChangeRequest::InvalidPath`]
+#[derive(Error, Debug, PartialEq, Clone)]
+#[error("attempted an operation on a path that does not exist: {path:?}, there is no {segment} in the {actual} at {nearest_ancestor:?}")]
+pub struct InvalidPath {
+    /// The path of the change
+    pub path: Path,
+    /// The first segment of `path` which couldn't be followed, a key or an index
+    pub segment: String,
+    /// The longest prefix of `path` which does exist
+    pub nearest_ancestor: Path,
+    /// The kinds of object which `segment` could be in
+    pub expected: &'static [ValueKind],
+    /// What is at `nearest_ancestor`. If this is one of `expected` then `segment` is just
+    /// missing from it, otherwise the path goes through the wrong kind of value.
+    pub actual: ValueKind,
+}
+
 #[derive(Error, Debug, PartialEq)]
 #[error("Attempted to access index {missing_index} in a collection with max index: {size_of_collection}")]
 pub struct MissingIndexError {
     pub missing_index: usize,
     pub size_of_collection: usize,
 }
+
+/// Errors converting between a [`Value`] and Rust types with
+/// [`crate::serde_value::to_value`] and [`crate::serde_value::from_value`]
+#[derive(Error, Debug, PartialEq)]
+pub enum SerdeValueError {
+    #[error("{0}")]
+    Message(String),
+    #[error("map keys must be strings or integers")]
+    KeyMustBeAString,
+    #[error("expected an integer for {datatype}")]
+    NotAnInteger { datatype: &'static str },
+}
+
+impl serde::ser::Error for SerdeValueError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        SerdeValueError::Message(msg.to_string())
+    }
+}
+
+impl serde::de::Error for SerdeValueError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        SerdeValueError::Message(msg.to_string())
+    }
+}
+
+/// Errors converting between rows of a document and Arrow record batches with
+/// [`crate::record_batch`]
+#[cfg(feature = "arrow")]
+#[derive(Error, Debug, PartialEq)]
+pub enum RecordBatchError {
+    #[error("expected a table or a list of maps but found a {0:?}")]
+    NotRows(ValueKind),
+    #[error("row {row} is a {found:?} rather than a map")]
+    RowNotAMap { row: usize, found: ValueKind },
+    #[error("the {column} property of a row is a {found:?}, only primitives can go in a column")]
+    NestedObject { column: SmolStr, found: ValueKind },
+    #[error("the {column} column holds a cursor, which has no arrow equivalent")]
+    Cursor { column: SmolStr },
+    #[error("the {column} column holds both {first} and {second} values")]
+    MixedColumn {
+        column: SmolStr,
+        first: String,
+        second: String,
+    },
+    #[error(
+        "a row of the table has a property named {}, which is the ID column",
+        crate::record_batch::ID_COLUMN
+    )]
+    IdColumnClash,
+    #[error("the record batch has no {} column", crate::record_batch::ID_COLUMN)]
+    NoIdColumn,
+    #[error("the ID of row {row} is not a string")]
+    InvalidId { row: usize },
+    #[error("the {column} column has the type {data_type}, which can't be converted")]
+    UnsupportedArrowType { column: String, data_type: String },
+    #[error("{0}")]
+    Arrow(String),
+}
+
+/// Errors reading a typed value out of a [`Value`] with [`crate::Hydrate`]
+#[derive(Error, Debug, PartialEq)]
+pub enum HydrateError {
+    #[error("expected {expected} but found {found:?}")]
+    Unexpected {
+        expected: &'static str,
+        found: Value,
+    },
+    #[error("missing field {field}")]
+    MissingField { field: &'static str },
+    #[error("no value at {path:?}")]
+    NoSuchPath { path: Path },
+}
+
+impl HydrateError {
+    pub(crate) fn unexpected(expected: &'static str, found: &Value) -> Self {
+        HydrateError::Unexpected {
+            expected,
+            found: found.clone(),
+        }
+    }
+}
+
+/// The views which a patch couldn't be applied to by [`crate::PatchFanout::apply_patch`]. The
+/// patch was still applied to every other view.
+#[derive(Error, Debug, PartialEq)]
+#[error("patch could not be applied to {} views", .failed.len())]
+pub struct FanoutError {
+    pub failed: Vec<(ViewId, InvalidPatch)>,
+}
@@ -0,0 +1,107 @@
+use std::fmt;
+
+use automerge_protocol as amp;
+
+use crate::{error::FanoutError, Frontend};
+
+/// Identifies a frontend registered with a [`PatchFanout`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ViewId(u64);
+
+impl fmt::Display for ViewId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "view {}", self.0)
+    }
+}
+
+/// Several frontends, or views, of the same document which are all kept up to date from one
+/// backend, e.g. one frontend per window of an application.
+///
+/// Every patch from the backend, including the patch for a local change made in one of the views,
+/// should be passed to [`PatchFanout::apply_patch`]. Each view has to have its own actor ID so
+/// that the views can tell their own changes from everyone else's.
+///
+/// ```
+/// use automerge_frontend::{Frontend, PatchFanout};
+///
+/// let mut views = PatchFanout::new();
+/// let left = views.add(Frontend::new());
+/// let right = views.add(Frontend::new());
+/// assert_eq!(views.len(), 2);
+/// assert!(views.get(left).is_some());
+/// assert!(views.remove(right).is_some());
+/// assert!(views.get(right).is_none());
+/// ```
+#[derive(Debug, Default)]
+pub struct PatchFanout {
+    next_id: u64,
+    views: Vec<(ViewId, Frontend)>,
+}
+
+impl PatchFanout {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register `frontend` as a view, it should already reflect the state of the backend
+    pub fn add(&mut self, frontend: Frontend) -> ViewId {
+        let id = ViewId(self.next_id);
+        self.next_id += 1;
+        self.views.push((id, frontend));
+        id
+    }
+
+    /// Stop sending patches to a view, returning its frontend
+    pub fn remove(&mut self, id: ViewId) -> Option<Frontend> {
+        let index = self.views.iter().position(|(i, _)| *i == id)?;
+        Some(self.views.remove(index).1)
+    }
+
+    pub fn get(&self, id: ViewId) -> Option<&Frontend> {
+        self.views.iter().find(|(i, _)| *i == id).map(|(_, f)| f)
+    }
+
+    pub fn get_mut(&mut self, id: ViewId) -> Option<&mut Frontend> {
+        self.views
+            .iter_mut()
+            .find(|(i, _)| *i == id)
+            .map(|(_, f)| f)
+    }
+
+    /// The views in the order they were added
+    pub fn views(&self) -> impl Iterator<Item = (ViewId, &Frontend)> {
+        self.views.iter().map(|(id, f)| (*id, f))
+    }
+
+    pub fn len(&self) -> usize {
+        self.views.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.views.is_empty()
+    }
+
+    /// Apply `patch` to every view. The patch is decoded once by the caller and moved into the
+    /// last view, so a copy is only made for each of the others.
+    ///
+    /// A view which rejects the patch doesn't stop it being applied to the rest, all of the
+    /// failures are returned together.
+    pub fn apply_patch(&mut self, patch: amp::Patch) -> Result<(), FanoutError> {
+        let mut failed = Vec::new();
+        if let Some(((last_id, last), rest)) = self.views.split_last_mut() {
+            for (id, view) in rest {
+                if let Err(e) = view.apply_patch(patch.clone()) {
+                    failed.push((*id, e));
+                }
+            }
+            if let Err(e) = last.apply_patch(patch) {
+                failed.push((*last_id, e));
+            }
+        }
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(FanoutError { failed })
+        }
+    }
+}
@@ -1,18 +1,37 @@
-use std::{collections::HashMap, convert::TryFrom, error::Error, fmt::Debug};
+#[cfg(feature = "std")]
+use std::convert::TryFrom;
+use std::{collections::HashMap, error::Error, fmt::Debug, ops::Range};
 
 use automerge_protocol as amp;
 use automerge_protocol::{ActorId, ObjectId, OpId, Patch};
+use smol_str::SmolStr;
+use unicode_segmentation::UnicodeSegmentation;
 
+#[cfg(feature = "patch-stats")]
+use crate::patch_stats::{self, PatchStats};
+#[cfg(feature = "im")]
+use crate::SharedValue;
 use crate::{
-    error::{InvalidInitialStateError, InvalidPatch},
-    mutation::{LocalChange, MutableDocument},
+    actor_registry::ActorRegistry,
+    document_state::DocumentState,
+    element_info::{ChangeTimes, ElementInfo},
+    error::{HydrateError, InvalidPatch},
+    guarded_value::{Generation, GuardedValue},
+    mutation::MutableDocument,
+    observe::{self, ChangeEvent, ObserverId, Observers},
     path::Path,
+    preview::{self, PatchEffects},
+    reconcile::Hydrate,
     state::FrontendState,
-    state_tree::StateTree,
-    value,
+    state_tree::{ResolvedPath, StateTree},
+    text_boundaries::Granularity,
+    text_indexing::TextIndexing,
+    trash::{self, TrashEntry},
     value::Value,
     value_ref::RootRef,
 };
+#[cfg(all(feature = "std", feature = "random"))]
+use crate::{error::InvalidInitialStateError, mutation::LocalChange, value};
 
 pub struct Frontend {
     pub actor_id: ActorId,
@@ -23,8 +42,24 @@ pub struct Frontend {
     state: FrontendState,
     /// A cache of the value of this frontend
     cached_value: Option<Value>,
+    /// A cache of the value of this frontend as a `SharedValue`
+    #[cfg(feature = "im")]
+    cached_shared_value: Option<SharedValue>,
     /// A function for generating timestamps
     timestamper: Box<dyn Fn() -> Option<i64>>,
+    /// Bumped whenever the state changes, see `GuardedValue`
+    generation: Generation,
+    /// Display metadata for the actors which have edited the document
+    actors: ActorRegistry,
+    /// The timestamps of the changes this frontend has made or been told about
+    change_times: ChangeTimes,
+    /// What indices into text objects count
+    text_indexing: TextIndexing,
+    /// Callbacks registered with `Frontend::observe`
+    observers: Observers,
+    /// What the last call to `apply_patch` did
+    #[cfg(feature = "patch-stats")]
+    last_patch_stats: PatchStats,
 }
 
 impl Debug for Frontend {
@@ -34,7 +69,16 @@ impl Debug for Frontend {
             seq,
             state,
             cached_value,
+            #[cfg(feature = "im")]
+                cached_shared_value: _,
             timestamper: _,
+            generation: _,
+            actors,
+            change_times,
+            text_indexing,
+            observers,
+            #[cfg(feature = "patch-stats")]
+                last_patch_stats: _,
         } = self;
         {
             let mut builder = f.debug_struct("Frontend");
@@ -42,12 +86,16 @@ impl Debug for Frontend {
             let _ = builder.field("seq", &seq);
             let _ = builder.field("state", &state);
             let _ = builder.field("cached_value", &cached_value);
+            let _ = builder.field("actors", &actors);
+            let _ = builder.field("change_times", &change_times);
+            let _ = builder.field("text_indexing", &text_indexing);
+            let _ = builder.field("observers", &observers);
             builder.finish()
         }
     }
 }
 
-#[cfg(feature = "std")]
+#[cfg(all(feature = "std", feature = "random"))]
 impl Default for Frontend {
     fn default() -> Self {
         Self::new()
@@ -55,7 +103,7 @@ impl Default for Frontend {
 }
 
 impl Frontend {
-    #[cfg(feature = "std")]
+    #[cfg(all(feature = "std", feature = "random"))]
     pub fn new() -> Self {
         let system_time = || {
             std::time::SystemTime::now()
@@ -77,6 +125,7 @@ impl Frontend {
         Self::new_with_timestamper_and_actor_id(Box::new(system_time), actor_id)
     }
 
+    #[cfg(feature = "random")]
     pub fn new_with_timestamper(t: Box<dyn Fn() -> Option<i64>>) -> Self {
         Self::new_with_timestamper_and_actor_id(t, uuid::Uuid::new_v4().as_bytes())
     }
@@ -95,11 +144,20 @@ impl Frontend {
                 deps_of_last_received_patch: Vec::new(),
             },
             cached_value: None,
+            #[cfg(feature = "im")]
+            cached_shared_value: None,
             timestamper: t,
+            generation: Generation::default(),
+            actors: ActorRegistry::new(),
+            change_times: ChangeTimes::default(),
+            text_indexing: TextIndexing::default(),
+            observers: Observers::default(),
+            #[cfg(feature = "patch-stats")]
+            last_patch_stats: PatchStats::default(),
         }
     }
 
-    #[cfg(feature = "std")]
+    #[cfg(all(feature = "std", feature = "random"))]
     pub fn new_with_initial_state(
         initial_state: Value,
     ) -> Result<(Self, amp::Change), InvalidInitialStateError> {
@@ -155,6 +213,33 @@ impl Frontend {
         }
     }
 
+    /// The value of this frontend as a [`SharedValue`], which is cheap to clone and keep around
+    /// as a snapshot. It's built from `state` and cached until the frontend changes.
+    #[cfg(feature = "im")]
+    pub fn shared_state(&mut self) -> SharedValue {
+        if let Some(ref v) = self.cached_shared_value {
+            v.clone()
+        } else {
+            let value = SharedValue::from(self.state());
+            self.cached_shared_value = Some(value.clone());
+            value
+        }
+    }
+
+    fn invalidate_cache(&mut self) {
+        self.cached_value = None;
+        #[cfg(feature = "im")]
+        {
+            self.cached_shared_value = None;
+        }
+    }
+
+    /// Like `state` but the returned value can detect if this frontend has changed since.
+    pub fn guarded_state(&mut self) -> GuardedValue {
+        let value = self.state().clone();
+        GuardedValue::new(value, self.generation.clone())
+    }
+
     pub fn value_ref(&self) -> RootRef {
         self.state.value_ref()
     }
@@ -169,10 +254,14 @@ impl Frontend {
         F: FnOnce(&mut dyn MutableDocument) -> Result<O, E>,
     {
         let start_op = self.state.max_op() + 1;
-        let change_result =
-            self.state
-                .optimistically_apply_change(&self.actor_id, change_closure, self.seq + 1)?;
-        self.cached_value = None;
+        let change_result = self.state.optimistically_apply_change(
+            &self.actor_id,
+            change_closure,
+            self.seq + 1,
+            self.text_indexing,
+        )?;
+        self.invalidate_cache();
+        self.generation.bump();
         if !change_result.ops.is_empty() {
             self.seq += 1;
             let change = amp::Change {
@@ -186,6 +275,7 @@ impl Frontend {
                 operations: change_result.ops,
                 extra_bytes: Vec::new(),
             };
+            self.change_times.record(&change);
             Ok((change_result.closure_result, Some(change)))
         } else {
             Ok((change_result.closure_result, None))
@@ -193,16 +283,70 @@ impl Frontend {
     }
 
     pub fn apply_patch(&mut self, patch: Patch) -> Result<(), InvalidPatch> {
-        self.cached_value = None;
+        self.invalidate_cache();
+        self.generation.bump();
         if let Some(seq) = patch.clock.get(&self.actor_id) {
             if *seq > self.seq {
                 self.seq = *seq;
             }
         }
-        self.state.apply_remote_patch(&self.actor_id, patch)?;
+        let events = if self.observers.is_empty() {
+            Vec::new()
+        } else {
+            observe::events(&patch.diffs, |path| self.state.get_object_id(path))
+        };
+        #[cfg(feature = "patch-stats")]
+        let result = {
+            let (result, stats) =
+                patch_stats::collect(|| self.state.apply_remote_patch(&self.actor_id, patch));
+            self.last_patch_stats = stats;
+            result
+        };
+        #[cfg(not(feature = "patch-stats"))]
+        let result = self.state.apply_remote_patch(&self.actor_id, patch);
+        result?;
+        self.observers.notify(&events);
         Ok(())
     }
 
+    /// Counters for the work the last call to [`Frontend::apply_patch`] did, for putting
+    /// numbers on a performance problem. Each call resets them, including calls which fail.
+    #[cfg(feature = "patch-stats")]
+    pub fn last_patch_stats(&self) -> PatchStats {
+        self.last_patch_stats
+    }
+
+    /// The document a patch from scratch, such as one from `Backend::get_patch_at`, describes.
+    /// This is for looking at old versions of a document without replacing the state of a
+    /// frontend.
+    pub fn value_of_patch(patch: Patch) -> Result<Value, InvalidPatch> {
+        let mut state = DocumentState::new();
+        state.apply_diff(patch.diffs)?;
+        Ok(state.value())
+    }
+
+    /// Work out what applying `patch` would do without applying it. The effects are relative to
+    /// the state the backend has told this frontend about, so an in flight local change shows up
+    /// in [`PatchEffects::in_flight_overridden`] rather than as a change.
+    pub fn preview_patch(&self, patch: &Patch) -> Result<PatchEffects, InvalidPatch> {
+        preview::preview(&self.state, &self.actor_id, patch)
+    }
+
+    /// Call `callback` with each change to `path` or anything under it made by the patches
+    /// applied from now on. Changes which replace or remove an ancestor of `path` are reported
+    /// too.
+    pub fn observe<F>(&mut self, path: Path, callback: F) -> ObserverId
+    where
+        F: FnMut(&ChangeEvent) + 'static,
+    {
+        self.observers.add(path, Box::new(callback))
+    }
+
+    /// Stop calling an observer, returns false if there was no such observer
+    pub fn unobserve(&mut self, id: ObserverId) -> bool {
+        self.observers.remove(id)
+    }
+
     pub fn get_object_id(&self, path: &Path) -> Option<ObjectId> {
         self.state.get_object_id(path)
     }
@@ -211,8 +355,17 @@ impl Frontend {
         self.state.in_flight_requests()
     }
 
+    /// Whether this frontend has never made a change nor applied a patch containing one, i.e. it
+    /// shows the empty document which `Backend::empty_document` encodes.
+    pub fn is_empty(&self) -> bool {
+        self.seq == 0 && self.state.is_empty()
+    }
+
     /// Gets the set of values for `path`, returns None if the path does not
-    /// exist
+    /// exist. If concurrent changes set `path` there is one entry per change,
+    /// keyed by the ID of its op, otherwise there is a single entry for the
+    /// current value. This is the equivalent of `Automerge.getConflicts` in the
+    /// JS library.
     pub fn get_conflicts(&self, path: &Path) -> Option<HashMap<OpId, Value>> {
         self.state.resolve_path(path).map(|o| o.values())
     }
@@ -221,4 +374,166 @@ impl Frontend {
     pub fn get_value(&self, path: &Path) -> Option<Value> {
         self.state.get_value(path)
     }
+
+    /// Read the value at `path` as a `T`
+    pub fn hydrate<T: Hydrate>(&self, path: &Path) -> Result<T, HydrateError> {
+        let value = self
+            .get_value(path)
+            .ok_or_else(|| HydrateError::NoSuchPath { path: path.clone() })?;
+        T::hydrate(&value)
+    }
+
+    /// Whether there is a value at `path`
+    pub fn exists_at(&self, path: &Path) -> bool {
+        self.state.resolve_path(path).is_some()
+    }
+
+    /// The keys of the map or table at `path`, sorted. Returns `None` if there is no map or table
+    /// at `path`.
+    pub fn keys_at(&self, path: &Path) -> Option<Vec<SmolStr>> {
+        self.state.resolve_path(path)?.keys()
+    }
+
+    /// The number of elements of the list or text, or the number of keys of the map or table, at
+    /// `path`. Returns `None` for anything else.
+    pub fn length_at(&self, path: &Path) -> Option<usize> {
+        let resolved = self.state.resolve_path(path)?;
+        match (self.text_indexing, &resolved) {
+            (TextIndexing::Graphemes, ResolvedPath::Text(text)) => {
+                Some(text.text().graphemes(true).count())
+            }
+            _ => resolved.len(),
+        }
+    }
+
+    pub fn text_indexing(&self) -> TextIndexing {
+        self.text_indexing
+    }
+
+    /// Change what indices into text objects count, see [`TextIndexing`]
+    pub fn set_text_indexing(&mut self, text_indexing: TextIndexing) {
+        self.text_indexing = text_indexing;
+    }
+
+    pub fn actor_registry(&self) -> &ActorRegistry {
+        &self.actors
+    }
+
+    pub fn actor_registry_mut(&mut self) -> &mut ActorRegistry {
+        &mut self.actors
+    }
+
+    /// The author and creation time of the element at `index` in the list or text object at
+    /// `path`. Returns `None` if there is no such element.
+    ///
+    /// The author comes from the element's ID. The time is only known for changes made by this
+    /// frontend or passed to [`Self::record_change_times`].
+    pub fn element_info(&self, path: &Path, index: u32) -> Option<ElementInfo> {
+        let cursor = match self.state.resolve_path(path)? {
+            ResolvedPath::List(list) => list.get_cursor(index).ok()?,
+            ResolvedPath::Text(text) => text.get_cursor(index).ok()?,
+            _ => return None,
+        };
+        let elem_id = cursor.elem_opid;
+        Some(ElementInfo {
+            actor: elem_id.1.clone(),
+            timestamp: self.change_times.time_of(&elem_id),
+            elem_id,
+        })
+    }
+
+    /// The entries in the trash, oldest first, see [`crate::trash`]
+    pub fn trash(&self) -> Vec<TrashEntry> {
+        trash::entries(self.get_value(&Path::root().key(trash::TRASH_KEY)).as_ref())
+    }
+
+    /// The contents of the text object at `path` as a string. Returns `None` if there is no text at
+    /// `path`.
+    pub fn text_at(&self, path: &Path) -> Option<String> {
+        match self.state.resolve_path(path)? {
+            ResolvedPath::Text(text) => Some(text.text()),
+            _ => None,
+        }
+    }
+
+    /// The range of elements of the word, run of whitespace or punctuation mark containing the
+    /// element at `index` of the text at `path`, e.g. for selecting a word on double click.
+    /// Returns `None` if there is no text at `path` or `index` is out of bounds.
+    ///
+    /// Like the other methods which find segments of text, this looks at the elements around
+    /// `index` rather than the whole text, and its indices count elements whatever the
+    /// [`TextIndexing`].
+    pub fn word_at(&self, path: &Path, index: u32) -> Option<Range<u32>> {
+        self.segment_at(path, index, Granularity::Word)
+    }
+
+    /// The range of elements of the segment containing the element at `index` of the text at
+    /// `path`
+    pub fn segment_at(
+        &self,
+        path: &Path,
+        index: u32,
+        granularity: Granularity,
+    ) -> Option<Range<u32>> {
+        match self.state.resolve_path(path)? {
+            ResolvedPath::Text(text) => text
+                .segment_at(index as usize, granularity)
+                .map(|range| range.start as u32..range.end as u32),
+            _ => None,
+        }
+    }
+
+    /// The first segment boundary after `index` in the text at `path`, for moving the cursor a
+    /// word or sentence forward. Returns `None` at the end of the text.
+    pub fn next_boundary(&self, path: &Path, index: u32, granularity: Granularity) -> Option<u32> {
+        self.segment_at(path, index, granularity)
+            .map(|range| range.end)
+    }
+
+    /// The last segment boundary before `index` in the text at `path`. Returns `None` at the
+    /// start of the text.
+    pub fn previous_boundary(
+        &self,
+        path: &Path,
+        index: u32,
+        granularity: Granularity,
+    ) -> Option<u32> {
+        let before = index.checked_sub(1)?;
+        self.segment_at(path, before, granularity)
+            .map(|range| range.start)
+    }
+
+    /// The formatting spans of the text at `path`, ordered by where they start. Returns `None` if
+    /// there is no text at `path`.
+    pub fn marks(&self, path: &Path) -> Option<Vec<amp::MarkSpan>> {
+        match self.state.resolve_path(path)? {
+            ResolvedPath::Text(text) => Some(text.marks()),
+            _ => None,
+        }
+    }
+
+    /// Remember the timestamps of `changes` so they can be reported by [`Self::element_info`].
+    /// Changes made by this frontend are recorded automatically.
+    pub fn record_change_times<'a, I>(&mut self, changes: I)
+    where
+        I: IntoIterator<Item = &'a amp::Change>,
+    {
+        for change in changes {
+            self.change_times.record(change);
+        }
+    }
+
+    /// Like `get_conflicts` but each value is paired with the display name of the actor who set
+    /// it, as given by the actor registry. The values are ordered by the ID of the operation
+    /// which set them.
+    pub fn get_attributed_conflicts(&self, path: &Path) -> Option<Vec<(String, Value)>> {
+        let mut conflicts: Vec<_> = self.get_conflicts(path)?.into_iter().collect();
+        conflicts.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Some(
+            conflicts
+                .into_iter()
+                .map(|(op_id, value)| (self.actors.display_name(&op_id.1), value))
+                .collect(),
+        )
+    }
 }
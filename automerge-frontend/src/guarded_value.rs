@@ -0,0 +1,61 @@
+use std::{cell::Cell, rc::Rc};
+
+use crate::Value;
+
+/// Counts modifications to a frontend so that handed out values can tell when they are stale.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Generation(Rc<Cell<u64>>);
+
+impl Generation {
+    pub(crate) fn current(&self) -> u64 {
+        self.0.get()
+    }
+
+    pub(crate) fn bump(&self) {
+        self.0.set(self.0.get().wrapping_add(1));
+    }
+}
+
+/// A snapshot of a frontend's value which knows whether the frontend has changed since it was
+/// taken, as returned by [`Frontend::guarded_state`](crate::Frontend::guarded_state).
+///
+/// In debug builds reading the value through [`GuardedValue::get`] after the frontend has applied
+/// a patch or a local change panics, which makes it easy to find UI code that holds on to an old
+/// snapshot of the document. In release builds the check is skipped.
+#[derive(Debug, Clone)]
+pub struct GuardedValue {
+    value: Value,
+    taken_at: u64,
+    generation: Generation,
+}
+
+impl GuardedValue {
+    pub(crate) fn new(value: Value, generation: Generation) -> Self {
+        GuardedValue {
+            value,
+            taken_at: generation.current(),
+            generation,
+        }
+    }
+
+    /// Whether the frontend has changed since this value was taken.
+    pub fn is_stale(&self) -> bool {
+        self.generation.current() != self.taken_at
+    }
+
+    /// # Panics
+    ///
+    /// In debug builds, panics if the value is stale.
+    pub fn get(&self) -> &Value {
+        debug_assert!(
+            !self.is_stale(),
+            "read a document value after the document had changed"
+        );
+        &self.value
+    }
+
+    /// Take the value out without checking whether it is stale.
+    pub fn into_inner(self) -> Value {
+        self.value
+    }
+}
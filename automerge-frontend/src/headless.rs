@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+#[cfg(feature = "std")]
+use std::convert::TryFrom;
+
+use automerge_protocol as amp;
+use automerge_protocol::{ActorId, ChangeHash};
+
+use crate::error::InvalidPatch;
+
+/// A frontend which only keeps track of what it needs to create changes: the sequence number,
+/// the highest op counter it has seen, the vector clock and the dependencies of the next change.
+///
+/// Unlike [`crate::Frontend`] there is no state tree, so the document can't be read and the
+/// operations of each change have to be given explicitly. This is useful for servers which
+/// generate edits programmatically and never look at the document themselves.
+pub struct HeadlessFrontend {
+    pub actor_id: ActorId,
+    pub seq: u64,
+    max_op: u64,
+    clock: HashMap<ActorId, u64>,
+    deps_of_last_received_patch: Vec<ChangeHash>,
+    /// The sequence numbers of local changes which have not yet been reflected in a patch
+    in_flight_requests: Vec<u64>,
+    timestamper: Box<dyn Fn() -> Option<i64>>,
+}
+
+impl std::fmt::Debug for HeadlessFrontend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HeadlessFrontend")
+            .field("actor_id", &self.actor_id)
+            .field("seq", &self.seq)
+            .field("max_op", &self.max_op)
+            .field("clock", &self.clock)
+            .field(
+                "deps_of_last_received_patch",
+                &self.deps_of_last_received_patch,
+            )
+            .field("in_flight_requests", &self.in_flight_requests)
+            .finish()
+    }
+}
+
+#[cfg(all(feature = "std", feature = "random"))]
+impl Default for HeadlessFrontend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HeadlessFrontend {
+    #[cfg(all(feature = "std", feature = "random"))]
+    pub fn new() -> Self {
+        Self::new_with_actor_id(uuid::Uuid::new_v4().as_bytes())
+    }
+
+    #[cfg(feature = "std")]
+    pub fn new_with_actor_id(actor_id: &[u8]) -> Self {
+        let system_time = || {
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .ok()
+                .and_then(|d| i64::try_from(d.as_millis()).ok())
+        };
+        Self::new_with_timestamper_and_actor_id(Box::new(system_time), actor_id)
+    }
+
+    #[cfg(feature = "random")]
+    pub fn new_with_timestamper(t: Box<dyn Fn() -> Option<i64>>) -> Self {
+        Self::new_with_timestamper_and_actor_id(t, uuid::Uuid::new_v4().as_bytes())
+    }
+
+    pub fn new_with_timestamper_and_actor_id(
+        t: Box<dyn Fn() -> Option<i64>>,
+        actor_id: &[u8],
+    ) -> Self {
+        HeadlessFrontend {
+            actor_id: ActorId::from(actor_id),
+            seq: 0,
+            max_op: 0,
+            clock: HashMap::new(),
+            deps_of_last_received_patch: Vec::new(),
+            in_flight_requests: Vec::new(),
+            timestamper: t,
+        }
+    }
+
+    /// The highest op counter this frontend knows about
+    pub fn max_op(&self) -> u64 {
+        self.max_op
+    }
+
+    /// The vector clock of the last patch this frontend received
+    pub fn clock(&self) -> &HashMap<ActorId, u64> {
+        &self.clock
+    }
+
+    /// The sequence numbers of local changes which the backend has not yet acknowledged
+    pub fn in_flight_requests(&self) -> &[u64] {
+        &self.in_flight_requests
+    }
+
+    /// The ID the first op of the next change will have. The op at position `n` of that change
+    /// gets `next_op_id().increment_by(n)`, which is how ops refer to objects created earlier in
+    /// the same change.
+    pub fn next_op_id(&self) -> amp::OpId {
+        self.actor_id.op_id_at(self.max_op + 1)
+    }
+
+    /// Create a change containing `operations`, or `None` if there are no operations.
+    ///
+    /// The ops are not checked in any way, it is up to the caller to make sure they refer to
+    /// objects and predecessors the backend knows about.
+    pub fn change(
+        &mut self,
+        message: Option<String>,
+        operations: Vec<amp::Op>,
+    ) -> Option<amp::Change> {
+        if operations.is_empty() {
+            return None;
+        }
+        let num_ops: u64 = operations
+            .iter()
+            .map(|op| match &op.action {
+                amp::OpType::MultiSet(values) => values.len() as u64,
+                amp::OpType::Del(count) => u64::from(count.get()),
+                _ => 1,
+            })
+            .sum();
+        let start_op = self.max_op + 1;
+        self.max_op += num_ops;
+        self.seq += 1;
+        // While requests are in flight the backend fills in the dependencies of local changes
+        let deps = if self.in_flight_requests.is_empty() {
+            self.deps_of_last_received_patch.clone()
+        } else {
+            Vec::new()
+        };
+        self.in_flight_requests.push(self.seq);
+        Some(amp::Change {
+            start_op,
+            actor_id: self.actor_id.clone(),
+            seq: self.seq,
+            time: (self.timestamper)().unwrap_or(0),
+            message,
+            hash: None,
+            deps,
+            operations,
+            extra_bytes: Vec::new(),
+        })
+    }
+
+    /// Apply a patch from the backend. The diffs are ignored, only the sequence number, clock,
+    /// dependencies and max op of the patch are used.
+    pub fn apply_patch(&mut self, patch: amp::Patch) -> Result<(), InvalidPatch> {
+        if let Some(seq) = patch.clock.get(&self.actor_id) {
+            if *seq > self.seq {
+                self.seq = *seq;
+            }
+        }
+        // A patch for one of our own changes acknowledges the oldest in flight request
+        if let (Some(patch_actor), Some(patch_seq), Some(expected)) = (
+            &patch.actor,
+            patch.seq,
+            self.in_flight_requests.first().copied(),
+        ) {
+            if *patch_actor == self.actor_id {
+                if expected != patch_seq {
+                    return Err(InvalidPatch::MismatchedSequenceNumber {
+                        expected,
+                        actual: patch_seq,
+                    });
+                }
+                self.in_flight_requests.remove(0);
+            }
+        }
+        self.clock = patch.clock;
+        if self.in_flight_requests.is_empty() {
+            self.max_op = patch.max_op;
+            self.deps_of_last_received_patch = patch.deps;
+        }
+        Ok(())
+    }
+}
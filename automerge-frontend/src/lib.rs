@@ -1,16 +1,56 @@
+mod actor_registry;
+mod document_state;
+mod element_info;
 mod error;
+mod fanout;
 mod frontend;
+mod guarded_value;
+mod headless;
 mod mutation;
+mod observe;
+mod patch_stats;
 mod path;
+mod preview;
+pub mod reconcile;
+#[cfg(feature = "arrow")]
+pub mod record_batch;
+pub mod register;
+pub mod serde_value;
 mod state;
 mod state_tree;
+mod text_boundaries;
+mod text_indexing;
+pub mod trash;
+mod unique_list;
 mod value;
 pub mod value_ref;
+#[cfg(feature = "tokio-watch")]
+mod watch;
 
+pub use actor_registry::{ActorInfo, ActorRegistry};
+pub use document_state::{apply_diff, DocumentState};
+pub use element_info::ElementInfo;
 pub use error::{
-    AutomergeFrontendError, InvalidChangeRequest, InvalidInitialStateError, InvalidPatch,
+    AutomergeFrontendError, FanoutError, HydrateError, InvalidChangeRequest,
+    InvalidInitialStateError, InvalidPatch, InvalidPath, SerdeValueError,
 };
+pub use fanout::{PatchFanout, ViewId};
 pub use frontend::Frontend;
-pub use mutation::{LocalChange, MutableDocument};
+pub use guarded_value::GuardedValue;
+pub use headless::HeadlessFrontend;
+pub use mutation::{LocalChange, MutableDocument, SetDeepOptions};
+pub use observe::{ChangeEvent, ChangeEventKind, ObserverId};
+#[cfg(feature = "patch-stats")]
+pub use patch_stats::PatchStats;
 pub use path::Path;
-pub use value::{Conflicts, Cursor, Primitive, Value};
+pub use preview::PatchEffects;
+pub use reconcile::{Hydrate, Reconcile};
+pub use serde_value::{from_value, to_value};
+pub use text_boundaries::Granularity;
+pub use text_indexing::TextIndexing;
+pub use unique_list::UniqueList;
+#[cfg(feature = "im")]
+pub use value::SharedValue;
+pub use value::{Conflicts, Cursor, Primitive, Value, ValueKind};
+#[cfg(feature = "tokio-watch")]
+pub use watch::WatchPublisher;
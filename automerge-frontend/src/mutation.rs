@@ -1,21 +1,72 @@
+use std::{collections::HashMap, num::NonZeroU32};
+
 use automerge_protocol as amp;
+use smol_str::SmolStr;
 use unicode_segmentation::UnicodeSegmentation;
 
 use crate::{
-    error::InvalidChangeRequest,
+    error::{InvalidChangeRequest, InvalidPath, MissingIndexError},
     path::PathElement,
     state_tree::{
         LocalOperationForRollback, LocalOperationResult, OptimisticStateTree, ResolvedPath,
         ResolvedPathMut, SetOrInsertPayload,
     },
-    value::{Cursor, Value},
+    text_indexing::{self, TextIndexing},
+    value::{Cursor, Value, ValueKind},
     Path, Primitive,
 };
 
 pub trait MutableDocument {
     fn value_at_path(&self, path: &Path) -> Option<Value>;
     fn cursor_to_path(&self, path: &Path) -> Option<Cursor>;
+    /// All of the values at `path`, keyed by the ID of the op which set them. There is more than
+    /// one if concurrent changes set `path`. See [`crate::Frontend::get_conflicts`].
+    fn conflicts_at_path(&self, path: &Path) -> Option<HashMap<amp::OpId, Value>>;
     fn add_change(&mut self, change: LocalChange) -> Result<(), InvalidChangeRequest>;
+
+    /// Delete every element of the list or text object at `path` for which `keep` returns false.
+    ///
+    /// Runs of adjacent deleted elements are deleted with a single multi-element `Del` op where
+    /// possible.
+    fn retain_in_list(
+        &mut self,
+        path: &Path,
+        keep: &mut dyn FnMut(&Value) -> bool,
+    ) -> Result<(), InvalidChangeRequest>;
+
+    /// Set the value at `path`, creating any objects on the way to it which don't exist yet.
+    /// Index segments create lists, see [`MutableDocument::set_deep_with`] to change that.
+    fn set_deep(&mut self, path: &Path, value: Value) -> Result<(), InvalidChangeRequest> {
+        self.set_deep_with(path, value, SetDeepOptions::default())
+    }
+
+    /// Like [`MutableDocument::set_deep`] but with `options` deciding what gets created.
+    ///
+    /// A list created for an index segment contains just the next object or value down the
+    /// path, so the index has to be 0. An index into a list which does exist may be one past the
+    /// end, in which case the new object is appended.
+    fn set_deep_with(
+        &mut self,
+        path: &Path,
+        value: Value,
+        options: SetDeepOptions,
+    ) -> Result<(), InvalidChangeRequest>;
+}
+
+/// What [`MutableDocument::set_deep_with`] creates for missing path segments
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SetDeepOptions {
+    /// Whether an index segment creates a list. If not it creates a map with the index as its
+    /// key, which suits paths built from user input like `config.servers.0.host`.
+    pub indexes_make_lists: bool,
+}
+
+impl Default for SetDeepOptions {
+    fn default() -> Self {
+        SetDeepOptions {
+            indexes_make_lists: true,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -25,12 +76,28 @@ pub enum LocalOperation {
     Increment(i64),
     Insert(Value),
     InsertMany(Vec<Value>),
+    Move {
+        from: u32,
+        to: u32,
+    },
+    Mark {
+        start: u32,
+        end: u32,
+        name: SmolStr,
+        value: Primitive,
+    },
+    SpliceText {
+        index: u32,
+        delete: u32,
+        insert: String,
+    },
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct LocalChange {
     pub(crate) path: Path,
     pub(crate) operation: LocalOperation,
+    pub(crate) create_missing: bool,
 }
 
 impl LocalChange {
@@ -42,6 +109,7 @@ impl LocalChange {
         LocalChange {
             path,
             operation: LocalOperation::Set(value.into()),
+            create_missing: false,
         }
     }
 
@@ -50,6 +118,7 @@ impl LocalChange {
         LocalChange {
             path,
             operation: LocalOperation::Delete,
+            create_missing: false,
         }
     }
 
@@ -58,6 +127,7 @@ impl LocalChange {
         LocalChange {
             path,
             operation: LocalOperation::Increment(1),
+            create_missing: false,
         }
     }
 
@@ -66,6 +136,7 @@ impl LocalChange {
         LocalChange {
             path,
             operation: LocalOperation::Increment(by),
+            create_missing: false,
         }
     }
 
@@ -73,6 +144,7 @@ impl LocalChange {
         LocalChange {
             path,
             operation: LocalOperation::Insert(value),
+            create_missing: false,
         }
     }
 
@@ -80,8 +152,77 @@ impl LocalChange {
         LocalChange {
             path,
             operation: LocalOperation::InsertMany(values),
+            create_missing: false,
         }
     }
+
+    /// Move the element at index `from` in the list at `path` so that it ends up at index `to`.
+    ///
+    /// Unlike deleting the element and inserting a copy, concurrent changes to the moved element
+    /// are kept.
+    pub fn move_item(path: Path, from: u32, to: u32) -> LocalChange {
+        LocalChange {
+            path,
+            operation: LocalOperation::Move { from, to },
+            create_missing: false,
+        }
+    }
+
+    /// Format the characters from `start` up to (but not including) `end` of the text at `path`
+    /// by setting the mark `name`, e.g. "bold" or "link", to `value`.
+    ///
+    /// Characters typed at the end of the range later on get the mark too, characters typed at
+    /// the start do not.
+    pub fn mark<N, P>(path: Path, start: u32, end: u32, name: N, value: P) -> LocalChange
+    where
+        N: Into<SmolStr>,
+        P: Into<Primitive>,
+    {
+        LocalChange {
+            path,
+            operation: LocalOperation::Mark {
+                start,
+                end,
+                name: name.into(),
+                value: value.into(),
+            },
+            create_missing: false,
+        }
+    }
+
+    /// Remove the mark `name` from the characters from `start` up to (but not including) `end`
+    /// of the text at `path`
+    pub fn unmark<N: Into<SmolStr>>(path: Path, start: u32, end: u32, name: N) -> LocalChange {
+        Self::mark(path, start, end, name, Primitive::Null)
+    }
+
+    /// Delete `delete` characters from the text at `path` starting at `index` and insert `text`
+    /// in their place. The deleted characters become a single multi-element delete and the
+    /// inserted ones a single multi-element insert.
+    pub fn splice_text(path: Path, index: u32, delete: u32, text: &str) -> LocalChange {
+        LocalChange {
+            path,
+            operation: LocalOperation::SpliceText {
+                index,
+                delete,
+                insert: text.to_string(),
+            },
+            create_missing: false,
+        }
+    }
+
+    /// Insert `text` into the text at `path` at `index`
+    pub fn insert_str(path: Path, index: u32, text: &str) -> LocalChange {
+        Self::splice_text(path, index, 0, text)
+    }
+
+    /// Create any maps which are missing along the path of a `set`, like `mkdir -p`, rather than
+    /// failing with [`InvalidChangeRequest::InvalidPath`]. Maps are only created for key
+    /// segments, so a missing list index is still an error.
+    pub fn create_missing(mut self) -> LocalChange {
+        self.create_missing = true;
+        self
+    }
 }
 
 /// `MutationTracker` is used as the context in which a mutation closure is
@@ -101,6 +242,7 @@ pub struct MutationTracker<'a> {
     copies_for_rollback: Vec<(Path, LocalOperationForRollback)>,
     max_op: u64,
     actor_id: amp::ActorId,
+    text_indexing: TextIndexing,
 }
 
 impl<'a> MutationTracker<'a> {
@@ -108,6 +250,7 @@ impl<'a> MutationTracker<'a> {
         state: &'a mut OptimisticStateTree,
         max_op: u64,
         actor_id: amp::ActorId,
+        text_indexing: TextIndexing,
     ) -> Self {
         Self {
             state,
@@ -115,6 +258,7 @@ impl<'a> MutationTracker<'a> {
             copies_for_rollback: Vec::new(),
             max_op,
             actor_id,
+            text_indexing,
         }
     }
 
@@ -216,30 +360,129 @@ impl<'a> MutationTracker<'a> {
             Err(InvalidChangeRequest::NoSuchPathError { path: path.clone() })
         }
     }
-}
 
-impl<'a> MutableDocument for MutationTracker<'a> {
-    fn value_at_path(&self, path: &Path) -> Option<Value> {
-        self.state.resolve_path(path).map(|r| r.default_value())
+    /// The longest prefix of `path` which exists in the document, along with the segments of
+    /// `path` after it
+    fn split_at_missing(&self, path: &Path) -> (Path, Vec<PathElement>) {
+        let mut existing = Path::root();
+        let mut rest = path.clone().elements().into_iter();
+        while let Some(segment) = rest.next() {
+            let next = existing.clone().child(segment.clone());
+            if self.state.resolve_path(&next).is_none() {
+                return (existing, std::iter::once(segment).chain(rest).collect());
+            }
+            existing = next;
+        }
+        (existing, Vec::new())
     }
 
-    fn cursor_to_path(&self, path: &Path) -> Option<Cursor> {
-        if let Some(PathElement::Index(i)) = path.name() {
-            if let Some(parent) = self.state.resolve_path(&path.parent()) {
-                match parent {
-                    ResolvedPath::List(list_target) => list_target.get_cursor(*i).ok(),
-                    ResolvedPath::Text(text_target) => text_target.get_cursor(*i).ok(),
-                    _ => None,
-                }
-            } else {
-                None
-            }
+    /// A [`InvalidPath`] describing why `path` couldn't be found
+    fn explain_missing_path(&self, path: Path) -> InvalidChangeRequest {
+        let (nearest_ancestor, rest) = self.split_at_missing(&path);
+        let actual = match self.state.resolve_path(&nearest_ancestor) {
+            Some(ResolvedPath::Root(_)) | Some(ResolvedPath::Map(_)) => ValueKind::Map,
+            Some(ResolvedPath::Table(_)) => ValueKind::Table,
+            Some(ResolvedPath::List(_)) => ValueKind::List,
+            Some(ResolvedPath::Text(_)) => ValueKind::Text,
+            Some(ResolvedPath::Character(_))
+            | Some(ResolvedPath::Counter(_))
+            | Some(ResolvedPath::Primitive(_)) => ValueKind::Primitive,
+            None => return InvalidChangeRequest::NoSuchPathError { path },
+        };
+        match rest.first() {
+            Some(segment) => InvalidChangeRequest::InvalidPath(InvalidPath {
+                segment: segment.to_string(),
+                expected: match segment {
+                    PathElement::Key(_) => &[ValueKind::Map, ValueKind::Table],
+                    PathElement::Index(_) => &[ValueKind::List, ValueKind::Text],
+                },
+                actual,
+                nearest_ancestor,
+                path,
+            }),
+            None => InvalidChangeRequest::NoSuchPathError { path },
+        }
+    }
+
+    /// If `change` sets a value under maps which don't exist yet, instead set the first missing
+    /// key to maps nested around the value
+    fn with_missing_maps(&self, change: LocalChange) -> LocalChange {
+        let value = match &change.operation {
+            LocalOperation::Set(value) => value,
+            _ => return change,
+        };
+        let (existing, rest) = self.split_at_missing(&change.path);
+        if rest.len() < 2 || rest.iter().any(|s| matches!(s, PathElement::Index(_))) {
+            return change;
+        }
+        let value = rest[1..]
+            .iter()
+            .rev()
+            .fold(value.clone(), |value, segment| {
+                let mut map = HashMap::new();
+                map.insert(segment.to_string().into(), value);
+                Value::Map(map)
+            });
+        LocalChange::set(existing.child(rest[0].clone()), value)
+    }
+
+    /// The change which sets `path` to `value`, with the first missing segment of `path` set to
+    /// new objects nested around `value`
+    fn deep_change(
+        &self,
+        path: &Path,
+        value: Value,
+        options: SetDeepOptions,
+    ) -> Result<LocalChange, InvalidChangeRequest> {
+        let is_sequence = |path: &Path| {
+            matches!(
+                self.state.resolve_path(path),
+                Some(ResolvedPath::List(_)) | Some(ResolvedPath::Text(_))
+            )
+        };
+        let path = if options.indexes_make_lists {
+            path.clone()
         } else {
-            None
+            // Indexes are keys unless they're into a list which already exists
+            path.clone()
+                .elements()
+                .into_iter()
+                .fold(Path::root(), |path, segment| match segment {
+                    PathElement::Index(i) if !is_sequence(&path) => path.key(i.to_string()),
+                    segment => path.child(segment),
+                })
+        };
+        let (existing, rest) = self.split_at_missing(&path);
+        let (first, nested) = match rest.split_first() {
+            Some(split) => split,
+            None => return Ok(LocalChange::set(path, value)),
+        };
+        let mut value = value;
+        for segment in nested.iter().rev() {
+            value = match segment {
+                PathElement::Index(0) => Value::List(vec![value]),
+                PathElement::Index(_) => {
+                    return Err(InvalidChangeRequest::InsertPastEndOfSequence {
+                        path,
+                        sequence_length: 0,
+                    })
+                }
+                PathElement::Key(key) => {
+                    let mut map = HashMap::new();
+                    map.insert(key.clone(), value);
+                    Value::Map(map)
+                }
+            };
         }
+        Ok(match first {
+            PathElement::Index(i) if is_sequence(&existing) => {
+                LocalChange::insert(existing.index(*i), value)
+            }
+            segment => LocalChange::set(existing.child(segment.clone()), value),
+        })
     }
 
-    fn add_change(&mut self, change: LocalChange) -> Result<(), InvalidChangeRequest> {
+    fn apply_change(&mut self, change: LocalChange) -> Result<(), InvalidChangeRequest> {
         match change.operation {
             LocalOperation::Set(value) => {
                 //TODO double resolving is ugly here
@@ -483,6 +726,246 @@ impl<'a> MutableDocument for MutationTracker<'a> {
                     Err(e) => Err(e),
                 }
             }
+            LocalOperation::Move { from, to } => match self.state.resolve_path_mut(&change.path) {
+                Some(ResolvedPathMut::List(mut list_target)) => {
+                    let (elem_id, res) =
+                        list_target.move_item(from, to, self.max_op + 1, &self.actor_id.clone())?;
+                    self.copies_for_rollback.push((
+                        change.path,
+                        LocalOperationForRollback::Move {
+                            from: from as usize,
+                            to: to as usize,
+                            elem_id,
+                        },
+                    ));
+                    self.apply_state_change(res);
+                    Ok(())
+                }
+                Some(_) => Err(InvalidChangeRequest::MoveForNonListObject { path: change.path }),
+                None => Err(InvalidChangeRequest::NoSuchPathError { path: change.path }),
+            },
+            LocalOperation::Mark {
+                start,
+                end,
+                name,
+                value,
+            } => {
+                if start >= end {
+                    return Err(InvalidChangeRequest::InvalidMarkRange {
+                        path: change.path,
+                        start,
+                        end,
+                    });
+                }
+                match self.state.resolve_path_mut(&change.path) {
+                    Some(ResolvedPathMut::Text(mut text_target)) => {
+                        let (old, res) = text_target.mark(
+                            start,
+                            end,
+                            name,
+                            (&value).into(),
+                            self.max_op + 1,
+                            &self.actor_id.clone(),
+                        )?;
+                        self.copies_for_rollback
+                            .push((change.path, LocalOperationForRollback::Mark { old }));
+                        self.apply_state_change(res);
+                        Ok(())
+                    }
+                    Some(_) => {
+                        Err(InvalidChangeRequest::MarkForNonTextObject { path: change.path })
+                    }
+                    None => Err(InvalidChangeRequest::NoSuchPathError { path: change.path }),
+                }
+            }
+            LocalOperation::SpliceText {
+                index,
+                delete,
+                insert,
+            } => {
+                let text = match self.state.resolve_path(&change.path) {
+                    Some(ResolvedPath::Text(text)) => text,
+                    Some(_) => {
+                        return Err(InvalidChangeRequest::SpliceForNonTextObject {
+                            path: change.path,
+                        })
+                    }
+                    None => {
+                        return Err(InvalidChangeRequest::NoSuchPathError { path: change.path })
+                    }
+                };
+                let range = match self.text_indexing {
+                    TextIndexing::Elements => {
+                        let end = index as usize + delete as usize;
+                        if end <= text.len() {
+                            Some((index, end as u32))
+                        } else {
+                            None
+                        }
+                    }
+                    TextIndexing::Graphemes => text_indexing::element_range(
+                        text.graphemes(),
+                        index as usize,
+                        delete as usize,
+                    )
+                    .map(|(start, end)| (start as u32, end as u32)),
+                };
+                let (start, end) = match range {
+                    Some(range) => range,
+                    // inserting past the end is reported by `insert_helper`, a grapheme index
+                    // past the end isn't an element index though
+                    None if delete == 0 && self.text_indexing == TextIndexing::Elements => {
+                        (index, index)
+                    }
+                    None => {
+                        let length = match self.text_indexing {
+                            TextIndexing::Elements => text.len(),
+                            TextIndexing::Graphemes => {
+                                text_indexing::grapheme_count(text.graphemes())
+                            }
+                        };
+                        return Err(MissingIndexError {
+                            missing_index: index as usize + (delete as usize).saturating_sub(1),
+                            size_of_collection: length,
+                        }
+                        .into());
+                    }
+                };
+
+                // delete from the back so the indices of the remaining characters don't shift
+                let first_op = self.ops.len();
+                for i in (start..end).rev() {
+                    self.add_change(LocalChange::delete(change.path.clone().index(i)))?;
+                }
+                let mut deletes = self.ops.split_off(first_op);
+                deletes.reverse();
+                self.ops.extend(merge_deletes(deletes));
+
+                let graphemes: Vec<Value> = insert
+                    .graphemes(true)
+                    .map(|g| Value::Primitive(Primitive::Str(g.into())))
+                    .collect();
+                if graphemes.is_empty() {
+                    Ok(())
+                } else {
+                    self.add_change(LocalChange::insert_many(
+                        change.path.index(start),
+                        graphemes,
+                    ))
+                }
+            }
+        }
+    }
+}
+
+/// Combine deletions of consecutive elements into multi-element deletes, `ops` must be in
+/// ascending order of index.
+///
+/// A multi-element delete covers elements whose IDs, and the IDs of the single op each of them
+/// is being deleted over, are consecutive, which is what you get when a run of elements was
+/// inserted in one go and not modified since.
+fn merge_deletes(ops: Vec<amp::Op>) -> Vec<amp::Op> {
+    let mut merged: Vec<amp::Op> = Vec::with_capacity(ops.len());
+    for op in ops {
+        if let Some(last) = merged.last_mut() {
+            if let amp::OpType::Del(count) = last.action {
+                let n = u64::from(count.get());
+                let continues_run = last.obj == op.obj
+                    && last.pred.len() == 1
+                    && op.pred.len() == 1
+                    && last.key.increment_by(n).as_ref() == Some(&op.key)
+                    && last.pred.get(0).map(|p| p.increment_by(n)).as_ref() == op.pred.get(0);
+                if continues_run {
+                    if let Some(count) = NonZeroU32::new(count.get() + 1) {
+                        last.action = amp::OpType::Del(count);
+                        continue;
+                    }
+                }
+            }
+        }
+        merged.push(op);
+    }
+    merged
+}
+
+impl<'a> MutableDocument for MutationTracker<'a> {
+    fn value_at_path(&self, path: &Path) -> Option<Value> {
+        self.state.resolve_path(path).map(|r| r.default_value())
+    }
+
+    fn conflicts_at_path(&self, path: &Path) -> Option<HashMap<amp::OpId, Value>> {
+        self.state.resolve_path(path).map(|r| r.values())
+    }
+
+    fn cursor_to_path(&self, path: &Path) -> Option<Cursor> {
+        if let Some(PathElement::Index(i)) = path.name() {
+            if let Some(parent) = self.state.resolve_path(&path.parent()) {
+                match parent {
+                    ResolvedPath::List(list_target) => list_target.get_cursor(*i).ok(),
+                    ResolvedPath::Text(text_target) => text_target.get_cursor(*i).ok(),
+                    _ => None,
+                }
+            } else {
+                None
+            }
+        } else {
+            None
+        }
+    }
+
+    fn add_change(&mut self, change: LocalChange) -> Result<(), InvalidChangeRequest> {
+        let change = if change.create_missing {
+            self.with_missing_maps(change)
+        } else {
+            change
+        };
+        self.apply_change(change).map_err(|e| match e {
+            InvalidChangeRequest::NoSuchPathError { path } => self.explain_missing_path(path),
+            e => e,
+        })
+    }
+
+    fn set_deep_with(
+        &mut self,
+        path: &Path,
+        value: Value,
+        options: SetDeepOptions,
+    ) -> Result<(), InvalidChangeRequest> {
+        let change = self.deep_change(path, value, options)?;
+        self.add_change(change)
+    }
+
+    fn retain_in_list(
+        &mut self,
+        path: &Path,
+        keep: &mut dyn FnMut(&Value) -> bool,
+    ) -> Result<(), InvalidChangeRequest> {
+        let values = match self.value_at_path(path) {
+            Some(Value::List(values)) => values,
+            Some(Value::Text(graphemes)) => graphemes
+                .into_iter()
+                .map(|g| Value::Primitive(Primitive::Str(g)))
+                .collect(),
+            Some(_) => {
+                return Err(InvalidChangeRequest::RetainForNonSequenceObject { path: path.clone() })
+            }
+            None => return Err(InvalidChangeRequest::NoSuchPathError { path: path.clone() }),
+        };
+        let doomed: Vec<usize> = values
+            .iter()
+            .enumerate()
+            .filter(|(_, value)| !keep(value))
+            .map(|(index, _)| index)
+            .collect();
+
+        // delete from the back so the indices of the remaining elements don't shift
+        let first_op = self.ops.len();
+        for index in doomed.into_iter().rev() {
+            self.add_change(LocalChange::delete(path.clone().index(index as u32)))?;
         }
+        let mut deletes = self.ops.split_off(first_op);
+        deletes.reverse();
+        self.ops.extend(merge_deletes(deletes));
+        Ok(())
     }
 }
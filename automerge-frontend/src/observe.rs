@@ -0,0 +1,226 @@
+//! Notifications of which parts of a document a patch changed.
+//!
+//! Callbacks registered with [`crate::Frontend::observe`] are called once for each
+//! [`ChangeEvent`] in a patch which touches the path they were registered for. Events are worked
+//! out from the diffs in the patch rather than by comparing values, so they describe what the
+//! backend sent: applying the patch for a local change reports that change again.
+use std::{collections::BTreeMap, fmt};
+
+use automerge_protocol as amp;
+use smol_str::SmolStr;
+
+use crate::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChangeEventKind {
+    /// A new element was inserted into a list or text object
+    Inserted,
+    /// A map key or list element was set, either to a primitive or to a new object. Changes
+    /// inside an object which already existed are reported at their own paths instead.
+    Updated,
+    /// A map key or list element was deleted
+    Removed,
+}
+
+/// Something in the document which a patch changed
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ChangeEvent {
+    /// The path which changed. Indices into lists are those at the point the patch made the
+    /// change, later edits in the same patch may have moved the element since.
+    pub path: Path,
+    pub kind: ChangeEventKind,
+}
+
+impl ChangeEvent {
+    fn new(path: Path, kind: ChangeEventKind) -> Self {
+        ChangeEvent { path, kind }
+    }
+}
+
+/// Returned by [`crate::Frontend::observe`] to pass to [`crate::Frontend::unobserve`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ObserverId(u64);
+
+type Callback = Box<dyn FnMut(&ChangeEvent)>;
+
+#[derive(Default)]
+pub(crate) struct Observers {
+    next_id: u64,
+    observers: Vec<(ObserverId, Path, Callback)>,
+}
+
+impl fmt::Debug for Observers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.observers.iter().map(|(id, path, _)| (id, path)))
+            .finish()
+    }
+}
+
+impl Observers {
+    pub(crate) fn add(&mut self, path: Path, callback: Callback) -> ObserverId {
+        let id = ObserverId(self.next_id);
+        self.next_id += 1;
+        self.observers.push((id, path, callback));
+        id
+    }
+
+    pub(crate) fn remove(&mut self, id: ObserverId) -> bool {
+        let before = self.observers.len();
+        self.observers.retain(|(i, _, _)| *i != id);
+        self.observers.len() != before
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.observers.is_empty()
+    }
+
+    /// Call every observer whose path is affected by `event`, i.e. the event is in the subtree
+    /// under the observed path or replaces or removes something above it
+    pub(crate) fn notify(&mut self, events: &[ChangeEvent]) {
+        for (_, path, callback) in &mut self.observers {
+            for event in events {
+                if event.path.starts_with(path) || path.starts_with(&event.path) {
+                    callback(event);
+                }
+            }
+        }
+    }
+}
+
+/// The events in `diffs`. `object_id_at` looks up the object at a path before the patch is
+/// applied, to tell edits to an existing object from the creation of a new one.
+pub(crate) fn events<F>(diffs: &amp::RootDiff, object_id_at: F) -> Vec<ChangeEvent>
+where
+    F: Fn(&Path) -> Option<amp::ObjectId>,
+{
+    events_and_value_counts(diffs, object_id_at).0
+}
+
+/// The events in `diffs` along with, for each path which the diffs set, how many (conflicting)
+/// values it has after the patch
+pub(crate) fn events_and_value_counts<F>(
+    diffs: &amp::RootDiff,
+    object_id_at: F,
+) -> (Vec<ChangeEvent>, Vec<(Path, usize)>)
+where
+    F: Fn(&Path) -> Option<amp::ObjectId>,
+{
+    let mut collector = Collector {
+        object_id_at,
+        events: Vec::new(),
+        value_counts: Vec::new(),
+    };
+    collector.props(&Path::root(), &diffs.props);
+    (collector.events, collector.value_counts)
+}
+
+struct Collector<F> {
+    object_id_at: F,
+    events: Vec<ChangeEvent>,
+    value_counts: Vec<(Path, usize)>,
+}
+
+impl<F> Collector<F>
+where
+    F: Fn(&Path) -> Option<amp::ObjectId>,
+{
+    fn props(&mut self, path: &Path, props: &BTreeMap<SmolStr, BTreeMap<amp::OpId, amp::Diff>>) {
+        for (key, values) in props {
+            let path = path.clone().key(key.clone());
+            if values.is_empty() {
+                self.events
+                    .push(ChangeEvent::new(path, ChangeEventKind::Removed));
+            } else {
+                self.values(path, values.values());
+            }
+        }
+    }
+
+    /// One or more conflicting values at `path`
+    fn values<'a, I: Iterator<Item = &'a amp::Diff>>(&mut self, path: Path, values: I) {
+        let existing = (self.object_id_at)(&path);
+        let mut updated = false;
+        let mut count = 0;
+        for value in values {
+            count += 1;
+            match value {
+                amp::Diff::Map(amp::MapDiff { object_id, props })
+                | amp::Diff::Table(amp::TableDiff { object_id, props })
+                    if Some(object_id) == existing.as_ref() =>
+                {
+                    self.props(&path, props)
+                }
+                amp::Diff::List(amp::ListDiff { object_id, edits })
+                | amp::Diff::Text(amp::TextDiff { object_id, edits })
+                    if Some(object_id) == existing.as_ref() =>
+                {
+                    self.edits(&path, edits)
+                }
+                _ => updated = true,
+            }
+        }
+        if updated {
+            self.events
+                .push(ChangeEvent::new(path.clone(), ChangeEventKind::Updated));
+        }
+        self.value_counts.push((path, count));
+    }
+
+    fn edits(&mut self, path: &Path, edits: &[amp::DiffEdit]) {
+        let mut edits = edits.iter().peekable();
+        while let Some(edit) = edits.next() {
+            match edit {
+                amp::DiffEdit::SingleElementInsert { index, .. } => {
+                    self.inserted(path, *index, 1);
+                }
+                amp::DiffEdit::MultiElementInsert(amp::MultiElementInsert {
+                    index,
+                    values,
+                    ..
+                }) => self.inserted(path, *index, values.len()),
+                amp::DiffEdit::TextInsert(amp::TextInsert { index, lengths, .. }) => {
+                    self.inserted(path, *index, lengths.len())
+                }
+                amp::DiffEdit::Update { index, value, .. } => {
+                    // Conflicting values at the same index come as consecutive updates
+                    let mut values = vec![value];
+                    while let Some(amp::DiffEdit::Update {
+                        index: next_index,
+                        value,
+                        ..
+                    }) = edits.peek()
+                    {
+                        if next_index != index {
+                            break;
+                        }
+                        values.push(value);
+                        edits.next();
+                    }
+                    self.values(path.clone().index(*index as u32), values.into_iter());
+                }
+                amp::DiffEdit::Remove { index, count } => {
+                    for i in *index..*index + *count {
+                        self.events.push(ChangeEvent::new(
+                            path.clone().index(i as u32),
+                            ChangeEventKind::Removed,
+                        ));
+                    }
+                }
+                amp::DiffEdit::Marks(_) => {
+                    self.events
+                        .push(ChangeEvent::new(path.clone(), ChangeEventKind::Updated));
+                }
+            }
+        }
+    }
+
+    fn inserted(&mut self, path: &Path, index: u64, count: usize) {
+        for i in index..index + count as u64 {
+            self.events.push(ChangeEvent::new(
+                path.clone().index(i as u32),
+                ChangeEventKind::Inserted,
+            ));
+        }
+    }
+}
@@ -0,0 +1,58 @@
+//! Counters for the work `Frontend::apply_patch` does, for attaching numbers to performance
+//! problems. The counters are only collected with the `patch-stats` feature, without it
+//! `record` does nothing.
+#![cfg_attr(not(feature = "patch-stats"), allow(dead_code))]
+
+#[cfg(feature = "patch-stats")]
+use std::cell::RefCell;
+
+/// What applying one patch to the state of a frontend took, see `Frontend::last_patch_stats`.
+///
+/// A patch which arrives while local changes are in flight is queued rather than applied, the
+/// work shows up in the stats for the patch which reconciles the state.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PatchStats {
+    /// List and text elements inserted, counting each element of a multi-insert
+    pub elements_inserted: u64,
+    pub elements_removed: u64,
+    /// Values built from the patch, each nested object and each element counts as one
+    pub nodes_created: u64,
+    /// Values copied from the existing state, e.g. to apply an update to one side of a conflict
+    pub nodes_cloned: u64,
+    /// Conflicting values dropped because the patch said they had been overwritten
+    pub conflicts_resolved: u64,
+}
+
+impl PatchStats {
+    /// A rough count of heap allocations: one for every node created or cloned and one for
+    /// every element inserted (elements are boxed). This ignores the allocations of the
+    /// collections themselves.
+    pub fn estimated_allocations(&self) -> u64 {
+        self.nodes_created + self.nodes_cloned + self.elements_inserted
+    }
+}
+
+#[cfg(feature = "patch-stats")]
+thread_local! {
+    static CURRENT: RefCell<Option<PatchStats>> = RefCell::new(None);
+}
+
+/// Update the counters for the patch being applied on this thread, if any
+#[inline]
+pub(crate) fn record<F: FnOnce(&mut PatchStats)>(_f: F) {
+    #[cfg(feature = "patch-stats")]
+    CURRENT.with(|current| {
+        if let Some(stats) = current.borrow_mut().as_mut() {
+            _f(stats)
+        }
+    })
+}
+
+/// Run `f`, returning what it recorded
+#[cfg(feature = "patch-stats")]
+pub(crate) fn collect<R, F: FnOnce() -> R>(f: F) -> (R, PatchStats) {
+    let outer = CURRENT.with(|current| current.borrow_mut().replace(PatchStats::default()));
+    let result = f();
+    let stats = CURRENT.with(|current| std::mem::replace(&mut *current.borrow_mut(), outer));
+    (result, stats.unwrap_or_default())
+}
@@ -36,6 +36,16 @@ impl Path {
         }
     }
 
+    /// Whether `prefix` is this path or one of its ancestors
+    pub fn starts_with(&self, prefix: &Path) -> bool {
+        self.0.starts_with(&prefix.0)
+    }
+
+    pub(crate) fn child(mut self, element: PathElement) -> Path {
+        self.0.push(element);
+        self
+    }
+
     /// Get the final component of the path, if any
     pub(crate) fn name(&self) -> Option<&PathElement> {
         self.0.last()
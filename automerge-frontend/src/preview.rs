@@ -0,0 +1,85 @@
+use automerge_protocol as amp;
+
+use crate::{
+    error::InvalidPatch,
+    observe::{self, ChangeEvent, ChangeEventKind},
+    state::FrontendState,
+    state_tree::StateTree,
+    Path,
+};
+
+/// What applying a patch would do, see [`crate::Frontend::preview_patch`]
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PatchEffects {
+    /// Everything the patch changes, in the order the patch changes it
+    pub changes: Vec<ChangeEvent>,
+    /// Paths which have a single value now and would have conflicting values afterwards
+    pub conflicts_created: Vec<Path>,
+    /// Paths which have conflicting values now and would have a single value (or none)
+    /// afterwards
+    pub conflicts_resolved: Vec<Path>,
+    /// Paths which an in flight local change has set and which the patch changes as well.
+    /// Whichever value wins, one of the two changes will be hidden once the backend has seen
+    /// both. Always empty for the patch confirming a local change.
+    pub in_flight_overridden: Vec<Path>,
+}
+
+impl PatchEffects {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Whether the patch would override any in flight local change
+    pub fn overrides_in_flight_changes(&self) -> bool {
+        !self.in_flight_overridden.is_empty()
+    }
+}
+
+fn conflict_count(state: &StateTree, path: &Path) -> usize {
+    state
+        .resolve_path(path)
+        .map(|r| r.values().len())
+        .unwrap_or(0)
+}
+
+pub(crate) fn preview(
+    state: &FrontendState,
+    self_actor: &amp::ActorId,
+    patch: &amp::Patch,
+) -> Result<PatchEffects, InvalidPatch> {
+    let before = state.reconciled_state()?;
+    before.check_diff(patch.diffs.clone())?;
+
+    // A diff for a key or index lists every value it has after the patch, so the conflicts
+    // afterwards can be read straight off the patch
+    let (changes, value_counts) = observe::events_and_value_counts(&patch.diffs, |path| {
+        before.resolve_path(path).and_then(|r| r.object_id())
+    });
+    let removed = changes
+        .iter()
+        .filter(|event| event.kind == ChangeEventKind::Removed)
+        .map(|event| (event.path.clone(), 0));
+    let mut effects = PatchEffects::default();
+    for (path, will_be) in value_counts.into_iter().chain(removed) {
+        let was = conflict_count(&before, &path);
+        if was <= 1 && will_be > 1 && !effects.conflicts_created.contains(&path) {
+            effects.conflicts_created.push(path);
+        } else if was > 1 && will_be <= 1 && !effects.conflicts_resolved.contains(&path) {
+            effects.conflicts_resolved.push(path);
+        }
+    }
+
+    if patch.actor.as_ref() != Some(self_actor) {
+        for ChangeEvent { path, .. } in &changes {
+            // the optimistic state only differs from the reconciled state where local changes
+            // haven't been confirmed yet
+            let locally_changed = state.resolve_path(path).map(|r| r.default_value())
+                != before.resolve_path(path).map(|r| r.default_value());
+            if locally_changed && !effects.in_flight_overridden.contains(path) {
+                effects.in_flight_overridden.push(path.clone());
+            }
+        }
+    }
+    effects.changes = changes;
+    Ok(effects)
+}
@@ -0,0 +1,274 @@
+//! Typed access to documents.
+//!
+//! [`Reconcile`] writes a Rust value into a document, generating only the changes needed to turn
+//! what is already there into that value, and [`Hydrate`] reads one back out of a [`Value`].
+//! Both are implemented here for primitives and the standard collections, and the
+//! `automerge-derive` crate derives them for structs with named fields.
+//!
+//! To keep a typed view of a document up to date, apply each patch to a [`crate::Frontend`] and
+//! hydrate from [`crate::Frontend::get_value`] (or [`crate::Frontend::hydrate`]) afterwards.
+use std::collections::HashMap;
+
+use smol_str::SmolStr;
+
+use crate::{
+    error::{HydrateError, InvalidChangeRequest},
+    LocalChange, MutableDocument, Path, Primitive, Value,
+};
+
+/// Something which can be written into a document at a path
+pub trait Reconcile {
+    /// Make the value at `path` in `doc` equal to `self`, leaving alone any parts which already
+    /// are. Maps and lists which already exist at `path` are edited in place rather than
+    /// replaced so that concurrent changes to other parts of them are kept.
+    fn reconcile(
+        &self,
+        doc: &mut dyn MutableDocument,
+        path: &Path,
+    ) -> Result<(), InvalidChangeRequest>;
+}
+
+/// Something which can be read out of a [`Value`]
+pub trait Hydrate: Sized {
+    fn hydrate(value: &Value) -> Result<Self, HydrateError>;
+}
+
+/// Set `path` to `value` unless it already is `value`
+pub fn reconcile_value(
+    doc: &mut dyn MutableDocument,
+    path: &Path,
+    value: Value,
+) -> Result<(), InvalidChangeRequest> {
+    if doc.value_at_path(path).as_ref() != Some(&value) {
+        doc.add_change(LocalChange::set(path.clone(), value))?;
+    }
+    Ok(())
+}
+
+/// Make sure there is a map at `path`, replacing whatever is there if it isn't a map, and
+/// return its keys
+pub fn reconcile_map(
+    doc: &mut dyn MutableDocument,
+    path: &Path,
+) -> Result<Vec<SmolStr>, InvalidChangeRequest> {
+    match doc.value_at_path(path) {
+        Some(Value::Map(map)) => Ok(map.into_keys().collect()),
+        _ => {
+            doc.add_change(LocalChange::set(path.clone(), Value::Map(HashMap::new())))?;
+            Ok(Vec::new())
+        }
+    }
+}
+
+/// The value under `key` in the map `value`, for hydrating struct fields
+pub fn field<'a>(value: &'a Value, key: &'static str) -> Result<&'a Value, HydrateError> {
+    match value {
+        Value::Map(map) => map
+            .get(key)
+            .ok_or(HydrateError::MissingField { field: key }),
+        other => Err(HydrateError::unexpected("a map", other)),
+    }
+}
+
+macro_rules! primitive {
+    ($t:ty, $expected:literal, |$v:ident| $to:expr, $from:pat => $res:expr) => {
+        impl Reconcile for $t {
+            fn reconcile(
+                &self,
+                doc: &mut dyn MutableDocument,
+                path: &Path,
+            ) -> Result<(), InvalidChangeRequest> {
+                let $v = self;
+                reconcile_value(doc, path, Value::Primitive($to))
+            }
+        }
+
+        impl Hydrate for $t {
+            fn hydrate(value: &Value) -> Result<Self, HydrateError> {
+                match value {
+                    Value::Primitive($from) => $res,
+                    other => Err(HydrateError::unexpected($expected, other)),
+                }
+            }
+        }
+    };
+}
+
+primitive!(bool, "a boolean", |v| Primitive::Boolean(*v), Primitive::Boolean(b) => Ok(*b));
+primitive!(f64, "a float", |v| Primitive::F64(*v), Primitive::F64(f) => Ok(*f));
+
+impl Reconcile for String {
+    fn reconcile(
+        &self,
+        doc: &mut dyn MutableDocument,
+        path: &Path,
+    ) -> Result<(), InvalidChangeRequest> {
+        reconcile_value(
+            doc,
+            path,
+            Value::Primitive(Primitive::Str(SmolStr::new(self))),
+        )
+    }
+}
+
+/// Strings are hydrated from text objects as well as string primitives
+impl Hydrate for String {
+    fn hydrate(value: &Value) -> Result<Self, HydrateError> {
+        match value {
+            Value::Primitive(Primitive::Str(s)) => Ok(s.to_string()),
+            Value::Text(graphemes) => Ok(graphemes.concat()),
+            other => Err(HydrateError::unexpected("a string", other)),
+        }
+    }
+}
+
+macro_rules! integer {
+    ($($t:ty => $variant:ident),*) => {
+        $(
+            impl Reconcile for $t {
+                fn reconcile(
+                    &self,
+                    doc: &mut dyn MutableDocument,
+                    path: &Path,
+                ) -> Result<(), InvalidChangeRequest> {
+                    reconcile_value(doc, path, Value::Primitive(Primitive::$variant((*self).into())))
+                }
+            }
+
+            impl Hydrate for $t {
+                fn hydrate(value: &Value) -> Result<Self, HydrateError> {
+                    use std::convert::TryFrom;
+                    let converted = match value {
+                        Value::Primitive(Primitive::Int(i))
+                        | Value::Primitive(Primitive::Counter(i))
+                        | Value::Primitive(Primitive::Timestamp(i)) => <$t>::try_from(*i).ok(),
+                        Value::Primitive(Primitive::Uint(u)) => <$t>::try_from(*u).ok(),
+                        _ => None,
+                    };
+                    converted.ok_or_else(|| HydrateError::unexpected(stringify!($t), value))
+                }
+            }
+        )*
+    };
+}
+
+integer!(i8 => Int, i16 => Int, i32 => Int, i64 => Int, u8 => Uint, u16 => Uint, u32 => Uint, u64 => Uint);
+
+impl<T: Reconcile> Reconcile for Option<T> {
+    fn reconcile(
+        &self,
+        doc: &mut dyn MutableDocument,
+        path: &Path,
+    ) -> Result<(), InvalidChangeRequest> {
+        match self {
+            Some(v) => v.reconcile(doc, path),
+            None => reconcile_value(doc, path, Value::Primitive(Primitive::Null)),
+        }
+    }
+}
+
+impl<T: Hydrate> Hydrate for Option<T> {
+    fn hydrate(value: &Value) -> Result<Self, HydrateError> {
+        match value {
+            Value::Primitive(Primitive::Null) => Ok(None),
+            other => T::hydrate(other).map(Some),
+        }
+    }
+}
+
+impl<T: Reconcile> Reconcile for Vec<T> {
+    /// Elements are reconciled index by index, extra elements in the document are deleted from
+    /// the end
+    fn reconcile(
+        &self,
+        doc: &mut dyn MutableDocument,
+        path: &Path,
+    ) -> Result<(), InvalidChangeRequest> {
+        let existing = match doc.value_at_path(path) {
+            Some(Value::List(values)) => values.len(),
+            _ => {
+                doc.add_change(LocalChange::set(path.clone(), Value::List(Vec::new())))?;
+                0
+            }
+        };
+        for (index, element) in self.iter().enumerate() {
+            let element_path = path.clone().index(index as u32);
+            if index >= existing {
+                doc.add_change(LocalChange::insert(
+                    element_path.clone(),
+                    Value::Primitive(Primitive::Null),
+                ))?;
+            }
+            element.reconcile(doc, &element_path)?;
+        }
+        for index in (self.len()..existing).rev() {
+            doc.add_change(LocalChange::delete(path.clone().index(index as u32)))?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: Hydrate> Hydrate for Vec<T> {
+    fn hydrate(value: &Value) -> Result<Self, HydrateError> {
+        match value {
+            Value::List(values) => values.iter().map(T::hydrate).collect(),
+            other => Err(HydrateError::unexpected("a list", other)),
+        }
+    }
+}
+
+impl<K, V> Reconcile for HashMap<K, V>
+where
+    K: AsRef<str>,
+    V: Reconcile,
+{
+    /// Keys in the document which aren't in the map are deleted
+    fn reconcile(
+        &self,
+        doc: &mut dyn MutableDocument,
+        path: &Path,
+    ) -> Result<(), InvalidChangeRequest> {
+        let existing = reconcile_map(doc, path)?;
+        for (key, value) in self {
+            value.reconcile(doc, &path.clone().key(key.as_ref()))?;
+        }
+        for key in existing {
+            if !self.keys().any(|k| k.as_ref() == key.as_str()) {
+                doc.add_change(LocalChange::delete(path.clone().key(key)))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<K, V> Hydrate for HashMap<K, V>
+where
+    K: From<SmolStr> + Eq + std::hash::Hash,
+    V: Hydrate,
+{
+    fn hydrate(value: &Value) -> Result<Self, HydrateError> {
+        match value {
+            Value::Map(map) | Value::Table(map) => map
+                .iter()
+                .map(|(k, v)| Ok((K::from(k.clone()), V::hydrate(v)?)))
+                .collect(),
+            other => Err(HydrateError::unexpected("a map", other)),
+        }
+    }
+}
+
+impl Reconcile for Value {
+    fn reconcile(
+        &self,
+        doc: &mut dyn MutableDocument,
+        path: &Path,
+    ) -> Result<(), InvalidChangeRequest> {
+        reconcile_value(doc, path, self.clone())
+    }
+}
+
+impl Hydrate for Value {
+    fn hydrate(value: &Value) -> Result<Self, HydrateError> {
+        Ok(value.clone())
+    }
+}
@@ -0,0 +1,378 @@
+//! Conversion between tables or lists of maps and Arrow [`RecordBatch`]es, so that analytics
+//! code can read a document a column at a time instead of a row at a time.
+//!
+//! Each property of the rows becomes a column, in order of name. The rows of a table come in
+//! order of their IDs, which go in a leading [`ID_COLUMN`]. A row without a property, or where the
+//! property is null, has a null in that column.
+//!
+//! | Primitive   | Arrow type                    |
+//! |-------------|-------------------------------|
+//! | `Str`       | `Utf8`                        |
+//! | `Int`       | `Int64`                       |
+//! | `Uint`      | `UInt64`                      |
+//! | `F64`       | `Float64`                     |
+//! | `Boolean`   | `Boolean`                     |
+//! | `Bytes`     | `Binary`                      |
+//! | `Timestamp` | `Timestamp(Millisecond)`      |
+//! | `Counter`   | `Int64`, see [`DATATYPE_KEY`] |
+//!
+//! A column whose values are all null has the `Null` type. Every value in a column must be the
+//! same kind of primitive, and nested objects and cursors can't be converted at all.
+//!
+//! Going the other way, [`insert_rows`] and [`set_rows`] turn a record batch into the local
+//! changes which add its rows to a list or table, without going through JSON. Narrower integer
+//! and float types, `LargeUtf8`, `LargeBinary` and timestamps in any unit are accepted as well.
+//! Nulls leave the property out of the row.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+
+use arrow_array::{
+    cast::AsArray,
+    types::{
+        Float32Type, Float64Type, Int16Type, Int32Type, Int64Type, Int8Type,
+        TimestampMicrosecondType, TimestampMillisecondType, TimestampNanosecondType,
+        TimestampSecondType, UInt16Type, UInt32Type, UInt64Type, UInt8Type,
+    },
+    Array, ArrayRef, BinaryArray, BooleanArray, Float64Array, Int64Array, NullArray, RecordBatch,
+    RecordBatchOptions, StringArray, TimestampMillisecondArray, UInt64Array,
+};
+use arrow_schema::{DataType, Field, Schema, TimeUnit};
+use smol_str::SmolStr;
+
+pub use crate::error::RecordBatchError;
+use crate::{LocalChange, Path, Primitive, Value};
+
+/// The name of the column holding the IDs of the rows of a table
+pub const ID_COLUMN: &str = "id";
+
+/// The field metadata key which marks an `Int64` column as holding counters, with the value
+/// [`COUNTER_DATATYPE`]
+pub const DATATYPE_KEY: &str = "automerge:datatype";
+pub const COUNTER_DATATYPE: &str = "counter";
+
+/// The kinds of primitive which can go in a column
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ColumnType {
+    Null,
+    Str,
+    Int,
+    Uint,
+    F64,
+    Boolean,
+    Bytes,
+    Timestamp,
+    Counter,
+}
+
+impl ColumnType {
+    fn of(primitive: &Primitive) -> Option<ColumnType> {
+        match primitive {
+            Primitive::Null => Some(ColumnType::Null),
+            Primitive::Str(_) => Some(ColumnType::Str),
+            Primitive::Int(_) => Some(ColumnType::Int),
+            Primitive::Uint(_) => Some(ColumnType::Uint),
+            Primitive::F64(_) => Some(ColumnType::F64),
+            Primitive::Boolean(_) => Some(ColumnType::Boolean),
+            Primitive::Bytes(_) => Some(ColumnType::Bytes),
+            Primitive::Timestamp(_) => Some(ColumnType::Timestamp),
+            Primitive::Counter(_) => Some(ColumnType::Counter),
+            Primitive::Cursor(_) => None,
+        }
+    }
+
+    fn field(self, name: &str) -> Field {
+        let data_type = match self {
+            ColumnType::Null => DataType::Null,
+            ColumnType::Str => DataType::Utf8,
+            ColumnType::Int | ColumnType::Counter => DataType::Int64,
+            ColumnType::Uint => DataType::UInt64,
+            ColumnType::F64 => DataType::Float64,
+            ColumnType::Boolean => DataType::Boolean,
+            ColumnType::Bytes => DataType::Binary,
+            ColumnType::Timestamp => DataType::Timestamp(TimeUnit::Millisecond, None),
+        };
+        let field = Field::new(name, data_type, true);
+        if self == ColumnType::Counter {
+            let metadata = HashMap::from([(DATATYPE_KEY.to_owned(), COUNTER_DATATYPE.to_owned())]);
+            field.with_metadata(metadata)
+        } else {
+            field
+        }
+    }
+
+    fn array(self, cells: &[Option<&Primitive>]) -> ArrayRef {
+        match self {
+            ColumnType::Null => Arc::new(NullArray::new(cells.len())),
+            ColumnType::Str => Arc::new(
+                cells
+                    .iter()
+                    .map(|c| c.and_then(Primitive::str))
+                    .collect::<StringArray>(),
+            ),
+            ColumnType::Int => Arc::new(
+                cells
+                    .iter()
+                    .map(|c| c.and_then(Primitive::int))
+                    .collect::<Int64Array>(),
+            ),
+            ColumnType::Counter => Arc::new(
+                cells
+                    .iter()
+                    .map(|c| c.and_then(Primitive::counter))
+                    .collect::<Int64Array>(),
+            ),
+            ColumnType::Uint => Arc::new(
+                cells
+                    .iter()
+                    .map(|c| c.and_then(Primitive::uint))
+                    .collect::<UInt64Array>(),
+            ),
+            ColumnType::F64 => Arc::new(
+                cells
+                    .iter()
+                    .map(|c| c.and_then(Primitive::f64))
+                    .collect::<Float64Array>(),
+            ),
+            ColumnType::Boolean => Arc::new(
+                cells
+                    .iter()
+                    .map(|c| c.and_then(Primitive::boolean))
+                    .collect::<BooleanArray>(),
+            ),
+            ColumnType::Bytes => Arc::new(
+                cells
+                    .iter()
+                    .map(|c| c.and_then(Primitive::bytes))
+                    .collect::<BinaryArray>(),
+            ),
+            ColumnType::Timestamp => Arc::new(
+                cells
+                    .iter()
+                    .map(|c| c.and_then(Primitive::timestamp))
+                    .collect::<TimestampMillisecondArray>(),
+            ),
+        }
+    }
+}
+
+/// Convert a table, or a list of maps, into a record batch with a row for each row of the table
+/// or element of the list.
+pub fn to_record_batch(rows: &Value) -> Result<RecordBatch, RecordBatchError> {
+    let (ids, rows) = match rows {
+        Value::Table(table) => {
+            let mut rows: Vec<_> = table.iter().collect();
+            rows.sort_by_key(|(id, _)| *id);
+            let (ids, rows): (Vec<_>, _) =
+                rows.into_iter().map(|(id, row)| (id.as_str(), row)).unzip();
+            (Some(ids), rows)
+        }
+        Value::List(rows) => (None, rows.iter().collect::<Vec<_>>()),
+        other => return Err(RecordBatchError::NotRows(other.kind())),
+    };
+
+    // the primitives in each column, by row
+    let mut columns: BTreeMap<&SmolStr, Vec<Option<&Primitive>>> = BTreeMap::new();
+    for (index, row) in rows.iter().enumerate() {
+        let row = row.map().ok_or_else(|| RecordBatchError::RowNotAMap {
+            row: index,
+            found: row.kind(),
+        })?;
+        for (name, value) in row {
+            if ids.is_some() && name == ID_COLUMN {
+                return Err(RecordBatchError::IdColumnClash);
+            }
+            let primitive = value
+                .primitive()
+                .ok_or_else(|| RecordBatchError::NestedObject {
+                    column: name.clone(),
+                    found: value.kind(),
+                })?;
+            columns
+                .entry(name)
+                .or_insert_with(|| vec![None; rows.len()])[index] = Some(primitive);
+        }
+    }
+
+    let mut fields = Vec::with_capacity(columns.len() + 1);
+    let mut arrays = Vec::with_capacity(columns.len() + 1);
+    if let Some(ids) = ids {
+        fields.push(Field::new(ID_COLUMN, DataType::Utf8, false));
+        arrays.push(Arc::new(StringArray::from(ids)) as ArrayRef);
+    }
+    for (name, cells) in columns {
+        let column_type = column_type(name, &cells)?;
+        fields.push(column_type.field(name));
+        arrays.push(column_type.array(&cells));
+    }
+    let options = RecordBatchOptions::new().with_row_count(Some(rows.len()));
+    RecordBatch::try_new_with_options(Arc::new(Schema::new(fields)), arrays, &options)
+        .map_err(|e| RecordBatchError::Arrow(e.to_string()))
+}
+
+/// The type of the non null primitives in a column
+fn column_type(
+    name: &SmolStr,
+    cells: &[Option<&Primitive>],
+) -> Result<ColumnType, RecordBatchError> {
+    let mut column_type = ColumnType::Null;
+    for primitive in cells.iter().flatten() {
+        let cell_type = ColumnType::of(primitive).ok_or_else(|| RecordBatchError::Cursor {
+            column: name.clone(),
+        })?;
+        match (column_type, cell_type) {
+            (_, ColumnType::Null) => {}
+            (ColumnType::Null, _) => column_type = cell_type,
+            (current, cell_type) if current == cell_type => {}
+            (current, cell_type) => {
+                return Err(RecordBatchError::MixedColumn {
+                    column: name.clone(),
+                    first: format!("{:?}", current),
+                    second: format!("{:?}", cell_type),
+                })
+            }
+        }
+    }
+    Ok(column_type)
+}
+
+/// The rows of `batch` as maps, in order
+pub fn from_record_batch(batch: &RecordBatch) -> Result<Vec<Value>, RecordBatchError> {
+    Ok(rows(batch)?.into_iter().map(Value::Map).collect())
+}
+
+/// A change which inserts the rows of `batch` into the list at `path`, which must end in the
+/// index to insert the first row at, as for [`LocalChange::insert_many`]
+pub fn insert_rows(path: Path, batch: &RecordBatch) -> Result<LocalChange, RecordBatchError> {
+    Ok(LocalChange::insert_many(path, from_record_batch(batch)?))
+}
+
+/// The changes which set the rows of the table at `path` to the rows of `batch`, which are keyed
+/// by its [`ID_COLUMN`]
+pub fn set_rows(path: Path, batch: &RecordBatch) -> Result<Vec<LocalChange>, RecordBatchError> {
+    if batch.schema().column_with_name(ID_COLUMN).is_none() {
+        return Err(RecordBatchError::NoIdColumn);
+    }
+    rows(batch)?
+        .into_iter()
+        .enumerate()
+        .map(|(index, mut row)| match row.remove(ID_COLUMN) {
+            Some(Value::Primitive(Primitive::Str(id))) => {
+                Ok(LocalChange::set(path.clone().key(id), Value::Map(row)))
+            }
+            _ => Err(RecordBatchError::InvalidId { row: index }),
+        })
+        .collect()
+}
+
+fn rows(batch: &RecordBatch) -> Result<Vec<HashMap<SmolStr, Value>>, RecordBatchError> {
+    let mut rows = vec![HashMap::new(); batch.num_rows()];
+    for (field, array) in batch.schema().fields().iter().zip(batch.columns()) {
+        let name = SmolStr::new(field.name());
+        for (row, cell) in rows.iter_mut().zip(primitives(field, array)?) {
+            if let Some(primitive) = cell {
+                row.insert(name.clone(), Value::Primitive(primitive));
+            }
+        }
+    }
+    Ok(rows)
+}
+
+/// The primitive in each row of a column, or `None` where it is null
+fn primitives(field: &Field, array: &ArrayRef) -> Result<Vec<Option<Primitive>>, RecordBatchError> {
+    macro_rules! convert {
+        ($array:expr, |$value:ident| $primitive:expr) => {{
+            let array = $array;
+            (0..array.len())
+                .map(|i| {
+                    if array.is_null(i) {
+                        None
+                    } else {
+                        let $value = array.value(i);
+                        Some($primitive)
+                    }
+                })
+                .collect()
+        }};
+    }
+
+    let is_counter =
+        field.metadata().get(DATATYPE_KEY).map(String::as_str) == Some(COUNTER_DATATYPE);
+    Ok(match array.data_type() {
+        DataType::Null => vec![None; array.len()],
+        DataType::Utf8 => convert!(array.as_string::<i32>(), |v| Primitive::Str(v.into())),
+        DataType::LargeUtf8 => convert!(array.as_string::<i64>(), |v| Primitive::Str(v.into())),
+        DataType::Int64 if is_counter => {
+            convert!(array.as_primitive::<Int64Type>(), |v| Primitive::Counter(v))
+        }
+        DataType::Int8 => convert!(array.as_primitive::<Int8Type>(), |v| Primitive::Int(
+            v.into()
+        )),
+        DataType::Int16 => {
+            convert!(array.as_primitive::<Int16Type>(), |v| Primitive::Int(
+                v.into()
+            ))
+        }
+        DataType::Int32 => {
+            convert!(array.as_primitive::<Int32Type>(), |v| Primitive::Int(
+                v.into()
+            ))
+        }
+        DataType::Int64 => convert!(array.as_primitive::<Int64Type>(), |v| Primitive::Int(v)),
+        DataType::UInt8 => {
+            convert!(array.as_primitive::<UInt8Type>(), |v| Primitive::Uint(
+                v.into()
+            ))
+        }
+        DataType::UInt16 => {
+            convert!(array.as_primitive::<UInt16Type>(), |v| Primitive::Uint(
+                v.into()
+            ))
+        }
+        DataType::UInt32 => {
+            convert!(array.as_primitive::<UInt32Type>(), |v| Primitive::Uint(
+                v.into()
+            ))
+        }
+        DataType::UInt64 => convert!(array.as_primitive::<UInt64Type>(), |v| Primitive::Uint(v)),
+        DataType::Float32 => {
+            convert!(array.as_primitive::<Float32Type>(), |v| Primitive::F64(
+                v.into()
+            ))
+        }
+        DataType::Float64 => convert!(array.as_primitive::<Float64Type>(), |v| Primitive::F64(v)),
+        DataType::Boolean => convert!(array.as_boolean(), |v| Primitive::Boolean(v)),
+        DataType::Binary => convert!(array.as_binary::<i32>(), |v| Primitive::Bytes(v.to_vec())),
+        DataType::LargeBinary => {
+            convert!(array.as_binary::<i64>(), |v| Primitive::Bytes(v.to_vec()))
+        }
+        DataType::Timestamp(TimeUnit::Second, _) => {
+            convert!(array.as_primitive::<TimestampSecondType>(), |v| {
+                Primitive::Timestamp(v.saturating_mul(1000))
+            })
+        }
+        DataType::Timestamp(TimeUnit::Millisecond, _) => {
+            convert!(array.as_primitive::<TimestampMillisecondType>(), |v| {
+                Primitive::Timestamp(v)
+            })
+        }
+        DataType::Timestamp(TimeUnit::Microsecond, _) => {
+            convert!(array.as_primitive::<TimestampMicrosecondType>(), |v| {
+                Primitive::Timestamp(v / 1000)
+            })
+        }
+        DataType::Timestamp(TimeUnit::Nanosecond, _) => {
+            convert!(array.as_primitive::<TimestampNanosecondType>(), |v| {
+                Primitive::Timestamp(v / 1_000_000)
+            })
+        }
+        other => {
+            return Err(RecordBatchError::UnsupportedArrowType {
+                column: field.name().clone(),
+                data_type: other.to_string(),
+            })
+        }
+    })
+}
@@ -0,0 +1,100 @@
+//! Max-wins and min-wins registers for numbers which are updated concurrently, such as
+//! high-water marks.
+//!
+//! Automerge resolves concurrent sets of the same key by picking a winner by op ID, which for a
+//! high-water mark means a lower value can win over a higher one. These helpers read all of the
+//! conflicting values at a path and treat the largest (or smallest) as the value of the register.
+//! [`set_max`] and [`set_min`] only write when the new value improves on all of them, so a write
+//! also resolves any conflicts.
+//!
+//! Integers, floats and timestamps can be compared with each other. Values at the path which
+//! aren't numbers are ignored.
+use std::cmp::Ordering;
+
+use crate::{
+    error::InvalidChangeRequest, Frontend, LocalChange, MutableDocument, Path, Primitive, Value,
+};
+
+/// The largest of the values at `path`
+pub fn max_at(frontend: &Frontend, path: &Path) -> Option<Primitive> {
+    best(
+        frontend.get_conflicts(path)?.into_values(),
+        Ordering::Greater,
+    )
+}
+
+/// The smallest of the values at `path`
+pub fn min_at(frontend: &Frontend, path: &Path) -> Option<Primitive> {
+    best(frontend.get_conflicts(path)?.into_values(), Ordering::Less)
+}
+
+/// Set `path` to `n` if it is larger than all of the values there, returning whether it was set
+pub fn set_max(
+    doc: &mut dyn MutableDocument,
+    path: &Path,
+    n: Primitive,
+) -> Result<bool, InvalidChangeRequest> {
+    set_if(doc, path, n, Ordering::Greater)
+}
+
+/// Set `path` to `n` if it is smaller than all of the values there, returning whether it was set
+pub fn set_min(
+    doc: &mut dyn MutableDocument,
+    path: &Path,
+    n: Primitive,
+) -> Result<bool, InvalidChangeRequest> {
+    set_if(doc, path, n, Ordering::Less)
+}
+
+fn set_if(
+    doc: &mut dyn MutableDocument,
+    path: &Path,
+    n: Primitive,
+    wins: Ordering,
+) -> Result<bool, InvalidChangeRequest> {
+    if compare(&n, &n).is_none() {
+        return Err(InvalidChangeRequest::NotANumber {
+            path: path.clone(),
+            value: n,
+        });
+    }
+    let current = doc
+        .conflicts_at_path(path)
+        .and_then(|values| best(values.into_values(), wins));
+    match current {
+        Some(current) if compare(&n, &current) != Some(wins) => Ok(false),
+        _ => {
+            doc.add_change(LocalChange::set(path.clone(), Value::Primitive(n)))?;
+            Ok(true)
+        }
+    }
+}
+
+fn best<I: Iterator<Item = Value>>(values: I, wins: Ordering) -> Option<Primitive> {
+    values
+        .filter_map(|value| match value {
+            Value::Primitive(p) if compare(&p, &p).is_some() => Some(p),
+            _ => None,
+        })
+        .fold(None, |best, p| match best {
+            Some(b) if compare(&p, &b) != Some(wins) => Some(b),
+            _ => Some(p),
+        })
+}
+
+/// Compare two numeric primitives, `None` if either isn't a number (or is NaN)
+fn compare(a: &Primitive, b: &Primitive) -> Option<Ordering> {
+    fn as_i128(p: &Primitive) -> Option<i128> {
+        match p {
+            Primitive::Int(i) | Primitive::Timestamp(i) => Some(i128::from(*i)),
+            Primitive::Uint(u) => Some(i128::from(*u)),
+            _ => None,
+        }
+    }
+    match (a, b) {
+        (Primitive::F64(a), Primitive::F64(b)) => a.partial_cmp(b),
+        (Primitive::F64(f), other) => f.partial_cmp(&(as_i128(other)? as f64)),
+        (other, Primitive::F64(f)) => (as_i128(other)? as f64).partial_cmp(f),
+        (a, b) => Some(as_i128(a)?.cmp(&as_i128(b)?)),
+    }
+}
@@ -0,0 +1,144 @@
+//! Conversion between [`Value`] and anything which implements `Serialize` or `Deserialize`,
+//! in the same way `serde_json::Value` works.
+//!
+//! ```
+//! use automerge_frontend::serde_value::{self, from_value, to_value};
+//! use serde::{Deserialize, Serialize};
+//!
+//! #[derive(Serialize, Deserialize, Debug, PartialEq)]
+//! struct Task {
+//!     title: String,
+//!     #[serde(with = "serde_value::timestamp")]
+//!     due: i64,
+//!     #[serde(with = "serde_value::counter")]
+//!     votes: i64,
+//! }
+//!
+//! let task = Task { title: "Feed the birds".into(), due: 1_600_000_000_000, votes: 3 };
+//! let value = to_value(&task).unwrap();
+//! assert_eq!(from_value::<Task>(value).unwrap(), task);
+//! ```
+//!
+//! Serde has no notion of timestamps or counters, a field which should become one of those
+//! datatypes is marked with `#[serde(with = "...")]` pointing at the [`timestamp`] or [`counter`]
+//! module. Byte buffers are serialized as sequences by default, the [`bytes`] module makes them
+//! [`Primitive::Bytes`] instead. With any other serializer the attributes make no difference.
+//!
+//! Map keys must serialize to strings or integers. Enums use serde's default externally tagged
+//! representation: unit variants are strings and other variants a map with a single key.
+mod de;
+mod ser;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+pub use crate::error::SerdeValueError;
+use crate::{Primitive, Value};
+
+/// The newtype struct name the [`timestamp`] module uses to tell [`to_value`] and
+/// [`from_value`] about timestamps. Other serializers treat it as a plain newtype.
+const TIMESTAMP_TOKEN: &str = "$automerge::Timestamp";
+const COUNTER_TOKEN: &str = "$automerge::Counter";
+
+/// Serialize `value` into a [`Value`]
+pub fn to_value<T>(value: &T) -> Result<Value, SerdeValueError>
+where
+    T: Serialize + ?Sized,
+{
+    value.serialize(ser::Serializer)
+}
+
+/// Deserialize a `T` from `value`
+pub fn from_value<T>(value: Value) -> Result<T, SerdeValueError>
+where
+    T: DeserializeOwned,
+{
+    T::deserialize(value)
+}
+
+/// `#[serde(with = "automerge_frontend::serde_value::timestamp")]` on an `i64` field holding
+/// milliseconds since the epoch makes it a [`Primitive::Timestamp`]
+pub mod timestamp {
+    use serde::{Deserializer, Serializer};
+
+    use super::TIMESTAMP_TOKEN;
+
+    pub fn serialize<S>(millis: &i64, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_newtype_struct(TIMESTAMP_TOKEN, millis)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<i64, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_newtype_struct(TIMESTAMP_TOKEN, super::de::I64Visitor)
+    }
+}
+
+/// `#[serde(with = "automerge_frontend::serde_value::counter")]` on an `i64` field makes it a
+/// [`Primitive::Counter`]
+pub mod counter {
+    use serde::{Deserializer, Serializer};
+
+    use super::COUNTER_TOKEN;
+
+    pub fn serialize<S>(count: &i64, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_newtype_struct(COUNTER_TOKEN, count)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<i64, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_newtype_struct(COUNTER_TOKEN, super::de::I64Visitor)
+    }
+}
+
+/// `#[serde(with = "automerge_frontend::serde_value::bytes")]` on a `Vec<u8>` field makes it a
+/// [`Primitive::Bytes`] rather than a list of integers
+pub mod bytes {
+    use serde::{Deserializer, Serializer};
+
+    pub fn serialize<T, S>(bytes: &T, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: AsRef<[u8]> + ?Sized,
+        S: Serializer,
+    {
+        serializer.serialize_bytes(bytes.as_ref())
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<u8>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_byte_buf(super::de::BytesVisitor)
+    }
+}
+
+impl Value {
+    fn unexpected(&self) -> serde::de::Unexpected<'_> {
+        use serde::de::Unexpected;
+        match self {
+            Value::Map(_) | Value::Table(_) => Unexpected::Map,
+            Value::List(_) => Unexpected::Seq,
+            Value::Text(_) => Unexpected::Other("text"),
+            Value::Primitive(p) => match p {
+                Primitive::Bytes(b) => Unexpected::Bytes(b),
+                Primitive::Str(s) => Unexpected::Str(s),
+                Primitive::Int(i) | Primitive::Counter(i) | Primitive::Timestamp(i) => {
+                    Unexpected::Signed(*i)
+                }
+                Primitive::Uint(u) => Unexpected::Unsigned(*u),
+                Primitive::F64(f) => Unexpected::Float(*f),
+                Primitive::Boolean(b) => Unexpected::Bool(*b),
+                Primitive::Cursor(_) => Unexpected::Other("cursor"),
+                Primitive::Null => Unexpected::Unit,
+            },
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use std::fmt;
+
+use serde::{
+    de::{
+        self,
+        value::{MapAccessDeserializer, MapDeserializer, SeqDeserializer},
+        IntoDeserializer, Visitor,
+    },
+    forward_to_deserialize_any,
+};
+
+use super::{COUNTER_TOKEN, TIMESTAMP_TOKEN};
+use crate::{error::SerdeValueError, Primitive, Value};
+
+impl<'de> IntoDeserializer<'de, SerdeValueError> for Value {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+impl<'de> de::Deserializer<'de> for Value {
+    type Error = SerdeValueError;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        match self {
+            Value::Map(map) | Value::Table(map) => {
+                let mut entries =
+                    MapDeserializer::new(map.into_iter().map(|(k, v)| (String::from(k), v)));
+                let result = visitor.visit_map(&mut entries)?;
+                entries.end()?;
+                Ok(result)
+            }
+            Value::List(values) => {
+                let mut elements = SeqDeserializer::new(values.into_iter());
+                let result = visitor.visit_seq(&mut elements)?;
+                elements.end()?;
+                Ok(result)
+            }
+            Value::Text(graphemes) => visitor.visit_string(graphemes.concat()),
+            Value::Primitive(p) => match p {
+                Primitive::Bytes(b) => visitor.visit_byte_buf(b),
+                Primitive::Str(s) => visitor.visit_string(s.into()),
+                Primitive::Int(i) | Primitive::Counter(i) | Primitive::Timestamp(i) => {
+                    visitor.visit_i64(i)
+                }
+                Primitive::Uint(u) => visitor.visit_u64(u),
+                Primitive::F64(f) => visitor.visit_f64(f),
+                Primitive::Boolean(b) => visitor.visit_bool(b),
+                Primitive::Cursor(c) => visitor.visit_u32(c.index),
+                Primitive::Null => visitor.visit_unit(),
+            },
+        }
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        match self {
+            Value::Primitive(Primitive::Null) => visitor.visit_none(),
+            other => visitor.visit_some(other),
+        }
+    }
+
+    fn deserialize_newtype_struct<V>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        match (name, &self) {
+            (TIMESTAMP_TOKEN, Value::Primitive(Primitive::Timestamp(_)))
+            | (COUNTER_TOKEN, Value::Primitive(Primitive::Counter(_)))
+            | (TIMESTAMP_TOKEN, Value::Primitive(Primitive::Int(_)))
+            | (COUNTER_TOKEN, Value::Primitive(Primitive::Int(_))) => {
+                visitor.visit_newtype_struct(self)
+            }
+            (TIMESTAMP_TOKEN, _) | (COUNTER_TOKEN, _) => {
+                Err(de::Error::invalid_type(self.unexpected(), &visitor))
+            }
+            _ => visitor.visit_newtype_struct(self),
+        }
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        match self {
+            Value::Primitive(Primitive::Str(variant)) => {
+                visitor.visit_enum(String::from(variant).into_deserializer())
+            }
+            Value::Map(map) if map.len() == 1 => visitor.visit_enum(MapAccessDeserializer::new(
+                MapDeserializer::new(map.into_iter().map(|(k, v)| (String::from(k), v))),
+            )),
+            other => Err(de::Error::invalid_type(
+                other.unexpected(),
+                &"a string or a map with a single key",
+            )),
+        }
+    }
+
+    fn deserialize_ignored_any<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_unit()
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct identifier
+    }
+}
+
+/// Reads the `i64` inside a timestamp or counter
+pub(super) struct I64Visitor;
+
+impl<'de> Visitor<'de> for I64Visitor {
+    type Value = i64;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an i64")
+    }
+
+    fn visit_i64<E>(self, v: i64) -> Result<i64, E>
+    where
+        E: de::Error,
+    {
+        Ok(v)
+    }
+
+    fn visit_u64<E>(self, v: u64) -> Result<i64, E>
+    where
+        E: de::Error,
+    {
+        if v <= i64::MAX as u64 {
+            Ok(v as i64)
+        } else {
+            Err(E::invalid_value(de::Unexpected::Unsigned(v), &self))
+        }
+    }
+
+    fn visit_newtype_struct<D>(self, deserializer: D) -> Result<i64, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        deserializer.deserialize_i64(self)
+    }
+}
+
+/// Reads a byte buffer from bytes, a string or a list of integers
+pub(super) struct BytesVisitor;
+
+impl<'de> Visitor<'de> for BytesVisitor {
+    type Value = Vec<u8>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("bytes")
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> Result<Vec<u8>, E>
+    where
+        E: de::Error,
+    {
+        Ok(v.to_vec())
+    }
+
+    fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<Vec<u8>, E>
+    where
+        E: de::Error,
+    {
+        Ok(v)
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Vec<u8>, E>
+    where
+        E: de::Error,
+    {
+        Ok(v.as_bytes().to_vec())
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Vec<u8>, A::Error>
+    where
+        A: de::SeqAccess<'de>,
+    {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+        Ok(bytes)
+    }
+}
@@ -6,7 +6,7 @@ use crate::{
     mutation::MutationTracker,
     state_tree::{OptimisticStateTree, ResolvedPath, StateTree},
     value_ref::RootRef,
-    InvalidPatch, MutableDocument, Path, Value,
+    InvalidPatch, MutableDocument, Path, TextIndexing, Value,
 };
 
 /// Tracks the possible states of the frontend
@@ -166,6 +166,30 @@ impl FrontendState {
         }
     }
 
+    /// The state as of the patches received so far, without any in flight local changes
+    pub(crate) fn reconciled_state(&self) -> Result<StateTree, InvalidPatch> {
+        match self {
+            FrontendState::WaitingForInFlightRequests {
+                optimistic_root_state,
+                queued_diffs,
+                ..
+            } => {
+                let mut optimistic_root_state = optimistic_root_state.clone();
+                optimistic_root_state.rollback_all();
+                let mut reconciled_root_state = optimistic_root_state.take_state();
+                for diff in queued_diffs {
+                    let checked_diff = reconciled_root_state.check_diff(diff.clone())?;
+                    reconciled_root_state.apply_diff(checked_diff);
+                }
+                Ok(reconciled_root_state)
+            }
+            FrontendState::Reconciled {
+                reconciled_root_state,
+                ..
+            } => Ok(reconciled_root_state.clone()),
+        }
+    }
+
     pub(crate) fn get_object_id(&self, path: &Path) -> Option<amp::ObjectId> {
         self.resolve_path(path).and_then(|r| r.object_id())
     }
@@ -197,6 +221,7 @@ impl FrontendState {
         actor: &amp::ActorId,
         change_closure: F,
         seq: u64,
+        text_indexing: TextIndexing,
     ) -> Result<OptimisticChangeResult<O>, E>
     where
         E: Error,
@@ -209,8 +234,12 @@ impl FrontendState {
                 max_op,
                 ..
             } => {
-                let mut mutation_tracker =
-                    MutationTracker::new(optimistic_root_state, *max_op, actor.clone());
+                let mut mutation_tracker = MutationTracker::new(
+                    optimistic_root_state,
+                    *max_op,
+                    actor.clone(),
+                    text_indexing,
+                );
 
                 let result = match change_closure(&mut mutation_tracker) {
                     Ok(result) => result,
@@ -242,8 +271,12 @@ impl FrontendState {
                 let mut optimistic_root_state =
                     OptimisticStateTree::new(std::mem::take(reconciled_root_state));
 
-                let mut mutation_tracker =
-                    MutationTracker::new(&mut optimistic_root_state, *max_op, actor.clone());
+                let mut mutation_tracker = MutationTracker::new(
+                    &mut optimistic_root_state,
+                    *max_op,
+                    actor.clone(),
+                    text_indexing,
+                );
 
                 let result = match change_closure(&mut mutation_tracker) {
                     Ok(result) => result,
@@ -294,6 +327,18 @@ impl FrontendState {
         }
     }
 
+    /// Whether no op has been made or received, nor any change the backend told us about
+    pub(crate) fn is_empty(&self) -> bool {
+        match self {
+            FrontendState::WaitingForInFlightRequests { .. } => false,
+            FrontendState::Reconciled {
+                max_op,
+                deps_of_last_received_patch,
+                ..
+            } => *max_op == 0 && deps_of_last_received_patch.is_empty(),
+        }
+    }
+
     pub(crate) fn max_op(&self) -> u64 {
         match self {
             FrontendState::WaitingForInFlightRequests { max_op, .. } => *max_op,
@@ -1,11 +1,15 @@
-use std::collections::HashMap;
+use std::{cell::OnceCell, collections::HashMap};
 
 use amp::{ActorId, OpId};
 use automerge_protocol as amp;
 use smol_str::SmolStr;
 
 use super::{MultiGrapheme, MultiValue, StateTreeValue};
-use crate::error::InvalidPatch;
+use crate::{
+    error::InvalidPatch,
+    patch_stats,
+    value::{Primitive, Value},
+};
 
 pub(crate) trait DiffableValue: Sized {
     fn take(&mut self) -> Self;
@@ -18,24 +22,19 @@ pub(crate) trait DiffableValue: Sized {
 
     fn construct(opid: amp::OpId, diff: amp::Diff) -> Self;
 
-    fn check_diff(
-        &self,
-        opid: &amp::OpId,
-        diff: &amp::Diff,
-        parent_object_id: &amp::ObjectId,
-    ) -> Result<(), InvalidPatch>;
-
     fn apply_diff(&mut self, opid: amp::OpId, diff: amp::Diff);
 
-    fn apply_diff_iter<I>(&mut self, diff: &mut I)
-    where
-        I: Iterator<Item = (amp::OpId, amp::Diff)>;
-
     fn default_opid(&self) -> amp::OpId;
 
+    /// The realised value of the winning op
+    fn default_value(&self) -> Value;
+
     fn only_for_opid(&self, opid: amp::OpId) -> Option<Self>;
 
     fn add_values_from(&mut self, other: Self);
+
+    /// The number of values, i.e. one more than the number of conflicts
+    fn value_count(&self) -> usize;
 }
 
 impl DiffableValue for MultiGrapheme {
@@ -61,31 +60,18 @@ impl DiffableValue for MultiGrapheme {
         MultiGrapheme::new_from_diff(opid, diff)
     }
 
-    fn check_diff(
-        &self,
-        opid: &amp::OpId,
-        diff: &amp::Diff,
-        parent_object_id: &amp::ObjectId,
-    ) -> Result<(), InvalidPatch> {
-        MultiGrapheme::check_diff(self, opid, diff, parent_object_id)
-    }
-
     fn apply_diff(&mut self, opid: amp::OpId, diff: amp::Diff) {
         MultiGrapheme::apply_diff(self, opid, diff)
     }
 
-    fn apply_diff_iter<I>(&mut self, diff: &mut I)
-    where
-        I: Iterator<Item = (amp::OpId, amp::Diff)>,
-    {
-        self.apply_diff_iter(diff)
-        //MultiGrapheme::apply_diff_iter(self, diff)
-    }
-
     fn default_opid(&self) -> amp::OpId {
         self.default_opid().clone()
     }
 
+    fn default_value(&self) -> Value {
+        Value::Primitive(Primitive::Str(self.default_grapheme().clone()))
+    }
+
     fn only_for_opid(&self, opid: amp::OpId) -> Option<MultiGrapheme> {
         self.only_for_opid(opid)
     }
@@ -93,6 +79,10 @@ impl DiffableValue for MultiGrapheme {
     fn add_values_from(&mut self, other: MultiGrapheme) {
         self.add_values_from(other)
     }
+
+    fn value_count(&self) -> usize {
+        self.conflicts.len() + 1
+    }
 }
 
 impl DiffableValue for MultiValue {
@@ -121,30 +111,18 @@ impl DiffableValue for MultiValue {
         MultiValue::new_from_diff(opid, diff)
     }
 
-    fn check_diff(
-        &self,
-        opid: &amp::OpId,
-        diff: &amp::Diff,
-        _parent_object_id: &amp::ObjectId,
-    ) -> Result<(), InvalidPatch> {
-        self.check_diff(opid, diff)
-    }
-
     fn apply_diff(&mut self, opid: amp::OpId, diff: amp::Diff) {
         self.apply_diff(opid, diff)
     }
 
-    fn apply_diff_iter<I>(&mut self, diff: &mut I)
-    where
-        I: Iterator<Item = (amp::OpId, amp::Diff)>,
-    {
-        self.apply_diff_iter(diff)
-    }
-
     fn default_opid(&self) -> amp::OpId {
         self.default_opid()
     }
 
+    fn default_value(&self) -> Value {
+        self.default_value()
+    }
+
     fn only_for_opid(&self, opid: amp::OpId) -> Option<MultiValue> {
         self.only_for_opid(opid)
     }
@@ -152,6 +130,10 @@ impl DiffableValue for MultiValue {
     fn add_values_from(&mut self, other: MultiValue) {
         self.add_values_from(other)
     }
+
+    fn value_count(&self) -> usize {
+        self.conflicts.len() + 1
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -163,6 +145,30 @@ where
 {
     opid: OpId,
     value: SequenceValue<T>,
+    winner: WinnerCache,
+}
+
+/// The realised winning value of a sequence element. Realising a conflicted element which
+/// holds a large object is expensive, and most elements don't change between one patch and
+/// the next, so it is kept until something modifies the element.
+#[derive(Clone, Debug, Default)]
+struct WinnerCache(OnceCell<Box<Value>>);
+
+impl WinnerCache {
+    fn get_or_init<F: FnOnce() -> Value>(&self, f: F) -> &Value {
+        self.0.get_or_init(|| Box::new(f()))
+    }
+
+    fn invalidate(&mut self) {
+        self.0.take();
+    }
+}
+
+// Whether the value has been realised yet doesn't change the element
+impl PartialEq for WinnerCache {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
 }
 
 impl<T> SequenceElement<T>
@@ -175,6 +181,7 @@ where
         Self {
             opid: value.default_opid(),
             value: SequenceValue::Original(value),
+            winner: WinnerCache::default(),
         }
     }
 
@@ -182,8 +189,15 @@ where
         Self {
             opid: value.default_opid(),
             value: SequenceValue::New(value),
+            winner: WinnerCache::default(),
         }
     }
+
+    fn default_value(&self) -> Value {
+        self.winner
+            .get_or_init(|| self.value.get().default_value())
+            .clone()
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -228,81 +242,7 @@ where
         object_id: &amp::ObjectId,
         edits: &[amp::DiffEdit],
     ) -> Result<(), InvalidPatch> {
-        let mut size = self.underlying.len();
-        for edit in edits {
-            match edit {
-                amp::DiffEdit::Remove { index, count } => {
-                    let index = *index as usize;
-                    let count = *count as usize;
-                    if index >= size {
-                        return Err(InvalidPatch::InvalidIndex {
-                            object_id: object_id.clone(),
-                            index,
-                        });
-                    }
-                    if index + count > size {
-                        return Err(InvalidPatch::InvalidIndex {
-                            object_id: object_id.clone(),
-                            index: size,
-                        });
-                    }
-                    size -= count;
-                }
-                amp::DiffEdit::SingleElementInsert {
-                    index,
-                    elem_id: _,
-                    op_id,
-                    value,
-                } => {
-                    T::check_construct(op_id, value, object_id)?;
-                    if *index as usize > size {
-                        return Err(InvalidPatch::InvalidIndex {
-                            object_id: object_id.clone(),
-                            index: *index as usize,
-                        });
-                    }
-                    size += 1;
-                }
-                amp::DiffEdit::MultiElementInsert(amp::MultiElementInsert {
-                    elem_id,
-                    values,
-                    index,
-                }) => {
-                    let index = *index as usize;
-                    if index > size {
-                        return Err(InvalidPatch::InvalidIndex {
-                            index,
-                            object_id: object_id.clone(),
-                        });
-                    }
-                    for (i, value) in values.iter().enumerate() {
-                        let opid = elem_id.as_opid().unwrap().increment_by(i as u64);
-                        T::check_construct(&opid, &amp::Diff::Value(value.clone()), object_id)?;
-                    }
-                    size += values.len();
-                }
-                amp::DiffEdit::Update {
-                    index,
-                    value: _,
-                    op_id: _,
-                } => {
-                    // TODO: handle updates after things like inserts shifting them
-                    if *index as usize >= size {
-                        return Err(InvalidPatch::InvalidIndex {
-                            index: *index as usize,
-                            object_id: object_id.clone(),
-                        });
-                    }
-
-                    // if let Some((_id, elem)) = self.underlying.get(*index as usize) {
-                    //     elem.check_diff(op_id, value)?;
-                    // } else {
-                    // }
-                }
-            };
-        }
-
-        Ok(())
+        check_edits::<T>(self.underlying.len(), object_id, edits)
     }
 
     pub fn apply_diff(&mut self, _object_id: &amp::ObjectId, edits: Vec<amp::DiffEdit>) {
@@ -313,6 +253,7 @@ where
                     let index = index as usize;
                     let count = count as usize;
                     self.underlying.slice(index..(index + count));
+                    patch_stats::record(|stats| stats.elements_removed += count as u64);
 
                     let mut i = 0;
                     while i < changed_indices.len() {
@@ -328,19 +269,28 @@ where
                         i += 1;
                     }
                 }
+                amp::DiffEdit::Marks(_) => {}
                 amp::DiffEdit::SingleElementInsert {
                     index,
-                    elem_id: _,
+                    elem_id,
                     op_id,
                     value,
                 } => {
                     let node = T::construct(op_id, value);
+                    // The element ID differs from the ID of the op which set the value when an
+                    // element has been moved
+                    let element = match elem_id.as_opid() {
+                        Some(elem_id) => SequenceElement {
+                            opid: elem_id.clone(),
+                            value: SequenceValue::New(node),
+                            winner: WinnerCache::default(),
+                        },
+                        None => SequenceElement::new(node),
+                    };
                     if (index as usize) == self.underlying.len() {
-                        self.underlying
-                            .push_back(Box::new(SequenceElement::new(node)));
+                        self.underlying.push_back(Box::new(element));
                     } else {
-                        self.underlying
-                            .insert(index as usize, Box::new(SequenceElement::new(node)));
+                        self.underlying.insert(index as usize, Box::new(element));
 
                         for changed_index in changed_indices.iter_mut() {
                             if *changed_index >= index as u64 {
@@ -349,36 +299,31 @@ where
                         }
                     };
                     changed_indices.push(index);
+                    patch_stats::record(|stats| stats.elements_inserted += 1);
                 }
                 amp::DiffEdit::MultiElementInsert(amp::MultiElementInsert {
                     elem_id,
                     values,
                     index,
                 }) => {
-                    let index = index as usize;
-                    // building an intermediate vector can be better than just inserting
-                    // TODO: only do this if there are a certain (to be worked out) number of
-                    // values
-                    // TODO: if all inserts are at the end then use push_back
-                    let mut intermediate = im_rc::Vector::new();
-                    for (i, value) in values.iter().enumerate() {
-                        let opid = elem_id.as_opid().unwrap().increment_by(i as u64);
-                        let mv = T::construct(opid, amp::Diff::Value(value.clone()));
-                        intermediate.push_back(Box::new(SequenceElement::new(mv)));
-                    }
-                    let right = self.underlying.split_off(index);
-                    self.underlying.append(intermediate);
-                    self.underlying.append(right);
-
-                    for changed_index in changed_indices.iter_mut() {
-                        if *changed_index >= index as u64 {
-                            *changed_index += values.len() as u64;
-                        }
-                    }
-
-                    for i in index..(index + values.len()) {
-                        changed_indices.push(i as u64);
-                    }
+                    self.insert_run(
+                        index as usize,
+                        elem_id.as_opid().unwrap(),
+                        values.iter().cloned(),
+                        &mut changed_indices,
+                    );
+                }
+                amp::DiffEdit::TextInsert(insert) => {
+                    // `check_diff` ensures the characters are well formed
+                    let characters = insert.characters().unwrap_or_default();
+                    self.insert_run(
+                        insert.index as usize,
+                        insert.elem_id.as_opid().unwrap(),
+                        characters
+                            .into_iter()
+                            .map(|c| amp::ScalarValue::Str(c.into())),
+                        &mut changed_indices,
+                    );
                 }
                 amp::DiffEdit::Update {
                     index,
@@ -386,6 +331,7 @@ where
                     op_id,
                 } => {
                     if let Some(v) = self.underlying.get_mut(index as usize) {
+                        v.winner.invalidate();
                         v.value.apply_diff(op_id, value);
                     }
                     changed_indices.push(index);
@@ -407,6 +353,43 @@ where
         );
     }
 
+    /// Insert `values` at `index`, the values have consecutive element IDs starting at `elem_id`
+    fn insert_run<I>(
+        &mut self,
+        index: usize,
+        elem_id: &OpId,
+        values: I,
+        changed_indices: &mut Vec<u64>,
+    ) where
+        I: Iterator<Item = amp::ScalarValue>,
+    {
+        // building an intermediate vector can be better than just inserting
+        // TODO: only do this if there are a certain (to be worked out) number of
+        // values
+        // TODO: if all inserts are at the end then use push_back
+        let mut intermediate = im_rc::Vector::new();
+        for (i, value) in values.enumerate() {
+            let opid = elem_id.increment_by(i as u64);
+            let mv = T::construct(opid, amp::Diff::Value(value));
+            intermediate.push_back(Box::new(SequenceElement::new(mv)));
+        }
+        let count = intermediate.len();
+        patch_stats::record(|stats| stats.elements_inserted += count as u64);
+        let right = self.underlying.split_off(index);
+        self.underlying.append(intermediate);
+        self.underlying.append(right);
+
+        for changed_index in changed_indices.iter_mut() {
+            if *changed_index >= index as u64 {
+                *changed_index += count as u64;
+            }
+        }
+
+        for i in index..(index + count) {
+            changed_indices.push(i as u64);
+        }
+    }
+
     pub(super) fn remove(&mut self, index: usize) -> T {
         match self.underlying.remove(index).value {
             SequenceValue::Original(t) => t,
@@ -434,6 +417,7 @@ where
                 Box::new(SequenceElement {
                     opid: elem_id,
                     value: SequenceValue::Original(value),
+                    winner: WinnerCache::default(),
                 }),
             )
             .value
@@ -446,9 +430,10 @@ where
     }
 
     pub(super) fn get_mut(&mut self, index: usize) -> Option<(&mut OpId, &mut T)> {
-        self.underlying
-            .get_mut(index)
-            .map(|e| (&mut e.opid, e.value.get_mut()))
+        self.underlying.get_mut(index).map(|e| {
+            e.winner.invalidate();
+            (&mut e.opid, e.value.get_mut())
+        })
     }
 
     pub(super) fn insert(&mut self, index: usize, value: T) {
@@ -456,14 +441,203 @@ where
             .insert(index, Box::new(SequenceElement::original(value)))
     }
 
-    pub(crate) fn iter(&self) -> impl std::iter::Iterator<Item = &T> {
-        // Making this unwrap safe is the entire point of this data structure
-        self.underlying.iter().map(|i| i.value.get())
+    /// Move the element at `from` to `to`, giving it the element ID `elem_id`. Returns the
+    /// element's previous ID.
+    pub(super) fn move_element(&mut self, from: usize, to: usize, elem_id: OpId) -> OpId {
+        let mut element = self.underlying.remove(from);
+        let old_elem_id = std::mem::replace(&mut element.opid, elem_id);
+        self.underlying.insert(to, element);
+        old_elem_id
+    }
+
+    /// The realised winning value of each element in order. These are cached, so realising a
+    /// sequence again after a patch only realises the elements the patch touched.
+    pub(crate) fn default_values(&self) -> impl Iterator<Item = Value> + '_ {
+        self.underlying.iter().map(|e| e.default_value())
+    }
+
+    /// The elements in order, along with their element IDs
+    pub(crate) fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            inner: self.underlying.iter(),
+        }
+    }
+}
+
+/// Check that `edits` can be applied to a sequence of `size` elements of type `T`
+pub(super) fn check_edits<T: DiffableValue>(
+    mut size: usize,
+    object_id: &amp::ObjectId,
+    edits: &[amp::DiffEdit],
+) -> Result<(), InvalidPatch> {
+    for edit in edits {
+        match edit {
+            amp::DiffEdit::Remove { index, count } => {
+                let index = *index as usize;
+                let count = *count as usize;
+                if index >= size {
+                    return Err(InvalidPatch::InvalidIndex {
+                        object_id: object_id.clone(),
+                        index,
+                    });
+                }
+                if index + count > size {
+                    return Err(InvalidPatch::InvalidIndex {
+                        object_id: object_id.clone(),
+                        index: size,
+                    });
+                }
+                size -= count;
+            }
+            // marks don't change the elements, `StateTreeText` keeps track of them
+            amp::DiffEdit::Marks(_) => {}
+            amp::DiffEdit::SingleElementInsert {
+                index,
+                elem_id: _,
+                op_id,
+                value,
+            } => {
+                T::check_construct(op_id, value, object_id)?;
+                if *index as usize > size {
+                    return Err(InvalidPatch::InvalidIndex {
+                        object_id: object_id.clone(),
+                        index: *index as usize,
+                    });
+                }
+                size += 1;
+            }
+            amp::DiffEdit::MultiElementInsert(amp::MultiElementInsert {
+                elem_id,
+                values,
+                index,
+            }) => {
+                let index = *index as usize;
+                if index > size {
+                    return Err(InvalidPatch::InvalidIndex {
+                        index,
+                        object_id: object_id.clone(),
+                    });
+                }
+                let elem_id = elem_id
+                    .as_opid()
+                    .ok_or(InvalidPatch::DiffEditWithHeadElemId)?;
+                for (i, value) in values.iter().enumerate() {
+                    let opid = elem_id.increment_by(i as u64);
+                    T::check_construct(&opid, &amp::Diff::Value(value.clone()), object_id)?;
+                }
+                size += values.len();
+            }
+            amp::DiffEdit::TextInsert(insert) => {
+                let index = insert.index as usize;
+                if index > size {
+                    return Err(InvalidPatch::InvalidIndex {
+                        index,
+                        object_id: object_id.clone(),
+                    });
+                }
+                let elem_id = insert
+                    .elem_id
+                    .as_opid()
+                    .ok_or(InvalidPatch::DiffEditWithHeadElemId)?;
+                let characters = insert
+                    .characters()
+                    .ok_or(InvalidPatch::MalformedTextInsert)?;
+                for (i, character) in characters.iter().enumerate() {
+                    T::check_construct(
+                        &elem_id.increment_by(i as u64),
+                        &amp::Diff::Value(amp::ScalarValue::Str((*character).into())),
+                        object_id,
+                    )?;
+                }
+                size += characters.len();
+            }
+            amp::DiffEdit::Update {
+                index,
+                value: _,
+                op_id: _,
+            } => {
+                // TODO: handle updates after things like inserts shifting them
+                if *index as usize >= size {
+                    return Err(InvalidPatch::InvalidIndex {
+                        index: *index as usize,
+                        object_id: object_id.clone(),
+                    });
+                }
+
+                // if let Some((_id, elem)) = self.underlying.get(*index as usize) {
+                //     elem.check_diff(op_id, value)?;
+                // } else {
+                // }
+            }
+        };
+    }
+
+    Ok(())
+}
+
+/// An iterator over the element IDs and values of a [`DiffableSequence`]
+pub(crate) struct Iter<'a, T>
+where
+    T: DiffableValue,
+    T: Clone,
+    T: PartialEq,
+{
+    inner: im_rc::vector::Iter<'a, Box<SequenceElement<T>>>,
+}
+
+impl<'a, T> Iterator for Iter<'a, T>
+where
+    T: Clone + 'a,
+    T: DiffableValue,
+    T: PartialEq,
+{
+    type Item = (&'a OpId, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Making this `get` safe is the entire point of this data structure
+        self.inner.next().map(|e| (&e.opid, e.value.get()))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<'a, T> DoubleEndedIterator for Iter<'a, T>
+where
+    T: Clone + 'a,
+    T: DiffableValue,
+    T: PartialEq,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back().map(|e| (&e.opid, e.value.get()))
+    }
+}
+
+impl<'a, T> ExactSizeIterator for Iter<'a, T>
+where
+    T: Clone + 'a,
+    T: DiffableValue,
+    T: PartialEq,
+{
+}
+
+impl<'a, T> IntoIterator for &'a DiffableSequence<T>
+where
+    T: Clone + 'a,
+    T: DiffableValue,
+    T: PartialEq,
+{
+    type Item = (&'a OpId, &'a T);
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
     }
 }
 
 #[derive(Clone, Debug, PartialEq)]
-enum SequenceValue<T>
+pub(super) enum SequenceValue<T>
 where
     T: DiffableValue,
 {
@@ -477,12 +651,12 @@ where
     T: DiffableValue,
     T: Clone,
 {
-    fn finish(&mut self) {
+    pub(super) fn finish(&mut self) {
         match self {
             SequenceValue::Original(_) => { // do nothing, this is the finished state
             }
             SequenceValue::New(v) => *self = SequenceValue::Original(v.take()),
-            SequenceValue::Updated { updates, .. } => {
+            SequenceValue::Updated { original, updates } => {
                 let initial_update = updates.remove(0);
                 let t =
                     std::mem::take(updates)
@@ -491,26 +665,27 @@ where
                             acc.add_values_from(elem);
                             acc
                         });
+                super::record_resolved(original.value_count(), t.value_count());
                 *self = SequenceValue::Original(t)
             }
         }
     }
 
-    fn get(&self) -> &T {
+    pub(super) fn get(&self) -> &T {
         match self {
             SequenceValue::Original(v) => v,
             _ => unreachable!(),
         }
     }
 
-    fn get_mut(&mut self) -> &mut T {
+    pub(super) fn get_mut(&mut self) -> &mut T {
         match self {
             SequenceValue::Original(v) => v,
             _ => unreachable!(),
         }
     }
 
-    fn apply_diff(&mut self, opid: amp::OpId, diff: amp::Diff) {
+    pub(super) fn apply_diff(&mut self, opid: amp::OpId, diff: amp::Diff) {
         match self {
             SequenceValue::Original(v) => {
                 let updated = if let Some(mut existing) = v.only_for_opid(opid.clone()) {
@@ -531,6 +706,7 @@ where
                 } else {
                     T::construct(opid, diff)
                 };
+                patch_stats::record(|stats| stats.nodes_cloned += 1);
                 *self = SequenceValue::Updated {
                     original: v.clone(),
                     updates: vec![v.take(), updated],
@@ -640,4 +816,78 @@ mod tests {
             ],
         )
     }
+
+    #[test]
+    fn iterate() {
+        let actor = ActorId::random();
+        let mut ds = DiffableSequence::<MultiValue>::new();
+        ds.apply_diff(
+            &ObjectId::Root,
+            (0..4)
+                .map(|i| DiffEdit::SingleElementInsert {
+                    index: i,
+                    elem_id: actor.op_id_at(i + 1).into(),
+                    op_id: actor.op_id_at(i + 1),
+                    value: Diff::Value(ScalarValue::Int(i as i64)),
+                })
+                .collect(),
+        );
+        let ids = |iter: Iter<MultiValue>| iter.map(|(id, _)| id.0).collect::<Vec<_>>();
+
+        assert_eq!(ids(ds.iter()), vec![1, 2, 3, 4]);
+        assert_eq!(ds.iter().rev().next().unwrap().0, &actor.op_id_at(4));
+        assert_eq!((&ds).into_iter().count(), 4);
+    }
+
+    #[test]
+    fn cached_winners_follow_updates() {
+        let actor = ActorId::random();
+        let other = ActorId::random();
+        let int = |i: i64| crate::Value::Primitive(crate::Primitive::Int(i));
+        let mut ds = DiffableSequence::<MultiValue>::new();
+        ds.apply_diff(
+            &ObjectId::Root,
+            (0..3)
+                .map(|i| DiffEdit::SingleElementInsert {
+                    index: i,
+                    elem_id: actor.op_id_at(i + 1).into(),
+                    op_id: actor.op_id_at(i + 1),
+                    value: Diff::Value(ScalarValue::Int(i as i64)),
+                })
+                .collect(),
+        );
+        assert_eq!(
+            ds.default_values().collect::<Vec<_>>(),
+            vec![int(0), int(1), int(2)]
+        );
+
+        // concurrent sets of the middle element, the winner is the op with the highest ID
+        ds.apply_diff(
+            &ObjectId::Root,
+            vec![
+                DiffEdit::Update {
+                    index: 1,
+                    op_id: other.op_id_at(5),
+                    value: Diff::Value(ScalarValue::Int(10)),
+                },
+                DiffEdit::Update {
+                    index: 1,
+                    op_id: actor.op_id_at(4),
+                    value: Diff::Value(ScalarValue::Int(20)),
+                },
+            ],
+        );
+        assert_eq!(
+            ds.default_values().collect::<Vec<_>>(),
+            vec![int(0), int(10), int(2)]
+        );
+        assert_eq!(ds.get(1).unwrap().1.realise_values().len(), 2);
+
+        let (id, value) = ds.get_mut(2).unwrap();
+        *value = MultiValue::new_from_diff(id.clone(), Diff::Value(ScalarValue::Int(30)));
+        assert_eq!(
+            ds.default_values().collect::<Vec<_>>(),
+            vec![int(0), int(10), int(30)]
+        );
+    }
 }
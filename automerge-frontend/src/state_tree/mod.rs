@@ -1,4 +1,7 @@
-use std::{collections::HashMap, convert::TryInto};
+use std::{
+    collections::{BTreeMap, HashMap},
+    convert::TryInto,
+};
 
 use amp::{ElementId, SortedVec};
 use automerge_protocol as amp;
@@ -7,17 +10,19 @@ use diffable_sequence::DiffableSequence;
 use multivalue::NewValueRequest;
 use smol_str::SmolStr;
 
-use crate::{error, path::PathElement, value_ref::RootRef, Path, Primitive, Value};
+use crate::{error, patch_stats, path::PathElement, value_ref::RootRef, Path, Primitive, Value};
 
 mod diffable_sequence;
 mod multivalue;
 mod optimistic;
 mod resolved_path;
+mod text_sequence;
 
 pub use multivalue::{MultiGrapheme, MultiValue};
 pub(crate) use optimistic::{LocalOperationForRollback, OptimisticStateTree};
 pub(crate) use resolved_path::SetOrInsertPayload;
 pub use resolved_path::{ResolvedPath, ResolvedPathMut};
+pub(crate) use text_sequence::TextSequence;
 
 #[derive(Debug, PartialEq, Clone, Default)]
 pub struct CheckedRootDiff(RootDiff);
@@ -54,23 +59,12 @@ impl StateTree {
 
     pub fn check_diff(&self, diff: amp::RootDiff) -> Result<CheckedRootDiff, error::InvalidPatch> {
         for (prop, prop_diff) in &diff.props {
-            let mut diff_iter = prop_diff.iter();
-            match diff_iter.next() {
+            match self.root_props.get(prop) {
+                Some(n) => n.check_diff_iter(&mut prop_diff.iter())?,
                 None => {
-                    // all ok here
-                }
-                Some((opid, diff)) => {
-                    match self.root_props.get(prop) {
-                        Some(n) => n.check_diff(opid, diff)?,
-                        None => {
-                            MultiValue::check_new_from_diff(opid, diff)?;
-                        }
-                    };
-                    // TODO: somehow get this working
-                    // self.root_props
-                    //     .get(prop)
-                    //     .unwrap()
-                    //     .check_diff_iter(&mut diff_iter)?;
+                    for (opid, diff) in prop_diff {
+                        MultiValue::check_new_from_diff(opid, diff)?;
+                    }
                 }
             }
         }
@@ -79,10 +73,16 @@ impl StateTree {
 
     pub fn apply_diff(&mut self, diff: CheckedRootDiff) {
         for (prop, prop_diff) in diff.0.props {
+            let opids: Vec<amp::OpId> = prop_diff.keys().cloned().collect();
             let mut diff_iter = prop_diff.into_iter();
-            match diff_iter.next() {
+            let before = self
+                .root_props
+                .get(&prop)
+                .map_or(0, MultiValue::value_count);
+            let after = match diff_iter.next() {
                 None => {
                     self.root_props.remove(&prop);
+                    0
                 }
                 Some((opid, diff)) => {
                     match self.root_props.get_mut(&prop) {
@@ -92,12 +92,13 @@ impl StateTree {
                             self.root_props.insert(prop.clone(), value);
                         }
                     };
-                    self.root_props
-                        .get_mut(&prop)
-                        .unwrap()
-                        .apply_diff_iter(&mut diff_iter);
+                    let values = self.root_props.get_mut(&prop).unwrap();
+                    values.apply_diff_iter(&mut diff_iter);
+                    values.retain(&opids);
+                    values.value_count()
                 }
-            }
+            };
+            record_resolved(before, after);
         }
     }
 
@@ -309,13 +310,10 @@ impl StateTreeComposite {
             ),
             Self::List(StateTreeList {
                 elements: elems, ..
-            }) => Value::List(elems.iter().map(|e| e.default_value()).collect()),
-            Self::Text(StateTreeText { graphemes, .. }) => Value::Text(
-                graphemes
-                    .iter()
-                    .map(|c| c.default_grapheme().clone())
-                    .collect(),
-            ),
+            }) => Value::List(elems.default_values().collect()),
+            Self::Text(StateTreeText { graphemes, .. }) => {
+                Value::Text(graphemes.graphemes().cloned().collect())
+            }
         }
     }
 
@@ -353,15 +351,27 @@ impl StateTreeValue {
                 | amp::ScalarValue::Null => Ok(()),
                 amp::ScalarValue::Cursor(..) => Err(error::InvalidPatch::ValueDiffContainedCursor),
             },
-            amp::Diff::Map(_)
-            | amp::Diff::Table(_)
-            | amp::Diff::List(_)
-            | amp::Diff::Text(_)
-            | amp::Diff::Cursor(_) => Ok(()),
+            // a new object starts out empty, so its contents are checked exactly as a diff
+            // against an empty object would be
+            amp::Diff::Map(amp::MapDiff { props, .. })
+            | amp::Diff::Table(amp::TableDiff { props, .. }) => {
+                for diff in props.values().flat_map(BTreeMap::values) {
+                    Self::check_new_from_diff(diff)?;
+                }
+                Ok(())
+            }
+            amp::Diff::List(amp::ListDiff { object_id, edits }) => {
+                diffable_sequence::check_edits::<MultiValue>(0, object_id, edits)
+            }
+            amp::Diff::Text(amp::TextDiff { object_id, edits }) => {
+                TextSequence::new().check_diff(object_id, edits)
+            }
+            amp::Diff::Cursor(_) => Ok(()),
         }
     }
 
     fn new_from_diff(diff: amp::Diff) -> StateTreeValue {
+        patch_stats::record(|stats| stats.nodes_created += 1);
         match diff {
             amp::Diff::Value(v) => {
                 let value = match v {
@@ -405,10 +415,7 @@ impl StateTreeValue {
                 StateTreeValue::Composite(StateTreeComposite::List(list))
             }
             amp::Diff::Text(amp::TextDiff { object_id, edits }) => {
-                let mut text = StateTreeText {
-                    object_id,
-                    graphemes: DiffableSequence::new(),
-                };
+                let mut text = StateTreeText::new(object_id, TextSequence::new());
                 text.apply_diff(edits);
                 StateTreeValue::Composite(StateTreeComposite::Text(text))
             }
@@ -434,36 +441,30 @@ pub(crate) struct StateTreeMap {
 impl StateTreeMap {
     fn check_diff(
         &self,
-        prop_diffs: &HashMap<SmolStr, HashMap<amp::OpId, amp::Diff>>,
+        prop_diffs: &BTreeMap<SmolStr, BTreeMap<amp::OpId, amp::Diff>>,
     ) -> Result<(), error::InvalidPatch> {
         for (prop, prop_diff) in prop_diffs {
-            let mut diff_iter = prop_diff.iter();
-            match diff_iter.next() {
-                None => {}
-                Some((opid, diff)) => {
-                    match self.props.get(prop) {
-                        Some(n) => n.check_diff(opid, diff)?,
-                        None => {
-                            MultiValue::check_new_from_diff(opid, diff)?;
-                        }
-                    };
-                    // TODO: get this working
-                    // self.props
-                    //     .get(prop)
-                    //     .unwrap()
-                    //     .check_diff_iter(&mut diff_iter)?;
+            match self.props.get(prop) {
+                Some(n) => n.check_diff_iter(&mut prop_diff.iter())?,
+                None => {
+                    for (opid, diff) in prop_diff {
+                        MultiValue::check_new_from_diff(opid, diff)?;
+                    }
                 }
             }
         }
         Ok(())
     }
 
-    fn apply_diff(&mut self, prop_diffs: HashMap<SmolStr, HashMap<amp::OpId, amp::Diff>>) {
+    fn apply_diff(&mut self, prop_diffs: BTreeMap<SmolStr, BTreeMap<amp::OpId, amp::Diff>>) {
         for (prop, prop_diff) in prop_diffs {
+            let opids: Vec<amp::OpId> = prop_diff.keys().cloned().collect();
             let mut diff_iter = prop_diff.into_iter();
-            match diff_iter.next() {
+            let before = self.props.get(&prop).map_or(0, MultiValue::value_count);
+            let after = match diff_iter.next() {
                 None => {
                     self.props.remove(&prop);
+                    0
                 }
                 Some((opid, diff)) => {
                     match self.props.get_mut(&prop) {
@@ -473,19 +474,20 @@ impl StateTreeMap {
                             self.props.insert(prop.clone(), value);
                         }
                     };
-                    self.props
-                        .get_mut(&prop)
-                        .unwrap()
-                        .apply_diff_iter(&mut diff_iter);
+                    let values = self.props.get_mut(&prop).unwrap();
+                    values.apply_diff_iter(&mut diff_iter);
+                    values.retain(&opids);
+                    values.value_count()
                 }
-            }
+            };
+            record_resolved(before, after);
         }
     }
 
     pub fn pred_for_key(&self, key: &str) -> SortedVec<amp::OpId> {
         self.props
             .get(key)
-            .map(|v| vec![v.default_opid()].into())
+            .map(MultiValue::pred)
             .unwrap_or_else(SortedVec::new)
     }
 
@@ -524,36 +526,30 @@ pub(crate) struct StateTreeTable {
 impl StateTreeTable {
     fn check_diff(
         &self,
-        prop_diffs: &HashMap<SmolStr, HashMap<amp::OpId, amp::Diff>>,
+        prop_diffs: &BTreeMap<SmolStr, BTreeMap<amp::OpId, amp::Diff>>,
     ) -> Result<(), error::InvalidPatch> {
         for (prop, prop_diff) in prop_diffs {
-            let mut diff_iter = prop_diff.iter();
-            match diff_iter.next() {
-                None => {}
-                Some((opid, diff)) => {
-                    match self.props.get(prop) {
-                        Some(n) => n.check_diff(opid, diff)?,
-                        None => {
-                            MultiValue::check_new_from_diff(opid, diff)?;
-                        }
-                    };
-                    // TODO: get this working
-                    // self.props
-                    //     .get(prop)
-                    //     .unwrap()
-                    //     .check_diff_iter(&mut diff_iter)?;
+            match self.props.get(prop) {
+                Some(n) => n.check_diff_iter(&mut prop_diff.iter())?,
+                None => {
+                    for (opid, diff) in prop_diff {
+                        MultiValue::check_new_from_diff(opid, diff)?;
+                    }
                 }
             }
         }
         Ok(())
     }
 
-    fn apply_diff(&mut self, prop_diffs: HashMap<SmolStr, HashMap<amp::OpId, amp::Diff>>) {
+    fn apply_diff(&mut self, prop_diffs: BTreeMap<SmolStr, BTreeMap<amp::OpId, amp::Diff>>) {
         for (prop, prop_diff) in prop_diffs {
+            let opids: Vec<amp::OpId> = prop_diff.keys().cloned().collect();
             let mut diff_iter = prop_diff.into_iter();
-            match diff_iter.next() {
+            let before = self.props.get(&prop).map_or(0, MultiValue::value_count);
+            let after = match diff_iter.next() {
                 None => {
                     self.props.remove(&prop);
+                    0
                 }
                 Some((opid, diff)) => {
                     match self.props.get_mut(&prop) {
@@ -563,19 +559,20 @@ impl StateTreeTable {
                             self.props.insert(prop.clone(), value);
                         }
                     };
-                    self.props
-                        .get_mut(&prop)
-                        .unwrap()
-                        .apply_diff_iter(&mut diff_iter);
+                    let values = self.props.get_mut(&prop).unwrap();
+                    values.apply_diff_iter(&mut diff_iter);
+                    values.retain(&opids);
+                    values.value_count()
                 }
-            }
+            };
+            record_resolved(before, after);
         }
     }
 
     pub fn pred_for_key(&self, key: &str) -> SortedVec<amp::OpId> {
         self.props
             .get(key)
-            .map(|v| vec![v.default_opid()].into())
+            .map(MultiValue::pred)
             .unwrap_or_else(SortedVec::new)
     }
 
@@ -608,10 +605,21 @@ impl StateTreeTable {
 #[derive(Debug, Clone, PartialEq)]
 pub(crate) struct StateTreeText {
     object_id: amp::ObjectId,
-    pub(crate) graphemes: DiffableSequence<MultiGrapheme>,
+    pub(crate) graphemes: TextSequence,
+    /// The formatting spans of the text, as last sent by the backend and adjusted for any edits
+    /// made since
+    marks: Vec<amp::MarkSpan>,
 }
 
 impl StateTreeText {
+    pub(crate) fn new(object_id: amp::ObjectId, graphemes: TextSequence) -> StateTreeText {
+        StateTreeText {
+            object_id,
+            graphemes,
+            marks: Vec::new(),
+        }
+    }
+
     fn remove(&mut self, index: usize) -> Result<MultiGrapheme, error::MissingIndexError> {
         if index >= self.graphemes.len() {
             Err(error::MissingIndexError {
@@ -620,10 +628,85 @@ impl StateTreeText {
             })
         } else {
             let old = self.graphemes.remove(index);
+            self.marks_removed(index as u64, 1);
             Ok(old)
         }
     }
 
+    /// The spans of the text which have a mark. Spans whose characters have all been removed are
+    /// kept, so that undoing the removal brings them back, but they are not returned here.
+    pub(crate) fn marks(&self) -> impl Iterator<Item = &amp::MarkSpan> {
+        self.marks.iter().filter(|span| span.start < span.end)
+    }
+
+    /// Set the mark `name` to `value` for the characters from `start` up to `end`, returning the
+    /// marks as they were before
+    pub(crate) fn set_mark(
+        &mut self,
+        start: u64,
+        end: u64,
+        name: &SmolStr,
+        value: &amp::ScalarValue,
+    ) -> Vec<amp::MarkSpan> {
+        let old = self.marks.clone();
+        let mut marks = Vec::with_capacity(self.marks.len() + 2);
+        for span in self.marks.drain(..) {
+            if &span.name != name || span.end <= start || span.start >= end {
+                marks.push(span);
+                continue;
+            }
+            if span.start < start {
+                marks.push(amp::MarkSpan {
+                    end: start,
+                    ..span.clone()
+                });
+            }
+            if span.end > end {
+                marks.push(amp::MarkSpan { start: end, ..span });
+            }
+        }
+        if *value != amp::ScalarValue::Null {
+            marks.push(amp::MarkSpan {
+                start,
+                end,
+                name: name.clone(),
+                value: value.clone(),
+            });
+        }
+        marks.sort_by(|a, b| (a.start, &a.name).cmp(&(b.start, &b.name)));
+        self.marks = marks;
+        old
+    }
+
+    pub(crate) fn restore_marks(&mut self, marks: Vec<amp::MarkSpan>) {
+        self.marks = marks;
+    }
+
+    /// Adjust the marks for `count` characters inserted at `index`. Characters inserted at the
+    /// end of a span become part of it but characters inserted at the start do not, this is where
+    /// the backend puts new characters relative to the boundaries of a span.
+    fn marks_inserted(&mut self, index: u64, count: u64) {
+        for span in &mut self.marks {
+            if index <= span.start {
+                span.start += count;
+                span.end += count;
+            } else if index <= span.end {
+                span.end += count;
+            }
+        }
+    }
+
+    fn marks_removed(&mut self, index: u64, count: u64) {
+        for span in &mut self.marks {
+            let removed_before = count.min(span.start.saturating_sub(index));
+            let removed_within = (index + count)
+                .min(span.end)
+                .saturating_sub(index.max(span.start));
+            span.start -= removed_before;
+            span.end -= removed_before + removed_within;
+        }
+    }
+
     fn set(
         &mut self,
         index: usize,
@@ -643,10 +726,9 @@ impl StateTreeText {
     pub(crate) fn elem_at(
         &self,
         index: usize,
-    ) -> Result<(&amp::OpId, &SmolStr), error::MissingIndexError> {
+    ) -> Result<(amp::OpId, &SmolStr), error::MissingIndexError> {
         self.graphemes
             .get(index)
-            .map(|mc| (mc.0, mc.1.default_grapheme()))
             .ok_or_else(|| error::MissingIndexError {
                 missing_index: index,
                 size_of_collection: self.graphemes.len(),
@@ -671,9 +753,9 @@ impl StateTreeText {
                 size_of_collection: self.graphemes.len(),
             })
         } else {
-            for (i, grapheme) in values.into_iter().enumerate() {
-                self.graphemes.insert(index + i, grapheme);
-            }
+            let len = self.graphemes.len();
+            self.graphemes.insert_many(index, values);
+            self.marks_inserted(index as u64, (self.graphemes.len() - len) as u64);
             Ok(())
         }
     }
@@ -684,20 +766,32 @@ impl StateTreeText {
     }
 
     fn apply_diff(&mut self, edits: Vec<amp::DiffEdit>) {
-        self.graphemes.apply_diff(&self.object_id, edits)
+        for edit in &edits {
+            match edit {
+                amp::DiffEdit::SingleElementInsert { index, .. } => self.marks_inserted(*index, 1),
+                amp::DiffEdit::MultiElementInsert(insert) => {
+                    self.marks_inserted(insert.index, insert.values.len() as u64)
+                }
+                amp::DiffEdit::TextInsert(insert) => {
+                    let count = insert.characters().map_or(0, |c| c.len());
+                    self.marks_inserted(insert.index, count as u64)
+                }
+                amp::DiffEdit::Remove { index, count } => self.marks_removed(*index, *count),
+                amp::DiffEdit::Marks(diff) => self.marks = diff.marks.clone(),
+                amp::DiffEdit::Update { .. } => {}
+            }
+        }
+        self.graphemes.apply_diff(edits)
     }
 
     pub fn pred_for_index(&self, index: u32) -> SortedVec<amp::OpId> {
-        self.graphemes
-            .get(index.try_into().unwrap())
-            .map(|v| vec![v.1.default_opid().clone()].into())
-            .unwrap_or_else(SortedVec::new)
+        self.graphemes.pred_for_index(index.try_into().unwrap())
     }
 
     pub(crate) fn resolve_path(&self, mut path: Vec<PathElement>) -> Option<ResolvedPath> {
         if let Some(PathElement::Index(i)) = path.pop() {
             if path.is_empty() {
-                self.graphemes.get(i as usize)?.1.resolve_path(path)
+                self.graphemes.grapheme(i as usize)?.resolve_path(path)
             } else {
                 None
             }
@@ -712,7 +806,7 @@ impl StateTreeText {
     ) -> Option<ResolvedPathMut> {
         if let Some(PathElement::Index(i)) = path.pop() {
             if path.is_empty() {
-                self.graphemes.get_mut(i as usize)?.1.resolve_path_mut(path)
+                self.graphemes.grapheme(i as usize)?.resolve_path_mut(path)
             } else {
                 None
             }
@@ -778,6 +872,23 @@ impl StateTreeList {
         }
     }
 
+    fn move_element(
+        &mut self,
+        from: usize,
+        to: usize,
+        elem_id: amp::OpId,
+    ) -> Result<amp::OpId, error::MissingIndexError> {
+        let max_index = std::cmp::max(from, to);
+        if max_index >= self.elements.len() {
+            Err(error::MissingIndexError {
+                missing_index: max_index,
+                size_of_collection: self.elements.len(),
+            })
+        } else {
+            Ok(self.elements.move_element(from, to, elem_id))
+        }
+    }
+
     fn check_diff(&self, edits: &[amp::DiffEdit]) -> Result<(), error::InvalidPatch> {
         self.elements.check_diff(&self.object_id, edits)
     }
@@ -789,7 +900,7 @@ impl StateTreeList {
     pub fn pred_for_index(&self, index: u32) -> SortedVec<amp::OpId> {
         self.elements
             .get(index.try_into().unwrap())
-            .map(|v| vec![v.1.default_opid()].into())
+            .map(|v| v.1.pred())
             .unwrap_or_else(SortedVec::new)
     }
 
@@ -843,8 +954,16 @@ impl StateTreeList {
     }
 }
 
-pub fn random_op_id() -> amp::OpId {
-    amp::OpId::new(1, &amp::ActorId::random())
+/// Stands in for the op which set the root object, which doesn't exist
+/// Count the conflicts a diff for one key or element resolved, going from `before` values to
+/// `after`. Overwriting a single value or creating a conflict resolves nothing.
+fn record_resolved(before: usize, after: usize) {
+    let resolved = before.saturating_sub(after.max(1));
+    patch_stats::record(|stats| stats.conflicts_resolved += resolved as u64);
+}
+
+pub fn root_op_id() -> amp::OpId {
+    amp::OpId::new(0, &amp::ActorId::from(&[][..]))
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -7,10 +7,10 @@ use unicode_segmentation::UnicodeSegmentation;
 
 use super::{
     CursorState, Cursors, DiffableSequence, ResolvedPath, ResolvedPathMut, StateTreeComposite,
-    StateTreeList, StateTreeMap, StateTreeTable, StateTreeText, StateTreeValue,
+    StateTreeList, StateTreeMap, StateTreeTable, StateTreeText, StateTreeValue, TextSequence,
 };
 use crate::{
-    error,
+    error, patch_stats,
     path::PathElement,
     value::{Primitive, Value},
 };
@@ -90,14 +90,6 @@ impl MultiValue {
         .create(value)
     }
 
-    pub(super) fn check_diff(
-        &self,
-        opid: &amp::OpId,
-        diff: &amp::Diff,
-    ) -> Result<(), error::InvalidPatch> {
-        self.check_diff_iter(&mut std::iter::once((opid, diff)))
-    }
-
     pub(super) fn check_diff_iter<'a, 'b, I>(&self, diff: &mut I) -> Result<(), error::InvalidPatch>
     where
         I: Iterator<Item = (&'a amp::OpId, &'b amp::Diff)>,
@@ -145,6 +137,18 @@ impl MultiValue {
         }
     }
 
+    /// Drop the values whose op IDs aren't in `opids`. A diff for a map key lists every value
+    /// the key has afterwards, so anything it doesn't mention has been overwritten.
+    pub(super) fn retain(&mut self, opids: &[amp::OpId]) {
+        self.conflicts.retain(|opid, _| opids.contains(opid));
+        if !opids.contains(&self.winning_value.0) {
+            if let Some(next) = self.conflicts.keys().max().cloned() {
+                let value = self.conflicts.remove(&next).unwrap();
+                self.winning_value = (next, value);
+            }
+        }
+    }
+
     fn get(&self, opid: &amp::OpId) -> Option<&StateTreeValue> {
         if opid == &self.winning_value.0 {
             Some(&self.winning_value.1)
@@ -188,6 +192,16 @@ impl MultiValue {
         self.winning_value.0.clone()
     }
 
+    /// The number of values, i.e. one more than the number of conflicts
+    pub(super) fn value_count(&self) -> usize {
+        self.conflicts.len() + 1
+    }
+
+    /// The pred of an op which overwrites this value, which includes every conflict
+    pub(super) fn pred(&self) -> SortedVec<amp::OpId> {
+        self.iter().map(|(opid, _)| opid.clone()).collect()
+    }
+
     fn iter(&self) -> impl std::iter::Iterator<Item = (&amp::OpId, &StateTreeValue)> {
         std::iter::once((&(self.winning_value).0, &(self.winning_value.1)))
             .chain(self.conflicts.iter())
@@ -274,7 +288,7 @@ impl MultiValue {
     }
 
     pub(super) fn only_for_opid(&self, opid: amp::OpId) -> Option<MultiValue> {
-        if opid == self.winning_value.0 {
+        let only = if opid == self.winning_value.0 {
             Some(MultiValue {
                 winning_value: self.winning_value.clone(),
                 conflicts: HashMap::new(),
@@ -284,11 +298,16 @@ impl MultiValue {
                 winning_value: (opid.clone(), value.clone()),
                 conflicts: HashMap::new(),
             })
+        };
+        if only.is_some() {
+            patch_stats::record(|stats| stats.nodes_cloned += 1);
         }
+        only
     }
 
     pub(super) fn add_values_from(&mut self, other: MultiValue) {
         for (opid, value) in other.iter() {
+            patch_stats::record(|stats| stats.nodes_cloned += 1);
             match opid.cmp(&self.winning_value.0) {
                 Ordering::Greater => {
                     let mut temp = (opid.clone(), value.clone());
@@ -375,50 +394,13 @@ impl MultiGrapheme {
             amp::Diff::Value(amp::ScalarValue::Str(s)) => s,
             _ => unreachable!("insert non text in text object"),
         };
+        patch_stats::record(|stats| stats.nodes_created += 1);
         MultiGrapheme {
             winning_value: (opid, winning_value),
             conflicts: HashMap::new(),
         }
     }
 
-    pub(super) fn check_diff(
-        &self,
-        opid: &amp::OpId,
-        diff: &amp::Diff,
-        parent_object_id: &amp::ObjectId,
-    ) -> Result<(), error::InvalidPatch> {
-        self.check_diff_iter(&mut std::iter::once((opid, diff)), parent_object_id)
-    }
-
-    pub(super) fn check_diff_iter<'a, 'b, I>(
-        &self,
-        diff: &mut I,
-        parent_object_id: &amp::ObjectId,
-    ) -> Result<(), error::InvalidPatch>
-    where
-        I: Iterator<Item = (&'a amp::OpId, &'b amp::Diff)>,
-    {
-        for (_opid, subdiff) in diff {
-            match subdiff {
-                amp::Diff::Value(amp::ScalarValue::Str(s)) => {
-                    if s.graphemes(true).count() != 1 {
-                        return Err(error::InvalidPatch::InsertNonTextInTextObject {
-                            object_id: parent_object_id.clone(),
-                            diff: subdiff.clone(),
-                        });
-                    }
-                }
-                _ => {
-                    return Err(error::InvalidPatch::InsertNonTextInTextObject {
-                        object_id: parent_object_id.clone(),
-                        diff: subdiff.clone(),
-                    });
-                }
-            }
-        }
-        Ok(())
-    }
-
     pub(super) fn apply_diff(&mut self, opid: amp::OpId, diff: amp::Diff) {
         self.apply_diff_iter(&mut std::iter::once((opid, diff)))
     }
@@ -462,6 +444,11 @@ impl MultiGrapheme {
         &self.winning_value.0
     }
 
+    /// The pred of an op which overwrites this grapheme, which includes every conflict
+    pub(super) fn pred(&self) -> SortedVec<amp::OpId> {
+        self.iter().map(|(opid, _)| opid.clone()).collect()
+    }
+
     fn iter(&self) -> impl std::iter::Iterator<Item = (&amp::OpId, &SmolStr)> {
         std::iter::once((&(self.winning_value).0, &(self.winning_value.1)))
             .chain(self.conflicts.iter())
@@ -474,7 +461,7 @@ impl MultiGrapheme {
     }
 
     pub(super) fn only_for_opid(&self, opid: amp::OpId) -> Option<MultiGrapheme> {
-        if opid == self.winning_value.0 {
+        let only = if opid == self.winning_value.0 {
             Some(MultiGrapheme {
                 winning_value: self.winning_value.clone(),
                 conflicts: HashMap::new(),
@@ -484,11 +471,16 @@ impl MultiGrapheme {
                 winning_value: (opid, value.clone()),
                 conflicts: HashMap::new(),
             })
+        };
+        if only.is_some() {
+            patch_stats::record(|stats| stats.nodes_cloned += 1);
         }
+        only
     }
 
     pub(super) fn add_values_from(&mut self, other: MultiGrapheme) {
         for (opid, value) in other.iter() {
+            patch_stats::record(|stats| stats.nodes_cloned += 1);
             match opid.cmp(&self.winning_value.0) {
                 Ordering::Greater => {
                     let mut temp = (opid.clone(), value.to_owned());
@@ -503,7 +495,7 @@ impl MultiGrapheme {
         }
     }
 
-    pub(crate) fn resolve_path(&self, path: Vec<PathElement>) -> Option<ResolvedPath> {
+    pub(crate) fn resolve_path<'a>(self, path: Vec<PathElement>) -> Option<ResolvedPath<'a>> {
         if path.is_empty() {
             Some(ResolvedPath::new_character(self))
         } else {
@@ -511,7 +503,10 @@ impl MultiGrapheme {
         }
     }
 
-    pub(crate) fn resolve_path_mut(&mut self, path: Vec<PathElement>) -> Option<ResolvedPathMut> {
+    pub(crate) fn resolve_path_mut<'a>(
+        self,
+        path: Vec<PathElement>,
+    ) -> Option<ResolvedPathMut<'a>> {
         if path.is_empty() {
             Some(ResolvedPathMut::new_character(self))
         } else {
@@ -684,11 +679,8 @@ where
             ops.push(op);
             last_elemid = opid.clone().into();
         }
-        let seq = DiffableSequence::new_from(multigraphemes);
-        let text = StateTreeComposite::Text(StateTreeText {
-            object_id: make_text_opid.clone().into(),
-            graphemes: seq,
-        });
+        let seq = TextSequence::new_from(multigraphemes);
+        let text = StateTreeComposite::Text(StateTreeText::new(make_text_opid.clone().into(), seq));
         let value = StateTreeValue::Composite(text);
         NewValue {
             value,
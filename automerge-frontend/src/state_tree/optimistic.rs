@@ -1,19 +1,43 @@
 use std::ops::{Deref, DerefMut};
 
+use automerge_protocol as amp;
+
 use super::{MultiGrapheme, MultiValue, ResolvedPathMut, StateTree};
 use crate::{path::PathElement, Path};
 
 /// Contains the required data to undo an operation on the state tree.
 #[derive(Clone, Debug)]
 pub(crate) enum LocalOperationForRollback {
-    Set { old: Option<MultiValue> },
-    SetList { old: MultiValue },
-    SetText { old: MultiGrapheme },
-    Delete { old: MultiValue },
-    DeleteText { old: MultiGrapheme },
+    Set {
+        old: Option<MultiValue>,
+    },
+    SetList {
+        old: MultiValue,
+    },
+    SetText {
+        old: MultiGrapheme,
+    },
+    Delete {
+        old: MultiValue,
+    },
+    DeleteText {
+        old: MultiGrapheme,
+    },
     Insert,
-    InsertMany { count: usize },
-    Increment { by: i64 },
+    InsertMany {
+        count: usize,
+    },
+    Move {
+        from: usize,
+        to: usize,
+        elem_id: amp::OpId,
+    },
+    Increment {
+        by: i64,
+    },
+    Mark {
+        old: Vec<amp::MarkSpan>,
+    },
 }
 
 /// Keeps track of the changes made to a state tree and allows rolling back changes.
@@ -252,6 +276,20 @@ impl OptimisticStateTree {
                         }
                     }
                 }
+                LocalOperationForRollback::Move { from, to, elem_id } => {
+                    if let Some(ResolvedPathMut::List(mut list)) =
+                        self.state.resolve_path_mut(&path)
+                    {
+                        list.rollback_move(from, to, elem_id)
+                    }
+                }
+                LocalOperationForRollback::Mark { old } => {
+                    if let Some(ResolvedPathMut::Text(mut text)) =
+                        self.state.resolve_path_mut(&path)
+                    {
+                        text.rollback_mark(old)
+                    }
+                }
                 LocalOperationForRollback::Increment { by } => {
                     if path.name().is_some() {
                         if let Some(ResolvedPathMut::Counter(mut counter)) =
@@ -2,6 +2,7 @@ use std::{
     convert::TryInto,
     mem::{discriminant, Discriminant},
     num::NonZeroU32,
+    ops::Range,
 };
 
 use amp::SortedVec;
@@ -9,10 +10,15 @@ use automerge_protocol as amp;
 use smol_str::SmolStr;
 
 use super::{
-    random_op_id, LocalOperationResult, MultiGrapheme, MultiValue, NewValueRequest, StateTree,
-    StateTreeComposite, StateTreeValue,
+    root_op_id, LocalOperationResult, MultiGrapheme, MultiValue, NewValueRequest, StateTree,
+    StateTreeComposite, StateTreeValue, TextSequence,
+};
+use crate::{
+    error,
+    text_boundaries::{self, Granularity},
+    value_ref::ValueRef,
+    Cursor, Primitive, Value,
 };
-use crate::{error, Cursor, Primitive, Value};
 
 pub enum ResolvedPath<'a> {
     Root(ResolvedRoot<'a>),
@@ -20,7 +26,7 @@ pub enum ResolvedPath<'a> {
     Table(ResolvedTable<'a>),
     List(ResolvedList<'a>),
     Text(ResolvedText<'a>),
-    Character(ResolvedChar<'a>),
+    Character(ResolvedChar),
     Counter(ResolvedCounter<'a>),
     Primitive(ResolvedPrimitive<'a>),
 }
@@ -101,7 +107,7 @@ impl<'a> ResolvedPath<'a> {
         ResolvedPath::Primitive(ResolvedPrimitive { multivalue: value })
     }
 
-    pub(super) fn new_character(c: &'a MultiGrapheme) -> ResolvedPath<'a> {
+    pub(super) fn new_character(c: MultiGrapheme) -> ResolvedPath<'a> {
         ResolvedPath::Character(ResolvedChar { multivalue: c })
     }
 
@@ -125,7 +131,7 @@ impl<'a> ResolvedPath<'a> {
             ResolvedPath::Map(maptarget) => maptarget.multivalue.realise_values(),
             ResolvedPath::Root(root) => {
                 let mut result = std::collections::HashMap::new();
-                result.insert(random_op_id(), root.root.value());
+                result.insert(root_op_id(), root.root.value());
                 result
             }
             ResolvedPath::Table(tabletarget) => tabletarget.multivalue.realise_values(),
@@ -137,6 +143,46 @@ impl<'a> ResolvedPath<'a> {
         }
     }
 
+    /// The keys of a map or table, in order. `None` for anything else.
+    pub(crate) fn keys(&self) -> Option<Vec<SmolStr>> {
+        let mut keys: Vec<SmolStr> = match self {
+            ResolvedPath::Root(root) => root.root.root_props.keys().cloned().collect(),
+            _ => match self.value_ref()? {
+                ValueRef::Map(map) => map.keys().cloned().collect(),
+                ValueRef::Table(table) => table.keys().cloned().collect(),
+                _ => return None,
+            },
+        };
+        keys.sort();
+        Some(keys)
+    }
+
+    /// The number of keys of a map or table, or the number of elements of a list or text.
+    /// `None` for anything else.
+    pub(crate) fn len(&self) -> Option<usize> {
+        match self {
+            ResolvedPath::Root(root) => Some(root.root.root_props.len()),
+            _ => match self.value_ref()? {
+                ValueRef::Map(map) => Some(map.len()),
+                ValueRef::Table(table) => Some(table.len()),
+                ValueRef::List(list) => Some(list.len()),
+                ValueRef::Text(text) => Some(text.len()),
+                ValueRef::Primitive(_) => None,
+            },
+        }
+    }
+
+    fn value_ref(&self) -> Option<ValueRef<'a>> {
+        let multivalue = match self {
+            ResolvedPath::Map(ResolvedMap { multivalue, .. })
+            | ResolvedPath::Table(ResolvedTable { multivalue, .. })
+            | ResolvedPath::List(ResolvedList { multivalue, .. })
+            | ResolvedPath::Text(ResolvedText { multivalue, .. }) => multivalue,
+            _ => return None,
+        };
+        Some(ValueRef::new(multivalue.default_statetree_value()))
+    }
+
     pub fn object_id(&self) -> Option<amp::ObjectId> {
         match &self {
             ResolvedPath::Map(maptarget) => Some(maptarget.object_id.clone()),
@@ -157,7 +203,7 @@ pub enum ResolvedPathMut<'a> {
     Table(ResolvedTableMut<'a>),
     List(ResolvedListMut<'a>),
     Text(ResolvedTextMut<'a>),
-    Character(ResolvedCharMut<'a>),
+    Character(ResolvedCharMut),
     Counter(ResolvedCounterMut<'a>),
     Primitive(ResolvedPrimitiveMut<'a>),
 }
@@ -250,7 +296,7 @@ impl<'a> ResolvedPathMut<'a> {
         ResolvedPathMut::Primitive(ResolvedPrimitiveMut { multivalue: value })
     }
 
-    pub(super) fn new_character(c: &'a mut MultiGrapheme) -> ResolvedPathMut<'a> {
+    pub(super) fn new_character(c: MultiGrapheme) -> ResolvedPathMut<'a> {
         ResolvedPathMut::Character(ResolvedCharMut { multivalue: c })
     }
 }
@@ -285,7 +331,7 @@ impl<'a> ResolvedRootMut<'a> {
             pred: self
                 .root
                 .get(&key)
-                .map(|mv| vec![mv.default_opid()].into())
+                .map(MultiValue::pred)
                 .unwrap_or_else(SortedVec::new),
         });
         let (multivalue, new_ops, _new_cursors) = newvalue.finish();
@@ -296,7 +342,7 @@ impl<'a> ResolvedRootMut<'a> {
     pub(crate) fn delete_key(&mut self, key: &str) -> Option<(MultiValue, LocalOperationResult)> {
         let existing_value = self.root.get(key);
         let pred = existing_value
-            .map(|v| vec![v.default_opid()].into())
+            .map(MultiValue::pred)
             .unwrap_or_else(SortedVec::new);
         let op_result = LocalOperationResult {
             new_ops: vec![amp::Op {
@@ -344,7 +390,7 @@ impl<'a> ResolvedCounterMut<'a> {
             StateTreeValue::Leaf(Primitive::Counter(c)) => c,
             _ => unreachable!(),
         };
-        *counter += by;
+        *counter = counter.saturating_add(by);
         LocalOperationResult {
             new_ops: vec![amp::Op {
                 action: amp::OpType::Inc(by),
@@ -361,7 +407,7 @@ impl<'a> ResolvedCounterMut<'a> {
             StateTreeValue::Leaf(Primitive::Counter(c)) => c,
             _ => unreachable!(),
         };
-        *counter -= by;
+        *counter = counter.saturating_sub(by);
     }
 }
 
@@ -615,7 +661,6 @@ impl<'a> ResolvedTextMut<'a> {
         };
         let index: usize = index.try_into().unwrap();
         let (current_elemid, _) = state_tree_text.elem_at(index)?;
-        let current_elemid = current_elemid.clone();
         let update_op = amp::OpId::new(payload.start_op, payload.actor);
         let c = MultiGrapheme::new_from_grapheme_cluster(update_op, payload.value.clone());
         let pred = state_tree_text.pred_for_index(index as u32);
@@ -643,7 +688,6 @@ impl<'a> ResolvedTextMut<'a> {
             _ => unreachable!(),
         };
         let (current_elemid, _) = state_tree_text.elem_at(index.try_into().unwrap())?;
-        let current_elemid = current_elemid.clone();
         let pred = state_tree_text.pred_for_index(index as u32);
         let old = state_tree_text.remove(index.try_into().unwrap())?;
         Ok((
@@ -689,9 +733,119 @@ impl<'a> ResolvedTextMut<'a> {
             .remove(index)
             .expect("Failed to rollback insert");
     }
+
+    /// Set the mark `name` to `value` for the characters from `start` up to (but not including)
+    /// `end`. This creates a `MarkBegin` op after the character before the span and a `MarkEnd`
+    /// op after the last character of the span. Returns the marks as they were before.
+    pub(crate) fn mark(
+        &mut self,
+        start: u32,
+        end: u32,
+        name: SmolStr,
+        value: amp::ScalarValue,
+        start_op: u64,
+        actor: &amp::ActorId,
+    ) -> Result<(Vec<amp::MarkSpan>, LocalOperationResult), error::MissingIndexError> {
+        let state_tree_text = match self.multivalue.default_statetree_value_mut() {
+            StateTreeValue::Composite(StateTreeComposite::Text(text)) => text,
+            _ => unreachable!(),
+        };
+        let after: amp::ElementId = match start {
+            0 => amp::ElementId::Head,
+            i => state_tree_text
+                .elem_at((i - 1).try_into().unwrap())?
+                .0
+                .into(),
+        };
+        let last = state_tree_text.elem_at((end - 1).try_into().unwrap())?.0;
+        let begin_op = amp::OpId::new(start_op, actor);
+        let old = state_tree_text.set_mark(start.into(), end.into(), &name, &value);
+        Ok((
+            old,
+            LocalOperationResult {
+                new_ops: vec![
+                    amp::Op {
+                        action: amp::OpType::MarkBegin(amp::MarkData { name, value }),
+                        obj: state_tree_text.object_id.clone(),
+                        key: after.into(),
+                        insert: true,
+                        pred: SortedVec::new(),
+                    },
+                    amp::Op {
+                        action: amp::OpType::MarkEnd(begin_op),
+                        obj: state_tree_text.object_id.clone(),
+                        key: last.into(),
+                        insert: true,
+                        pred: SortedVec::new(),
+                    },
+                ],
+            },
+        ))
+    }
+
+    pub(crate) fn rollback_mark(&mut self, old: Vec<amp::MarkSpan>) {
+        let state_tree_text = match self.multivalue.default_statetree_value_mut() {
+            StateTreeValue::Composite(StateTreeComposite::Text(text)) => text,
+            _ => unreachable!(),
+        };
+        state_tree_text.restore_marks(old);
+    }
 }
 
 impl<'a> ResolvedText<'a> {
+    fn sequence(&self) -> &'a TextSequence {
+        match self.multivalue.default_statetree_value() {
+            StateTreeValue::Composite(StateTreeComposite::Text(text)) => &text.graphemes,
+            _ => unreachable!(),
+        }
+    }
+
+    /// The number of elements
+    pub(crate) fn len(&self) -> usize {
+        self.sequence().len()
+    }
+
+    /// The value of each element, in order
+    pub(crate) fn graphemes(&self) -> impl Iterator<Item = &'a SmolStr> {
+        self.sequence().graphemes()
+    }
+
+    pub(crate) fn text(&self) -> String {
+        match self.multivalue.default_statetree_value() {
+            StateTreeValue::Composite(StateTreeComposite::Text(text)) => {
+                text.graphemes.graphemes().map(SmolStr::as_str).collect()
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// The range of elements of the segment containing the element at `index`
+    pub(crate) fn segment_at(
+        &self,
+        index: usize,
+        granularity: Granularity,
+    ) -> Option<Range<usize>> {
+        let text = match self.multivalue.default_statetree_value() {
+            StateTreeValue::Composite(StateTreeComposite::Text(text)) => text,
+            _ => unreachable!(),
+        };
+        text_boundaries::segment_at(
+            |i| text.graphemes.get(i).map_or("", |(_, g)| g.as_str()),
+            text.graphemes.len(),
+            index,
+            granularity,
+        )
+    }
+
+    pub(crate) fn marks(&self) -> Vec<amp::MarkSpan> {
+        match self.multivalue.default_statetree_value() {
+            StateTreeValue::Composite(StateTreeComposite::Text(text)) => {
+                text.marks().cloned().collect()
+            }
+            _ => unreachable!(),
+        }
+    }
+
     pub(crate) fn get_cursor(&self, index: u32) -> Result<Cursor, error::MissingIndexError> {
         let state_tree_text = match self.multivalue.default_statetree_value() {
             StateTreeValue::Composite(StateTreeComposite::Text(text)) => text,
@@ -844,6 +998,55 @@ impl<'a> ResolvedListMut<'a> {
         ))
     }
 
+    /// Move the element at `from` so that it ends up at index `to`. The element is given the ID
+    /// of the move op, which is `start_op`.
+    pub(crate) fn move_item(
+        &mut self,
+        from: u32,
+        to: u32,
+        start_op: u64,
+        actor: &amp::ActorId,
+    ) -> Result<(amp::OpId, LocalOperationResult), error::MissingIndexError> {
+        let state_tree_list = match self.multivalue.default_statetree_value_mut() {
+            StateTreeValue::Composite(StateTreeComposite::List(list)) => list,
+            _ => unreachable!(),
+        };
+        let op_id = amp::OpId::new(start_op, actor);
+        let old_elem_id =
+            state_tree_list.move_element(from as usize, to as usize, op_id.clone())?;
+        // the element now at `to - 1` is the one the moved element follows
+        let key = match to {
+            0 => amp::ElementId::Head,
+            i => state_tree_list
+                .elem_at((i - 1).try_into().unwrap())?
+                .0
+                .clone()
+                .into(),
+        };
+        Ok((
+            old_elem_id.clone(),
+            LocalOperationResult {
+                new_ops: vec![amp::Op {
+                    action: amp::OpType::Move(old_elem_id),
+                    obj: state_tree_list.object_id.clone(),
+                    key: key.into(),
+                    insert: true,
+                    pred: SortedVec::new(),
+                }],
+            },
+        ))
+    }
+
+    pub(crate) fn rollback_move(&mut self, from: usize, to: usize, elem_id: amp::OpId) {
+        let state_tree_list = match self.multivalue.default_statetree_value_mut() {
+            StateTreeValue::Composite(StateTreeComposite::List(list)) => list,
+            _ => unreachable!(),
+        };
+        state_tree_list
+            .move_element(to, from, elem_id)
+            .expect("Failed to rollback move");
+    }
+
     pub(crate) fn rollback_set(&mut self, index: usize, value: MultiValue) {
         let state_tree_list = match self.multivalue.default_statetree_value_mut() {
             StateTreeValue::Composite(StateTreeComposite::List(list)) => list,
@@ -890,12 +1093,13 @@ impl<'a> ResolvedList<'a> {
     }
 }
 
-pub struct ResolvedChar<'a> {
-    pub(super) multivalue: &'a MultiGrapheme,
+/// A character of a text object. Characters are not stored individually, so this is a copy.
+pub struct ResolvedChar {
+    pub(super) multivalue: MultiGrapheme,
 }
 
-pub struct ResolvedCharMut<'a> {
-    pub(super) multivalue: &'a mut MultiGrapheme,
+pub struct ResolvedCharMut {
+    pub(super) multivalue: MultiGrapheme,
 }
 
 pub struct ResolvedPrimitive<'a> {
@@ -0,0 +1,933 @@
+use std::{collections::HashMap, rc::Rc};
+
+use amp::{OpId, SortedVec};
+use automerge_protocol as amp;
+use smol_str::SmolStr;
+
+use super::{
+    diffable_sequence::{check_edits, SequenceValue},
+    MultiGrapheme,
+};
+use crate::{error::InvalidPatch, patch_stats};
+
+/// The most characters kept in one run, which bounds the cost of splitting a run to insert or
+/// remove characters in the middle of it
+const MAX_RUN: usize = 256;
+
+/// The most entries in a leaf of the tree of entries, or children of one of its internal nodes
+const MAX_NODE: usize = 32;
+
+/// Nodes smaller than this are merged with a neighbour
+const MIN_NODE: usize = MAX_NODE / 4;
+
+/// The characters of a text object.
+///
+/// Most characters are inserted by consecutive ops of one actor and never change afterwards, so
+/// the ID of each character (which is both its element ID and the ID of the op which set its
+/// value) can be derived from the ID of the first. Such characters are stored as runs of
+/// graphemes, which takes a fraction of the memory of a [`super::DiffableSequence`] of
+/// [`MultiGrapheme`]s. Only characters which have been overwritten, or have conflicting values,
+/// are stored as elements of their own.
+///
+/// The entries are kept in a B-tree whose nodes know how many characters they hold, so finding
+/// the character at an index is logarithmic in the number of entries however fragmented the
+/// runs become. The nodes are shared between clones of the sequence.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct TextSequence {
+    root: Rc<Node>,
+    len: usize,
+}
+
+#[derive(Clone, Debug)]
+enum Node {
+    Leaf(Vec<Entry>),
+    Internal(Vec<Child>),
+}
+
+#[derive(Clone, Debug)]
+struct Child {
+    /// The number of characters under `node`
+    len: usize,
+    node: Rc<Node>,
+}
+
+#[derive(Clone, Debug)]
+enum Entry {
+    /// Characters with the IDs `start`, `start + 1` and so on, each having a single value
+    Run {
+        start: OpId,
+        graphemes: Rc<Vec<SmolStr>>,
+    },
+    Element(Box<Element>),
+}
+
+#[derive(Clone, Debug)]
+struct Element {
+    elem_id: OpId,
+    value: SequenceValue<MultiGrapheme>,
+}
+
+impl Entry {
+    fn len(&self) -> usize {
+        match self {
+            Entry::Run { graphemes, .. } => graphemes.len(),
+            Entry::Element(_) => 1,
+        }
+    }
+
+    /// The entry for a character with the element ID `elem_id`
+    fn new(elem_id: OpId, value: MultiGrapheme) -> Entry {
+        if value.conflicts.is_empty() && value.winning_value.0 == elem_id {
+            Entry::Run {
+                start: elem_id,
+                graphemes: Rc::new(vec![value.winning_value.1]),
+            }
+        } else {
+            Entry::Element(Box::new(Element {
+                elem_id,
+                value: SequenceValue::Original(value),
+            }))
+        }
+    }
+
+    /// Split off the characters from `offset` onwards, which must be inside a run
+    fn split_off(&mut self, offset: usize) -> Entry {
+        match self {
+            Entry::Run { start, graphemes } => Entry::Run {
+                start: start.increment_by(offset as u64),
+                graphemes: Rc::new(Rc::make_mut(graphemes).split_off(offset)),
+            },
+            Entry::Element(_) => unreachable!("an element is a single character"),
+        }
+    }
+
+    /// Whether `other` can be appended to this entry, i.e. they are both runs and the IDs of
+    /// `other` follow on
+    fn can_merge(&self, other: &Entry) -> bool {
+        match (self, other) {
+            (
+                Entry::Run { start, graphemes },
+                Entry::Run {
+                    start: other_start,
+                    graphemes: other_graphemes,
+                },
+            ) => {
+                start.1 == other_start.1
+                    && start.0 + graphemes.len() as u64 == other_start.0
+                    && graphemes.len() + other_graphemes.len() <= MAX_RUN
+            }
+            _ => false,
+        }
+    }
+
+    /// Append `other` to this entry if [`Entry::can_merge`]
+    fn try_merge(&mut self, other: &Entry) -> bool {
+        if !self.can_merge(other) {
+            return false;
+        }
+        if let (
+            Entry::Run { graphemes, .. },
+            Entry::Run {
+                graphemes: other, ..
+            },
+        ) = (self, other)
+        {
+            Rc::make_mut(graphemes).extend(other.iter().cloned());
+        }
+        true
+    }
+}
+
+/// The index in `entries` of the entry containing the character at `index` and the offset of
+/// the character within it. Past the last character this is one past the last entry.
+fn locate_in(entries: &[Entry], mut index: usize) -> (usize, usize) {
+    for (i, entry) in entries.iter().enumerate() {
+        if index < entry.len() {
+            return (i, index);
+        }
+        index -= entry.len();
+    }
+    (entries.len(), 0)
+}
+
+impl Default for Node {
+    fn default() -> Self {
+        Node::Leaf(Vec::new())
+    }
+}
+
+impl Node {
+    /// The number of entries or children
+    fn width(&self) -> usize {
+        match self {
+            Node::Leaf(entries) => entries.len(),
+            Node::Internal(children) => children.len(),
+        }
+    }
+
+    /// The number of characters
+    fn len(&self) -> usize {
+        match self {
+            Node::Leaf(entries) => entries.iter().map(Entry::len).sum(),
+            Node::Internal(children) => children.iter().map(|child| child.len).sum(),
+        }
+    }
+
+    /// The entry containing the character at `index`, which must exist, and the offset of the
+    /// character within it
+    fn locate(&self, mut index: usize) -> (&Entry, usize) {
+        let mut node = self;
+        loop {
+            match node {
+                Node::Internal(children) => {
+                    let mut i = 0;
+                    while index >= children[i].len {
+                        index -= children[i].len;
+                        i += 1;
+                    }
+                    node = &children[i].node;
+                }
+                Node::Leaf(entries) => {
+                    let (i, offset) = locate_in(entries, index);
+                    return (&entries[i], offset);
+                }
+            }
+        }
+    }
+
+    fn entries(&self) -> Box<dyn Iterator<Item = &Entry> + '_> {
+        match self {
+            Node::Leaf(entries) => Box::new(entries.iter()),
+            Node::Internal(children) => {
+                Box::new(children.iter().flat_map(|child| child.node.entries()))
+            }
+        }
+    }
+
+    /// Call `f` with the leaf holding the character at `index`, or the last leaf if `index` is
+    /// the length, and the index of the character within the leaf
+    fn edit_leaf<F, R>(&mut self, mut index: usize, f: F) -> R
+    where
+        F: FnOnce(&mut Vec<Entry>, usize) -> R,
+    {
+        match self {
+            Node::Leaf(entries) => f(entries, index),
+            Node::Internal(children) => {
+                let mut i = 0;
+                while i + 1 < children.len() && index >= children[i].len {
+                    index -= children[i].len;
+                    i += 1;
+                }
+                let result = Rc::make_mut(&mut children[i].node).edit_leaf(index, f);
+                children[i].len = children[i].node.len();
+                rebalance(children, i);
+                result
+            }
+        }
+    }
+
+    /// Remove the entries holding the characters `start..end`, which must begin and end on
+    /// entry boundaries
+    fn remove_range(&mut self, start: usize, end: usize) {
+        match self {
+            Node::Leaf(entries) => {
+                let mut offset = 0;
+                entries.retain(|entry| {
+                    let keep = offset < start || offset >= end;
+                    offset += entry.len();
+                    keep
+                });
+            }
+            Node::Internal(children) => {
+                let mut offset = 0;
+                let mut i = 0;
+                while i < children.len() {
+                    let len = children[i].len;
+                    if offset + len <= start || offset >= end {
+                        i += 1;
+                    } else if start <= offset && offset + len <= end {
+                        children.remove(i);
+                    } else {
+                        let child = &mut children[i];
+                        Rc::make_mut(&mut child.node)
+                            .remove_range(start.saturating_sub(offset), (end - offset).min(len));
+                        child.len = child.node.len();
+                        i += 1;
+                    }
+                    offset += len;
+                }
+                let mut i = 0;
+                while i < children.len() {
+                    rebalance(children, i);
+                    i += 1;
+                }
+            }
+        }
+    }
+
+    /// Append the entries or children of `other`, which must be at the same depth
+    fn append(&mut self, other: Node) {
+        match (self, other) {
+            (Node::Leaf(entries), Node::Leaf(other)) => entries.extend(other),
+            (Node::Internal(children), Node::Internal(other)) => children.extend(other),
+            _ => unreachable!("all the leaves are at the same depth"),
+        }
+    }
+
+    /// Split a node which is too wide into nodes of roughly equal width
+    fn split(self) -> Vec<Node> {
+        let pieces = self.width().div_ceil(MAX_NODE);
+        let size = self.width().div_ceil(pieces);
+        match self {
+            Node::Leaf(entries) => entries
+                .chunks(size)
+                .map(|chunk| Node::Leaf(chunk.to_vec()))
+                .collect(),
+            Node::Internal(children) => children
+                .chunks(size)
+                .map(|chunk| Node::Internal(chunk.to_vec()))
+                .collect(),
+        }
+    }
+}
+
+impl Child {
+    fn new(node: Node) -> Child {
+        Child {
+            len: node.len(),
+            node: Rc::new(node),
+        }
+    }
+
+    fn into_node(self) -> Node {
+        Rc::try_unwrap(self.node).unwrap_or_else(|node| (*node).clone())
+    }
+}
+
+/// Split or merge `children[i]` if it has become too wide or too narrow
+fn rebalance(children: &mut Vec<Child>, i: usize) {
+    let width = children[i].node.width();
+    if width == 0 {
+        children.remove(i);
+    } else if width > MAX_NODE {
+        let pieces = children.remove(i).into_node().split();
+        children.splice(i..i, pieces.into_iter().map(Child::new));
+    } else if width < MIN_NODE && children.len() > 1 {
+        let left = if i > 0 { i - 1 } else { i };
+        let right = children.remove(left + 1);
+        children[left].len += right.len;
+        Rc::make_mut(&mut children[left].node).append(right.into_node());
+        if children[left].node.width() > MAX_NODE {
+            let pieces = children.remove(left).into_node().split();
+            children.splice(left..left, pieces.into_iter().map(Child::new));
+        }
+    }
+}
+
+/// The ranges of element IDs inserted by a patch, see `TextSequence::apply_diff`
+#[derive(Default)]
+struct Inserted(Vec<(OpId, usize)>);
+
+impl Inserted {
+    fn contains(&self, elem_id: &OpId) -> bool {
+        self.0.iter().any(|(start, len)| {
+            start.1 == elem_id.1 && elem_id.0 >= start.0 && elem_id.0 < start.0 + *len as u64
+        })
+    }
+}
+
+/// The indexes of the characters updated by a patch, which are finished once the whole patch
+/// has been applied, see `TextSequence::apply_diff`
+#[derive(Default)]
+struct Updated(Vec<usize>);
+
+impl Updated {
+    fn inserted(&mut self, index: usize, count: usize) {
+        for updated in &mut self.0 {
+            if *updated >= index {
+                *updated += count;
+            }
+        }
+    }
+
+    fn removed(&mut self, index: usize, count: usize) {
+        self.0
+            .retain(|updated| *updated < index || *updated >= index + count);
+        for updated in &mut self.0 {
+            if *updated >= index {
+                *updated -= count;
+            }
+        }
+    }
+}
+
+impl TextSequence {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// A sequence of characters whose element IDs are the IDs of their values
+    pub(super) fn new_from<I>(values: I) -> Self
+    where
+        I: IntoIterator<Item = MultiGrapheme>,
+    {
+        let mut sequence = Self::new();
+        sequence.insert_many(0, values);
+        sequence
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Call `f` with the leaf holding the character at `index` and the index of the character
+    /// within it, then restore the balance of the tree
+    fn edit_leaf<F, R>(&mut self, index: usize, f: F) -> R
+    where
+        F: FnOnce(&mut Vec<Entry>, usize) -> R,
+    {
+        let result = Rc::make_mut(&mut self.root).edit_leaf(index, f);
+        self.fix_root();
+        result
+    }
+
+    /// Grow the tree if the root has become too wide and shrink it if the root has a single
+    /// child
+    fn fix_root(&mut self) {
+        loop {
+            match &*self.root {
+                root if root.width() > MAX_NODE => {
+                    let root = std::mem::take(&mut self.root);
+                    let pieces = Rc::try_unwrap(root)
+                        .unwrap_or_else(|root| (*root).clone())
+                        .split();
+                    self.root =
+                        Rc::new(Node::Internal(pieces.into_iter().map(Child::new).collect()));
+                }
+                Node::Internal(children) if children.len() == 1 => {
+                    self.root = children[0].node.clone();
+                }
+                Node::Internal(children) if children.is_empty() => {
+                    self.root = Rc::default();
+                }
+                _ => return,
+            }
+        }
+    }
+
+    /// Call `f` with the entry starting at `index`
+    fn with_entry<F, R>(&mut self, index: usize, f: F) -> R
+    where
+        F: FnOnce(&mut Entry) -> R,
+    {
+        self.edit_leaf(index, |entries, index| {
+            let (i, _) = locate_in(entries, index);
+            f(&mut entries[i])
+        })
+    }
+
+    /// Make sure an entry starts at `index`, splitting a run if need be
+    fn split_at(&mut self, index: usize) {
+        if index >= self.len {
+            return;
+        }
+        self.edit_leaf(index, |entries, index| {
+            let (i, offset) = locate_in(entries, index);
+            if offset > 0 {
+                let right = entries[i].split_off(offset);
+                entries.insert(i + 1, right);
+            }
+        });
+    }
+
+    /// Merge the entries either side of `index` if they are runs which follow on
+    fn merge_at(&mut self, index: usize) {
+        if index == 0 || index >= self.len {
+            return;
+        }
+        let (before, offset) = self.root.locate(index - 1);
+        if offset + 1 != before.len() {
+            return;
+        }
+        let (after, offset) = self.root.locate(index);
+        if offset != 0 || !before.can_merge(after) {
+            return;
+        }
+        let after = after.clone();
+        Rc::make_mut(&mut self.root).remove_range(index, index + after.len());
+        self.fix_root();
+        self.edit_leaf(index - 1, |entries, index| {
+            let (i, _) = locate_in(entries, index);
+            entries[i].try_merge(&after);
+        });
+    }
+
+    fn insert_entries(&mut self, index: usize, entries: Vec<Entry>) {
+        if entries.is_empty() {
+            return;
+        }
+        let added: usize = entries.iter().map(Entry::len).sum();
+        self.split_at(index);
+        self.edit_leaf(index, |leaf, index| {
+            let (i, _) = locate_in(leaf, index);
+            leaf.splice(i..i, entries);
+        });
+        self.len += added;
+        self.merge_at(index + added);
+        self.merge_at(index);
+    }
+
+    fn remove_range(&mut self, index: usize, count: usize) {
+        if count == 0 {
+            return;
+        }
+        self.split_at(index);
+        self.split_at(index + count);
+        Rc::make_mut(&mut self.root).remove_range(index, index + count);
+        self.fix_root();
+        self.len -= count;
+        self.merge_at(index);
+    }
+
+    /// The element ID and current value of the character at `index`
+    pub(crate) fn get(&self, index: usize) -> Option<(OpId, &SmolStr)> {
+        if index >= self.len {
+            return None;
+        }
+        let (entry, offset) = self.root.locate(index);
+        Some(match entry {
+            Entry::Run { start, graphemes } => {
+                (start.increment_by(offset as u64), &graphemes[offset])
+            }
+            Entry::Element(element) => (
+                element.elem_id.clone(),
+                element.value.get().default_grapheme(),
+            ),
+        })
+    }
+
+    /// All the values of the character at `index`
+    pub(crate) fn grapheme(&self, index: usize) -> Option<MultiGrapheme> {
+        if index >= self.len {
+            return None;
+        }
+        let (entry, offset) = self.root.locate(index);
+        Some(match entry {
+            Entry::Run { start, graphemes } => MultiGrapheme {
+                winning_value: (start.increment_by(offset as u64), graphemes[offset].clone()),
+                conflicts: HashMap::new(),
+            },
+            Entry::Element(element) => element.value.get().clone(),
+        })
+    }
+
+    pub(crate) fn pred_for_index(&self, index: usize) -> SortedVec<OpId> {
+        self.grapheme(index)
+            .map_or_else(SortedVec::new, |grapheme| grapheme.pred())
+    }
+
+    /// The current value of each character, in order
+    pub(crate) fn graphemes(&self) -> impl Iterator<Item = &SmolStr> + '_ {
+        self.root.entries().flat_map(|entry| {
+            let (run, element) = match entry {
+                Entry::Run { graphemes, .. } => (Some(graphemes.iter()), None),
+                Entry::Element(element) => (None, Some(element.value.get().default_grapheme())),
+            };
+            run.into_iter().flatten().chain(element)
+        })
+    }
+
+    /// Replace the character at `index` with `value`, keeping its element ID
+    pub(super) fn set(&mut self, index: usize, value: MultiGrapheme) -> MultiGrapheme {
+        let (elem_id, _) = self
+            .get(index)
+            .expect("Failed to get existing index in set");
+        let old = self.grapheme(index).unwrap();
+        self.split_at(index);
+        self.split_at(index + 1);
+        self.with_entry(index, |entry| *entry = Entry::new(elem_id, value));
+        self.merge_at(index + 1);
+        self.merge_at(index);
+        old
+    }
+
+    pub(super) fn remove(&mut self, index: usize) -> MultiGrapheme {
+        let old = self.grapheme(index).unwrap();
+        self.remove_range(index, 1);
+        old
+    }
+
+    /// Insert `values` at `index`, each with the ID of its winning value as its element ID
+    pub(super) fn insert_many<I>(&mut self, index: usize, values: I)
+    where
+        I: IntoIterator<Item = MultiGrapheme>,
+    {
+        let mut entries: Vec<Entry> = Vec::new();
+        for value in values {
+            let entry = Entry::new(value.winning_value.0.clone(), value);
+            let merged = entries
+                .last_mut()
+                .is_some_and(|last| last.try_merge(&entry));
+            if !merged {
+                entries.push(entry);
+            }
+        }
+        self.insert_entries(index, entries);
+    }
+
+    /// Insert the characters `graphemes` with the IDs `start`, `start + 1` and so on
+    fn insert_run<I>(
+        &mut self,
+        index: usize,
+        start: &OpId,
+        graphemes: I,
+        inserted: &mut Inserted,
+    ) -> usize
+    where
+        I: Iterator<Item = SmolStr>,
+    {
+        let graphemes: Vec<SmolStr> = graphemes.collect();
+        let count = graphemes.len();
+        patch_stats::record(|stats| {
+            stats.nodes_created += count as u64;
+            stats.elements_inserted += count as u64;
+        });
+        inserted.0.push((start.clone(), count));
+        let entries = graphemes
+            .chunks(MAX_RUN)
+            .enumerate()
+            .map(|(i, chunk)| Entry::Run {
+                start: start.increment_by((i * MAX_RUN) as u64),
+                graphemes: Rc::new(chunk.to_vec()),
+            })
+            .collect();
+        self.insert_entries(index, entries);
+        count
+    }
+
+    pub(crate) fn check_diff(
+        &self,
+        object_id: &amp::ObjectId,
+        edits: &[amp::DiffEdit],
+    ) -> Result<(), InvalidPatch> {
+        check_edits::<MultiGrapheme>(self.len, object_id, edits)
+    }
+
+    /// Apply edits which `check_diff` has accepted.
+    ///
+    /// Values set for a character by `Update` edits in one patch replace all of its previous
+    /// values, exactly as for the elements of a `DiffableSequence`, except that a character
+    /// inserted by the same patch keeps the value it was inserted with.
+    pub(crate) fn apply_diff(&mut self, edits: Vec<amp::DiffEdit>) {
+        let mut inserted = Inserted::default();
+        let mut updated = Updated::default();
+        for edit in edits {
+            match edit {
+                amp::DiffEdit::Remove { index, count } => {
+                    self.remove_range(index as usize, count as usize);
+                    updated.removed(index as usize, count as usize);
+                    patch_stats::record(|stats| stats.elements_removed += count);
+                }
+                // marks don't change the characters, `StateTreeText` keeps track of them
+                amp::DiffEdit::Marks(_) => {}
+                amp::DiffEdit::SingleElementInsert {
+                    index,
+                    elem_id,
+                    op_id,
+                    value,
+                } => {
+                    let value = MultiGrapheme::new_from_diff(op_id, value);
+                    patch_stats::record(|stats| stats.elements_inserted += 1);
+                    let elem_id = elem_id.as_opid().unwrap_or(&value.winning_value.0).clone();
+                    inserted.0.push((elem_id.clone(), 1));
+                    self.insert_entries(index as usize, vec![Entry::new(elem_id, value)]);
+                    updated.inserted(index as usize, 1);
+                }
+                amp::DiffEdit::MultiElementInsert(amp::MultiElementInsert {
+                    elem_id,
+                    values,
+                    index,
+                }) => {
+                    let count = self.insert_run(
+                        index as usize,
+                        elem_id.as_opid().unwrap(),
+                        values.iter().map(|value| match value {
+                            amp::ScalarValue::Str(s) => s.clone(),
+                            _ => unreachable!("insert non text in text object"),
+                        }),
+                        &mut inserted,
+                    );
+                    updated.inserted(index as usize, count);
+                }
+                amp::DiffEdit::TextInsert(insert) => {
+                    // `check_diff` ensures the characters are well formed
+                    let characters = insert.characters().unwrap_or_default();
+                    let count = self.insert_run(
+                        insert.index as usize,
+                        insert.elem_id.as_opid().unwrap(),
+                        characters.into_iter().map(SmolStr::from),
+                        &mut inserted,
+                    );
+                    updated.inserted(insert.index as usize, count);
+                }
+                amp::DiffEdit::Update {
+                    index,
+                    value,
+                    op_id,
+                } => {
+                    let index = index as usize;
+                    if index >= self.len {
+                        continue;
+                    }
+                    self.split_at(index);
+                    self.split_at(index + 1);
+                    self.with_entry(index, |entry| {
+                        if let Entry::Run { start, graphemes } = entry {
+                            let grapheme = MultiGrapheme {
+                                winning_value: (start.clone(), graphemes[0].clone()),
+                                conflicts: HashMap::new(),
+                            };
+                            let value = if inserted.contains(start) {
+                                SequenceValue::New(grapheme)
+                            } else {
+                                SequenceValue::Original(grapheme)
+                            };
+                            *entry = Entry::Element(Box::new(Element {
+                                elem_id: start.clone(),
+                                value,
+                            }));
+                        }
+                        if let Entry::Element(element) = entry {
+                            element.value.apply_diff(op_id, value);
+                        }
+                    });
+                    updated.0.push(index);
+                }
+            }
+        }
+
+        for index in updated.0 {
+            self.with_entry(index, |entry| {
+                if let Entry::Element(element) = entry {
+                    element.value.finish();
+                }
+            });
+        }
+    }
+}
+
+// Where the runs happen to be split doesn't change the text
+impl PartialEq for TextSequence {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len
+            && (0..self.len).all(|i| {
+                self.get(i).map(|(id, _)| id) == other.get(i).map(|(id, _)| id)
+                    && self.grapheme(i) == other.grapheme(i)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use amp::{ActorId, Diff, DiffEdit, ScalarValue};
+
+    use super::*;
+
+    fn text(sequence: &TextSequence) -> String {
+        sequence.graphemes().map(SmolStr::as_str).collect()
+    }
+
+    fn insert(actor: &ActorId, index: u64, counter: u64, text: &str) -> DiffEdit {
+        DiffEdit::TextInsert(amp::TextInsert {
+            index,
+            elem_id: actor.op_id_at(counter).into(),
+            text: text.to_string(),
+            lengths: Vec::new(),
+        })
+    }
+
+    #[test]
+    fn typing_is_kept_in_one_run() {
+        let actor = ActorId::random();
+        let mut sequence = TextSequence::new();
+        for (i, c) in "hello".chars().enumerate() {
+            sequence.apply_diff(vec![DiffEdit::SingleElementInsert {
+                index: i as u64,
+                elem_id: actor.op_id_at(i as u64 + 1).into(),
+                op_id: actor.op_id_at(i as u64 + 1),
+                value: Diff::Value(ScalarValue::Str(c.to_string().into())),
+            }]);
+        }
+        assert_eq!(text(&sequence), "hello");
+        assert_eq!(sequence.root.entries().count(), 1);
+        assert_eq!(sequence.get(4).unwrap().0, actor.op_id_at(5));
+    }
+
+    #[test]
+    fn long_inserts_are_split_into_runs() {
+        let actor = ActorId::random();
+        let mut sequence = TextSequence::new();
+        let long = "a".repeat(MAX_RUN * 2 + 1);
+        sequence.apply_diff(vec![insert(&actor, 0, 1, &long)]);
+        assert_eq!(sequence.len(), MAX_RUN * 2 + 1);
+        assert_eq!(sequence.root.entries().count(), 3);
+        assert_eq!(
+            sequence.get(MAX_RUN * 2).unwrap().0,
+            actor.op_id_at(MAX_RUN as u64 * 2 + 1)
+        );
+    }
+
+    #[test]
+    fn splices_split_and_merge_runs() {
+        let actor = ActorId::random();
+        let other = ActorId::random();
+        let mut sequence = TextSequence::new();
+        sequence.apply_diff(vec![insert(&actor, 0, 1, "abcdef")]);
+        sequence.apply_diff(vec![insert(&other, 3, 10, "XY")]);
+        assert_eq!(text(&sequence), "abcXYdef");
+        assert_eq!(sequence.get(5).unwrap().0, actor.op_id_at(4));
+        assert_eq!(sequence.get(4).unwrap().0, other.op_id_at(11));
+
+        sequence.apply_diff(vec![DiffEdit::Remove { index: 3, count: 2 }]);
+        assert_eq!(text(&sequence), "abcdef");
+        // the two halves of the original run follow on again
+        assert_eq!(sequence.root.entries().count(), 1);
+
+        sequence.apply_diff(vec![DiffEdit::Remove { index: 1, count: 4 }]);
+        assert_eq!(text(&sequence), "af");
+        assert_eq!(sequence.get(1).unwrap().0, actor.op_id_at(6));
+    }
+
+    #[test]
+    fn updates_make_conflicts() {
+        let actor = ActorId::random();
+        let other = ActorId::random();
+        let mut sequence = TextSequence::new();
+        sequence.apply_diff(vec![insert(&actor, 0, 1, "abc")]);
+        sequence.apply_diff(vec![
+            DiffEdit::Update {
+                index: 1,
+                op_id: actor.op_id_at(4),
+                value: Diff::Value(ScalarValue::Str("x".into())),
+            },
+            DiffEdit::Update {
+                index: 1,
+                op_id: other.op_id_at(4),
+                value: Diff::Value(ScalarValue::Str("y".into())),
+            },
+        ]);
+        let winner = if other > actor { "y" } else { "x" };
+        assert_eq!(text(&sequence), format!("a{}c", winner));
+        // the element ID of the character stays the same
+        assert_eq!(sequence.get(1).unwrap().0, actor.op_id_at(2));
+        assert_eq!(sequence.grapheme(1).unwrap().realise_values().len(), 2);
+        assert_eq!(sequence.pred_for_index(1).len(), 2);
+
+        // the characters around the updated one are still there
+        sequence.apply_diff(vec![insert(&actor, 3, 5, "d")]);
+        assert_eq!(sequence.get(2).unwrap().0, actor.op_id_at(3));
+        assert_eq!(sequence.len(), 4);
+    }
+
+    #[test]
+    fn equality_ignores_how_runs_are_split() {
+        let actor = ActorId::random();
+        let long = "a".repeat(MAX_RUN + 10);
+        let mut whole = TextSequence::new();
+        whole.apply_diff(vec![insert(&actor, 0, 1, &long)]);
+        let mut pieces = TextSequence::new();
+        pieces.apply_diff(vec![
+            insert(&actor, 0, 1, &long[..10]),
+            insert(&actor, 10, 11, &long[10..]),
+        ]);
+        assert_ne!(
+            whole.root.entries().next().unwrap().len(),
+            pieces.root.entries().next().unwrap().len()
+        );
+        assert_eq!(whole, pieces);
+        pieces.apply_diff(vec![DiffEdit::Remove { index: 0, count: 1 }]);
+        assert_ne!(whole, pieces);
+    }
+
+    /// The depth of `node`, checking that all its leaves are at that depth and the lengths it
+    /// caches are right
+    fn check_node(node: &Node) -> usize {
+        match node {
+            Node::Leaf(_) => 0,
+            Node::Internal(children) => {
+                assert!(!children.is_empty());
+                let depths: Vec<usize> = children
+                    .iter()
+                    .map(|child| {
+                        assert_eq!(child.len, child.node.len());
+                        check_node(&child.node)
+                    })
+                    .collect();
+                assert!(depths.iter().all(|depth| *depth == depths[0]));
+                depths[0] + 1
+            }
+        }
+    }
+
+    #[test]
+    fn random_edits_match_a_vector() {
+        let actor = ActorId::random();
+        let other = ActorId::random();
+        let mut sequence = TextSequence::new();
+        let mut expected: Vec<(OpId, String)> = Vec::new();
+        let mut counter = 1;
+        let mut seed: u64 = 7;
+        let mut random = |bound: usize| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 33) as usize % bound
+        };
+        sequence.apply_diff(vec![insert(&actor, 0, counter, &"a".repeat(5000))]);
+        expected.extend((0..5000).map(|i| (actor.op_id_at(counter + i), "a".to_string())));
+        counter += 5000;
+        for _ in 0..3000 {
+            let index = random(expected.len() + 1);
+            if random(3) == 0 && index < expected.len() {
+                let count = (random(5) + 1).min(expected.len() - index);
+                sequence.apply_diff(vec![DiffEdit::Remove {
+                    index: index as u64,
+                    count: count as u64,
+                }]);
+                expected.drain(index..index + count);
+            } else {
+                let author = if random(2) == 0 { &actor } else { &other };
+                let len = random(3) + 1;
+                sequence.apply_diff(vec![insert(
+                    author,
+                    index as u64,
+                    counter,
+                    &"b".repeat(len),
+                )]);
+                expected.splice(
+                    index..index,
+                    (0..len as u64).map(|i| (author.op_id_at(counter + i), "b".to_string())),
+                );
+                counter += len as u64;
+            }
+        }
+        assert_eq!(sequence.len(), expected.len());
+        assert_eq!(sequence.root.len(), expected.len());
+        assert!(check_node(&sequence.root) > 1);
+        for (i, (elem_id, value)) in expected.iter().enumerate() {
+            let (id, grapheme) = sequence.get(i).unwrap();
+            assert_eq!((&id, grapheme.as_str()), (elem_id, value.as_str()));
+        }
+
+        sequence.apply_diff(vec![DiffEdit::Remove {
+            index: 0,
+            count: expected.len() as u64,
+        }]);
+        assert!(sequence.is_empty());
+        assert_eq!(check_node(&sequence.root), 0);
+    }
+}
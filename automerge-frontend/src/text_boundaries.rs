@@ -0,0 +1,164 @@
+use std::ops::Range;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+/// The kind of segment [`crate::Frontend::segment_at`] and friends look for, as defined by
+/// [Unicode Standard Annex #29](https://www.unicode.org/reports/tr29/)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Granularity {
+    /// Words, runs of whitespace and punctuation are all segments of their own
+    Word,
+    /// Sentences, including the whitespace which follows them
+    Sentence,
+}
+
+impl Granularity {
+    /// Whether the segments on either side of `element` never depend on what is on its other
+    /// side. Segmenting starts and stops at these, rather than looking at the whole text.
+    fn is_separator(self, element: &str) -> bool {
+        match self {
+            Granularity::Word => element.chars().all(char::is_whitespace),
+            // there is always a sentence break after a paragraph separator
+            Granularity::Sentence => element
+                .chars()
+                .any(|c| matches!(c, '\n' | '\r' | '\u{85}' | '\u{2029}')),
+        }
+    }
+}
+
+/// The range of elements of the segment containing the element at `index`, or `None` if
+/// `index` is out of bounds.
+///
+/// Only the elements between the separators around `index` are looked at, so the cost depends
+/// on the length of the word or paragraph rather than of the whole text.
+pub(crate) fn segment_at<'a, F>(
+    element: F,
+    len: usize,
+    index: usize,
+    granularity: Granularity,
+) -> Option<Range<usize>>
+where
+    F: Fn(usize) -> &'a str,
+{
+    if index >= len {
+        return None;
+    }
+    let is_separator = |i: usize| granularity.is_separator(element(i));
+    let (start, end) = match granularity {
+        // there is a word break between whitespace and anything else, so runs of either can be
+        // segmented on their own
+        Granularity::Word => {
+            let class = is_separator(index);
+            let start = (0..index)
+                .rev()
+                .find(|i| is_separator(*i) != class)
+                .map_or(0, |i| i + 1);
+            let end = (index + 1..len)
+                .find(|i| is_separator(*i) != class)
+                .unwrap_or(len);
+            (start, end)
+        }
+        Granularity::Sentence => {
+            let start = (0..index)
+                .rev()
+                .find(|i| is_separator(*i))
+                .map_or(0, |i| i + 1);
+            let end = (index..len)
+                .find(|i| is_separator(*i))
+                .map_or(len, |i| i + 1);
+            (start, end)
+        }
+    };
+
+    let window: String = (start..end).map(&element).collect();
+    let mut offsets = Vec::with_capacity(end - start);
+    let mut offset = 0;
+    for i in start..end {
+        offsets.push(offset);
+        offset += element(i).len();
+    }
+    let target = offsets[index - start];
+    let segments: Vec<(usize, &str)> = match granularity {
+        Granularity::Word => window.split_word_bound_indices().collect(),
+        Granularity::Sentence => window.split_sentence_bound_indices().collect(),
+    };
+    let (segment_start, segment) = segments
+        .into_iter()
+        .take_while(|(segment_start, _)| *segment_start <= target)
+        .last()?;
+    let segment_end = segment_start + segment.len();
+    // a boundary in the middle of an element is rounded down to the start of that element
+    let element_at = |byte: usize| start + offsets.partition_point(|offset| *offset <= byte) - 1;
+    let first = element_at(segment_start);
+    let last = if segment_end >= window.len() {
+        end
+    } else {
+        element_at(segment_end)
+    };
+    Some(first..last.max(index + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(text: &str, index: usize, granularity: Granularity) -> Option<String> {
+        let elements: Vec<&str> = text.graphemes(true).collect();
+        let range = segment_at(|i| elements[i], elements.len(), index, granularity)?;
+        Some(elements[range].concat())
+    }
+
+    #[test]
+    fn finds_words() {
+        let text = "Hello, wide  world.";
+        assert_eq!(
+            segment(text, 0, Granularity::Word).as_deref(),
+            Some("Hello")
+        );
+        assert_eq!(
+            segment(text, 4, Granularity::Word).as_deref(),
+            Some("Hello")
+        );
+        assert_eq!(segment(text, 5, Granularity::Word).as_deref(), Some(","));
+        assert_eq!(segment(text, 6, Granularity::Word).as_deref(), Some(" "));
+        assert_eq!(segment(text, 9, Granularity::Word).as_deref(), Some("wide"));
+        assert_eq!(segment(text, 11, Granularity::Word).as_deref(), Some("  "));
+        assert_eq!(segment(text, 18, Granularity::Word).as_deref(), Some("."));
+        assert_eq!(segment(text, 19, Granularity::Word).as_deref(), None);
+    }
+
+    #[test]
+    fn finds_sentences() {
+        let text = "One. Two? Three\nFour";
+        assert_eq!(
+            segment(text, 2, Granularity::Sentence).as_deref(),
+            Some("One. ")
+        );
+        assert_eq!(
+            segment(text, 4, Granularity::Sentence).as_deref(),
+            Some("One. ")
+        );
+        assert_eq!(
+            segment(text, 5, Granularity::Sentence).as_deref(),
+            Some("Two? ")
+        );
+        assert_eq!(
+            segment(text, 15, Granularity::Sentence).as_deref(),
+            Some("Three\n")
+        );
+        assert_eq!(
+            segment(text, 16, Granularity::Sentence).as_deref(),
+            Some("Four")
+        );
+    }
+
+    #[test]
+    fn words_of_clusters() {
+        let text = "ne\u{301}e 🇬🇧";
+        assert_eq!(
+            segment(text, 1, Granularity::Word).as_deref(),
+            Some("ne\u{301}e")
+        );
+        assert_eq!(segment(text, 4, Granularity::Word).as_deref(), Some("🇬🇧"));
+    }
+}
@@ -0,0 +1,109 @@
+use smol_str::SmolStr;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// What the indices passed to [`crate::LocalChange::splice_text`] and the lengths returned by
+/// [`crate::Frontend::length_at`] count in a text object.
+///
+/// Text inserted by this crate always has one grapheme cluster per element, so the two modes
+/// agree. Other implementations split text differently, e.g. into unicode scalar values, in which
+/// case an emoji or a character with combining marks spans several elements. With `Graphemes`
+/// indices count what a user sees as a single character regardless of how the text was
+/// inserted. Patches are unaffected, the indices in a `DiffEdit` always count elements.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TextIndexing {
+    /// Indices count the elements of the text object
+    #[default]
+    Elements,
+    /// Indices count extended grapheme clusters
+    Graphemes,
+}
+
+/// The number of grapheme clusters in the text made up of `elements`
+pub(crate) fn grapheme_count<'a, I>(elements: I) -> usize
+where
+    I: IntoIterator<Item = &'a SmolStr>,
+{
+    elements
+        .into_iter()
+        .map(SmolStr::as_str)
+        .collect::<String>()
+        .graphemes(true)
+        .count()
+}
+
+/// The range of elements covering the `count` grapheme clusters starting at grapheme `start`,
+/// or `None` if the text isn't that long.
+///
+/// A grapheme boundary in the middle of an element is rounded down to the start of that element.
+/// Elements are only taken from `elements` until the end of the range is known.
+pub(crate) fn element_range<'a, I>(
+    elements: I,
+    start: usize,
+    count: usize,
+) -> Option<(usize, usize)>
+where
+    I: IntoIterator<Item = &'a SmolStr>,
+{
+    let mut elements = elements.into_iter();
+    let mut text = String::new();
+    // the byte offset in `text` of the end of each element
+    let mut ends = Vec::new();
+    let mut chunk = 64;
+    let (start_byte, end_byte) = loop {
+        let before = ends.len();
+        for element in elements.by_ref().take(chunk) {
+            text.push_str(element);
+            ends.push(text.len());
+        }
+        let exhausted = ends.len() - before < chunk;
+        chunk *= 2;
+        // where a cluster starts doesn't depend on what follows it, but the end of the text is
+        // only a boundary once there is nothing more to come
+        let mut boundaries: Vec<usize> = text
+            .grapheme_indices(true)
+            .map(|(offset, _)| offset)
+            .collect();
+        if exhausted {
+            boundaries.push(text.len());
+        }
+        if let Some(end_byte) = boundaries.get(start + count) {
+            break (boundaries[start], *end_byte);
+        }
+        if exhausted {
+            return None;
+        }
+    };
+    let element_at = |byte: usize| ends.partition_point(|end| *end <= byte);
+    Some((element_at(start_byte), element_at(end_byte)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_graphemes_to_elements() {
+        // "é" as "e" and a combining accent, then a flag split into its two regional indicators
+        let elements: Vec<SmolStr> = vec!["a", "e", "\u{301}", "🇬", "🇧", "b"]
+            .into_iter()
+            .map(SmolStr::from)
+            .collect();
+        assert_eq!(grapheme_count(&elements), 4);
+        assert_eq!(element_range(&elements, 0, 1), Some((0, 1)));
+        assert_eq!(element_range(&elements, 1, 1), Some((1, 3)));
+        assert_eq!(element_range(&elements, 2, 2), Some((3, 6)));
+        assert_eq!(element_range(&elements, 4, 0), Some((6, 6)));
+        assert_eq!(element_range(&elements, 3, 2), None);
+        assert_eq!(element_range(&elements, 5, 0), None);
+    }
+
+    #[test]
+    fn maps_graphemes_beyond_the_first_chunk() {
+        let mut elements = vec![SmolStr::from("a"); 200];
+        elements.extend(["e", "\u{301}"].iter().copied().map(SmolStr::from));
+        assert_eq!(element_range(&elements, 150, 1), Some((150, 151)));
+        assert_eq!(element_range(&elements, 199, 2), Some((199, 202)));
+        assert_eq!(element_range(&elements, 201, 0), Some((202, 202)));
+        assert_eq!(element_range(&elements, 201, 1), None);
+    }
+}
@@ -0,0 +1,186 @@
+//! Recoverable deletion of parts of a document.
+//!
+//! Deleting something from an automerge document can't be undone by a later change, the best a
+//! later change can do is write the old value back. [`soft_delete`] does that bookkeeping up
+//! front: it copies the value into a trash map under the reserved [`TRASH_KEY`] in the root, along
+//! with where it came from and when it was deleted, and then deletes the original. [`restore`]
+//! writes it back again and [`purge`] empties the trash according to an [`ExpiryPolicy`].
+//!
+//! Copies in the trash are new objects, so concurrent edits to the original made by other actors
+//! after the soft delete are lost, just as they would be with a real delete.
+#[cfg(feature = "random")]
+use std::collections::HashMap;
+
+use smol_str::SmolStr;
+
+use crate::{
+    error::InvalidChangeRequest, path::PathElement, LocalChange, MutableDocument, Path, Primitive,
+    Value,
+};
+
+/// The key in the root map which the trash lives under
+pub const TRASH_KEY: &str = "_trash";
+
+const PATH_KEY: &str = "path";
+const DELETED_AT_KEY: &str = "deletedAt";
+const VALUE_KEY: &str = "value";
+
+/// Something which has been soft deleted
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrashEntry {
+    pub id: SmolStr,
+    /// Where the value was deleted from
+    pub path: Path,
+    /// When the value was deleted, in milliseconds since the epoch
+    pub deleted_at: i64,
+    pub value: Value,
+}
+
+/// When entries in the trash are removed for good by [`purge`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExpiryPolicy {
+    /// How long entries are kept for, in milliseconds
+    pub max_age: i64,
+}
+
+impl ExpiryPolicy {
+    pub fn is_expired(&self, entry: &TrashEntry, now: i64) -> bool {
+        now.saturating_sub(entry.deleted_at) >= self.max_age
+    }
+}
+
+fn trash_path() -> Path {
+    Path::root().key(TRASH_KEY)
+}
+
+/// Move the value at `path` to the trash, returning the ID of the trash entry
+#[cfg(feature = "random")]
+pub fn soft_delete(
+    doc: &mut dyn MutableDocument,
+    path: &Path,
+    now: i64,
+) -> Result<SmolStr, InvalidChangeRequest> {
+    if path.is_root() {
+        return Err(InvalidChangeRequest::CannotDeleteRootObject);
+    }
+    let value = doc
+        .value_at_path(path)
+        .ok_or_else(|| InvalidChangeRequest::NoSuchPathError { path: path.clone() })?;
+    if doc.value_at_path(&trash_path()).is_none() {
+        doc.add_change(LocalChange::set(trash_path(), Value::Map(HashMap::new())))?;
+    }
+    let encoded_path = path
+        .clone()
+        .elements()
+        .into_iter()
+        .map(|element| match element {
+            PathElement::Key(k) => Value::Primitive(Primitive::Str(k)),
+            PathElement::Index(i) => Value::Primitive(Primitive::Uint(u64::from(i))),
+        })
+        .collect();
+    let id: SmolStr = uuid::Uuid::new_v4().to_simple().to_string().into();
+    let mut entry = HashMap::new();
+    entry.insert(PATH_KEY.into(), Value::List(encoded_path));
+    entry.insert(
+        DELETED_AT_KEY.into(),
+        Value::Primitive(Primitive::Timestamp(now)),
+    );
+    entry.insert(VALUE_KEY.into(), value);
+    doc.add_change(LocalChange::set(
+        trash_path().key(id.clone()),
+        Value::Map(entry),
+    ))?;
+    doc.add_change(LocalChange::delete(path.clone()))?;
+    Ok(id)
+}
+
+/// Put the value in the trash entry `id` back where it was deleted from, returning that path.
+///
+/// A value deleted from a list is inserted at its old index, or at the end of the list if the
+/// list has become shorter since. A value deleted from a map overwrites whatever has been put
+/// under its key since.
+pub fn restore(doc: &mut dyn MutableDocument, id: &str) -> Result<Path, InvalidChangeRequest> {
+    let entry = doc
+        .value_at_path(&trash_path().key(id))
+        .and_then(|value| parse_entry(id.into(), value))
+        .ok_or_else(|| InvalidChangeRequest::NoSuchTrashEntry { id: id.into() })?;
+    let path = match entry.path.name() {
+        Some(PathElement::Index(index)) => {
+            let parent = entry.path.parent();
+            let length = match doc.value_at_path(&parent) {
+                Some(Value::List(values)) => values.len(),
+                Some(Value::Text(graphemes)) => graphemes.len(),
+                _ => return Err(InvalidChangeRequest::NoSuchPathError { path: entry.path }),
+            };
+            let path = parent.index((*index).min(length as u32));
+            doc.add_change(LocalChange::insert(path.clone(), entry.value))?;
+            path
+        }
+        _ => {
+            doc.add_change(LocalChange::set(entry.path.clone(), entry.value))?;
+            entry.path
+        }
+    };
+    doc.add_change(LocalChange::delete(trash_path().key(id)))?;
+    Ok(path)
+}
+
+/// Remove the entries `policy` says have expired from the trash for good, returning their IDs
+pub fn purge(
+    doc: &mut dyn MutableDocument,
+    policy: &ExpiryPolicy,
+    now: i64,
+) -> Result<Vec<SmolStr>, InvalidChangeRequest> {
+    let mut purged: Vec<SmolStr> = entries(doc.value_at_path(&trash_path()).as_ref())
+        .into_iter()
+        .filter(|entry| policy.is_expired(entry, now))
+        .map(|entry| entry.id)
+        .collect();
+    purged.sort();
+    for id in &purged {
+        doc.add_change(LocalChange::delete(trash_path().key(id.clone())))?;
+    }
+    Ok(purged)
+}
+
+/// The entries in `trash`, the value under [`TRASH_KEY`], oldest first
+pub(crate) fn entries(trash: Option<&Value>) -> Vec<TrashEntry> {
+    let mut entries: Vec<TrashEntry> = match trash {
+        Some(Value::Map(items)) => items
+            .iter()
+            .filter_map(|(id, value)| parse_entry(id.clone(), value.clone()))
+            .collect(),
+        _ => Vec::new(),
+    };
+    entries.sort_by(|a, b| (a.deleted_at, &a.id).cmp(&(b.deleted_at, &b.id)));
+    entries
+}
+
+fn parse_entry(id: SmolStr, value: Value) -> Option<TrashEntry> {
+    let mut items = match value {
+        Value::Map(items) => items,
+        _ => return None,
+    };
+    let deleted_at = match items.get(DELETED_AT_KEY)? {
+        Value::Primitive(Primitive::Timestamp(t)) => *t,
+        _ => return None,
+    };
+    let path = match items.get(PATH_KEY)? {
+        Value::List(elements) => {
+            elements
+                .iter()
+                .try_fold(Path::root(), |path, element| match element {
+                    Value::Primitive(Primitive::Str(k)) => Some(path.key(k.clone())),
+                    Value::Primitive(Primitive::Uint(i)) => Some(path.index(*i as u32)),
+                    _ => None,
+                })?
+        }
+        _ => return None,
+    };
+    Some(TrashEntry {
+        id,
+        path,
+        deleted_at,
+        value: items.remove(VALUE_KEY)?,
+    })
+}
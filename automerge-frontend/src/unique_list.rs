@@ -0,0 +1,106 @@
+use crate::{error::InvalidChangeRequest, Frontend, LocalChange, MutableDocument, Path, Value};
+
+/// A list which is treated as a set, e.g. a list of tags or of the members of a group.
+///
+/// Automerge lists allow duplicates, so if two actors concurrently add the same tag it appears
+/// twice once their changes are merged. Reading through a `UniqueList` hides the later copies and
+/// [`UniqueList::dedup`] removes them from the document. Nothing stops other code from inserting
+/// duplicates into the underlying list, this is only a way of looking at it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UniqueList {
+    path: Path,
+}
+
+impl UniqueList {
+    /// A unique list view of the list at `path`
+    pub fn new(path: Path) -> Self {
+        UniqueList { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The values in the list, keeping only the first occurrence of each. Returns `None` if there
+    /// is no list at `path`.
+    pub fn values(&self, frontend: &Frontend) -> Option<Vec<Value>> {
+        match frontend.get_value(&self.path)? {
+            Value::List(values) => Some(dedup(values)),
+            _ => None,
+        }
+    }
+
+    pub fn contains(&self, frontend: &Frontend, value: &Value) -> bool {
+        matches!(frontend.get_value(&self.path), Some(Value::List(values)) if values.contains(value))
+    }
+
+    /// Append `value` to the list unless it is already there. Returns whether it was added.
+    pub fn insert(
+        &self,
+        doc: &mut dyn MutableDocument,
+        value: Value,
+    ) -> Result<bool, InvalidChangeRequest> {
+        let values = self.list(doc)?;
+        if values.contains(&value) {
+            return Ok(false);
+        }
+        doc.add_change(LocalChange::insert(
+            self.path.clone().index(values.len() as u32),
+            value,
+        ))?;
+        Ok(true)
+    }
+
+    /// Remove every occurrence of `value` from the list. Returns whether there were any.
+    pub fn remove(
+        &self,
+        doc: &mut dyn MutableDocument,
+        value: &Value,
+    ) -> Result<bool, InvalidChangeRequest> {
+        let found = self.list(doc)?.contains(value);
+        if found {
+            doc.retain_in_list(&self.path, &mut |v| v != value)?;
+        }
+        Ok(found)
+    }
+
+    /// Delete all but the first occurrence of each value in the list, returning how many
+    /// elements were deleted
+    pub fn dedup(&self, doc: &mut dyn MutableDocument) -> Result<usize, InvalidChangeRequest> {
+        let before = self.list(doc)?.len();
+        let mut seen = Vec::new();
+        doc.retain_in_list(&self.path, &mut |value| {
+            if seen.contains(value) {
+                false
+            } else {
+                seen.push(value.clone());
+                true
+            }
+        })?;
+        Ok(before - seen.len())
+    }
+
+    fn list(&self, doc: &dyn MutableDocument) -> Result<Vec<Value>, InvalidChangeRequest> {
+        match doc.value_at_path(&self.path) {
+            Some(Value::List(values)) => Ok(values),
+            Some(_) => Err(InvalidChangeRequest::InsertForNonSequenceObject {
+                path: self.path.clone(),
+            }),
+            None => Err(InvalidChangeRequest::NoSuchPathError {
+                path: self.path.clone(),
+            }),
+        }
+    }
+}
+
+/// `values` with only the first occurrence of each value. Values aren't hashable (they can
+/// contain floats), but the lists this is for are short.
+fn dedup(values: Vec<Value>) -> Vec<Value> {
+    let mut unique: Vec<Value> = Vec::with_capacity(values.len());
+    for value in values {
+        if !unique.contains(&value) {
+            unique.push(value);
+        }
+    }
+    unique
+}
@@ -1,24 +1,30 @@
 mod conflicts;
 mod cursor;
 mod primitive;
+#[cfg(feature = "im")]
+mod shared;
 
-use std::{borrow::Cow, collections::HashMap};
+use std::{borrow::Cow, collections::HashMap, fmt};
 
-use amp::SortedVec;
-use automerge_protocol as amp;
+#[cfg(all(feature = "std", feature = "random"))]
+use automerge_protocol::{self as amp, SortedVec};
 pub use conflicts::Conflicts;
 pub use cursor::Cursor;
 pub use primitive::Primitive;
 use serde::Serialize;
+#[cfg(feature = "im")]
+pub use shared::SharedValue;
 use smol_str::SmolStr;
+use strum::EnumDiscriminants;
 
 use crate::path::PathElement;
 
 /// A composite value, composing maps, tables, lists, text and primitives.
 ///
 /// A `Value` is the general container type for objects in the document tree.
-#[derive(Serialize, Clone, Debug, PartialEq)]
+#[derive(Serialize, Clone, Debug, PartialEq, EnumDiscriminants)]
 #[cfg_attr(feature = "derive-arbitrary", derive(arbitrary::Arbitrary))]
+#[strum_discriminants(name(ValueKind), derive(Hash))]
 #[serde(untagged)]
 pub enum Value {
     /// A mapping from string keys to values.
@@ -34,6 +40,10 @@ pub enum Value {
 }
 
 impl Value {
+    pub fn kind(&self) -> ValueKind {
+        ValueKind::from(self)
+    }
+
     /// Return whether the [`Value`] is a map.
     pub fn is_map(&self) -> bool {
         matches!(self, Self::Map(_))
@@ -100,6 +110,7 @@ impl Value {
     }
 
     /// Convert a JSON object into a [`Value`].
+    #[cfg(feature = "json")]
     pub fn from_json(json: &serde_json::Value) -> Value {
         match json {
             serde_json::Value::Object(kvs) => {
@@ -120,6 +131,7 @@ impl Value {
     }
 
     /// Convert this [`Value`] into a JSON object.
+    #[cfg(feature = "json")]
     pub fn to_json(&self) -> serde_json::Value {
         match self {
             Value::Map(map) => {
@@ -198,6 +210,12 @@ impl Value {
     }
 }
 
+impl fmt::Display for ValueKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
 impl From<Cursor> for Value {
     fn from(c: Cursor) -> Self {
         Value::Primitive(Primitive::Cursor(c))
@@ -271,6 +289,7 @@ impl AsRef<Value> for Value {
 ///
 ///
 /// Returns a vector of the op requests which will create this value
+#[cfg(all(feature = "std", feature = "random"))]
 pub(crate) fn value_to_op_requests(
     actor: &amp::ActorId,
     start_op: u64,
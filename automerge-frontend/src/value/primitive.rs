@@ -99,6 +99,16 @@ impl Primitive {
         }
     }
 
+    /// Return whether the [`Primitive`] is a counter which has hit `i64::MAX` or `i64::MIN`.
+    ///
+    /// Counters which overflow stick at the limit rather than wrapping around, and the backend
+    /// keeps the exact total so that increments in the other direction bring the counter back
+    /// in range. A frontend only sees the clamped value, so a counter which is exactly at a
+    /// limit counts as saturated.
+    pub fn is_saturated_counter(&self) -> bool {
+        matches!(self, Self::Counter(i64::MAX) | Self::Counter(i64::MIN))
+    }
+
     /// Return whether the [`Primitive`] is a timestamp.
     pub fn is_timestamp(&self) -> bool {
         matches!(self, Self::Timestamp(_))
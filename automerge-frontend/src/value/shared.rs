@@ -0,0 +1,110 @@
+use smol_str::SmolStr;
+
+use super::{Primitive, Value, ValueKind};
+
+/// A [`Value`] whose maps, lists and text are persistent data structures from `im-rc`, so that
+/// clones share their structure with the original and cost O(1) however big the document is.
+///
+/// This is handy for keeping snapshots of a document around, for instance to render from while
+/// the frontend moves on, or to diff against later. Like the rest of the frontend it uses `Rc`,
+/// so a `SharedValue` can't be sent to another thread; convert it into a [`Value`] for that.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SharedValue {
+    Map(im_rc::HashMap<SmolStr, SharedValue>),
+    Table(im_rc::HashMap<SmolStr, SharedValue>),
+    List(im_rc::Vector<SharedValue>),
+    Text(im_rc::Vector<SmolStr>),
+    Primitive(Primitive),
+}
+
+impl SharedValue {
+    pub fn kind(&self) -> ValueKind {
+        match self {
+            Self::Map(_) => ValueKind::Map,
+            Self::Table(_) => ValueKind::Table,
+            Self::List(_) => ValueKind::List,
+            Self::Text(_) => ValueKind::Text,
+            Self::Primitive(_) => ValueKind::Primitive,
+        }
+    }
+
+    pub fn map(&self) -> Option<&im_rc::HashMap<SmolStr, SharedValue>> {
+        match self {
+            Self::Map(m) => Some(m),
+            _ => None,
+        }
+    }
+
+    pub fn table(&self) -> Option<&im_rc::HashMap<SmolStr, SharedValue>> {
+        match self {
+            Self::Table(m) => Some(m),
+            _ => None,
+        }
+    }
+
+    pub fn list(&self) -> Option<&im_rc::Vector<SharedValue>> {
+        match self {
+            Self::List(l) => Some(l),
+            _ => None,
+        }
+    }
+
+    pub fn text(&self) -> Option<&im_rc::Vector<SmolStr>> {
+        match self {
+            Self::Text(t) => Some(t),
+            _ => None,
+        }
+    }
+
+    pub fn primitive(&self) -> Option<&Primitive> {
+        match self {
+            Self::Primitive(p) => Some(p),
+            _ => None,
+        }
+    }
+
+    /// Copy this value into a plain [`Value`]
+    pub fn to_value(&self) -> Value {
+        Value::from(self)
+    }
+}
+
+impl From<&Value> for SharedValue {
+    fn from(value: &Value) -> Self {
+        match value {
+            Value::Map(m) => Self::Map(m.iter().map(|(k, v)| (k.clone(), v.into())).collect()),
+            Value::Table(m) => Self::Table(m.iter().map(|(k, v)| (k.clone(), v.into())).collect()),
+            Value::List(l) => Self::List(l.iter().map(Self::from).collect()),
+            Value::Text(t) => Self::Text(t.iter().cloned().collect()),
+            Value::Primitive(p) => Self::Primitive(p.clone()),
+        }
+    }
+}
+
+impl From<Value> for SharedValue {
+    fn from(value: Value) -> Self {
+        Self::from(&value)
+    }
+}
+
+impl From<&SharedValue> for Value {
+    fn from(value: &SharedValue) -> Self {
+        match value {
+            SharedValue::Map(m) => {
+                Value::Map(m.iter().map(|(k, v)| (k.clone(), v.into())).collect())
+            }
+            SharedValue::Table(m) => {
+                Value::Table(m.iter().map(|(k, v)| (k.clone(), v.into())).collect())
+            }
+            SharedValue::List(l) => Value::List(l.iter().map(Value::from).collect()),
+            SharedValue::Text(t) => Value::Text(t.iter().cloned().collect()),
+            SharedValue::Primitive(p) => Value::Primitive(p.clone()),
+        }
+    }
+}
+
+impl From<SharedValue> for Value {
+    fn from(value: SharedValue) -> Self {
+        Self::from(&value)
+    }
+}
@@ -29,12 +29,12 @@ impl<'a> ListRef<'a> {
         self.stl
             .elements
             .iter()
-            .map(|mv| ValueRef::new(mv.default_statetree_value()))
+            .map(|(_, mv)| ValueRef::new(mv.default_statetree_value()))
     }
 
     pub fn value(&self) -> Value {
         let mut v = Vec::new();
-        for e in self.stl.elements.iter() {
+        for (_, e) in &self.stl.elements {
             v.push(e.default_value())
         }
         Value::List(v)
@@ -21,21 +21,14 @@ impl<'a> TextRef<'a> {
     }
 
     pub fn get(&self, index: usize) -> Option<&SmolStr> {
-        self.stt
-            .graphemes
-            .get(index)
-            .map(|(_, mg)| mg.default_grapheme())
+        self.stt.graphemes.get(index).map(|(_, g)| g)
     }
 
     pub fn iter(&self) -> impl Iterator<Item = &SmolStr> {
-        self.stt.graphemes.iter().map(|mg| mg.default_grapheme())
+        self.stt.graphemes.graphemes()
     }
 
     pub fn value(&self) -> Value {
-        let mut v = Vec::new();
-        for e in self.stt.graphemes.iter() {
-            v.push(e.default_grapheme().clone())
-        }
-        Value::Text(v)
+        Value::Text(self.stt.graphemes.graphemes().cloned().collect())
     }
 }
@@ -0,0 +1,89 @@
+use automerge_protocol::Patch;
+use tokio::sync::watch;
+
+use crate::{error::InvalidPatch, Frontend, Path, Value};
+
+/// Publishes the state of a [`Frontend`] into [`tokio::sync::watch`] channels.
+///
+/// Async code which only cares about the latest version of a document (a configuration document
+/// say) can hold on to a receiver and await `changed()` instead of listening for patches. The
+/// root receiver always holds the whole document, receivers for subpaths hold the value at that
+/// path, or `None` if there isn't one. A receiver is only notified when its value has actually
+/// changed.
+///
+/// The frontend itself isn't `Send`, so the publisher lives next to it and the receivers are
+/// handed out to other tasks.
+#[derive(Debug)]
+pub struct WatchPublisher {
+    root: watch::Sender<Value>,
+    paths: Vec<(Path, watch::Sender<Option<Value>>)>,
+}
+
+impl WatchPublisher {
+    /// Create a publisher for `frontend` along with a receiver for the whole document
+    pub fn new(frontend: &Frontend) -> (Self, watch::Receiver<Value>) {
+        let (root, receiver) = watch::channel(frontend.get_value(&Path::root()).unwrap());
+        (
+            WatchPublisher {
+                root,
+                paths: Vec::new(),
+            },
+            receiver,
+        )
+    }
+
+    /// A receiver for the root of the document
+    pub fn subscribe(&self) -> watch::Receiver<Value> {
+        self.root.subscribe()
+    }
+
+    /// A receiver for the value at `path`. Receivers for the same path share a channel.
+    pub fn subscribe_path(
+        &mut self,
+        frontend: &Frontend,
+        path: Path,
+    ) -> watch::Receiver<Option<Value>> {
+        if let Some((_, sender)) = self.paths.iter().find(|(p, _)| *p == path) {
+            return sender.subscribe();
+        }
+        let (sender, receiver) = watch::channel(frontend.get_value(&path));
+        self.paths.push((path, sender));
+        receiver
+    }
+
+    /// Apply `patch` to `frontend` and publish the new state
+    pub fn apply_patch(
+        &mut self,
+        frontend: &mut Frontend,
+        patch: Patch,
+    ) -> Result<(), InvalidPatch> {
+        frontend.apply_patch(patch)?;
+        self.publish(frontend);
+        Ok(())
+    }
+
+    /// Send the current state of `frontend` to any receivers whose value has changed. This needs
+    /// calling after local changes, which don't go through [`Self::apply_patch`].
+    ///
+    /// Paths which no longer have any receivers are dropped.
+    pub fn publish(&mut self, frontend: &Frontend) {
+        // `get_value` on the root never fails
+        let root = frontend.get_value(&Path::root()).unwrap();
+        self.root
+            .send_if_modified(|current| replace_if_changed(current, root));
+        self.paths.retain(|(_, sender)| !sender.is_closed());
+        for (path, sender) in &self.paths {
+            let value = frontend.get_value(path);
+            sender.send_if_modified(|current| replace_if_changed(current, value));
+        }
+    }
+}
+
+fn replace_if_changed<T: PartialEq>(current: &mut T, new: T) -> bool {
+    if *current == new {
+        false
+    } else {
+        *current = new;
+        true
+    }
+}
@@ -1,9 +1,9 @@
-use std::{collections::HashMap, convert::TryInto};
+use std::{collections::BTreeMap, convert::TryInto};
 
 use amp::RootDiff;
-use automerge_frontend::{Frontend, Path, Primitive, Value};
+use automerge_frontend::{apply_diff, DocumentState, Frontend, Path, Primitive, Value};
 use automerge_protocol as amp;
-use maplit::hashmap;
+use maplit::{btreemap, hashmap};
 use unicode_segmentation::UnicodeSegmentation;
 
 #[test]
@@ -19,8 +19,8 @@ fn set_object_root_properties() {
             actor.clone() => 1,
         },
         diffs: RootDiff {
-            props: hashmap! {
-                "bird".into() => hashmap!{
+            props: btreemap! {
+                "bird".into() => btreemap!{
                     actor.op_id_at(1) => "magpie".into()
                 }
             },
@@ -47,8 +47,8 @@ fn set_bytes_value() {
             actor.clone() => 1,
         },
         diffs: amp::RootDiff {
-            props: hashmap! {
-                "bird".into() => hashmap!{
+            props: btreemap! {
+                "bird".into() => btreemap!{
                     actor.op_id_at(1) => amp::Diff::Value(amp::ScalarValue::Bytes(vec![1, 2, 3])),
                 }
             },
@@ -81,8 +81,8 @@ fn reveal_conflicts_on_root_properties() {
         },
         deps: Vec::new(),
         diffs: RootDiff {
-            props: hashmap! {
-                "favouriteBird".into() => hashmap!{
+            props: btreemap! {
+                "favouriteBird".into() => btreemap!{
                     actor1.op_id_at(1) => amp::Diff::Value("robin".into()),
                     actor2.op_id_at(1) => amp::Diff::Value("wagtail".into()),
                 }
@@ -121,12 +121,12 @@ fn create_nested_maps() {
             actor.clone() => 1,
         },
         diffs: RootDiff {
-            props: hashmap! {
-                "birds".into() => hashmap!{
+            props: btreemap! {
+                "birds".into() => btreemap!{
                     actor.op_id_at(1) => amp::Diff::Map(amp::MapDiff{
                         object_id: actor.op_id_at(2).into(),
-                        props: hashmap!{
-                            "wrens".into() => hashmap!{
+                        props: btreemap!{
+                            "wrens".into() => btreemap!{
                                 actor.op_id_at(2) => amp::Diff::Value(amp::ScalarValue::Int(3))
                             }
                         }
@@ -156,12 +156,12 @@ fn apply_updates_inside_nested_maps() {
             actor.clone() => 1,
         },
         diffs: RootDiff {
-            props: hashmap! {
-                "birds".into() => hashmap!{
+            props: btreemap! {
+                "birds".into() => btreemap!{
                     actor.op_id_at(1) => amp::Diff::Map(amp::MapDiff{
                         object_id: actor.op_id_at(2).into(),
-                        props: hashmap!{
-                            "wrens".into() => hashmap!{
+                        props: btreemap!{
+                            "wrens".into() => btreemap!{
                                 actor.op_id_at(2) => amp::Diff::Value(amp::ScalarValue::Int(3))
                             }
                         }
@@ -185,12 +185,12 @@ fn apply_updates_inside_nested_maps() {
             actor.clone() => 2,
         },
         diffs: RootDiff {
-            props: hashmap! {
-                "birds".into() => hashmap!{
+            props: btreemap! {
+                "birds".into() => btreemap!{
                     actor.op_id_at(1) => amp::Diff::Map(amp::MapDiff{
                         object_id: birds_id,
-                        props: hashmap!{
-                            "sparrows".into() => hashmap!{
+                        props: btreemap!{
+                            "sparrows".into() => btreemap!{
                                 actor.op_id_at(3) => amp::Diff::Value(amp::ScalarValue::Int(15))
                             }
                         }
@@ -229,20 +229,20 @@ fn apply_updates_inside_map_conflicts() {
             actor2.clone() => 1,
         },
         diffs: RootDiff {
-            props: hashmap! {
-                "favouriteBirds".into() => hashmap!{
+            props: btreemap! {
+                "favouriteBirds".into() => btreemap!{
                     actor1.op_id_at(1) => amp::Diff::Map(amp::MapDiff{
                         object_id: actor1.op_id_at(1).into(),
-                        props: hashmap!{
-                            "blackbirds".into() => hashmap!{
+                        props: btreemap!{
+                            "blackbirds".into() => btreemap!{
                                 actor1.op_id_at(2) => amp::Diff::Value(amp::ScalarValue::Int(1)),
                             }
                         },
                     }),
                     actor2.op_id_at(1) => amp::Diff::Map(amp::MapDiff{
                         object_id: actor2.op_id_at(1).into(),
-                        props: hashmap!{
-                            "wrens".into() => hashmap!{
+                        props: btreemap!{
+                            "wrens".into() => btreemap!{
                                 actor2.op_id_at(2) => amp::Diff::Value(amp::ScalarValue::Int(3)),
                             }
                         },
@@ -280,19 +280,19 @@ fn apply_updates_inside_map_conflicts() {
             actor2.clone() => 1,
         },
         diffs: RootDiff {
-            props: hashmap! {
-                "favouriteBirds".into() => hashmap!{
+            props: btreemap! {
+                "favouriteBirds".into() => btreemap!{
                     actor1.op_id_at(1) => amp::Diff::Map(amp::MapDiff{
                         object_id: actor1.op_id_at(1).into(),
-                        props: hashmap!{
-                            "blackbirds".into() => hashmap!{
+                        props: btreemap!{
+                            "blackbirds".into() => btreemap!{
                                 actor1.op_id_at(3) => amp::Diff::Value(amp::ScalarValue::Int(2)),
                             }
                         },
                     }),
                     actor2.op_id_at(1) => amp::Diff::Map(amp::MapDiff{
                         object_id: actor2.op_id_at(1).into(),
-                        props: HashMap::new(),
+                        props: BTreeMap::new(),
                     })
                 }
             },
@@ -331,11 +331,11 @@ fn delete_keys_in_maps() {
             actor.clone() => 1,
         },
         diffs: RootDiff {
-            props: hashmap! {
-                "magpies".into() => hashmap!{
+            props: btreemap! {
+                "magpies".into() => btreemap!{
                     actor.op_id_at(1) => amp::Diff::Value(amp::ScalarValue::Int(2))
                 },
-                "sparrows".into() => hashmap!{
+                "sparrows".into() => btreemap!{
                     actor.op_id_at(2) => amp::Diff::Value(amp::ScalarValue::Int(15))
                 }
             },
@@ -359,8 +359,8 @@ fn delete_keys_in_maps() {
             actor => 2,
         },
         diffs: RootDiff {
-            props: hashmap! {
-                "magpies".into() => hashmap!{}
+            props: btreemap! {
+                "magpies".into() => btreemap!{}
             },
         },
     };
@@ -386,8 +386,8 @@ fn create_lists() {
             actor.clone() => 2,
         },
         diffs: RootDiff {
-            props: hashmap! {
-                "birds".into() => hashmap!{
+            props: btreemap! {
+                "birds".into() => btreemap!{
                     actor.op_id_at(1) => amp::Diff::List(amp::ListDiff{
                         object_id: actor.op_id_at(1).into(),
                         edits: vec![amp::DiffEdit::SingleElementInsert {
@@ -423,8 +423,8 @@ fn apply_updates_inside_lists() {
             actor.clone() => 1,
         },
         diffs: RootDiff {
-            props: hashmap! {
-                "birds".into() => hashmap!{
+            props: btreemap! {
+                "birds".into() => btreemap!{
                     actor.op_id_at(1) => amp::Diff::List(amp::ListDiff{
                         object_id: actor.op_id_at(1).into(),
                         edits: vec![amp::DiffEdit::SingleElementInsert {
@@ -450,8 +450,8 @@ fn apply_updates_inside_lists() {
             actor.clone() => 2,
         },
         diffs: RootDiff {
-            props: hashmap! {
-                "birds".into() => hashmap!{
+            props: btreemap! {
+                "birds".into() => btreemap!{
                     actor.op_id_at(1) => amp::Diff::List(amp::ListDiff{
                         object_id: actor.op_id_at(1).into(),
                         edits: vec![amp::DiffEdit::Update{
@@ -484,8 +484,8 @@ fn apply_multi_insert_updates_inside_lists() {
             actor.clone() => 1,
         },
         diffs: RootDiff {
-            props: hashmap! {
-                "birds".into() => hashmap!{
+            props: btreemap! {
+                "birds".into() => btreemap!{
                     actor.op_id_at(1) => amp::Diff::List(amp::ListDiff {
                         object_id: actor.op_id_at(1).into(),
                         edits: vec![amp::DiffEdit::MultiElementInsert(amp::MultiElementInsert {
@@ -534,8 +534,8 @@ fn apply_updates_inside_list_conflicts() {
             actor2.clone() => 1,
         },
         diffs: RootDiff {
-            props: hashmap! {
-                "birds".into() => hashmap!{
+            props: btreemap! {
+                "birds".into() => btreemap!{
                     other_actor.op_id_at(1) => amp::Diff::List(amp::ListDiff{
                         object_id: other_actor.op_id_at(1).into(),
                         edits: vec![
@@ -545,11 +545,11 @@ fn apply_updates_inside_list_conflicts() {
                                 op_id: actor1.op_id_at(2),
                                 value: amp::Diff::Map(amp::MapDiff{
                                     object_id: actor1.op_id_at(2).into(),
-                                    props: hashmap!{
-                                        "species".into() => hashmap!{
+                                    props: btreemap!{
+                                        "species".into() => btreemap!{
                                             actor1.op_id_at(3) => amp::Diff::Value("woodpecker".into()),
                                         },
-                                        "numSeen".into() => hashmap!{
+                                        "numSeen".into() => btreemap!{
                                             actor1.op_id_at(4) => amp::Diff::Value(amp::ScalarValue::Int(1)),
                                         },
                                     }
@@ -560,11 +560,11 @@ fn apply_updates_inside_list_conflicts() {
                                 op_id: actor2.op_id_at(2),
                                 value: amp::Diff::Map(amp::MapDiff{
                                     object_id: actor2.op_id_at(2).into(),
-                                    props: hashmap!{
-                                        "species".into() => hashmap!{
+                                    props: btreemap!{
+                                        "species".into() => btreemap!{
                                             actor2.op_id_at(3) => amp::Diff::Value("lapwing".into()),
                                         },
-                                        "numSeen".into() => hashmap!{
+                                        "numSeen".into() => btreemap!{
                                             actor2.op_id_at(4) => amp::Diff::Value(amp::ScalarValue::Int(2)),
                                         },
                                     }
@@ -614,8 +614,8 @@ fn apply_updates_inside_list_conflicts() {
             actor2.clone() => 1,
         },
         diffs: RootDiff {
-            props: hashmap! {
-                "birds".into() => hashmap!{
+            props: btreemap! {
+                "birds".into() => btreemap!{
                     other_actor.op_id_at(1) => amp::Diff::List(amp::ListDiff{
                         object_id: other_actor.op_id_at(1).into(),
                         edits: vec![
@@ -624,8 +624,8 @@ fn apply_updates_inside_list_conflicts() {
                                 op_id: actor1.op_id_at(2),
                                 value: amp::Diff::Map(amp::MapDiff{
                                     object_id: actor1.op_id_at(2).into(),
-                                    props: hashmap!{
-                                        "numSeen".into() => hashmap!{
+                                    props: btreemap!{
+                                        "numSeen".into() => btreemap!{
                                             actor1.op_id_at(5) => amp::Diff::Value(amp::ScalarValue::Int(2)),
                                         },
                                     }
@@ -636,7 +636,7 @@ fn apply_updates_inside_list_conflicts() {
                                 op_id: actor2.op_id_at(2),
                                 value: amp::Diff::Map(amp::MapDiff{
                                     object_id: actor2.op_id_at(2).into(),
-                                    props: HashMap::new(),
+                                    props: BTreeMap::new(),
                                 })
                             }
                         ],
@@ -682,8 +682,8 @@ fn apply_updates_inside_list_conflicts() {
             actor2.clone() => 1,
         },
         diffs: RootDiff {
-            props: hashmap! {
-                "birds".into() => hashmap!{
+            props: btreemap! {
+                "birds".into() => btreemap!{
                     other_actor.op_id_at(1) => amp::Diff::List(amp::ListDiff{
                         object_id: other_actor.op_id_at(1).into(),
                         edits: vec![
@@ -692,7 +692,7 @@ fn apply_updates_inside_list_conflicts() {
                                 op_id: actor2.op_id_at(2),
                                 value: amp::Diff::Map(amp::MapDiff{
                                     object_id: actor2.op_id_at(2).into(),
-                                    props: HashMap::new(),
+                                    props: BTreeMap::new(),
                                 })
                             }
                         ],
@@ -739,8 +739,8 @@ fn delete_list_elements() {
             actor.clone() => 1,
         },
         diffs: RootDiff {
-            props: hashmap! {
-                "birds".into() => hashmap!{
+            props: btreemap! {
+                "birds".into() => btreemap!{
                     actor.op_id_at(1) => amp::Diff::List(amp::ListDiff{
                         object_id: actor.op_id_at(1).into(),
                         edits: vec![
@@ -778,8 +778,8 @@ fn delete_list_elements() {
             actor.clone() => 2,
         },
         diffs: RootDiff {
-            props: hashmap! {
-                "birds".into() => hashmap!{
+            props: btreemap! {
+                "birds".into() => btreemap!{
                     actor.op_id_at(1) => amp::Diff::List(amp::ListDiff{
                         object_id: actor.op_id_at(1).into(),
                         edits: vec![amp::DiffEdit::Remove{ index: 0, count: 1 }],
@@ -806,18 +806,18 @@ fn apply_updates_at_different_levels_of_object_tree() {
         actor: None,
         deps: Vec::new(),
         diffs: RootDiff {
-            props: hashmap! {
-                "counts".into() => hashmap!{
+            props: btreemap! {
+                "counts".into() => btreemap!{
                     actor.op_id_at(1) => amp::Diff::Map(amp::MapDiff{
                         object_id: actor.op_id_at(1).into(),
-                        props: hashmap!{
-                            "magpie".into() => hashmap!{
+                        props: btreemap!{
+                            "magpie".into() => btreemap!{
                                 actor.op_id_at(2) => amp::Diff::Value(amp::ScalarValue::Int(2))
                             }
                         }
                     })
                 },
-                "details".into() => hashmap!{
+                "details".into() => btreemap!{
                     actor.op_id_at(3) => amp::Diff::List(amp::ListDiff{
                         object_id: actor.op_id_at(3).into(),
                         edits: vec![amp::DiffEdit::SingleElementInsert{
@@ -826,11 +826,11 @@ fn apply_updates_at_different_levels_of_object_tree() {
                             op_id: actor.op_id_at(4),
                             value:  amp::Diff::Map(amp::MapDiff{
                                 object_id: actor.op_id_at(4).into(),
-                                props: hashmap!{
-                                    "species".into() => hashmap!{
+                                props: btreemap!{
+                                    "species".into() => btreemap!{
                                         actor.op_id_at(5) => amp::Diff::Value("magpie".into())
                                     },
-                                    "family".into() => hashmap!{
+                                    "family".into() => btreemap!{
                                         actor.op_id_at(6) => amp::Diff::Value("Corvidae".into())
                                     }
                                 }
@@ -864,18 +864,18 @@ fn apply_updates_at_different_levels_of_object_tree() {
         actor: None,
         deps: Vec::new(),
         diffs: RootDiff {
-            props: hashmap! {
-                "counts".into() => hashmap!{
+            props: btreemap! {
+                "counts".into() => btreemap!{
                     actor.op_id_at(1) => amp::Diff::Map(amp::MapDiff{
                         object_id: actor.op_id_at(1).into(),
-                        props: hashmap!{
-                            "magpie".into() => hashmap!{
+                        props: btreemap!{
+                            "magpie".into() => btreemap!{
                                 actor.op_id_at(7) => amp::Diff::Value(amp::ScalarValue::Int(3))
                             }
                         }
                     })
                 },
-                "details".into() => hashmap!{
+                "details".into() => btreemap!{
                     actor.op_id_at(3) => amp::Diff::List(amp::ListDiff{
                         object_id: actor.op_id_at(3).into(),
                         edits: vec![amp::DiffEdit::Update{
@@ -883,8 +883,8 @@ fn apply_updates_at_different_levels_of_object_tree() {
                             op_id: actor.op_id_at(4),
                             value: amp::Diff::Map(amp::MapDiff{
                                 object_id: actor.op_id_at(4).into(),
-                                props: hashmap!{
-                                    "species".into() => hashmap!{
+                                props: btreemap!{
+                                    "species".into() => btreemap!{
                                         actor.op_id_at(8) => amp::Diff::Value("Eurasian magpie".into())
                                     },
                                 }
@@ -924,8 +924,8 @@ fn test_text_objects() {
             actor.clone() => 2,
         },
         diffs: RootDiff {
-            props: hashmap! {
-                "name".into() => hashmap!{
+            props: btreemap! {
+                "name".into() => btreemap!{
                     actor.op_id_at(1) => amp::Diff::Text(amp::TextDiff{
                         object_id: actor.op_id_at(1).into(),
                         edits: vec![
@@ -972,8 +972,8 @@ fn test_text_objects() {
             actor.clone() => 3,
         },
         diffs: RootDiff {
-            props: hashmap! {
-                "name".into() => hashmap!{
+            props: btreemap! {
+                "name".into() => btreemap!{
                     actor.op_id_at(1) => amp::Diff::Text(amp::TextDiff{
                         object_id: actor.op_id_at(1).into(),
                         edits: vec![
@@ -1011,8 +1011,8 @@ fn test_unchanged_diff_creates_empty_objects() {
         max_op: 1,
         pending_changes: 0,
         diffs: RootDiff {
-            props: hashmap! {
-                "text".into() => hashmap!{
+            props: btreemap! {
+                "text".into() => btreemap!{
                     "1@cfe5fefb771f4c15a716d488012cbf40".try_into().unwrap() =>  amp::Diff::Text(amp::TextDiff{
                         object_id: "1@cfe5fefb771f4c15a716d488012cbf40".try_into().unwrap(),
                         edits: Vec::new(),
@@ -1027,3 +1027,230 @@ fn test_unchanged_diff_creates_empty_objects() {
         &Value::Map(hashmap! {"text".into() => Value::Text(Vec::new())},),
     );
 }
+
+#[test]
+fn apply_compacted_text_inserts() {
+    let actor = amp::ActorId::random();
+    let characters = vec!["h", "é", "👍🏽", "y"];
+    let mut patch = amp::Patch {
+        actor: None,
+        seq: None,
+        max_op: 5,
+        pending_changes: 0,
+        deps: Vec::new(),
+        clock: hashmap! {
+            actor.clone() => 1,
+        },
+        diffs: RootDiff {
+            props: btreemap! {
+                "text".into() => btreemap!{
+                    actor.op_id_at(1) => amp::Diff::Text(amp::TextDiff{
+                        object_id: actor.op_id_at(1).into(),
+                        edits: vec![amp::DiffEdit::MultiElementInsert(amp::MultiElementInsert {
+                            index: 0,
+                            elem_id: actor.op_id_at(2).into(),
+                            values: characters
+                                .iter()
+                                .map(|c| amp::ScalarValue::Str((*c).into()))
+                                .collect::<Vec<_>>()
+                                .try_into()
+                                .unwrap(),
+                        })],
+                    })
+                }
+            },
+        },
+    };
+    patch.compact_text_edits();
+
+    let edits = match &patch.diffs.props["text"][&actor.op_id_at(1)] {
+        amp::Diff::Text(amp::TextDiff { edits, .. }) => edits.clone(),
+        other => panic!("expected a text diff, got {:?}", other),
+    };
+    assert_eq!(
+        edits,
+        vec![amp::DiffEdit::TextInsert(amp::TextInsert {
+            index: 0,
+            elem_id: actor.op_id_at(2).into(),
+            text: "hé👍🏽y".to_string(),
+            lengths: vec![1, 1, 2, 1],
+        })]
+    );
+
+    let json = serde_json::to_string(&patch).unwrap();
+    let patch: amp::Patch = serde_json::from_str(&json).unwrap();
+
+    let mut frontend = Frontend::new();
+    frontend.apply_patch(patch).unwrap();
+    assert_eq!(
+        frontend.state(),
+        &Into::<Value>::into(hashmap! {
+            "text" => Value::Text(characters.iter().map(|c| (*c).into()).collect())
+        })
+    );
+}
+
+#[test]
+fn malformed_text_insert_is_rejected() {
+    let actor = amp::ActorId::random();
+    let text_patch = |edits| amp::Patch {
+        actor: None,
+        seq: None,
+        max_op: 3,
+        pending_changes: 0,
+        deps: Vec::new(),
+        clock: hashmap! {
+            actor.clone() => 1,
+        },
+        diffs: RootDiff {
+            props: btreemap! {
+                "text".into() => btreemap!{
+                    actor.op_id_at(1) => amp::Diff::Text(amp::TextDiff{
+                        object_id: actor.op_id_at(1).into(),
+                        edits,
+                    })
+                }
+            },
+        },
+    };
+
+    let mut frontend = Frontend::new();
+    frontend.apply_patch(text_patch(Vec::new())).unwrap();
+
+    // the lengths add up to more characters than the text contains
+    let malformed = text_patch(vec![amp::DiffEdit::TextInsert(amp::TextInsert {
+        index: 0,
+        elem_id: actor.op_id_at(2).into(),
+        text: "ab".to_string(),
+        lengths: vec![1, 2],
+    })]);
+    assert!(frontend.apply_patch(malformed).is_err());
+    assert_eq!(
+        frontend.state(),
+        &Value::Map(hashmap! {"text".into() => Value::Text(Vec::new())})
+    );
+}
+
+#[test]
+fn malformed_diffs_are_rejected() {
+    let actor = amp::ActorId::random();
+    let patch = |props| amp::Patch {
+        actor: None,
+        seq: None,
+        max_op: 3,
+        pending_changes: 0,
+        deps: Vec::new(),
+        clock: hashmap! {
+            actor.clone() => 1,
+        },
+        diffs: RootDiff { props },
+    };
+
+    // a text object nested in a new map, with a character which isn't a string
+    let nested_text = patch(btreemap! {
+        "map".into() => btreemap!{
+            actor.op_id_at(1) => amp::Diff::Map(amp::MapDiff{
+                object_id: actor.op_id_at(1).into(),
+                props: btreemap!{
+                    "text".into() => btreemap!{
+                        actor.op_id_at(2) => amp::Diff::Text(amp::TextDiff{
+                            object_id: actor.op_id_at(2).into(),
+                            edits: vec![amp::DiffEdit::SingleElementInsert{
+                                index: 0,
+                                elem_id: actor.op_id_at(3).into(),
+                                op_id: actor.op_id_at(3),
+                                value: amp::Diff::Value(amp::ScalarValue::Int(1)),
+                            }],
+                        })
+                    }
+                },
+            })
+        }
+    });
+    // only the second of the conflicting values is malformed
+    let conflict = patch(btreemap! {
+        "key".into() => btreemap!{
+            actor.op_id_at(1) => amp::Diff::Value(amp::ScalarValue::Int(1)),
+            actor.op_id_at(2) => amp::Diff::Value(amp::ScalarValue::Cursor(actor.op_id_at(1))),
+        }
+    });
+    let head_insert = patch(btreemap! {
+        "list".into() => btreemap!{
+            actor.op_id_at(1) => amp::Diff::List(amp::ListDiff{
+                object_id: actor.op_id_at(1).into(),
+                edits: vec![amp::DiffEdit::MultiElementInsert(amp::MultiElementInsert{
+                    index: 0,
+                    elem_id: amp::ElementId::Head,
+                    values: vec![amp::ScalarValue::Int(1)].try_into().unwrap(),
+                })],
+            })
+        }
+    });
+
+    let mut frontend = Frontend::new();
+    assert!(frontend.apply_patch(nested_text).is_err());
+    assert!(frontend.apply_patch(conflict).is_err());
+    assert!(frontend.apply_patch(head_insert).is_err());
+    assert_eq!(frontend.state(), &Value::Map(hashmap! {}));
+}
+
+#[test]
+fn apply_diff_leaves_the_old_state_alone() {
+    let actor = amp::ActorId::random();
+    let set_bird = |counter, bird: &str| RootDiff {
+        props: btreemap! {
+            "bird".into() => btreemap!{
+                actor.op_id_at(counter) => bird.into()
+            }
+        },
+    };
+
+    let empty = DocumentState::new();
+    let magpie = apply_diff(&empty, set_bird(1, "magpie")).unwrap();
+    let wren = apply_diff(&magpie, set_bird(2, "wren")).unwrap();
+    assert_eq!(empty.value(), Value::Map(hashmap! {}));
+    assert_eq!(
+        magpie.get_value(&Path::root().key("bird")),
+        Some(Value::Primitive(Primitive::Str("magpie".into())))
+    );
+    assert_eq!(
+        wren.value(),
+        Into::<Value>::into(hashmap! {"bird" => "wren"})
+    );
+    assert_eq!(
+        wren.value(),
+        Frontend::value_of_patch(amp::Patch {
+            actor: None,
+            seq: None,
+            max_op: 2,
+            pending_changes: 0,
+            deps: Vec::new(),
+            clock: hashmap! {actor.clone() => 2},
+            diffs: set_bird(2, "wren"),
+        })
+        .unwrap()
+    );
+
+    // an invalid diff changes nothing
+    let text = |edits| RootDiff {
+        props: btreemap! {
+            "text".into() => btreemap!{
+                actor.op_id_at(3) => amp::Diff::Text(amp::TextDiff{
+                    object_id: actor.op_id_at(3).into(),
+                    edits,
+                })
+            }
+        },
+    };
+    let with_text = apply_diff(&wren, text(Vec::new())).unwrap();
+    let mut state = with_text.clone();
+    // the lengths add up to more characters than the text contains
+    let malformed = text(vec![amp::DiffEdit::TextInsert(amp::TextInsert {
+        index: 0,
+        elem_id: actor.op_id_at(4).into(),
+        text: "ab".to_string(),
+        lengths: vec![1, 2],
+    })]);
+    assert!(state.apply_diff(malformed).is_err());
+    assert_eq!(state, with_text);
+}
@@ -1,10 +1,12 @@
 use amp::{RootDiff, SortedVec};
 use automerge_backend::Backend;
 use automerge_frontend::{
-    Frontend, InvalidChangeRequest, InvalidPatch, LocalChange, Path, Primitive, Value,
+    register::{max_at, min_at, set_max},
+    Frontend, HeadlessFrontend, InvalidChangeRequest, InvalidPatch, LocalChange, Path, Primitive,
+    Value,
 };
 use automerge_protocol as amp;
-use maplit::hashmap;
+use maplit::{btreemap, hashmap};
 use pretty_assertions::assert_eq;
 
 fn random_op_id() -> amp::OpId {
@@ -28,8 +30,8 @@ fn use_version_and_sequence_number_from_backend() {
         },
         deps: Vec::new(),
         diffs: RootDiff {
-            props: hashmap! {
-                "blackbirds".into() => hashmap!{
+            props: btreemap! {
+                "blackbirds".into() => btreemap!{
                     random_op_id() => amp::Diff::Value(amp::ScalarValue::F64(24.0))
                 }
             },
@@ -120,8 +122,8 @@ fn remove_pending_requests_once_handled() {
         pending_changes: 0,
         deps: Vec::new(),
         diffs: RootDiff {
-            props: hashmap! {
-                "blackbirds".into() => hashmap!{
+            props: btreemap! {
+                "blackbirds".into() => btreemap!{
                     random_op_id() => amp::Diff::Value(amp::ScalarValue::Int(24))
                 }
             },
@@ -151,8 +153,8 @@ fn remove_pending_requests_once_handled() {
         pending_changes: 0,
         deps: Vec::new(),
         diffs: RootDiff {
-            props: hashmap! {
-                "partridges".into() => hashmap!{
+            props: btreemap! {
+                "partridges".into() => btreemap!{
                     random_op_id() => amp::Diff::Value(amp::ScalarValue::Int(1))
                 }
             },
@@ -208,8 +210,8 @@ fn leave_request_queue_unchanged_on_remote_changes() {
         },
         deps: Vec::new(),
         diffs: RootDiff {
-            props: hashmap! {
-                "pheasants".into() => hashmap!{
+            props: btreemap! {
+                "pheasants".into() => btreemap!{
                     random_op_id() => amp::Diff::Value(amp::ScalarValue::Int(2))
                 }
             },
@@ -239,8 +241,8 @@ fn leave_request_queue_unchanged_on_remote_changes() {
         pending_changes: 0,
         deps: Vec::new(),
         diffs: RootDiff {
-            props: hashmap! {
-                "blackbirds".into() => hashmap!{
+            props: btreemap! {
+                "blackbirds".into() => btreemap!{
                     random_op_id() => amp::Diff::Value(amp::ScalarValue::Int(24))
                 }
             },
@@ -288,8 +290,8 @@ fn dont_allow_out_of_order_request_patches() {
         },
         deps: Vec::new(),
         diffs: RootDiff {
-            props: hashmap! {
-                "partridges".into() => hashmap!{
+            props: btreemap! {
+                "partridges".into() => btreemap!{
                     random_op_id() => amp::Diff::Value(amp::ScalarValue::Int(1))
                 }
             },
@@ -334,8 +336,8 @@ fn handle_concurrent_insertions_into_lists() {
         },
         deps: Vec::new(),
         diffs: RootDiff {
-            props: hashmap! {
-                "birds".into() => hashmap!{
+            props: btreemap! {
+                "birds".into() => btreemap!{
                     doc.actor_id.op_id_at(1) => amp::Diff::List(amp::ListDiff{
                         object_id: birds_id.clone(),
                         edits: vec![amp::DiffEdit::SingleElementInsert{
@@ -397,8 +399,8 @@ fn handle_concurrent_insertions_into_lists() {
         seq: None,
         deps: Vec::new(),
         diffs: RootDiff {
-            props: hashmap! {
-                "birds".into() => hashmap!{
+            props: btreemap! {
+                "birds".into() => btreemap!{
                     doc.actor_id.op_id_at(1) => amp::Diff::List(amp::ListDiff{
                         object_id: birds_id.clone(),
                         edits: vec![amp::DiffEdit::SingleElementInsert{
@@ -434,8 +436,8 @@ fn handle_concurrent_insertions_into_lists() {
         },
         deps: Vec::new(),
         diffs: RootDiff {
-            props: hashmap! {
-                "birds".into() => hashmap!{
+            props: btreemap! {
+                "birds".into() => btreemap!{
                     doc.actor_id.op_id_at(1) => amp::Diff::List(amp::ListDiff{
                         object_id: birds_id,
                         edits: vec![
@@ -733,3 +735,577 @@ fn test_deps_are_filled_in_if_frontend_does_not_have_latest_patch() {
     };
     assert_eq!(change4, expected_change4);
 }
+
+#[test]
+fn headless_frontend_creates_changes_from_ops() {
+    let mut backend = Backend::new();
+    let mut headless = HeadlessFrontend::new();
+    let list_id = headless.next_op_id();
+    let list = amp::ObjectId::from(list_id.clone());
+    let change1 = headless
+        .change(
+            None,
+            vec![
+                amp::Op {
+                    action: amp::OpType::Make(amp::ObjType::List),
+                    obj: amp::ObjectId::Root,
+                    key: "list".into(),
+                    insert: false,
+                    pred: SortedVec::new(),
+                },
+                amp::Op {
+                    action: amp::OpType::Set("a".into()),
+                    obj: list.clone(),
+                    key: amp::ElementId::Head.into(),
+                    insert: true,
+                    pred: SortedVec::new(),
+                },
+            ],
+        )
+        .unwrap();
+    assert_eq!((change1.seq, change1.start_op), (1, 1));
+    assert_eq!(headless.max_op(), 2);
+
+    // A second change made before the first is acknowledged
+    let change2 = headless
+        .change(
+            None,
+            vec![amp::Op {
+                action: amp::OpType::Set("b".into()),
+                obj: list,
+                key: list_id.increment_by(1).into(),
+                insert: true,
+                pred: SortedVec::new(),
+            }],
+        )
+        .unwrap();
+    assert_eq!((change2.seq, change2.start_op), (2, 3));
+    assert_eq!(headless.in_flight_requests(), &[1, 2]);
+    assert_eq!(headless.change(None, Vec::new()), None);
+
+    let (patch1, _) = backend.apply_local_change(change1).unwrap();
+    let (patch2, _) = backend.apply_local_change(change2).unwrap();
+    assert_eq!(
+        headless.apply_patch(patch2.clone()),
+        Err(InvalidPatch::MismatchedSequenceNumber {
+            expected: 1,
+            actual: 2
+        })
+    );
+    headless.apply_patch(patch1).unwrap();
+    headless.apply_patch(patch2).unwrap();
+    assert!(headless.in_flight_requests().is_empty());
+    assert_eq!(headless.max_op(), 3);
+    assert_eq!(headless.clock(), &hashmap! {headless.actor_id.clone() => 2});
+
+    // A full frontend sees the edits made by the headless one
+    let mut doc = Frontend::new();
+    doc.apply_patch(backend.get_patch().unwrap()).unwrap();
+    assert_eq!(
+        doc.get_value(&Path::root().key("list")),
+        Some(Value::List(vec![
+            Value::Primitive(Primitive::Str("a".into())),
+            Value::Primitive(Primitive::Str("b".into())),
+        ]))
+    );
+
+    let change3 = headless
+        .change(
+            Some("reconciled".to_string()),
+            vec![amp::Op {
+                action: amp::OpType::Set(amp::ScalarValue::from(1)),
+                obj: amp::ObjectId::Root,
+                key: "number".into(),
+                insert: false,
+                pred: SortedVec::new(),
+            }],
+        )
+        .unwrap();
+    assert_eq!(change3.start_op, 4);
+    backend.apply_local_change(change3).unwrap();
+    assert_eq!(backend.get_changes(&[]).len(), 3);
+}
+
+fn set_bird(backend: &mut Backend, doc: &mut Frontend, bird: &str) {
+    let change = doc
+        .change::<_, _, InvalidChangeRequest>(None, |d| {
+            d.add_change(LocalChange::set(
+                Path::root().key("bird"),
+                Primitive::Str(bird.into()),
+            ))
+        })
+        .unwrap()
+        .1
+        .unwrap();
+    let (patch, _) = backend.apply_local_change(change).unwrap();
+    doc.apply_patch(patch).unwrap();
+}
+
+#[test]
+fn get_conflicts_returns_concurrent_values() {
+    let mut doc1 = Frontend::new();
+    let mut doc2 = Frontend::new();
+    let mut backend = Backend::new();
+
+    set_bird(&mut backend, &mut doc1, "magpie");
+    set_bird(&mut backend, &mut doc2, "robin");
+
+    let mut doc3 = Frontend::new();
+    doc3.apply_patch(backend.get_patch().unwrap()).unwrap();
+    assert_eq!(
+        doc3.get_conflicts(&Path::root().key("bird")),
+        Some(hashmap! {
+            doc1.actor_id.op_id_at(1) => Value::Primitive(Primitive::Str("magpie".into())),
+            doc2.actor_id.op_id_at(1) => Value::Primitive(Primitive::Str("robin".into())),
+        })
+    );
+    assert_eq!(doc3.get_conflicts(&Path::root().key("fish")), None);
+
+    // Overwriting the key after seeing both values resolves the conflict
+    set_bird(&mut backend, &mut doc3, "wren");
+    assert_eq!(
+        doc3.get_conflicts(&Path::root().key("bird")),
+        Some(hashmap! {
+            doc3.actor_id.op_id_at(2) => Value::Primitive(Primitive::Str("wren".into())),
+        })
+    );
+}
+
+fn set_high_water(backend: &mut Backend, doc: &mut Frontend, n: i64) -> bool {
+    let (set, change) = doc
+        .change::<_, _, InvalidChangeRequest>(None, |d| {
+            set_max(d, &Path::root().key("highWater"), Primitive::Int(n))
+        })
+        .unwrap();
+    if let Some(change) = change {
+        let (patch, _) = backend.apply_local_change(change).unwrap();
+        doc.apply_patch(patch).unwrap();
+    }
+    set
+}
+
+#[test]
+fn max_register_surfaces_the_largest_concurrent_value() {
+    let path = Path::root().key("highWater");
+    let mut backend = Backend::new();
+
+    let mut doc1 = Frontend::new();
+    let mut doc2 = Frontend::new();
+    assert!(set_high_water(&mut backend, &mut doc1, 9));
+    assert!(set_high_water(&mut backend, &mut doc2, 5));
+    assert!(!set_high_water(&mut backend, &mut doc1, 3));
+
+    let mut doc3 = Frontend::new();
+    doc3.apply_patch(backend.get_patch().unwrap()).unwrap();
+    assert_eq!(doc3.get_conflicts(&path).unwrap().len(), 2);
+    assert_eq!(max_at(&doc3, &path), Some(Primitive::Int(9)));
+    assert_eq!(min_at(&doc3, &path), Some(Primitive::Int(5)));
+
+    // 7 beats the value doc3 might see as the winner but not the maximum
+    assert!(!set_high_water(&mut backend, &mut doc3, 7));
+    assert!(set_high_water(&mut backend, &mut doc3, 12));
+    assert_eq!(doc3.get_conflicts(&path).unwrap().len(), 1);
+    assert_eq!(max_at(&doc3, &path), Some(Primitive::Int(12)));
+
+    assert_eq!(
+        doc3.change::<_, _, InvalidChangeRequest>(None, |d| {
+            set_max(d, &path, Primitive::Str("twelve".into()))
+        }),
+        Err(InvalidChangeRequest::NotANumber {
+            path: path.clone(),
+            value: Primitive::Str("twelve".into()),
+        })
+    );
+}
+
+#[test]
+fn observers_are_told_about_changes_under_their_path() {
+    use std::{cell::RefCell, rc::Rc};
+
+    use automerge_frontend::{ChangeEvent, ChangeEventKind};
+
+    let mut backend = Backend::new();
+    let mut remote = Frontend::new();
+    let mut doc = Frontend::new();
+    let seen: Rc<RefCell<Vec<ChangeEvent>>> = Rc::default();
+    let observer = {
+        let seen = seen.clone();
+        doc.observe(Path::root().key("birds"), move |event| {
+            seen.borrow_mut().push(event.clone())
+        })
+    };
+
+    let birds = Path::root().key("birds");
+    let mut edit = |backend: &mut Backend, doc: &mut Frontend, change: LocalChange| {
+        let change = remote
+            .change::<_, _, InvalidChangeRequest>(None, |d| d.add_change(change))
+            .unwrap()
+            .1
+            .unwrap();
+        let (patch, _) = backend.apply_local_change(change).unwrap();
+        remote.apply_patch(patch.clone()).unwrap();
+        doc.apply_patch(patch).unwrap();
+    };
+    let take = || std::mem::take(&mut *seen.borrow_mut());
+
+    edit(
+        &mut backend,
+        &mut doc,
+        LocalChange::set(birds.clone(), Value::List(vec!["wren".into()])),
+    );
+    assert_eq!(
+        take(),
+        vec![ChangeEvent {
+            path: birds.clone(),
+            kind: ChangeEventKind::Updated
+        }]
+    );
+
+    edit(
+        &mut backend,
+        &mut doc,
+        LocalChange::insert(birds.clone().index(1), "robin".into()),
+    );
+    edit(
+        &mut backend,
+        &mut doc,
+        LocalChange::set(Path::root().key("fish"), Primitive::Str("carp".into())),
+    );
+    edit(
+        &mut backend,
+        &mut doc,
+        LocalChange::delete(birds.clone().index(0)),
+    );
+    assert_eq!(
+        take(),
+        vec![
+            ChangeEvent {
+                path: birds.clone().index(1),
+                kind: ChangeEventKind::Inserted
+            },
+            ChangeEvent {
+                path: birds.clone().index(0),
+                kind: ChangeEventKind::Removed
+            },
+        ]
+    );
+
+    assert!(doc.unobserve(observer));
+    assert!(!doc.unobserve(observer));
+    edit(&mut backend, &mut doc, LocalChange::delete(birds.clone()));
+    assert_eq!(take(), Vec::new());
+}
+
+#[test]
+fn preview_patch_reports_effects_without_applying() {
+    use automerge_frontend::{ChangeEvent, ChangeEventKind};
+
+    let bird = Path::root().key("bird");
+    let set = |doc: &mut Frontend, name: &str| {
+        doc.change::<_, _, InvalidChangeRequest>(None, |d| {
+            d.add_change(LocalChange::set(
+                Path::root().key("bird"),
+                Primitive::Str(name.into()),
+            ))
+        })
+        .unwrap()
+        .1
+        .unwrap()
+    };
+
+    let mut doc1 = Frontend::new();
+    let mut doc2 = Frontend::new();
+    let change1 = set(&mut doc1, "magpie");
+    let change2 = set(&mut doc2, "robin");
+
+    // doc2's backend hears about doc1's change before doc2's in flight change
+    let mut backend = Backend::new();
+    let remote_patch = backend
+        .apply_changes(vec![automerge_backend::Change::from(change1)])
+        .unwrap();
+    let effects = doc2.preview_patch(&remote_patch).unwrap();
+    assert_eq!(
+        effects.changes,
+        vec![ChangeEvent {
+            path: bird.clone(),
+            kind: ChangeEventKind::Updated
+        }]
+    );
+    assert_eq!(effects.in_flight_overridden, vec![bird.clone()]);
+    assert!(effects.overrides_in_flight_changes());
+    assert!(effects.conflicts_created.is_empty());
+    assert_eq!(
+        doc2.get_value(&bird),
+        Some(Value::Primitive(Primitive::Str("robin".into())))
+    );
+    doc2.apply_patch(remote_patch).unwrap();
+
+    // the patch confirming doc2's change creates a conflict with doc1's value
+    let (local_patch, _) = backend.apply_local_change(change2).unwrap();
+    let effects = doc2.preview_patch(&local_patch).unwrap();
+    assert_eq!(effects.conflicts_created, vec![bird.clone()]);
+    assert!(!effects.overrides_in_flight_changes());
+    doc2.apply_patch(local_patch).unwrap();
+    assert_eq!(doc2.get_conflicts(&bird).unwrap().len(), 2);
+
+    // and a patch with a single value for the key resolves it
+    let patch = amp::Patch {
+        actor: None,
+        seq: None,
+        clock: hashmap! {},
+        deps: Vec::new(),
+        diffs: RootDiff {
+            props: btreemap! {
+                "bird".into() => btreemap! {
+                    random_op_id() => amp::Diff::Value("wren".into())
+                }
+            },
+        },
+        max_op: 3,
+        pending_changes: 0,
+    };
+    let effects = doc2.preview_patch(&patch).unwrap();
+    assert_eq!(effects.conflicts_resolved, vec![bird]);
+}
+
+#[test]
+fn value_of_patch_shows_old_versions() {
+    let mut backend = Backend::new();
+    let mut doc = Frontend::new();
+    let mut heads = Vec::new();
+    for bird in &["magpie", "wren"] {
+        set_bird(&mut backend, &mut doc, bird);
+        heads.push(backend.get_heads());
+    }
+
+    let then = Frontend::value_of_patch(backend.get_patch_at(&heads[0]).unwrap()).unwrap();
+    assert_eq!(
+        then,
+        Value::Map(hashmap! {"bird".into() => Value::Primitive(Primitive::Str("magpie".into()))})
+    );
+    let now = Frontend::value_of_patch(backend.get_patch_at(&heads[1]).unwrap()).unwrap();
+    assert_eq!(now, doc.state().clone());
+}
+
+#[test]
+fn patch_fanout_keeps_every_view_up_to_date() {
+    use automerge_frontend::PatchFanout;
+
+    let mut backend = Backend::new();
+    let mut views = PatchFanout::new();
+    let left = views.add(Frontend::new());
+    let right = views.add(Frontend::new());
+
+    let change = views
+        .get_mut(left)
+        .unwrap()
+        .change::<_, _, InvalidChangeRequest>(None, |d| {
+            d.add_change(LocalChange::set(
+                Path::root().key("bird"),
+                Primitive::Str("wren".into()),
+            ))
+        })
+        .unwrap()
+        .1
+        .unwrap();
+    let (patch, _) = backend.apply_local_change(change).unwrap();
+    views.apply_patch(patch).unwrap();
+
+    let expected = Value::from_json(&serde_json::json!({"bird": "wren"}));
+    for (_, view) in views.views() {
+        assert_eq!(view.get_value(&Path::root()), Some(expected.clone()));
+    }
+    // The patch confirmed the change in flight in the left view
+    assert!(views.get_mut(left).unwrap().in_flight_requests().is_empty());
+
+    // A patch claiming to be for a change the left view never made is rejected by it, but still
+    // reaches the right view
+    views
+        .get_mut(left)
+        .unwrap()
+        .change::<_, _, InvalidChangeRequest>(None, |d| {
+            d.add_change(LocalChange::set(
+                Path::root().key("fish"),
+                Primitive::Str("carp".into()),
+            ))
+        })
+        .unwrap();
+    let mut patch = backend.get_patch().unwrap();
+    patch.actor = Some(views.get(left).unwrap().actor_id.clone());
+    patch.seq = Some(7);
+    let err = views.apply_patch(patch).unwrap_err();
+    assert_eq!(
+        err.failed.iter().map(|(id, _)| *id).collect::<Vec<_>>(),
+        vec![left]
+    );
+    assert!(views.remove(right).is_some());
+    assert_eq!(views.len(), 1);
+}
+
+#[test]
+fn backend_diff_turns_one_version_into_another() {
+    fn edit(backend: &mut Backend, doc: &mut Frontend, changes: Vec<LocalChange>) {
+        let change = doc
+            .change::<_, _, InvalidChangeRequest>(None, |d| {
+                for change in changes {
+                    d.add_change(change)?;
+                }
+                Ok(())
+            })
+            .unwrap()
+            .1
+            .unwrap();
+        let (patch, _) = backend.apply_local_change(change).unwrap();
+        doc.apply_patch(patch).unwrap();
+    }
+
+    let mut backend = Backend::new();
+    let mut doc = Frontend::new();
+    let birds = Path::root().key("birds");
+    let notes = Path::root().key("notes");
+    let mut heads = vec![backend.get_heads()];
+    edit(
+        &mut backend,
+        &mut doc,
+        vec![
+            LocalChange::set(
+                birds.clone(),
+                Value::from_json(&serde_json::json!(["wren", {"name": "robin"}])),
+            ),
+            LocalChange::set(notes.clone(), Value::Text(vec!["h".into(), "i".into()])),
+            LocalChange::set(Path::root().key("count"), Primitive::Counter(1)),
+        ],
+    );
+    heads.push(backend.get_heads());
+    edit(
+        &mut backend,
+        &mut doc,
+        vec![
+            LocalChange::delete(birds.clone().index(0)),
+            LocalChange::set(
+                birds.clone().index(0).key("name"),
+                Primitive::Str("blackbird".into()),
+            ),
+            LocalChange::insert(birds.clone().index(1), "jay".into()),
+            LocalChange::insert_str(notes.clone(), 2, " there"),
+            LocalChange::increment(Path::root().key("count")),
+        ],
+    );
+    heads.push(backend.get_heads());
+
+    // A concurrent branch, so some pairs of heads aren't ancestors of each other
+    let mut other_backend = Backend::new();
+    other_backend
+        .apply_changes(
+            backend
+                .get_changes(&[])
+                .into_iter()
+                .take(1)
+                .cloned()
+                .collect(),
+        )
+        .unwrap();
+    let mut other = Frontend::new();
+    other
+        .apply_patch(other_backend.get_patch().unwrap())
+        .unwrap();
+    edit(
+        &mut other_backend,
+        &mut other,
+        vec![
+            LocalChange::delete(notes.clone()),
+            LocalChange::insert(birds.clone().index(0), "owl".into()),
+        ],
+    );
+    backend
+        .apply_changes(
+            other_backend
+                .get_changes(&heads[1])
+                .into_iter()
+                .cloned()
+                .collect(),
+        )
+        .unwrap();
+    heads.push(other_backend.get_heads());
+    heads.push(backend.get_heads());
+
+    for before in &heads {
+        for after in &heads {
+            let mut viewer = Frontend::new();
+            viewer
+                .apply_patch(backend.get_patch_at(before).unwrap())
+                .unwrap();
+            let mut patch = backend.get_patch_at(after).unwrap();
+            patch.diffs = backend.diff(before, after).unwrap();
+            viewer.apply_patch(patch.clone()).unwrap();
+            assert_eq!(
+                viewer.state().clone(),
+                Frontend::value_of_patch(backend.get_patch_at(after).unwrap()).unwrap(),
+                "diff from {:?} to {:?}: {:?}",
+                before,
+                after,
+                patch.diffs
+            );
+            if before == after {
+                assert!(patch.diffs.props.is_empty());
+            }
+        }
+    }
+}
+
+#[test]
+fn overwriting_a_conflict_overwrites_every_value() {
+    let actor1 = amp::ActorId::random();
+    let actor2 = amp::ActorId::random();
+    let conflicted_doc = || {
+        let mut doc = Frontend::new();
+        doc.apply_patch(amp::Patch {
+            actor: None,
+            seq: None,
+            clock: hashmap! {
+                actor1.clone() => 1,
+                actor2.clone() => 1,
+            },
+            deps: Vec::new(),
+            diffs: RootDiff {
+                props: btreemap! {
+                    "bird".into() => btreemap!{
+                        actor1.op_id_at(1) => amp::Diff::Value("robin".into()),
+                        actor2.op_id_at(1) => amp::Diff::Value("wagtail".into()),
+                    }
+                },
+            },
+            max_op: 1,
+            pending_changes: 0,
+        })
+        .unwrap();
+        doc
+    };
+    let conflicts: SortedVec<amp::OpId> = vec![actor1.op_id_at(1), actor2.op_id_at(1)].into();
+
+    let mut doc = conflicted_doc();
+    let set = doc
+        .change::<_, _, InvalidChangeRequest>(None, |d| {
+            d.add_change(LocalChange::set(
+                Path::root().key("bird"),
+                Value::from("magpie"),
+            ))
+        })
+        .unwrap()
+        .1
+        .unwrap();
+    assert_eq!(set.operations[0].pred, conflicts);
+    assert_eq!(
+        doc.get_conflicts(&Path::root().key("bird")).unwrap().len(),
+        1
+    );
+
+    let delete = conflicted_doc()
+        .change::<_, _, InvalidChangeRequest>(None, |d| {
+            d.add_change(LocalChange::delete(Path::root().key("bird")))
+        })
+        .unwrap()
+        .1
+        .unwrap();
+    assert_eq!(delete.operations[0].pred, conflicts);
+}
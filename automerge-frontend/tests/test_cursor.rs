@@ -4,7 +4,7 @@ use amp::RootDiff;
 use automerge_backend::Backend;
 use automerge_frontend::{Frontend, InvalidChangeRequest, LocalChange, Path, Primitive, Value};
 use automerge_protocol as amp;
-use maplit::hashmap;
+use maplit::{btreemap, hashmap};
 use unicode_segmentation::UnicodeSegmentation;
 
 #[test]
@@ -146,8 +146,8 @@ fn test_set_cursor_to_new_element_in_diff() {
         max_op: 3,
         pending_changes: 0,
         diffs: RootDiff {
-            props: hashmap! {
-                "list".into() => hashmap!{
+            props: btreemap! {
+                "list".into() => btreemap!{
                     actor.op_id_at(1) => amp::Diff::List(amp::ListDiff{
                         object_id: actor.op_id_at(1).into(),
                         edits: vec![
@@ -166,7 +166,7 @@ fn test_set_cursor_to_new_element_in_diff() {
                         ],
                     }),
                 },
-                "cursor".into() => hashmap!{
+                "cursor".into() => btreemap!{
                     actor.op_id_at(4) => amp::Diff::Cursor(amp::CursorDiff{
                         elem_id: actor.op_id_at(3),
                         index: 1,
@@ -186,8 +186,8 @@ fn test_set_cursor_to_new_element_in_diff() {
         max_op: 5,
         pending_changes: 0,
         diffs: RootDiff {
-            props: hashmap! {
-                "cursor".into() => hashmap!{
+            props: btreemap! {
+                "cursor".into() => btreemap!{
                     actor.op_id_at(4) => amp::Diff::Cursor(amp::CursorDiff{
                         elem_id: actor.op_id_at(2),
                         index: 0,
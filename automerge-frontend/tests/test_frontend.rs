@@ -1,7 +1,10 @@
-use std::{convert::TryInto, num::NonZeroU32};
+use std::{collections::HashMap, convert::TryInto, num::NonZeroU32};
 
 use amp::SortedVec;
-use automerge_frontend::{Frontend, InvalidChangeRequest, LocalChange, Path, Primitive, Value};
+use automerge_frontend::{
+    ActorInfo, Frontend, Granularity, InvalidChangeRequest, LocalChange, Path, Primitive,
+    TextIndexing, Value,
+};
 use automerge_protocol as amp;
 use maplit::hashmap;
 use pretty_assertions::assert_eq;
@@ -907,3 +910,711 @@ fn test_inserts_at_end_of_lists() {
     });
     assert_eq!(value, expected_value);
 }
+
+#[test]
+fn test_guarded_state_detects_stale_reads() {
+    let mut frontend = Frontend::new();
+    let before = frontend.guarded_state();
+    assert!(!before.is_stale());
+    assert_eq!(before.get(), &Value::Map(HashMap::new()));
+
+    frontend
+        .change::<_, _, InvalidChangeRequest>(None, |doc| {
+            doc.add_change(LocalChange::set(
+                Path::root().key("bird"),
+                Value::Primitive(Primitive::Str("magpie".into())),
+            ))?;
+            Ok(())
+        })
+        .unwrap();
+    assert!(before.is_stale());
+
+    let after = frontend.guarded_state();
+    assert!(!after.is_stale());
+    assert_eq!(
+        after.get(),
+        &Value::Map(hashmap! {
+            "bird".into() => Value::Primitive(Primitive::Str("magpie".into())),
+        })
+    );
+    assert_eq!(before.into_inner(), Value::Map(HashMap::new()));
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "read a document value after the document had changed")]
+fn test_guarded_state_panics_on_stale_read() {
+    let mut frontend = Frontend::new();
+    let before = frontend.guarded_state();
+    frontend
+        .change::<_, _, InvalidChangeRequest>(None, |doc| {
+            doc.add_change(LocalChange::set(
+                Path::root().key("bird"),
+                Value::Primitive(Primitive::Str("magpie".into())),
+            ))?;
+            Ok(())
+        })
+        .unwrap();
+    before.get();
+}
+
+#[test]
+fn test_attributed_conflicts_use_actor_registry() {
+    let mut frontend = Frontend::new();
+    let actor = frontend.actor_id.clone();
+    frontend
+        .change::<_, _, InvalidChangeRequest>(None, |doc| {
+            doc.add_change(LocalChange::set(
+                Path::root().key("bird"),
+                Value::Primitive(Primitive::Str("magpie".into())),
+            ))?;
+            Ok(())
+        })
+        .unwrap();
+
+    let path = Path::root().key("bird");
+    let magpie = Value::Primitive(Primitive::Str("magpie".into()));
+    assert_eq!(
+        frontend.get_attributed_conflicts(&path),
+        Some(vec![(actor.to_hex_string(), magpie.clone())])
+    );
+
+    let people = Value::Map(hashmap! {
+        actor.to_hex_string().into() => Value::Map(hashmap! {
+            "name".into() => Value::Primitive(Primitive::Str("Alice".into())),
+            "device".into() => Value::Primitive(Primitive::Str("laptop".into())),
+        }),
+        "not an actor".into() => Value::Map(HashMap::new()),
+    });
+    assert_eq!(frontend.actor_registry_mut().load_from_value(&people), 1);
+    assert_eq!(
+        frontend.actor_registry().get(&actor),
+        Some(&ActorInfo {
+            name: Some("Alice".into()),
+            color: None,
+            device: Some("laptop".into()),
+        })
+    );
+    assert_eq!(
+        frontend.get_attributed_conflicts(&path),
+        Some(vec![("Alice".to_string(), magpie)])
+    );
+    assert_eq!(
+        frontend.get_attributed_conflicts(&Path::root().key("missing")),
+        None
+    );
+}
+
+#[test]
+fn test_element_info() {
+    let birds = Path::root().key("birds");
+    let mut backend = automerge_backend::Backend::new();
+    let mut frontend = Frontend::new_with_timestamper(Box::new(|| Some(42)));
+    let (_, change) = frontend
+        .change::<_, _, InvalidChangeRequest>(None, |doc| {
+            doc.add_change(LocalChange::set(
+                birds.clone(),
+                Value::List(vec!["wren".into()]),
+            ))
+        })
+        .unwrap();
+    let (patch, _) = backend.apply_local_change(change.unwrap()).unwrap();
+    frontend.apply_patch(patch).unwrap();
+
+    let info = frontend.element_info(&birds, 0).unwrap();
+    assert_eq!(info.actor, frontend.actor_id);
+    assert_eq!(info.timestamp, Some(42));
+
+    let mut other = Frontend::new_with_timestamper(Box::new(|| Some(7)));
+    other.apply_patch(backend.get_patch().unwrap()).unwrap();
+    let (_, change) = other
+        .change::<_, _, InvalidChangeRequest>(None, |doc| {
+            doc.add_change(LocalChange::insert(birds.clone().index(0), "robin".into()))
+        })
+        .unwrap();
+    let change = change.unwrap();
+    let patch = backend
+        .apply_changes(vec![change.clone().try_into().unwrap()])
+        .unwrap();
+    frontend.apply_patch(patch).unwrap();
+
+    let info = frontend.element_info(&birds, 0).unwrap();
+    assert_eq!(info.actor, other.actor_id);
+    assert_eq!(info.elem_id, other.actor_id.op_id_at(change.start_op));
+    assert_eq!(info.timestamp, None);
+    frontend.record_change_times(&[change]);
+    assert_eq!(frontend.element_info(&birds, 0).unwrap().timestamp, Some(7));
+    assert_eq!(
+        frontend.element_info(&birds, 1).unwrap().timestamp,
+        Some(42)
+    );
+
+    assert_eq!(frontend.element_info(&birds, 2), None);
+    assert_eq!(frontend.element_info(&Path::root(), 0), None);
+}
+
+#[test]
+fn test_marks() {
+    let text = Path::root().key("text");
+    let span = |start, end, name: &str| amp::MarkSpan {
+        start,
+        end,
+        name: name.into(),
+        value: amp::ScalarValue::Boolean(true),
+    };
+    let mut backend = automerge_backend::Backend::new();
+    let mut frontend = Frontend::new();
+    let (_, change) = frontend
+        .change::<_, _, InvalidChangeRequest>(None, |doc| {
+            doc.add_change(LocalChange::set(
+                text.clone(),
+                Value::Text("hello".graphemes(true).map(|s| s.into()).collect()),
+            ))?;
+            doc.add_change(LocalChange::mark(
+                text.clone(),
+                1,
+                4,
+                "bold",
+                Primitive::Boolean(true),
+            ))
+        })
+        .unwrap();
+    assert_eq!(frontend.marks(&text), Some(vec![span(1, 4, "bold")]));
+    let (patch, _) = backend.apply_local_change(change.unwrap()).unwrap();
+    frontend.apply_patch(patch).unwrap();
+    assert_eq!(frontend.marks(&text), Some(vec![span(1, 4, "bold")]));
+
+    let (_, change) = frontend
+        .change::<_, _, InvalidChangeRequest>(None, |doc| {
+            doc.add_change(LocalChange::unmark(text.clone(), 2, 3, "bold"))
+        })
+        .unwrap();
+    let expected = vec![span(1, 2, "bold"), span(3, 4, "bold")];
+    assert_eq!(frontend.marks(&text), Some(expected.clone()));
+    let (patch, _) = backend.apply_local_change(change.unwrap()).unwrap();
+    frontend.apply_patch(patch).unwrap();
+    assert_eq!(frontend.marks(&text), Some(expected.clone()));
+
+    let mut other = Frontend::new();
+    other.apply_patch(backend.get_patch().unwrap()).unwrap();
+    assert_eq!(other.marks(&text), Some(expected));
+
+    let result = frontend.change::<_, _, InvalidChangeRequest>(None, |doc| {
+        doc.add_change(LocalChange::mark(
+            text.clone(),
+            3,
+            3,
+            "bold",
+            Primitive::Boolean(true),
+        ))
+    });
+    assert!(matches!(
+        result,
+        Err(InvalidChangeRequest::InvalidMarkRange { .. })
+    ));
+    let result = frontend.change::<_, _, InvalidChangeRequest>(None, |doc| {
+        doc.add_change(LocalChange::mark(
+            Path::root(),
+            0,
+            1,
+            "bold",
+            Primitive::Boolean(true),
+        ))
+    });
+    assert!(matches!(
+        result,
+        Err(InvalidChangeRequest::MarkForNonTextObject { .. })
+    ));
+    assert_eq!(frontend.marks(&Path::root()), None);
+}
+
+#[test]
+fn test_reading_at_paths() {
+    let mut frontend = Frontend::new();
+    frontend
+        .change::<_, _, InvalidChangeRequest>(None, |doc| {
+            doc.add_change(LocalChange::set(
+                Path::root().key("birds"),
+                Value::Map(hashmap! {
+                    "wrens".into() => Value::List(vec!["jenny".into(), "carolina".into()]),
+                    "magpies".into() => Primitive::Int(2).into(),
+                }),
+            ))?;
+            doc.add_change(LocalChange::set(
+                Path::root().key("note"),
+                Value::Text("hi".graphemes(true).map(|s| s.into()).collect()),
+            ))
+        })
+        .unwrap();
+
+    let birds = Path::root().key("birds");
+    assert_eq!(
+        frontend.keys_at(&Path::root()),
+        Some(vec!["birds".into(), "note".into()])
+    );
+    assert_eq!(
+        frontend.keys_at(&birds),
+        Some(vec!["magpies".into(), "wrens".into()])
+    );
+    assert_eq!(frontend.keys_at(&birds.clone().key("wrens")), None);
+
+    assert_eq!(frontend.length_at(&birds), Some(2));
+    assert_eq!(frontend.length_at(&birds.clone().key("wrens")), Some(2));
+    assert_eq!(frontend.length_at(&Path::root().key("note")), Some(2));
+    assert_eq!(frontend.length_at(&birds.clone().key("magpies")), None);
+
+    assert!(frontend.exists_at(&birds.clone().key("wrens").index(1)));
+    assert!(!frontend.exists_at(&birds.clone().key("wrens").index(2)));
+    assert!(!frontend.exists_at(&birds.clone().key("owls")));
+    assert_eq!(
+        frontend.get_value(&birds.key("wrens").index(1)),
+        Some("carolina".into())
+    );
+}
+
+#[test]
+fn test_splice_text() {
+    let text = Path::root().key("text");
+    let mut backend = automerge_backend::Backend::new();
+    let mut frontend = Frontend::new();
+    let (_, change) = frontend
+        .change::<_, _, InvalidChangeRequest>(None, |doc| {
+            doc.add_change(LocalChange::set(text.clone(), Value::Text(Vec::new())))?;
+            doc.add_change(LocalChange::insert_str(text.clone(), 0, "hello world"))
+        })
+        .unwrap();
+    let change = change.unwrap();
+    // one op to make the text and a single multi-insert for the characters
+    assert_eq!(change.operations.len(), 2);
+    assert!(matches!(
+        &change.operations[1].action,
+        amp::OpType::MultiSet(values) if values.len() == 11
+    ));
+    assert_eq!(frontend.text_at(&text), Some("hello world".to_string()));
+    let (patch, _) = backend.apply_local_change(change).unwrap();
+    frontend.apply_patch(patch).unwrap();
+
+    let (_, change) = frontend
+        .change::<_, _, InvalidChangeRequest>(None, |doc| {
+            doc.add_change(LocalChange::splice_text(text.clone(), 6, 5, "🇬🇧 there"))
+        })
+        .unwrap();
+    let change = change.unwrap();
+    assert_eq!(
+        change.operations[0].action,
+        amp::OpType::Del(NonZeroU32::new(5).unwrap())
+    );
+    assert_eq!(frontend.text_at(&text), Some("hello 🇬🇧 there".to_string()));
+    assert_eq!(frontend.length_at(&text), Some(13));
+    let (patch, _) = backend.apply_local_change(change).unwrap();
+    frontend.apply_patch(patch).unwrap();
+
+    let mut other = Frontend::new();
+    other.apply_patch(backend.get_patch().unwrap()).unwrap();
+    assert_eq!(other.text_at(&text), Some("hello 🇬🇧 there".to_string()));
+    assert_eq!(other.text_at(&Path::root().key("missing")), None);
+
+    let result = frontend.change::<_, _, InvalidChangeRequest>(None, |doc| {
+        doc.add_change(LocalChange::splice_text(text.clone(), 10, 4, ""))
+    });
+    assert!(matches!(
+        result,
+        Err(InvalidChangeRequest::MissingIndexError { .. })
+    ));
+    let result = frontend.change::<_, _, InvalidChangeRequest>(None, |doc| {
+        doc.add_change(LocalChange::set(Path::root().key("n"), Primitive::Int(1)))?;
+        doc.add_change(LocalChange::insert_str(Path::root().key("n"), 0, "x"))
+    });
+    assert_eq!(
+        result,
+        Err(InvalidChangeRequest::SpliceForNonTextObject {
+            path: Path::root().key("n")
+        })
+    );
+    assert_eq!(frontend.text_at(&text), Some("hello 🇬🇧 there".to_string()));
+}
+
+#[test]
+fn test_soft_delete_and_restore() {
+    use automerge_frontend::trash::{self, ExpiryPolicy};
+
+    let birds = Path::root().key("birds");
+    let config = Path::root().key("config");
+    let mut frontend = Frontend::new();
+    frontend
+        .change::<_, _, InvalidChangeRequest>(None, |doc| {
+            doc.add_change(LocalChange::set(
+                birds.clone(),
+                Value::List(vec!["wren".into(), "robin".into(), "jay".into()]),
+            ))?;
+            doc.add_change(LocalChange::set(
+                config.clone(),
+                Value::Map(hashmap! {"colour".into() => "blue".into()}),
+            ))
+        })
+        .unwrap();
+    let original = frontend.state().clone();
+
+    let (robin, _) = frontend
+        .change::<_, _, InvalidChangeRequest>(None, |doc| {
+            trash::soft_delete(doc, &birds.clone().index(1), 1000)
+        })
+        .unwrap();
+    let (config_id, _) = frontend
+        .change::<_, _, InvalidChangeRequest>(None, |doc| trash::soft_delete(doc, &config, 5000))
+        .unwrap();
+    assert_eq!(
+        frontend.get_value(&birds),
+        Some(Value::List(vec!["wren".into(), "jay".into()]))
+    );
+    assert_eq!(frontend.get_value(&config), None);
+    let entries = frontend.trash();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].id, robin);
+    assert_eq!(entries[0].path, birds.clone().index(1));
+    assert_eq!(entries[0].value, "robin".into());
+    assert_eq!(entries[1].deleted_at, 5000);
+
+    let (restored, _) = frontend
+        .change::<_, _, InvalidChangeRequest>(None, |doc| trash::restore(doc, &config_id))
+        .unwrap();
+    assert_eq!(restored, config);
+    assert_eq!(
+        frontend.get_value(&config),
+        Some(Value::Map(hashmap! {"colour".into() => "blue".into()}))
+    );
+    assert_eq!(
+        frontend.change::<_, _, InvalidChangeRequest>(None, |doc| trash::restore(doc, &config_id)),
+        Err(InvalidChangeRequest::NoSuchTrashEntry {
+            id: config_id.clone()
+        })
+    );
+
+    frontend
+        .change::<_, _, InvalidChangeRequest>(None, |doc| trash::restore(doc, &robin))
+        .unwrap();
+    assert_eq!(
+        frontend.get_value(&Path::root()).map(|mut v| {
+            if let Value::Map(items) = &mut v {
+                items.remove(trash::TRASH_KEY);
+            }
+            v
+        }),
+        Some(original)
+    );
+
+    // purging only removes expired entries
+    frontend
+        .change::<_, _, InvalidChangeRequest>(None, |doc| {
+            trash::soft_delete(doc, &birds.clone().index(0), 1000)?;
+            trash::soft_delete(doc, &birds.clone().index(0), 9000)
+        })
+        .unwrap();
+    let policy = ExpiryPolicy { max_age: 5000 };
+    let (purged, _) = frontend
+        .change::<_, _, InvalidChangeRequest>(None, |doc| trash::purge(doc, &policy, 10_000))
+        .unwrap();
+    assert_eq!(purged.len(), 1);
+    let remaining = frontend.trash();
+    assert_eq!(remaining.len(), 1);
+    assert_eq!(remaining[0].value, "robin".into());
+}
+
+#[test]
+fn test_grapheme_text_indexing() {
+    // Text written by an implementation which uses one element per unicode scalar value
+    let actor = amp::ActorId::random();
+    let text_id = actor.op_id_at(1);
+    let change = amp::Change {
+        actor_id: actor.clone(),
+        seq: 1,
+        start_op: 1,
+        time: 0,
+        message: None,
+        hash: None,
+        deps: Vec::new(),
+        operations: vec![
+            amp::Op {
+                action: amp::OpType::Make(amp::ObjType::Text),
+                obj: amp::ObjectId::Root,
+                key: "text".into(),
+                insert: false,
+                pred: SortedVec::new(),
+            },
+            amp::Op {
+                action: amp::OpType::MultiSet(
+                    vec!["a", "e", "\u{301}", "🇬", "🇧", "b"]
+                        .into_iter()
+                        .map(amp::ScalarValue::from)
+                        .collect::<Vec<_>>()
+                        .try_into()
+                        .unwrap(),
+                ),
+                obj: text_id.into(),
+                key: amp::ElementId::Head.into(),
+                insert: true,
+                pred: SortedVec::new(),
+            },
+        ],
+        extra_bytes: Vec::new(),
+    };
+    let mut backend = automerge_backend::Backend::new();
+    let patch = backend
+        .apply_changes(vec![change.try_into().unwrap()])
+        .unwrap();
+    let text = Path::root().key("text");
+    let mut frontend = Frontend::new();
+    frontend.apply_patch(patch).unwrap();
+    assert_eq!(frontend.length_at(&text), Some(6));
+
+    frontend.set_text_indexing(TextIndexing::Graphemes);
+    assert_eq!(frontend.length_at(&text), Some(4));
+    let (_, change) = frontend
+        .change::<_, _, InvalidChangeRequest>(None, |doc| {
+            // replace the flag, the third grapheme
+            doc.add_change(LocalChange::splice_text(text.clone(), 2, 1, "🇫🇷"))
+        })
+        .unwrap();
+    assert_eq!(
+        change.unwrap().operations[0].action,
+        amp::OpType::Del(NonZeroU32::new(2).unwrap())
+    );
+    assert_eq!(frontend.text_at(&text), Some("ae\u{301}🇫🇷b".to_string()));
+    assert_eq!(frontend.length_at(&text), Some(4));
+
+    let result = frontend.change::<_, _, InvalidChangeRequest>(None, |doc| {
+        doc.add_change(LocalChange::splice_text(text.clone(), 3, 2, ""))
+    });
+    assert!(matches!(
+        result,
+        Err(InvalidChangeRequest::MissingIndexError { .. })
+    ));
+
+    // a grapheme index past the end isn't taken as an element index
+    let result = frontend.change::<_, _, InvalidChangeRequest>(None, |doc| {
+        doc.add_change(LocalChange::splice_text(text.clone(), 5, 0, "X"))
+    });
+    match result {
+        Err(InvalidChangeRequest::MissingIndexError { source }) => {
+            assert_eq!((source.missing_index, source.size_of_collection), (5, 4))
+        }
+        other => panic!("expected a missing index error, got {:?}", other),
+    }
+    assert_eq!(frontend.text_at(&text), Some("ae\u{301}🇫🇷b".to_string()));
+}
+
+#[test]
+fn test_serde_value_round_trip() {
+    use automerge_frontend::serde_value::{self, from_value, to_value};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    enum Status {
+        Open,
+        Snoozed { until: u64 },
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Task {
+        title: String,
+        tags: Vec<String>,
+        #[serde(with = "serde_value::timestamp")]
+        created: i64,
+        #[serde(with = "serde_value::counter")]
+        votes: i64,
+        #[serde(with = "serde_value::bytes")]
+        thumbnail: Vec<u8>,
+        assignee: Option<String>,
+        status: Status,
+    }
+
+    let task = Task {
+        title: "Refill feeder".into(),
+        tags: vec!["garden".into()],
+        created: 1_600_000_000_000,
+        votes: 2,
+        thumbnail: vec![1, 2, 3],
+        assignee: None,
+        status: Status::Snoozed { until: 7 },
+    };
+    let value = to_value(&task).unwrap();
+    assert_eq!(
+        value,
+        Value::Map(hashmap! {
+            "title".into() => "Refill feeder".into(),
+            "tags".into() => Value::List(vec!["garden".into()]),
+            "created".into() => Value::Primitive(Primitive::Timestamp(1_600_000_000_000)),
+            "votes".into() => Value::Primitive(Primitive::Counter(2)),
+            "thumbnail".into() => Value::Primitive(Primitive::Bytes(vec![1, 2, 3])),
+            "assignee".into() => Value::Primitive(Primitive::Null),
+            "status".into() => Value::Map(hashmap! {
+                "Snoozed".into() => Value::Map(hashmap! {
+                    "until".into() => Value::Primitive(Primitive::Uint(7)),
+                }),
+            }),
+        })
+    );
+
+    // Values read back out of a document deserialize too, text becomes a string
+    let mut doc = Frontend::new();
+    doc.change::<_, _, InvalidChangeRequest>(None, |d| {
+        d.add_change(LocalChange::set(Path::root().key("task"), value))?;
+        d.add_change(LocalChange::set(
+            Path::root().key("task").key("title"),
+            Value::Text("Fill feeder".graphemes(true).map(|s| s.into()).collect()),
+        ))?;
+        d.add_change(LocalChange::set(
+            Path::root().key("task").key("status"),
+            "Open",
+        ))
+    })
+    .unwrap();
+    let task: Task = from_value(doc.get_value(&Path::root().key("task")).unwrap()).unwrap();
+    assert_eq!(task.title, "Fill feeder");
+    assert_eq!(task.votes, 2);
+    assert_eq!(task.status, Status::Open);
+    assert_eq!(task.thumbnail, vec![1, 2, 3]);
+
+    // the attributes are transparent to other formats
+    let json = serde_json::to_string(&task).unwrap();
+    assert_eq!(serde_json::from_str::<Task>(&json).unwrap(), task);
+
+    assert!(from_value::<Task>(Value::Primitive(Primitive::Int(1))).is_err());
+}
+
+#[test]
+fn test_value_and_diff_kinds() {
+    use automerge_frontend::ValueKind;
+
+    let mut doc = Frontend::new();
+    doc.change::<_, _, InvalidChangeRequest>(None, |d| {
+        d.add_change(LocalChange::set(
+            Path::root().key("birds"),
+            Value::List(vec!["wren".into(), "robin".into()]),
+        ))?;
+        d.add_change(LocalChange::set(
+            Path::root().key("note"),
+            Value::Text(vec!["h".into(), "i".into()]),
+        ))
+    })
+    .unwrap();
+
+    let root = doc.get_value(&Path::root()).unwrap();
+    assert_eq!(root.kind(), ValueKind::Map);
+    let mut counts: HashMap<ValueKind, usize> = HashMap::new();
+    for value in root.map().unwrap().values() {
+        *counts.entry(value.kind()).or_default() += 1;
+    }
+    assert_eq!(
+        counts,
+        hashmap! { ValueKind::List => 1, ValueKind::Text => 1 }
+    );
+    assert_eq!(
+        Value::Primitive(Primitive::Null).kind().to_string(),
+        "Primitive"
+    );
+
+    assert_eq!(amp::Diff::from("wren").kind(), amp::DiffKind::Value);
+    assert_eq!(
+        amp::ScalarValue::Counter(1).kind(),
+        amp::ScalarValueKind::Counter
+    );
+}
+
+#[test]
+fn counters_saturate_instead_of_overflowing() {
+    let mut doc = Frontend::new();
+    let wrens = Path::root().key("wrens");
+    doc.change::<_, _, InvalidChangeRequest>(None, |doc| {
+        doc.add_change(LocalChange::set(
+            wrens.clone(),
+            Value::Primitive(Primitive::Counter(i64::MAX - 1)),
+        ))?;
+        doc.add_change(LocalChange::increment_by(wrens.clone(), 5))
+    })
+    .unwrap();
+    let counter = match doc.get_value(&wrens) {
+        Some(Value::Primitive(p)) => p,
+        other => panic!("expected a counter, got {:?}", other),
+    };
+    assert_eq!(counter, Primitive::Counter(i64::MAX));
+    assert!(counter.is_saturated_counter());
+    assert!(!Primitive::Counter(i64::MAX - 1).is_saturated_counter());
+    assert!(!Primitive::Int(i64::MAX).is_saturated_counter());
+}
+
+#[test]
+fn test_text_boundaries() {
+    let text = Path::root().key("text");
+    let mut frontend = Frontend::new();
+    frontend
+        .change::<_, _, InvalidChangeRequest>(None, |doc| {
+            doc.add_change(LocalChange::set(text.clone(), Value::Text(Vec::new())))?;
+            doc.add_change(LocalChange::insert_str(
+                text.clone(),
+                0,
+                "Double click me. Then 🇬🇧 go",
+            ))
+        })
+        .unwrap();
+
+    assert_eq!(frontend.word_at(&text, 9), Some(7..12));
+    assert_eq!(frontend.word_at(&text, 6), Some(6..7));
+    assert_eq!(frontend.word_at(&text, 22), Some(22..23));
+    assert_eq!(frontend.word_at(&text, 26), None);
+    assert_eq!(frontend.word_at(&Path::root().key("missing"), 0), None);
+
+    assert_eq!(frontend.next_boundary(&text, 0, Granularity::Word), Some(6));
+    assert_eq!(frontend.next_boundary(&text, 6, Granularity::Word), Some(7));
+    assert_eq!(
+        frontend.next_boundary(&text, 25, Granularity::Word),
+        Some(26)
+    );
+    assert_eq!(frontend.next_boundary(&text, 26, Granularity::Word), None);
+    assert_eq!(
+        frontend.previous_boundary(&text, 9, Granularity::Word),
+        Some(7)
+    );
+    assert_eq!(
+        frontend.previous_boundary(&text, 7, Granularity::Word),
+        Some(6)
+    );
+    assert_eq!(
+        frontend.previous_boundary(&text, 0, Granularity::Word),
+        None
+    );
+
+    assert_eq!(
+        frontend.segment_at(&text, 3, Granularity::Sentence),
+        Some(0..17)
+    );
+    assert_eq!(
+        frontend.next_boundary(&text, 3, Granularity::Sentence),
+        Some(17)
+    );
+    assert_eq!(
+        frontend.previous_boundary(&text, 20, Granularity::Sentence),
+        Some(17)
+    );
+}
+
+#[test]
+fn test_is_empty() {
+    let mut frontend = Frontend::new();
+    assert!(frontend.is_empty());
+
+    // a change which does nothing makes no op, but it is still a change
+    frontend
+        .change::<_, _, InvalidChangeRequest>(None, |_| Ok(()))
+        .unwrap();
+    assert!(frontend.is_empty());
+    frontend
+        .change::<_, _, InvalidChangeRequest>(None, |doc| {
+            doc.add_change(LocalChange::set(
+                Path::root().key("key"),
+                Value::Primitive(Primitive::Str("value".into())),
+            ))
+        })
+        .unwrap();
+    assert!(!frontend.is_empty());
+}
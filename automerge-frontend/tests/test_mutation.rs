@@ -1,7 +1,9 @@
 use std::{collections::HashMap, convert::TryInto};
 
 use amp::SortedVec;
-use automerge_frontend::{Frontend, InvalidChangeRequest, LocalChange, Path, Value};
+use automerge_frontend::{
+    Frontend, InvalidChangeRequest, InvalidPath, LocalChange, Path, Value, ValueKind,
+};
 use automerge_protocol as amp;
 use maplit::hashmap;
 
@@ -189,7 +191,16 @@ fn test_delete_non_existent_root_key() {
         })
         .unwrap_err();
 
-    assert_eq!(cr, InvalidChangeRequest::NoSuchPathError { path })
+    assert_eq!(
+        cr,
+        InvalidChangeRequest::InvalidPath(InvalidPath {
+            path,
+            segment: "missing".to_string(),
+            nearest_ancestor: Path::root(),
+            expected: &[ValueKind::Map, ValueKind::Table],
+            actual: ValueKind::Map,
+        })
+    )
 }
 
 #[test]
@@ -207,5 +218,306 @@ fn test_delete_non_existent_map_key() {
         })
         .unwrap_err();
 
-    assert_eq!(cr, InvalidChangeRequest::NoSuchPathError { path })
+    assert_eq!(
+        cr,
+        InvalidChangeRequest::InvalidPath(InvalidPath {
+            path,
+            segment: "missing".to_string(),
+            nearest_ancestor: Path::root().key("a"),
+            expected: &[ValueKind::Map, ValueKind::Table],
+            actual: ValueKind::Map,
+        })
+    )
+}
+
+#[test]
+fn test_set_through_wrong_type() {
+    let mut frontend = Frontend::new();
+    let path = Path::root().key("birds").key("wrens").key("count");
+    let cr = frontend
+        .change::<_, _, InvalidChangeRequest>(None, |doc| {
+            doc.add_change(LocalChange::set(
+                Path::root().key("birds"),
+                Value::List(vec!["wren".into()]),
+            ))?;
+            doc.add_change(LocalChange::set(path.clone(), 3i64))?;
+            Ok(())
+        })
+        .unwrap_err();
+
+    assert_eq!(
+        cr,
+        InvalidChangeRequest::InvalidPath(InvalidPath {
+            path,
+            segment: "wrens".to_string(),
+            nearest_ancestor: Path::root().key("birds"),
+            expected: &[ValueKind::Map, ValueKind::Table],
+            actual: ValueKind::List,
+        })
+    )
+}
+
+#[test]
+fn test_set_create_missing() {
+    let mut frontend = Frontend::new();
+    let path = Path::root().key("birds").key("wrens").key("count");
+    frontend
+        .change::<_, _, InvalidChangeRequest>(None, |doc| {
+            doc.add_change(LocalChange::set(
+                Path::root().key("birds"),
+                Value::Map(HashMap::new()),
+            ))?;
+            doc.add_change(LocalChange::set(path.clone(), 3i64).create_missing())?;
+            // nothing is missing the second time
+            doc.add_change(LocalChange::set(path.clone(), 4i64).create_missing())?;
+            Ok(())
+        })
+        .unwrap();
+    assert_eq!(frontend.get_value(&path), Some(4i64.into()));
+
+    let cr = frontend
+        .change::<_, _, InvalidChangeRequest>(None, |doc| {
+            doc.add_change(
+                LocalChange::set(Path::root().key("lists").index(0).key("a"), 1i64)
+                    .create_missing(),
+            )
+        })
+        .unwrap_err();
+    assert!(matches!(
+        cr,
+        InvalidChangeRequest::InvalidPath(InvalidPath { ref segment, .. }) if segment == "lists"
+    ));
+}
+
+#[test]
+fn test_retain_in_list_merges_deletes() {
+    let mut backend = automerge_backend::Backend::new();
+    let mut frontend = Frontend::new();
+    let (_, change) = frontend
+        .change::<_, _, InvalidChangeRequest>(None, |doc| {
+            doc.add_change(LocalChange::set(
+                Path::root().key("birds"),
+                Value::List(
+                    ["wren", "robin", "jay", "wren", "crow", "rook"]
+                        .iter()
+                        .map(|b| (*b).into())
+                        .collect(),
+                ),
+            ))?;
+            Ok(())
+        })
+        .unwrap();
+    let (patch, _) = backend.apply_local_change(change.unwrap()).unwrap();
+    frontend.apply_patch(patch).unwrap();
+
+    let (_, change) = frontend
+        .change::<_, _, InvalidChangeRequest>(None, |doc| {
+            doc.retain_in_list(&Path::root().key("birds"), &mut |v| {
+                v == &Value::from("wren")
+            })
+        })
+        .unwrap();
+    let change = change.unwrap();
+    let counts: Vec<_> = change
+        .operations
+        .iter()
+        .map(|op| match op.action {
+            amp::OpType::Del(n) => n.get(),
+            _ => panic!("expected only deletes, got {:?}", op),
+        })
+        .collect();
+    assert_eq!(counts, vec![2, 2]);
+    assert_eq!(
+        frontend.get_value(&Path::root().key("birds")),
+        Some(Value::List(vec!["wren".into(), "wren".into()]))
+    );
+
+    // the backend agrees with the optimistic state
+    let (patch, _) = backend.apply_local_change(change).unwrap();
+    frontend.apply_patch(patch).unwrap();
+    assert_eq!(
+        frontend.get_value(&Path::root().key("birds")),
+        Some(Value::List(vec!["wren".into(), "wren".into()]))
+    );
+    let mut other = Frontend::new();
+    other.apply_patch(backend.get_patch().unwrap()).unwrap();
+    assert_eq!(
+        other.get_value(&Path::root().key("birds")),
+        Some(Value::List(vec!["wren".into(), "wren".into()]))
+    );
+
+    assert_eq!(
+        frontend
+            .change::<_, _, InvalidChangeRequest>(None, |doc| {
+                doc.retain_in_list(&Path::root(), &mut |_| true)
+            })
+            .unwrap_err(),
+        InvalidChangeRequest::RetainForNonSequenceObject { path: Path::root() }
+    );
+}
+
+#[test]
+fn test_move_item_keeps_concurrent_edits() {
+    let birds = Path::root().key("birds");
+    let mut backend1 = automerge_backend::Backend::new();
+    let mut frontend1 = Frontend::new();
+    let (_, change) = frontend1
+        .change::<_, _, InvalidChangeRequest>(None, |doc| {
+            doc.add_change(LocalChange::set(
+                birds.clone(),
+                Value::List(vec![
+                    "wren".into(),
+                    "robin".into(),
+                    Value::Map(hashmap! {"name".into() => "jay".into()}),
+                ]),
+            ))
+        })
+        .unwrap();
+    let (patch, initial) = backend1.apply_local_change(change.unwrap()).unwrap();
+    let initial = initial.clone();
+    frontend1.apply_patch(patch).unwrap();
+
+    let mut backend2 = automerge_backend::Backend::new();
+    backend2.apply_changes(vec![initial]).unwrap();
+    let mut frontend2 = Frontend::new();
+    frontend2
+        .apply_patch(backend2.get_patch().unwrap())
+        .unwrap();
+
+    // frontend1 moves the jay to the front while frontend2 renames it
+    let (_, change) = frontend1
+        .change::<_, _, InvalidChangeRequest>(None, |doc| {
+            doc.add_change(LocalChange::move_item(birds.clone(), 2, 0))
+        })
+        .unwrap();
+    let change = change.unwrap();
+    assert_eq!(change.operations.len(), 1);
+    assert!(matches!(change.operations[0].action, amp::OpType::Move(_)));
+    assert_eq!(
+        frontend1.get_value(&birds.clone().index(0).key("name")),
+        Some("jay".into())
+    );
+    let (patch, moved) = backend1.apply_local_change(change).unwrap();
+    let moved = moved.clone();
+    frontend1.apply_patch(patch).unwrap();
+
+    let (_, change) = frontend2
+        .change::<_, _, InvalidChangeRequest>(None, |doc| {
+            doc.add_change(LocalChange::set(
+                birds.clone().index(2).key("name"),
+                "blue jay",
+            ))
+        })
+        .unwrap();
+    let (patch, renamed) = backend2.apply_local_change(change.unwrap()).unwrap();
+    let renamed = renamed.clone();
+    frontend2.apply_patch(patch).unwrap();
+
+    frontend1
+        .apply_patch(backend1.apply_changes(vec![renamed]).unwrap())
+        .unwrap();
+    frontend2
+        .apply_patch(backend2.apply_changes(vec![moved]).unwrap())
+        .unwrap();
+    let expected = Value::List(vec![
+        Value::Map(hashmap! {"name".into() => "blue jay".into()}),
+        "wren".into(),
+        "robin".into(),
+    ]);
+    assert_eq!(frontend1.get_value(&birds), Some(expected.clone()));
+    assert_eq!(frontend2.get_value(&birds), Some(expected));
+
+    // inserting after the moved element puts the new element next to its new position
+    let (_, change) = frontend2
+        .change::<_, _, InvalidChangeRequest>(None, |doc| {
+            doc.add_change(LocalChange::insert(birds.clone().index(1), "owl".into()))
+        })
+        .unwrap();
+    let (patch, _) = backend2.apply_local_change(change.unwrap()).unwrap();
+    frontend2.apply_patch(patch).unwrap();
+    let mut loaded = Frontend::new();
+    loaded.apply_patch(backend2.get_patch().unwrap()).unwrap();
+    for frontend in &[frontend2, loaded] {
+        assert_eq!(
+            frontend.get_value(&birds),
+            Some(Value::List(vec![
+                Value::Map(hashmap! {"name".into() => "blue jay".into()}),
+                "owl".into(),
+                "wren".into(),
+                "robin".into(),
+            ]))
+        );
+    }
+
+    assert!(matches!(
+        frontend1
+            .change::<_, _, InvalidChangeRequest>(None, |doc| {
+                doc.add_change(LocalChange::move_item(birds.clone(), 0, 3))
+            })
+            .unwrap_err(),
+        InvalidChangeRequest::MissingIndexError { .. }
+    ));
+}
+
+#[test]
+fn test_unique_list_collapses_concurrent_adds() {
+    use automerge_frontend::UniqueList;
+
+    let tags = UniqueList::new(Path::root().key("tags"));
+    let mut backend = automerge_backend::Backend::new();
+    let mut doc1 = Frontend::new();
+    let (_, change) = doc1
+        .change::<_, _, InvalidChangeRequest>(None, |doc| {
+            doc.add_change(LocalChange::set(
+                tags.path().clone(),
+                Value::List(Vec::new()),
+            ))
+        })
+        .unwrap();
+    let (patch, _) = backend.apply_local_change(change.unwrap()).unwrap();
+    doc1.apply_patch(patch).unwrap();
+    let mut doc2 = Frontend::new();
+    doc2.apply_patch(backend.get_patch().unwrap()).unwrap();
+
+    // both actors add "urgent" concurrently
+    for doc in [&mut doc1, &mut doc2].iter_mut() {
+        let (added, change) = doc
+            .change::<_, _, InvalidChangeRequest>(None, |d| tags.insert(d, "urgent".into()))
+            .unwrap();
+        assert!(added);
+        backend.apply_local_change(change.unwrap()).unwrap();
+    }
+    let (added, change) = doc1
+        .change::<_, _, InvalidChangeRequest>(None, |d| tags.insert(d, "urgent".into()))
+        .unwrap();
+    assert!(!added);
+    assert!(change.is_none());
+
+    let mut merged = Frontend::new();
+    merged.apply_patch(backend.get_patch().unwrap()).unwrap();
+    assert_eq!(
+        merged.get_value(tags.path()),
+        Some(Value::List(vec!["urgent".into(), "urgent".into()]))
+    );
+    assert_eq!(tags.values(&merged), Some(vec!["urgent".into()]));
+    assert!(tags.contains(&merged, &"urgent".into()));
+
+    let (removed, _) = merged
+        .change::<_, _, InvalidChangeRequest>(None, |d| tags.dedup(d))
+        .unwrap();
+    assert_eq!(removed, 1);
+    assert_eq!(
+        merged.get_value(tags.path()),
+        Some(Value::List(vec!["urgent".into()]))
+    );
+
+    let (found, _) = merged
+        .change::<_, _, InvalidChangeRequest>(None, |d| tags.remove(d, &"urgent".into()))
+        .unwrap();
+    assert!(found);
+    assert_eq!(tags.values(&merged), Some(Vec::new()));
+    assert_eq!(
+        UniqueList::new(Path::root().key("missing")).values(&merged),
+        None
+    );
 }
@@ -0,0 +1,93 @@
+#![cfg(feature = "patch-stats")]
+
+use automerge_backend::{Backend, Change};
+use automerge_frontend::{Frontend, InvalidChangeRequest, LocalChange, Path, Primitive, Value};
+use pretty_assertions::assert_eq;
+
+/// Make a change on `doc` and return the binary change from its backend
+fn change(doc: &mut Frontend, backend: &mut Backend, local: LocalChange) -> Change {
+    let change = doc
+        .change::<_, _, InvalidChangeRequest>(None, |d| d.add_change(local))
+        .unwrap()
+        .1
+        .unwrap();
+    let (patch, change) = backend.apply_local_change(change).unwrap();
+    let change = change.clone();
+    doc.apply_patch(patch).unwrap();
+    change
+}
+
+fn set_bird(name: &str) -> LocalChange {
+    LocalChange::set(Path::root().key("bird"), Primitive::Str(name.into()))
+}
+
+#[test]
+fn counts_inserted_elements_and_created_nodes() {
+    let mut writer = Frontend::new();
+    let mut writer_backend = Backend::new();
+    let mut reader = Frontend::new();
+    let mut reader_backend = Backend::new();
+
+    let birds = Value::List(vec!["wren".into(), "robin".into(), "jay".into()]);
+    let birds = change(
+        &mut writer,
+        &mut writer_backend,
+        LocalChange::set(Path::root().key("birds"), birds),
+    );
+    let patch = reader_backend.apply_changes(vec![birds]).unwrap();
+    reader.apply_patch(patch).unwrap();
+    let stats = reader.last_patch_stats();
+    assert_eq!(stats.elements_inserted, 3);
+    assert_eq!(stats.elements_removed, 0);
+    // the list and its three elements
+    assert_eq!(stats.nodes_created, 4);
+    assert_eq!(stats.conflicts_resolved, 0);
+    assert!(stats.estimated_allocations() >= 7);
+
+    let removal = change(
+        &mut writer,
+        &mut writer_backend,
+        LocalChange::delete(Path::root().key("birds").index(0)),
+    );
+    let patch = reader_backend.apply_changes(vec![removal]).unwrap();
+    reader.apply_patch(patch).unwrap();
+    let stats = reader.last_patch_stats();
+    assert_eq!(stats.elements_inserted, 0);
+    assert_eq!(stats.elements_removed, 1);
+}
+
+#[test]
+fn counts_resolved_conflicts() {
+    let mut alice = Frontend::new();
+    let mut alice_backend = Backend::new();
+    let mut bob = Frontend::new();
+    let mut bob_backend = Backend::new();
+    let mut reader = Frontend::new();
+    let mut reader_backend = Backend::new();
+
+    let magpie = change(&mut alice, &mut alice_backend, set_bird("magpie"));
+    let jay = change(&mut bob, &mut bob_backend, set_bird("jay"));
+    let patch = reader_backend
+        .apply_changes(vec![magpie, jay.clone()])
+        .unwrap();
+    reader.apply_patch(patch).unwrap();
+    assert_eq!(reader.last_patch_stats().conflicts_resolved, 0);
+    assert_eq!(
+        reader
+            .get_conflicts(&Path::root().key("bird"))
+            .unwrap()
+            .len(),
+        2
+    );
+
+    let patch = alice_backend.apply_changes(vec![jay]).unwrap();
+    alice.apply_patch(patch).unwrap();
+    let robin = change(&mut alice, &mut alice_backend, set_bird("robin"));
+    let patch = reader_backend.apply_changes(vec![robin]).unwrap();
+    reader.apply_patch(patch).unwrap();
+    assert_eq!(reader.last_patch_stats().conflicts_resolved, 1);
+    assert_eq!(
+        reader.get_value(&Path::root().key("bird")),
+        Some(Value::Primitive(Primitive::Str("robin".into())))
+    );
+}
@@ -0,0 +1,150 @@
+#![cfg(feature = "arrow")]
+
+use std::{collections::HashMap, sync::Arc};
+
+use arrow_array::{cast::AsArray, types::Int64Type, Array, Int32Array, RecordBatch, StringArray};
+use arrow_schema::{DataType, Field, Schema};
+use automerge_frontend::{
+    record_batch::{self, RecordBatchError, COUNTER_DATATYPE, DATATYPE_KEY, ID_COLUMN},
+    Frontend, InvalidChangeRequest, LocalChange, Path, Primitive, Value, ValueKind,
+};
+use maplit::hashmap;
+use pretty_assertions::assert_eq;
+
+fn row(props: HashMap<&str, Primitive>) -> Value {
+    Value::Map(
+        props
+            .into_iter()
+            .map(|(k, v)| (k.into(), Value::Primitive(v)))
+            .collect(),
+    )
+}
+
+fn birds() -> Vec<Value> {
+    vec![
+        row(hashmap! {
+            "name" => Primitive::Str("magpie".into()),
+            "seen" => Primitive::Timestamp(1_600_000_000_000),
+            "count" => Primitive::Counter(3),
+        }),
+        row(hashmap! {
+            "name" => Primitive::Str("wren".into()),
+            "wingspan" => Primitive::F64(15.5),
+        }),
+    ]
+}
+
+#[test]
+fn test_list_of_maps_round_trips() {
+    let batch = record_batch::to_record_batch(&Value::List(birds())).unwrap();
+
+    let schema = batch.schema();
+    let names: Vec<_> = schema.fields().iter().map(|f| f.name().as_str()).collect();
+    assert_eq!(names, vec!["count", "name", "seen", "wingspan"]);
+    let count = schema.field_with_name("count").unwrap();
+    assert_eq!(count.data_type(), &DataType::Int64);
+    assert_eq!(
+        count.metadata().get(DATATYPE_KEY).map(String::as_str),
+        Some(COUNTER_DATATYPE)
+    );
+    let counts = batch.column(0).as_primitive::<Int64Type>();
+    assert_eq!(counts.value(0), 3);
+    assert!(counts.is_null(1));
+
+    assert_eq!(record_batch::from_record_batch(&batch).unwrap(), birds());
+}
+
+#[test]
+fn test_table_rows_are_keyed_by_id() {
+    let table = Value::Table(
+        vec![
+            ("b".into(), birds()[1].clone()),
+            ("a".into(), birds()[0].clone()),
+        ]
+        .into_iter()
+        .collect(),
+    );
+    let batch = record_batch::to_record_batch(&table).unwrap();
+    assert_eq!(batch.schema().field(0).name(), ID_COLUMN);
+    let ids = batch.column(0).as_string::<i32>();
+    assert_eq!(ids.iter().collect::<Vec<_>>(), vec![Some("a"), Some("b")]);
+
+    let mut doc = Frontend::new();
+    doc.change::<_, _, InvalidChangeRequest>(None, |d| {
+        d.add_change(LocalChange::set(
+            Path::root().key("birds"),
+            Value::Table(HashMap::new()),
+        ))?;
+        for change in record_batch::set_rows(Path::root().key("birds"), &batch).unwrap() {
+            d.add_change(change)?;
+        }
+        Ok(())
+    })
+    .unwrap();
+    assert_eq!(doc.get_value(&Path::root().key("birds")), Some(table));
+}
+
+#[test]
+fn test_insert_rows_into_a_list() {
+    let schema = Schema::new(vec![
+        Field::new("name", DataType::Utf8, true),
+        Field::new("count", DataType::Int32, true),
+    ]);
+    let batch = RecordBatch::try_new(
+        Arc::new(schema),
+        vec![
+            Arc::new(StringArray::from(vec![Some("jay"), None])),
+            Arc::new(Int32Array::from(vec![Some(2), Some(5)])),
+        ],
+    )
+    .unwrap();
+
+    let mut doc = Frontend::new();
+    doc.change::<_, _, InvalidChangeRequest>(None, |d| {
+        d.add_change(LocalChange::set(
+            Path::root().key("birds"),
+            Value::List(Vec::new()),
+        ))?;
+        d.add_change(record_batch::insert_rows(Path::root().key("birds").index(0), &batch).unwrap())
+    })
+    .unwrap();
+    assert_eq!(
+        doc.get_value(&Path::root().key("birds")),
+        Some(Value::List(vec![
+            row(hashmap! {
+                "name" => Primitive::Str("jay".into()),
+                "count" => Primitive::Int(2),
+            }),
+            row(hashmap! { "count" => Primitive::Int(5) }),
+        ]))
+    );
+}
+
+#[test]
+fn test_rows_which_do_not_fit_in_columns() {
+    let mixed = Value::List(vec![
+        row(hashmap! { "count" => Primitive::Int(1) }),
+        row(hashmap! { "count" => Primitive::Str("two".into()) }),
+    ]);
+    assert!(matches!(
+        record_batch::to_record_batch(&mixed),
+        Err(RecordBatchError::MixedColumn { column, .. }) if column == "count"
+    ));
+
+    let nested = Value::List(vec![Value::Map(hashmap! {
+        "wings".into() => Value::List(Vec::new()),
+    })]);
+    assert_eq!(
+        record_batch::to_record_batch(&nested).unwrap_err(),
+        RecordBatchError::NestedObject {
+            column: "wings".into(),
+            found: ValueKind::List,
+        }
+    );
+
+    let batch = record_batch::to_record_batch(&Value::List(birds())).unwrap();
+    assert_eq!(
+        record_batch::set_rows(Path::root().key("birds"), &batch).unwrap_err(),
+        RecordBatchError::NoIdColumn
+    );
+}
@@ -0,0 +1,86 @@
+use automerge_frontend::{Frontend, InvalidChangeRequest, Path, SetDeepOptions, Value};
+use serde_json::json;
+
+fn set_deep(frontend: &mut Frontend, sets: Vec<(Path, Value)>, options: SetDeepOptions) {
+    frontend
+        .change::<_, _, InvalidChangeRequest>(None, |doc| {
+            for (path, value) in sets {
+                doc.set_deep_with(&path, value, options)?;
+            }
+            Ok(())
+        })
+        .unwrap();
+}
+
+#[test]
+fn set_deep_creates_maps_and_lists() {
+    let mut frontend = Frontend::new();
+    set_deep(
+        &mut frontend,
+        vec![
+            (
+                Path::root().key("birds").key("wrens").key("count"),
+                Value::from_json(&json!(3.0)),
+            ),
+            (
+                Path::root().key("sightings").index(0).key("name"),
+                "wren".into(),
+            ),
+            (
+                Path::root().key("sightings").index(1).key("name"),
+                "magpie".into(),
+            ),
+            (
+                Path::root().key("sightings").index(0).key("count"),
+                Value::from_json(&json!(2.0)),
+            ),
+        ],
+        SetDeepOptions::default(),
+    );
+    assert_eq!(
+        frontend.state().to_json(),
+        json!({
+            "birds": {"wrens": {"count": 3.0}},
+            "sightings": [{"name": "wren", "count": 2.0}, {"name": "magpie"}],
+        })
+    );
+}
+
+#[test]
+fn set_deep_with_indexes_as_keys() {
+    let mut frontend = Frontend::new();
+    let options = SetDeepOptions {
+        indexes_make_lists: false,
+    };
+    let server = Path::root().key("config").key("servers").index(0);
+    set_deep(
+        &mut frontend,
+        vec![
+            (server.clone().key("host"), "localhost".into()),
+            (server.key("port"), Value::from_json(&json!(8080.0))),
+        ],
+        options,
+    );
+    assert_eq!(
+        frontend.state().to_json(),
+        json!({"config": {"servers": {"0": {"host": "localhost", "port": 8080.0}}}})
+    );
+}
+
+#[test]
+fn set_deep_cannot_create_list_with_gaps() {
+    let mut frontend = Frontend::new();
+    let path = Path::root().key("sightings").index(2);
+    let err = frontend
+        .change::<_, _, InvalidChangeRequest>(None, |doc| {
+            doc.set_deep(&path.clone().key("name"), "wren".into())
+        })
+        .unwrap_err();
+    assert_eq!(
+        err,
+        InvalidChangeRequest::InsertPastEndOfSequence {
+            path: path.key("name"),
+            sequence_length: 0
+        }
+    );
+}
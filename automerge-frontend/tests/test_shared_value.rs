@@ -0,0 +1,46 @@
+#![cfg(feature = "im")]
+
+use automerge_frontend::{Frontend, LocalChange, Path, Primitive, SharedValue, Value};
+use maplit::hashmap;
+use pretty_assertions::assert_eq;
+
+#[test]
+fn test_shared_state_round_trips() {
+    let mut doc = Frontend::new();
+    doc.change::<_, _, automerge_frontend::InvalidChangeRequest>(None, |d| {
+        d.add_change(LocalChange::set(
+            Path::root().key("birds"),
+            Value::List(vec!["wren".into(), "magpie".into()]),
+        ))?;
+        d.add_change(LocalChange::set(
+            Path::root().key("title"),
+            Value::Text("hi".chars().map(|c| c.to_string().into()).collect()),
+        ))
+    })
+    .unwrap();
+
+    let snapshot = doc.shared_state();
+    let birds = snapshot.map().unwrap()["birds"].list().unwrap();
+    assert_eq!(
+        birds.iter().map(SharedValue::primitive).collect::<Vec<_>>(),
+        vec![
+            Some(&Primitive::Str("wren".into())),
+            Some(&Primitive::Str("magpie".into()))
+        ]
+    );
+    assert_eq!(snapshot.to_value(), *doc.state());
+
+    // Snapshots keep the value they were taken at
+    doc.change::<_, _, automerge_frontend::InvalidChangeRequest>(None, |d| {
+        d.add_change(LocalChange::set(Path::root().key("birds"), "none"))
+    })
+    .unwrap();
+    assert_eq!(snapshot.map().unwrap()["birds"].list().unwrap().len(), 2);
+    assert_eq!(
+        Value::from(doc.shared_state()),
+        Value::Map(hashmap! {
+            "birds".into() => "none".into(),
+            "title".into() => Value::Text(vec!["h".into(), "i".into()]),
+        })
+    );
+}
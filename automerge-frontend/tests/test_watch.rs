@@ -0,0 +1,64 @@
+#![cfg(feature = "tokio-watch")]
+
+use automerge_backend::Backend;
+use automerge_frontend::{
+    Frontend, InvalidChangeRequest, LocalChange, Path, Primitive, Value, WatchPublisher,
+};
+use automerge_protocol as amp;
+use maplit::hashmap;
+use pretty_assertions::assert_eq;
+
+fn set(doc: &mut Frontend, backend: &mut Backend, key: &str, value: &str) -> amp::Patch {
+    let change = doc
+        .change::<_, _, InvalidChangeRequest>(None, |d| {
+            d.add_change(LocalChange::set(
+                Path::root().key(key),
+                Primitive::Str(value.into()),
+            ))
+        })
+        .unwrap()
+        .1
+        .unwrap();
+    backend.apply_local_change(change).unwrap().0
+}
+
+#[test]
+fn publishes_values_on_patches() {
+    let mut backend = Backend::new();
+    let mut writer = Frontend::new();
+    let mut reader = Frontend::new();
+    let (mut publisher, mut root) = WatchPublisher::new(&reader);
+    let mut bird = publisher.subscribe_path(&reader, Path::root().key("bird"));
+    let fish = publisher.subscribe_path(&reader, Path::root().key("fish"));
+    assert_eq!(*root.borrow_and_update(), Value::Map(hashmap! {}));
+    assert_eq!(*bird.borrow_and_update(), None);
+
+    set(&mut writer, &mut backend, "bird", "magpie");
+    publisher
+        .apply_patch(&mut reader, backend.get_patch().unwrap())
+        .unwrap();
+    assert!(root.has_changed().unwrap());
+    assert_eq!(
+        *root.borrow_and_update(),
+        Value::Map(hashmap! {"bird".into() => Value::Primitive(Primitive::Str("magpie".into()))})
+    );
+    assert!(bird.has_changed().unwrap());
+    assert_eq!(
+        *bird.borrow_and_update(),
+        Some(Value::Primitive(Primitive::Str("magpie".into())))
+    );
+    // receivers for values which didn't change aren't notified
+    assert!(!fish.has_changed().unwrap());
+
+    // local changes are published with `publish`
+    let mut other_bird = publisher.subscribe_path(&reader, Path::root().key("bird"));
+    let change = set(&mut reader, &mut backend, "bird", "jay");
+    publisher.publish(&reader);
+    assert!(other_bird.has_changed().unwrap());
+    assert_eq!(
+        *other_bird.borrow_and_update(),
+        Some(Value::Primitive(Primitive::Str("jay".into())))
+    );
+    publisher.apply_patch(&mut reader, change).unwrap();
+    assert!(!other_bird.has_changed().unwrap());
+}
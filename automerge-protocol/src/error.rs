@@ -1,6 +1,6 @@
 use thiserror::Error;
 
-use crate::{DataType, ScalarValue, ScalarValueKind};
+use crate::{ActorId, DataType, ObjectId, OpId, ScalarValue, ScalarValueKind};
 
 #[derive(Error, Debug)]
 #[error("Invalid OpID: {0}")]
@@ -22,6 +22,10 @@ pub struct InvalidActorId(pub String);
 #[error("Invalid change hash slice: {0:?}")]
 pub struct InvalidChangeHashSlice(pub Vec<u8>);
 
+#[derive(Error, Debug, PartialEq)]
+#[error("Invalid change hash: {0}")]
+pub struct InvalidChangeHash(pub String);
+
 #[derive(Error, Debug, PartialEq)]
 #[error("Invalid scalar value, expected {expected} but received {unexpected}")]
 pub struct InvalidScalarValue {
@@ -38,3 +42,34 @@ pub enum InvalidScalarValues {
     #[error("Expected kind: `{0}` but got kind: `{1}`")]
     UnexpectedKind(ScalarValueKind, ScalarValueKind),
 }
+
+#[derive(Error, Debug, PartialEq)]
+pub enum InvalidPatch {
+    #[error("Object {object_id} is nested under op {op_id}, which didn't create it")]
+    ObjectIdMismatch { op_id: OpId, object_id: ObjectId },
+    #[error("Object {0} appears more than once in the patch")]
+    DuplicateObject(ObjectId),
+    #[error("Op {op_id} is newer than the patch's max op {max_op}")]
+    OpIdAboveMaxOp { op_id: OpId, max_op: u64 },
+    #[error("Seq {seq} of actor {actor} is ahead of its clock {clock}")]
+    SeqNotInClock {
+        actor: ActorId,
+        seq: u64,
+        clock: u64,
+    },
+    #[error("Empty remove at index {index} of {object_id}")]
+    EmptyRemove { object_id: ObjectId, index: u64 },
+    #[error("Empty insert at index {index} of {object_id}")]
+    EmptyInsert { object_id: ObjectId, index: u64 },
+    #[error("Mark span from {start} to {end} of {object_id} is empty")]
+    EmptyMarkSpan {
+        object_id: ObjectId,
+        start: u64,
+        end: u64,
+    },
+    #[error("A {edit} edit can't appear where it does in {object_id}")]
+    MisplacedEdit {
+        object_id: ObjectId,
+        edit: &'static str,
+    },
+}
@@ -0,0 +1,263 @@
+//! `JsonSchema` impls for the types whose serde impls are written by hand (see `serde_impls`).
+//!
+//! Each of these describes the JSON the type serializes to with a private stand-in type which
+//! has the same shape, so the derived schema of the stand-in can be used for the real type.
+
+use std::{borrow::Cow, collections::BTreeMap};
+
+use schemars::{
+    gen::SchemaGenerator,
+    schema::{InstanceType, Schema, SchemaObject, StringValidation},
+    JsonSchema,
+};
+use smol_str::SmolStr;
+
+use crate::{
+    ActorId, ChangeHash, CursorDiff, DataType, Diff, DiffEdit, ElementId, MapType,
+    MultiElementInsert, ObjectId, Op, OpId, OpType, RootDiff, ScalarValue, SequenceType,
+};
+
+fn string_matching(pattern: &str, description: &str) -> Schema {
+    SchemaObject {
+        instance_type: Some(InstanceType::String.into()),
+        string: Some(Box::new(StringValidation {
+            pattern: Some(pattern.to_owned()),
+            ..StringValidation::default()
+        })),
+        metadata: Some(Box::new(schemars::schema::Metadata {
+            description: Some(description.to_owned()),
+            ..schemars::schema::Metadata::default()
+        })),
+        ..SchemaObject::default()
+    }
+    .into()
+}
+
+macro_rules! string_schema {
+    ($type:ty, $name:literal, $pattern:expr, $description:literal) => {
+        impl JsonSchema for $type {
+            fn schema_name() -> String {
+                $name.to_owned()
+            }
+
+            fn schema_id() -> Cow<'static, str> {
+                Cow::Borrowed(concat!("automerge_protocol::", $name))
+            }
+
+            fn json_schema(_: &mut SchemaGenerator) -> Schema {
+                string_matching($pattern, $description)
+            }
+        }
+    };
+}
+
+string_schema!(
+    ActorId,
+    "ActorId",
+    "^([0-9a-f]{2})*$",
+    "The bytes of the actor ID as hex"
+);
+string_schema!(
+    ChangeHash,
+    "ChangeHash",
+    "^[0-9a-f]{64}$",
+    "The SHA-256 hash of a change as hex"
+);
+string_schema!(
+    OpId,
+    "OpId",
+    "^[0-9]+@([0-9a-f]{2})*$",
+    "The counter and actor of an operation, as \"counter@actor\""
+);
+string_schema!(
+    ObjectId,
+    "ObjectId",
+    "^(_root|[0-9]+@([0-9a-f]{2})*)$",
+    "\"_root\" or the ID of the operation which made the object"
+);
+string_schema!(
+    ElementId,
+    "ElementId",
+    "^(_head|[0-9]+@([0-9a-f]{2})*)$",
+    "\"_head\" or the ID of the operation which inserted the element"
+);
+
+/// Delegate the schema of `$type` to the stand-in type `$shape`
+macro_rules! shaped_like {
+    ($type:ty, $shape:ty, $name:literal) => {
+        impl JsonSchema for $type {
+            fn schema_name() -> String {
+                $name.to_owned()
+            }
+
+            fn schema_id() -> Cow<'static, str> {
+                Cow::Borrowed(concat!("automerge_protocol::", $name))
+            }
+
+            fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+                <$shape>::json_schema(gen)
+            }
+        }
+    };
+}
+
+/// An `OpType` serializes to just the `action` of the op, its data goes in other fields
+#[derive(JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[allow(dead_code)]
+enum Action {
+    MakeMap,
+    MakeTable,
+    MakeList,
+    MakeText,
+    Del,
+    Inc,
+    Set,
+    Move,
+    MarkBegin,
+    MarkEnd,
+}
+
+#[derive(JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[allow(dead_code)]
+struct OpShape {
+    action: OpType,
+    obj: ObjectId,
+    /// Set for ops on maps and tables
+    key: Option<SmolStr>,
+    /// Set for ops on lists and text
+    elem_id: Option<ElementId>,
+    insert: Option<bool>,
+    /// Only set for numeric values
+    datatype: Option<DataType>,
+    /// The value of `set`, `inc` and `markBegin` ops
+    value: Option<ScalarValue>,
+    /// The values of a `set` op which sets several consecutive elements
+    values: Option<Vec<ScalarValue>>,
+    /// The number of elements a `del` op deletes, if more than one
+    multi_op: Option<u32>,
+    /// The element a `move` op moves, or the `markBegin` op a `markEnd` op ends
+    #[serde(rename = "ref")]
+    reference: Option<OpId>,
+    /// The name of the mark started by a `markBegin` op
+    name: Option<SmolStr>,
+    pred: Vec<OpId>,
+}
+
+/// The `type` of a diff of a primitive value
+#[derive(JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[allow(dead_code)]
+enum ValueType {
+    Value,
+}
+
+/// The `datatype` of a cursor diff
+#[derive(JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[allow(dead_code)]
+enum CursorType {
+    Cursor,
+}
+
+/// The `objectId` of the root diff
+#[derive(JsonSchema)]
+#[allow(dead_code)]
+enum RootId {
+    #[serde(rename = "_root")]
+    Root,
+}
+
+/// The `type` of the root diff
+#[derive(JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[allow(dead_code)]
+enum RootType {
+    Map,
+}
+
+#[derive(JsonSchema)]
+#[schemars(rename = "MapDiff")]
+#[serde(rename_all = "camelCase")]
+#[allow(dead_code)]
+struct MapDiffShape {
+    object_id: ObjectId,
+    #[serde(rename = "type")]
+    map_type: MapType,
+    props: BTreeMap<SmolStr, BTreeMap<OpId, Diff>>,
+}
+
+#[derive(JsonSchema)]
+#[schemars(rename = "SequenceDiff")]
+#[serde(rename_all = "camelCase")]
+#[allow(dead_code)]
+struct SeqDiffShape {
+    object_id: ObjectId,
+    #[serde(rename = "type")]
+    seq_type: SequenceType,
+    edits: Vec<DiffEdit>,
+}
+
+#[derive(JsonSchema)]
+#[schemars(rename = "ValueDiff")]
+#[allow(dead_code)]
+struct ValueDiffShape {
+    #[serde(rename = "type")]
+    value_type: ValueType,
+    value: ScalarValue,
+    /// Only set for numeric values
+    datatype: Option<DataType>,
+}
+
+#[derive(JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[allow(dead_code)]
+struct CursorDiffShape {
+    ref_object_id: ObjectId,
+    elem_id: OpId,
+    index: u32,
+    datatype: CursorType,
+}
+
+#[derive(JsonSchema)]
+#[serde(untagged)]
+#[allow(dead_code)]
+enum DiffShape {
+    Map(MapDiffShape),
+    Seq(SeqDiffShape),
+    Value(ValueDiffShape),
+    Cursor(CursorDiff),
+}
+
+#[derive(JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[allow(dead_code)]
+struct RootDiffShape {
+    object_id: RootId,
+    #[serde(rename = "type")]
+    map_type: RootType,
+    props: BTreeMap<SmolStr, BTreeMap<OpId, Diff>>,
+}
+
+#[derive(JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[allow(dead_code)]
+struct MultiElementInsertShape {
+    index: u64,
+    elem_id: ElementId,
+    /// Only set for numeric values
+    datatype: Option<DataType>,
+    values: Vec<ScalarValue>,
+}
+
+shaped_like!(OpType, Action, "OpType");
+shaped_like!(Op, OpShape, "Op");
+shaped_like!(Diff, DiffShape, "Diff");
+shaped_like!(CursorDiff, CursorDiffShape, "CursorDiff");
+shaped_like!(RootDiff, RootDiffShape, "RootDiff");
+shaped_like!(
+    MultiElementInsert,
+    MultiElementInsertShape,
+    "MultiElementInsert"
+);
@@ -1,8 +1,25 @@
+//! The types which make up the protocol between the automerge frontend and backend.
+//!
+//! With `default-features = false` this crate has no source of randomness, so it builds for
+//! any target. The `random` feature (on by default) adds [`ActorId::random`], which on
+//! wasm32-unknown-unknown gets its randomness from javascript.
+//!
+//! The `testing` feature adds the [`testing`] module, proptest strategies for generating changes
+//! and patches.
+//!
+//! The `json-schema` feature implements `schemars::JsonSchema` for `Patch`, `Change` and the
+//! types they are made of, describing the JSON they serialize to.
+
 pub mod error;
+#[cfg(feature = "json-schema")]
+mod json_schema;
+mod patch_builder;
 mod serde_impls;
+#[cfg(feature = "testing")]
+pub mod testing;
 mod utility_impls;
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     convert::{TryFrom, TryInto},
     fmt,
     iter::FromIterator,
@@ -11,6 +28,7 @@ use std::{
 };
 
 use error::InvalidScalarValues;
+pub use patch_builder::{ObjectDiffBuilder, PatchBuilder};
 use serde::{
     de::{Error, MapAccess, Unexpected},
     Deserialize, Serialize,
@@ -37,6 +55,7 @@ impl fmt::Debug for ActorId {
 }
 
 impl ActorId {
+    #[cfg(feature = "random")]
     pub fn random() -> ActorId {
         ActorId(TinyVec::from(*uuid::Uuid::new_v4().as_bytes()))
     }
@@ -55,6 +74,7 @@ impl ActorId {
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Copy, Hash)]
+#[cfg_attr(feature = "derive-arbitrary", derive(arbitrary::Arbitrary))]
 #[serde(rename_all = "camelCase", untagged)]
 pub enum ObjType {
     Map,
@@ -100,6 +120,7 @@ impl fmt::Display for ObjType {
 
 #[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Copy, Hash)]
 #[cfg_attr(feature = "derive-arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "json-schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 pub enum MapType {
     Map,
@@ -107,6 +128,8 @@ pub enum MapType {
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Copy, Hash)]
+#[cfg_attr(feature = "derive-arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "json-schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 pub enum SequenceType {
     List,
@@ -145,6 +168,7 @@ pub enum ObjectId {
 }
 
 #[derive(PartialEq, Eq, Debug, Hash, Clone)]
+#[cfg_attr(feature = "derive-arbitrary", derive(arbitrary::Arbitrary))]
 pub enum ElementId {
     Head,
     Id(OpId),
@@ -178,6 +202,8 @@ impl ElementId {
 }
 
 #[derive(Serialize, PartialEq, Eq, Debug, Hash, Clone)]
+#[cfg_attr(feature = "derive-arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "json-schema", derive(schemars::JsonSchema))]
 #[serde(untagged)]
 pub enum Key {
     Map(SmolStr),
@@ -218,6 +244,7 @@ impl Key {
 }
 
 #[derive(Deserialize, Serialize, PartialEq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "json-schema", derive(schemars::JsonSchema))]
 pub enum DataType {
     #[serde(rename = "counter")]
     Counter,
@@ -337,8 +364,24 @@ impl ScalarValues {
     }
 }
 
+/// Values of a different kind to the first are skipped, a `ScalarValues` is never mixed
+#[cfg(feature = "derive-arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for ScalarValues {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        let first: ScalarValue = u.arbitrary()?;
+        let mut values = ScalarValues::new(first.kind());
+        values.vec.push(first);
+        for value in u.arbitrary_iter::<ScalarValue>()? {
+            let _ = values.append(value?);
+        }
+        Ok(values)
+    }
+}
+
 #[derive(Serialize, PartialEq, Debug, Clone, EnumDiscriminants)]
-#[strum_discriminants(name(ScalarValueKind))]
+#[cfg_attr(feature = "derive-arbitrary", derive(arbitrary::Arbitrary))]
+#[strum_discriminants(name(ScalarValueKind), derive(Hash))]
+#[cfg_attr(feature = "json-schema", derive(schemars::JsonSchema))]
 #[serde(untagged)]
 pub enum ScalarValue {
     Bytes(Vec<u8>),
@@ -354,6 +397,10 @@ pub enum ScalarValue {
 }
 
 impl ScalarValue {
+    pub fn kind(&self) -> ScalarValueKind {
+        ScalarValueKind::from(self)
+    }
+
     pub fn as_datatype(
         &self,
         datatype: DataType,
@@ -496,6 +543,7 @@ impl ScalarValue {
 }
 
 #[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "derive-arbitrary", derive(arbitrary::Arbitrary))]
 pub enum OpType {
     Make(ObjType),
     /// Perform a deletion, expanding the operation to cover `n` deletions (multiOp).
@@ -503,6 +551,32 @@ pub enum OpType {
     Inc(i64),
     Set(ScalarValue),
     MultiSet(ScalarValues),
+    /// Move the element with the given ID to a new position in the same sequence.
+    ///
+    /// A move is an insertion (`insert` is true and the key is the element to insert after)
+    /// which carries the element along with it, so edits made to the element concurrently with
+    /// the move are kept. If an element is moved concurrently by several actors the move with the
+    /// highest op ID wins.
+    Move(OpId),
+    /// Start a formatting span in a text object.
+    ///
+    /// Like a move this is an insertion (the key is the element the span starts after), but the
+    /// inserted element is never visible, it only marks a boundary. The characters between this
+    /// op and its `MarkEnd` have the mark `name` set to `value`. Setting a mark to null removes
+    /// it, which is how spans are unmarked. Where spans with the same name overlap the op with the
+    /// highest ID wins.
+    MarkBegin(MarkData),
+    /// End the span started by the `MarkBegin` op with the given ID. The key is the last character
+    /// in the span.
+    MarkEnd(OpId),
+}
+
+#[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "derive-arbitrary", derive(arbitrary::Arbitrary))]
+pub struct MarkData {
+    /// The kind of formatting, e.g. "bold" or "link"
+    pub name: SmolStr,
+    pub value: ScalarValue,
 }
 
 #[derive(Debug, Default, Clone, PartialEq, Serialize)]
@@ -553,6 +627,13 @@ impl<T: Ord> FromIterator<T> for SortedVec<T> {
     }
 }
 
+#[cfg(feature = "derive-arbitrary")]
+impl<'a, T: arbitrary::Arbitrary<'a> + Ord> arbitrary::Arbitrary<'a> for SortedVec<T> {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(Vec::<T>::arbitrary(u)?.into())
+    }
+}
+
 impl<T> IntoIterator for SortedVec<T> {
     type Item = T;
 
@@ -578,6 +659,7 @@ where
 }
 
 #[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "derive-arbitrary", derive(arbitrary::Arbitrary))]
 pub struct Op {
     pub action: OpType,
     pub obj: ObjectId,
@@ -608,6 +690,7 @@ impl Op {
 }
 
 #[derive(Eq, PartialEq, Hash, Clone, PartialOrd, Ord, Copy)]
+#[cfg_attr(feature = "derive-arbitrary", derive(arbitrary::Arbitrary))]
 pub struct ChangeHash(pub [u8; 32]);
 
 impl fmt::Debug for ChangeHash {
@@ -642,7 +725,9 @@ impl fmt::Debug for ChangeHash {
 //      }
 // }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, EnumDiscriminants)]
+#[cfg_attr(feature = "derive-arbitrary", derive(arbitrary::Arbitrary))]
+#[strum_discriminants(name(DiffKind), derive(Hash))]
 pub enum Diff {
     Map(MapDiff),
     Table(TableDiff),
@@ -653,6 +738,10 @@ pub enum Diff {
 }
 
 impl Diff {
+    pub fn kind(&self) -> DiffKind {
+        DiffKind::from(self)
+    }
+
     pub fn object_type(&self) -> Option<ObjType> {
         match self {
             Diff::Map(_) => Some(ObjType::Map),
@@ -664,6 +753,35 @@ impl Diff {
         }
     }
 
+    fn compact_text_edits(&mut self) {
+        match self {
+            Diff::Map(MapDiff { props, .. }) | Diff::Table(TableDiff { props, .. }) => {
+                for diff in props.values_mut().flat_map(BTreeMap::values_mut) {
+                    diff.compact_text_edits();
+                }
+            }
+            Diff::List(ListDiff { edits, .. }) => {
+                for edit in edits {
+                    if let DiffEdit::SingleElementInsert { value, .. }
+                    | DiffEdit::Update { value, .. } = edit
+                    {
+                        value.compact_text_edits();
+                    }
+                }
+            }
+            Diff::Text(TextDiff { edits, .. }) => {
+                for edit in edits {
+                    if let DiffEdit::MultiElementInsert(insert) = edit {
+                        if let Some(text_insert) = TextInsert::from_multi_insert(insert) {
+                            *edit = DiffEdit::TextInsert(text_insert);
+                        }
+                    }
+                }
+            }
+            Diff::Value(_) | Diff::Cursor(_) => {}
+        }
+    }
+
     pub fn object_id(&self) -> Option<ObjectId> {
         match self {
             Diff::Map(mapdiff) => Some(mapdiff.object_id.clone()),
@@ -677,20 +795,23 @@ impl Diff {
 }
 
 #[derive(Deserialize, Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "derive-arbitrary", derive(arbitrary::Arbitrary))]
 #[serde(rename_all = "camelCase")]
 pub struct MapDiff {
     pub object_id: ObjectId,
-    pub props: HashMap<SmolStr, HashMap<OpId, Diff>>,
+    pub props: BTreeMap<SmolStr, BTreeMap<OpId, Diff>>,
 }
 
 #[derive(Deserialize, Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "derive-arbitrary", derive(arbitrary::Arbitrary))]
 #[serde(rename_all = "camelCase")]
 pub struct TableDiff {
     pub object_id: ObjectId,
-    pub props: HashMap<SmolStr, HashMap<OpId, Diff>>,
+    pub props: BTreeMap<SmolStr, BTreeMap<OpId, Diff>>,
 }
 
 #[derive(Deserialize, Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "derive-arbitrary", derive(arbitrary::Arbitrary))]
 #[serde(rename_all = "camelCase")]
 pub struct ListDiff {
     pub object_id: ObjectId,
@@ -698,6 +819,7 @@ pub struct ListDiff {
 }
 
 #[derive(Deserialize, Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "derive-arbitrary", derive(arbitrary::Arbitrary))]
 #[serde(rename_all = "camelCase")]
 pub struct TextDiff {
     pub object_id: ObjectId,
@@ -713,6 +835,7 @@ pub struct ObjDiff {
 }
 
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "derive-arbitrary", derive(arbitrary::Arbitrary))]
 pub struct CursorDiff {
     pub object_id: ObjectId,
     pub elem_id: OpId,
@@ -720,6 +843,8 @@ pub struct CursorDiff {
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "derive-arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "json-schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase", tag = "action")]
 pub enum DiffEdit {
     /// Describes the insertion of a single element into a list or text object.
@@ -761,9 +886,42 @@ pub enum DiffEdit {
     },
     #[serde(rename_all = "camelCase")]
     Remove { index: u64, count: u64 },
+    /// A compact form of `MultiElementInsert` for runs of characters inserted into a text
+    /// object. Patches only contain these if they have been through
+    /// [`Patch::compact_text_edits`].
+    #[serde(rename = "text-insert")]
+    TextInsert(TextInsert),
+    /// The formatting of a text object. This comes after any other edits to the text object and
+    /// is sent whenever a patch changes the marks of the object or the characters they cover.
+    #[serde(rename = "marks")]
+    Marks(MarkDiff),
+}
+
+/// Every formatting span of a text object, replacing any spans the object previously had
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+#[cfg_attr(feature = "derive-arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "json-schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct MarkDiff {
+    pub marks: Vec<MarkSpan>,
+}
+
+/// A mark which applies to the characters from `start` up to (but not including) `end`.
+///
+/// Spans with the same name never overlap, and unmarked characters have no span.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "derive-arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "json-schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct MarkSpan {
+    pub start: u64,
+    pub end: u64,
+    pub name: SmolStr,
+    pub value: ScalarValue,
 }
 
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "derive-arbitrary", derive(arbitrary::Arbitrary))]
 pub struct MultiElementInsert {
     /// the list index at which to insert the first value
     pub index: u64,
@@ -772,7 +930,86 @@ pub struct MultiElementInsert {
     pub values: ScalarValues,
 }
 
+/// A run of consecutive characters inserted into a text object, stored as one string rather than
+/// one JSON string per character. As with `MultiElementInsert` the characters are given
+/// consecutive element IDs starting at `elem_id`.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "derive-arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "json-schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct TextInsert {
+    /// the index at which to insert the first character
+    pub index: u64,
+    /// the unique ID of the first inserted character
+    pub elem_id: ElementId,
+    pub text: String,
+    /// The number of `char`s in each inserted character. This is empty if every character is a
+    /// single `char`, which is the common case.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub lengths: Vec<u32>,
+}
+
+impl TextInsert {
+    /// Returns `None` if any of the values in `insert` is not a string
+    pub fn from_multi_insert(insert: &MultiElementInsert) -> Option<TextInsert> {
+        let mut text = String::new();
+        let mut lengths = Vec::with_capacity(insert.values.len());
+        for value in insert.values.iter() {
+            match value {
+                ScalarValue::Str(s) => {
+                    text.push_str(s);
+                    lengths.push(s.chars().count() as u32);
+                }
+                _ => return None,
+            }
+        }
+        if lengths.iter().all(|l| *l == 1) {
+            lengths.clear();
+        }
+        Some(TextInsert {
+            index: insert.index,
+            elem_id: insert.elem_id.clone(),
+            text,
+            lengths,
+        })
+    }
+
+    /// The inserted characters, or `None` if `lengths` does not match `text`
+    pub fn characters(&self) -> Option<Vec<&str>> {
+        if self.lengths.is_empty() {
+            return Some(
+                self.text
+                    .char_indices()
+                    .map(|(i, c)| &self.text[i..i + c.len_utf8()])
+                    .collect(),
+            );
+        }
+        let mut characters = Vec::with_capacity(self.lengths.len());
+        let mut rest = self.text.as_str();
+        for length in &self.lengths {
+            let end = match rest.char_indices().nth(*length as usize) {
+                Some((end, _)) => end,
+                None if rest.chars().count() == *length as usize => rest.len(),
+                None => return None,
+            };
+            let (character, tail) = rest.split_at(end);
+            if character.is_empty() {
+                return None;
+            }
+            characters.push(character);
+            rest = tail;
+        }
+        if rest.is_empty() {
+            Some(characters)
+        } else {
+            None
+        }
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+#[cfg_attr(feature = "derive-arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "json-schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 pub struct Patch {
     #[serde(skip_serializing_if = "Option::is_none", default)]
@@ -789,13 +1026,27 @@ pub struct Patch {
     pub diffs: RootDiff,
 }
 
+impl Patch {
+    /// Replace the runs of characters inserted into text objects with `DiffEdit::TextInsert`s,
+    /// which serialize to a fraction of the size. Only do this if whatever consumes the patch
+    /// understands `TextInsert`, as the Rust frontend does.
+    pub fn compact_text_edits(&mut self) {
+        for diff in self.diffs.props.values_mut().flat_map(BTreeMap::values_mut) {
+            diff.compact_text_edits();
+        }
+    }
+}
+
 /// A custom MapDiff that implicitly has the object_id Root and is a map object.
 #[derive(Debug, PartialEq, Clone, Default)]
+#[cfg_attr(feature = "derive-arbitrary", derive(arbitrary::Arbitrary))]
 pub struct RootDiff {
-    pub props: HashMap<SmolStr, HashMap<OpId, Diff>>,
+    pub props: BTreeMap<SmolStr, BTreeMap<OpId, Diff>>,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
+#[cfg_attr(feature = "derive-arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "json-schema", derive(schemars::JsonSchema))]
 pub struct Change {
     #[serde(rename = "ops")]
     pub operations: Vec<Op>,
@@ -809,7 +1060,7 @@ pub struct Change {
     pub time: i64,
     pub message: Option<String>,
     pub deps: Vec<ChangeHash>,
-    #[serde(skip_serializing_if = "Vec::is_empty", default = "Default::default")]
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
     pub extra_bytes: Vec<u8>,
 }
 
@@ -0,0 +1,329 @@
+//! Building patches by hand, for alternative backends and test fixtures.
+//!
+//! ```
+//! use automerge_protocol::{ActorId, ObjType, PatchBuilder, ObjectDiffBuilder};
+//!
+//! let actor = ActorId::from(&[1, 2, 3][..]);
+//! let list_id = actor.op_id_at(1);
+//! let birds = ObjectDiffBuilder::new(list_id.clone(), ObjType::List)
+//!     .insert(0, actor.op_id_at(2), "wren".into())
+//!     .insert(1, actor.op_id_at(3), "magpie".into())
+//!     .build();
+//! let patch = PatchBuilder::new()
+//!     .clock(actor.clone(), 1)
+//!     .max_op(3)
+//!     .prop("birds", list_id, birds)
+//!     .build()
+//!     .unwrap();
+//! assert_eq!(patch.diffs.props.len(), 1);
+//! ```
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use smol_str::SmolStr;
+
+use crate::{
+    error::InvalidPatch, ActorId, ChangeHash, Diff, DiffEdit, ListDiff, MapDiff, MarkDiff,
+    MarkSpan, ObjType, ObjectId, OpId, Patch, RootDiff, TableDiff, TextDiff,
+};
+
+/// Assembles a [`Patch`], checking the invariants the frontend relies on when it is built, see
+/// [`Patch::validate`].
+#[derive(Debug, Default, Clone)]
+pub struct PatchBuilder {
+    actor: Option<(ActorId, u64)>,
+    clock: HashMap<ActorId, u64>,
+    deps: Vec<ChangeHash>,
+    max_op: u64,
+    pending_changes: usize,
+    props: BTreeMap<SmolStr, BTreeMap<OpId, Diff>>,
+}
+
+impl PatchBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Make this the patch for a local change, `seq` of `actor`
+    pub fn actor(mut self, actor: ActorId, seq: u64) -> Self {
+        self.actor = Some((actor, seq));
+        self
+    }
+
+    pub fn clock(mut self, actor: ActorId, seq: u64) -> Self {
+        self.clock.insert(actor, seq);
+        self
+    }
+
+    pub fn deps(mut self, deps: Vec<ChangeHash>) -> Self {
+        self.deps = deps;
+        self
+    }
+
+    pub fn max_op(mut self, max_op: u64) -> Self {
+        self.max_op = max_op;
+        self
+    }
+
+    pub fn pending_changes(mut self, pending_changes: usize) -> Self {
+        self.pending_changes = pending_changes;
+        self
+    }
+
+    /// Set `key` in the root object to `diff`, as assigned by the op `op_id`. Setting the same
+    /// key more than once with different ops makes a conflict.
+    pub fn prop<K: Into<SmolStr>>(mut self, key: K, op_id: OpId, diff: Diff) -> Self {
+        self.props
+            .entry(key.into())
+            .or_default()
+            .insert(op_id, diff);
+        self
+    }
+
+    pub fn build(self) -> Result<Patch, InvalidPatch> {
+        let patch = Patch {
+            actor: self.actor.as_ref().map(|(actor, _)| actor.clone()),
+            seq: self.actor.as_ref().map(|(_, seq)| *seq),
+            clock: self.clock,
+            deps: self.deps,
+            max_op: self.max_op,
+            pending_changes: self.pending_changes,
+            diffs: RootDiff { props: self.props },
+        };
+        patch.validate()?;
+        Ok(patch)
+    }
+}
+
+/// Assembles the diff of a single map, table, list or text object
+#[derive(Debug, Clone)]
+pub struct ObjectDiffBuilder {
+    object_id: ObjectId,
+    obj_type: ObjType,
+    props: BTreeMap<SmolStr, BTreeMap<OpId, Diff>>,
+    edits: Vec<DiffEdit>,
+}
+
+impl ObjectDiffBuilder {
+    /// The diff of the object created by the op `object_id`
+    pub fn new(object_id: OpId, obj_type: ObjType) -> Self {
+        ObjectDiffBuilder {
+            object_id: object_id.into(),
+            obj_type,
+            props: BTreeMap::new(),
+            edits: Vec::new(),
+        }
+    }
+
+    /// Set `key` of a map or table
+    pub fn prop<K: Into<SmolStr>>(mut self, key: K, op_id: OpId, diff: Diff) -> Self {
+        self.props
+            .entry(key.into())
+            .or_default()
+            .insert(op_id, diff);
+        self
+    }
+
+    /// Insert a new element at `index` of a list or text object. The element ID is the ID of
+    /// the op which inserted it.
+    pub fn insert(mut self, index: u64, op_id: OpId, value: Diff) -> Self {
+        self.edits.push(DiffEdit::SingleElementInsert {
+            index,
+            elem_id: op_id.clone().into(),
+            op_id,
+            value,
+        });
+        self
+    }
+
+    pub fn update(mut self, index: u64, op_id: OpId, value: Diff) -> Self {
+        self.edits.push(DiffEdit::Update {
+            index,
+            op_id,
+            value,
+        });
+        self
+    }
+
+    pub fn remove(mut self, index: u64, count: u64) -> Self {
+        self.edits.push(DiffEdit::Remove { index, count });
+        self
+    }
+
+    /// The formatting spans of a text object
+    pub fn marks(mut self, marks: Vec<MarkSpan>) -> Self {
+        self.edits.push(DiffEdit::Marks(MarkDiff { marks }));
+        self
+    }
+
+    pub fn build(self) -> Diff {
+        let ObjectDiffBuilder {
+            object_id,
+            obj_type,
+            props,
+            edits,
+        } = self;
+        match obj_type {
+            ObjType::Map => Diff::Map(MapDiff { object_id, props }),
+            ObjType::Table => Diff::Table(TableDiff { object_id, props }),
+            ObjType::List => Diff::List(ListDiff { object_id, edits }),
+            ObjType::Text => Diff::Text(TextDiff { object_id, edits }),
+        }
+    }
+}
+
+impl Patch {
+    /// Check the invariants a frontend relies on when applying this patch:
+    ///
+    /// - every object is nested under the op which created it, so its object ID is that op's ID,
+    ///   and no object appears twice
+    /// - no op in the patch is newer than `max_op`
+    /// - sequence edits are well formed: inserts and removes are not empty, text-only edits
+    ///   only appear in text objects, and the marks of a text object come last with each span
+    ///   covering at least one character
+    /// - the seq of a local patch is in its clock
+    ///
+    /// The indices of sequence edits can't be checked without knowing the state the patch
+    /// applies to.
+    pub fn validate(&self) -> Result<(), InvalidPatch> {
+        if let (Some(actor), Some(seq)) = (&self.actor, self.seq) {
+            let clock = self.clock.get(actor).copied().unwrap_or(0);
+            if seq > clock {
+                return Err(InvalidPatch::SeqNotInClock {
+                    actor: actor.clone(),
+                    seq,
+                    clock,
+                });
+            }
+        }
+        let mut validator = Validator {
+            max_op: self.max_op,
+            seen: HashSet::new(),
+        };
+        validator.props(&self.diffs.props)
+    }
+}
+
+struct Validator {
+    max_op: u64,
+    seen: HashSet<ObjectId>,
+}
+
+impl Validator {
+    fn op_id(&self, op_id: &OpId) -> Result<(), InvalidPatch> {
+        if op_id.0 > self.max_op {
+            Err(InvalidPatch::OpIdAboveMaxOp {
+                op_id: op_id.clone(),
+                max_op: self.max_op,
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    fn props(
+        &mut self,
+        props: &BTreeMap<SmolStr, BTreeMap<OpId, Diff>>,
+    ) -> Result<(), InvalidPatch> {
+        for (op_id, diff) in props.values().flatten() {
+            self.value(op_id, diff)?;
+        }
+        Ok(())
+    }
+
+    /// The value `diff` set by `op_id`
+    fn value(&mut self, op_id: &OpId, diff: &Diff) -> Result<(), InvalidPatch> {
+        self.op_id(op_id)?;
+        let object_id = match diff {
+            Diff::Value(_) => return Ok(()),
+            Diff::Cursor(cursor) => return self.op_id(&cursor.elem_id),
+            Diff::Map(MapDiff { object_id, .. })
+            | Diff::Table(TableDiff { object_id, .. })
+            | Diff::List(ListDiff { object_id, .. })
+            | Diff::Text(TextDiff { object_id, .. }) => object_id,
+        };
+        if object_id != &ObjectId::from(op_id) {
+            return Err(InvalidPatch::ObjectIdMismatch {
+                op_id: op_id.clone(),
+                object_id: object_id.clone(),
+            });
+        }
+        if !self.seen.insert(object_id.clone()) {
+            return Err(InvalidPatch::DuplicateObject(object_id.clone()));
+        }
+        match diff {
+            Diff::Map(MapDiff { props, .. }) | Diff::Table(TableDiff { props, .. }) => {
+                self.props(props)
+            }
+            Diff::List(ListDiff { edits, .. }) => self.edits(object_id, edits, false),
+            Diff::Text(TextDiff { edits, .. }) => self.edits(object_id, edits, true),
+            Diff::Value(_) | Diff::Cursor(_) => Ok(()),
+        }
+    }
+
+    fn edits(
+        &mut self,
+        object_id: &ObjectId,
+        edits: &[DiffEdit],
+        is_text: bool,
+    ) -> Result<(), InvalidPatch> {
+        let misplaced = |edit: &'static str| InvalidPatch::MisplacedEdit {
+            object_id: object_id.clone(),
+            edit,
+        };
+        for (i, edit) in edits.iter().enumerate() {
+            match edit {
+                DiffEdit::SingleElementInsert {
+                    elem_id,
+                    op_id,
+                    value,
+                    ..
+                } => {
+                    if let Some(elem_id) = elem_id.as_opid() {
+                        self.op_id(elem_id)?;
+                    }
+                    self.value(op_id, value)?;
+                }
+                DiffEdit::Update { op_id, value, .. } => self.value(op_id, value)?,
+                DiffEdit::MultiElementInsert(insert) => {
+                    if let Some(elem_id) = insert.elem_id.as_opid() {
+                        self.op_id(
+                            &elem_id.increment_by((insert.values.len() as u64).saturating_sub(1)),
+                        )?;
+                    }
+                }
+                DiffEdit::Remove { index, count } => {
+                    if *count == 0 {
+                        return Err(InvalidPatch::EmptyRemove {
+                            object_id: object_id.clone(),
+                            index: *index,
+                        });
+                    }
+                }
+                DiffEdit::TextInsert(insert) => {
+                    if !is_text {
+                        return Err(misplaced("text-insert"));
+                    }
+                    if insert.text.is_empty() {
+                        return Err(InvalidPatch::EmptyInsert {
+                            object_id: object_id.clone(),
+                            index: insert.index,
+                        });
+                    }
+                }
+                DiffEdit::Marks(MarkDiff { marks }) => {
+                    if !is_text || i != edits.len() - 1 {
+                        return Err(misplaced("marks"));
+                    }
+                    if let Some(span) = marks.iter().find(|span| span.start >= span.end) {
+                        return Err(InvalidPatch::EmptyMarkSpan {
+                            object_id: object_id.clone(),
+                            start: span.start,
+                            end: span.end,
+                        });
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,4 +1,4 @@
-use std::{collections::HashMap, fmt};
+use std::{collections::BTreeMap, fmt};
 
 use serde::{
     de,
@@ -169,7 +169,7 @@ impl<'de> Deserialize<'de> for Diff {
                 let mut object_id: Option<ObjectId> = None;
                 let mut diff_type: Option<RawDiffType> = None;
                 //let mut obj_type: Option<ObjType> = None;
-                let mut props: Option<HashMap<SmolStr, HashMap<OpId, Diff>>> = None;
+                let mut props: Option<BTreeMap<SmolStr, BTreeMap<OpId, Diff>>> = None;
                 let mut value: Option<ScalarValue> = None;
                 let mut datatype: Option<DataType> = None;
                 let mut elem_id: Option<OpId> = None;
@@ -305,7 +305,7 @@ fn maybe_add_datatype_to_value(value: ScalarValue, datatype: DataType) -> Scalar
 mod tests {
     use std::{convert::TryInto, str::FromStr};
 
-    use maplit::hashmap;
+    use maplit::btreemap;
 
     use crate::{CursorDiff, Diff, ListDiff, MapDiff, ObjectId, OpId};
 
@@ -325,8 +325,8 @@ mod tests {
         });
         let diff = Diff::Map(MapDiff {
             object_id: ObjectId::from_str("1@6121f8757d5d46609b665218b2b3a141").unwrap(),
-            props: hashmap! {
-                "key".into() => hashmap!{
+            props: btreemap! {
+                "key".into() => btreemap!{
                     OpId::from_str("1@4a093244de2b4fd0a4203724e15dfc16").unwrap() => "value".into()
                 }
             },
@@ -5,10 +5,12 @@ use serde::{
     ser::SerializeStruct,
     Deserialize, Deserializer, Serialize, Serializer,
 };
+use smol_str::SmolStr;
 
 use super::read_field;
 use crate::{
-    DataType, Key, ObjType, ObjectId, Op, OpId, OpType, ScalarValue, ScalarValues, SortedVec,
+    DataType, Key, MarkData, ObjType, ObjectId, Op, OpId, OpType, ScalarValue, ScalarValues,
+    SortedVec,
 };
 
 impl Serialize for Op {
@@ -25,6 +27,7 @@ impl Serialize for Op {
         let numerical_datatype = match &self.action {
             OpType::Set(value) => value.as_numerical_datatype(),
             OpType::MultiSet(values) => values.as_numerical_datatype(),
+            OpType::MarkBegin(mark) => mark.value.as_numerical_datatype(),
             _ => None,
         };
 
@@ -33,6 +36,9 @@ impl Serialize for Op {
         } else if !matches!(&self.action, OpType::Make(..)) {
             fields += 1
         };
+        if matches!(&self.action, OpType::MarkBegin(..)) {
+            fields += 1
+        }
 
         let mut op = serializer.serialize_struct("Operation", fields)?;
         op.serialize_field("action", &self.action)?;
@@ -56,6 +62,12 @@ impl Serialize for Op {
             OpType::Set(value) => op.serialize_field("value", &value)?,
             OpType::MultiSet(values) => op.serialize_field("values", &values.vec)?,
             OpType::Del(multi_op) => op.serialize_field("multiOp", &multi_op)?,
+            OpType::Move(source) => op.serialize_field("ref", &source)?,
+            OpType::MarkBegin(mark) => {
+                op.serialize_field("name", &mark.name)?;
+                op.serialize_field("value", &mark.value)?;
+            }
+            OpType::MarkEnd(begin) => op.serialize_field("ref", &begin)?,
             OpType::Make(..) => {}
         }
         op.serialize_field("pred", &self.pred)?;
@@ -77,6 +89,9 @@ pub enum RawOpType {
     Del,
     Inc,
     Set,
+    Move,
+    MarkBegin,
+    MarkEnd,
 }
 
 impl Serialize for RawOpType {
@@ -92,6 +107,9 @@ impl Serialize for RawOpType {
             RawOpType::Del => "del",
             RawOpType::Inc => "inc",
             RawOpType::Set => "set",
+            RawOpType::Move => "move",
+            RawOpType::MarkBegin => "markBegin",
+            RawOpType::MarkEnd => "markEnd",
         };
         serializer.serialize_str(s)
     }
@@ -110,6 +128,9 @@ impl<'de> Deserialize<'de> for RawOpType {
             "del",
             "inc",
             "set",
+            "move",
+            "markBegin",
+            "markEnd",
         ];
         // TODO: Probably more efficient to deserialize to a `&str`
         let raw_type = String::deserialize(deserializer)?;
@@ -121,6 +142,9 @@ impl<'de> Deserialize<'de> for RawOpType {
             "del" => Ok(RawOpType::Del),
             "inc" => Ok(RawOpType::Inc),
             "set" => Ok(RawOpType::Set),
+            "move" => Ok(RawOpType::Move),
+            "markBegin" => Ok(RawOpType::MarkBegin),
+            "markEnd" => Ok(RawOpType::MarkEnd),
             other => Err(Error::unknown_variant(other, VARIANTS)),
         }
     }
@@ -154,6 +178,7 @@ impl<'de> Deserialize<'de> for Op {
                 let mut ref_id: Option<OpId> = None;
                 let mut values: Option<Vec<ScalarValue>> = None;
                 let mut multi_op: Option<u32> = None;
+                let mut name: Option<SmolStr> = None;
                 while let Some(field) = map.next_key::<String>()? {
                     match field.as_ref() {
                         "action" => read_field("action", &mut action, &mut map)?,
@@ -179,6 +204,7 @@ impl<'de> Deserialize<'de> for Op {
                         "ref" => read_field("ref", &mut ref_id, &mut map)?,
                         "values" => read_field("values", &mut values, &mut map)?,
                         "multiOp" => read_field("multiOp", &mut multi_op, &mut map)?,
+                        "name" => read_field("name", &mut name, &mut map)?,
                         _ => return Err(Error::unknown_field(&field, FIELDS)),
                     }
                 }
@@ -203,32 +229,19 @@ impl<'de> Deserialize<'de> for Op {
                                 ScalarValues::from_values_and_datatype::<V>(values, datatype)?;
                             OpType::MultiSet(values)
                         } else {
-                            let value = if let Some(datatype) = datatype {
-                                match datatype {
-                                    DataType::Cursor => match ref_id {
-                                        Some(opid) => ScalarValue::Cursor(opid),
-                                        None => return Err(Error::missing_field("ref")),
-                                    },
-                                    _ => {
-                                        let raw_value = value
-                                            .ok_or_else(|| Error::missing_field("value"))?
-                                            .unwrap_or(ScalarValue::Null);
-                                        raw_value.as_datatype(datatype).map_err(|e| {
-                                            Error::invalid_value(
-                                                Unexpected::Other(e.unexpected.as_str()),
-                                                &e.expected.as_str(),
-                                            )
-                                        })?
-                                    }
-                                }
-                            } else {
-                                value
-                                    .ok_or_else(|| Error::missing_field("value"))?
-                                    .unwrap_or(ScalarValue::Null)
-                            };
-                            OpType::Set(value)
+                            OpType::Set(read_scalar_value(value, datatype, ref_id)?)
                         }
                     }
+                    RawOpType::Move => {
+                        OpType::Move(ref_id.ok_or_else(|| Error::missing_field("ref"))?)
+                    }
+                    RawOpType::MarkBegin => OpType::MarkBegin(MarkData {
+                        name: name.ok_or_else(|| Error::missing_field("name"))?,
+                        value: read_scalar_value(value, datatype, ref_id)?,
+                    }),
+                    RawOpType::MarkEnd => {
+                        OpType::MarkEnd(ref_id.ok_or_else(|| Error::missing_field("ref"))?)
+                    }
                     RawOpType::Inc => match value.flatten() {
                         Some(ScalarValue::Int(n)) => Ok(OpType::Inc(n)),
                         Some(ScalarValue::Uint(n)) => Ok(OpType::Inc(n as i64)),
@@ -267,11 +280,43 @@ impl<'de> Deserialize<'de> for Op {
     }
 }
 
+/// Read the value of a `set` or `markBegin` op
+fn read_scalar_value<E: Error>(
+    value: Option<Option<ScalarValue>>,
+    datatype: Option<DataType>,
+    ref_id: Option<OpId>,
+) -> Result<ScalarValue, E> {
+    if let Some(datatype) = datatype {
+        match datatype {
+            DataType::Cursor => match ref_id {
+                Some(opid) => Ok(ScalarValue::Cursor(opid)),
+                None => Err(Error::missing_field("ref")),
+            },
+            _ => {
+                let raw_value = value
+                    .ok_or_else(|| Error::missing_field("value"))?
+                    .unwrap_or(ScalarValue::Null);
+                raw_value.as_datatype(datatype).map_err(|e| {
+                    Error::invalid_value(
+                        Unexpected::Other(e.unexpected.as_str()),
+                        &e.expected.as_str(),
+                    )
+                })
+            }
+        }
+    } else {
+        Ok(value
+            .ok_or_else(|| Error::missing_field("value"))?
+            .unwrap_or(ScalarValue::Null))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::{convert::TryInto, str::FromStr};
 
     use super::*;
+    use crate::ElementId;
 
     #[test]
     fn test_deserialize_action() {
@@ -552,6 +597,69 @@ mod tests {
                     &"A valid OpID",
                 )),
             },
+            Scenario {
+                name: "Move",
+                json: serde_json::json!({
+                    "action": "move",
+                    "obj": actor.op_id_at(1).to_string(),
+                    "elemId": "_head",
+                    "insert": true,
+                    "ref": actor.op_id_at(2).to_string(),
+                    "pred": []
+                }),
+                expected: Ok(Op {
+                    action: OpType::Move(actor.op_id_at(2)),
+                    obj: ObjectId::from(actor.op_id_at(1)),
+                    key: ElementId::Head.into(),
+                    insert: true,
+                    pred: SortedVec::new(),
+                }),
+            },
+            Scenario {
+                name: "Move without ref",
+                json: serde_json::json!({
+                    "action": "move",
+                    "obj": actor.op_id_at(1).to_string(),
+                    "elemId": "_head",
+                    "insert": true,
+                    "pred": []
+                }),
+                expected: Err(serde_json::Error::missing_field("ref")),
+            },
+            Scenario {
+                name: "MarkBegin",
+                json: serde_json::json!({
+                    "action": "markBegin",
+                    "obj": actor.op_id_at(1).to_string(),
+                    "elemId": actor.op_id_at(2).to_string(),
+                    "insert": true,
+                    "name": "bold",
+                    "value": true,
+                    "pred": []
+                }),
+                expected: Ok(Op {
+                    action: OpType::MarkBegin(MarkData {
+                        name: "bold".into(),
+                        value: ScalarValue::Boolean(true),
+                    }),
+                    obj: ObjectId::from(actor.op_id_at(1)),
+                    key: actor.op_id_at(2).into(),
+                    insert: true,
+                    pred: SortedVec::new(),
+                }),
+            },
+            Scenario {
+                name: "MarkBegin without name",
+                json: serde_json::json!({
+                    "action": "markBegin",
+                    "obj": actor.op_id_at(1).to_string(),
+                    "elemId": "_head",
+                    "insert": true,
+                    "value": true,
+                    "pred": []
+                }),
+                expected: Err(serde_json::Error::missing_field("name")),
+            },
             Scenario {
                 name: "set with multiple values",
                 json: serde_json::json!({
@@ -735,6 +843,34 @@ mod tests {
                 insert: true,
                 pred: SortedVec::new(),
             },
+            Op {
+                action: OpType::Move(OpId::from_str("2@7ef48769b04d47e9a88e98a134d62716").unwrap()),
+                obj: ObjectId::from_str("1@7ef48769b04d47e9a88e98a134d62716").unwrap(),
+                key: ElementId::Head.into(),
+                insert: true,
+                pred: SortedVec::new(),
+            },
+            Op {
+                action: OpType::MarkBegin(MarkData {
+                    name: "comment".into(),
+                    value: ScalarValue::Counter(3),
+                }),
+                obj: ObjectId::from_str("1@7ef48769b04d47e9a88e98a134d62716").unwrap(),
+                key: ElementId::Head.into(),
+                insert: true,
+                pred: SortedVec::new(),
+            },
+            Op {
+                action: OpType::MarkEnd(
+                    OpId::from_str("3@7ef48769b04d47e9a88e98a134d62716").unwrap(),
+                ),
+                obj: ObjectId::from_str("1@7ef48769b04d47e9a88e98a134d62716").unwrap(),
+                key: OpId::from_str("2@7ef48769b04d47e9a88e98a134d62716")
+                    .unwrap()
+                    .into(),
+                insert: true,
+                pred: SortedVec::new(),
+            },
         ];
         for (testcase_num, testcase) in testcases.iter().enumerate() {
             #[allow(clippy::expect_fun_call)]
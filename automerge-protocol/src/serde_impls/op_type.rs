@@ -19,6 +19,9 @@ impl Serialize for OpType {
             OpType::Inc(_) => RawOpType::Inc,
             OpType::Set(_) => RawOpType::Set,
             OpType::MultiSet(..) => RawOpType::Set,
+            OpType::Move(_) => RawOpType::Move,
+            OpType::MarkBegin(_) => RawOpType::MarkBegin,
+            OpType::MarkEnd(_) => RawOpType::MarkEnd,
         };
         raw_type.serialize(serializer)
     }
@@ -0,0 +1,384 @@
+//! [proptest](https://docs.rs/proptest) strategies for the protocol types, for property testing
+//! code which consumes changes and patches.
+//!
+//! Most of the strategies here are structural, they produce values which are well formed but
+//! which won't make sense to a backend, e.g. an op whose `pred` refers to ops which never
+//! existed. [`valid_ops`] and [`valid_change`] produce ops which can be applied to an empty
+//! document.
+use std::{collections::BTreeMap, convert::TryInto, num::NonZeroU32};
+
+use proptest::{
+    arbitrary::Arbitrary,
+    collection::{btree_map, hash_map, vec},
+    option,
+    prelude::*,
+    sample::{select, Index},
+};
+use smol_str::SmolStr;
+
+use crate::{
+    ActorId, Change, ChangeHash, Diff, DiffEdit, ElementId, Key, ListDiff, MapDiff,
+    MultiElementInsert, ObjType, ObjectId, Op, OpId, OpType, Patch, RootDiff, ScalarValue,
+    SortedVec, TableDiff, TextDiff,
+};
+
+pub fn obj_type() -> impl Strategy<Value = ObjType> {
+    prop_oneof![
+        Just(ObjType::Map),
+        Just(ObjType::Table),
+        Just(ObjType::List),
+        Just(ObjType::Text),
+    ]
+}
+
+/// Any scalar value apart from a cursor
+pub fn scalar_value() -> impl Strategy<Value = ScalarValue> {
+    prop_oneof![
+        any::<String>().prop_map(|s| ScalarValue::Str(s.into())),
+        any::<i64>().prop_map(ScalarValue::Int),
+        any::<u64>().prop_map(ScalarValue::Uint),
+        any::<f64>().prop_map(ScalarValue::F64),
+        any::<i64>().prop_map(ScalarValue::Counter),
+        any::<i64>().prop_map(ScalarValue::Timestamp),
+        any::<bool>().prop_map(ScalarValue::Boolean),
+        Just(ScalarValue::Null),
+    ]
+}
+
+pub fn op_type() -> impl Strategy<Value = OpType> {
+    prop_oneof![
+        obj_type().prop_map(OpType::Make),
+        any::<u32>().prop_map(|u| OpType::Del(NonZeroU32::new(u.saturating_add(1)).unwrap())),
+        any::<i64>().prop_map(OpType::Inc),
+        scalar_value().prop_map(OpType::Set),
+    ]
+}
+
+pub fn actor_id() -> impl Strategy<Value = ActorId> {
+    vec(any::<u8>(), 16).prop_map(|bytes| ActorId::from(&bytes))
+}
+
+pub fn op_id() -> impl Strategy<Value = OpId> {
+    (any::<u64>(), actor_id()).prop_map(|(counter, actor)| OpId::new(counter, &actor))
+}
+
+pub fn object_id() -> impl Strategy<Value = ObjectId> {
+    prop_oneof![Just(ObjectId::Root), op_id().prop_map(ObjectId::Id)]
+}
+
+pub fn element_id() -> impl Strategy<Value = ElementId> {
+    prop_oneof![Just(ElementId::Head), op_id().prop_map(ElementId::Id)]
+}
+
+pub fn key() -> impl Strategy<Value = Key> {
+    prop_oneof![
+        any::<String>().prop_map(|s| Key::Map(s.into())),
+        element_id().prop_map(Key::Seq),
+    ]
+}
+
+pub fn change_hash() -> impl Strategy<Value = ChangeHash> {
+    any::<[u8; 32]>().prop_map(ChangeHash)
+}
+
+prop_compose! {
+    /// An op with arbitrary fields, see [`valid_ops`] for ops which fit together
+    pub fn op()
+        (insert in any::<bool>(),
+         action in op_type(),
+         obj in object_id(),
+         key in key(),
+         pred in vec(op_id(), 0..10)) -> Op {
+            Op {
+                action,
+                obj,
+                key,
+                pred: SortedVec::from(pred),
+                insert,
+            }
+    }
+}
+
+prop_compose! {
+    /// A change with arbitrary fields, see [`valid_change`] for a change a backend will accept
+    pub fn change()
+        (seq in any::<u64>(),
+         actor_id in actor_id(),
+         start_op in any::<u64>(),
+         time in any::<i64>(),
+         message in option::of(any::<String>()),
+         deps in vec(change_hash(), 0..10),
+         extra_bytes in vec(any::<u8>(), 0..10),
+         operations in vec(op(), 0..10)) -> Change {
+            Change {
+                seq,
+                actor_id,
+                start_op,
+                time,
+                hash: None,
+                message,
+                deps,
+                operations,
+                extra_bytes,
+            }
+    }
+}
+
+fn props(
+    diff: impl Strategy<Value = Diff>,
+) -> impl Strategy<Value = BTreeMap<SmolStr, BTreeMap<OpId, Diff>>> {
+    btree_map(
+        any::<String>().prop_map(SmolStr::from),
+        btree_map(op_id(), diff, 1..3),
+        0..4,
+    )
+}
+
+fn edit(value: impl Strategy<Value = Diff> + Clone) -> impl Strategy<Value = DiffEdit> {
+    prop_oneof![
+        (any::<u64>(), element_id(), op_id(), value.clone()).prop_map(
+            |(index, elem_id, op_id, value)| DiffEdit::SingleElementInsert {
+                index,
+                elem_id,
+                op_id,
+                value,
+            }
+        ),
+        (any::<u64>(), element_id(), vec(any::<i64>(), 1..10)).prop_map(
+            |(index, elem_id, values)| DiffEdit::MultiElementInsert(MultiElementInsert {
+                index,
+                elem_id,
+                values: values
+                    .into_iter()
+                    .map(ScalarValue::Int)
+                    .collect::<Vec<_>>()
+                    .try_into()
+                    .unwrap(),
+            })
+        ),
+        (any::<u64>(), op_id(), value).prop_map(|(index, op_id, value)| DiffEdit::Update {
+            index,
+            op_id,
+            value,
+        }),
+        (any::<u64>(), any::<u64>()).prop_map(|(index, count)| DiffEdit::Remove { index, count }),
+    ]
+}
+
+/// A diff with arbitrary contents, nested up to three objects deep
+pub fn diff() -> impl Strategy<Value = Diff> {
+    let leaf = scalar_value().prop_map(Diff::Value);
+    leaf.prop_recursive(3, 32, 4, |inner| {
+        prop_oneof![
+            (object_id(), props(inner.clone()))
+                .prop_map(|(object_id, props)| Diff::Map(MapDiff { object_id, props })),
+            (object_id(), props(inner.clone()))
+                .prop_map(|(object_id, props)| Diff::Table(TableDiff { object_id, props })),
+            (object_id(), vec(edit(inner), 0..4))
+                .prop_map(|(object_id, edits)| Diff::List(ListDiff { object_id, edits })),
+            (
+                object_id(),
+                vec(edit(any::<char>().prop_map(text_value)), 0..4)
+            )
+                .prop_map(|(object_id, edits)| Diff::Text(TextDiff { object_id, edits })),
+        ]
+    })
+}
+
+fn text_value(c: char) -> Diff {
+    Diff::Value(ScalarValue::Str(c.to_string().into()))
+}
+
+prop_compose! {
+    pub fn patch()
+        (actor in option::of(actor_id()),
+         seq in option::of(any::<u64>()),
+         clock in hash_map(actor_id(), any::<u64>(), 0..4),
+         deps in vec(change_hash(), 0..4),
+         max_op in any::<u64>(),
+         pending_changes in any::<usize>(),
+         props in props(diff())) -> Patch {
+            Patch {
+                actor,
+                seq,
+                clock,
+                deps,
+                max_op,
+                pending_changes,
+                diffs: RootDiff { props },
+            }
+    }
+}
+
+/// The contents of an object in the model which [`valid_ops`] builds, each key or element maps
+/// to the IDs of the ops which set its current values
+enum Contents {
+    Map(BTreeMap<SmolStr, Vec<OpId>>),
+    Sequence {
+        text: bool,
+        elements: Vec<(OpId, Vec<OpId>)>,
+    },
+}
+
+/// The choices made for one op of [`valid_ops`], interpreted against the document so far
+type Step = (Index, Index, u8, &'static str, ScalarValue, char, ObjType);
+
+/// Up to `max_ops` ops by `actor`, numbered from `start_op`, which are valid against an empty
+/// document.
+///
+/// Every op is made on an object which exists, by the root or an earlier `Make`, and its `pred`
+/// is exactly the current values of the key or element it sets. Sequence inserts come after an
+/// element which exists, characters inserted in to text are single characters.
+pub fn valid_ops(actor: ActorId, start_op: u64, max_ops: usize) -> impl Strategy<Value = Vec<Op>> {
+    let step = (
+        any::<Index>(),
+        any::<Index>(),
+        any::<u8>(),
+        select(&["a", "b", "c", "d"][..]),
+        scalar_value(),
+        any::<char>(),
+        obj_type(),
+    );
+    vec(step, 0..=max_ops).prop_map(move |steps| ops_from_steps(&actor, start_op, steps))
+}
+
+fn ops_from_steps(actor: &ActorId, start_op: u64, steps: Vec<Step>) -> Vec<Op> {
+    let mut objects = vec![(ObjectId::Root, Contents::Map(BTreeMap::new()))];
+    let mut ops = Vec::with_capacity(steps.len());
+    for (object, position, choice, key, value, c, obj_type) in steps {
+        let opid = actor.op_id_at(start_op + ops.len() as u64);
+        let object = object.index(objects.len());
+        let (obj, contents) = &mut objects[object];
+        let set_or_make = if choice % 4 == 0 {
+            OpType::Make(obj_type)
+        } else {
+            OpType::Set(value)
+        };
+        let del = OpType::Del(NonZeroU32::new(1).unwrap());
+        let op = match contents {
+            Contents::Map(props) => {
+                let pred = props.remove(key).unwrap_or_default();
+                let action = if choice % 3 == 0 && !pred.is_empty() {
+                    del
+                } else {
+                    props.insert(key.into(), vec![opid.clone()]);
+                    set_or_make
+                };
+                Op {
+                    action,
+                    obj: obj.clone(),
+                    key: Key::Map(key.into()),
+                    pred: pred.into(),
+                    insert: false,
+                }
+            }
+            Contents::Sequence { text, elements } => {
+                let action = if *text {
+                    OpType::Set(ScalarValue::Str(c.to_string().into()))
+                } else {
+                    set_or_make
+                };
+                if elements.is_empty() || choice % 3 == 0 {
+                    let index = position.index(elements.len() + 1);
+                    let key = match index {
+                        0 => ElementId::Head,
+                        i => ElementId::Id(elements[i - 1].0.clone()),
+                    };
+                    elements.insert(index, (opid.clone(), vec![opid.clone()]));
+                    Op {
+                        action,
+                        obj: obj.clone(),
+                        key: Key::Seq(key),
+                        pred: SortedVec::new(),
+                        insert: true,
+                    }
+                } else {
+                    let index = position.index(elements.len());
+                    let (elem_id, values) = &mut elements[index];
+                    let key = Key::Seq(ElementId::Id(elem_id.clone()));
+                    let pred = std::mem::replace(values, vec![opid.clone()]);
+                    let action = if choice % 3 == 1 {
+                        elements.remove(index);
+                        del
+                    } else {
+                        action
+                    };
+                    Op {
+                        action,
+                        obj: obj.clone(),
+                        key,
+                        pred: pred.into(),
+                        insert: false,
+                    }
+                }
+            }
+        };
+        if let OpType::Make(obj_type) = op.action {
+            let contents = match obj_type {
+                ObjType::Map | ObjType::Table => Contents::Map(BTreeMap::new()),
+                ObjType::List | ObjType::Text => Contents::Sequence {
+                    text: obj_type == ObjType::Text,
+                    elements: Vec::new(),
+                },
+            };
+            objects.push((opid.into(), contents));
+        }
+        ops.push(op);
+    }
+    ops
+}
+
+prop_compose! {
+    /// The first change of a new document, made up of up to `max_ops` ops from [`valid_ops`].
+    ///
+    /// The change has no dependencies, changes which depend on it need its hash which only a
+    /// backend can compute.
+    pub fn valid_change(max_ops: usize)
+        (actor_id in actor_id())
+        (operations in valid_ops(actor_id.clone(), 1, max_ops),
+         actor_id in Just(actor_id),
+         time in any::<i64>(),
+         message in option::of(any::<String>())) -> Change {
+            Change {
+                seq: 1,
+                actor_id,
+                start_op: 1,
+                time,
+                hash: None,
+                message,
+                deps: Vec::new(),
+                operations,
+                extra_bytes: Vec::new(),
+            }
+    }
+}
+
+macro_rules! impl_arbitrary {
+    ($($ty:ty => $strategy:expr),* $(,)?) => {
+        $(
+            impl Arbitrary for $ty {
+                type Parameters = ();
+                type Strategy = BoxedStrategy<$ty>;
+
+                fn arbitrary_with(_: ()) -> Self::Strategy {
+                    $strategy.boxed()
+                }
+            }
+        )*
+    };
+}
+
+impl_arbitrary! {
+    ActorId => actor_id(),
+    OpId => op_id(),
+    ObjectId => object_id(),
+    ElementId => element_id(),
+    Key => key(),
+    ChangeHash => change_hash(),
+    ScalarValue => scalar_value(),
+    OpType => op_type(),
+    Op => op(),
+    Change => change(),
+    Diff => diff(),
+    Patch => patch(),
+}
@@ -14,6 +14,7 @@ impl TryFrom<&str> for ActorId {
     }
 }
 
+#[cfg(feature = "random")]
 impl From<uuid::Uuid> for ActorId {
     fn from(u: uuid::Uuid) -> Self {
         ActorId(TinyVec::from(*u.as_bytes()))
@@ -1,6 +1,9 @@
-use std::convert::TryFrom;
+use std::{convert::TryFrom, fmt, str::FromStr};
 
-use crate::{error::InvalidChangeHashSlice, ChangeHash};
+use crate::{
+    error::{InvalidChangeHash, InvalidChangeHashSlice},
+    ChangeHash,
+};
 
 impl TryFrom<&[u8]> for ChangeHash {
     type Error = InvalidChangeHashSlice;
@@ -15,3 +18,20 @@ impl TryFrom<&[u8]> for ChangeHash {
         }
     }
 }
+
+impl FromStr for ChangeHash {
+    type Err = InvalidChangeHash;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        hex::decode(s)
+            .ok()
+            .and_then(|bytes| ChangeHash::try_from(bytes.as_slice()).ok())
+            .ok_or_else(|| InvalidChangeHash(s.into()))
+    }
+}
+
+impl fmt::Display for ChangeHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
@@ -0,0 +1,9 @@
+use std::fmt;
+
+use crate::DiffKind;
+
+impl fmt::Display for DiffKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
@@ -1,6 +1,7 @@
 mod actor_id;
 mod change_hash;
 mod diff;
+mod diff_kind;
 mod element_id;
 mod key;
 mod object_id;
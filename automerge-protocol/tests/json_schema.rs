@@ -0,0 +1,137 @@
+#![cfg(feature = "json-schema")]
+extern crate automerge_protocol as amp;
+use amp::testing;
+use proptest::prelude::*;
+use schemars::{schema::RootSchema, schema_for};
+use serde_json::{Map, Value};
+
+/// Whether `value` matches `schema`, as far as the parts of JSON schema which `schemars` uses go.
+///
+/// This is stricter than JSON schema in one way: an object may only have the properties its
+/// schema lists, so a field missing from a schema is caught.
+fn conforms(root: &Value, schema: &Value, value: &Value) -> bool {
+    let schema = match schema {
+        Value::Bool(b) => return *b,
+        Value::Object(schema) => schema,
+        _ => panic!("not a schema: {}", schema),
+    };
+    if let Some(Value::String(reference)) = schema.get("$ref") {
+        let name = reference.trim_start_matches("#/definitions/");
+        return conforms(root, &root["definitions"][name], value);
+    }
+    if let Some(Value::Array(all)) = schema.get("allOf") {
+        if !all.iter().all(|s| conforms(root, s, value)) {
+            return false;
+        }
+    }
+    if let Some(Value::Array(any)) = schema.get("anyOf") {
+        if !any.iter().any(|s| conforms(root, s, value)) {
+            return false;
+        }
+    }
+    if let Some(Value::Array(one)) = schema.get("oneOf") {
+        if one.iter().filter(|s| conforms(root, s, value)).count() != 1 {
+            return false;
+        }
+    }
+    if let Some(Value::Array(options)) = schema.get("enum") {
+        if !options.contains(value) {
+            return false;
+        }
+    }
+    if let Some(instance_type) = schema.get("type") {
+        let types = match instance_type {
+            Value::Array(types) => types.iter().collect(),
+            single => vec![single],
+        };
+        if !types.iter().any(|t| has_type(t.as_str().unwrap(), value)) {
+            return false;
+        }
+    }
+    match value {
+        Value::Array(items) => match schema.get("items") {
+            Some(item) => items.iter().all(|i| conforms(root, item, i)),
+            None => true,
+        },
+        Value::Object(fields) => object_conforms(root, schema, fields),
+        _ => true,
+    }
+}
+
+fn object_conforms(root: &Value, schema: &Map<String, Value>, fields: &Map<String, Value>) -> bool {
+    if let Some(Value::Array(required)) = schema.get("required") {
+        if !required
+            .iter()
+            .all(|r| fields.contains_key(r.as_str().unwrap()))
+        {
+            return false;
+        }
+    }
+    match (schema.get("properties"), schema.get("additionalProperties")) {
+        (Some(Value::Object(properties)), _) => fields.iter().all(
+            |(name, field)| matches!(properties.get(name), Some(p) if conforms(root, p, field)),
+        ),
+        (None, Some(additional)) => fields.values().all(|f| conforms(root, additional, f)),
+        _ => true,
+    }
+}
+
+fn has_type(instance_type: &str, value: &Value) -> bool {
+    match instance_type {
+        "null" => value.is_null(),
+        "boolean" => value.is_boolean(),
+        "string" => value.is_string(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "number" => value.is_number(),
+        "array" => value.is_array(),
+        "object" => value.is_object(),
+        other => panic!("unknown type {}", other),
+    }
+}
+
+fn schema_value(schema: RootSchema) -> Value {
+    serde_json::to_value(schema).unwrap()
+}
+
+#[test]
+fn test_schema_rejects_the_wrong_shape() {
+    let schema = schema_value(schema_for!(amp::Change));
+    let change = serde_json::json!({
+        "ops": [{"action": "set", "obj": "_root", "key": "bird", "value": "magpie", "pred": []}],
+        "actor": "7b7723afd9e6480397a4d467b7693156",
+        "seq": 1,
+        "startOp": 1,
+        "time": 0,
+        "message": null,
+        "deps": [],
+    });
+    assert!(conforms(&schema, &schema, &change));
+
+    let mut unknown_action = change.clone();
+    unknown_action["ops"][0]["action"] = "link".into();
+    assert!(!conforms(&schema, &schema, &unknown_action));
+
+    let mut missing_field = change;
+    missing_field.as_object_mut().unwrap().remove("startOp");
+    assert!(!conforms(&schema, &schema, &missing_field));
+}
+
+proptest! {
+    #[test]
+    fn test_changes_match_their_schema(change in testing::change()) {
+        let schema = schema_value(schema_for!(amp::Change));
+        let json = serde_json::to_value(&change).unwrap();
+        prop_assert!(conforms(&schema, &schema, &json), "{}", json);
+    }
+
+    #[test]
+    fn test_patches_match_their_schema(mut patch in testing::patch()) {
+        let schema = schema_value(schema_for!(amp::Patch));
+        let json = serde_json::to_value(&patch).unwrap();
+        prop_assert!(conforms(&schema, &schema, &json), "{}", json);
+
+        patch.compact_text_edits();
+        let json = serde_json::to_value(&patch).unwrap();
+        prop_assert!(conforms(&schema, &schema, &json), "{}", json);
+    }
+}
@@ -0,0 +1,176 @@
+use automerge_protocol::{
+    error::InvalidPatch, ActorId, Diff, DiffEdit, MarkSpan, ObjType, ObjectDiffBuilder, ObjectId,
+    PatchBuilder,
+};
+
+fn actor() -> ActorId {
+    ActorId::from(&[1, 2, 3][..])
+}
+
+#[test]
+fn test_builds_nested_patch() {
+    let actor = actor();
+    let map_id = actor.op_id_at(1);
+    let text_id = actor.op_id_at(2);
+    let text = ObjectDiffBuilder::new(text_id.clone(), ObjType::Text)
+        .insert(0, actor.op_id_at(3), "a".into())
+        .insert(1, actor.op_id_at(4), "b".into())
+        .marks(vec![MarkSpan {
+            start: 0,
+            end: 2,
+            name: "bold".into(),
+            value: true.into(),
+        }])
+        .build();
+    let map = ObjectDiffBuilder::new(map_id.clone(), ObjType::Map)
+        .prop("text", text_id, text)
+        .build();
+    let patch = PatchBuilder::new()
+        .actor(actor.clone(), 1)
+        .clock(actor.clone(), 1)
+        .max_op(4)
+        .prop("doc", map_id.clone(), map)
+        .build()
+        .unwrap();
+    assert_eq!(patch.actor, Some(actor));
+    assert_eq!(patch.seq, Some(1));
+    match &patch.diffs.props["doc"][&map_id] {
+        Diff::Map(map) => assert_eq!(map.object_id, ObjectId::from(&map_id)),
+        other => panic!("expected a map diff, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_rejects_object_under_wrong_op() {
+    let actor = actor();
+    let list = ObjectDiffBuilder::new(actor.op_id_at(1), ObjType::List).build();
+    let result = PatchBuilder::new()
+        .max_op(2)
+        .prop("list", actor.op_id_at(2), list)
+        .build();
+    assert_eq!(
+        result,
+        Err(InvalidPatch::ObjectIdMismatch {
+            op_id: actor.op_id_at(2),
+            object_id: actor.op_id_at(1).into(),
+        })
+    );
+}
+
+#[test]
+fn test_rejects_duplicate_objects() {
+    let actor = actor();
+    let list = ObjectDiffBuilder::new(actor.op_id_at(1), ObjType::List).build();
+    let result = PatchBuilder::new()
+        .max_op(1)
+        .prop("a", actor.op_id_at(1), list.clone())
+        .prop("b", actor.op_id_at(1), list)
+        .build();
+    assert_eq!(
+        result,
+        Err(InvalidPatch::DuplicateObject(actor.op_id_at(1).into()))
+    );
+}
+
+#[test]
+fn test_rejects_ops_above_max_op() {
+    let actor = actor();
+    let list = ObjectDiffBuilder::new(actor.op_id_at(1), ObjType::List)
+        .insert(0, actor.op_id_at(2), "a".into())
+        .build();
+    let result = PatchBuilder::new()
+        .max_op(1)
+        .prop("list", actor.op_id_at(1), list)
+        .build();
+    assert_eq!(
+        result,
+        Err(InvalidPatch::OpIdAboveMaxOp {
+            op_id: actor.op_id_at(2),
+            max_op: 1,
+        })
+    );
+}
+
+#[test]
+fn test_rejects_seq_ahead_of_clock() {
+    let actor = actor();
+    let result = PatchBuilder::new().actor(actor.clone(), 2).build();
+    assert_eq!(
+        result,
+        Err(InvalidPatch::SeqNotInClock {
+            actor,
+            seq: 2,
+            clock: 0,
+        })
+    );
+}
+
+#[test]
+fn test_rejects_malformed_edits() {
+    let actor = actor();
+    let list_id = actor.op_id_at(1);
+
+    let empty_remove = ObjectDiffBuilder::new(list_id.clone(), ObjType::List)
+        .remove(3, 0)
+        .build();
+    let result = PatchBuilder::new()
+        .max_op(1)
+        .prop("list", list_id.clone(), empty_remove)
+        .build();
+    assert_eq!(
+        result,
+        Err(InvalidPatch::EmptyRemove {
+            object_id: list_id.clone().into(),
+            index: 3,
+        })
+    );
+
+    let marks_in_list = ObjectDiffBuilder::new(list_id.clone(), ObjType::List)
+        .marks(Vec::new())
+        .build();
+    let result = PatchBuilder::new()
+        .max_op(1)
+        .prop("list", list_id.clone(), marks_in_list)
+        .build();
+    assert!(matches!(
+        result,
+        Err(InvalidPatch::MisplacedEdit { edit: "marks", .. })
+    ));
+
+    let marks_before_insert = Diff::Text(automerge_protocol::TextDiff {
+        object_id: list_id.clone().into(),
+        edits: vec![
+            DiffEdit::Marks(automerge_protocol::MarkDiff { marks: Vec::new() }),
+            DiffEdit::Remove { index: 0, count: 1 },
+        ],
+    });
+    let result = PatchBuilder::new()
+        .max_op(1)
+        .prop("text", list_id.clone(), marks_before_insert)
+        .build();
+    assert!(matches!(
+        result,
+        Err(InvalidPatch::MisplacedEdit { edit: "marks", .. })
+    ));
+
+    let empty_span = ObjectDiffBuilder::new(list_id.clone(), ObjType::Text)
+        .marks(vec![MarkSpan {
+            start: 1,
+            end: 1,
+            name: "bold".into(),
+            value: true.into(),
+        }])
+        .build();
+    let result = PatchBuilder::new()
+        .max_op(1)
+        .prop("text", list_id.clone(), empty_span)
+        .build();
+    assert_eq!(
+        result,
+        Err(InvalidPatch::EmptyMarkSpan {
+            object_id: list_id.into(),
+            start: 1,
+            end: 1,
+        })
+    );
+}
@@ -1,13 +1,13 @@
 extern crate automerge_protocol as amp;
-use maplit::hashmap;
+use maplit::btreemap;
 
 // This was not caught in the proptests
 #[test]
 fn test_msgpack_roundtrip_diff() {
     let actor = amp::ActorId::from("bd1850df21004038a8141a98473ff142".as_bytes());
     let diff = amp::RootDiff {
-        props: hashmap! {
-            "bird".into() => hashmap! {
+        props: btreemap! {
+            "bird".into() => btreemap! {
                 actor.op_id_at(1) => "magpie".into()
             }
         },
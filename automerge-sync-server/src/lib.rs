@@ -0,0 +1,16 @@
+//! A collaboration server for automerge documents.
+//!
+//! [`Server`] hosts documents keyed by ID and syncs them between the clients connected to each
+//! one. It doesn't do any networking itself: a client [joins](Server::join) a document with a
+//! queue for the messages the server sends it, and passes the messages it receives to the
+//! [`Connection`].
+//!
+//! The `experimental-websocket` feature adds a minimal WebSocket implementation, the
+//! `automerge-sync-server` binary which serves documents over it, and [`Server::serve`] for
+//! embedding that in another program.
+
+mod server;
+#[cfg(feature = "experimental-websocket")]
+pub mod websocket;
+
+pub use server::{Connection, Error, Server, DEFAULT_MAX_DOCUMENTS, OUTGOING_QUEUE_LEN};
@@ -0,0 +1,73 @@
+use std::{net::TcpListener, process, str::FromStr, sync::Arc};
+
+use automerge_backend::Quotas;
+use automerge_sync_server::Server;
+use tracing_subscriber::EnvFilter;
+
+const USAGE: &str = "usage: automerge-sync-server [--listen <address>] [--data <dir>]
+    [--max-depth <n>] [--max-value-size <bytes>] [--max-ops-per-change <n>]
+    [--max-changes-per-second <n>]
+
+Hosts automerge documents, in memory or in <dir> if --data is given. Clients connect to
+ws://<address>/<document id> and send sync messages in binary frames. Changes which break any
+of the limits are rejected and the client sending them is disconnected. Set RUST_LOG=debug to
+log connections.";
+
+/// Compact a stored document once the changes since its last snapshot take up this much space
+const MAX_LOG_BYTES: u64 = 1024 * 1024;
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2)
+}
+
+fn parse<T: FromStr>(value: &str) -> T {
+    value.parse().unwrap_or_else(|_| usage())
+}
+
+fn main() {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .init();
+
+    let mut address = "127.0.0.1:8080".to_string();
+    let mut data = None;
+    let mut quotas = Quotas::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match (arg.as_str(), args.next()) {
+            ("--listen", Some(value)) => address = value,
+            ("--data", Some(value)) => data = Some(value),
+            ("--max-depth", Some(value)) => quotas.max_depth = Some(parse(&value)),
+            ("--max-value-size", Some(value)) => quotas.max_value_size = Some(parse(&value)),
+            ("--max-ops-per-change", Some(value)) => {
+                quotas.max_ops_per_change = Some(parse(&value))
+            }
+            ("--max-changes-per-second", Some(value)) => {
+                quotas.max_changes_per_second = Some(parse(&value))
+            }
+            _ => usage(),
+        }
+    }
+
+    let mut server = Server::new();
+    if let Some(dir) = data {
+        server.persist_to_dir(dir, MAX_LOG_BYTES);
+    }
+    server.set_quotas(quotas);
+
+    let listener = match TcpListener::bind(&address) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("could not listen on {}: {}", address, e);
+            process::exit(1);
+        }
+    };
+    if let Ok(address) = listener.local_addr() {
+        eprintln!("listening on ws://{}", address);
+    }
+    if let Err(e) = Arc::new(server).serve(listener) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,461 @@
+use std::{
+    collections::HashMap,
+    fmt, io,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::SyncSender,
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+};
+#[cfg(feature = "experimental-websocket")]
+use std::{
+    net::{Shutdown, TcpListener, TcpStream},
+    sync::mpsc::{self, Receiver},
+    thread,
+    time::Duration,
+};
+
+use automerge_backend::{
+    AutomergeError, DecodingError, FilePersister, MemoryPersister, PersistentBackend, Persister,
+    Quotas, SyncMessage, SyncState,
+};
+use thiserror::Error;
+
+#[cfg(feature = "experimental-websocket")]
+use crate::websocket::{self, Message, WebSocket};
+
+/// The number of documents [`Server::new`] will host at once
+pub const DEFAULT_MAX_DOCUMENTS: usize = 1024;
+/// The number of messages which can be waiting to go to a client before it is dropped for
+/// being too slow
+pub const OUTGOING_QUEUE_LEN: usize = 64;
+/// How long a write to a client can block before the client is dropped
+#[cfg(feature = "experimental-websocket")]
+const WRITE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Opens the persister for the document with the given ID
+type OpenPersister = dyn Fn(&str) -> io::Result<Box<dyn Persister + Send>> + Send + Sync;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[cfg(feature = "experimental-websocket")]
+    #[error(transparent)]
+    WebSocket(#[from] websocket::Error),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("bad sync message: {0}")]
+    BadMessage(#[from] DecodingError),
+    #[error(transparent)]
+    Automerge(#[from] AutomergeError),
+    #[error("no document ID in the request path")]
+    NoDocumentId,
+    #[error("the server is already hosting as many documents as it can")]
+    TooManyDocuments,
+    #[error("document {0} was dropped after a panic")]
+    DocumentLost(String),
+}
+
+/// Hosts documents and syncs each one with every client connected to it.
+///
+/// Each client [joins](Server::join) a document and then hands the server the encoded
+/// [`SyncMessage`]s it receives. The server keeps a [`Backend`] per document and runs the sync
+/// protocol with each client, so changes from one client reach the others as soon as they
+/// arrive. Ephemeral messages are passed on to the other clients of the document without being
+/// stored.
+///
+/// Messages for a client are put on the queue it joined with, so a slow client never holds up
+/// the others. A client whose queue is full is dropped.
+///
+/// Documents are loaded when the first client joins them and dropped from memory when the last
+/// one leaves, so the server only holds the documents which are in use. At most
+/// [`Server::max_documents`] are hosted at once. By default documents are only kept in memory,
+/// so a document nobody is connected to is gone and clients sync their own copies back when
+/// they reconnect. With [`Server::persist_to_dir`] or [`Server::set_persister`] every change is
+/// stored before it is passed on, so documents outlive their clients and the server.
+///
+/// Every change a client sends is checked against [`Server::quotas`], and a client which breaks
+/// them is disconnected.
+///
+/// With the `experimental-websocket` feature [`Server::serve`] accepts clients over WebSockets.
+pub struct Server {
+    rooms: Mutex<HashMap<String, Arc<Mutex<Room>>>>,
+    next_peer: AtomicU64,
+    max_documents: usize,
+    open_persister: Box<OpenPersister>,
+    quotas: Quotas,
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Self::with_max_documents(DEFAULT_MAX_DOCUMENTS)
+    }
+}
+
+impl fmt::Debug for Server {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Server")
+            .field("rooms", &self.rooms)
+            .field("next_peer", &self.next_peer)
+            .field("max_documents", &self.max_documents)
+            .field("quotas", &self.quotas)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_max_documents(max_documents: usize) -> Self {
+        Server {
+            rooms: Mutex::new(HashMap::new()),
+            next_peer: AtomicU64::new(0),
+            max_documents,
+            open_persister: Box::new(|_| Ok(Box::new(MemoryPersister::new()))),
+            quotas: Quotas::default(),
+        }
+    }
+
+    pub fn max_documents(&self) -> usize {
+        self.max_documents
+    }
+
+    /// Keep each document in the persister `open` returns for its ID. The persister is opened
+    /// whenever the document is loaded, and everything in it is loaded as the document.
+    pub fn set_persister<F, P>(&mut self, open: F)
+    where
+        F: Fn(&str) -> io::Result<P> + Send + Sync + 'static,
+        P: Persister + Send + 'static,
+    {
+        self.open_persister = Box::new(move |doc_id| Ok(Box::new(open(doc_id)?)));
+    }
+
+    /// Keep each document in its own [`FilePersister`] in a directory under `dir`, which is
+    /// compacted once its log is longer than `max_log_bytes`
+    pub fn persist_to_dir<D: Into<PathBuf>>(&mut self, dir: D, max_log_bytes: u64) {
+        let dir = dir.into();
+        self.set_persister(move |doc_id| {
+            FilePersister::open(dir.join(file_name(doc_id)), max_log_bytes)
+        });
+    }
+
+    pub fn quotas(&self) -> &Quotas {
+        &self.quotas
+    }
+
+    /// Enforce `quotas` on every change clients send to any document
+    pub fn set_quotas(&mut self, quotas: Quotas) {
+        self.quotas = quotas;
+    }
+
+    /// Add a client to the document with `doc_id`, creating the document if nobody else is
+    /// connected to it. Encoded sync messages for the client are sent on `outgoing`, and it
+    /// leaves the document when the [`Connection`] is dropped.
+    pub fn join(
+        &self,
+        doc_id: &str,
+        outgoing: SyncSender<Vec<u8>>,
+    ) -> Result<Connection<'_>, Error> {
+        let mut rooms = self.rooms();
+        let room = match rooms.get(doc_id) {
+            // a room which panicked is replaced, its clients find out when they next use it
+            Some(room) if !room.is_poisoned() => Arc::clone(room),
+            existing => {
+                if existing.is_none() && rooms.len() >= self.max_documents {
+                    return Err(Error::TooManyDocuments);
+                }
+                // the document is loaded with the map locked so that it is only ever open once
+                let room = Arc::new(Mutex::new(self.open_room(doc_id)?));
+                rooms.insert(doc_id.to_string(), Arc::clone(&room));
+                room
+            }
+        };
+        let peer = self.next_peer.fetch_add(1, Ordering::Relaxed);
+        let connection = Connection {
+            server: self,
+            doc_id: doc_id.to_string(),
+            room,
+            peer,
+        };
+        connection.lock()?.join(peer, outgoing);
+        tracing::debug!(peer, doc_id, "client joined");
+        Ok(connection)
+    }
+
+    /// The IDs of the documents the server is hosting
+    pub fn documents(&self) -> Vec<String> {
+        let mut ids: Vec<String> = self.rooms().keys().cloned().collect();
+        ids.sort();
+        ids
+    }
+
+    /// Save the document with `doc_id`, or `None` if the server isn't hosting it
+    pub fn save(&self, doc_id: &str) -> Option<Result<Vec<u8>, Error>> {
+        let room = self.rooms().get(doc_id).cloned()?;
+        let saved = match room.lock() {
+            Ok(room) => room.backend.backend().save().map_err(Error::from),
+            Err(_) => Err(Error::DocumentLost(doc_id.to_string())),
+        };
+        Some(saved)
+    }
+
+    fn open_room(&self, doc_id: &str) -> Result<Room, Error> {
+        let mut backend = PersistentBackend::load((self.open_persister)(doc_id)?)?;
+        backend.set_quotas(self.quotas.clone());
+        Ok(Room {
+            backend,
+            peers: HashMap::new(),
+        })
+    }
+
+    /// The map of documents is only ever changed by a single insert or remove, so it is
+    /// consistent even if a thread panicked while holding the lock
+    fn rooms(&self) -> MutexGuard<'_, HashMap<String, Arc<Mutex<Room>>>> {
+        self.rooms.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(feature = "experimental-websocket")]
+impl Server {
+    /// Accept WebSocket connections from `listener` forever, handling each one on its own
+    /// thread. Clients connect to `ws://<host>/<document id>` and send sync messages in binary
+    /// frames.
+    pub fn serve(self: Arc<Self>, listener: TcpListener) -> io::Result<()> {
+        for stream in listener.incoming() {
+            let stream = stream?;
+            let server = Arc::clone(&self);
+            thread::spawn(move || {
+                let peer = stream.peer_addr().ok();
+                if let Err(e) = server.handle(stream) {
+                    tracing::debug!(?peer, error = %e, "connection ended with an error");
+                }
+            });
+        }
+        Ok(())
+    }
+
+    /// Handle one WebSocket connection until it closes. Messages to the client are written by
+    /// a second thread.
+    pub fn handle(&self, stream: TcpStream) -> Result<(), Error> {
+        stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+        let (mut socket, path) = WebSocket::accept(stream)?;
+        let doc_id = document_id(&path).ok_or(Error::NoDocumentId)?;
+        let writer = Arc::new(Mutex::new(socket.try_clone()?));
+        let (outgoing, queued) = mpsc::sync_channel(OUTGOING_QUEUE_LEN);
+        let connection = self.join(doc_id, outgoing)?;
+        {
+            let writer = Arc::clone(&writer);
+            thread::spawn(move || write_queued(&writer, queued));
+        }
+        let result = read_messages(&mut socket, &connection, &writer);
+        tracing::debug!(peer = connection.peer, doc_id, "client left");
+        result
+    }
+}
+
+/// A client's place in a document, made by [`Server::join`]. The client leaves the document
+/// when this is dropped.
+#[derive(Debug)]
+pub struct Connection<'a> {
+    server: &'a Server,
+    doc_id: String,
+    room: Arc<Mutex<Room>>,
+    peer: u64,
+}
+
+impl Connection<'_> {
+    pub fn document_id(&self) -> &str {
+        &self.doc_id
+    }
+
+    /// Handle an encoded sync message from the client
+    pub fn receive(&self, bytes: &[u8]) -> Result<(), Error> {
+        self.lock()?.receive(self.peer, bytes)
+    }
+
+    /// A panic while the room was locked could have left its backend half way through applying
+    /// a change, so the document is given up on rather than used
+    fn lock(&self) -> Result<MutexGuard<'_, Room>, Error> {
+        self.room
+            .lock()
+            .map_err(|_| Error::DocumentLost(self.doc_id.clone()))
+    }
+}
+
+impl Drop for Connection<'_> {
+    fn drop(&mut self) {
+        let mut rooms = self.server.rooms();
+        // removing the peer is safe even if the room panicked
+        let mut room = self.room.lock().unwrap_or_else(PoisonError::into_inner);
+        room.peers.remove(&self.peer);
+        let current = rooms
+            .get(&self.doc_id)
+            .is_some_and(|room| Arc::ptr_eq(room, &self.room));
+        if room.peers.is_empty() && current {
+            rooms.remove(&self.doc_id);
+            tracing::debug!(doc_id = %self.doc_id, "dropped document");
+        }
+    }
+}
+
+/// Write the messages queued for a client until the queue is dropped or writing fails
+#[cfg(feature = "experimental-websocket")]
+fn write_queued(writer: &Mutex<WebSocket<TcpStream>>, queued: Receiver<Vec<u8>>) {
+    for bytes in queued {
+        if send(writer, &Message::Binary(bytes)).is_err() {
+            return;
+        }
+    }
+    // the client was dropped from its document, make the reading thread finish too
+    let socket = writer.lock().unwrap_or_else(PoisonError::into_inner);
+    let _ = socket.get_ref().shutdown(Shutdown::Both);
+}
+
+/// Send `message`, shutting the connection down if that fails so that the thread reading from
+/// it finishes
+#[cfg(feature = "experimental-websocket")]
+fn send(writer: &Mutex<WebSocket<TcpStream>>, message: &Message) -> Result<(), websocket::Error> {
+    let mut socket = writer.lock().unwrap_or_else(PoisonError::into_inner);
+    socket.send(message).map_err(|e| {
+        tracing::debug!(error = %e, "dropping client");
+        let _ = socket.get_ref().shutdown(Shutdown::Both);
+        e
+    })
+}
+
+/// Read messages from one client until it goes away
+#[cfg(feature = "experimental-websocket")]
+fn read_messages(
+    socket: &mut WebSocket<TcpStream>,
+    connection: &Connection,
+    writer: &Mutex<WebSocket<TcpStream>>,
+) -> Result<(), Error> {
+    loop {
+        match socket.recv()? {
+            Some(Message::Binary(bytes)) => connection.receive(&bytes)?,
+            Some(Message::Ping(payload)) => send(writer, &Message::Pong(payload))?,
+            Some(Message::Close(_)) => {
+                send(writer, &Message::Close(None))?;
+                return Ok(());
+            }
+            Some(Message::Text(_)) | Some(Message::Pong(_)) => {}
+            None => return Ok(()),
+        }
+    }
+}
+
+/// `/notes/shopping?x=y` is the document `notes/shopping`
+#[cfg(feature = "experimental-websocket")]
+fn document_id(path: &str) -> Option<&str> {
+    let path = path.split('?').next().unwrap_or_default().trim_matches('/');
+    if path.is_empty() {
+        None
+    } else {
+        Some(path)
+    }
+}
+
+/// Document IDs come from clients, so they are hex encoded rather than used as paths
+fn file_name(doc_id: &str) -> String {
+    doc_id.bytes().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// One document and the clients connected to it
+struct Room {
+    backend: PersistentBackend<Box<dyn Persister + Send>>,
+    peers: HashMap<u64, Peer>,
+}
+
+impl fmt::Debug for Room {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Room")
+            .field("heads", &self.backend.backend().get_heads())
+            .field("peers", &self.peers)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug)]
+struct Peer {
+    state: SyncState,
+    outgoing: SyncSender<Vec<u8>>,
+}
+
+impl Room {
+    fn join(&mut self, peer: u64, outgoing: SyncSender<Vec<u8>>) {
+        self.peers.insert(
+            peer,
+            Peer {
+                state: SyncState::default(),
+                outgoing,
+            },
+        );
+        self.flush();
+    }
+
+    fn receive(&mut self, peer: u64, bytes: &[u8]) -> Result<(), Error> {
+        let message = SyncMessage::decode(bytes)?;
+        let ephemeral = match self.peers.get_mut(&peer) {
+            Some(sender) => {
+                self.backend
+                    .apply_sync_message(&mut sender.state, message)?;
+                sender.state.take_ephemeral()
+            }
+            None => return Ok(()),
+        };
+        // the backend quarantines changes which break the quotas, and the client would keep
+        // offering them
+        if let Some(rejected) = self.backend.take_quarantined().pop() {
+            tracing::debug!(peer, reason = %rejected.reason, "dropping client which broke the quotas");
+            self.peers.remove(&peer);
+            return Err(AutomergeError::from(rejected.reason).into());
+        }
+        let mut too_slow = Vec::new();
+        for payload in &ephemeral {
+            let bytes = SyncState::encode_ephemeral(payload).map_err(AutomergeError::from)?;
+            for (id, other) in &mut self.peers {
+                if *id != peer && !other.queue(bytes.clone()) {
+                    too_slow.push(*id);
+                }
+            }
+        }
+        self.drop_peers(too_slow);
+        self.flush();
+        Ok(())
+    }
+
+    /// Queue whatever each client is missing
+    fn flush(&mut self) {
+        let mut too_slow = Vec::new();
+        for (id, peer) in &mut self.peers {
+            if let Some(message) = self.backend.generate_sync_message(&mut peer.state) {
+                match message.encode() {
+                    Ok(bytes) => {
+                        if !peer.queue(bytes) {
+                            too_slow.push(*id);
+                        }
+                    }
+                    Err(e) => tracing::warn!(error = %e, "could not encode sync message"),
+                }
+            }
+        }
+        self.drop_peers(too_slow);
+    }
+
+    /// Dropping a client's queue tells whoever is writing to it that it has gone
+    fn drop_peers(&mut self, peers: Vec<u64>) {
+        for peer in peers {
+            tracing::debug!(peer, "dropping client which isn't keeping up");
+            self.peers.remove(&peer);
+        }
+    }
+}
+
+impl Peer {
+    /// Queue `bytes` for the client, returning false if its queue is full or gone
+    fn queue(&self, bytes: Vec<u8>) -> bool {
+        self.outgoing.try_send(bytes).is_ok()
+    }
+}
@@ -0,0 +1,465 @@
+//! Just enough of RFC 6455 to carry sync messages: the opening handshake, framing,
+//! fragmentation and the control frames. Extensions and subprotocols are not supported.
+//!
+//! This is experimental, it is only built with the `experimental-websocket` feature and hasn't
+//! been tested against other implementations beyond browsers. Programs which need a complete
+//! WebSocket implementation should use their own and hand the messages to
+//! [`crate::Connection`].
+
+use std::{
+    convert::{TryFrom, TryInto},
+    io::{self, Read, Write},
+    net::TcpStream,
+};
+
+use thiserror::Error;
+
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// The longest request or response head we will read during the handshake
+const MAX_HEAD_LEN: usize = 16 * 1024;
+/// The default for [`WebSocket::set_max_message_len`]
+pub const MAX_MESSAGE_LEN: usize = 16 * 1024 * 1024;
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xa;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("bad handshake: {0}")]
+    Handshake(String),
+    #[error("protocol error: {0}")]
+    Protocol(&'static str),
+    #[error("message of {0} bytes is too large")]
+    TooLarge(u64),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    /// The close frame, with the status code if the peer sent one
+    Close(Option<u16>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Role {
+    Server,
+    Client,
+}
+
+/// One end of a WebSocket connection over `S`.
+///
+/// [`WebSocket::recv`] reassembles fragmented messages but hands control frames back to the
+/// caller rather than answering them, so that all writes to the connection can go through
+/// whatever lock the caller uses for sending.
+#[derive(Debug)]
+pub struct WebSocket<S> {
+    stream: S,
+    role: Role,
+    max_message_len: usize,
+}
+
+impl<S: Read + Write> WebSocket<S> {
+    /// Perform the server side of the opening handshake, returning the socket and the path
+    /// which was requested. Requests which aren't WebSocket upgrades are answered with
+    /// `400 Bad Request`.
+    pub fn accept(mut stream: S) -> Result<(Self, String), Error> {
+        let head = read_head(&mut stream)?;
+        match upgrade_request(&head) {
+            Ok((path, key)) => {
+                let response = format!(
+                    "HTTP/1.1 101 Switching Protocols\r\n\
+                     Upgrade: websocket\r\n\
+                     Connection: Upgrade\r\n\
+                     Sec-WebSocket-Accept: {}\r\n\r\n",
+                    accept_key(&key)
+                );
+                stream.write_all(response.as_bytes())?;
+                stream.flush()?;
+                let socket = WebSocket {
+                    stream,
+                    role: Role::Server,
+                    max_message_len: MAX_MESSAGE_LEN,
+                };
+                Ok((socket, path))
+            }
+            Err(problem) => {
+                let response = format!(
+                    "HTTP/1.1 400 Bad Request\r\n\
+                     Sec-WebSocket-Version: 13\r\n\
+                     Content-Length: {}\r\n\r\n{}",
+                    problem.len(),
+                    problem
+                );
+                stream.write_all(response.as_bytes())?;
+                stream.flush()?;
+                Err(Error::Handshake(problem))
+            }
+        }
+    }
+
+    /// Perform the client side of the opening handshake for `path` on `host`
+    pub fn connect(mut stream: S, host: &str, path: &str) -> Result<Self, Error> {
+        let key = base64(&random_bytes::<16>()?);
+        let request = format!(
+            "GET {} HTTP/1.1\r\n\
+             Host: {}\r\n\
+             Upgrade: websocket\r\n\
+             Connection: Upgrade\r\n\
+             Sec-WebSocket-Key: {}\r\n\
+             Sec-WebSocket-Version: 13\r\n\r\n",
+            path, host, key
+        );
+        stream.write_all(request.as_bytes())?;
+        stream.flush()?;
+        let head = read_head(&mut stream)?;
+        let status = head.first().map(String::as_str).unwrap_or_default();
+        if status.split(' ').nth(1) != Some("101") {
+            return Err(Error::Handshake(format!("unexpected response: {}", status)));
+        }
+        if header(&head, "sec-websocket-accept") != Some(accept_key(&key).as_str()) {
+            return Err(Error::Handshake("wrong Sec-WebSocket-Accept".to_string()));
+        }
+        Ok(WebSocket {
+            stream,
+            role: Role::Client,
+            max_message_len: MAX_MESSAGE_LEN,
+        })
+    }
+
+    pub fn max_message_len(&self) -> usize {
+        self.max_message_len
+    }
+
+    /// Reject messages longer than `len` bytes, in either direction. Memory for a message is
+    /// only allocated as its bytes arrive, so this bounds what one connection can use.
+    pub fn set_max_message_len(&mut self, len: usize) {
+        self.max_message_len = len;
+    }
+
+    pub fn send(&mut self, message: &Message) -> Result<(), Error> {
+        let close;
+        let (opcode, payload) = match message {
+            Message::Text(text) => (OP_TEXT, text.as_bytes()),
+            Message::Binary(bytes) => (OP_BINARY, bytes.as_slice()),
+            Message::Ping(bytes) => (OP_PING, bytes.as_slice()),
+            Message::Pong(bytes) => (OP_PONG, bytes.as_slice()),
+            Message::Close(code) => {
+                close = code.map(u16::to_be_bytes);
+                (OP_CLOSE, close.as_ref().map_or(&[][..], |code| &code[..]))
+            }
+        };
+        if payload.len() > self.max_message_len {
+            return Err(Error::TooLarge(payload.len() as u64));
+        }
+        if opcode >= OP_CLOSE && payload.len() > 125 {
+            return Err(Error::Protocol("control frame payload too long"));
+        }
+
+        let mut frame = Vec::with_capacity(payload.len() + 14);
+        frame.push(0x80 | opcode);
+        let mask_bit = if self.role == Role::Client { 0x80 } else { 0 };
+        if payload.len() < 126 {
+            frame.push(mask_bit | payload.len() as u8);
+        } else if let Ok(len) = u16::try_from(payload.len()) {
+            frame.push(mask_bit | 126);
+            frame.extend_from_slice(&len.to_be_bytes());
+        } else {
+            frame.push(mask_bit | 127);
+            frame.extend_from_slice(&(payload.len() as u64).to_be_bytes());
+        }
+        if self.role == Role::Client {
+            let mask = random_bytes::<4>()?;
+            frame.extend_from_slice(&mask);
+            frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        } else {
+            frame.extend_from_slice(payload);
+        }
+        self.stream.write_all(&frame)?;
+        self.stream.flush()?;
+        Ok(())
+    }
+
+    /// Wait for the next message. Returns `None` if the connection closed without a close frame.
+    pub fn recv(&mut self) -> Result<Option<Message>, Error> {
+        let mut fragmented: Option<(u8, Vec<u8>)> = None;
+        loop {
+            let frame = match self.read_frame(fragmented.as_ref().map_or(0, |(_, b)| b.len()))? {
+                Some(frame) => frame,
+                None => return Ok(None),
+            };
+            match frame.opcode {
+                OP_PING => return Ok(Some(Message::Ping(frame.payload))),
+                OP_PONG => return Ok(Some(Message::Pong(frame.payload))),
+                OP_CLOSE => {
+                    let code = match frame.payload.len() {
+                        0 => None,
+                        1 => return Err(Error::Protocol("truncated close code")),
+                        _ => Some(u16::from_be_bytes([frame.payload[0], frame.payload[1]])),
+                    };
+                    return Ok(Some(Message::Close(code)));
+                }
+                OP_TEXT | OP_BINARY if fragmented.is_some() => {
+                    return Err(Error::Protocol("new message before the last one finished"))
+                }
+                OP_TEXT | OP_BINARY => fragmented = Some((frame.opcode, frame.payload)),
+                OP_CONTINUATION => match &mut fragmented {
+                    Some((_, payload)) => payload.extend_from_slice(&frame.payload),
+                    None => return Err(Error::Protocol("continuation of nothing")),
+                },
+                _ => return Err(Error::Protocol("unknown opcode")),
+            }
+            if frame.fin {
+                if let Some((opcode, payload)) = fragmented.take() {
+                    return if opcode == OP_TEXT {
+                        String::from_utf8(payload)
+                            .map(|text| Some(Message::Text(text)))
+                            .map_err(|_| Error::Protocol("text message is not UTF-8"))
+                    } else {
+                        Ok(Some(Message::Binary(payload)))
+                    };
+                }
+            }
+        }
+    }
+
+    /// Read one frame, `buffered` is the length of the message assembled so far
+    fn read_frame(&mut self, buffered: usize) -> Result<Option<Frame>, Error> {
+        let mut header = [0; 2];
+        match self.stream.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+        let fin = header[0] & 0x80 != 0;
+        let opcode = header[0] & 0x0f;
+        if header[0] & 0x70 != 0 {
+            return Err(Error::Protocol("reserved bits set"));
+        }
+        let masked = header[1] & 0x80 != 0;
+        if masked != (self.role == Role::Server) {
+            return Err(Error::Protocol("wrong masking for direction"));
+        }
+        let len = match header[1] & 0x7f {
+            126 => {
+                let mut len = [0; 2];
+                self.stream.read_exact(&mut len)?;
+                u64::from(u16::from_be_bytes(len))
+            }
+            127 => {
+                let mut len = [0; 8];
+                self.stream.read_exact(&mut len)?;
+                u64::from_be_bytes(len)
+            }
+            len => u64::from(len),
+        };
+        if opcode >= OP_CLOSE && (len > 125 || !fin) {
+            return Err(Error::Protocol("bad control frame"));
+        }
+        if len.saturating_add(buffered as u64) > self.max_message_len as u64 {
+            return Err(Error::TooLarge(len.saturating_add(buffered as u64)));
+        }
+        let mut mask = [0; 4];
+        if masked {
+            self.stream.read_exact(&mut mask)?;
+        }
+        // grown as the bytes arrive, rather than trusting the length up front
+        let mut payload = Vec::new();
+        (&mut self.stream).take(len).read_to_end(&mut payload)?;
+        if payload.len() as u64 != len {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        if masked {
+            for (i, byte) in payload.iter_mut().enumerate() {
+                *byte ^= mask[i % 4];
+            }
+        }
+        Ok(Some(Frame {
+            fin,
+            opcode,
+            payload,
+        }))
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+}
+
+impl WebSocket<TcpStream> {
+    /// Another handle to the same connection, e.g. to send from one thread while another
+    /// receives
+    pub fn try_clone(&self) -> io::Result<Self> {
+        Ok(WebSocket {
+            stream: self.stream.try_clone()?,
+            role: self.role,
+            max_message_len: self.max_message_len,
+        })
+    }
+}
+
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+/// The `Sec-WebSocket-Accept` value for a `Sec-WebSocket-Key`
+pub fn accept_key(key: &str) -> String {
+    let mut input = key.trim().as_bytes().to_vec();
+    input.extend_from_slice(ACCEPT_GUID.as_bytes());
+    base64(&sha1(&input))
+}
+
+/// Check that `head` is a WebSocket upgrade, returning the path and key if so
+fn upgrade_request(head: &[String]) -> Result<(String, String), String> {
+    let mut request_line = head
+        .first()
+        .map(String::as_str)
+        .unwrap_or_default()
+        .split(' ');
+    let path = match (request_line.next(), request_line.next()) {
+        (Some("GET"), Some(path)) => path.to_string(),
+        _ => return Err("expected a GET request".to_string()),
+    };
+    let upgrade = header(head, "upgrade").unwrap_or_default();
+    let connection = header(head, "connection").unwrap_or_default();
+    if !upgrade.eq_ignore_ascii_case("websocket")
+        || !connection
+            .split(',')
+            .any(|token| token.trim().eq_ignore_ascii_case("upgrade"))
+    {
+        return Err("expected a WebSocket upgrade".to_string());
+    }
+    if header(head, "sec-websocket-version") != Some("13") {
+        return Err("unsupported WebSocket version".to_string());
+    }
+    match header(head, "sec-websocket-key") {
+        Some(key) => Ok((path, key.to_string())),
+        None => Err("missing Sec-WebSocket-Key".to_string()),
+    }
+}
+
+/// Read the lines of an HTTP head up to the blank line which ends it. This reads a byte at a
+/// time so that nothing after the head is consumed.
+fn read_head<S: Read>(stream: &mut S) -> Result<Vec<String>, Error> {
+    let mut head = Vec::new();
+    let mut byte = [0];
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() >= MAX_HEAD_LEN {
+            return Err(Error::Handshake("head too long".to_string()));
+        }
+        stream.read_exact(&mut byte)?;
+        head.push(byte[0]);
+    }
+    let head =
+        String::from_utf8(head).map_err(|_| Error::Handshake("head is not UTF-8".to_string()))?;
+    Ok(head
+        .split("\r\n")
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+fn header<'a>(head: &'a [String], name: &str) -> Option<&'a str> {
+    head.iter().skip(1).find_map(|line| {
+        let (key, value) = line.split_at(line.find(':')?);
+        if key.trim().eq_ignore_ascii_case(name) {
+            Some(value[1..].trim())
+        } else {
+            None
+        }
+    })
+}
+
+/// Masks and handshake keys have to be unpredictable, so they come from the OS
+fn random_bytes<const N: usize>() -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    getrandom::getrandom(&mut bytes).map_err(|e| io::Error::other(e.to_string()))?;
+    Ok(bytes)
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = u32::from(b[0]) << 16 | u32::from(b[1]) << 8 | u32::from(b[2]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// SHA-1, which the handshake requires. It isn't used for anything which needs to be secure.
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [
+        0x6745_2301,
+        0xefcd_ab89,
+        0x98ba_dcfe,
+        0x1032_5476,
+        0xc3d2_e1f0,
+    ];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+
+    for block in message.chunks(64) {
+        let mut w = [0_u32; 80];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes(word.try_into().unwrap());
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a82_7999),
+                20..=39 => (b ^ c ^ d, 0x6ed9_eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1b_bcdc),
+                _ => (b ^ c ^ d, 0xca62_c1d6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (h, v) in h.iter_mut().zip([a, b, c, d, e].iter()) {
+            *h = h.wrapping_add(*v);
+        }
+    }
+
+    let mut digest = [0; 20];
+    for (chunk, word) in digest.chunks_mut(4).zip(h.iter()) {
+        chunk.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
@@ -0,0 +1,151 @@
+use std::{
+    convert::TryInto,
+    path::PathBuf,
+    sync::mpsc::{self, Receiver},
+};
+
+use amp::SortedVec;
+use automerge_backend::{AutomergeError, Backend, Change, Quotas, SyncMessage, SyncState};
+use automerge_protocol as amp;
+use automerge_sync_server::{Connection, Error, Server, OUTGOING_QUEUE_LEN};
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "automerge-sync-server-{}-{}",
+        name,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+/// A change setting each of `keys` in the root of `backend`
+fn set(backend: &mut Backend, actor: &amp::ActorId, keys: &[&str]) -> Change {
+    let seq = backend
+        .get_changes_for_actor_id(actor)
+        .map_or(0, |changes| changes.len()) as u64
+        + 1;
+    let change: Change = amp::Change {
+        actor_id: actor.clone(),
+        seq,
+        start_op: backend.get_changes(&[]).len() as u64 + 1,
+        time: 0,
+        message: None,
+        hash: None,
+        deps: backend.get_heads(),
+        operations: keys
+            .iter()
+            .map(|key| amp::Op {
+                action: amp::OpType::Set(amp::ScalarValue::Int(seq as i64)),
+                obj: amp::ObjectId::Root,
+                key: (*key).into(),
+                insert: false,
+                pred: SortedVec::new(),
+            })
+            .collect(),
+        extra_bytes: Vec::new(),
+    }
+    .into();
+    backend.apply_changes(vec![change.clone()]).unwrap();
+    change
+}
+
+struct Client<'a> {
+    connection: Connection<'a>,
+    incoming: Receiver<Vec<u8>>,
+    backend: Backend,
+    state: SyncState,
+}
+
+impl<'a> Client<'a> {
+    fn join(server: &'a Server, doc_id: &str) -> Self {
+        let (outgoing, incoming) = mpsc::sync_channel(OUTGOING_QUEUE_LEN);
+        Client {
+            connection: server.join(doc_id, outgoing).unwrap(),
+            incoming,
+            backend: Backend::new(),
+            state: SyncState::default(),
+        }
+    }
+
+    /// Exchange sync messages with the server until neither side has anything more to say
+    fn sync(&mut self) -> Result<(), Error> {
+        loop {
+            if let Some(message) = self.backend.generate_sync_message(&mut self.state) {
+                self.connection.receive(&message.encode().unwrap())?;
+            }
+            let mut received = false;
+            while let Ok(bytes) = self.incoming.try_recv() {
+                let message = SyncMessage::decode(&bytes).unwrap();
+                self.backend
+                    .receive_sync_message(&mut self.state, message)
+                    .unwrap();
+                received = true;
+            }
+            if !received {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[test]
+fn test_documents_survive_the_server() {
+    let dir = temp_dir("restart");
+    let actor: amp::ActorId = "02ef21f3c9eb4087880ebedd7c4bbe43".try_into().unwrap();
+    let mut server = Server::new();
+    server.persist_to_dir(&dir, u64::MAX);
+    let mut client = Client::join(&server, "../notes/shopping");
+    set(&mut client.backend, &actor, &["milk"]);
+    client.sync().unwrap();
+    let heads = client.backend.get_heads();
+    drop(client);
+    assert!(server.documents().is_empty());
+
+    // the document ID doesn't escape the directory
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+    let mut server = Server::new();
+    server.persist_to_dir(&dir, u64::MAX);
+    let mut client = Client::join(&server, "../notes/shopping");
+    client.sync().unwrap();
+    assert_eq!(client.backend.get_heads(), heads);
+    drop(client);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_documents_are_dropped_without_a_persister() {
+    let actor: amp::ActorId = "02ef21f3c9eb4087880ebedd7c4bbe43".try_into().unwrap();
+    let server = Server::new();
+    let mut client = Client::join(&server, "notes");
+    set(&mut client.backend, &actor, &["milk"]);
+    client.sync().unwrap();
+    drop(client);
+
+    let mut client = Client::join(&server, "notes");
+    client.sync().unwrap();
+    assert!(client.backend.get_heads().is_empty());
+}
+
+#[test]
+fn test_changes_which_break_the_quotas_are_rejected() {
+    let actor: amp::ActorId = "02ef21f3c9eb4087880ebedd7c4bbe43".try_into().unwrap();
+    let mut server = Server::new();
+    server.set_quotas(Quotas {
+        max_ops_per_change: Some(1),
+        ..Quotas::default()
+    });
+    let mut client = Client::join(&server, "notes");
+    set(&mut client.backend, &actor, &["milk"]);
+    client.sync().unwrap();
+    set(&mut client.backend, &actor, &["eggs", "flour"]);
+    assert!(matches!(
+        client.sync(),
+        Err(Error::Automerge(AutomergeError::QuotaExceeded(_)))
+    ));
+
+    let mut other = Client::join(&server, "notes");
+    other.sync().unwrap();
+    assert_eq!(other.backend.get_changes(&[]).len(), 1);
+}
@@ -0,0 +1,179 @@
+use std::{
+    convert::TryInto,
+    io,
+    net::{TcpListener, TcpStream},
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+use amp::SortedVec;
+use automerge_backend::{Backend, Change, SyncMessage, SyncState};
+use automerge_protocol as amp;
+use automerge_sync_server::{
+    websocket::{accept_key, Error, Message, WebSocket},
+    Server,
+};
+
+fn start_server() -> (Arc<Server>, String) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let server = Arc::new(Server::new());
+    let serving = Arc::clone(&server);
+    thread::spawn(move || serving.serve(listener));
+    (server, address)
+}
+
+struct Client {
+    socket: WebSocket<TcpStream>,
+    backend: Backend,
+    state: SyncState,
+    ephemeral: Vec<Vec<u8>>,
+}
+
+impl Client {
+    fn connect(address: &str, doc_id: &str) -> Self {
+        let stream = TcpStream::connect(address).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+        Client {
+            socket: WebSocket::connect(stream, address, &format!("/{}", doc_id)).unwrap(),
+            backend: Backend::new(),
+            state: SyncState::default(),
+            ephemeral: Vec::new(),
+        }
+    }
+
+    /// Exchange sync messages with the server until it has nothing more to say
+    fn sync(&mut self) {
+        self.send();
+        loop {
+            match self.socket.recv() {
+                Ok(Some(Message::Binary(bytes))) => {
+                    let message = SyncMessage::decode(&bytes).unwrap();
+                    self.backend
+                        .receive_sync_message(&mut self.state, message)
+                        .unwrap();
+                    self.ephemeral.extend(self.state.take_ephemeral());
+                    self.send();
+                }
+                Ok(other) => panic!("unexpected message {:?}", other),
+                Err(Error::Io(e))
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    return
+                }
+                Err(e) => panic!("{}", e),
+            }
+        }
+    }
+
+    fn send(&mut self) {
+        if let Some(message) = self.backend.generate_sync_message(&mut self.state) {
+            self.socket
+                .send(&Message::Binary(message.encode().unwrap()))
+                .unwrap();
+        }
+    }
+
+    fn set(&mut self, actor: &amp::ActorId, seq: u64, key: &str) {
+        let change: Change = amp::Change {
+            actor_id: actor.clone(),
+            seq,
+            start_op: self.backend.get_changes(&[]).len() as u64 + 1,
+            time: 0,
+            message: None,
+            hash: None,
+            deps: self.backend.get_heads(),
+            operations: vec![amp::Op {
+                action: amp::OpType::Set(amp::ScalarValue::Int(seq as i64)),
+                obj: amp::ObjectId::Root,
+                key: key.into(),
+                insert: false,
+                pred: SortedVec::new(),
+            }],
+            extra_bytes: Vec::new(),
+        }
+        .into();
+        self.backend.apply_changes(vec![change]).unwrap();
+    }
+}
+
+#[test]
+fn test_accept_key() {
+    // The example from RFC 6455 section 1.3
+    assert_eq!(
+        accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+        "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+    );
+}
+
+#[test]
+fn test_changes_are_relayed_between_clients_of_a_document() {
+    let (server, address) = start_server();
+    let alice: amp::ActorId = "02ef21f3c9eb4087880ebedd7c4bbe43".try_into().unwrap();
+    let bob: amp::ActorId = "2a1d376b24f744008d4af58252d644dd".try_into().unwrap();
+
+    let mut a = Client::connect(&address, "birds");
+    a.set(&alice, 1, "robin");
+    a.sync();
+    let mut b = Client::connect(&address, "birds");
+    b.sync();
+    assert_eq!(b.backend.get_heads(), a.backend.get_heads());
+
+    b.set(&bob, 1, "wren");
+    b.sync();
+    a.sync();
+    assert_eq!(a.backend.get_heads(), b.backend.get_heads());
+    assert_eq!(a.backend.get_changes(&[]).len(), 2);
+
+    let mut c = Client::connect(&address, "fish");
+    c.sync();
+    assert!(c.backend.get_heads().is_empty());
+    assert_eq!(server.documents(), vec!["birds", "fish"]);
+
+    let saved = server.save("birds").unwrap().unwrap();
+    let loaded = Backend::load(saved).unwrap();
+    assert_eq!(loaded.get_heads(), a.backend.get_heads());
+    assert!(server.save("cats").is_none());
+}
+
+#[test]
+fn test_ephemeral_messages_go_to_the_other_clients() {
+    let (_server, address) = start_server();
+    let mut a = Client::connect(&address, "cursors");
+    let mut b = Client::connect(&address, "cursors");
+    a.sync();
+    b.sync();
+
+    let message = SyncState::encode_ephemeral(b"over here").unwrap();
+    a.socket.send(&Message::Binary(message)).unwrap();
+    a.sync();
+    b.sync();
+    assert_eq!(b.ephemeral, vec![b"over here".to_vec()]);
+    assert!(a.ephemeral.is_empty());
+}
+
+#[test]
+fn test_pings_are_answered_and_plain_http_is_refused() {
+    let (_server, address) = start_server();
+    let mut a = Client::connect(&address, "birds");
+    a.sync();
+    a.socket.send(&Message::Ping(b"hello".to_vec())).unwrap();
+    assert_eq!(
+        a.socket.recv().unwrap(),
+        Some(Message::Pong(b"hello".to_vec()))
+    );
+    a.socket.send(&Message::Close(Some(1000))).unwrap();
+    assert_eq!(a.socket.recv().unwrap(), Some(Message::Close(None)));
+
+    let mut stream = TcpStream::connect(&address).unwrap();
+    io::Write::write_all(&mut stream, b"GET /birds HTTP/1.1\r\nHost: x\r\n\r\n").unwrap();
+    let mut response = String::new();
+    io::Read::read_to_string(&mut stream, &mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 400"));
+}
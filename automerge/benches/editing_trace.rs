@@ -0,0 +1,262 @@
+use std::{fs, time::Duration};
+
+use automerge::{
+    Backend, Change, Frontend, InvalidChangeRequest, LocalChange, Path, Primitive, Value,
+};
+use automerge_backend::SyncState;
+use automerge_protocol::Patch;
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use smol_str::SmolStr;
+
+/// How many edits of the trace to replay, the whole trace is ~260k edits which is too slow to
+/// run repeatedly
+const TRACE_PREFIX: usize = 10_000;
+
+/// The length of the text the random editing session starts with
+const RANDOM_TEXT_LEN: usize = 400_000;
+
+/// How many random edits to make to the text, enough that its runs of characters are broken up
+/// many times over
+const RANDOM_EDITS: usize = 30_000;
+
+/// An edit from the trace: the index, the number of characters to delete and the character to
+/// insert
+type Edit = (u32, u32, Option<SmolStr>);
+
+/// The editing trace from the automerge-perf repository, it is the history of typing an
+/// academic paper
+fn load_trace() -> Vec<Edit> {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/../perf/edits.json");
+    let json = fs::read_to_string(path).unwrap();
+    let edits: Vec<Vec<serde_json::Value>> = serde_json::from_str(&json).unwrap();
+    edits
+        .into_iter()
+        .take(TRACE_PREFIX)
+        .map(|edit| {
+            (
+                edit[0].as_u64().unwrap() as u32,
+                edit[1].as_u64().unwrap() as u32,
+                edit.get(2).map(|c| SmolStr::new(c.as_str().unwrap())),
+            )
+        })
+        .collect()
+}
+
+/// Replay `edits` one change per edit, returning the changes and the backend they produced
+fn replay(edits: &[Edit]) -> (Vec<Change>, Backend) {
+    let mut frontend = Frontend::new_with_timestamper(Box::new(|| None));
+    let mut backend = Backend::new();
+    let text = Path::root().key("text");
+    let mut changes = Vec::with_capacity(edits.len() + 1);
+
+    let (_, change) = frontend
+        .change::<_, _, InvalidChangeRequest>(None, |doc| {
+            doc.add_change(LocalChange::set(text.clone(), Value::Text(Vec::new())))
+        })
+        .unwrap();
+    let (patch, change) = backend.apply_local_change(change.unwrap()).unwrap();
+    frontend.apply_patch(patch).unwrap();
+    changes.push(change.clone());
+
+    for (index, deletions, insertion) in edits {
+        let (_, change) = frontend
+            .change::<_, _, InvalidChangeRequest>(None, |doc| {
+                for _ in 0..*deletions {
+                    doc.add_change(LocalChange::delete(text.clone().index(*index)))?;
+                }
+                if let Some(c) = insertion {
+                    doc.add_change(LocalChange::insert(
+                        text.clone().index(*index),
+                        Value::Primitive(Primitive::Str(c.clone())),
+                    ))?;
+                }
+                Ok(())
+            })
+            .unwrap();
+        let (patch, change) = backend.apply_local_change(change.unwrap()).unwrap();
+        frontend.apply_patch(patch).unwrap();
+        changes.push(change.clone());
+    }
+    (changes, backend)
+}
+
+/// The patches for a session of `RANDOM_EDITS` single character inserts and deletes at random
+/// positions in a text of `RANDOM_TEXT_LEN` characters, as another peer receives them
+fn random_edit_patches() -> Vec<Patch> {
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut frontend = Frontend::new_with_timestamper(Box::new(|| None));
+    let mut backend = Backend::new();
+    let text = Path::root().key("text");
+    let mut changes = Vec::with_capacity(RANDOM_EDITS + 1);
+
+    let characters = (0..RANDOM_TEXT_LEN)
+        .map(|_| SmolStr::new(rng.gen_range('a'..='z').to_string()))
+        .collect();
+    let (_, change) = frontend
+        .change::<_, _, InvalidChangeRequest>(None, |doc| {
+            doc.add_change(LocalChange::set(text.clone(), Value::Text(characters)))
+        })
+        .unwrap();
+    let (patch, change) = backend.apply_local_change(change.unwrap()).unwrap();
+    frontend.apply_patch(patch).unwrap();
+    changes.push(change.clone());
+
+    let mut len = RANDOM_TEXT_LEN;
+    for _ in 0..RANDOM_EDITS {
+        let delete = rng.gen_bool(0.5);
+        let index = rng.gen_range(0..if delete { len } else { len + 1 }) as u32;
+        let (_, change) = frontend
+            .change::<_, _, InvalidChangeRequest>(None, |doc| {
+                if delete {
+                    doc.add_change(LocalChange::delete(text.clone().index(index)))
+                } else {
+                    doc.add_change(LocalChange::insert(
+                        text.clone().index(index),
+                        Value::Primitive(Primitive::Str(
+                            rng.gen_range('a'..='z').to_string().into(),
+                        )),
+                    ))
+                }
+            })
+            .unwrap();
+        len = if delete { len - 1 } else { len + 1 };
+        let (patch, change) = backend.apply_local_change(change.unwrap()).unwrap();
+        frontend.apply_patch(patch).unwrap();
+        changes.push(change.clone());
+    }
+
+    let mut receiver = Backend::new();
+    changes
+        .into_iter()
+        .map(|change| receiver.apply_changes(vec![change]).unwrap())
+        .collect()
+}
+
+fn random_editing_session(c: &mut Criterion) {
+    let patches = random_edit_patches();
+
+    let mut group = c.benchmark_group("random editing session");
+    group.throughput(Throughput::Elements(RANDOM_EDITS as u64));
+    group.bench_function("apply the patches to a frontend", |b| {
+        b.iter_batched(
+            || patches.clone(),
+            |patches| {
+                let mut frontend = Frontend::new();
+                for patch in patches {
+                    frontend.apply_patch(patch).unwrap();
+                }
+                frontend
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+fn editing_trace(c: &mut Criterion) {
+    let edits = load_trace();
+    let (changes, backend) = replay(&edits);
+    let saved = backend.save().unwrap();
+
+    let mut group = c.benchmark_group("editing trace");
+    group.throughput(Throughput::Elements(edits.len() as u64));
+
+    group.bench_function("replay through a frontend and apply_local_change", |b| {
+        b.iter(|| black_box(replay(&edits)))
+    });
+
+    group.bench_function("apply_changes one at a time", |b| {
+        b.iter_batched(
+            || changes.clone(),
+            |changes| {
+                let mut backend = Backend::new();
+                for change in changes {
+                    black_box(backend.apply_changes(vec![change]).unwrap());
+                }
+                backend
+            },
+            BatchSize::LargeInput,
+        )
+    });
+
+    group.bench_function("apply_changes in one batch", |b| {
+        b.iter_batched(
+            || changes.clone(),
+            |changes| {
+                let mut backend = Backend::new();
+                black_box(backend.apply_changes(changes).unwrap());
+                backend
+            },
+            BatchSize::LargeInput,
+        )
+    });
+
+    group.bench_function("get_patch", |b| {
+        b.iter(|| black_box(backend.get_patch().unwrap()))
+    });
+
+    group.bench_function("apply the full patch to a new frontend", |b| {
+        b.iter_batched(
+            || backend.get_patch().unwrap(),
+            |patch| {
+                let mut frontend = Frontend::new();
+                frontend.apply_patch(patch).unwrap();
+                frontend
+            },
+            BatchSize::LargeInput,
+        )
+    });
+
+    group.bench_function("save", |b| b.iter(|| black_box(backend.save().unwrap())));
+
+    group.bench_function("load", |b| {
+        b.iter_batched(
+            || saved.clone(),
+            |saved| Backend::load(saved).unwrap(),
+            BatchSize::LargeInput,
+        )
+    });
+
+    group.bench_function("sync to an empty peer", |b| {
+        b.iter_batched(
+            || (backend.clone(), Backend::new()),
+            |(mut a, mut b)| {
+                sync(&mut a, &mut b);
+                (a, b)
+            },
+            BatchSize::LargeInput,
+        )
+    });
+
+    group.finish();
+}
+
+/// Exchange sync messages between `a` and `b` until neither has anything to send
+fn sync(a: &mut Backend, b: &mut Backend) {
+    const MAX_ITER: u32 = 10;
+    let mut a_state = SyncState::default();
+    let mut b_state = SyncState::default();
+    for _ in 0..MAX_ITER {
+        let a_to_b = a.generate_sync_message(&mut a_state);
+        if let Some(message) = a_to_b.clone() {
+            b.receive_sync_message(&mut b_state, message).unwrap();
+        }
+        let b_to_a = b.generate_sync_message(&mut b_state);
+        if let Some(message) = b_to_a.clone() {
+            a.receive_sync_message(&mut a_state, message).unwrap();
+        }
+        if a_to_b.is_none() && b_to_a.is_none() {
+            assert_eq!(a.get_heads(), b.get_heads());
+            return;
+        }
+    }
+    panic!("did not converge within {} iterations", MAX_ITER);
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10).measurement_time(Duration::from_secs(20));
+    targets = editing_trace, random_editing_session
+}
+criterion_main!(benches);
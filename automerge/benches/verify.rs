@@ -0,0 +1,67 @@
+use automerge::{Backend, Change, Frontend, InvalidChangeRequest, LocalChange, Path, Value};
+use automerge_backend::{ChangeHasher, Sha2};
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+
+/// A text object typed one character per change, which gives lots of small changes
+fn typed_backend(characters: u32) -> Backend {
+    let mut frontend = Frontend::new();
+    let mut backend = Backend::new();
+    let text = Path::root().key("text");
+    let (_, change) = frontend
+        .change::<_, _, InvalidChangeRequest>(None, |doc| {
+            doc.add_change(LocalChange::set(text.clone(), Value::Text(Vec::new())))
+        })
+        .unwrap();
+    backend.apply_local_change(change.unwrap()).unwrap();
+    for i in 0..characters {
+        let (_, change) = frontend
+            .change::<_, _, InvalidChangeRequest>(None, |doc| {
+                doc.add_change(LocalChange::insert(
+                    text.clone().index(i),
+                    Value::Primitive(automerge::Primitive::Str("a".into())),
+                ))
+            })
+            .unwrap();
+        backend.apply_local_change(change.unwrap()).unwrap();
+    }
+    backend
+}
+
+fn verification_throughput(c: &mut Criterion) {
+    let backend = typed_backend(1000);
+    let document = backend.save().unwrap();
+    let changes: Vec<Vec<u8>> = backend
+        .get_changes(&[])
+        .iter()
+        .map(|change| change.raw_bytes().to_vec())
+        .collect();
+    let change_bytes: usize = changes.iter().map(Vec::len).sum();
+
+    let mut group = c.benchmark_group("verify");
+
+    group.throughput(Throughput::Bytes(document.len() as u64));
+    group.bench_function("sha256 of a document", |b| {
+        b.iter(|| black_box(Sha2.sha256(&document)))
+    });
+    group.bench_function("load a document", |b| {
+        b.iter(|| black_box(Backend::load(document.clone()).unwrap()))
+    });
+
+    group.throughput(Throughput::Bytes(change_bytes as u64));
+    group.bench_function("decode change chunks", |b| {
+        b.iter(|| {
+            for bytes in &changes {
+                black_box(Change::from_bytes(bytes.clone()).unwrap());
+            }
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default();
+    targets = verification_throughput
+}
+criterion_main!(benches);
@@ -1,3 +1,6 @@
+pub mod simulation;
+pub mod testing;
+
 pub use automerge_backend::{Backend, Change};
 pub use automerge_frontend::{
     value_ref, Frontend, InvalidChangeRequest, LocalChange, MutableDocument, Path, Primitive, Value,
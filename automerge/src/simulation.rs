@@ -0,0 +1,561 @@
+//! A deterministic simulation of peers editing a document over an unreliable network.
+//!
+//! A [`Simulation`] runs a number of peers, each a [`Frontend`] with its own [`Backend`]. The
+//! peers make local edits and exchange their changes, or sync protocol messages, over a simulated
+//! network which can drop, duplicate and reorder messages as set by a [`Network`]. Every random
+//! choice comes from the seed, so a failing run can be repeated exactly and its
+//! [`events`](Simulation::events) inspected.
+//!
+//! [`Simulation::settle`] delivers everything over a reliable network until the peers have
+//! exchanged all of their changes, after which [`Simulation::check_converged`] checks that they
+//! all have the same document.
+//!
+//! ```
+//! use automerge::simulation::{Network, Simulation, Transport};
+//!
+//! let network = Network {
+//!     drop: 0.1,
+//!     duplicate: 0.1,
+//!     reorder: true,
+//!     transport: Transport::Sync,
+//! };
+//! for seed in 0..5 {
+//!     let mut simulation = Simulation::new(3, seed, network.clone());
+//!     simulation.run(100).unwrap();
+//!     simulation.settle().unwrap();
+//!     simulation.check_converged().unwrap();
+//! }
+//! ```
+//!
+//! Scenario tests can make specific edits with [`Simulation::change`] and control delivery with
+//! [`Simulation::sync`] and [`Simulation::deliver_next`] rather than using [`Simulation::run`].
+//! Every peer starts with a list at [`LIST_KEY`] and a text object at [`TEXT_KEY`] to edit.
+use std::fmt;
+
+use automerge_backend::{Backend, Change, SyncMessage, SyncState};
+use automerge_frontend::{
+    Frontend, InvalidChangeRequest, LocalChange, MutableDocument, Path, Primitive, Value,
+};
+
+use crate::testing::Rng;
+
+/// The key of the list every peer starts with
+pub const LIST_KEY: &str = "list";
+/// The key of the text object every peer starts with
+pub const TEXT_KEY: &str = "text";
+/// The keys random edits set and delete in the root map
+const KEYS: [&str; 3] = ["a", "b", "c"];
+/// How many messages [`Simulation::settle`] delivers before giving up
+const MAX_SETTLE_MESSAGES: usize = 100_000;
+
+/// What the peers send each other
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    /// Each local change is sent to every other peer as soon as it is made
+    Changes,
+    /// Peers only send sync protocol messages, a sync round starts when
+    /// [`Simulation::sync`] is called (which [`Simulation::run`] does at random) and carries on
+    /// with a reply to each message until the two peers are in sync
+    Sync,
+}
+
+/// How unreliable the simulated network is
+#[derive(Debug, Clone, PartialEq)]
+pub struct Network {
+    /// The probability that a message is lost
+    pub drop: f64,
+    /// The probability that a message which is delivered will be delivered again later
+    pub duplicate: f64,
+    /// Deliver messages in a random order rather than the order they were sent in, with
+    /// [`Transport::Sync`] messages from one peer to another still arrive in order
+    pub reorder: bool,
+    pub transport: Transport,
+}
+
+impl Default for Network {
+    /// A network which delivers every change once and in order
+    fn default() -> Self {
+        Network {
+            drop: 0.0,
+            duplicate: 0.0,
+            reorder: false,
+            transport: Transport::Changes,
+        }
+    }
+}
+
+/// Something which happened during a simulation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// `peer` made a local change
+    Change {
+        peer: usize,
+    },
+    Send {
+        from: usize,
+        to: usize,
+    },
+    Drop {
+        from: usize,
+        to: usize,
+    },
+    /// The message from `from` to `to` was delivered and will be delivered again
+    Duplicate {
+        from: usize,
+        to: usize,
+    },
+    Deliver {
+        from: usize,
+        to: usize,
+    },
+}
+
+#[derive(Debug)]
+pub enum SimulationError {
+    /// The frontend or backend of `peer` returned an error
+    Peer { peer: usize, error: String },
+    /// [`Simulation::settle`] gave up before the peers had exchanged all their changes
+    NotSettled,
+    /// `peer`'s document isn't the same as the first peer's, or its frontend doesn't match its
+    /// backend
+    Diverged {
+        peer: usize,
+        expected: Box<Value>,
+        actual: Box<Value>,
+    },
+}
+
+impl fmt::Display for SimulationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SimulationError::Peer { peer, error } => write!(f, "peer {}: {}", peer, error),
+            SimulationError::NotSettled => write!(f, "the peers did not settle"),
+            SimulationError::Diverged {
+                peer,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "peer {} diverged, expected {:?} but found {:?}",
+                peer, expected, actual
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SimulationError {}
+
+fn peer_error(peer: usize) -> impl FnOnce(&dyn std::error::Error) -> SimulationError {
+    move |error| SimulationError::Peer {
+        peer,
+        error: error.to_string(),
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Payload {
+    /// Encoded changes
+    Changes(Vec<Vec<u8>>),
+    /// An encoded sync message
+    Sync(Vec<u8>),
+}
+
+#[derive(Debug, Clone)]
+struct Message {
+    from: usize,
+    to: usize,
+    payload: Payload,
+}
+
+struct Peer {
+    frontend: Frontend,
+    backend: Backend,
+    /// The state of the sync protocol with each of the other peers
+    sync_states: Vec<SyncState>,
+}
+
+impl Peer {
+    fn receive(&mut self, index: usize, changes: Vec<Change>) -> Result<(), SimulationError> {
+        let patch = self
+            .backend
+            .apply_changes(changes)
+            .map_err(|e| peer_error(index)(&e))?;
+        self.frontend
+            .apply_patch(patch)
+            .map_err(|e| peer_error(index)(&e))
+    }
+
+    fn len(&self, key: &str) -> usize {
+        match self.frontend.get_value(&Path::root().key(key)) {
+            Some(Value::List(items)) => items.len(),
+            Some(Value::Text(graphemes)) => graphemes.len(),
+            _ => 0,
+        }
+    }
+}
+
+/// Peers editing a document over a simulated network, see the [module documentation](self)
+pub struct Simulation {
+    peers: Vec<Peer>,
+    network: Network,
+    /// Messages which have been sent but not delivered, oldest first
+    in_flight: Vec<Message>,
+    rng: Rng,
+    events: Vec<Event>,
+}
+
+impl Simulation {
+    /// `peers` peers which make their random choices using `seed`.
+    ///
+    /// # Panics
+    ///
+    /// If `peers` is zero or more than 255
+    pub fn new(peers: usize, seed: u64, network: Network) -> Simulation {
+        assert!(
+            (1..=255).contains(&peers),
+            "a simulation needs between 1 and 255 peers"
+        );
+        let mut peers: Vec<Peer> = (0..peers)
+            .map(|index| Peer {
+                // no timestamps, so the hashes of the changes only depend on the seed
+                frontend: Frontend::new_with_timestamper_and_actor_id(
+                    Box::new(|| None),
+                    &[index as u8 + 1; 16],
+                ),
+                backend: Backend::new(),
+                sync_states: (0..peers).map(|_| SyncState::default()).collect(),
+            })
+            .collect();
+
+        let (_, change) = peers[0]
+            .frontend
+            .change::<_, _, InvalidChangeRequest>(None, |doc| {
+                doc.add_change(LocalChange::set(
+                    Path::root().key(LIST_KEY),
+                    Value::List(Vec::new()),
+                ))?;
+                doc.add_change(LocalChange::set(
+                    Path::root().key(TEXT_KEY),
+                    Value::Text(Vec::new()),
+                ))
+            })
+            .unwrap();
+        let (patch, change) = peers[0]
+            .backend
+            .apply_local_change(change.unwrap())
+            .unwrap();
+        let change = change.clone();
+        peers[0].frontend.apply_patch(patch).unwrap();
+        for (index, peer) in peers.iter_mut().enumerate().skip(1) {
+            peer.receive(index, vec![change.clone()]).unwrap();
+        }
+
+        Simulation {
+            peers,
+            network,
+            in_flight: Vec::new(),
+            rng: Rng(seed),
+            events: Vec::new(),
+        }
+    }
+
+    pub fn peers(&self) -> usize {
+        self.peers.len()
+    }
+
+    pub fn frontend(&self, peer: usize) -> &Frontend {
+        &self.peers[peer].frontend
+    }
+
+    pub fn backend(&self, peer: usize) -> &Backend {
+        &self.peers[peer].backend
+    }
+
+    /// Everything which has happened so far, in order
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    /// The number of messages which have been sent and not yet delivered or dropped
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    /// Make a local change on `peer`, which is applied to its backend straight away. With
+    /// [`Transport::Changes`] the change is sent to every other peer.
+    pub fn change<F>(&mut self, peer: usize, f: F) -> Result<(), SimulationError>
+    where
+        F: FnOnce(&mut dyn MutableDocument) -> Result<(), InvalidChangeRequest>,
+    {
+        let p = &mut self.peers[peer];
+        let (_, change) = p
+            .frontend
+            .change(None, f)
+            .map_err(|e| peer_error(peer)(&e))?;
+        let change = match change {
+            Some(change) => change,
+            None => return Ok(()),
+        };
+        let (patch, change) = p
+            .backend
+            .apply_local_change(change)
+            .map_err(|e| peer_error(peer)(&e))?;
+        let bytes = change.raw_bytes().to_vec();
+        p.frontend
+            .apply_patch(patch)
+            .map_err(|e| peer_error(peer)(&e))?;
+        self.events.push(Event::Change { peer });
+
+        if self.network.transport == Transport::Changes {
+            for to in (0..self.peers.len()).filter(|to| *to != peer) {
+                self.send(peer, to, Payload::Changes(vec![bytes.clone()]));
+            }
+        }
+        Ok(())
+    }
+
+    /// A random local change on `peer`: setting or deleting a key, or inserting into or removing
+    /// from the list or the text
+    pub fn random_change(&mut self, peer: usize) -> Result<(), SimulationError> {
+        let key = KEYS[self.rng.below(KEYS.len())];
+        let value = self.rng.below(1000) as i64;
+        let list_len = self.peers[peer].len(LIST_KEY);
+        let text_len = self.peers[peer].len(TEXT_KEY);
+        let local = match self.rng.below(6) {
+            // deleting a key which isn't there fails, so set it instead
+            0 if self.peers[peer]
+                .frontend
+                .get_value(&Path::root().key(key))
+                .is_some() =>
+            {
+                LocalChange::delete(Path::root().key(key))
+            }
+            1 => LocalChange::insert(
+                Path::root()
+                    .key(LIST_KEY)
+                    .index(self.rng.below(list_len + 1) as u32),
+                Value::from(value),
+            ),
+            2 if list_len > 0 => LocalChange::delete(
+                Path::root()
+                    .key(LIST_KEY)
+                    .index(self.rng.below(list_len) as u32),
+            ),
+            3 => {
+                let c = (b'a' + self.rng.below(26) as u8) as char;
+                LocalChange::insert(
+                    Path::root()
+                        .key(TEXT_KEY)
+                        .index(self.rng.below(text_len + 1) as u32),
+                    Value::Primitive(Primitive::Str(c.to_string().into())),
+                )
+            }
+            4 if text_len > 0 => LocalChange::delete(
+                Path::root()
+                    .key(TEXT_KEY)
+                    .index(self.rng.below(text_len) as u32),
+            ),
+            _ => LocalChange::set(Path::root().key(key), Value::from(value)),
+        };
+        self.change(peer, |doc| doc.add_change(local))
+    }
+
+    /// Start a sync round from `from` to `to` by sending `to` a sync message, if `from` has one
+    /// to send
+    pub fn sync(&mut self, from: usize, to: usize) -> Result<(), SimulationError> {
+        let peer = &mut self.peers[from];
+        let message = peer
+            .backend
+            .generate_sync_message(&mut peer.sync_states[to]);
+        if let Some(message) = message {
+            let bytes = message.encode().map_err(|e| peer_error(from)(&e))?;
+            self.send(from, to, Payload::Sync(bytes));
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, from: usize, to: usize, payload: Payload) {
+        self.events.push(Event::Send { from, to });
+        self.in_flight.push(Message { from, to, payload });
+    }
+
+    /// Deliver (or drop) one message, returning false if there were none in flight
+    pub fn deliver_next(&mut self) -> Result<bool, SimulationError> {
+        if self.in_flight.is_empty() {
+            return Ok(false);
+        }
+        let mut index = if self.network.reorder {
+            self.rng.below(self.in_flight.len())
+        } else {
+            0
+        };
+        if self.network.transport == Transport::Sync {
+            // the sync protocol runs over a connection which delivers messages in order, so only
+            // messages between different pairs of peers are reordered
+            let (from, to) = (self.in_flight[index].from, self.in_flight[index].to);
+            index = self
+                .in_flight
+                .iter()
+                .position(|message| message.from == from && message.to == to)
+                .unwrap();
+        }
+        let message = self.in_flight.remove(index);
+        let (from, to) = (message.from, message.to);
+        if self.rng.chance(self.network.drop) {
+            self.events.push(Event::Drop { from, to });
+            return Ok(true);
+        }
+        if self.rng.chance(self.network.duplicate) {
+            self.events.push(Event::Duplicate { from, to });
+            self.in_flight.push(message.clone());
+        } else {
+            self.events.push(Event::Deliver { from, to });
+        }
+
+        match message.payload {
+            Payload::Changes(changes) => {
+                let changes = changes
+                    .into_iter()
+                    .map(Change::from_bytes)
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|e| peer_error(to)(&e))?;
+                self.peers[to].receive(to, changes)?;
+            }
+            Payload::Sync(bytes) => {
+                let message = SyncMessage::decode(&bytes).map_err(|e| peer_error(to)(&e))?;
+                let peer = &mut self.peers[to];
+                let patch = peer
+                    .backend
+                    .receive_sync_message(&mut peer.sync_states[from], message)
+                    .map_err(|e| peer_error(to)(&e))?;
+                if let Some(patch) = patch {
+                    peer.frontend
+                        .apply_patch(patch)
+                        .map_err(|e| peer_error(to)(&e))?;
+                }
+                self.sync(to, from)?;
+            }
+        }
+        Ok(true)
+    }
+
+    /// Take `steps` random steps: local changes on random peers, deliveries of messages and,
+    /// with [`Transport::Sync`], the start of sync rounds between random pairs of peers
+    pub fn run(&mut self, steps: usize) -> Result<(), SimulationError> {
+        for _ in 0..steps {
+            let peer = self.rng.below(self.peers.len());
+            match self.rng.below(4) {
+                0 => self.random_change(peer)?,
+                1 if self.network.transport == Transport::Sync && self.peers.len() > 1 => {
+                    let mut to = self.rng.below(self.peers.len() - 1);
+                    if to >= peer {
+                        to += 1;
+                    }
+                    self.sync(peer, to)?;
+                }
+                _ => {
+                    if !self.deliver_next()? {
+                        self.random_change(peer)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Heal the network and have each pair of peers catch up on what was lost, until every peer
+    /// has every change. With [`Transport::Changes`] the messages in flight are delivered first.
+    /// With [`Transport::Sync`] the peers reconnect: messages in flight are discarded and the
+    /// sync states reset, as the protocol doesn't resend changes it thinks were delivered.
+    pub fn settle(&mut self) -> Result<(), SimulationError> {
+        let reliable = Network {
+            drop: 0.0,
+            duplicate: 0.0,
+            ..self.network.clone()
+        };
+        let network = std::mem::replace(&mut self.network, reliable);
+        let settled = self.settle_reliably();
+        self.network = network;
+        settled
+    }
+
+    fn settle_reliably(&mut self) -> Result<(), SimulationError> {
+        if self.network.transport == Transport::Sync {
+            self.in_flight.clear();
+            self.reset_sync_states();
+        }
+        let mut delivered = 0;
+        loop {
+            while self.deliver_next()? {
+                delivered += 1;
+                if delivered > MAX_SETTLE_MESSAGES {
+                    return Err(SimulationError::NotSettled);
+                }
+            }
+            if self.heads_agree() {
+                return Ok(());
+            }
+            self.reset_sync_states();
+            for from in 0..self.peers.len() {
+                for to in (0..self.peers.len()).filter(|to| *to != from) {
+                    match self.network.transport {
+                        Transport::Sync => self.sync(from, to)?,
+                        Transport::Changes => {
+                            let missing: Vec<Vec<u8>> = self.peers[to]
+                                .backend
+                                .get_changes_added(&self.peers[from].backend)
+                                .into_iter()
+                                .map(|change| change.raw_bytes().to_vec())
+                                .collect();
+                            if !missing.is_empty() {
+                                self.send(from, to, Payload::Changes(missing));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn reset_sync_states(&mut self) {
+        for peer in &mut self.peers {
+            for state in &mut peer.sync_states {
+                *state = SyncState::default();
+            }
+        }
+    }
+
+    fn heads_agree(&self) -> bool {
+        let heads = self.peers[0].backend.get_heads();
+        self.peers[1..].iter().all(|peer| {
+            peer.backend.get_heads() == heads && peer.backend.get_missing_deps(&[]).is_empty()
+        }) && self.peers[0].backend.get_missing_deps(&[]).is_empty()
+    }
+
+    /// Check that every peer has the same heads and document as the first, and that each
+    /// frontend shows the document its backend has
+    pub fn check_converged(&mut self) -> Result<(), SimulationError> {
+        if !self.heads_agree() {
+            return Err(SimulationError::NotSettled);
+        }
+        let expected = self.peers[0].frontend.state().clone();
+        for (index, peer) in self.peers.iter_mut().enumerate() {
+            let patch = peer
+                .backend
+                .get_patch()
+                .map_err(|e| peer_error(index)(&e))?;
+            let from_backend =
+                Frontend::value_of_patch(patch).map_err(|e| peer_error(index)(&e))?;
+            for actual in [peer.frontend.state().clone(), from_backend] {
+                if actual != expected {
+                    return Err(SimulationError::Diverged {
+                        peer: index,
+                        expected: Box::new(expected),
+                        actual: Box::new(actual),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,554 @@
+//! A randomised concurrency checker for [`Frontend`].
+//!
+//! [`Harness`] runs a handful of peers, each a frontend with its own backend, through a random
+//! interleaving of local changes, acknowledgements of those changes by the backend, delivery of
+//! changes between peers and reads. After every step the state of each frontend is compared with
+//! a model: a plain `serde_json` tree computed directly from the changes the frontend should be
+//! showing, using the merge rules of the data model (the op with the highest ID wins, sequence
+//! elements are ordered by RGA). The first step after which a frontend disagrees with the model
+//! is reported as a [`Divergence`], along with the steps which led up to it so the failure can be
+//! replayed with [`Harness::replay`].
+//!
+//! The harness only makes maps, lists, and primitive values, so the model doesn't know about
+//! counters, text, moves or marks.
+//!
+//! ```
+//! use automerge::testing::Harness;
+//!
+//! for seed in 0..10 {
+//!     Harness::new(3).run(seed, 100).unwrap();
+//! }
+//! ```
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+};
+
+use automerge_backend::Backend;
+use automerge_frontend::{Frontend, InvalidChangeRequest, LocalChange, Path, Value};
+use automerge_protocol as amp;
+
+/// The keys the harness sets and deletes in the root map
+const KEYS: [&str; 3] = ["a", "b", "c"];
+/// The key of the list the harness inserts into and removes from
+const LIST_KEY: &str = "items";
+
+/// One step of a run
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    /// Make a local change on `peer`. The change is in flight until the next
+    /// [`Step::ApplyLocal`] on the same peer.
+    Change { peer: usize, edit: Edit },
+    /// Apply the oldest in flight change of `peer` to its backend and give the resulting patch
+    /// back to its frontend
+    ApplyLocal { peer: usize },
+    /// Apply every change `from`'s backend has that `to`'s doesn't to `to`'s backend and give
+    /// the resulting patch to `to`'s frontend
+    Deliver { from: usize, to: usize },
+    /// Read `key` from the root of `peer`'s frontend
+    Read { peer: usize, key: String },
+}
+
+/// A local change made by [`Step::Change`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Edit {
+    Set {
+        key: String,
+        value: i64,
+    },
+    Delete {
+        key: String,
+    },
+    /// Replace whatever is at the list key with a new, empty list
+    CreateList,
+    Insert {
+        index: u32,
+        value: i64,
+    },
+    Remove {
+        index: u32,
+    },
+}
+
+/// What went wrong at a step
+#[derive(Debug, Clone, PartialEq)]
+pub enum Problem {
+    /// The frontend's state (or the value read by a [`Step::Read`]) doesn't match the model
+    State {
+        expected: serde_json::Value,
+        actual: serde_json::Value,
+    },
+    /// The frontend or backend returned an error
+    Error(String),
+}
+
+/// The first step of a run after which a frontend disagreed with the model
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    /// The steps of the run up to and including the one which failed
+    pub steps: Vec<Step>,
+    /// The peer whose frontend is wrong
+    pub peer: usize,
+    pub problem: Problem,
+}
+
+impl Divergence {
+    /// The step which failed
+    pub fn step(&self) -> &Step {
+        // a divergence is only created after a step has run
+        self.steps.last().unwrap()
+    }
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "peer {} diverged at step {} ({:?}): ",
+            self.peer,
+            self.steps.len() - 1,
+            self.step()
+        )?;
+        match &self.problem {
+            Problem::State { expected, actual } => {
+                write!(f, "expected {} but found {}", expected, actual)
+            }
+            Problem::Error(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Divergence {}
+
+struct Peer {
+    frontend: Frontend,
+    backend: Backend,
+    /// Local changes which haven't been applied to the backend yet, oldest first
+    in_flight: Vec<amp::Change>,
+    /// While the frontend is waiting for in flight changes it shows the changes it had when the
+    /// first of them was made plus its own local changes since, this is those two sets
+    optimistic: Option<(Vec<amp::Change>, Vec<amp::Change>)>,
+}
+
+impl Peer {
+    /// The changes the frontend's state should reflect
+    fn visible_changes(&self) -> Vec<amp::Change> {
+        match &self.optimistic {
+            Some((base, local)) => base.iter().chain(local.iter()).cloned().collect(),
+            None => self
+                .backend
+                .get_changes(&[])
+                .into_iter()
+                .map(|c| c.decode())
+                .collect(),
+        }
+    }
+}
+
+/// A set of peers to run random steps against, see the [module documentation](self)
+pub struct Harness {
+    peers: Vec<Peer>,
+}
+
+impl Harness {
+    /// A harness with `peers` default frontends
+    pub fn new(peers: usize) -> Harness {
+        Harness::with_frontends(
+            (0..peers)
+                .map(|i| Frontend::new_with_actor_id(&[i as u8 + 1; 16]))
+                .collect(),
+        )
+    }
+
+    /// A harness with a peer for each of `frontends`, to check frontends which have been
+    /// customised (e.g. with a timestamper or an initial state). Each frontend must have a
+    /// different actor ID and any changes it has already made are ignored.
+    pub fn with_frontends(frontends: Vec<Frontend>) -> Harness {
+        Harness {
+            peers: frontends
+                .into_iter()
+                .map(|frontend| Peer {
+                    frontend,
+                    backend: Backend::new(),
+                    in_flight: Vec::new(),
+                    optimistic: None,
+                })
+                .collect(),
+        }
+    }
+
+    /// Run `steps` random steps chosen using `seed`, stopping at the first divergence. The same
+    /// seed always produces the same steps for the same number of peers.
+    pub fn run(&mut self, seed: u64, steps: usize) -> Result<(), Divergence> {
+        let mut rng = Rng(seed);
+        let mut history = Vec::with_capacity(steps);
+        for _ in 0..steps {
+            let step = self.random_step(&mut rng);
+            history.push(step.clone());
+            self.check_step(&step, &history)?;
+        }
+        Ok(())
+    }
+
+    /// Run the given steps in order, stopping at the first divergence, e.g. to reproduce a
+    /// failure from [`Harness::run`] after changing the frontend
+    pub fn replay(&mut self, steps: &[Step]) -> Result<(), Divergence> {
+        let mut history = Vec::with_capacity(steps.len());
+        for step in steps {
+            history.push(step.clone());
+            self.check_step(step, &history)?;
+        }
+        Ok(())
+    }
+
+    fn check_step(&mut self, step: &Step, history: &[Step]) -> Result<(), Divergence> {
+        let diverged = |peer, problem| Divergence {
+            steps: history.to_vec(),
+            peer,
+            problem,
+        };
+        self.apply(step)
+            .map_err(|(peer, e)| diverged(peer, Problem::Error(e)))?;
+        if let Step::Read { peer, key } = step {
+            let model = model_json(&self.peers[*peer].visible_changes());
+            let expected = model.get(key).cloned().unwrap_or(serde_json::Value::Null);
+            let actual = self.peers[*peer]
+                .frontend
+                .get_value(&Path::root().key(key.as_str()))
+                .map_or(serde_json::Value::Null, |v| v.to_json());
+            if expected != actual {
+                return Err(diverged(*peer, Problem::State { expected, actual }));
+            }
+        }
+        for (index, peer) in self.peers.iter_mut().enumerate() {
+            let expected = model_json(&peer.visible_changes());
+            let actual = peer.frontend.state().to_json();
+            if expected != actual {
+                return Err(diverged(index, Problem::State { expected, actual }));
+            }
+        }
+        Ok(())
+    }
+
+    fn apply(&mut self, step: &Step) -> Result<(), (usize, String)> {
+        match step {
+            Step::Change { peer: index, edit } => {
+                let peer = &mut self.peers[*index];
+                let local = match edit {
+                    Edit::Set { key, value } => {
+                        LocalChange::set(Path::root().key(key.as_str()), Value::from(*value))
+                    }
+                    Edit::Delete { key } => LocalChange::delete(Path::root().key(key.as_str())),
+                    Edit::CreateList => {
+                        LocalChange::set(Path::root().key(LIST_KEY), Value::List(Vec::new()))
+                    }
+                    Edit::Insert { index, value } => LocalChange::insert(
+                        Path::root().key(LIST_KEY).index(*index),
+                        Value::from(*value),
+                    ),
+                    Edit::Remove { index } => {
+                        LocalChange::delete(Path::root().key(LIST_KEY).index(*index))
+                    }
+                };
+                let (_, change) = peer
+                    .frontend
+                    .change::<_, _, InvalidChangeRequest>(None, |doc| doc.add_change(local))
+                    .map_err(|e| (*index, e.to_string()))?;
+                if let Some(change) = change {
+                    let backend = &peer.backend;
+                    let (_, local) = peer.optimistic.get_or_insert_with(|| {
+                        (
+                            backend
+                                .get_changes(&[])
+                                .into_iter()
+                                .map(|c| c.decode())
+                                .collect(),
+                            Vec::new(),
+                        )
+                    });
+                    local.push(change.clone());
+                    peer.in_flight.push(change);
+                }
+            }
+            Step::ApplyLocal { peer: index } => {
+                let peer = &mut self.peers[*index];
+                if peer.in_flight.is_empty() {
+                    return Ok(());
+                }
+                let change = peer.in_flight.remove(0);
+                let (patch, _) = peer
+                    .backend
+                    .apply_local_change(change)
+                    .map_err(|e| (*index, e.to_string()))?;
+                peer.frontend
+                    .apply_patch(patch)
+                    .map_err(|e| (*index, e.to_string()))?;
+                if peer.in_flight.is_empty() {
+                    peer.optimistic = None;
+                }
+            }
+            Step::Deliver { from, to } => {
+                let changes: Vec<_> = self.peers[*to]
+                    .backend
+                    .get_changes_added(&self.peers[*from].backend)
+                    .into_iter()
+                    .cloned()
+                    .collect();
+                if changes.is_empty() {
+                    return Ok(());
+                }
+                let peer = &mut self.peers[*to];
+                let patch = peer
+                    .backend
+                    .apply_changes(changes)
+                    .map_err(|e| (*to, e.to_string()))?;
+                peer.frontend
+                    .apply_patch(patch)
+                    .map_err(|e| (*to, e.to_string()))?;
+            }
+            Step::Read { .. } => {}
+        }
+        Ok(())
+    }
+
+    /// A random step which is valid for the current state of the peers
+    fn random_step(&mut self, rng: &mut Rng) -> Step {
+        let peers = self.peers.len();
+        let peer = rng.below(peers);
+        match rng.below(8) {
+            0..=3 => {
+                let state = self.peers[peer].frontend.state().to_json();
+                let list_len = state
+                    .get(LIST_KEY)
+                    .and_then(|v| v.as_array())
+                    .map(|items| items.len() as u32);
+                let key = KEYS[rng.below(KEYS.len())].to_string();
+                let value = rng.below(1000) as i64;
+                let edit = match (rng.below(4), list_len) {
+                    (0, _) if state.get(&key).is_some() => Edit::Delete { key },
+                    (1, Some(len)) if len > 0 && rng.below(3) == 0 => Edit::Remove {
+                        index: rng.below(len as usize) as u32,
+                    },
+                    (1, Some(len)) => Edit::Insert {
+                        index: rng.below(len as usize + 1) as u32,
+                        value,
+                    },
+                    (1, None) => Edit::CreateList,
+                    (2, Some(_)) if rng.below(10) == 0 => Edit::CreateList,
+                    _ => Edit::Set { key, value },
+                };
+                Step::Change { peer, edit }
+            }
+            4 | 5 => Step::ApplyLocal { peer },
+            6 if peers > 1 => {
+                let mut to = rng.below(peers - 1);
+                if to >= peer {
+                    to += 1;
+                }
+                Step::Deliver { from: peer, to }
+            }
+            _ => {
+                let keys = KEYS.len() + 1;
+                let key = KEYS.get(rng.below(keys)).copied().unwrap_or(LIST_KEY);
+                Step::Read {
+                    peer,
+                    key: key.to_string(),
+                }
+            }
+        }
+    }
+}
+
+/// splitmix64, which is plenty for choosing steps and means the harness doesn't need `rand`
+pub(crate) struct Rng(pub(crate) u64);
+
+impl Rng {
+    pub(crate) fn below(&mut self, n: usize) -> usize {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        (z % n as u64) as usize
+    }
+
+    /// True with the given probability
+    pub(crate) fn chance(&mut self, probability: f64) -> bool {
+        const SCALE: usize = 1 << 24;
+        (self.below(SCALE) as f64) < probability * SCALE as f64
+    }
+}
+
+/// An op with the multi-ops expanded and its ID attached
+struct ModelOp {
+    id: amp::OpId,
+    obj: amp::ObjectId,
+    key: amp::Key,
+    insert: bool,
+    action: amp::OpType,
+}
+
+/// The document made by `changes`, as JSON
+fn model_json(changes: &[amp::Change]) -> serde_json::Value {
+    let mut ops = Vec::new();
+    let mut overwritten = HashSet::new();
+    for change in changes {
+        let mut counter = change.start_op;
+        for op in &change.operations {
+            match &op.action {
+                amp::OpType::MultiSet(values) => {
+                    let mut key = op.key.clone();
+                    for value in values.iter() {
+                        let id = amp::OpId(counter, change.actor_id.clone());
+                        ops.push(ModelOp {
+                            id: id.clone(),
+                            obj: op.obj.clone(),
+                            key,
+                            insert: op.insert,
+                            action: amp::OpType::Set(value.clone()),
+                        });
+                        key = amp::Key::Seq(amp::ElementId::Id(id));
+                        counter += 1;
+                    }
+                }
+                amp::OpType::Del(count) if count.get() > 1 => {
+                    // a multi-op delete removes elements with consecutive IDs
+                    for i in 0..u64::from(count.get()) {
+                        if let Some(pred) = op.pred.get(0) {
+                            overwritten.insert(pred.increment_by(i));
+                        }
+                        counter += 1;
+                    }
+                }
+                action => {
+                    overwritten.extend(op.pred.iter().cloned());
+                    ops.push(ModelOp {
+                        id: amp::OpId(counter, change.actor_id.clone()),
+                        obj: op.obj.clone(),
+                        key: op.key.clone(),
+                        insert: op.insert,
+                        action: action.clone(),
+                    });
+                    counter += 1;
+                }
+            }
+        }
+    }
+    let model = Model {
+        ops: ops.iter().collect(),
+        overwritten,
+    };
+    model.object(&amp::ObjectId::Root, amp::ObjType::Map)
+}
+
+struct Model<'a> {
+    ops: Vec<&'a ModelOp>,
+    overwritten: HashSet<amp::OpId>,
+}
+
+impl<'a> Model<'a> {
+    fn object(&self, obj: &amp::ObjectId, obj_type: amp::ObjType) -> serde_json::Value {
+        let ops = self.ops.iter().filter(|op| &op.obj == obj);
+        match obj_type {
+            amp::ObjType::Map | amp::ObjType::Table => {
+                let mut winners: BTreeMap<String, &ModelOp> = BTreeMap::new();
+                for op in ops.filter(|op| self.is_visible(op)) {
+                    if let amp::Key::Map(key) = &op.key {
+                        let winner = winners.entry(key.to_string()).or_insert(op);
+                        if op.id > winner.id {
+                            *winner = op;
+                        }
+                    }
+                }
+                serde_json::Value::Object(
+                    winners
+                        .into_iter()
+                        .map(|(key, op)| (key, self.value(op)))
+                        .collect(),
+                )
+            }
+            amp::ObjType::List | amp::ObjType::Text => {
+                let ops: Vec<_> = ops.collect();
+                let mut children: HashMap<amp::ElementId, Vec<amp::OpId>> = HashMap::new();
+                for op in ops.iter().filter(|op| op.insert) {
+                    if let amp::Key::Seq(after) = &op.key {
+                        children
+                            .entry(after.clone())
+                            .or_default()
+                            .push(op.id.clone());
+                    }
+                }
+                let mut order = Vec::new();
+                rga_order(&children, &amp::ElementId::Head, &mut order);
+                let values = order.into_iter().filter_map(|elem| {
+                    ops.iter()
+                        .filter(|op| {
+                            self.is_visible(op)
+                                && if op.insert {
+                                    op.id == elem
+                                } else {
+                                    op.key == amp::Key::Seq(amp::ElementId::Id(elem.clone()))
+                                }
+                        })
+                        .max_by(|a, b| a.id.cmp(&b.id))
+                        .map(|op| self.value(op))
+                });
+                if obj_type == amp::ObjType::Text {
+                    serde_json::Value::String(
+                        values
+                            .map(|v| v.as_str().map(str::to_string).unwrap_or_default())
+                            .collect(),
+                    )
+                } else {
+                    serde_json::Value::Array(values.collect())
+                }
+            }
+        }
+    }
+
+    fn is_visible(&self, op: &ModelOp) -> bool {
+        matches!(op.action, amp::OpType::Set(_) | amp::OpType::Make(_))
+            && !self.overwritten.contains(&op.id)
+    }
+
+    fn value(&self, op: &ModelOp) -> serde_json::Value {
+        match &op.action {
+            amp::OpType::Make(obj_type) => {
+                self.object(&amp::ObjectId::Id(op.id.clone()), *obj_type)
+            }
+            amp::OpType::Set(value) => scalar_json(value),
+            _ => serde_json::Value::Null,
+        }
+    }
+}
+
+/// Append the elements inserted after `parent` to `order`: the most recent insertion comes
+/// first, and each element is followed by the elements inserted after it
+fn rga_order(
+    children: &HashMap<amp::ElementId, Vec<amp::OpId>>,
+    parent: &amp::ElementId,
+    order: &mut Vec<amp::OpId>,
+) {
+    let mut after = children.get(parent).cloned().unwrap_or_default();
+    after.sort_by(|a, b| b.cmp(a));
+    for id in after {
+        order.push(id.clone());
+        rga_order(children, &amp::ElementId::Id(id), order);
+    }
+}
+
+fn scalar_json(value: &amp::ScalarValue) -> serde_json::Value {
+    match value {
+        amp::ScalarValue::Str(s) => serde_json::Value::String(s.to_string()),
+        amp::ScalarValue::Int(n)
+        | amp::ScalarValue::Counter(n)
+        | amp::ScalarValue::Timestamp(n) => (*n).into(),
+        amp::ScalarValue::Uint(n) => (*n).into(),
+        amp::ScalarValue::F64(n) => {
+            serde_json::Number::from_f64(*n).map_or_else(|| 0.into(), serde_json::Value::Number)
+        }
+        amp::ScalarValue::Boolean(b) => (*b).into(),
+        amp::ScalarValue::Bytes(bytes) => bytes.to_vec().into(),
+        amp::ScalarValue::Cursor(_) | amp::ScalarValue::Null => serde_json::Value::Null,
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+
+use automerge::{
+    Backend, Change, Frontend, InvalidChangeRequest, LocalChange, Path, Primitive, Value,
+};
+use pretty_assertions::assert_eq;
+
+fn apply_local(frontend: &mut Frontend, backend: &mut Backend, change: LocalChange) {
+    let ((), change) = frontend
+        .change::<_, _, InvalidChangeRequest>(None, |doc| doc.add_change(change))
+        .unwrap();
+    let (patch, _) = backend.apply_local_change(change.unwrap()).unwrap();
+    frontend.apply_patch(patch).unwrap();
+}
+
+fn numbers(count: i64) -> Value {
+    Value::List(
+        (0..count)
+            .map(|i| Value::Primitive(Primitive::Int(i)))
+            .collect(),
+    )
+}
+
+fn map_of(count: usize) -> Value {
+    Value::Map(
+        (0..count)
+            .map(|i| {
+                (
+                    format!("key{}", i).into(),
+                    Value::Primitive(Primitive::Uint(i as u64)),
+                )
+            })
+            .collect(),
+    )
+}
+
+/// Two peers' changes to a document with a long list, a large nested map, some text and a key
+/// which both peers set to different things
+fn changes() -> Vec<Change> {
+    let mut frontend1 = Frontend::new();
+    let mut backend1 = Backend::new();
+    let mut nested = HashMap::new();
+    nested.insert("inner".into(), numbers(15));
+    let root = vec![
+        ("numbers", numbers(40)),
+        ("settings", map_of(25)),
+        (
+            "text",
+            Value::Text(
+                "the quick brown fox"
+                    .chars()
+                    .map(|c| c.to_string().into())
+                    .collect(),
+            ),
+        ),
+        ("nested", Value::List(vec![Value::Map(nested)])),
+    ];
+    for (key, value) in root {
+        apply_local(
+            &mut frontend1,
+            &mut backend1,
+            LocalChange::set(Path::root().key(key), value),
+        );
+    }
+
+    let mut frontend2 = Frontend::new();
+    let mut backend2 = Backend::new();
+    apply_local(
+        &mut frontend1,
+        &mut backend1,
+        LocalChange::set(
+            Path::root().key("conflict"),
+            Value::Primitive(Primitive::Int(1)),
+        ),
+    );
+    apply_local(
+        &mut frontend2,
+        &mut backend2,
+        LocalChange::set(Path::root().key("conflict"), map_of(12)),
+    );
+
+    backend1
+        .get_changes(&[])
+        .into_iter()
+        .chain(backend2.get_changes(&[]))
+        .cloned()
+        .collect()
+}
+
+#[test]
+fn chunked_patches_reach_the_same_state() {
+    let changes = changes();
+
+    let mut whole = Frontend::new();
+    whole
+        .apply_patch(Backend::new().apply_changes(changes.clone()).unwrap())
+        .unwrap();
+
+    let mut backend = Backend::new();
+    let patches = backend.apply_changes_chunked(changes, 10).unwrap();
+    assert!(patches.len() > 5, "only {} patches", patches.len());
+
+    let mut chunked = Frontend::new();
+    for patch in patches {
+        chunked.apply_patch(patch).unwrap();
+    }
+    assert_eq!(chunked.state(), whole.state());
+    assert_eq!(
+        chunked.get_conflicts(&Path::root().key("conflict")),
+        whole.get_conflicts(&Path::root().key("conflict"))
+    );
+}
+
+#[test]
+fn every_chunk_is_small() {
+    let patches = Backend::new().apply_changes_chunked(changes(), 10).unwrap();
+    for patch in &patches {
+        // The edit counts aren't visible outside the backend, so use the size of the patch as a
+        // stand in. A chunk of ten edits, plus the paths to them, is far smaller than this.
+        let size = serde_json::to_string(patch).unwrap().len();
+        assert!(size < 4000, "chunk of {} bytes", size);
+    }
+}
+
+#[test]
+fn small_patches_are_not_split() {
+    let mut backend = Backend::new();
+    let changes = changes();
+    let expected = Backend::new().apply_changes(changes.clone()).unwrap();
+    let patches = backend.apply_changes_chunked(changes, usize::MAX).unwrap();
+    assert_eq!(patches, vec![expected]);
+}
@@ -0,0 +1,16 @@
+use automerge::testing::{Divergence, Harness};
+
+#[test]
+fn test_random_interleavings_match_the_model() {
+    for seed in 0..50 {
+        if let Err(divergence) = Harness::new(3).run(seed, 200) {
+            panic!("seed {}: {}", seed, divergence);
+        }
+    }
+}
+
+#[test]
+fn test_replaying_a_run_takes_the_same_steps() {
+    let run = |seed| -> Result<(), Divergence> { Harness::new(2).run(seed, 30) };
+    assert_eq!(run(7), run(7));
+}
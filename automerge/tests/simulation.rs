@@ -0,0 +1,116 @@
+use automerge::{
+    simulation::{Event, Network, Simulation, SimulationError, Transport, LIST_KEY},
+    LocalChange, Path, Primitive, Value,
+};
+
+fn converges(peers: usize, seed: u64, network: Network, steps: usize) {
+    let run = || -> Result<(), SimulationError> {
+        let mut simulation = Simulation::new(peers, seed, network.clone());
+        simulation.run(steps)?;
+        simulation.settle()?;
+        simulation.check_converged()
+    };
+    if let Err(e) = run() {
+        panic!("seed {}: {}", seed, e);
+    }
+}
+
+#[test]
+fn test_reliable_network_converges() {
+    for seed in 0..20 {
+        converges(3, seed, Network::default(), 200);
+    }
+}
+
+#[test]
+fn test_changes_over_a_lossy_reordering_network_converge() {
+    let network = Network {
+        drop: 0.2,
+        duplicate: 0.2,
+        reorder: true,
+        transport: Transport::Changes,
+    };
+    for seed in 0..20 {
+        converges(4, seed, network.clone(), 200);
+    }
+}
+
+#[test]
+fn test_sync_over_a_lossy_reordering_network_converges() {
+    let network = Network {
+        drop: 0.2,
+        duplicate: 0.1,
+        reorder: true,
+        transport: Transport::Sync,
+    };
+    for seed in 0..20 {
+        converges(3, seed, network.clone(), 200);
+    }
+}
+
+#[test]
+fn test_the_same_seed_makes_the_same_run() {
+    let network = Network {
+        drop: 0.3,
+        duplicate: 0.3,
+        reorder: true,
+        transport: Transport::Changes,
+    };
+    let run = |seed| {
+        let mut simulation = Simulation::new(3, seed, network.clone());
+        simulation.run(100).unwrap();
+        (
+            simulation.events().to_vec(),
+            simulation.backend(0).get_heads(),
+        )
+    };
+    assert_eq!(run(42), run(42));
+    assert_ne!(run(42).0, run(43).0);
+}
+
+#[test]
+fn test_partitioned_peers_converge_after_syncing() {
+    let mut simulation = Simulation::new(
+        2,
+        0,
+        Network {
+            transport: Transport::Sync,
+            ..Network::default()
+        },
+    );
+    let item = |value| Value::Primitive(Primitive::Int(value));
+    simulation
+        .change(0, |doc| {
+            doc.add_change(LocalChange::insert(
+                Path::root().key(LIST_KEY).index(0),
+                item(0),
+            ))
+        })
+        .unwrap();
+    simulation
+        .change(1, |doc| {
+            doc.add_change(LocalChange::insert(
+                Path::root().key(LIST_KEY).index(0),
+                item(1),
+            ))
+        })
+        .unwrap();
+    // With the sync transport nothing is sent until a sync round starts
+    assert_eq!(simulation.in_flight(), 0);
+    assert!(simulation.check_converged().is_err());
+
+    simulation.sync(0, 1).unwrap();
+    while simulation.deliver_next().unwrap() {}
+    simulation.check_converged().unwrap();
+    match simulation
+        .frontend(1)
+        .get_value(&Path::root().key(LIST_KEY))
+    {
+        Some(Value::List(items)) => assert_eq!(items.len(), 2),
+        other => panic!("expected a list, found {:?}", other),
+    }
+    assert!(simulation
+        .events()
+        .iter()
+        .any(|event| *event == Event::Deliver { from: 1, to: 0 }));
+}
@@ -0,0 +1,8 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: Vec<u8>| {
+    if let Ok(change) = automerge_backend::Change::from_bytes(data) {
+        let _ = change.decode();
+    }
+});
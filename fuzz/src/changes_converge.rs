@@ -0,0 +1,159 @@
+#![no_main]
+use arbitrary::Arbitrary;
+use automerge_backend::{Backend, Change};
+use automerge_frontend::{Frontend, InvalidChangeRequest, LocalChange, Path, Primitive, Value};
+use libfuzzer_sys::fuzz_target;
+
+const PEERS: usize = 3;
+
+#[derive(Arbitrary, Debug)]
+enum Action {
+    Set { peer: u8, key: u8, value: i64 },
+    Delete { peer: u8, key: u8 },
+    Insert { peer: u8, index: u8, value: char },
+    Remove { peer: u8, index: u8 },
+    Merge { from: u8, to: u8 },
+}
+
+/// Edits made by the peers, followed by the order to deliver the resulting changes in
+#[derive(Arbitrary, Debug)]
+struct Input {
+    actions: Vec<Action>,
+    order: Vec<u16>,
+}
+
+struct Peer {
+    frontend: Frontend,
+    backend: Backend,
+}
+
+impl Peer {
+    fn new(index: usize) -> Self {
+        Peer {
+            frontend: Frontend::new_with_timestamper_and_actor_id(
+                Box::new(|| None),
+                &[index as u8 + 1; 16],
+            ),
+            backend: Backend::new(),
+        }
+    }
+
+    fn text_len(&self) -> usize {
+        match self.frontend.get_value(&Path::root().key("text")) {
+            Some(Value::Text(graphemes)) => graphemes.len(),
+            _ => 0,
+        }
+    }
+
+    /// Make a local change, changes the frontend rejects are ignored
+    fn edit(&mut self, local_change: LocalChange) {
+        let change = self
+            .frontend
+            .change::<_, _, InvalidChangeRequest>(None, |doc| doc.add_change(local_change));
+        if let Ok((_, Some(change))) = change {
+            let (patch, _) = self.backend.apply_local_change(change).unwrap();
+            self.frontend.apply_patch(patch).unwrap();
+        }
+    }
+
+    fn receive(&mut self, changes: Vec<Change>) {
+        let patch = self.backend.apply_changes(changes).unwrap();
+        self.frontend.apply_patch(patch).unwrap();
+    }
+}
+
+fn key(key: u8) -> Path {
+    Path::root().key(format!("key{}", key % 4))
+}
+
+fuzz_target!(|input: Input| {
+    let mut peers: Vec<Peer> = (0..PEERS).map(Peer::new).collect();
+    peers[0].edit(LocalChange::set(
+        Path::root().key("text"),
+        Value::Text(Vec::new()),
+    ));
+    let initial: Vec<Change> = peers[0]
+        .backend
+        .get_changes(&[])
+        .into_iter()
+        .cloned()
+        .collect();
+    for peer in &mut peers[1..] {
+        peer.receive(initial.clone());
+    }
+
+    for action in input.actions {
+        match action {
+            Action::Set {
+                peer,
+                key: k,
+                value,
+            } => peers[peer as usize % PEERS].edit(LocalChange::set(
+                key(k),
+                Value::Primitive(Primitive::Int(value)),
+            )),
+            Action::Delete { peer, key: k } => {
+                peers[peer as usize % PEERS].edit(LocalChange::delete(key(k)))
+            }
+            Action::Insert { peer, index, value } => {
+                let peer = &mut peers[peer as usize % PEERS];
+                let index = index as usize % (peer.text_len() + 1);
+                peer.edit(LocalChange::insert(
+                    Path::root().key("text").index(index as u32),
+                    Value::Primitive(Primitive::Str(value.to_string().into())),
+                ))
+            }
+            Action::Remove { peer, index } => {
+                let peer = &mut peers[peer as usize % PEERS];
+                let len = peer.text_len();
+                if len > 0 {
+                    let index = index as usize % len;
+                    peer.edit(LocalChange::delete(
+                        Path::root().key("text").index(index as u32),
+                    ))
+                }
+            }
+            Action::Merge { from, to } => {
+                let (from, to) = (from as usize % PEERS, to as usize % PEERS);
+                if from != to {
+                    let changes = peers[to]
+                        .backend
+                        .get_changes_added(&peers[from].backend)
+                        .into_iter()
+                        .cloned()
+                        .collect();
+                    peers[to].receive(changes);
+                }
+            }
+        }
+    }
+
+    // Everything any peer has seen, in the order it was applied
+    let mut reference = Backend::new();
+    for peer in &peers {
+        let changes = reference
+            .get_changes_added(&peer.backend)
+            .into_iter()
+            .cloned()
+            .collect();
+        reference.apply_changes(changes).unwrap();
+    }
+    let mut changes: Vec<Change> = reference.get_changes(&[]).into_iter().cloned().collect();
+
+    // Shuffle the changes with the fuzzer's choices, changes which arrive before their
+    // dependencies are queued by the backend
+    let mut choices = input.order.into_iter();
+    for i in (1..changes.len()).rev() {
+        let j = choices.next().unwrap_or(0) as usize % (i + 1);
+        changes.swap(i, j);
+    }
+    let mut shuffled = Backend::new();
+    for change in changes {
+        shuffled.apply_changes(vec![change]).unwrap();
+    }
+
+    assert_eq!(shuffled.get_heads(), reference.get_heads());
+    let expected = Frontend::value_of_patch(reference.get_patch().unwrap()).unwrap();
+    let actual = Frontend::value_of_patch(shuffled.get_patch().unwrap()).unwrap();
+    assert_eq!(actual, expected);
+});
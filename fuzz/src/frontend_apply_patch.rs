@@ -0,0 +1,13 @@
+#![no_main]
+use automerge_frontend::Frontend;
+use automerge_protocol::Patch;
+use libfuzzer_sys::fuzz_target;
+
+// Patches are applied one after the other so that later patches are checked against a non empty
+// document, an invalid patch must be rejected with an error rather than a panic
+fuzz_target!(|patches: Vec<Patch>| {
+    let mut frontend = Frontend::new_with_actor_id(&[1, 2, 3, 4]);
+    for patch in patches {
+        let _ = frontend.apply_patch(patch);
+    }
+});